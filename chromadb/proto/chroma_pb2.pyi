@@ -17,6 +17,8 @@ class ScalarEncoding(int, metaclass=_enum_type_wrapper.EnumTypeWrapper):
     __slots__ = ()
     FLOAT32: _ClassVar[ScalarEncoding]
     INT32: _ClassVar[ScalarEncoding]
+    FLOAT16: _ClassVar[ScalarEncoding]
+    SPARSE: _ClassVar[ScalarEncoding]
 
 class SegmentScope(int, metaclass=_enum_type_wrapper.EnumTypeWrapper):
     __slots__ = ()
@@ -57,6 +59,8 @@ UPSERT: Operation
 DELETE: Operation
 FLOAT32: ScalarEncoding
 INT32: ScalarEncoding
+FLOAT16: ScalarEncoding
+SPARSE: ScalarEncoding
 VECTOR: SegmentScope
 METADATA: SegmentScope
 RECORD: SegmentScope
@@ -381,26 +385,40 @@ class GetVectorsResponse(_message.Message):
     def __init__(self, records: _Optional[_Iterable[_Union[VectorEmbeddingRecord, _Mapping]]] = ...) -> None: ...
 
 class VectorEmbeddingRecord(_message.Message):
-    __slots__ = ("id", "vector")
+    __slots__ = ("id", "vector", "not_found")
     ID_FIELD_NUMBER: _ClassVar[int]
     VECTOR_FIELD_NUMBER: _ClassVar[int]
+    NOT_FOUND_FIELD_NUMBER: _ClassVar[int]
     id: str
     vector: Vector
-    def __init__(self, id: _Optional[str] = ..., vector: _Optional[_Union[Vector, _Mapping]] = ...) -> None: ...
+    not_found: bool
+    def __init__(self, id: _Optional[str] = ..., vector: _Optional[_Union[Vector, _Mapping]] = ..., not_found: bool = ...) -> None: ...
 
 class QueryVectorsRequest(_message.Message):
-    __slots__ = ("vectors", "k", "allowed_ids", "include_embeddings", "segment_id")
+    __slots__ = ("vectors", "k", "allowed_ids", "include_embeddings", "segment_id", "ef_search", "where", "where_document", "include_documents", "include_metadatas", "return_similarity")
     VECTORS_FIELD_NUMBER: _ClassVar[int]
     K_FIELD_NUMBER: _ClassVar[int]
     ALLOWED_IDS_FIELD_NUMBER: _ClassVar[int]
     INCLUDE_EMBEDDINGS_FIELD_NUMBER: _ClassVar[int]
     SEGMENT_ID_FIELD_NUMBER: _ClassVar[int]
+    EF_SEARCH_FIELD_NUMBER: _ClassVar[int]
+    WHERE_FIELD_NUMBER: _ClassVar[int]
+    WHERE_DOCUMENT_FIELD_NUMBER: _ClassVar[int]
+    INCLUDE_DOCUMENTS_FIELD_NUMBER: _ClassVar[int]
+    INCLUDE_METADATAS_FIELD_NUMBER: _ClassVar[int]
+    RETURN_SIMILARITY_FIELD_NUMBER: _ClassVar[int]
     vectors: _containers.RepeatedCompositeFieldContainer[Vector]
     k: int
     allowed_ids: _containers.RepeatedScalarFieldContainer[str]
     include_embeddings: bool
     segment_id: str
-    def __init__(self, vectors: _Optional[_Iterable[_Union[Vector, _Mapping]]] = ..., k: _Optional[int] = ..., allowed_ids: _Optional[_Iterable[str]] = ..., include_embeddings: bool = ..., segment_id: _Optional[str] = ...) -> None: ...
+    ef_search: int
+    where: Where
+    where_document: WhereDocument
+    include_documents: bool
+    include_metadatas: bool
+    return_similarity: bool
+    def __init__(self, vectors: _Optional[_Iterable[_Union[Vector, _Mapping]]] = ..., k: _Optional[int] = ..., allowed_ids: _Optional[_Iterable[str]] = ..., include_embeddings: bool = ..., segment_id: _Optional[str] = ..., ef_search: _Optional[int] = ..., where: _Optional[_Union[Where, _Mapping]] = ..., where_document: _Optional[_Union[WhereDocument, _Mapping]] = ..., include_documents: bool = ..., include_metadatas: bool = ..., return_similarity: bool = ...) -> None: ...
 
 class QueryVectorsResponse(_message.Message):
     __slots__ = ("results",)
@@ -415,11 +433,71 @@ class VectorQueryResults(_message.Message):
     def __init__(self, results: _Optional[_Iterable[_Union[VectorQueryResult, _Mapping]]] = ...) -> None: ...
 
 class VectorQueryResult(_message.Message):
-    __slots__ = ("id", "distance", "vector")
+    __slots__ = ("id", "distance", "vector", "document", "metadata")
     ID_FIELD_NUMBER: _ClassVar[int]
     DISTANCE_FIELD_NUMBER: _ClassVar[int]
     VECTOR_FIELD_NUMBER: _ClassVar[int]
+    DOCUMENT_FIELD_NUMBER: _ClassVar[int]
+    METADATA_FIELD_NUMBER: _ClassVar[int]
     id: str
     distance: float
     vector: Vector
-    def __init__(self, id: _Optional[str] = ..., distance: _Optional[float] = ..., vector: _Optional[_Union[Vector, _Mapping]] = ...) -> None: ...
+    document: str
+    metadata: UpdateMetadata
+    def __init__(self, id: _Optional[str] = ..., distance: _Optional[float] = ..., vector: _Optional[_Union[Vector, _Mapping]] = ..., document: _Optional[str] = ..., metadata: _Optional[_Union[UpdateMetadata, _Mapping]] = ...) -> None: ...
+
+class GetIndexStatsRequest(_message.Message):
+    __slots__ = ("segment_id", "collection_id")
+    SEGMENT_ID_FIELD_NUMBER: _ClassVar[int]
+    COLLECTION_ID_FIELD_NUMBER: _ClassVar[int]
+    segment_id: str
+    collection_id: str
+    def __init__(self, segment_id: _Optional[str] = ..., collection_id: _Optional[str] = ...) -> None: ...
+
+class IndexStats(_message.Message):
+    __slots__ = ("index_id", "element_count", "deleted_count", "capacity", "dimension", "distance_function", "estimated_bytes", "last_persisted_ms", "dirty", "cached")
+    INDEX_ID_FIELD_NUMBER: _ClassVar[int]
+    ELEMENT_COUNT_FIELD_NUMBER: _ClassVar[int]
+    DELETED_COUNT_FIELD_NUMBER: _ClassVar[int]
+    CAPACITY_FIELD_NUMBER: _ClassVar[int]
+    DIMENSION_FIELD_NUMBER: _ClassVar[int]
+    DISTANCE_FUNCTION_FIELD_NUMBER: _ClassVar[int]
+    ESTIMATED_BYTES_FIELD_NUMBER: _ClassVar[int]
+    LAST_PERSISTED_MS_FIELD_NUMBER: _ClassVar[int]
+    DIRTY_FIELD_NUMBER: _ClassVar[int]
+    CACHED_FIELD_NUMBER: _ClassVar[int]
+    index_id: str
+    element_count: int
+    deleted_count: int
+    capacity: int
+    dimension: int
+    distance_function: str
+    estimated_bytes: int
+    last_persisted_ms: int
+    dirty: bool
+    cached: bool
+    def __init__(self, index_id: _Optional[str] = ..., element_count: _Optional[int] = ..., deleted_count: _Optional[int] = ..., capacity: _Optional[int] = ..., dimension: _Optional[int] = ..., distance_function: _Optional[str] = ..., estimated_bytes: _Optional[int] = ..., last_persisted_ms: _Optional[int] = ..., dirty: bool = ..., cached: bool = ...) -> None: ...
+
+class CheckIndexRequest(_message.Message):
+    __slots__ = ("collection_id", "rebuild", "rebuild_id", "collection_version")
+    COLLECTION_ID_FIELD_NUMBER: _ClassVar[int]
+    REBUILD_FIELD_NUMBER: _ClassVar[int]
+    REBUILD_ID_FIELD_NUMBER: _ClassVar[int]
+    COLLECTION_VERSION_FIELD_NUMBER: _ClassVar[int]
+    collection_id: str
+    rebuild: bool
+    rebuild_id: str
+    collection_version: int
+    def __init__(self, collection_id: _Optional[str] = ..., rebuild: bool = ..., rebuild_id: _Optional[str] = ..., collection_version: _Optional[int] = ...) -> None: ...
+
+class CheckIndexResponse(_message.Message):
+    __slots__ = ("collection_version", "stats", "discrepancies", "rebuilt")
+    COLLECTION_VERSION_FIELD_NUMBER: _ClassVar[int]
+    STATS_FIELD_NUMBER: _ClassVar[int]
+    DISCREPANCIES_FIELD_NUMBER: _ClassVar[int]
+    REBUILT_FIELD_NUMBER: _ClassVar[int]
+    collection_version: int
+    stats: IndexStats
+    discrepancies: _containers.RepeatedScalarFieldContainer[str]
+    rebuilt: IndexStats
+    def __init__(self, collection_version: _Optional[int] = ..., stats: _Optional[_Union[IndexStats, _Mapping]] = ..., discrepancies: _Optional[_Iterable[str]] = ..., rebuilt: _Optional[_Union[IndexStats, _Mapping]] = ...) -> None: ...
@@ -270,3 +270,124 @@ class VectorReader(object):
             timeout,
             metadata,
             _registered_method=True)
+
+
+class IndexAdminStub(object):
+    """Index Admin Interface 
+
+    """
+
+    def __init__(self, channel):
+        """Constructor.
+
+        Args:
+            channel: A grpc.Channel.
+        """
+        self.GetIndexStats = channel.unary_unary(
+                '/chroma.IndexAdmin/GetIndexStats',
+                request_serializer=chromadb_dot_proto_dot_chroma__pb2.GetIndexStatsRequest.SerializeToString,
+                response_deserializer=chromadb_dot_proto_dot_chroma__pb2.IndexStats.FromString,
+                _registered_method=True)
+        self.CheckIndex = channel.unary_unary(
+                '/chroma.IndexAdmin/CheckIndex',
+                request_serializer=chromadb_dot_proto_dot_chroma__pb2.CheckIndexRequest.SerializeToString,
+                response_deserializer=chromadb_dot_proto_dot_chroma__pb2.CheckIndexResponse.FromString,
+                _registered_method=True)
+
+
+class IndexAdminServicer(object):
+    """Index Admin Interface 
+
+    """
+
+    def GetIndexStats(self, request, context):
+        """Missing associated documentation comment in .proto file."""
+        context.set_code(grpc.StatusCode.UNIMPLEMENTED)
+        context.set_details('Method not implemented!')
+        raise NotImplementedError('Method not implemented!')
+
+    def CheckIndex(self, request, context):
+        """Missing associated documentation comment in .proto file."""
+        context.set_code(grpc.StatusCode.UNIMPLEMENTED)
+        context.set_details('Method not implemented!')
+        raise NotImplementedError('Method not implemented!')
+
+
+def add_IndexAdminServicer_to_server(servicer, server):
+    rpc_method_handlers = {
+            'GetIndexStats': grpc.unary_unary_rpc_method_handler(
+                    servicer.GetIndexStats,
+                    request_deserializer=chromadb_dot_proto_dot_chroma__pb2.GetIndexStatsRequest.FromString,
+                    response_serializer=chromadb_dot_proto_dot_chroma__pb2.IndexStats.SerializeToString,
+            ),
+            'CheckIndex': grpc.unary_unary_rpc_method_handler(
+                    servicer.CheckIndex,
+                    request_deserializer=chromadb_dot_proto_dot_chroma__pb2.CheckIndexRequest.FromString,
+                    response_serializer=chromadb_dot_proto_dot_chroma__pb2.CheckIndexResponse.SerializeToString,
+            ),
+    }
+    generic_handler = grpc.method_handlers_generic_handler(
+            'chroma.IndexAdmin', rpc_method_handlers)
+    server.add_generic_rpc_handlers((generic_handler,))
+    server.add_registered_method_handlers('chroma.IndexAdmin', rpc_method_handlers)
+
+
+ # This class is part of an EXPERIMENTAL API.
+class IndexAdmin(object):
+    """Index Admin Interface 
+
+    """
+
+    @staticmethod
+    def GetIndexStats(request,
+            target,
+            options=(),
+            channel_credentials=None,
+            call_credentials=None,
+            insecure=False,
+            compression=None,
+            wait_for_ready=None,
+            timeout=None,
+            metadata=None):
+        return grpc.experimental.unary_unary(
+            request,
+            target,
+            '/chroma.IndexAdmin/GetIndexStats',
+            chromadb_dot_proto_dot_chroma__pb2.GetIndexStatsRequest.SerializeToString,
+            chromadb_dot_proto_dot_chroma__pb2.IndexStats.FromString,
+            options,
+            channel_credentials,
+            insecure,
+            call_credentials,
+            compression,
+            wait_for_ready,
+            timeout,
+            metadata,
+            _registered_method=True)
+
+    @staticmethod
+    def CheckIndex(request,
+            target,
+            options=(),
+            channel_credentials=None,
+            call_credentials=None,
+            insecure=False,
+            compression=None,
+            wait_for_ready=None,
+            timeout=None,
+            metadata=None):
+        return grpc.experimental.unary_unary(
+            request,
+            target,
+            '/chroma.IndexAdmin/CheckIndex',
+            chromadb_dot_proto_dot_chroma__pb2.CheckIndexRequest.SerializeToString,
+            chromadb_dot_proto_dot_chroma__pb2.CheckIndexResponse.FromString,
+            options,
+            channel_credentials,
+            insecure,
+            call_credentials,
+            compression,
+            wait_for_ready,
+            timeout,
+            metadata,
+            _registered_method=True)
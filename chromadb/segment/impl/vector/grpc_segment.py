@@ -56,6 +56,9 @@ class GrpcVectorSegment(VectorReader, EnforceOverrides):
         response: GetVectorsResponse = self._vector_reader_stub.GetVectors(request)
         results: List[VectorEmbeddingRecord] = []
         for vector in response.records:
+            # Ids the segment has no record of come back marked not_found.
+            if vector.not_found:
+                continue
             result = from_proto_vector_embedding_record(vector)
             results.append(result)
         return results
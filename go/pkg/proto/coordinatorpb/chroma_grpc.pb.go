@@ -271,3 +271,130 @@ var VectorReader_ServiceDesc = grpc.ServiceDesc{
 	Streams:  []grpc.StreamDesc{},
 	Metadata: "chromadb/proto/chroma.proto",
 }
+
+const (
+	IndexAdmin_GetIndexStats_FullMethodName = "/chroma.IndexAdmin/GetIndexStats"
+	IndexAdmin_CheckIndex_FullMethodName    = "/chroma.IndexAdmin/CheckIndex"
+)
+
+// IndexAdminClient is the client API for IndexAdmin service.
+//
+// For semantics around ctx use and closing/ending streaming RPCs, please refer to https://pkg.go.dev/google.golang.org/grpc/?tab=doc#ClientConn.NewStream.
+type IndexAdminClient interface {
+	GetIndexStats(ctx context.Context, in *GetIndexStatsRequest, opts ...grpc.CallOption) (*IndexStats, error)
+	CheckIndex(ctx context.Context, in *CheckIndexRequest, opts ...grpc.CallOption) (*CheckIndexResponse, error)
+}
+
+type indexAdminClient struct {
+	cc grpc.ClientConnInterface
+}
+
+func NewIndexAdminClient(cc grpc.ClientConnInterface) IndexAdminClient {
+	return &indexAdminClient{cc}
+}
+
+func (c *indexAdminClient) GetIndexStats(ctx context.Context, in *GetIndexStatsRequest, opts ...grpc.CallOption) (*IndexStats, error) {
+	out := new(IndexStats)
+	err := c.cc.Invoke(ctx, IndexAdmin_GetIndexStats_FullMethodName, in, out, opts...)
+	if err != nil {
+		return nil, err
+	}
+	return out, nil
+}
+
+func (c *indexAdminClient) CheckIndex(ctx context.Context, in *CheckIndexRequest, opts ...grpc.CallOption) (*CheckIndexResponse, error) {
+	out := new(CheckIndexResponse)
+	err := c.cc.Invoke(ctx, IndexAdmin_CheckIndex_FullMethodName, in, out, opts...)
+	if err != nil {
+		return nil, err
+	}
+	return out, nil
+}
+
+// IndexAdminServer is the server API for IndexAdmin service.
+// All implementations must embed UnimplementedIndexAdminServer
+// for forward compatibility
+type IndexAdminServer interface {
+	GetIndexStats(context.Context, *GetIndexStatsRequest) (*IndexStats, error)
+	CheckIndex(context.Context, *CheckIndexRequest) (*CheckIndexResponse, error)
+	mustEmbedUnimplementedIndexAdminServer()
+}
+
+// UnimplementedIndexAdminServer must be embedded to have forward compatible implementations.
+type UnimplementedIndexAdminServer struct {
+}
+
+func (UnimplementedIndexAdminServer) GetIndexStats(context.Context, *GetIndexStatsRequest) (*IndexStats, error) {
+	return nil, status.Errorf(codes.Unimplemented, "method GetIndexStats not implemented")
+}
+func (UnimplementedIndexAdminServer) CheckIndex(context.Context, *CheckIndexRequest) (*CheckIndexResponse, error) {
+	return nil, status.Errorf(codes.Unimplemented, "method CheckIndex not implemented")
+}
+func (UnimplementedIndexAdminServer) mustEmbedUnimplementedIndexAdminServer() {}
+
+// UnsafeIndexAdminServer may be embedded to opt out of forward compatibility for this service.
+// Use of this interface is not recommended, as added methods to IndexAdminServer will
+// result in compilation errors.
+type UnsafeIndexAdminServer interface {
+	mustEmbedUnimplementedIndexAdminServer()
+}
+
+func RegisterIndexAdminServer(s grpc.ServiceRegistrar, srv IndexAdminServer) {
+	s.RegisterService(&IndexAdmin_ServiceDesc, srv)
+}
+
+func _IndexAdmin_GetIndexStats_Handler(srv interface{}, ctx context.Context, dec func(interface{}) error, interceptor grpc.UnaryServerInterceptor) (interface{}, error) {
+	in := new(GetIndexStatsRequest)
+	if err := dec(in); err != nil {
+		return nil, err
+	}
+	if interceptor == nil {
+		return srv.(IndexAdminServer).GetIndexStats(ctx, in)
+	}
+	info := &grpc.UnaryServerInfo{
+		Server:     srv,
+		FullMethod: IndexAdmin_GetIndexStats_FullMethodName,
+	}
+	handler := func(ctx context.Context, req interface{}) (interface{}, error) {
+		return srv.(IndexAdminServer).GetIndexStats(ctx, req.(*GetIndexStatsRequest))
+	}
+	return interceptor(ctx, in, info, handler)
+}
+
+func _IndexAdmin_CheckIndex_Handler(srv interface{}, ctx context.Context, dec func(interface{}) error, interceptor grpc.UnaryServerInterceptor) (interface{}, error) {
+	in := new(CheckIndexRequest)
+	if err := dec(in); err != nil {
+		return nil, err
+	}
+	if interceptor == nil {
+		return srv.(IndexAdminServer).CheckIndex(ctx, in)
+	}
+	info := &grpc.UnaryServerInfo{
+		Server:     srv,
+		FullMethod: IndexAdmin_CheckIndex_FullMethodName,
+	}
+	handler := func(ctx context.Context, req interface{}) (interface{}, error) {
+		return srv.(IndexAdminServer).CheckIndex(ctx, req.(*CheckIndexRequest))
+	}
+	return interceptor(ctx, in, info, handler)
+}
+
+// IndexAdmin_ServiceDesc is the grpc.ServiceDesc for IndexAdmin service.
+// It's only intended for direct use with grpc.RegisterService,
+// and not to be introspected or modified (even as a copy)
+var IndexAdmin_ServiceDesc = grpc.ServiceDesc{
+	ServiceName: "chroma.IndexAdmin",
+	HandlerType: (*IndexAdminServer)(nil),
+	Methods: []grpc.MethodDesc{
+		{
+			MethodName: "GetIndexStats",
+			Handler:    _IndexAdmin_GetIndexStats_Handler,
+		},
+		{
+			MethodName: "CheckIndex",
+			Handler:    _IndexAdmin_CheckIndex_Handler,
+		},
+	},
+	Streams:  []grpc.StreamDesc{},
+	Metadata: "chromadb/proto/chroma.proto",
+}
@@ -126,7 +126,17 @@ public:
         {
             std::runtime_error("Index not inited");
         }
-        appr_alg->markDelete(id);
+        // hnswlib throws when the label was never added or is already deleted. Deleting
+        // such a label is a no-op for us, so report it instead of letting the exception
+        // unwind across the FFI boundary.
+        try
+        {
+            appr_alg->markDelete(id);
+        }
+        catch (const std::runtime_error &e)
+        {
+            return 1;
+        }
         return 0;
     }
 
@@ -261,6 +271,11 @@ extern "C"
         return index->appr_alg->max_elements_;
     }
 
+    size_t element_count(Index<float> *index)
+    {
+        return index->appr_alg->getCurrentElementCount();
+    }
+
     void resize_index(Index<float> *index, size_t new_size)
     {
         index->resize_index(new_size);
@@ -2,9 +2,10 @@ use crate::{config::Configurable, errors::ChromaError};
 
 use super::{
     config::{AssignmentPolicyConfig, HasherType},
-    rendezvous_hash::{assign, AssignmentError, Murmur3Hasher},
+    rendezvous_hash::{assign, assign_weighted, AssignmentError, Murmur3Hasher},
 };
 use async_trait::async_trait;
+use std::collections::HashMap;
 
 /*
 ===========================================
@@ -35,6 +36,9 @@ Implementation
 pub(crate) struct RendezvousHashingAssignmentPolicy {
     hasher: Murmur3Hasher,
     members: Vec<String>,
+    // Members without a weight have a weight of 1. Without any weights, keys are assigned
+    // unweighted, like the go and python services do.
+    weights: HashMap<String, f64>,
 }
 
 impl RendezvousHashingAssignmentPolicy {
@@ -42,6 +46,7 @@ impl RendezvousHashingAssignmentPolicy {
         return RendezvousHashingAssignmentPolicy {
             hasher: Murmur3Hasher {},
             members: vec![],
+            weights: HashMap::new(),
         };
     }
 }
@@ -57,17 +62,33 @@ impl Configurable<AssignmentPolicyConfig> for RendezvousHashingAssignmentPolicy
         let hasher = match assignment_policy_config.hasher {
             HasherType::Murmur3 => Murmur3Hasher {},
         };
+        for (member, weight) in &assignment_policy_config.weights {
+            if !(*weight > 0.0 && weight.is_finite()) {
+                return Err(Box::new(AssignmentError::InvalidWeight {
+                    member: member.clone(),
+                    weight: *weight,
+                }));
+            }
+        }
         return Ok(RendezvousHashingAssignmentPolicy {
             hasher: hasher,
             members: vec![],
+            weights: assignment_policy_config.weights.clone(),
         });
     }
 }
 
 impl AssignmentPolicy for RendezvousHashingAssignmentPolicy {
     fn assign(&self, key: &str) -> Result<String, AssignmentError> {
-        let members = self.get_members();
-        assign(key, members, &self.hasher)
+        if self.weights.is_empty() {
+            let members = self.get_members();
+            return assign(key, members, &self.hasher);
+        }
+        let members = self.members.iter().map(|member| {
+            let weight = self.weights.get(member).copied().unwrap_or(1.0);
+            (member, weight)
+        });
+        assign_weighted(key, members, &self.hasher)
     }
 
     fn get_members(&self) -> Vec<String> {
@@ -80,3 +101,65 @@ impl AssignmentPolicy for RendezvousHashingAssignmentPolicy {
         self.members = members;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assignment::config::RendezvousHashingAssignmentPolicyConfig;
+
+    fn config(weights: Vec<(&str, f64)>) -> AssignmentPolicyConfig {
+        AssignmentPolicyConfig::RendezvousHashing(RendezvousHashingAssignmentPolicyConfig {
+            hasher: HasherType::Murmur3,
+            weights: weights
+                .into_iter()
+                .map(|(member, weight)| (member.to_string(), weight))
+                .collect(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_weighted_assignment_policy() {
+        let members: Vec<String> = (0..3).map(|i| format!("member{}", i)).collect();
+        let mut unweighted = RendezvousHashingAssignmentPolicy::try_from_config(&config(vec![]))
+            .await
+            .unwrap();
+        unweighted.set_members(members.clone());
+        // member0 gets twice the keys of the others, member2 has the default weight.
+        let mut weighted = RendezvousHashingAssignmentPolicy::try_from_config(&config(vec![
+            ("member0", 2.0),
+            ("member1", 1.0),
+        ]))
+        .await
+        .unwrap();
+        weighted.set_members(members.clone());
+
+        let mut counts = vec![0; members.len()];
+        for i in 0..10000 {
+            let key = format!("key_{}", i);
+            // Unweighted assignment is unchanged.
+            assert_eq!(
+                unweighted.assign(&key).unwrap(),
+                assign(&key, &members, &Murmur3Hasher {}).unwrap()
+            );
+            let member = weighted.assign(&key).unwrap();
+            counts[members.iter().position(|m| *m == member).unwrap()] += 1;
+        }
+        for (count, expected) in counts.into_iter().zip([5000, 2500, 2500]) {
+            assert!((count - expected as i32).abs() < 300);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_weights_are_rejected() {
+        for weight in [0.0, -2.0, f64::NAN] {
+            let result = RendezvousHashingAssignmentPolicy::try_from_config(&config(vec![(
+                "member0", weight,
+            )]))
+            .await;
+            match result {
+                Err(err) => assert_eq!(err.code(), crate::errors::ErrorCodes::InvalidArgument),
+                Ok(_) => panic!("Expected weight {} to be rejected", weight),
+            }
+        }
+    }
+}
@@ -1,4 +1,6 @@
+use crate::config::{ConfigValidator, ValidateConfig};
 use serde::Deserialize;
+use std::collections::HashMap;
 
 #[derive(Deserialize)]
 /// The type of hasher to use.
@@ -16,6 +18,8 @@ pub(crate) enum HasherType {
 /// See config.rs in the root of the worker crate for an example of how to use
 /// config files to configure the worker.
 pub(crate) enum AssignmentPolicyConfig {
+    // case-insensitive
+    #[serde(alias = "rendezvoushashing")]
     RendezvousHashing(RendezvousHashingAssignmentPolicyConfig),
 }
 
@@ -23,6 +27,36 @@ pub(crate) enum AssignmentPolicyConfig {
 /// The configuration for the rendezvous hashing assignment policy.
 /// # Fields
 /// - hasher: The type of hasher to use.
+/// - weights: The weight of every member, by member id. A member is assigned keys in
+/// proportion to its weight, members without one have a weight of 1. When no weights are
+/// set, keys are assigned exactly like the go and python services do.
+#[serde(deny_unknown_fields)]
 pub(crate) struct RendezvousHashingAssignmentPolicyConfig {
     pub(crate) hasher: HasherType,
+    #[serde(default)]
+    pub(crate) weights: HashMap<String, f64>,
+}
+
+impl ValidateConfig for AssignmentPolicyConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        match self {
+            AssignmentPolicyConfig::RendezvousHashing(rendezvous_hashing) => {
+                validator.section("RendezvousHashing", rendezvous_hashing)
+            }
+        }
+    }
+}
+
+impl ValidateConfig for RendezvousHashingAssignmentPolicyConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        let mut members: Vec<_> = self.weights.iter().collect();
+        members.sort_by(|a, b| a.0.cmp(b.0));
+        for (member, weight) in members {
+            validator.check(
+                &format!("weights.{}", member),
+                *weight > 0.0 && weight.is_finite(),
+                "a finite number greater than 0",
+            );
+        }
+    }
 }
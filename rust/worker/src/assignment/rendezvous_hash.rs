@@ -23,6 +23,8 @@ pub(crate) enum AssignmentError {
     NoMembers,
     #[error("Error hashing member")]
     HashError,
+    #[error("Invalid weight {weight} of member {member}, must be positive")]
+    InvalidWeight { member: String, weight: f64 },
 }
 
 impl ChromaError for AssignmentError {
@@ -31,6 +33,7 @@ impl ChromaError for AssignmentError {
             AssignmentError::EmptyKey => ErrorCodes::InvalidArgument,
             AssignmentError::NoMembers => ErrorCodes::InvalidArgument,
             AssignmentError::HashError => ErrorCodes::Internal,
+            AssignmentError::InvalidWeight { .. } => ErrorCodes::InvalidArgument,
         }
     }
 }
@@ -87,6 +90,58 @@ pub(crate) fn assign<H: Hasher>(
     }
 }
 
+/// Assign a key to a member using the weighted rendezvous hash algorithm.
+/// # Arguments
+/// - key: The key to assign.
+/// - members: The members to assign to, with their weights.
+/// - hasher: The hasher to use.
+/// # Returns
+/// The member that the key was assigned to.
+/// # Errors
+/// - If the key is empty.
+/// - If there are no members to assign to.
+/// - If there is an error hashing a member.
+/// - If a weight is not a positive number.
+/// # Notes
+/// Every member scores `weight / -ln(hash)`, with the hash mapped into (0, 1), and the
+/// highest score wins. Members are assigned keys in proportion to their weights, and
+/// adding or removing a member only moves the keys that it gains or loses. When all
+/// weights are equal, keys are assigned as by `assign`.
+pub(crate) fn assign_weighted<H: Hasher>(
+    key: &str,
+    members: impl IntoIterator<Item = (impl AsRef<str>, f64)>,
+    hasher: &H,
+) -> Result<String, AssignmentError> {
+    if key.is_empty() {
+        return Err(AssignmentError::EmptyKey);
+    }
+
+    let mut max_score = f64::NEG_INFINITY;
+    let mut max_member = None;
+    for (member, weight) in members {
+        if !(weight > 0.0 && weight.is_finite()) {
+            return Err(AssignmentError::InvalidWeight {
+                member: member.as_ref().to_string(),
+                weight,
+            });
+        }
+        let hash = hasher.hash(member.as_ref(), key)?;
+        // Map the hash to (0, 1), excluding both ends so that the logarithm is finite and
+        // negative.
+        let unit = (hash as f64 + 0.5) / (u64::MAX as f64 + 1.0);
+        let score = weight / -unit.ln();
+        if max_member.is_none() || score > max_score {
+            max_score = score;
+            max_member = Some(member);
+        }
+    }
+
+    match max_member {
+        Some(max_member) => Ok(max_member.as_ref().to_string()),
+        None => Err(AssignmentError::NoMembers),
+    }
+}
+
 fn merge_hashes(x: u64, y: u64) -> u64 {
     let mut acc = x ^ y;
     acc ^= acc >> 33;
@@ -142,6 +197,96 @@ mod tests {
         assert_eq!(member, "c".to_string());
     }
 
+    #[test]
+    fn test_assign_weighted() {
+        let hasher = MockHasher {};
+        // All weights equal, the highest hash wins as with assign.
+        let member = assign_weighted("key", vec![("a", 1.0), ("b", 1.0), ("c", 1.0)], &hasher);
+        assert_eq!(member.unwrap(), "c".to_string());
+
+        let members: Vec<(&str, f64)> = vec![];
+        let err = assign_weighted("key", members, &hasher).unwrap_err();
+        assert!(matches!(err, AssignmentError::NoMembers));
+        let err = assign_weighted("", vec![("a", 1.0)], &hasher).unwrap_err();
+        assert!(matches!(err, AssignmentError::EmptyKey));
+        for weight in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let err = assign_weighted("key", vec![("a", 1.0), ("b", weight)], &hasher).unwrap_err();
+            assert!(
+                matches!(err, AssignmentError::InvalidWeight { ref member, .. } if member == "b")
+            );
+            assert_eq!(err.code(), ErrorCodes::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn test_equal_weights_assign_as_unweighted() {
+        let hasher = Murmur3Hasher {};
+        let members: Vec<String> = (0..7).map(|i| format!("member{}", i)).collect();
+        for i in 0..1000 {
+            let key = format!("key_{}", i);
+            let weighted = members.iter().map(|member| (member, 3.0));
+            assert_eq!(
+                assign_weighted(&key, weighted, &hasher).unwrap(),
+                assign(&key, &members, &hasher).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_weighted_distribution() {
+        let hasher = Murmur3Hasher {};
+        let members = vec![
+            ("member0".to_string(), 1.0),
+            ("member1".to_string(), 2.0),
+            ("member2".to_string(), 1.0),
+            ("member3".to_string(), 4.0),
+        ];
+        let total_weight: f64 = members.iter().map(|(_, weight)| weight).sum();
+        let num_keys = 10000;
+        let mut counts = vec![0; members.len()];
+        for i in 0..num_keys {
+            let key = format!("key_{}", i);
+            let weighted = members.iter().map(|(member, weight)| (member, *weight));
+            let member = assign_weighted(&key, weighted, &hasher).unwrap();
+            counts[members.iter().position(|(m, _)| *m == member).unwrap()] += 1;
+        }
+        for ((_, weight), count) in members.iter().zip(counts) {
+            let expected = num_keys as f64 * weight / total_weight;
+            // Within 3% of all keys.
+            assert!((count as f64 - expected).abs() < 0.03 * num_keys as f64);
+        }
+    }
+
+    #[test]
+    fn test_removing_a_weighted_member_only_moves_its_keys() {
+        let hasher = Murmur3Hasher {};
+        let members = vec![
+            ("member0".to_string(), 1.0),
+            ("member1".to_string(), 2.0),
+            ("member2".to_string(), 0.5),
+            ("member3".to_string(), 1.0),
+        ];
+        let removed = "member1";
+        let mut moved = 0;
+        for i in 0..10000 {
+            let key = format!("key_{}", i);
+            let weighted = members.iter().map(|(member, weight)| (member, *weight));
+            let before = assign_weighted(&key, weighted, &hasher).unwrap();
+            let remaining = members
+                .iter()
+                .filter(|(member, _)| member != removed)
+                .map(|(member, weight)| (member, *weight));
+            let after = assign_weighted(&key, remaining, &hasher).unwrap();
+            if before == removed {
+                moved += 1;
+            } else {
+                assert_eq!(before, after);
+            }
+        }
+        // The removed member owned about 2 / 4.5 of the keys.
+        assert!((moved as f64 - 10000.0 * 2.0 / 4.5).abs() < 300.0);
+    }
+
     #[test]
     fn test_even_distribution() {
         let member_count = 10;
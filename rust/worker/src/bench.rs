@@ -0,0 +1,57 @@
+// Hooks for the benches under src/benches, which are built as their own crates and can't
+// reach the crate private types they measure. Only built with the bench feature.
+
+use crate::distance::DistanceFunction;
+use crate::index::{HnswIndex, HnswIndexConfig, Index, IndexConfig};
+use parking_lot::RwLock;
+use uuid::Uuid;
+
+/// An HNSW index that is shared between threads the way a segment shares it between its
+/// writer and its readers.
+pub struct SharedHnswIndex {
+    index: RwLock<HnswIndex>,
+}
+
+impl SharedHnswIndex {
+    pub fn new(dimensionality: usize, max_elements: usize, persist_path: &str) -> Self {
+        let index = HnswIndex::init(
+            &IndexConfig {
+                dimensionality: dimensionality as i32,
+                distance_function: DistanceFunction::Euclidean,
+            },
+            Some(&HnswIndexConfig {
+                max_elements,
+                m: 16,
+                ef_construction: 100,
+                ef_search: 100,
+                random_seed: 0,
+                persist_path: persist_path.to_string(),
+            }),
+            Uuid::new_v4(),
+        )
+        .unwrap();
+        SharedHnswIndex {
+            index: RwLock::new(index),
+        }
+    }
+
+    /// Adds a vector, taking the lock for the add alone as the segment writer does.
+    pub fn add(&self, id: usize, vector: &[f32]) {
+        HnswIndex::add_growing(&self.index, id, vector, 2.0);
+    }
+
+    /// Adds a batch of vectors under one write lock.
+    pub fn add_batch<'a>(&self, vectors: impl IntoIterator<Item = (usize, &'a [f32])>) {
+        let mut index = self.index.write();
+        for (id, vector) in vectors {
+            if index.is_full() {
+                index.grow(2.0);
+            }
+            index.add(id, vector);
+        }
+    }
+
+    pub fn query(&self, vector: &[f32], k: usize) -> Vec<usize> {
+        self.index.read().query(vector, k, &[], &[]).0
+    }
+}
@@ -0,0 +1,122 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use worker::distance::knn::{par_top_k, par_top_k_batch};
+use worker::distance::{Distance, DistanceFunction};
+
+fn random_vector(dimension: usize) -> Vec<f32> {
+    (0..dimension)
+        .map(|_| rand::random::<f32>() * 2.0 - 1.0)
+        .collect()
+}
+
+// A brute force scan of the candidates of a segment, scored in one batch on the calling
+// thread, and in batches of 1024 spread over compute pools of increasing size.
+fn brute_force_knn(c: &mut Criterion) {
+    let (num_candidates, dimension, k) = (100_000, 384, 10);
+    let query = random_vector(dimension);
+    let embeddings: Vec<Vec<f32>> = (0..num_candidates)
+        .map(|_| random_vector(dimension))
+        .collect();
+    let candidates: Vec<&[f32]> = embeddings.iter().map(|e| e.as_slice()).collect();
+    let distance_function = DistanceFunction::Euclidean;
+
+    let mut group = c.benchmark_group("brute_force_knn");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(num_candidates as u64));
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            std::hint::black_box(distance_function.nearest_neighbors(&query, &candidates, k))
+        });
+    });
+    for num_threads in [1, 2, 4, 8] {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .unwrap();
+        group.bench_with_input(
+            BenchmarkId::new("parallel", num_threads),
+            &num_threads,
+            |b, _| {
+                b.iter(|| {
+                    pool.install(|| {
+                        std::hint::black_box(par_top_k(
+                            &distance_function,
+                            &query,
+                            &candidates,
+                            k,
+                            1024,
+                            || Ok::<(), ()>(()),
+                        ))
+                    })
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+// A request with several query vectors, scanning the candidates once per query vector and
+// once for all of them.
+fn brute_force_knn_batch(c: &mut Criterion) {
+    let (num_candidates, dimension, k) = (100_000, 384, 10);
+    let embeddings: Vec<Vec<f32>> = (0..num_candidates)
+        .map(|_| random_vector(dimension))
+        .collect();
+    let candidates: Vec<&[f32]> = embeddings.iter().map(|e| e.as_slice()).collect();
+    let distance_function = DistanceFunction::Euclidean;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("brute_force_knn_batch");
+    group.sample_size(10);
+    for num_queries in [1, 10, 50] {
+        let query_vectors: Vec<Vec<f32>> =
+            (0..num_queries).map(|_| random_vector(dimension)).collect();
+        let queries: Vec<(&[f32], usize)> =
+            query_vectors.iter().map(|q| (q.as_slice(), k)).collect();
+        group.throughput(Throughput::Elements((num_candidates * num_queries) as u64));
+        group.bench_with_input(
+            BenchmarkId::new("one_by_one", num_queries),
+            &num_queries,
+            |b, _| {
+                b.iter(|| {
+                    pool.install(|| {
+                        for (query, k) in &queries {
+                            std::hint::black_box(par_top_k(
+                                &distance_function,
+                                query,
+                                &candidates,
+                                *k,
+                                1024,
+                                || Ok::<(), ()>(()),
+                            ))
+                            .unwrap();
+                        }
+                    })
+                });
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("batch", num_queries),
+            &num_queries,
+            |b, _| {
+                b.iter(|| {
+                    pool.install(|| {
+                        std::hint::black_box(par_top_k_batch(
+                            &distance_function,
+                            &queries,
+                            &candidates,
+                            1024,
+                            || Ok::<(), ()>(()),
+                        ))
+                    })
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, brute_force_knn, brute_force_knn_batch);
+criterion_main!(benches);
@@ -1,16 +1,20 @@
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use worker::distance::binary;
+use worker::distance::distance_scalar;
 use worker::distance::DistanceFunction;
 
+fn random_vector(dimension: usize) -> Vec<f32> {
+    let mut x: Vec<f32> = Vec::with_capacity(dimension);
+    for _ in 0..x.capacity() {
+        x.push(rand::random());
+    }
+    x
+}
+
 fn distance_metrics(c: &mut Criterion) {
     c.bench_function("distance_metrics", |b| {
-        let mut x: Vec<f32> = Vec::with_capacity(786);
-        for _ in 0..x.capacity() {
-            x.push(rand::random());
-        }
-        let mut y: Vec<f32> = Vec::with_capacity(786);
-        for _ in 0..y.capacity() {
-            y.push(rand::random());
-        }
+        let x = random_vector(786);
+        let y = random_vector(786);
         b.iter(|| {
             let d = DistanceFunction::Cosine;
             std::hint::black_box(DistanceFunction::distance(&d, &x, &y));
@@ -18,5 +22,53 @@ fn distance_metrics(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, distance_metrics,);
+// Compares the runtime-dispatched (SIMD where available) kernels against the scalar
+// fallback at the dimensionality of OpenAI embeddings.
+fn simd_vs_scalar(c: &mut Criterion) {
+    let dimension = 1536;
+    let x = random_vector(dimension);
+    let y = random_vector(dimension);
+    let scalar_kernels: [(&str, fn(&[f32], &[f32]) -> f32); 3] = [
+        ("l2", distance_scalar::euclidean_distance),
+        ("cosine", distance_scalar::cosine_distance),
+        ("ip", distance_scalar::inner_product),
+    ];
+    let distance_functions = [
+        DistanceFunction::Euclidean,
+        DistanceFunction::Cosine,
+        DistanceFunction::InnerProduct,
+    ];
+
+    let mut group = c.benchmark_group("simd_vs_scalar");
+    for ((name, scalar), distance_function) in scalar_kernels.iter().zip(distance_functions.iter())
+    {
+        group.bench_with_input(BenchmarkId::new("scalar", name), &dimension, |b, _| {
+            b.iter(|| std::hint::black_box(scalar(&x, &y)));
+        });
+        group.bench_with_input(BenchmarkId::new("dispatched", name), &dimension, |b, _| {
+            b.iter(|| std::hint::black_box(distance_function.distance(&x, &y)));
+        });
+    }
+    group.finish();
+}
+
+// Documents the speedup of scoring binarized embeddings by Hamming distance over their
+// packed bits, compared to cosine over the f32 embeddings they were binarized from.
+fn hamming_vs_cosine(c: &mut Criterion) {
+    let dimension = 1536;
+    let x = random_vector(dimension);
+    let y = random_vector(dimension);
+    let (x_bits, y_bits) = (binary::pack_bits(&x), binary::pack_bits(&y));
+
+    let mut group = c.benchmark_group("hamming_vs_cosine");
+    group.bench_with_input(BenchmarkId::new("cosine", "f32"), &dimension, |b, _| {
+        b.iter(|| std::hint::black_box(DistanceFunction::Cosine.distance(&x, &y)));
+    });
+    group.bench_with_input(BenchmarkId::new("hamming", "packed"), &dimension, |b, _| {
+        b.iter(|| std::hint::black_box(binary::hamming_distance(&x_bits, &y_bits)));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, distance_metrics, simd_vs_scalar, hamming_vs_cosine);
 criterion_main!(benches);
@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::sync::atomic::{AtomicBool, Ordering};
+use worker::bench::SharedHnswIndex;
+
+// Query latency of an index while it is being written, for a writer that takes the lock
+// for each add as the segment writer does, and for one that holds the write lock for each
+// batch of adds. Writers update the vectors already in the index, so that it keeps its
+// size for as long as the bench runs.
+fn query_latency_during_writes(c: &mut Criterion) {
+    let (n, dimension, k, batch_size) = (20_000, 128, 10, 1000);
+    let data: Vec<f32> = (0..n * dimension)
+        .map(|_| rand::random::<f32>() * 2.0 - 1.0)
+        .collect();
+    let vector = |id: usize| &data[id * dimension..(id + 1) * dimension];
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let index = SharedHnswIndex::new(dimension, n, tmp_dir.path().to_str().unwrap());
+    for id in 0..n {
+        index.add(id, vector(id));
+    }
+
+    let mut group = c.benchmark_group("hnsw_query_latency");
+    group.bench_function("idle", |b| {
+        b.iter(|| std::hint::black_box(index.query(vector(0), k)));
+    });
+    for writer in ["lock_per_add", "write_lock_per_batch"] {
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut start = 0;
+                while !done.load(Ordering::Acquire) {
+                    // Each id gets the vector of its neighbour, so every add moves it.
+                    let batch =
+                        (start..start + batch_size).map(|id| (id % n, vector((id + 1) % n)));
+                    match writer {
+                        "lock_per_add" => batch.for_each(|(id, v)| index.add(id, v)),
+                        _ => index.add_batch(batch),
+                    }
+                    start = (start + batch_size) % n;
+                }
+            });
+            group.bench_with_input(BenchmarkId::new("writing", writer), &writer, |b, _| {
+                b.iter(|| std::hint::black_box(index.query(vector(0), k)));
+            });
+            done.store(true, Ordering::Release);
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, query_latency_during_writes);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+// Reads are verified by hashing every buffer as it is copied out of storage. Compares that
+// against the copy alone, at the buffer size of a read and the default part size.
+fn copy_with_checksum(c: &mut Criterion) {
+    let mut group = c.benchmark_group("storage_checksum");
+    for size in [1024 * 1024, 8 * 1024 * 1024] {
+        let source: Vec<u8> = (0..size).map(|_| rand::random()).collect();
+        let mut destination = vec![0u8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("copy", size), &source, |b, source| {
+            b.iter(|| {
+                destination.copy_from_slice(source);
+                std::hint::black_box(&destination);
+            });
+        });
+        group.bench_with_input(
+            BenchmarkId::new("copy_and_checksum", size),
+            &source,
+            |b, source| {
+                b.iter(|| {
+                    destination.copy_from_slice(source);
+                    std::hint::black_box(crc32c::crc32c_append(0, &destination));
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, copy_with_checksum);
+criterion_main!(benches);
@@ -12,7 +12,7 @@ use crate::{
 };
 use arrow::array::BinaryArray;
 use arrow::{
-    array::{Array, FixedSizeListArray, Float32Array, StringArray, StructArray},
+    array::{Array, FixedSizeListArray, Float32Array, ListArray, StringArray, StructArray},
     util::bit_util,
 };
 use prost::Message;
@@ -115,22 +115,39 @@ impl<'referred_data> ArrowReadableValue<'referred_data> for DataRecord<'referred
             .downcast_ref::<StringArray>()
             .unwrap();
 
-        // Read out embedding
-        let embedding_arr = as_struct_array
-            .column(1)
+        // Read out embedding, from a list when the embeddings of the block have different
+        // lengths
+        let embedding_column = as_struct_array.column(1);
+        let embedding = match embedding_column
             .as_any()
             .downcast_ref::<FixedSizeListArray>()
-            .unwrap();
-        let target_vec = embedding_arr.value(index);
-        let embedding_len = target_vec.len();
-        let embedding_values = embedding_arr
-            .values()
-            .as_any()
-            .downcast_ref::<Float32Array>()
-            .unwrap()
-            .values();
-        let embedding =
-            &embedding_values[(index * embedding_len)..(index * embedding_len) + embedding_len];
+        {
+            Some(embedding_arr) => {
+                let target_vec = embedding_arr.value(index);
+                let embedding_len = target_vec.len();
+                let embedding_values = embedding_arr
+                    .values()
+                    .as_any()
+                    .downcast_ref::<Float32Array>()
+                    .unwrap()
+                    .values();
+                &embedding_values[(index * embedding_len)..(index * embedding_len) + embedding_len]
+            }
+            None => {
+                let embedding_arr = embedding_column
+                    .as_any()
+                    .downcast_ref::<ListArray>()
+                    .unwrap();
+                let offsets = embedding_arr.value_offsets();
+                let embedding_values = embedding_arr
+                    .values()
+                    .as_any()
+                    .downcast_ref::<Float32Array>()
+                    .unwrap()
+                    .values();
+                &embedding_values[offsets[index] as usize..offsets[index + 1] as usize]
+            }
+        };
 
         // Read out metadata
         let metadata_arr = as_struct_array
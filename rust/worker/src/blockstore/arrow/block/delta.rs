@@ -112,8 +112,9 @@ impl BlockDelta {
         self.builder.to_record_batch::<K>()
     }
 
-    /// Splits the block delta into two block deltas. The split point is the last key
-    /// that pushes the block over the half size.
+    /// Splits the block delta into two or more block deltas. The split point is the last key
+    /// that pushes the block over the half size, and a new block delta that is still over the
+    /// max block size is split again.
     /// # Arguments
     /// - provider: the arrow block provider to create the new block.
     /// # Returns
@@ -129,8 +130,11 @@ impl BlockDelta {
         blocks_to_split.push(self.clone());
         let mut output = Vec::new();
         // iterate over all blocks to split until its empty
-        while !blocks_to_split.is_empty() {
-            let curr_block = blocks_to_split.pop().unwrap();
+        while let Some(curr_block) = blocks_to_split.pop() {
+            // A single entry can not be split, even if it is larger than a block by itself.
+            if curr_block.len() < 2 {
+                continue;
+            }
             let mut curr_split_index = 0;
             let mut curr_running_prefix_size = 0;
             let mut curr_running_key_size = 0;
@@ -169,11 +173,16 @@ impl BlockDelta {
                 id: Uuid::new_v4(),
             };
 
+            // Either half can still be over the max block size when it holds a large value.
+            // Deltas share their storage with their clones, so splitting one again keeps its
+            // head in the delta it is already known as and adds the rest as new blocks.
+            if curr_block.get_size::<K, V>() > MAX_BLOCK_SIZE {
+                blocks_to_split.push(curr_block);
+            }
             if new_block.get_size::<K, V>() > MAX_BLOCK_SIZE {
-                blocks_to_split.push(new_block);
-            } else {
-                output.push((split_key.clone(), new_block));
+                blocks_to_split.push(new_block.clone());
             }
+            output.push((split_key, new_block));
         }
 
         return output;
@@ -380,6 +389,102 @@ mod test {
         assert_eq!(size, block.get_size());
     }
 
+    #[tokio::test]
+    async fn test_block_from_bytes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let block_manager = BlockManager::new(storage);
+        let delta = block_manager.create::<bool, u32>();
+        for i in 0..10u32 {
+            delta.add(format!("prefix{}", i).as_str(), i % 2 == 0, i);
+        }
+        block_manager.commit::<bool, u32>(&delta);
+        let block = block_manager.get(&delta.id).await.unwrap();
+
+        let loaded = Block::from_bytes(&block.to_bytes(), delta.id).unwrap();
+        assert_eq!(loaded.id, delta.id);
+        assert_eq!(loaded.len(), 10);
+        for i in 0..10u32 {
+            let read = loaded.get::<bool, u32>(format!("prefix{}", i).as_str(), i % 2 == 0);
+            assert_eq!(read, Some(i));
+        }
+        assert_eq!(loaded.get::<bool, u32>("prefix0", false), None);
+
+        // Truncated or foreign bytes are an error rather than a panic.
+        let bytes = block.to_bytes();
+        assert!(Block::from_bytes(&bytes[..bytes.len() / 2], delta.id).is_err());
+        assert!(Block::from_bytes(b"not a block", delta.id).is_err());
+    }
+
+    // The address ranges the file at `path` is mapped at in this process.
+    fn mapped_ranges(path: &std::path::Path) -> Vec<std::ops::Range<usize>> {
+        let path = path.to_str().unwrap();
+        std::fs::read_to_string("/proc/self/maps")
+            .unwrap()
+            .lines()
+            .filter(|line| line.ends_with(path))
+            .map(|line| {
+                let (start, end) = line.split(' ').next().unwrap().split_once('-').unwrap();
+                let start = usize::from_str_radix(start, 16).unwrap();
+                start..usize::from_str_radix(end, 16).unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_block_load_mmap() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let block_manager = BlockManager::new(storage);
+        let delta = block_manager.create::<&str, &DataRecord>();
+        let ids: Vec<String> = (0..100).map(|i| format!("embedding_id_{:03}", i)).collect();
+        let embeddings: Vec<Vec<f32>> = (0..100)
+            .map(|_| (0..7).map(|_| random()).collect())
+            .collect();
+        for (id, embedding) in ids.iter().zip(embeddings.iter()) {
+            let record = DataRecord {
+                id,
+                embedding,
+                metadata: None,
+                document: None,
+            };
+            delta.add("", id.as_str(), &record);
+        }
+        block_manager.commit::<&str, &DataRecord>(&delta);
+        let block = block_manager.get(&delta.id).await.unwrap();
+        let path = tmp_dir.path().join("block.arrow");
+        std::fs::write(&path, block.to_bytes()).unwrap();
+
+        let mapped = Block::load_mmap(&path, delta.id).unwrap();
+        assert_eq!(mapped.id, delta.id);
+        assert_eq!(mapped.data, block.data);
+        let ranges = mapped_ranges(&path);
+        assert!(!ranges.is_empty());
+        for (id, embedding) in ids.iter().zip(embeddings.iter()) {
+            let read = mapped.get::<&str, DataRecord>("", id).unwrap();
+            assert_eq!(read.embedding, embedding.as_slice());
+            // The embedding is read in place from the mapping, not from a copy.
+            let address = read.embedding.as_ptr() as usize;
+            assert!(ranges.iter().any(|range| range.contains(&address)));
+        }
+
+        let clone = mapped.clone();
+        drop(mapped);
+        assert!(!mapped_ranges(&path).is_empty());
+        drop(clone);
+        assert!(mapped_ranges(&path).is_empty());
+
+        // Truncated or foreign files are an error rather than a panic.
+        let bytes = block.to_bytes();
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        assert!(Block::load_mmap(&path, delta.id).is_err());
+        std::fs::write(&path, b"not a block").unwrap();
+        assert!(Block::load_mmap(&path, delta.id).is_err());
+        std::fs::write(&path, b"").unwrap();
+        assert!(Block::load_mmap(&path, delta.id).is_err());
+        assert!(Block::load_mmap(&tmp_dir.path().join("missing"), delta.id).is_err());
+    }
+
     // #[test]
     // fn test_sizing_uint_key_val() {
     //     let block_provider = ArrowBlockProvider::new();
@@ -646,17 +646,79 @@ impl DataRecordStorage {
             .fold(0, |acc, (_, value)| acc + value.len())
     }
 
+    // The length of the embeddings from `start` to `end`, if they all have the same one.
+    // Embeddings of different lengths, like sparse ones, are stored as a list with an
+    // offset for each rather than as a fixed size list.
+    fn get_embedding_len(&self, start: usize, end: usize) -> Option<usize> {
+        let embedding_storage = self.embedding_storage.read();
+        let mut lens = embedding_storage
+            .iter()
+            .skip(start)
+            .take(end - start)
+            .map(|(_, value)| value.len());
+        let first = lens.next().unwrap_or(0);
+        lens.all(|len| len == first).then_some(first)
+    }
+
     fn get_value_size(&self, start: usize, end: usize) -> usize {
         let id_size = bit_util::round_upto_multiple_of_64(self.get_id_size(start, end));
         let embedding_size =
             bit_util::round_upto_multiple_of_64(self.get_embedding_size(start, end));
+        let embedding_offset_size = match self.get_embedding_len(start, end) {
+            Some(_) => 0,
+            None => bit_util::round_upto_multiple_of_64((end - start + 1) * 4),
+        };
         let metadata_size = bit_util::round_upto_multiple_of_64(self.get_metadata_size(start, end));
         let document_size = bit_util::round_upto_multiple_of_64(self.get_document_size(start, end));
-        let total_size = id_size + embedding_size + metadata_size + document_size;
+        let total_size =
+            id_size + embedding_size + embedding_offset_size + metadata_size + document_size;
 
         total_size
     }
 
+    fn embeddings_to_arrow(&self) -> (Field, ArrayRef) {
+        let item_capacity = self.len();
+        let values_builder = Float32Builder::with_capacity(self.get_total_embedding_count());
+        let item_field = Arc::new(Field::new(
+            "item",
+            arrow::datatypes::DataType::Float32,
+            true,
+        ));
+        let embedding_len = self.get_embedding_len(0, item_capacity);
+        let embedding_storage = self.embedding_storage.read();
+        match embedding_len {
+            Some(embedding_len) => {
+                let mut builder = FixedSizeListBuilder::with_capacity(
+                    values_builder,
+                    embedding_len as i32,
+                    item_capacity,
+                );
+                for (_, embedding) in embedding_storage.iter() {
+                    builder.values().append_slice(embedding);
+                    builder.append(true);
+                }
+                let data_type =
+                    arrow::datatypes::DataType::FixedSizeList(item_field, embedding_len as i32);
+                (
+                    Field::new("embedding", data_type, true),
+                    Arc::new(builder.finish()),
+                )
+            }
+            None => {
+                let mut builder = ListBuilder::with_capacity(values_builder, item_capacity);
+                for (_, embedding) in embedding_storage.iter() {
+                    builder.values().append_slice(embedding);
+                    builder.append(true);
+                }
+                let data_type = arrow::datatypes::DataType::List(item_field);
+                (
+                    Field::new("embedding", data_type, true),
+                    Arc::new(builder.finish()),
+                )
+            }
+        }
+    }
+
     fn split(&self, prefix: &str, key: KeyWrapper) -> DataRecordStorage {
         let mut id_storage_guard = self.id_storage.write();
         let mut embedding_storage_guard = self.embedding_storage.write();
@@ -706,66 +768,38 @@ impl DataRecordStorage {
 
     fn to_arrow(&self) -> (Field, ArrayRef) {
         let item_capacity = self.len();
-        let embedding_len = self.embedding_storage.read().iter().next().unwrap().1.len() as i32;
+        let (embedding_field, embedding_arr) = self.embeddings_to_arrow();
         let mut id_builder =
             StringBuilder::with_capacity(item_capacity, self.get_id_size(0, self.len()));
-        let mut embedding_builder = FixedSizeListBuilder::with_capacity(
-            Float32Builder::with_capacity(self.get_total_embedding_count()),
-            embedding_len,
-            item_capacity,
-        );
         let mut metadata_builder =
             BinaryBuilder::with_capacity(item_capacity, self.get_metadata_size(0, self.len()));
         let mut document_builder =
             StringBuilder::with_capacity(item_capacity, self.get_document_size(0, self.len()));
 
         let id_storage = self.id_storage.read();
-        let embedding_storage = self.embedding_storage.read();
         let metadata_storage = self.metadata_storage.read();
         let document_storage = self.document_storage.read();
         let iter = id_storage
             .iter()
-            .zip(embedding_storage.iter())
             .zip(metadata_storage.iter())
             .zip(document_storage.iter());
-        for ((((_, id), (_, embedding)), (_, metadata)), (_, document)) in iter {
+        for (((_, id), (_, metadata)), (_, document)) in iter {
             id_builder.append_value(id);
-            let embedding_arr = embedding_builder.values();
-            for entry in embedding.iter() {
-                embedding_arr.append_value(*entry);
-            }
-            embedding_builder.append(true);
             metadata_builder.append_option(metadata.as_deref());
             document_builder.append_option(document.as_deref());
         }
 
         let id_field = Field::new("id", arrow::datatypes::DataType::Utf8, true);
-        let embedding_field = Field::new(
-            "embedding",
-            arrow::datatypes::DataType::FixedSizeList(
-                Arc::new(Field::new(
-                    "item",
-                    arrow::datatypes::DataType::Float32,
-                    true,
-                )),
-                embedding_len,
-            ),
-            true,
-        );
         let metadata_field = Field::new("metadata", arrow::datatypes::DataType::Binary, true);
         let document_field = Field::new("document", arrow::datatypes::DataType::Utf8, true);
 
         let id_arr = id_builder.finish();
-        let embedding_arr = embedding_builder.finish();
         let metadata_arr = metadata_builder.finish();
         let document_arr = document_builder.finish();
 
         let struct_arr = StructArray::from(vec![
             (Arc::new(id_field.clone()), Arc::new(id_arr) as ArrayRef),
-            (
-                Arc::new(embedding_field.clone()),
-                Arc::new(embedding_arr) as ArrayRef,
-            ),
+            (Arc::new(embedding_field.clone()), embedding_arr),
             (
                 Arc::new(metadata_field.clone()),
                 Arc::new(metadata_arr) as ArrayRef,
@@ -1,10 +1,21 @@
 use super::delta::BlockDelta;
 use crate::blockstore::arrow::types::{ArrowReadableKey, ArrowReadableValue};
-use crate::errors::ChromaError;
+use crate::errors::{ChromaError, ErrorCodes};
 use arrow::{
     array::{Array, StringArray},
+    buffer::Buffer,
+    error::ArrowError,
+    ipc::{
+        convert::fb_to_schema,
+        reader::{read_footer_length, FileDecoder},
+        root_as_footer,
+    },
     record_batch::RecordBatch,
 };
+use std::path::Path;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use thiserror::Error;
 use uuid::Uuid;
 
 /// A block in a blockfile. A block is a sorted collection of data that is immutable once it has been committed.
@@ -263,30 +274,99 @@ impl Block {
 
     pub fn from_bytes(bytes: &[u8], id: Uuid) -> Result<Self, Box<dyn ChromaError>> {
         let cursor = std::io::Cursor::new(bytes);
-        let mut reader =
-            arrow::ipc::reader::FileReader::try_new(cursor, None).expect("Error creating reader");
-        return Self::load_with_reader(reader, id);
+        let reader = match arrow::ipc::reader::FileReader::try_new(cursor, None) {
+            Ok(reader) => reader,
+            Err(e) => return Err(Box::new(BlockLoadError::ArrowError(e))),
+        };
+        Self::load_with_reader(reader, id)
     }
 
     pub fn load(path: &str, id: Uuid) -> Result<Self, Box<dyn ChromaError>> {
-        let file = std::fs::File::open(path);
-        let file = match file {
+        let file = match std::fs::File::open(path) {
             Ok(file) => file,
-            Err(e) => {
-                // TODO: Return a proper error
-                panic!("Error opening file: {:?}", e)
-            }
+            Err(e) => return Err(Box::new(BlockLoadError::IOError(e))),
         };
         let mut reader = std::io::BufReader::new(file);
-        let reader = arrow::ipc::reader::FileReader::try_new(&mut reader, None);
-        let mut reader = match reader {
+        let reader = match arrow::ipc::reader::FileReader::try_new(&mut reader, None) {
             Ok(reader) => reader,
-            Err(e) => {
-                // TODO: Return a proper error
-                panic!("Error creating reader: {:?}", e)
+            Err(e) => return Err(Box::new(BlockLoadError::ArrowError(e))),
+        };
+        Self::load_with_reader(reader, id)
+    }
+
+    /// Loads a block from a file by memory-mapping it, instead of reading it onto the heap.
+    /// # Description
+    /// The arrays of the block are backed by the mapping, so values like the embeddings of
+    /// data records are read in place from the page cache. A buffer is only copied out when
+    /// its offset in the file is misaligned for its type, which the IPC writer never does.
+    /// The file is unmapped once the last clone of the block, and of any array taken from it,
+    /// is dropped.
+    /// # Notes
+    /// The file must not be changed while it is mapped. Blocks are immutable, and the block
+    /// manager only writes the file of a block once, under a temporary name.
+    pub fn load_mmap(path: &Path, id: Uuid) -> Result<Self, Box<dyn ChromaError>> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Err(Box::new(BlockLoadError::IOError(e))),
+        };
+        // SAFETY: See the notes above, the file is not changed while it is mapped.
+        let mmap = match unsafe { memmap2::Mmap::map(&file) } {
+            Ok(mmap) => mmap,
+            Err(e) => return Err(Box::new(BlockLoadError::IOError(e))),
+        };
+        let buffer = match NonNull::new(mmap.as_ptr() as *mut u8) {
+            // SAFETY: The buffer owns the mapping, which outlives every slice of the buffer.
+            Some(ptr) => unsafe { Buffer::from_custom_allocation(ptr, mmap.len(), Arc::new(mmap)) },
+            None => return Err(Box::new(BlockLoadError::NoRecordBatch)),
+        };
+        match Self::decode_file(&buffer) {
+            Ok(batch) => Ok(Self::from_record_batch(id, batch)),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Decodes the record batch of an IPC file without copying its buffers, following the
+    // example of `FileDecoder`.
+    fn decode_file(buffer: &Buffer) -> Result<RecordBatch, BlockLoadError> {
+        let invalid = |message: &str| ArrowError::ParseError(message.to_string());
+        let trailer_start = match buffer.len().checked_sub(10) {
+            Some(trailer_start) => trailer_start,
+            None => return Err(invalid("The file is too short for an IPC file").into()),
+        };
+        let footer_len = read_footer_length(buffer[trailer_start..].try_into().unwrap())?;
+        let footer_start = match trailer_start.checked_sub(footer_len) {
+            Some(footer_start) => footer_start,
+            None => return Err(invalid("The footer is longer than the file").into()),
+        };
+        let footer = root_as_footer(&buffer[footer_start..trailer_start])
+            .map_err(|e| ArrowError::ParseError(format!("Unable to read the footer: {}", e)))?;
+        let schema = match footer.schema() {
+            Some(schema) => fb_to_schema(schema),
+            None => return Err(invalid("The footer has no schema").into()),
+        };
+        let mut decoder = FileDecoder::new(Arc::new(schema), footer.version());
+        let slice = |block: &arrow::ipc::Block| {
+            let start = block.offset() as usize;
+            let len = block.metaDataLength() as usize + block.bodyLength() as usize;
+            match start.checked_add(len) {
+                Some(end) if end <= footer_start => Ok(buffer.slice_with_length(start, len)),
+                _ => Err(invalid("A block of the file is out of bounds")),
             }
         };
-        return Self::load_with_reader(reader, id);
+        for block in footer.dictionaries().iter().flatten() {
+            decoder.read_dictionary(block, &slice(block)?)?;
+        }
+        let block = match footer
+            .recordBatches()
+            .and_then(|batches| batches.iter().next())
+        {
+            Some(block) => block,
+            None => return Err(BlockLoadError::NoRecordBatch),
+        };
+        match decoder.read_record_batch(block, &slice(block)?)? {
+            Some(batch) => Ok(batch),
+            None => Err(BlockLoadError::NoRecordBatch),
+        }
     }
 
     fn load_with_reader<R>(
@@ -296,17 +376,31 @@ impl Block {
     where
         R: std::io::Read + std::io::Seek,
     {
-        let batch = reader.next().unwrap();
         // TODO: how to store / hydrate id?
-        match batch {
-            Ok(batch) => Ok(Self::from_record_batch(id, batch)),
-            Err(e) => {
-                panic!("Error reading batch: {:?}", e);
-            }
+        match reader.next() {
+            Some(Ok(batch)) => Ok(Self::from_record_batch(id, batch)),
+            Some(Err(e)) => Err(Box::new(BlockLoadError::ArrowError(e))),
+            None => Err(Box::new(BlockLoadError::NoRecordBatch)),
         }
     }
 }
 
+#[derive(Error, Debug)]
+pub enum BlockLoadError {
+    #[error("IO Error")]
+    IOError(#[from] std::io::Error),
+    #[error("Arrow Error")]
+    ArrowError(#[from] arrow::error::ArrowError),
+    #[error("No record batch in the block data")]
+    NoRecordBatch,
+}
+
+impl ChromaError for BlockLoadError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::Internal
+    }
+}
+
 // #[derive(Error, Debug)]
 // pub enum FinishError {
 //     #[error("Arrow error")]
@@ -10,8 +10,9 @@ use crate::blockstore::key::KeyWrapper;
 use crate::blockstore::BlockfileError;
 use crate::errors::ErrorCodes;
 use crate::{blockstore::key::CompositeKey, errors::ChromaError};
+use futures::{Stream, StreamExt};
 use parking_lot::Mutex;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, ops::Range, sync::Arc};
 use std::{collections::HashSet, mem::transmute};
 use thiserror::Error;
 use uuid::Uuid;
@@ -325,151 +326,114 @@ impl<'me, K: ArrowReadableKey<'me> + Into<KeyWrapper>, V: ArrowReadableValue<'me
         }
     }
 
+    // Streams the records that `read` picks out of each of the blocks, fetching a block
+    // only once the records of the blocks before it have been consumed.
+    fn scan<F>(
+        &'me self,
+        block_ids: Vec<Uuid>,
+        read: F,
+    ) -> impl Stream<Item = Result<(&'me str, K, V), Box<dyn ChromaError>>>
+    where
+        F: Fn(&'me Block) -> Option<Vec<(&'me str, K, V)>>,
+    {
+        futures::stream::iter(block_ids)
+            .then(move |block_id| self.get_block(block_id))
+            .flat_map(move |block| {
+                let records: Vec<Result<_, Box<dyn ChromaError>>> = match block {
+                    Some(block) => match read(block) {
+                        Some(data) => data.into_iter().map(Ok).collect(),
+                        None => vec![Err(Box::new(BlockfileError::NotFoundError))],
+                    },
+                    None => vec![Err(Box::new(ArrowBlockfileError::BlockNotFound))],
+                };
+                futures::stream::iter(records)
+            })
+    }
+
     /// Returns all arrow records whose key > supplied key.
-    pub(crate) async fn get_gt(
+    pub(crate) fn get_gt(
         &'me self,
         prefix: &str,
         key: K,
-    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+    ) -> impl Stream<Item = Result<(&'me str, K, V), Box<dyn ChromaError>>> {
         // Get all block ids that contain keys > key from sparse index for this prefix.
         let block_ids = self.sparse_index.get_block_ids_gt(prefix, key.clone());
-        let mut result: Vec<(&str, K, V)> = vec![];
-        // Read all the blocks individually to get keys > key.
-        for block_id in block_ids {
-            let block_opt = self.get_block(block_id).await;
-            let block = match block_opt {
-                Some(b) => b,
-                None => {
-                    return Err(Box::new(ArrowBlockfileError::BlockNotFound));
-                }
-            };
-            match block.get_gt(prefix, key.clone()) {
-                Some(data) => {
-                    result.extend(data);
-                }
-                None => {
-                    return Err(Box::new(BlockfileError::NotFoundError));
-                }
-            };
-        }
-        return Ok(result);
+        let prefix = prefix.to_string();
+        self.scan(block_ids, move |block| block.get_gt(&prefix, key.clone()))
     }
 
     /// Returns all arrow records whose key < supplied key.
-    pub(crate) async fn get_lt(
+    pub(crate) fn get_lt(
         &'me self,
         prefix: &str,
         key: K,
-    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+    ) -> impl Stream<Item = Result<(&'me str, K, V), Box<dyn ChromaError>>> {
         // Get all block ids that contain keys < key from sparse index.
         let block_ids = self.sparse_index.get_block_ids_lt(prefix, key.clone());
-        let mut result: Vec<(&str, K, V)> = vec![];
-        // Read all the blocks individually to get keys < key.
-        for block_id in block_ids {
-            let block_opt = self.get_block(block_id).await;
-            let block = match block_opt {
-                Some(b) => b,
-                None => {
-                    return Err(Box::new(ArrowBlockfileError::BlockNotFound));
-                }
-            };
-            match block.get_lt(prefix, key.clone()) {
-                Some(data) => {
-                    result.extend(data);
-                }
-                None => {
-                    return Err(Box::new(BlockfileError::NotFoundError));
-                }
-            };
-        }
-        return Ok(result);
+        let prefix = prefix.to_string();
+        self.scan(block_ids, move |block| block.get_lt(&prefix, key.clone()))
     }
 
     /// Returns all arrow records whose key >= supplied key.
-    pub(crate) async fn get_gte(
+    pub(crate) fn get_gte(
         &'me self,
         prefix: &str,
         key: K,
-    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+    ) -> impl Stream<Item = Result<(&'me str, K, V), Box<dyn ChromaError>>> {
         // Get all block ids that contain keys >= key from sparse index.
         let block_ids = self.sparse_index.get_block_ids_gte(prefix, key.clone());
-        let mut result: Vec<(&str, K, V)> = vec![];
-        // Read all the blocks individually to get keys >= key.
-        for block_id in block_ids {
-            let block_opt = self.get_block(block_id).await;
-            let block = match block_opt {
-                Some(b) => b,
-                None => {
-                    return Err(Box::new(ArrowBlockfileError::BlockNotFound));
-                }
-            };
-            match block.get_gte(prefix, key.clone()) {
-                Some(data) => {
-                    result.extend(data);
-                }
-                None => {
-                    return Err(Box::new(BlockfileError::NotFoundError));
-                }
-            };
-        }
-        return Ok(result);
+        let prefix = prefix.to_string();
+        self.scan(block_ids, move |block| block.get_gte(&prefix, key.clone()))
     }
 
     /// Returns all arrow records whose key <= supplied key.
-    pub(crate) async fn get_lte(
+    pub(crate) fn get_lte(
         &'me self,
         prefix: &str,
         key: K,
-    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+    ) -> impl Stream<Item = Result<(&'me str, K, V), Box<dyn ChromaError>>> {
         // Get all block ids that contain keys <= key from sparse index.
         let block_ids = self.sparse_index.get_block_ids_lte(prefix, key.clone());
-        let mut result: Vec<(&str, K, V)> = vec![];
-        // Read all the blocks individually to get keys <= key.
-        for block_id in block_ids {
-            let block_opt = self.get_block(block_id).await;
-            let block = match block_opt {
-                Some(b) => b,
-                None => {
-                    return Err(Box::new(ArrowBlockfileError::BlockNotFound));
-                }
-            };
-            match block.get_lte(prefix, key.clone()) {
-                Some(data) => {
-                    result.extend(data);
-                }
-                None => {
-                    return Err(Box::new(BlockfileError::NotFoundError));
-                }
-            };
-        }
-        return Ok(result);
+        let prefix = prefix.to_string();
+        self.scan(block_ids, move |block| block.get_lte(&prefix, key.clone()))
+    }
+
+    /// Returns all arrow records whose key is in the supplied range, in key order.
+    pub(crate) fn get_range(
+        &'me self,
+        prefix: &str,
+        range: Range<K>,
+    ) -> impl Stream<Item = Result<(&'me str, K, V), Box<dyn ChromaError>>> {
+        // Only the blocks that can hold keys >= start and keys < end.
+        let block_ids_lt: HashSet<Uuid> = self
+            .sparse_index
+            .get_block_ids_lt(prefix, range.end.clone())
+            .into_iter()
+            .collect();
+        let block_ids = self
+            .sparse_index
+            .get_block_ids_gte(prefix, range.start.clone())
+            .into_iter()
+            .filter(|block_id| block_ids_lt.contains(block_id))
+            .collect();
+        let prefix = prefix.to_string();
+        self.scan(block_ids, move |block| {
+            block.get_gte(&prefix, range.start.clone()).map(|data| {
+                data.into_iter()
+                    .filter(|(_, key, _)| *key < range.end)
+                    .collect()
+            })
+        })
     }
 
     /// Returns all arrow records whose prefix is same as supplied prefix.
-    pub(crate) async fn get_by_prefix(
+    pub(crate) fn get_by_prefix(
         &'me self,
         prefix: &str,
-    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+    ) -> impl Stream<Item = Result<(&'me str, K, V), Box<dyn ChromaError>>> {
         let block_ids = self.sparse_index.get_block_ids_prefix(prefix);
-        let mut result: Vec<(&str, K, V)> = vec![];
-        for block_id in block_ids {
-            let block_opt = self.get_block(block_id).await;
-            let block = match block_opt {
-                Some(b) => b,
-                None => {
-                    return Err(Box::new(ArrowBlockfileError::BlockNotFound));
-                }
-            };
-            match block.get_prefix(prefix) {
-                Some(data) => {
-                    result.extend(data);
-                }
-                None => {
-                    return Err(Box::new(BlockfileError::NotFoundError));
-                }
-            };
-        }
-        Ok(result)
+        let prefix = prefix.to_string();
+        self.scan(block_ids, move |block| block.get_prefix(&prefix))
     }
 
     pub(crate) async fn contains(&'me self, prefix: &str, key: K) -> bool {
@@ -518,6 +482,7 @@ impl<'me, K: ArrowReadableKey<'me> + Into<KeyWrapper>, V: ArrowReadableValue<'me
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::{
         blockstore::arrow::{blockfile::MAX_BLOCK_SIZE, provider::ArrowBlockfileProvider},
         log::config::{self, GrpcLogConfig},
@@ -526,9 +491,11 @@ mod tests {
         types::MetadataValue,
     };
     use arrow::array::Int32Array;
+    use futures::{StreamExt, TryStreamExt};
     use proptest::prelude::*;
     use proptest::test_runner::Config;
-    use rand::seq::IteratorRandom;
+    use rand::seq::{IteratorRandom, SliceRandom};
+    use rand::Rng;
     use std::{
         collections::HashMap,
         time::{SystemTime, UNIX_EPOCH},
@@ -591,7 +558,10 @@ mod tests {
             let reader = blockfile_provider.open::<&str, u32>(&id).await.unwrap();
             let prefix_query = format!("{}/{}", "prefix", prefix_for_query);
             println!("Query {}, num_keys {}", prefix_query, num_keys);
-            let res = reader.get_by_prefix(prefix_query.as_str()).await;
+            let res = reader
+                .get_by_prefix(prefix_query.as_str())
+                .try_collect::<Vec<_>>()
+                .await;
             match res {
                 Ok(c) => {
                     let mut kv_map = HashMap::new();
@@ -645,13 +615,29 @@ mod tests {
             println!("Query {}", query);
             println!("Operation {:?}", operation);
             let greater_than = match operation {
-                ComparisonOperation::GreaterThan => reader.get_gt(prefix, query.as_str()).await,
+                ComparisonOperation::GreaterThan => {
+                    reader
+                        .get_gt(prefix, query.as_str())
+                        .try_collect::<Vec<_>>()
+                        .await
+                }
                 ComparisonOperation::GreaterThanOrEquals => {
-                    reader.get_gte(prefix, query.as_str()).await
+                    reader
+                        .get_gte(prefix, query.as_str())
+                        .try_collect::<Vec<_>>()
+                        .await
+                }
+                ComparisonOperation::LessThan => {
+                    reader
+                        .get_lt(prefix, query.as_str())
+                        .try_collect::<Vec<_>>()
+                        .await
                 }
-                ComparisonOperation::LessThan => reader.get_lt(prefix, query.as_str()).await,
                 ComparisonOperation::LessThanOrEquals => {
-                    reader.get_lte(prefix, query.as_str()).await
+                    reader
+                        .get_lte(prefix, query.as_str())
+                        .try_collect::<Vec<_>>()
+                        .await
                 }
                 _ => {
                     assert!(true, "Invalid operation");
@@ -1058,6 +1044,120 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_data_record_embeddings_of_different_lengths() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = ArrowBlockfileProvider::new(storage.clone());
+
+        let writer = blockfile_provider.create::<&str, &DataRecord>().unwrap();
+        let id = writer.id();
+
+        // Like sparse embeddings, which have as many elements as they have components.
+        let n = 2000;
+        let embedding = |i: usize| (0..i % 7).map(|j| (i + j) as f32).collect::<Vec<_>>();
+        for i in 0..n {
+            let key = format!("{:04}", i);
+            let value = DataRecord {
+                id: &key,
+                embedding: &embedding(i),
+                document: None,
+                metadata: None,
+            };
+            writer.set("key", key.as_str(), &value).await.unwrap();
+        }
+        writer
+            .commit::<&str, &DataRecord>()
+            .unwrap()
+            .flush::<&str, &DataRecord>()
+            .await
+            .unwrap();
+
+        // A fresh provider reads the blocks back from storage.
+        let reader = ArrowBlockfileProvider::new(storage)
+            .open::<&str, DataRecord>(&id)
+            .await
+            .unwrap();
+        assert!(num_blocks(&reader) > 1);
+        for i in 0..n {
+            let key = format!("{:04}", i);
+            let value = reader.get("key", &key).await.unwrap();
+            assert_eq!(value.id, key);
+            assert_eq!(value.embedding, embedding(i).as_slice());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mmap_reads_match_heap_reads() {
+        use crate::distance::{Distance, DistanceFunction};
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let writer = ArrowBlockfileProvider::new(storage.clone())
+            .create::<&str, &DataRecord>()
+            .unwrap();
+        let id = writer.id();
+        let n = 2000;
+        let mut rng = rand::thread_rng();
+        let embeddings: Vec<Vec<f32>> = (0..n)
+            .map(|_| (0..16).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+        for (i, embedding) in embeddings.iter().enumerate() {
+            let key = format!("{:04}", i);
+            let value = DataRecord {
+                id: &key,
+                embedding,
+                document: None,
+                metadata: None,
+            };
+            writer.set("key", key.as_str(), &value).await.unwrap();
+        }
+        writer
+            .commit::<&str, &DataRecord>()
+            .unwrap()
+            .flush::<&str, &DataRecord>()
+            .await
+            .unwrap();
+
+        let mmap_dir = tempfile::tempdir().unwrap();
+        let heap_provider = ArrowBlockfileProvider::new(storage.clone());
+        let mmap_provider =
+            ArrowBlockfileProvider::new_mmap(storage, mmap_dir.path().join("blocks"));
+        let heap_reader = heap_provider.open::<&str, DataRecord>(&id).await.unwrap();
+        let mmap_reader = mmap_provider.open::<&str, DataRecord>(&id).await.unwrap();
+        assert!(num_blocks(&mmap_reader) > 1);
+        let keys: Vec<String> = (0..n).map(|i| format!("{:04}", i)).collect();
+        let mut heap_embeddings = Vec::new();
+        let mut mmap_embeddings = Vec::new();
+        for key in keys.iter() {
+            heap_embeddings.push(heap_reader.get("key", key).await.unwrap().embedding);
+            mmap_embeddings.push(mmap_reader.get("key", key).await.unwrap().embedding);
+        }
+        assert_eq!(heap_embeddings, mmap_embeddings);
+        assert_eq!(mmap_embeddings[7], embeddings[7].as_slice());
+
+        let maps = || std::fs::read_to_string("/proc/self/maps").unwrap();
+        let mapped_dir = mmap_dir.path().join("blocks");
+        // The files are gone from the disk as soon as they are mapped.
+        assert_eq!(std::fs::read_dir(&mapped_dir).unwrap().count(), 0);
+        let mapped_dir = mapped_dir.to_str().unwrap();
+        assert!(maps().contains(mapped_dir));
+        let query: Vec<f32> = (0..16).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        for distance_function in [DistanceFunction::Euclidean, DistanceFunction::Cosine] {
+            assert_eq!(
+                distance_function.nearest_neighbors(&query, &mmap_embeddings, 10),
+                distance_function.nearest_neighbors(&query, &heap_embeddings, 10)
+            );
+        }
+
+        // The blocks are unmapped once nothing reads from them anymore.
+        drop(mmap_embeddings);
+        drop(mmap_reader);
+        assert!(maps().contains(mapped_dir));
+        drop(mmap_provider);
+        assert!(!maps().contains(mapped_dir));
+    }
+
     #[tokio::test]
     async fn test_large_split_value() {
         // Tests the case where a value is larger than half the block size
@@ -1083,6 +1183,180 @@ mod tests {
         assert_eq!(val_2, val_2_large);
     }
 
+    #[tokio::test]
+    async fn test_range_reads_across_blocks() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = ArrowBlockfileProvider::new(storage);
+        let writer = blockfile_provider.create::<u32, &str>().unwrap();
+        let id = writer.id();
+
+        // Each prefix spans several blocks, and blocks start in the middle of a prefix.
+        let n = 1000;
+        let prefixes = ["a", "b", "c"];
+        let value = "v".repeat(32);
+        for prefix in prefixes {
+            for i in 0..n {
+                writer.set(prefix, i, value.as_str()).await.unwrap();
+            }
+        }
+        writer.commit::<u32, &str>().unwrap();
+
+        let reader = blockfile_provider.open::<u32, &str>(&id).await.unwrap();
+        match &reader {
+            crate::blockstore::BlockfileReader::ArrowBlockfileReader(reader) => {
+                assert!(reader.sparse_index.len() > prefixes.len() * 2);
+            }
+            _ => panic!("Unexpected reader type"),
+        }
+
+        // Blocks are only fetched once the reads get to them.
+        let mut values = Box::pin(reader.get_by_prefix("a"));
+        assert_eq!(values.next().await.unwrap().unwrap().1, 0);
+        match &reader {
+            crate::blockstore::BlockfileReader::ArrowBlockfileReader(reader) => {
+                assert_eq!(reader.loaded_blocks.lock().len(), 1);
+            }
+            _ => panic!("Unexpected reader type"),
+        }
+
+        let keys = |values: Vec<(&str, u32, &str)>, prefix: &str| -> Vec<u32> {
+            assert!(values.iter().all(|(p, _, _)| *p == prefix));
+            values.into_iter().map(|(_, key, _)| key).collect()
+        };
+        for prefix in prefixes {
+            for (start, end) in [(0, n), (10, 20), (n / 3, n * 2 / 3), (n - 1, n)] {
+                let values = reader
+                    .get_range(prefix, start..end)
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .unwrap();
+                assert_eq!(keys(values, prefix), (start..end).collect::<Vec<u32>>());
+            }
+            let values = reader
+                .get_gt(prefix, n / 2)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(keys(values, prefix), (n / 2 + 1..n).collect::<Vec<u32>>());
+            let values = reader
+                .get_gte(prefix, n - 10)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(keys(values, prefix), (n - 10..n).collect::<Vec<u32>>());
+            let values = reader
+                .get_lt(prefix, 10)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(keys(values, prefix), (0..10).collect::<Vec<u32>>());
+            let values = reader
+                .get_by_prefix(prefix)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(keys(values, prefix), (0..n).collect::<Vec<u32>>());
+
+            assert!(reader
+                .get_range(prefix, 5..5)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .is_empty());
+            assert!(reader
+                .get_range(prefix, n..n + 10)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .is_empty());
+        }
+        assert!(reader
+            .get_range("d", 0..n)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(reader
+            .get_range("0", 0..n)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_split_large_values_in_random_order() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = ArrowBlockfileProvider::new(storage);
+        let writer = blockfile_provider.create::<&str, &str>().unwrap();
+        let id = writer.id();
+
+        let n = 300;
+        let mut rng = rand::thread_rng();
+        // Mostly small values, with some larger than a whole block.
+        let values: Vec<String> = (0..n)
+            .map(|i| {
+                let len = match i % 25 {
+                    0 => MAX_BLOCK_SIZE * 3 / 2,
+                    1 => MAX_BLOCK_SIZE / 2,
+                    _ => rng.gen_range(1..MAX_BLOCK_SIZE / 16),
+                };
+                format!("{:04}", i).repeat(len / 4 + 1)
+            })
+            .collect();
+        let mut order: Vec<usize> = (0..n).collect();
+        order.shuffle(&mut rng);
+        for i in order {
+            let key = format!("{:04}", i);
+            writer
+                .set("key", key.as_str(), values[i].as_str())
+                .await
+                .unwrap();
+        }
+        writer.commit::<&str, &str>().unwrap();
+
+        let reader = blockfile_provider.open::<&str, &str>(&id).await.unwrap();
+        let reader = match &reader {
+            crate::blockstore::BlockfileReader::ArrowBlockfileReader(reader) => reader,
+            _ => panic!("Unexpected reader type"),
+        };
+        assert!(reader.sparse_index.is_valid());
+        let block_ids: Vec<Uuid> = reader
+            .sparse_index
+            .forward
+            .lock()
+            .values()
+            .copied()
+            .collect();
+        let mut num_entries = 0;
+        for block_id in block_ids {
+            let block = reader.get_block(block_id).await.unwrap();
+            num_entries += block.len();
+            // Only a block holding a single entry that is too large by itself may be
+            // larger than the max block size.
+            assert!(block.len() == 1 || block.get_size() <= MAX_BLOCK_SIZE);
+        }
+        assert_eq!(num_entries, n);
+
+        for i in 0..n {
+            let key = format!("{:04}", i);
+            assert_eq!(reader.get("key", &key).await.unwrap(), values[i]);
+        }
+        let scanned = reader
+            .get_by_prefix("key")
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(scanned.len(), n);
+        for (i, (prefix, key, value)) in scanned.into_iter().enumerate() {
+            assert_eq!(prefix, "key");
+            assert_eq!(key, format!("{:04}", i));
+            assert_eq!(value, values[i]);
+        }
+    }
+
     #[tokio::test]
     async fn test_delete() {
         let tmp_dir = tempfile::tempdir().unwrap();
@@ -1167,4 +1441,141 @@ mod tests {
             assert_eq!(res.2, expected_value);
         }
     }
+
+    fn num_blocks<'me, K, V>(reader: &crate::blockstore::BlockfileReader<'me, K, V>) -> usize
+    where
+        K: crate::blockstore::Key + Into<KeyWrapper> + ArrowReadableKey<'me>,
+        V: crate::blockstore::Value + ArrowReadableValue<'me>,
+    {
+        match reader {
+            crate::blockstore::BlockfileReader::ArrowBlockfileReader(reader) => {
+                assert!(reader.sparse_index.is_valid());
+                reader.sparse_index.len()
+            }
+            _ => panic!("Unexpected reader type"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reopen_from_storage() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let n = 3000u32;
+
+        let blockfile_provider = ArrowBlockfileProvider::new(storage.clone());
+        let str_writer = blockfile_provider.create::<&str, u32>().unwrap();
+        let f32_writer = blockfile_provider.create::<f32, u32>().unwrap();
+        let u32_writer = blockfile_provider.create::<u32, u32>().unwrap();
+        let bool_writer = blockfile_provider.create::<bool, u32>().unwrap();
+        let ids = (
+            str_writer.id(),
+            f32_writer.id(),
+            u32_writer.id(),
+            bool_writer.id(),
+        );
+        // Written out of order, so that blocks are split in the middle as well as at the end.
+        for i in (0..n).rev() {
+            let key = format!("{:05}", i);
+            str_writer.set("key", key.as_str(), i).await.unwrap();
+            f32_writer.set("key", i as f32, i).await.unwrap();
+            u32_writer.set("key", i, i).await.unwrap();
+            // There are only two bool keys, so spread the entries over the prefixes.
+            let prefix = format!("{:05}", i / 2);
+            bool_writer
+                .set(prefix.as_str(), i % 2 == 1, i)
+                .await
+                .unwrap();
+        }
+        str_writer
+            .commit::<&str, u32>()
+            .unwrap()
+            .flush::<&str, u32>()
+            .await
+            .unwrap();
+        f32_writer
+            .commit::<f32, u32>()
+            .unwrap()
+            .flush::<f32, u32>()
+            .await
+            .unwrap();
+        u32_writer
+            .commit::<u32, u32>()
+            .unwrap()
+            .flush::<u32, u32>()
+            .await
+            .unwrap();
+        bool_writer
+            .commit::<bool, u32>()
+            .unwrap()
+            .flush::<bool, u32>()
+            .await
+            .unwrap();
+
+        // A new provider has nothing cached, so every block and sparse index is read back
+        // from the bytes in storage.
+        let blockfile_provider = ArrowBlockfileProvider::new(storage);
+
+        let reader = blockfile_provider.open::<&str, u32>(&ids.0).await.unwrap();
+        assert!(num_blocks(&reader) > 1);
+        assert_eq!(reader.count().await.unwrap(), n as usize);
+        for i in 0..n {
+            let key = format!("{:05}", i);
+            assert_eq!(reader.get("key", &key).await.unwrap(), i);
+        }
+        let values: Vec<u32> = reader
+            .get_gte("key", "01000")
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, _, value)| value)
+            .collect();
+        assert_eq!(values, (1000..n).collect::<Vec<u32>>());
+
+        let reader = blockfile_provider.open::<f32, u32>(&ids.1).await.unwrap();
+        assert!(num_blocks(&reader) > 1);
+        for i in 0..n {
+            assert_eq!(reader.get("key", i as f32).await.unwrap(), i);
+        }
+        let values: Vec<u32> = reader
+            .get_lt("key", 1000.0)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, _, value)| value)
+            .collect();
+        assert_eq!(values, (0..1000).collect::<Vec<u32>>());
+
+        let reader = blockfile_provider.open::<u32, u32>(&ids.2).await.unwrap();
+        assert!(num_blocks(&reader) > 1);
+        for i in 0..n {
+            assert_eq!(reader.get("key", i).await.unwrap(), i);
+        }
+        let values: Vec<u32> = reader
+            .get_gt("key", 1999)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, _, value)| value)
+            .collect();
+        assert_eq!(values, (2000..n).collect::<Vec<u32>>());
+
+        let reader = blockfile_provider.open::<bool, u32>(&ids.3).await.unwrap();
+        assert!(num_blocks(&reader) > 1);
+        for i in 0..n {
+            let prefix = format!("{:05}", i / 2);
+            assert_eq!(reader.get(prefix.as_str(), i % 2 == 1).await.unwrap(), i);
+        }
+        let values: Vec<u32> = reader
+            .get_by_prefix("01000")
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, _, value)| value)
+            .collect();
+        assert_eq!(values, vec![2000, 2001]);
+    }
 }
@@ -12,11 +12,16 @@ use crate::{
         BlockfileReader, BlockfileWriter, Key, Value,
     },
     errors::{ChromaError, ErrorCodes},
-    storage::Storage,
+    storage::{GetError, Storage},
+    tracing::metrics::metrics,
 };
 use core::panic;
 use parking_lot::RwLock;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use thiserror::Error;
 use tokio::io::AsyncReadExt;
 use uuid::Uuid;
@@ -37,6 +42,15 @@ impl ArrowBlockfileProvider {
         }
     }
 
+    /// A provider whose blocks are memory-mapped from files under `mmap_path` when they are
+    /// read, instead of being read onto the heap. See `BlockManager::new_mmap`.
+    pub(crate) fn new_mmap(storage: Storage, mmap_path: PathBuf) -> Self {
+        Self {
+            block_manager: BlockManager::new_mmap(storage.clone(), mmap_path),
+            sparse_index_manager: SparseIndexManager::new(storage),
+        }
+    }
+
     pub(crate) async fn open<
         'new,
         K: Key + Into<KeyWrapper> + ArrowReadableKey<'new> + 'new,
@@ -79,7 +93,10 @@ impl ArrowBlockfileProvider {
     ) -> Result<crate::blockstore::BlockfileWriter, Box<CreateError>> {
         println!("Forking blockfile from {:?}", id);
         let new_id = Uuid::new_v4();
-        let new_sparse_index = self.sparse_index_manager.fork::<K>(id, new_id).await;
+        let new_sparse_index = match self.sparse_index_manager.fork::<K>(id, new_id).await {
+            Some(sparse_index) => sparse_index,
+            None => return Err(Box::new(CreateError::NotFound)),
+        };
         let file = ArrowBlockfileWriter::from_sparse_index(
             new_id,
             self.block_manager.clone(),
@@ -100,6 +117,8 @@ impl ArrowBlockfileProvider {
 pub(super) struct BlockManager {
     read_cache: Arc<RwLock<HashMap<Uuid, Block>>>,
     storage: Storage,
+    // Where the blocks that are read are downloaded to and mapped from, if they are mapped.
+    mmap_path: Option<PathBuf>,
 }
 
 impl BlockManager {
@@ -107,6 +126,21 @@ impl BlockManager {
         Self {
             read_cache: Arc::new(RwLock::new(HashMap::new())),
             storage,
+            mmap_path: None,
+        }
+    }
+
+    /// A block manager that downloads the blocks it reads to files under `mmap_path` and
+    /// memory-maps them, so that their data is held once, in the page cache, and a block
+    /// is readable as soon as it is mapped instead of once it has been copied onto the heap.
+    /// # Notes
+    /// A file is removed as soon as it is mapped. The mapping keeps its data readable, and
+    /// its space is released once the block leaves the cache and nothing reads from it.
+    pub(super) fn new_mmap(storage: Storage, mmap_path: PathBuf) -> Self {
+        Self {
+            read_cache: Arc::new(RwLock::new(HashMap::new())),
+            storage,
+            mmap_path: Some(mmap_path),
         }
     }
 
@@ -159,7 +193,11 @@ impl BlockManager {
             cache.get(id).cloned()
         };
         match block {
-            Some(block) => Some(block),
+            Some(block) => {
+                metrics().record_block_read("cache");
+                Some(block)
+            }
+            None if self.mmap_path.is_some() => self.get_mapped(id).await,
             None => {
                 let key = format!("block/{}", id);
                 let bytes = self.storage.get(&key).await;
@@ -177,6 +215,7 @@ impl BlockManager {
                         let block = Block::from_bytes(&buf, *id);
                         match block {
                             Ok(block) => {
+                                metrics().record_block_read("storage");
                                 self.read_cache.write().insert(*id, block.clone());
                                 Some(block)
                             }
@@ -195,6 +234,42 @@ impl BlockManager {
         }
     }
 
+    // Downloads the file of a block and maps it. Every read has a file of its own, so that
+    // concurrent reads of a block don't remove the file the other one is about to map.
+    async fn get_mapped(&self, id: &Uuid) -> Option<Block> {
+        let mmap_path = self.mmap_path.as_deref()?;
+        let path = mmap_path.join(format!("{}.{}", id, Uuid::new_v4()));
+        if let Err(e) = self.download(id, mmap_path, &path).await {
+            // TODO: switch to logging when logging is implemented
+            println!("Error downloading block {}: {}", id, e);
+            return None;
+        }
+        let block = Block::load_mmap(&path, *id);
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            println!("Error removing the file of block {}: {}", id, e);
+        }
+        match block {
+            Ok(block) => {
+                metrics().record_block_read("mmap");
+                self.read_cache.write().insert(*id, block.clone());
+                Some(block)
+            }
+            Err(e) => {
+                println!("Error mapping block {}: {}", id, e);
+                None
+            }
+        }
+    }
+
+    // The file of a block only appears once it has been downloaded whole, so a file that is
+    // still being written is never mapped.
+    async fn download(&self, id: &Uuid, mmap_path: &Path, path: &Path) -> Result<(), GetError> {
+        tokio::fs::create_dir_all(mmap_path).await?;
+        self.storage
+            .get_to_file(&format!("block/{}", id), path)
+            .await
+    }
+
     pub(super) async fn flush(&self, id: &Uuid) -> Result<(), Box<dyn ChromaError>> {
         let block = self.get(id).await;
 
@@ -365,13 +440,11 @@ impl SparseIndexManager {
         &self,
         old_id: &Uuid,
         new_id: Uuid,
-    ) -> SparseIndex {
-        // TODO: error handling
+    ) -> Option<SparseIndex> {
         println!("Forking sparse index from {:?}", old_id);
-        let original = self.get::<K::ReadableKey<'key>>(old_id).await.unwrap();
-        let forked = original.fork(new_id);
-        self.cache.write().insert(new_id, forked.clone());
-        forked
+        let original = self.get::<K::ReadableKey<'key>>(old_id).await?;
+        // The fork is only cached once its writer commits, so it can't be opened before then.
+        Some(original.fork(new_id))
     }
 }
 
@@ -196,7 +196,12 @@ impl SparseIndex {
                 if curr_key.unwrap().key > key.clone().into() {
                     block_ids.push(*curr_uuid);
                 } else {
-                    if next_key.is_none() || next_key.unwrap().key > key.clone().into() {
+                    // The block holds the keys of the prefix up to the next block, or all of
+                    // the remaining ones if the next block starts with another prefix.
+                    if next_key.is_none()
+                        || next_key.unwrap().prefix.as_str() != prefix
+                        || next_key.unwrap().key > key.clone().into()
+                    {
                         block_ids.push(*curr_uuid);
                     }
                 }
@@ -274,7 +279,12 @@ impl SparseIndex {
                 if curr_key.unwrap().key >= key.clone().into() {
                     block_ids.push(*curr_uuid);
                 } else {
-                    if next_key.is_none() || next_key.unwrap().key >= key.clone().into() {
+                    // The block holds the keys of the prefix up to the next block, or all of
+                    // the remaining ones if the next block starts with another prefix.
+                    if next_key.is_none()
+                        || next_key.unwrap().prefix.as_str() != prefix
+                        || next_key.unwrap().key >= key.clone().into()
+                    {
                         block_ids.push(*curr_uuid);
                     }
                 }
@@ -412,8 +422,7 @@ impl SparseIndex {
                         delta.add(&k.prefix, *f, block_id.to_string().as_str());
                     }
                     KeyWrapper::Bool(b) => {
-                        unimplemented!();
-                        // delta.add("KEY", b, block_id.to_string().as_str());
+                        delta.add(&k.prefix, *b, block_id.to_string().as_str());
                     }
                     KeyWrapper::Uint32(u) => {
                         delta.add(&k.prefix, *u, block_id.to_string().as_str());
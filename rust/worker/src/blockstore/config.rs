@@ -0,0 +1,31 @@
+use crate::config::{ConfigValidator, ValidateConfig};
+use crate::storage::config::is_writable_dir;
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+/// The configuration for the blockfile provider.
+/// # Fields
+/// - mmap_path: When set, the blocks that are read are downloaded to files in this directory
+/// and memory-mapped, instead of being read onto the heap. Unset by default, since memory
+/// mapped files behave badly on NFS-backed volumes.
+/// # Notes
+/// See config.rs in the root of the worker crate for an example of how to use
+/// config files to configure the worker.
+#[serde(deny_unknown_fields)]
+pub(crate) struct BlockfileProviderConfig {
+    #[serde(default)]
+    pub(crate) mmap_path: Option<String>,
+}
+
+impl ValidateConfig for BlockfileProviderConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        if let Some(mmap_path) = &self.mmap_path {
+            validator.check(
+                "mmap_path",
+                is_writable_dir(Path::new(mmap_path)),
+                "a writable directory, or a path one can be created at",
+            );
+        }
+    }
+}
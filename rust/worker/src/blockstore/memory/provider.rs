@@ -33,6 +33,9 @@ impl HashMapBlockfileProvider {
         &self,
         id: &uuid::Uuid,
     ) -> Result<BlockfileReader<'new, K, V>, Box<OpenError>> {
+        if self.storage_manager.get(*id).is_none() {
+            return Err(Box::new(OpenError::NotFound));
+        }
         let reader = MemoryBlockfileReader::open(*id, self.storage_manager.clone());
         Ok(BlockfileReader::<K, V>::MemoryBlockfileReader(reader))
     }
@@ -53,7 +56,10 @@ impl HashMapBlockfileProvider {
         &self,
         id: &uuid::Uuid,
     ) -> Result<BlockfileWriter, Box<CreateError>> {
-        todo!();
+        match MemoryBlockfileWriter::fork(*id, self.storage_manager.clone()) {
+            Some(writer) => Ok(BlockfileWriter::MemoryBlockfileWriter(writer)),
+            None => Err(Box::new(CreateError::NotFound)),
+        }
     }
 }
 
@@ -62,7 +68,7 @@ mod tests {
     use crate::{
         execution::data::data_chunk::Chunk,
         segment::DataRecord,
-        types::{LogRecord, Operation, OperationRecord},
+        types::{Embedding, LogRecord, Operation, OperationRecord},
     };
 
     use super::*;
@@ -74,7 +80,7 @@ mod tests {
                 log_offset: 1,
                 record: OperationRecord {
                     id: "embedding_id_1".to_string(),
-                    embedding: Some(vec![1.0, 2.0, 3.0]),
+                    embedding: Some(vec![1.0, 2.0, 3.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -85,7 +91,7 @@ mod tests {
                 log_offset: 2,
                 record: OperationRecord {
                     id: "embedding_id_2".to_string(),
-                    embedding: Some(vec![4.0, 5.0, 6.0]),
+                    embedding: Some(vec![4.0, 5.0, 6.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -96,7 +102,7 @@ mod tests {
                 log_offset: 3,
                 record: OperationRecord {
                     id: "embedding_id_1".to_string(),
-                    embedding: Some(vec![7.0, 8.0, 9.0]),
+                    embedding: Some(vec![7.0, 8.0, 9.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -109,7 +115,10 @@ mod tests {
             .iter()
             .map(|record| DataRecord {
                 id: &record.0.record.id,
-                embedding: record.0.record.embedding.as_ref().unwrap(),
+                embedding: match record.0.record.embedding.as_ref() {
+                    Some(Embedding::F32(embedding)) => embedding,
+                    _ => panic!("Expected a float32 embedding"),
+                },
                 document: None,
                 metadata: None,
             })
@@ -3,6 +3,7 @@ use super::{
     storage::{Readable, Storage, StorageBuilder, StorageManager, Writeable},
 };
 use crate::{blockstore::key::KeyWrapper, errors::ChromaError};
+use std::ops::Range;
 
 #[derive(Clone)]
 pub(crate) struct MemoryBlockfileWriter {
@@ -32,8 +33,18 @@ impl MemoryBlockfileWriter {
         }
     }
 
+    pub(super) fn fork(id: uuid::Uuid, storage_manager: StorageManager) -> Option<Self> {
+        let builder = storage_manager.fork(id)?;
+        let id = builder.id;
+        Some(Self {
+            builder,
+            storage_manager,
+            id,
+        })
+    }
+
     pub(crate) fn commit(&self) -> Result<MemoryBlockfileFlusher, Box<dyn ChromaError>> {
-        self.storage_manager.commit(self.builder.id);
+        self.storage_manager.commit(&self.builder);
         Ok(MemoryBlockfileFlusher { id: self.id })
     }
 
@@ -100,9 +111,6 @@ impl<
         prefix: &str,
     ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
         let values = V::get_by_prefix_from_storage(prefix, &self.storage);
-        if values.is_empty() {
-            return Err(Box::new(BlockfileError::NotFoundError));
-        }
         let values = values
             .iter()
             .map(|(key, value)| (key.prefix.as_str(), K::from(&key.key), value.clone()))
@@ -117,9 +125,6 @@ impl<
     ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
         let key = key.into();
         let values = V::read_gt_from_storage(prefix, key, &self.storage);
-        if values.is_empty() {
-            return Err(Box::new(BlockfileError::NotFoundError));
-        }
         let values = values
             .iter()
             .map(|(key, value)| (key.prefix.as_str(), K::from(&key.key), value.clone()))
@@ -134,9 +139,6 @@ impl<
     ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
         let key = key.into();
         let values = V::read_lt_from_storage(prefix, key, &self.storage);
-        if values.is_empty() {
-            return Err(Box::new(BlockfileError::NotFoundError));
-        }
         let values = values
             .iter()
             .map(|(key, value)| (key.prefix.as_str(), K::from(&key.key), value.clone()))
@@ -151,9 +153,6 @@ impl<
     ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
         let key = key.into();
         let values = V::read_gte_from_storage(prefix, key, &self.storage);
-        if values.is_empty() {
-            return Err(Box::new(BlockfileError::NotFoundError));
-        }
         let values = values
             .iter()
             .map(|(key, value)| (key.prefix.as_str(), K::from(&key.key), value.clone()))
@@ -168,9 +167,6 @@ impl<
     ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
         let key = key.into();
         let values = V::read_lte_from_storage(prefix, key, &self.storage);
-        if values.is_empty() {
-            return Err(Box::new(BlockfileError::NotFoundError));
-        }
         let values = values
             .iter()
             .map(|(key, value)| (key.prefix.as_str(), K::from(&key.key), value.clone()))
@@ -178,6 +174,21 @@ impl<
         Ok(values)
     }
 
+    pub(crate) fn get_range(
+        &'storage self,
+        prefix: &str,
+        range: Range<K>,
+    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+        let end: KeyWrapper = range.end.into();
+        let values = V::read_gte_from_storage(prefix, range.start.into(), &self.storage);
+        let values = values
+            .iter()
+            .take_while(|(key, _)| key.key < end)
+            .map(|(key, value)| (key.prefix.as_str(), K::from(&key.key), value.clone()))
+            .collect();
+        Ok(values)
+    }
+
     pub(crate) fn get_at_index(
         &'storage self,
         index: usize,
@@ -208,7 +219,7 @@ mod tests {
     use super::*;
     use crate::execution::data::data_chunk::Chunk;
     use crate::segment::DataRecord;
-    use crate::types::{LogRecord, Operation, OperationRecord};
+    use crate::types::{Embedding, LogRecord, Operation, OperationRecord};
 
     #[test]
     fn test_blockfile_string() {
@@ -261,7 +272,7 @@ mod tests {
                 log_offset: 1,
                 record: OperationRecord {
                     id: "embedding_id_1".to_string(),
-                    embedding: Some(vec![1.0, 2.0, 3.0]),
+                    embedding: Some(vec![1.0, 2.0, 3.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -272,7 +283,7 @@ mod tests {
                 log_offset: 2,
                 record: OperationRecord {
                     id: "embedding_id_2".to_string(),
-                    embedding: Some(vec![4.0, 5.0, 6.0]),
+                    embedding: Some(vec![4.0, 5.0, 6.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -283,7 +294,7 @@ mod tests {
                 log_offset: 3,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![7.0, 8.0, 9.0]),
+                    embedding: Some(vec![7.0, 8.0, 9.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -296,7 +307,10 @@ mod tests {
             .iter()
             .map(|record| DataRecord {
                 id: &record.0.record.id,
-                embedding: record.0.record.embedding.as_ref().unwrap(),
+                embedding: match record.0.record.embedding.as_ref() {
+                    Some(Embedding::F32(embedding)) => embedding,
+                    _ => panic!("Expected a float32 embedding"),
+                },
                 document: None,
                 metadata: None,
             })
@@ -387,8 +401,8 @@ mod tests {
 
         let reader: MemoryBlockfileReader<u32, &str> =
             MemoryBlockfileReader::open(writer.id, storage_manager);
-        let values = reader.get_gt("prefix", 3);
-        assert!(values.is_err());
+        let values = reader.get_gt("prefix", 3).unwrap();
+        assert!(values.is_empty());
     }
 
     #[test]
@@ -447,8 +461,8 @@ mod tests {
 
         let reader: MemoryBlockfileReader<f32, &str> =
             MemoryBlockfileReader::open(writer.id, storage_manager);
-        let values = reader.get_gt("prefix", 3.0);
-        assert!(values.is_err());
+        let values = reader.get_gt("prefix", 3.0).unwrap();
+        assert!(values.is_empty());
     }
 
     #[test]
@@ -507,8 +521,8 @@ mod tests {
 
         let reader: MemoryBlockfileReader<u32, &str> =
             MemoryBlockfileReader::open(writer.id, storage_manager);
-        let values = reader.get_gte("prefix", 4);
-        assert!(values.is_err());
+        let values = reader.get_gte("prefix", 4).unwrap();
+        assert!(values.is_empty());
     }
 
     #[test]
@@ -567,8 +581,8 @@ mod tests {
 
         let reader: MemoryBlockfileReader<f32, &str> =
             MemoryBlockfileReader::open(writer.id, storage_manager);
-        let values = reader.get_gte("prefix", 3.5);
-        assert!(values.is_err());
+        let values = reader.get_gte("prefix", 3.5).unwrap();
+        assert!(values.is_empty());
     }
 
     #[test]
@@ -627,8 +641,8 @@ mod tests {
 
         let reader: MemoryBlockfileReader<u32, &str> =
             MemoryBlockfileReader::open(writer.id, storage_manager);
-        let values = reader.get_lt("prefix", 1);
-        assert!(values.is_err());
+        let values = reader.get_lt("prefix", 1).unwrap();
+        assert!(values.is_empty());
     }
 
     #[test]
@@ -687,8 +701,8 @@ mod tests {
 
         let reader: MemoryBlockfileReader<f32, &str> =
             MemoryBlockfileReader::open(writer.id, storage_manager);
-        let values = reader.get_lt("prefix", 0.5);
-        assert!(values.is_err());
+        let values = reader.get_lt("prefix", 0.5).unwrap();
+        assert!(values.is_empty());
     }
 
     #[test]
@@ -747,8 +761,8 @@ mod tests {
 
         let reader: MemoryBlockfileReader<u32, &str> =
             MemoryBlockfileReader::open(writer.id, storage_manager);
-        let values = reader.get_lte("prefix", 0);
-        assert!(values.is_err());
+        let values = reader.get_lte("prefix", 0).unwrap();
+        assert!(values.is_empty());
     }
 
     #[test]
@@ -807,8 +821,8 @@ mod tests {
 
         let reader: MemoryBlockfileReader<f32, &str> =
             MemoryBlockfileReader::open(writer.id, storage_manager);
-        let values = reader.get_lte("prefix", 0.5);
-        assert!(values.is_err());
+        let values = reader.get_lte("prefix", 0.5).unwrap();
+        assert!(values.is_empty());
     }
 
     #[test]
@@ -856,6 +870,34 @@ mod tests {
             .any(|(prefix, key, value)| *prefix == "prefix" && *key == 2.0 && *value == "value2"));
     }
 
+    #[test]
+    fn test_get_range() {
+        let storage_manager = StorageManager::new();
+        let writer = MemoryBlockfileWriter::new(storage_manager.clone());
+        for i in 0..10u32 {
+            let _ = writer.set("prefix", i, format!("value{}", i).as_str());
+            let _ = writer.set("other_prefix", i, "other");
+        }
+        let _ = writer.commit();
+
+        let reader: MemoryBlockfileReader<u32, &str> =
+            MemoryBlockfileReader::open(writer.id, storage_manager);
+        let values = reader.get_range("prefix", 3..6).unwrap();
+        let expected: Vec<(&str, u32, String)> = (3..6)
+            .map(|i| ("prefix", i, format!("value{}", i)))
+            .collect();
+        assert_eq!(
+            values
+                .into_iter()
+                .map(|(prefix, key, value)| (prefix, key, value.to_string()))
+                .collect::<Vec<_>>(),
+            expected
+        );
+        assert!(reader.get_range("prefix", 4..4).unwrap().is_empty());
+        assert!(reader.get_range("prefix", 20..30).unwrap().is_empty());
+        assert!(reader.get_range("missing", 0..10).unwrap().is_empty());
+    }
+
     #[test]
     fn test_delete() {
         let storage_manager = StorageManager::new();
@@ -1092,17 +1092,17 @@ pub(crate) struct Storage {
     pub(super) id: uuid::Uuid,
 }
 
+/// Keeps the committed storages by id. A StorageBuilder is only owned by its writer
+/// until it is committed, so dropping an uncommitted writer discards its writes.
 #[derive(Clone)]
 pub(crate) struct StorageManager {
     read_cache: Arc<RwLock<HashMap<uuid::Uuid, Storage>>>,
-    write_cache: Arc<RwLock<HashMap<uuid::Uuid, StorageBuilder>>>,
 }
 
 impl StorageManager {
     pub(super) fn new() -> Self {
         Self {
             read_cache: Arc::new(RwLock::new(HashMap::new())),
-            write_cache: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -1114,7 +1114,7 @@ impl StorageManager {
 
     pub(super) fn create(&self) -> StorageBuilder {
         let id = uuid::Uuid::new_v4();
-        let builder = StorageBuilder {
+        StorageBuilder {
             bool_storage: Arc::new(RwLock::new(Some(BTreeMap::new()))),
             string_value_storage: Arc::new(RwLock::new(Some(BTreeMap::new()))),
             u32_storage: Arc::new(RwLock::new(Some(BTreeMap::new()))),
@@ -1124,15 +1124,38 @@ impl StorageManager {
             data_record_id_storage: Arc::new(RwLock::new(Some(BTreeMap::new()))),
             data_record_embedding_storage: Arc::new(RwLock::new(Some(BTreeMap::new()))),
             id,
-        };
-        let mut cache_guard = self.write_cache.write();
-        cache_guard.insert(id, builder.clone());
-        builder
+        }
+    }
+
+    /// Returns a builder under a new id that starts out with a copy of the committed
+    /// storage `id`, or None if `id` has not been committed.
+    pub(super) fn fork(&self, id: uuid::Uuid) -> Option<StorageBuilder> {
+        let storage = self.get(id)?;
+        Some(StorageBuilder {
+            bool_storage: Arc::new(RwLock::new(Some((*storage.bool_storage).clone()))),
+            string_value_storage: Arc::new(RwLock::new(Some(
+                (*storage.string_value_storage).clone(),
+            ))),
+            u32_storage: Arc::new(RwLock::new(Some((*storage.u32_storage).clone()))),
+            f32_storage: Arc::new(RwLock::new(Some((*storage.f32_storage).clone()))),
+            roaring_bitmap_storage: Arc::new(RwLock::new(Some(
+                (*storage.roaring_bitmap_storage).clone(),
+            ))),
+            int32_array_storage: Arc::new(RwLock::new(Some(
+                (*storage.int32_array_storage).clone(),
+            ))),
+            data_record_id_storage: Arc::new(RwLock::new(Some(
+                (*storage.data_record_id_storage).clone(),
+            ))),
+            data_record_embedding_storage: Arc::new(RwLock::new(Some(
+                (*storage.data_record_embedding_storage).clone(),
+            ))),
+            id: uuid::Uuid::new_v4(),
+        })
     }
 
-    pub(super) fn commit(&self, id: uuid::Uuid) -> Storage {
-        let mut write_cache_guard = self.write_cache.write();
-        let builder = write_cache_guard.remove(&id).unwrap();
+    pub(super) fn commit(&self, builder: &StorageBuilder) -> Storage {
+        let id = builder.id;
         let storage = Storage {
             bool_storage: builder.bool_storage.write().take().unwrap().into(),
             string_value_storage: builder.string_value_storage.write().take().unwrap().into(),
@@ -2,6 +2,7 @@ pub mod positional_posting_list_value;
 mod types;
 
 pub mod arrow;
+pub(crate) mod config;
 pub mod key;
 pub mod memory;
 pub(crate) mod provider;
@@ -2,6 +2,7 @@ use super::arrow::provider::ArrowBlockfileProvider;
 use super::arrow::types::{
     ArrowReadableKey, ArrowReadableValue, ArrowWriteableKey, ArrowWriteableValue,
 };
+use super::config::BlockfileProviderConfig;
 use super::key::KeyWrapper;
 use super::memory::provider::HashMapBlockfileProvider;
 use super::memory::storage::{Readable, Writeable};
@@ -11,6 +12,7 @@ use crate::errors::ChromaError;
 use crate::storage::Storage;
 use core::fmt::{self, Debug};
 use std::fmt::Formatter;
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Clone)]
@@ -41,6 +43,19 @@ impl BlockfileProvider {
         BlockfileProvider::ArrowBlockfileProvider(ArrowBlockfileProvider::new(storage))
     }
 
+    /// An arrow blockfile provider configured by `config`, see `BlockfileProviderConfig`.
+    pub(crate) fn new_arrow_with_config(
+        storage: Storage,
+        config: &BlockfileProviderConfig,
+    ) -> Self {
+        match &config.mmap_path {
+            Some(mmap_path) => BlockfileProvider::ArrowBlockfileProvider(
+                ArrowBlockfileProvider::new_mmap(storage, PathBuf::from(mmap_path)),
+            ),
+            None => BlockfileProvider::new_arrow(storage),
+        }
+    }
+
     pub(crate) async fn open<
         'new,
         K: Key + Into<KeyWrapper> + From<&'new KeyWrapper> + ArrowReadableKey<'new> + 'new,
@@ -96,10 +111,102 @@ impl ChromaError for OpenError {
 pub(crate) enum CreateError {
     #[error("Blockfile already exists")]
     AlreadyExists,
+    #[error("Blockfile to fork from not found")]
+    NotFound,
 }
 
 impl ChromaError for CreateError {
     fn code(&self) -> crate::errors::ErrorCodes {
-        crate::errors::ErrorCodes::AlreadyExists
+        match self {
+            CreateError::AlreadyExists => crate::errors::ErrorCodes::AlreadyExists,
+            CreateError::NotFound => crate::errors::ErrorCodes::NotFound,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::local::LocalStorage;
+
+    // Writes to a forked blockfile must only become visible, all at once, when its writer
+    // commits. Readers of the blockfile it was forked from never see them.
+    async fn test_fork_commits_atomically(provider: BlockfileProvider) {
+        let n = 3000u32;
+        let writer = provider.create::<u32, u32>().unwrap();
+        let id = writer.id();
+        for i in 0..n {
+            writer.set("key", i, i).await.unwrap();
+        }
+        assert!(provider.open::<u32, u32>(&id).await.is_err());
+        writer
+            .commit::<u32, u32>()
+            .unwrap()
+            .flush::<u32, u32>()
+            .await
+            .unwrap();
+        let reader = provider.open::<u32, u32>(&id).await.unwrap();
+
+        // The writer fails part way through and is dropped without committing.
+        let writer = provider.fork::<u32, u32>(&id).await.unwrap();
+        let dropped_id = writer.id();
+        for i in 0..n / 2 {
+            writer.set("key", i, i + n).await.unwrap();
+        }
+        writer.delete::<u32, u32>("key", n - 1).await.unwrap();
+        drop(writer);
+        assert!(provider.open::<u32, u32>(&dropped_id).await.is_err());
+
+        let writer = provider.fork::<u32, u32>(&id).await.unwrap();
+        let forked_id = writer.id();
+        for i in 0..n {
+            writer.set("key", i, i + n).await.unwrap();
+        }
+        writer.delete::<u32, u32>("key", n - 1).await.unwrap();
+        assert!(provider.open::<u32, u32>(&forked_id).await.is_err());
+        writer.commit::<u32, u32>().unwrap();
+
+        let forked = provider.open::<u32, u32>(&forked_id).await.unwrap();
+        assert_eq!(forked.count().await.unwrap(), n as usize - 1);
+        for i in 0..n - 1 {
+            assert_eq!(forked.get("key", i).await.unwrap(), i + n);
+        }
+        assert!(forked.get("key", n - 1).await.is_err());
+        // Both the reader opened before the fork and a new one still see the original values.
+        let reopened = provider.open::<u32, u32>(&id).await.unwrap();
+        for reader in [&reader, &reopened] {
+            assert_eq!(reader.count().await.unwrap(), n as usize);
+            for i in 0..n {
+                assert_eq!(reader.get("key", i).await.unwrap(), i);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_fork_commits_atomically() {
+        test_fork_commits_atomically(BlockfileProvider::new_memory()).await;
+    }
+
+    #[tokio::test]
+    async fn test_arrow_fork_commits_atomically() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        test_fork_commits_atomically(BlockfileProvider::new_arrow(storage)).await;
+    }
+
+    #[tokio::test]
+    async fn test_fork_of_missing_blockfile() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        for provider in [
+            BlockfileProvider::new_memory(),
+            BlockfileProvider::new_arrow(storage),
+        ] {
+            let id = uuid::Uuid::new_v4();
+            match provider.fork::<u32, u32>(&id).await {
+                Err(e) => assert!(matches!(*e, CreateError::NotFound)),
+                Ok(_) => panic!("Forked a blockfile that doesn't exist"),
+            }
+        }
     }
 }
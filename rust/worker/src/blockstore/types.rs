@@ -15,7 +15,7 @@ use arrow::array::{Array, Int32Array};
 use futures::{Stream, StreamExt};
 use roaring::RoaringBitmap;
 use std::fmt::{Debug, Display};
-use std::pin::Pin;
+use std::ops::Range;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -282,57 +282,95 @@ impl<
     }
 
     // TODO: make prefix &str
-    pub(crate) async fn get_by_prefix(
+    /// Returns the key-value pairs of `prefix` as a stream that fetches the blocks that hold
+    /// them as it is polled. A read that matches nothing is an empty stream.
+    pub(crate) fn get_by_prefix(
         &'referred_data self,
         prefix: &str,
-    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+    ) -> impl Stream<Item = Result<(&'referred_data str, K, V), Box<dyn ChromaError>>> {
         match self {
-            BlockfileReader::MemoryBlockfileReader(reader) => reader.get_by_prefix(prefix),
-            BlockfileReader::ArrowBlockfileReader(reader) => reader.get_by_prefix(prefix).await,
+            BlockfileReader::MemoryBlockfileReader(reader) => {
+                memory_stream(reader.get_by_prefix(prefix)).left_stream()
+            }
+            BlockfileReader::ArrowBlockfileReader(reader) => {
+                reader.get_by_prefix(prefix).right_stream()
+            }
         }
     }
 
-    pub(crate) async fn get_gt(
+    pub(crate) fn get_gt(
         &'referred_data self,
         prefix: &str,
         key: K,
-    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+    ) -> impl Stream<Item = Result<(&'referred_data str, K, V), Box<dyn ChromaError>>> {
         match self {
-            BlockfileReader::MemoryBlockfileReader(reader) => reader.get_gt(prefix, key),
-            BlockfileReader::ArrowBlockfileReader(reader) => reader.get_gt(prefix, key).await,
+            BlockfileReader::MemoryBlockfileReader(reader) => {
+                memory_stream(reader.get_gt(prefix, key)).left_stream()
+            }
+            BlockfileReader::ArrowBlockfileReader(reader) => {
+                reader.get_gt(prefix, key).right_stream()
+            }
         }
     }
 
-    pub(crate) async fn get_lt(
+    pub(crate) fn get_lt(
         &'referred_data self,
         prefix: &str,
         key: K,
-    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+    ) -> impl Stream<Item = Result<(&'referred_data str, K, V), Box<dyn ChromaError>>> {
         match self {
-            BlockfileReader::MemoryBlockfileReader(reader) => reader.get_lt(prefix, key),
-            BlockfileReader::ArrowBlockfileReader(reader) => reader.get_lt(prefix, key).await,
+            BlockfileReader::MemoryBlockfileReader(reader) => {
+                memory_stream(reader.get_lt(prefix, key)).left_stream()
+            }
+            BlockfileReader::ArrowBlockfileReader(reader) => {
+                reader.get_lt(prefix, key).right_stream()
+            }
         }
     }
 
-    pub(crate) async fn get_gte(
+    pub(crate) fn get_gte(
         &'referred_data self,
         prefix: &str,
         key: K,
-    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+    ) -> impl Stream<Item = Result<(&'referred_data str, K, V), Box<dyn ChromaError>>> {
         match self {
-            BlockfileReader::MemoryBlockfileReader(reader) => reader.get_gte(prefix, key),
-            BlockfileReader::ArrowBlockfileReader(reader) => reader.get_gte(prefix, key).await,
+            BlockfileReader::MemoryBlockfileReader(reader) => {
+                memory_stream(reader.get_gte(prefix, key)).left_stream()
+            }
+            BlockfileReader::ArrowBlockfileReader(reader) => {
+                reader.get_gte(prefix, key).right_stream()
+            }
         }
     }
 
-    pub(crate) async fn get_lte(
+    pub(crate) fn get_lte(
         &'referred_data self,
         prefix: &str,
         key: K,
-    ) -> Result<Vec<(&str, K, V)>, Box<dyn ChromaError>> {
+    ) -> impl Stream<Item = Result<(&'referred_data str, K, V), Box<dyn ChromaError>>> {
+        match self {
+            BlockfileReader::MemoryBlockfileReader(reader) => {
+                memory_stream(reader.get_lte(prefix, key)).left_stream()
+            }
+            BlockfileReader::ArrowBlockfileReader(reader) => {
+                reader.get_lte(prefix, key).right_stream()
+            }
+        }
+    }
+
+    /// Returns the key-value pairs of `prefix` whose key is in `range`, in key order.
+    pub(crate) fn get_range(
+        &'referred_data self,
+        prefix: &str,
+        range: Range<K>,
+    ) -> impl Stream<Item = Result<(&'referred_data str, K, V), Box<dyn ChromaError>>> {
         match self {
-            BlockfileReader::MemoryBlockfileReader(reader) => reader.get_lte(prefix, key),
-            BlockfileReader::ArrowBlockfileReader(reader) => reader.get_lte(prefix, key).await,
+            BlockfileReader::MemoryBlockfileReader(reader) => {
+                memory_stream(reader.get_range(prefix, range)).left_stream()
+            }
+            BlockfileReader::ArrowBlockfileReader(reader) => {
+                reader.get_range(prefix, range).right_stream()
+            }
         }
     }
 
@@ -353,3 +391,14 @@ impl<
         }
     }
 }
+
+// Memory blockfiles are read whole, so their reads are streamed from the records they return.
+fn memory_stream<T>(
+    read: Result<Vec<T>, Box<dyn ChromaError>>,
+) -> impl Stream<Item = Result<T, Box<dyn ChromaError>>> {
+    let records: Vec<_> = match read {
+        Ok(records) => records.into_iter().map(Ok).collect(),
+        Err(e) => vec![Err(e)],
+    };
+    futures::stream::iter(records)
+}
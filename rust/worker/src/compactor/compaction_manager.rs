@@ -1,7 +1,9 @@
-use super::scheduler::Scheduler;
-use super::scheduler_policy::LasCompactionTimeSchedulerPolicy;
+use super::scheduler::{Scheduler, SchedulerSettings};
+use super::scheduler_policy;
 use crate::blockstore::provider::BlockfileProvider;
+use crate::compactor::types::CheckBacklogMessage;
 use crate::compactor::types::CompactionJob;
+use crate::compactor::types::CompactionJobDone;
 use crate::compactor::types::ScheduleMessage;
 use crate::config::CompactionServiceConfig;
 use crate::config::Configurable;
@@ -9,7 +11,6 @@ use crate::errors::ChromaError;
 use crate::errors::ErrorCodes;
 use crate::execution::operator::TaskMessage;
 use crate::execution::orchestration::CompactOrchestrator;
-use crate::execution::orchestration::CompactionResponse;
 use crate::index::hnsw_provider::HnswIndexProvider;
 use crate::log::log::Log;
 use crate::memberlist::Memberlist;
@@ -22,8 +23,7 @@ use crate::system::Handler;
 use crate::system::Receiver;
 use crate::system::System;
 use async_trait::async_trait;
-use futures::stream::FuturesUnordered;
-use futures::StreamExt;
+use futures::FutureExt;
 use std::fmt::Debug;
 use std::fmt::Formatter;
 use std::path::PathBuf;
@@ -31,6 +31,8 @@ use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
+use tokio::select;
+use tokio::task::JoinSet;
 
 pub(crate) struct CompactionManager {
     system: Option<System>,
@@ -43,12 +45,35 @@ pub(crate) struct CompactionManager {
     hnsw_index_provider: HnswIndexProvider,
     // Dispatcher
     dispatcher: Option<Box<dyn Receiver<TaskMessage>>>,
+    // The compaction jobs running in the background, waited for when the manager stops.
+    jobs: JoinSet<()>,
     // Config
     compaction_manager_queue_size: usize,
+    // The size of the queue of the orchestrator of each compaction job.
+    orchestrator_queue_size: usize,
     compaction_interval: Duration,
+    // How often to check for backlogs over the thresholds of the compaction trigger, if it
+    // has any.
+    backlog_check_interval: Option<Duration>,
     min_compaction_size: usize,
 }
 
+/// How the compaction manager queues and paces its work.
+/// # Fields
+/// - compaction_manager_queue_size: The size of the queue of the compaction manager.
+/// - orchestrator_queue_size: The size of the queue of the orchestrator of each compaction job.
+/// - compaction_interval: How often to schedule the collections with new data.
+/// - backlog_check_interval: How often to check for backlogs over the thresholds of the
+///   compaction trigger, if it has any.
+/// - min_compaction_size: The fewest new log records a collection needs to be compacted.
+pub(crate) struct CompactionManagerSettings {
+    pub(crate) compaction_manager_queue_size: usize,
+    pub(crate) orchestrator_queue_size: usize,
+    pub(crate) compaction_interval: Duration,
+    pub(crate) backlog_check_interval: Option<Duration>,
+    pub(crate) min_compaction_size: usize,
+}
+
 #[derive(Error, Debug)]
 pub(crate) enum CompactionError {
     #[error("Failed to compact")]
@@ -71,10 +96,15 @@ impl CompactionManager {
         storage: Storage,
         blockfile_provider: BlockfileProvider,
         hnsw_index_provider: HnswIndexProvider,
-        compaction_manager_queue_size: usize,
-        compaction_interval: Duration,
-        min_compaction_size: usize,
+        settings: CompactionManagerSettings,
     ) -> Self {
+        let CompactionManagerSettings {
+            compaction_manager_queue_size,
+            orchestrator_queue_size,
+            compaction_interval,
+            backlog_check_interval,
+            min_compaction_size,
+        } = settings;
         CompactionManager {
             system: None,
             scheduler,
@@ -84,81 +114,87 @@ impl CompactionManager {
             blockfile_provider,
             hnsw_index_provider,
             dispatcher: None,
+            jobs: JoinSet::new(),
             compaction_manager_queue_size,
+            orchestrator_queue_size,
             compaction_interval,
+            backlog_check_interval,
             min_compaction_size,
         }
     }
 
-    async fn compact(
+    fn orchestrator(
         &self,
         compaction_job: &CompactionJob,
-    ) -> Result<CompactionResponse, Box<dyn ChromaError>> {
+    ) -> Result<CompactOrchestrator, CompactionError> {
         let dispatcher = match self.dispatcher {
             Some(ref dispatcher) => dispatcher,
             None => {
                 println!("No dispatcher found");
-                return Err(Box::new(CompactionError::FailedToCompact));
+                return Err(CompactionError::FailedToCompact);
             }
         };
 
         match self.system {
-            Some(ref system) => {
-                let orchestrator = CompactOrchestrator::new(
-                    compaction_job.clone(),
-                    system.clone(),
-                    compaction_job.collection_id,
-                    self.log.clone(),
-                    self.sysdb.clone(),
-                    self.blockfile_provider.clone(),
-                    self.hnsw_index_provider.clone(),
-                    dispatcher.clone(),
-                    None,
-                    None,
-                    Arc::new(AtomicU32::new(0)),
-                );
-
-                match orchestrator.run().await {
-                    Ok(result) => {
-                        println!("Compaction Job completed");
-                        return Ok(result);
-                    }
-                    Err(e) => {
-                        println!("Compaction Job failed");
-                        return Err(e);
-                    }
-                }
-            }
+            Some(ref system) => Ok(CompactOrchestrator::new(
+                compaction_job.clone(),
+                system.clone(),
+                compaction_job.collection_id,
+                self.log.clone(),
+                self.sysdb.clone(),
+                self.blockfile_provider.clone(),
+                self.hnsw_index_provider.clone(),
+                dispatcher.clone(),
+                None,
+                None,
+                Arc::new(AtomicU32::new(0)),
+            )
+            .with_queue_size(self.orchestrator_queue_size)),
             None => {
                 println!("No system found");
-                return Err(Box::new(CompactionError::FailedToCompact));
+                Err(CompactionError::FailedToCompact)
             }
-        };
+        }
     }
 
-    // TODO: make the return type more informative
-    pub(crate) async fn compact_batch(&mut self) -> (u32, u32) {
-        self.scheduler.schedule().await;
-        let mut jobs = FuturesUnordered::new();
-        for job in self.scheduler.get_jobs() {
-            jobs.push(self.compact(job));
+    /// Runs the jobs of the last schedule in the background. Every job reports back with a
+    /// `CompactionJobDone` message, so that the scheduler does not schedule its collection
+    /// again while it runs. A stopped manager waits for the jobs still running.
+    fn start_jobs(&mut self, ctx: &ComponentContext<CompactionManager>) {
+        let jobs: Vec<CompactionJob> = self.scheduler.get_jobs().cloned().collect();
+        if !jobs.is_empty() {
+            println!("Compacting {} jobs", jobs.len());
         }
-        println!("Compacting {} jobs", jobs.len());
-        let mut num_completed_jobs = 0;
-        let mut num_failed_jobs = 0;
-        while let Some(job) = jobs.next().await {
-            match job {
-                Ok(result) => {
-                    println!("Compaction completed: {:?}", result);
-                    num_completed_jobs += 1;
-                }
+        for job in jobs {
+            let collection_id = job.collection_id;
+            let orchestrator = match self.orchestrator(&job) {
+                Ok(orchestrator) => orchestrator,
                 Err(e) => {
                     println!("Compaction failed: {:?}", e);
-                    num_failed_jobs += 1;
+                    self.scheduler.finish_job(collection_id);
+                    continue;
                 }
-            }
+            };
+            let sender = ctx.sender.clone();
+            let stopped = ctx.cancellation_token.clone();
+            self.jobs.spawn(async move {
+                let result = orchestrator.run().await;
+                let done = CompactionJobDone {
+                    collection_id,
+                    result,
+                };
+                // A stopped manager no longer handles messages, and waits for this job.
+                select! {
+                    sent = sender.send(done, None) => {
+                        if let Err(e) = sent {
+                            // TODO: switch to logging when logging is implemented
+                            println!("Failed to report a finished compaction job: {:?}", e);
+                        }
+                    }
+                    _ = stopped.cancelled() => {}
+                }
+            });
         }
-        (num_completed_jobs, num_failed_jobs)
     }
 
     pub(crate) fn set_dispatcher(&mut self, dispatcher: Box<dyn Receiver<TaskMessage>>) {
@@ -198,11 +234,17 @@ impl Configurable<CompactionServiceConfig> for CompactionManager {
         };
 
         let my_ip = config.my_member_id.clone();
-        let policy = Box::new(LasCompactionTimeSchedulerPolicy {});
+        let policy = scheduler_policy::from_config(&config.compactor.scheduler_policy);
         let compaction_interval_sec = config.compactor.compaction_interval_sec;
         let max_concurrent_jobs = config.compactor.max_concurrent_jobs;
         let compaction_manager_queue_size = config.compactor.compaction_manager_queue_size;
+        let orchestrator_queue_size = config.compactor.orchestrator_queue_size;
         let min_compaction_size = config.compactor.min_compaction_size;
+        let compaction_trigger = config.compactor.compaction_trigger.clone();
+        let backlog_check_interval = match compaction_trigger.is_enabled() {
+            true => Some(Duration::from_millis(compaction_trigger.check_interval_ms)),
+            false => None,
+        };
 
         let assignment_policy_config = &config.assignment_policy;
         let assignment_policy = match crate::assignment::from_config(assignment_policy_config).await
@@ -216,9 +258,12 @@ impl Configurable<CompactionServiceConfig> for CompactionManager {
             my_ip,
             log.clone(),
             sysdb.clone(),
-            policy,
-            max_concurrent_jobs,
-            min_compaction_size,
+            SchedulerSettings {
+                policy,
+                max_concurrent_jobs,
+                min_compaction_size,
+                trigger: compaction_trigger,
+            },
             assignment_policy,
         );
 
@@ -232,10 +277,14 @@ impl Configurable<CompactionServiceConfig> for CompactionManager {
             sysdb,
             storage.clone(),
             BlockfileProvider::new_arrow(storage.clone()),
-            HnswIndexProvider::new(storage.clone(), path),
-            compaction_manager_queue_size,
-            Duration::from_secs(compaction_interval_sec),
-            min_compaction_size,
+            HnswIndexProvider::new(storage.clone(), path, &config.hnsw_provider),
+            CompactionManagerSettings {
+                compaction_manager_queue_size,
+                orchestrator_queue_size,
+                compaction_interval: Duration::from_secs(compaction_interval_sec),
+                backlog_check_interval,
+                min_compaction_size,
+            },
         ))
     }
 }
@@ -259,6 +308,21 @@ impl Component for CompactionManager {
             self.compaction_interval,
             ctx,
         );
+        if let Some(backlog_check_interval) = self.backlog_check_interval {
+            ctx.scheduler.schedule(
+                ctx.sender.clone(),
+                CheckBacklogMessage {},
+                backlog_check_interval,
+                ctx,
+            );
+        }
+    }
+
+    async fn on_stop(&mut self, _ctx: &crate::system::ComponentContext<Self>) -> () {
+        if !self.jobs.is_empty() {
+            println!("Waiting for {} compaction jobs", self.jobs.len());
+        }
+        while self.jobs.join_next().await.is_some() {}
     }
 }
 
@@ -277,8 +341,8 @@ impl Handler<ScheduleMessage> for CompactionManager {
         ctx: &ComponentContext<CompactionManager>,
     ) {
         println!("CompactionManager: Performing compaction");
-        self.compact_batch().await;
-        // Compaction is done, schedule the next compaction
+        self.scheduler.schedule().await;
+        self.start_jobs(ctx);
         ctx.scheduler.schedule(
             ctx.sender.clone(),
             ScheduleMessage {},
@@ -288,6 +352,43 @@ impl Handler<ScheduleMessage> for CompactionManager {
     }
 }
 
+#[async_trait]
+impl Handler<CheckBacklogMessage> for CompactionManager {
+    async fn handle(
+        &mut self,
+        _message: CheckBacklogMessage,
+        ctx: &ComponentContext<CompactionManager>,
+    ) {
+        self.scheduler.schedule_over_threshold().await;
+        self.start_jobs(ctx);
+        if let Some(backlog_check_interval) = self.backlog_check_interval {
+            ctx.scheduler.schedule(
+                ctx.sender.clone(),
+                CheckBacklogMessage {},
+                backlog_check_interval,
+                ctx,
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<CompactionJobDone> for CompactionManager {
+    async fn handle(
+        &mut self,
+        message: CompactionJobDone,
+        _ctx: &ComponentContext<CompactionManager>,
+    ) {
+        match message.result {
+            Ok(result) => println!("Compaction completed: {:?}", result),
+            Err(e) => println!("Compaction failed: {:?}", e),
+        }
+        self.scheduler.finish_job(message.collection_id);
+        // Forget the jobs that are done.
+        while let Some(Some(_)) = self.jobs.join_next().now_or_never() {}
+    }
+}
+
 #[async_trait]
 impl Handler<Memberlist> for CompactionManager {
     async fn handle(&mut self, message: Memberlist, _ctx: &ComponentContext<CompactionManager>) {
@@ -302,7 +403,10 @@ mod tests {
     use super::*;
     use crate::assignment::assignment_policy::AssignmentPolicy;
     use crate::assignment::assignment_policy::RendezvousHashingAssignmentPolicy;
+    use crate::compactor::config::CompactionTriggerConfig;
+    use crate::compactor::scheduler_policy::LasCompactionTimeSchedulerPolicy;
     use crate::execution::dispatcher::Dispatcher;
+    use crate::index::config::HnswProviderConfig;
     use crate::log::log::InMemoryLog;
     use crate::log::log::InternalLogRecord;
     use crate::storage::local::LocalStorage;
@@ -312,9 +416,33 @@ mod tests {
     use crate::types::Operation;
     use crate::types::OperationRecord;
     use crate::types::Segment;
+    use futures::stream::FuturesUnordered;
+    use futures::StreamExt;
     use std::str::FromStr;
     use uuid::Uuid;
 
+    // Compacts the collections with new data and waits for the jobs, which the component
+    // runs in the background. Returns how many jobs completed and how many failed.
+    async fn compact_batch(manager: &mut CompactionManager) -> (u32, u32) {
+        manager.scheduler.schedule().await;
+        let mut jobs = FuturesUnordered::new();
+        for job in manager.scheduler.get_jobs() {
+            let orchestrator = manager.orchestrator(job).unwrap();
+            let collection_id = job.collection_id;
+            jobs.push(async move { (collection_id, orchestrator.run().await) });
+        }
+        let mut num_completed_jobs = 0;
+        let mut num_failed_jobs = 0;
+        while let Some((collection_id, result)) = jobs.next().await {
+            match result {
+                Ok(_) => num_completed_jobs += 1,
+                Err(_) => num_failed_jobs += 1,
+            }
+            manager.scheduler.finish_job(collection_id);
+        }
+        (num_completed_jobs, num_failed_jobs)
+    }
+
     #[tokio::test]
     async fn test_compaction_manager() {
         let mut log = Box::new(Log::InMemory(InMemoryLog::new()));
@@ -336,7 +464,7 @@ mod tests {
                     log_offset: 0,
                     record: OperationRecord {
                         id: "embedding_id_1".to_string(),
-                        embedding: Some(vec![1.0, 2.0, 3.0]),
+                        embedding: Some(vec![1.0, 2.0, 3.0].into()),
                         encoding: None,
                         metadata: None,
                         document: None,
@@ -357,7 +485,7 @@ mod tests {
                     log_offset: 0,
                     record: OperationRecord {
                         id: "embedding_id_2".to_string(),
-                        embedding: Some(vec![4.0, 5.0, 6.0]),
+                        embedding: Some(vec![4.0, 5.0, 6.0].into()),
                         encoding: None,
                         metadata: None,
                         document: None,
@@ -374,7 +502,7 @@ mod tests {
             id: collection_uuid_1,
             name: "collection_1".to_string(),
             metadata: None,
-            dimension: Some(1),
+            dimension: Some(3),
             tenant: tenant_1.clone(),
             database: "database_1".to_string(),
             log_position: -1,
@@ -386,7 +514,7 @@ mod tests {
             id: collection_uuid_2,
             name: "collection_2".to_string(),
             metadata: None,
-            dimension: Some(1),
+            dimension: Some(3),
             tenant: tenant_2.clone(),
             database: "database_2".to_string(),
             log_position: -1,
@@ -484,9 +612,12 @@ mod tests {
             my_member_id.clone(),
             log.clone(),
             sysdb.clone(),
-            Box::new(LasCompactionTimeSchedulerPolicy {}),
-            max_concurrent_jobs,
-            min_compaction_size,
+            SchedulerSettings {
+                policy: Box::new(LasCompactionTimeSchedulerPolicy {}),
+                max_concurrent_jobs,
+                min_compaction_size,
+                trigger: CompactionTriggerConfig::default(),
+            },
             assignment_policy,
         );
         // Set memberlist
@@ -498,10 +629,18 @@ mod tests {
             sysdb,
             storage.clone(),
             BlockfileProvider::new_arrow(storage.clone()),
-            HnswIndexProvider::new(storage, PathBuf::from(tmpdir.path().to_str().unwrap())),
-            compaction_manager_queue_size,
-            compaction_interval,
-            min_compaction_size,
+            HnswIndexProvider::new(
+                storage,
+                PathBuf::from(tmpdir.path().to_str().unwrap()),
+                &HnswProviderConfig::default(),
+            ),
+            CompactionManagerSettings {
+                compaction_manager_queue_size,
+                orchestrator_queue_size: 1000,
+                compaction_interval,
+                backlog_check_interval: None,
+                min_compaction_size,
+            },
         );
 
         let system = System::new();
@@ -510,8 +649,151 @@ mod tests {
         let dispatcher_handle = system.start_component(dispatcher);
         manager.set_dispatcher(dispatcher_handle.receiver());
         manager.set_system(system);
-        let (num_completed, number_failed) = manager.compact_batch().await;
+        let (num_completed, number_failed) = compact_batch(&mut manager).await;
         assert_eq!(num_completed, 2);
         assert_eq!(number_failed, 0);
     }
+
+    #[tokio::test]
+    async fn test_stopping_waits_for_running_compactions() {
+        let num_records = 5000;
+        let mut log = Box::new(Log::InMemory(InMemoryLog::new()));
+        let collection_id = Uuid::new_v4();
+        match *log {
+            Log::InMemory(ref mut log) => {
+                for i in 0..num_records {
+                    log.add_log(
+                        collection_id,
+                        Box::new(InternalLogRecord {
+                            collection_id,
+                            log_offset: i,
+                            log_ts: i,
+                            record: LogRecord {
+                                log_offset: i,
+                                record: OperationRecord {
+                                    id: format!("embedding_id_{}", i),
+                                    embedding: Some(vec![i as f32, 1.0, 2.0].into()),
+                                    encoding: None,
+                                    metadata: None,
+                                    document: None,
+                                    operation: Operation::Add,
+                                },
+                            },
+                        }),
+                    );
+                }
+            }
+            _ => panic!("Expected InMemoryLog"),
+        }
+
+        let mut sysdb = Box::new(SysDb::Test(TestSysDb::new()));
+        match *sysdb {
+            SysDb::Test(ref mut sysdb) => {
+                sysdb.add_collection(Collection {
+                    id: collection_id,
+                    name: "collection".to_string(),
+                    metadata: None,
+                    dimension: Some(3),
+                    tenant: "tenant".to_string(),
+                    database: "database".to_string(),
+                    log_position: -1,
+                    version: 0,
+                });
+                sysdb.add_tenant_last_compaction_time("tenant".to_string(), 0);
+                for (r#type, scope) in [
+                    (
+                        crate::types::SegmentType::BlockfileRecord,
+                        crate::types::SegmentScope::RECORD,
+                    ),
+                    (
+                        crate::types::SegmentType::HnswDistributed,
+                        crate::types::SegmentScope::VECTOR,
+                    ),
+                    (
+                        crate::types::SegmentType::BlockfileMetadata,
+                        crate::types::SegmentScope::METADATA,
+                    ),
+                ] {
+                    sysdb.add_segment(Segment {
+                        id: Uuid::new_v4(),
+                        r#type,
+                        scope,
+                        collection: Some(collection_id),
+                        metadata: None,
+                        file_path: HashMap::new(),
+                    });
+                }
+            }
+            _ => panic!("Invalid sysdb type"),
+        }
+
+        let my_member_id = "1".to_string();
+        let mut assignment_policy = Box::new(RendezvousHashingAssignmentPolicy::new());
+        assignment_policy.set_members(vec![my_member_id.clone()]);
+        let mut scheduler = Scheduler::new(
+            my_member_id.clone(),
+            log.clone(),
+            sysdb.clone(),
+            SchedulerSettings {
+                policy: Box::new(LasCompactionTimeSchedulerPolicy {}),
+                max_concurrent_jobs: 10,
+                min_compaction_size: 0,
+                trigger: CompactionTriggerConfig::default(),
+            },
+            assignment_policy,
+        );
+        scheduler.set_memberlist(vec![my_member_id]);
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmpdir.path().to_str().unwrap()));
+        let mut manager = CompactionManager::new(
+            scheduler,
+            log,
+            sysdb.clone(),
+            storage.clone(),
+            BlockfileProvider::new_arrow(storage.clone()),
+            HnswIndexProvider::new(
+                storage,
+                PathBuf::from(tmpdir.path().to_str().unwrap()),
+                &HnswProviderConfig::default(),
+            ),
+            CompactionManagerSettings {
+                compaction_manager_queue_size: 1000,
+                orchestrator_queue_size: 1000,
+                // Only the schedule sent below runs.
+                compaction_interval: Duration::from_secs(3600),
+                backlog_check_interval: None,
+                min_compaction_size: 0,
+            },
+        );
+        let system = System::new();
+        let mut dispatcher_handle = system.start_component(Dispatcher::new(10, 10, 10));
+        manager.set_dispatcher(dispatcher_handle.receiver());
+        manager.set_system(system.clone());
+        let mut manager_handle = system.start_component(manager);
+
+        // Stop the manager as soon as it has started the job, which takes a while to compact
+        // all of the records.
+        manager_handle
+            .receiver()
+            .send(ScheduleMessage {}, None)
+            .await
+            .unwrap();
+        while manager_handle.queue_depth() > 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(manager_handle.stop_and_join(Duration::from_secs(60)).await);
+        assert!(
+            dispatcher_handle
+                .stop_and_join(Duration::from_secs(60))
+                .await
+        );
+
+        // The job was not cut short by the dispatcher stopping under it.
+        let collections = sysdb
+            .get_collections(Some(collection_id), None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(collections[0].log_position, num_records - 1);
+    }
 }
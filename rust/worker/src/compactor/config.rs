@@ -1,9 +1,150 @@
+use crate::config::{ConfigValidator, ValidateConfig};
 use serde::Deserialize;
 
+const DEFAULT_BACKLOG_WEIGHT: f64 = 0.01;
+const DEFAULT_CHECK_INTERVAL_MS: u64 = 1000;
+const DEFAULT_ORCHESTRATOR_QUEUE_SIZE: usize = 1000;
+
+fn default_backlog_weight() -> f64 {
+    DEFAULT_BACKLOG_WEIGHT
+}
+
+fn default_check_interval_ms() -> u64 {
+    DEFAULT_CHECK_INTERVAL_MS
+}
+
+fn default_orchestrator_queue_size() -> usize {
+    DEFAULT_ORCHESTRATOR_QUEUE_SIZE
+}
+
 #[derive(Deserialize)]
+/// The configuration for the compactor.
+/// # Fields
+/// - compaction_manager_queue_size: The size of the queue of the compaction manager.
+/// - orchestrator_queue_size: The size of the queue of the orchestrator of each compaction
+/// job, which the records pulled from the log and the results of the operators writing them
+/// to the segments go through. Defaults to 1000.
+/// - max_concurrent_jobs: How many compaction jobs are run at once at most.
+/// - compaction_interval_sec: How long to wait between compaction iterations. Every
+/// iteration compacts all collections with new data, so this is also the longest a
+/// collection whose backlog crosses no threshold waits to be compacted.
+/// - min_compaction_size: How many log records a collection needs before it is compacted.
+/// - scheduler_policy: How to choose the collections to compact when there are more than
+/// max_concurrent_jobs. Defaults to LastCompactionTime.
+/// - compaction_trigger: When to compact a collection before the next compaction iteration.
+/// Defaults to never.
+#[serde(deny_unknown_fields)]
 pub(crate) struct CompactorConfig {
     pub(crate) compaction_manager_queue_size: usize,
+    #[serde(default = "default_orchestrator_queue_size")]
+    pub(crate) orchestrator_queue_size: usize,
     pub(crate) max_concurrent_jobs: usize,
     pub(crate) compaction_interval_sec: u64,
     pub(crate) min_compaction_size: usize,
+    #[serde(default)]
+    pub(crate) scheduler_policy: SchedulerPolicyConfig,
+    #[serde(default)]
+    pub(crate) compaction_trigger: CompactionTriggerConfig,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+/// The configuration for compacting collections as soon as their backlog is large.
+/// # Fields
+/// - check_interval_ms: How often the backlogs are checked. Defaults to 1000.
+/// - record_threshold: How many log records a collection needs to be compacted without
+/// waiting for the next compaction iteration. Defaults to none.
+/// - byte_threshold: How many bytes of log records a collection needs to be compacted
+/// without waiting for the next compaction iteration. Defaults to none.
+/// # Notes
+/// The log service does not report the size of a backlog, so only the record threshold
+/// triggers compactions of collections in the log service.
+#[serde(deny_unknown_fields)]
+pub(crate) struct CompactionTriggerConfig {
+    #[serde(default = "default_check_interval_ms")]
+    pub(crate) check_interval_ms: u64,
+    #[serde(default)]
+    pub(crate) record_threshold: Option<u64>,
+    #[serde(default)]
+    pub(crate) byte_threshold: Option<u64>,
+}
+
+impl Default for CompactionTriggerConfig {
+    fn default() -> Self {
+        CompactionTriggerConfig {
+            check_interval_ms: DEFAULT_CHECK_INTERVAL_MS,
+            record_threshold: None,
+            byte_threshold: None,
+        }
+    }
+}
+
+impl CompactionTriggerConfig {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.record_threshold.is_some() || self.byte_threshold.is_some()
+    }
+}
+
+#[derive(Deserialize, Default)]
+/// The configuration for the compaction scheduler policy.
+/// # Options
+/// - LastCompactionTime: Collections whose tenant was compacted longest ago first.
+/// - RoundRobin: Collections in turns.
+/// - Weighted: Collections that have waited longest for the size of their backlog first.
+pub(crate) enum SchedulerPolicyConfig {
+    #[default]
+    LastCompactionTime,
+    RoundRobin,
+    // case-insensitive
+    #[serde(alias = "weighted")]
+    Weighted(WeightedSchedulerPolicyConfig),
+}
+
+#[derive(Deserialize)]
+/// The configuration for the weighted scheduler policy.
+/// # Fields
+/// - backlog_weight: How many seconds of waiting a record of backlog is worth. Defaults to
+/// 0.01, so a collection with 6000 records to compact waits a minute longer than one
+/// with none.
+#[serde(deny_unknown_fields)]
+pub(crate) struct WeightedSchedulerPolicyConfig {
+    #[serde(default = "default_backlog_weight")]
+    pub(crate) backlog_weight: f64,
+}
+
+impl ValidateConfig for CompactorConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.positive(
+            "compaction_manager_queue_size",
+            self.compaction_manager_queue_size,
+        );
+        validator.positive("orchestrator_queue_size", self.orchestrator_queue_size);
+        validator.positive("max_concurrent_jobs", self.max_concurrent_jobs);
+        validator.positive("compaction_interval_sec", self.compaction_interval_sec);
+        if let SchedulerPolicyConfig::Weighted(weighted) = &self.scheduler_policy {
+            validator.section("scheduler_policy.Weighted", weighted);
+        }
+        validator.section("compaction_trigger", &self.compaction_trigger);
+    }
+}
+
+impl ValidateConfig for CompactionTriggerConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.positive("check_interval_ms", self.check_interval_ms);
+        if let Some(record_threshold) = self.record_threshold {
+            validator.positive("record_threshold", record_threshold);
+        }
+        if let Some(byte_threshold) = self.byte_threshold {
+            validator.positive("byte_threshold", byte_threshold);
+        }
+    }
+}
+
+impl ValidateConfig for WeightedSchedulerPolicyConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.check(
+            "backlog_weight",
+            self.backlog_weight >= 0.0 && self.backlog_weight.is_finite(),
+            "a finite number of at least 0",
+        );
+    }
 }
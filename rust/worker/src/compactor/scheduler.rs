@@ -1,13 +1,38 @@
 use crate::assignment::assignment_policy::AssignmentPolicy;
+use crate::compactor::config::CompactionTriggerConfig;
 use crate::compactor::scheduler_policy::SchedulerPolicy;
 use crate::compactor::types::CompactionJob;
+use crate::errors::ChromaError;
 use crate::log::log::CollectionInfo;
 use crate::log::log::CollectionRecord;
 use crate::log::log::Log;
 use crate::memberlist::Memberlist;
 use crate::sysdb::sysdb::SysDb;
+use std::collections::HashSet;
 use uuid::Uuid;
 
+/// What the scheduler schedules and how much of it at once.
+/// # Fields
+/// - policy: The order in which the collections with new data are scheduled.
+/// - max_concurrent_jobs: The most jobs that can be queued or running at once.
+/// - min_compaction_size: The fewest new log records a collection needs to be scheduled.
+/// - trigger: The backlog thresholds that schedule a collection between compaction iterations.
+pub(crate) struct SchedulerSettings {
+    pub(crate) policy: Box<dyn SchedulerPolicy>,
+    pub(crate) max_concurrent_jobs: usize,
+    pub(crate) min_compaction_size: usize,
+    pub(crate) trigger: CompactionTriggerConfig,
+}
+
+/// Chooses the collections to compact.
+/// # Description
+/// `schedule` is run every compaction iteration and schedules the collections with new data,
+/// `schedule_over_threshold` is run between them and only schedules the collections whose
+/// backlog crossed a threshold of the compaction trigger.
+/// # Notes
+/// A collection is never scheduled while a job for it is queued or running, so triggers for
+/// it are coalesced until `finish_job` is called for its job. A job already reads everything
+/// in the log up to when it starts, and whatever comes in later is picked up by the next one.
 pub(crate) struct Scheduler {
     my_ip: String,
     log: Box<Log>,
@@ -16,6 +41,9 @@ pub(crate) struct Scheduler {
     job_queue: Vec<CompactionJob>,
     max_concurrent_jobs: usize,
     min_compaction_size: usize,
+    trigger: CompactionTriggerConfig,
+    // The collections with a job that is queued or running.
+    in_flight: HashSet<Uuid>,
     memberlist: Option<Memberlist>,
     assignment_policy: Box<dyn AssignmentPolicy>,
 }
@@ -25,16 +53,22 @@ impl Scheduler {
         my_ip: String,
         log: Box<Log>,
         sysdb: Box<SysDb>,
-        policy: Box<dyn SchedulerPolicy>,
-        max_concurrent_jobs: usize,
-        min_compaction_size: usize,
+        settings: SchedulerSettings,
         assignment_policy: Box<dyn AssignmentPolicy>,
     ) -> Scheduler {
+        let SchedulerSettings {
+            policy,
+            max_concurrent_jobs,
+            min_compaction_size,
+            trigger,
+        } = settings;
         Scheduler {
             my_ip,
             log,
             sysdb,
             min_compaction_size,
+            trigger,
+            in_flight: HashSet::new(),
             policy,
             job_queue: Vec::with_capacity(max_concurrent_jobs),
             max_concurrent_jobs,
@@ -43,10 +77,13 @@ impl Scheduler {
         }
     }
 
-    async fn get_collections_with_new_data(&mut self) -> Vec<CollectionInfo> {
+    async fn get_collections_with_new_data(
+        &mut self,
+        min_compaction_size: u64,
+    ) -> Vec<CollectionInfo> {
         let collections = self
             .log
-            .get_collections_with_new_data(self.min_compaction_size as u64)
+            .get_collections_with_new_data(min_compaction_size)
             .await;
         // TODO: filter collecitons based on memberlist
         let collections = match collections {
@@ -60,6 +97,42 @@ impl Scheduler {
         collections
     }
 
+    async fn get_collections_over_threshold(&mut self) -> Vec<CollectionInfo> {
+        let min_compaction_size = self.min_compaction_size as u64;
+        let mut collections = Vec::new();
+        if let Some(record_threshold) = self.trigger.record_threshold {
+            // The log only returns the collections with at least this many records, so this
+            // works whether or not it reports their backlog.
+            collections = self
+                .get_collections_with_new_data(record_threshold.max(min_compaction_size))
+                .await;
+        }
+        if let Some(byte_threshold) = self.trigger.byte_threshold {
+            for collection in self
+                .get_collections_with_new_data(min_compaction_size)
+                .await
+            {
+                let over_threshold = collection
+                    .backlog_bytes
+                    .is_some_and(|backlog_bytes| backlog_bytes >= byte_threshold);
+                let already_found = collections
+                    .iter()
+                    .any(|found| found.collection_id == collection.collection_id);
+                if over_threshold && !already_found {
+                    collections.push(collection);
+                }
+            }
+        }
+        collections
+    }
+
+    fn is_in_flight(&self, collection: &CollectionInfo) -> bool {
+        match Uuid::parse_str(collection.collection_id.as_str()) {
+            Ok(collection_id) => self.in_flight.contains(&collection_id),
+            Err(_) => false,
+        }
+    }
+
     async fn verify_and_enrich_collections(
         &mut self,
         collections: Vec<CollectionInfo>,
@@ -97,6 +170,12 @@ impl Scheduler {
                         Err(e) => {
                             // TODO: Log error
                             println!("Error: {:?}", e);
+                            if e.is_retryable() {
+                                // Sysdb already retried this, so it is most likely down.
+                                // Stop this iteration instead of failing every remaining
+                                // collection, the next one will pick them up again.
+                                break;
+                            }
                             // Ignore this collection id for this compaction iteration
                             println!("Ignoring collection: {:?}", collection_info.collection_id);
                             continue;
@@ -124,11 +203,16 @@ impl Scheduler {
                         first_record_time: collection_info.first_log_ts,
                         offset,
                         collection_version: collection[0].version,
+                        backlog: collection_info.backlog,
                     });
                 }
                 Err(e) => {
                     // TODO: Log error
                     println!("Error: {:?}", e);
+                    if e.is_retryable() {
+                        // See above, retrying the remaining collections would fail too.
+                        break;
+                    }
                 }
             }
         }
@@ -160,22 +244,46 @@ impl Scheduler {
     }
 
     pub(crate) async fn schedule_internal(&mut self, collection_records: Vec<CollectionRecord>) {
+        self.job_queue.clear();
+        let collection_records: Vec<CollectionRecord> = collection_records
+            .into_iter()
+            .filter(|collection| !self.in_flight.contains(&collection.id))
+            .collect();
+        // Jobs that are still running count towards the limit.
+        let number_jobs = self
+            .max_concurrent_jobs
+            .saturating_sub(self.in_flight.len());
+        if collection_records.is_empty() || number_jobs == 0 {
+            return;
+        }
         let jobs = self
             .policy
-            .determine(collection_records, self.max_concurrent_jobs as i32);
-        self.job_queue.clear();
+            .determine(collection_records, number_jobs as i32);
+        for job in &jobs {
+            self.in_flight.insert(job.collection_id);
+        }
         self.job_queue.extend(jobs);
     }
 
-    pub(crate) async fn schedule(&mut self) {
-        // For now, we clear the job queue every time, assuming we will not have any pending jobs running
-        self.job_queue.clear();
+    fn can_schedule(&self) -> bool {
         if self.memberlist.is_none() || self.memberlist.as_ref().unwrap().is_empty() {
             // TODO: Log error
             println!("Memberlist is not set or empty. Cannot schedule compaction jobs.");
-            return;
+            return false;
+        }
+        if !self.memberlist.as_ref().unwrap().contains(&self.my_ip) {
+            // This member is being drained, every collection belongs to another member now.
+            println!("This member is not in the memberlist. Not scheduling compaction jobs.");
+            return false;
         }
-        let collections = self.get_collections_with_new_data().await;
+        true
+    }
+
+    async fn schedule_collections(&mut self, collections: Vec<CollectionInfo>) {
+        let collections: Vec<CollectionInfo> = collections
+            .into_iter()
+            .filter(|collection| !self.is_in_flight(collection))
+            .collect();
         if collections.is_empty() {
             return;
         }
@@ -183,11 +291,51 @@ impl Scheduler {
         self.schedule_internal(collection_records).await;
     }
 
+    /// Schedules the collections with at least `min_compaction_size` new log records.
+    pub(crate) async fn schedule(&mut self) {
+        self.job_queue.clear();
+        if !self.can_schedule() {
+            return;
+        }
+        let collections = self
+            .get_collections_with_new_data(self.min_compaction_size as u64)
+            .await;
+        self.schedule_collections(collections).await;
+    }
+
+    /// Schedules the collections whose backlog crossed a threshold of the compaction trigger.
+    /// Schedules nothing if the trigger has no thresholds.
+    pub(crate) async fn schedule_over_threshold(&mut self) {
+        self.job_queue.clear();
+        if !self.trigger.is_enabled() || !self.can_schedule() {
+            return;
+        }
+        let collections = self.get_collections_over_threshold().await;
+        self.schedule_collections(collections).await;
+    }
+
+    /// The jobs scheduled by the last call to `schedule` or `schedule_over_threshold`.
     pub(crate) fn get_jobs(&self) -> impl Iterator<Item = &CompactionJob> {
         self.job_queue.iter()
     }
 
+    /// Marks the job for a collection as done, whether it succeeded or not, so that the
+    /// collection can be scheduled again.
+    pub(crate) fn finish_job(&mut self, collection_id: Uuid) {
+        self.in_flight.remove(&collection_id);
+    }
+
     pub(crate) fn set_memberlist(&mut self, memberlist: Memberlist) {
+        let previous = self.memberlist.as_deref().unwrap_or_default();
+        let joined: Vec<&String> = memberlist
+            .iter()
+            .filter(|member| !previous.contains(member))
+            .collect();
+        let left: Vec<&String> = previous
+            .iter()
+            .filter(|member| !memberlist.contains(member))
+            .collect();
+        println!("Memberlist changed. Joined: {:?}. Left: {:?}", joined, left);
         self.memberlist = Some(memberlist);
     }
 }
@@ -197,6 +345,7 @@ mod tests {
     use super::*;
     use crate::assignment::assignment_policy::RendezvousHashingAssignmentPolicy;
     use crate::compactor::scheduler_policy::LasCompactionTimeSchedulerPolicy;
+    use crate::compactor::scheduler_policy::WeightedSchedulerPolicy;
     use crate::log::log::InMemoryLog;
     use crate::log::log::InternalLogRecord;
     use crate::sysdb::test_sysdb::TestSysDb;
@@ -206,6 +355,13 @@ mod tests {
     use crate::types::OperationRecord;
     use std::str::FromStr;
 
+    fn finish_jobs(scheduler: &mut Scheduler) {
+        let collection_ids: Vec<Uuid> = scheduler.get_jobs().map(|job| job.collection_id).collect();
+        for collection_id in collection_ids {
+            scheduler.finish_job(collection_id);
+        }
+    }
+
     #[tokio::test]
     async fn test_scheduler() {
         let mut log = Box::new(Log::InMemory(InMemoryLog::new()));
@@ -303,9 +459,12 @@ mod tests {
             my_member_id.clone(),
             log,
             sysdb.clone(),
-            scheduler_policy,
-            max_concurrent_jobs,
-            1,
+            SchedulerSettings {
+                policy: scheduler_policy,
+                max_concurrent_jobs,
+                min_compaction_size: 1,
+                trigger: CompactionTriggerConfig::default(),
+            },
             assignment_policy,
         );
         // Scheduler does nothing without memberlist
@@ -328,6 +487,7 @@ mod tests {
         // Scheduler ignores collection that failed to fetch last compaction time
         assert_eq!(jobs.len(), 1);
         assert_eq!(jobs[0].collection_id, collection_uuid_1,);
+        finish_jobs(&mut scheduler);
 
         // Add last compaction time for tenant_2
         match *sysdb {
@@ -344,6 +504,7 @@ mod tests {
         assert_eq!(jobs.len(), 2);
         assert_eq!(jobs[0].collection_id, collection_uuid_2,);
         assert_eq!(jobs[1].collection_id, collection_uuid_1,);
+        finish_jobs(&mut scheduler);
 
         // Test filter_collections
         let member_1 = "1".to_string();
@@ -353,6 +514,300 @@ mod tests {
         scheduler.schedule().await;
         let jobs = scheduler.get_jobs();
         assert_eq!(jobs.count(), 1);
+        finish_jobs(&mut scheduler);
+
+        // No jobs once this member has left the memberlist
+        scheduler.set_memberlist(vec![member_2.clone()]);
+        scheduler.schedule().await;
+        let jobs = scheduler.get_jobs();
+        assert_eq!(jobs.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_with_backlogs() {
+        let mut log = Box::new(Log::InMemory(InMemoryLog::new()));
+        let mut sysdb = Box::new(SysDb::Test(TestSysDb::new()));
+        let tenant = "tenant_1".to_string();
+        // Three collections with different backlogs, compacted last at the same time.
+        let backlogs = [(1, 10), (2, 50), (3, 20)];
+        for (id, backlog) in backlogs {
+            let collection_id = Uuid::from_u128(id);
+            for log_offset in 0..backlog {
+                match *log {
+                    Log::InMemory(ref mut log) => log.add_log(
+                        collection_id,
+                        Box::new(InternalLogRecord {
+                            collection_id,
+                            log_offset,
+                            log_ts: log_offset,
+                            record: LogRecord {
+                                log_offset,
+                                record: OperationRecord {
+                                    id: format!("embedding_id_{}", log_offset),
+                                    embedding: None,
+                                    encoding: None,
+                                    metadata: None,
+                                    document: None,
+                                    operation: Operation::Add,
+                                },
+                            },
+                        }),
+                    ),
+                    _ => panic!("Invalid log type"),
+                }
+            }
+            match *sysdb {
+                SysDb::Test(ref mut sysdb) => sysdb.add_collection(Collection {
+                    id: collection_id,
+                    name: format!("collection_{}", id),
+                    metadata: None,
+                    dimension: Some(1),
+                    tenant: tenant.clone(),
+                    database: "database_1".to_string(),
+                    log_position: -1,
+                    version: 0,
+                }),
+                _ => panic!("Invalid sysdb type"),
+            }
+        }
+        match *sysdb {
+            SysDb::Test(ref mut sysdb) => sysdb.add_tenant_last_compaction_time(tenant, 0),
+            _ => panic!("Invalid sysdb type"),
+        }
+
+        let my_member_id = "1".to_string();
+        let mut assignment_policy = Box::new(RendezvousHashingAssignmentPolicy::new());
+        assignment_policy.set_members(vec![my_member_id.clone()]);
+        let mut scheduler = Scheduler::new(
+            my_member_id.clone(),
+            log,
+            sysdb,
+            SchedulerSettings {
+                policy: Box::new(WeightedSchedulerPolicy::new(1.0)),
+                max_concurrent_jobs: 2,
+                min_compaction_size: 1,
+                trigger: CompactionTriggerConfig::default(),
+            },
+            assignment_policy,
+        );
+        scheduler.set_memberlist(vec![my_member_id]);
+        scheduler.schedule().await;
+        let jobs = scheduler.get_jobs().collect::<Vec<&CompactionJob>>();
+        // No more jobs than max_concurrent_jobs, the smallest backlogs first.
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].collection_id, Uuid::from_u128(1));
+        assert_eq!(jobs[1].collection_id, Uuid::from_u128(3));
+        assert_eq!(jobs[0].offset, 0);
+    }
+
+    fn add_records(
+        scheduler: &mut Scheduler,
+        collection_id: Uuid,
+        log_offsets: std::ops::Range<i64>,
+        dimension: usize,
+    ) {
+        let log = match *scheduler.log {
+            Log::InMemory(ref mut log) => log,
+            _ => panic!("Invalid log type"),
+        };
+        for log_offset in log_offsets {
+            log.add_log(
+                collection_id,
+                Box::new(InternalLogRecord {
+                    collection_id,
+                    log_offset,
+                    log_ts: log_offset,
+                    record: LogRecord {
+                        log_offset,
+                        record: OperationRecord {
+                            id: format!("{:04}", log_offset),
+                            embedding: Some(vec![0.0; dimension].into()),
+                            encoding: None,
+                            metadata: None,
+                            document: None,
+                            operation: Operation::Add,
+                        },
+                    },
+                }),
+            );
+        }
+    }
+
+    // What a compaction job that read the log up to `log_position` leaves behind.
+    async fn compact_up_to(scheduler: &mut Scheduler, collection: &Collection, log_position: i64) {
+        let _ = scheduler
+            .log
+            .update_collection_log_offset(collection.id, log_position)
+            .await;
+        match *scheduler.sysdb {
+            SysDb::Test(ref mut sysdb) => sysdb.add_collection(Collection {
+                log_position,
+                ..collection.clone()
+            }),
+            _ => panic!("Invalid sysdb type"),
+        }
+        scheduler.finish_job(collection.id);
+    }
+
+    async fn scheduled_over_threshold(scheduler: &mut Scheduler) -> Vec<Uuid> {
+        scheduler.schedule_over_threshold().await;
+        scheduler.get_jobs().map(|job| job.collection_id).collect()
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_triggers_on_backlog_thresholds() {
+        let mut sysdb = Box::new(SysDb::Test(TestSysDb::new()));
+        let tenant = "tenant_1".to_string();
+        let collections: Vec<Collection> = (1..=2)
+            .map(|id| Collection {
+                id: Uuid::from_u128(id),
+                name: format!("collection_{}", id),
+                metadata: None,
+                dimension: Some(1),
+                tenant: tenant.clone(),
+                database: "database_1".to_string(),
+                log_position: -1,
+                version: 0,
+            })
+            .collect();
+        match *sysdb {
+            SysDb::Test(ref mut sysdb) => {
+                for collection in &collections {
+                    sysdb.add_collection(collection.clone());
+                }
+                sysdb.add_tenant_last_compaction_time(tenant, 0);
+            }
+            _ => panic!("Invalid sysdb type"),
+        }
+        let (records, bytes) = (&collections[0], &collections[1]);
+
+        let my_member_id = "1".to_string();
+        let mut assignment_policy = Box::new(RendezvousHashingAssignmentPolicy::new());
+        assignment_policy.set_members(vec![my_member_id.clone()]);
+        let mut scheduler = Scheduler::new(
+            my_member_id.clone(),
+            Box::new(Log::InMemory(InMemoryLog::new())),
+            sysdb,
+            SchedulerSettings {
+                policy: Box::new(LasCompactionTimeSchedulerPolicy {}),
+                max_concurrent_jobs: 10,
+                min_compaction_size: 1,
+                trigger: CompactionTriggerConfig {
+                    check_interval_ms: 1000,
+                    record_threshold: Some(10),
+                    // 2 records of a 1000 dimensional embedding, or 1000 records of a 1
+                    // dimensional one.
+                    byte_threshold: Some(8000),
+                },
+            },
+            assignment_policy,
+        );
+        scheduler.set_memberlist(vec![my_member_id]);
+
+        // Below both thresholds
+        add_records(&mut scheduler, records.id, 0..9, 1);
+        add_records(&mut scheduler, bytes.id, 0..1, 1000);
+        assert!(scheduled_over_threshold(&mut scheduler).await.is_empty());
+
+        // Crossing the record threshold schedules one job, and more records or a compaction
+        // iteration don't schedule another while it runs.
+        add_records(&mut scheduler, records.id, 9..10, 1);
+        assert_eq!(
+            scheduled_over_threshold(&mut scheduler).await,
+            vec![records.id]
+        );
+        add_records(&mut scheduler, records.id, 10..15, 1);
+        assert!(scheduled_over_threshold(&mut scheduler).await.is_empty());
+        scheduler.schedule().await;
+        let jobs = scheduler.get_jobs().collect::<Vec<&CompactionJob>>();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].collection_id, bytes.id);
+        finish_jobs(&mut scheduler);
+
+        // Once the job is done, the collection is only scheduled again when its backlog
+        // crosses the threshold again.
+        compact_up_to(&mut scheduler, records, 14).await;
+        assert!(scheduled_over_threshold(&mut scheduler).await.is_empty());
+        add_records(&mut scheduler, records.id, 15..24, 1);
+        assert!(scheduled_over_threshold(&mut scheduler).await.is_empty());
+        add_records(&mut scheduler, records.id, 24..25, 1);
+        assert_eq!(
+            scheduled_over_threshold(&mut scheduler).await,
+            vec![records.id]
+        );
+        compact_up_to(&mut scheduler, records, 24).await;
+
+        // Crossing the byte threshold schedules one job too, even below the record threshold.
+        add_records(&mut scheduler, bytes.id, 1..2, 1000);
+        assert_eq!(
+            scheduled_over_threshold(&mut scheduler).await,
+            vec![bytes.id]
+        );
+        add_records(&mut scheduler, bytes.id, 2..3, 1000);
+        assert!(scheduled_over_threshold(&mut scheduler).await.is_empty());
+        compact_up_to(&mut scheduler, bytes, 2).await;
+        assert!(scheduled_over_threshold(&mut scheduler).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_running_jobs_count_towards_max_concurrent_jobs() {
+        let mut sysdb = Box::new(SysDb::Test(TestSysDb::new()));
+        let tenant = "tenant_1".to_string();
+        let my_member_id = "1".to_string();
+        let mut assignment_policy = Box::new(RendezvousHashingAssignmentPolicy::new());
+        assignment_policy.set_members(vec![my_member_id.clone()]);
+        match *sysdb {
+            SysDb::Test(ref mut sysdb) => {
+                for id in 1..=3 {
+                    sysdb.add_collection(Collection {
+                        id: Uuid::from_u128(id),
+                        name: format!("collection_{}", id),
+                        metadata: None,
+                        dimension: Some(1),
+                        tenant: tenant.clone(),
+                        database: "database_1".to_string(),
+                        log_position: -1,
+                        version: 0,
+                    });
+                }
+                sysdb.add_tenant_last_compaction_time(tenant, 0);
+            }
+            _ => panic!("Invalid sysdb type"),
+        }
+        let mut scheduler = Scheduler::new(
+            my_member_id.clone(),
+            Box::new(Log::InMemory(InMemoryLog::new())),
+            sysdb,
+            SchedulerSettings {
+                policy: Box::new(LasCompactionTimeSchedulerPolicy {}),
+                max_concurrent_jobs: 2,
+                min_compaction_size: 1,
+                trigger: CompactionTriggerConfig::default(),
+            },
+            assignment_policy,
+        );
+        scheduler.set_memberlist(vec![my_member_id]);
+        for id in 1..=3 {
+            add_records(&mut scheduler, Uuid::from_u128(id), 0..1, 1);
+        }
+
+        scheduler.schedule().await;
+        let first: Vec<Uuid> = scheduler.get_jobs().map(|job| job.collection_id).collect();
+        assert_eq!(first.len(), 2);
+        // Both jobs are still running
+        scheduler.schedule().await;
+        assert_eq!(scheduler.get_jobs().count(), 0);
+        // One is done, so the collection that was left out gets the free slot.
+        scheduler.finish_job(first[0]);
+        scheduler.schedule().await;
+        let second: Vec<Uuid> = scheduler.get_jobs().map(|job| job.collection_id).collect();
+        assert_eq!(second.len(), 1);
+        assert!(!first[1..].contains(&second[0]));
+        // Without thresholds, nothing is scheduled between compaction iterations.
+        scheduler.finish_job(first[1]);
+        scheduler.finish_job(second[0]);
+        scheduler.schedule_over_threshold().await;
+        assert_eq!(scheduler.get_jobs().count(), 0);
     }
 
     #[tokio::test]
@@ -479,9 +934,12 @@ mod tests {
             my_ip.clone(),
             log,
             sysdb.clone(),
-            scheduler_policy,
-            max_concurrent_jobs,
-            1,
+            SchedulerSettings {
+                policy: scheduler_policy,
+                max_concurrent_jobs,
+                min_compaction_size: 1,
+                trigger: CompactionTriggerConfig::default(),
+            },
             assignment_policy,
         );
 
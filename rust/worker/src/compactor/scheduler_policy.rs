@@ -1,9 +1,14 @@
+use crate::compactor::config::SchedulerPolicyConfig;
 use crate::compactor::types::CompactionJob;
 use crate::log::log::CollectionRecord;
+use uuid::Uuid;
 
 pub(crate) trait SchedulerPolicy: Send + Sync + SchedulerPolicyClone {
-    fn determine(&self, collections: Vec<CollectionRecord>, number_jobs: i32)
-        -> Vec<CompactionJob>;
+    fn determine(
+        &mut self,
+        collections: Vec<CollectionRecord>,
+        number_jobs: i32,
+    ) -> Vec<CompactionJob>;
 }
 
 pub(crate) trait SchedulerPolicyClone {
@@ -25,12 +30,31 @@ impl Clone for Box<dyn SchedulerPolicy> {
     }
 }
 
+pub(crate) fn from_config(config: &SchedulerPolicyConfig) -> Box<dyn SchedulerPolicy> {
+    match config {
+        SchedulerPolicyConfig::LastCompactionTime => Box::new(LasCompactionTimeSchedulerPolicy {}),
+        SchedulerPolicyConfig::RoundRobin => Box::new(RoundRobinSchedulerPolicy::default()),
+        SchedulerPolicyConfig::Weighted(config) => {
+            Box::new(WeightedSchedulerPolicy::new(config.backlog_weight))
+        }
+    }
+}
+
+fn to_job(collection: &CollectionRecord) -> CompactionJob {
+    CompactionJob {
+        collection_id: collection.id,
+        tenant_id: collection.tenant_id.clone(),
+        offset: collection.offset,
+        collection_version: collection.collection_version,
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct LasCompactionTimeSchedulerPolicy {}
 
 impl SchedulerPolicy for LasCompactionTimeSchedulerPolicy {
     fn determine(
-        &self,
+        &mut self,
         collections: Vec<CollectionRecord>,
         number_jobs: i32,
     ) -> Vec<CompactionJob> {
@@ -43,28 +67,121 @@ impl SchedulerPolicy for LasCompactionTimeSchedulerPolicy {
         };
         let mut tasks = Vec::new();
         for collection in &collections[0..number_tasks as usize] {
-            tasks.push(CompactionJob {
-                collection_id: collection.id.clone(),
-                tenant_id: collection.tenant_id.clone(),
-                offset: collection.offset,
-                collection_version: collection.collection_version,
-            });
+            tasks.push(to_job(collection));
         }
         tasks
     }
 }
 
+/// Schedules the collections in turns.
+/// # Description
+/// Collections are ordered by id, and every iteration starts with the collection after the
+/// last one scheduled in the previous iteration. When there are more collections with new
+/// data than jobs, every collection gets a job within a few iterations, whatever its
+/// backlog or the order the log returns collections in.
+#[derive(Clone, Default)]
+pub(crate) struct RoundRobinSchedulerPolicy {
+    last_scheduled: Option<Uuid>,
+}
+
+impl SchedulerPolicy for RoundRobinSchedulerPolicy {
+    fn determine(
+        &mut self,
+        collections: Vec<CollectionRecord>,
+        number_jobs: i32,
+    ) -> Vec<CompactionJob> {
+        let mut collections = collections;
+        collections.sort_by_key(|collection| collection.id);
+        let next = match self.last_scheduled {
+            Some(last_scheduled) => {
+                collections.partition_point(|collection| collection.id <= last_scheduled)
+            }
+            None => 0,
+        };
+        collections.rotate_left(next);
+        let jobs: Vec<CompactionJob> = collections
+            .iter()
+            .take(number_jobs.max(0) as usize)
+            .map(to_job)
+            .collect();
+        if let Some(job) = jobs.last() {
+            self.last_scheduled = Some(job.collection_id);
+        }
+        jobs
+    }
+}
+
+/// Schedules the collections that have waited longest for the size of their backlog first.
+/// # Description
+/// A collection is due at its last compaction time, pushed back by `backlog_weight` seconds
+/// for every log record it has to compact, and the collections that are due first are
+/// scheduled. So a collection with a few new records is compacted before a bulk import
+/// that was last compacted at the same time, but the bulk import still gets its turn once
+/// it has waited long enough.
+/// # Notes
+/// Collections whose backlog the log does not report are treated as having none.
+#[derive(Clone)]
+pub(crate) struct WeightedSchedulerPolicy {
+    backlog_weight: f64,
+}
+
+impl WeightedSchedulerPolicy {
+    pub(crate) fn new(backlog_weight: f64) -> Self {
+        WeightedSchedulerPolicy { backlog_weight }
+    }
+
+    fn due_at(&self, collection: &CollectionRecord) -> f64 {
+        let backlog = collection.backlog.unwrap_or(0) as f64;
+        collection.last_compaction_time as f64 + self.backlog_weight * backlog
+    }
+}
+
+impl SchedulerPolicy for WeightedSchedulerPolicy {
+    fn determine(
+        &mut self,
+        collections: Vec<CollectionRecord>,
+        number_jobs: i32,
+    ) -> Vec<CompactionJob> {
+        let mut collections = collections;
+        collections.sort_by(|a, b| {
+            self.due_at(a)
+                .total_cmp(&self.due_at(b))
+                .then(a.first_record_time.cmp(&b.first_record_time))
+        });
+        collections
+            .iter()
+            .take(number_jobs.max(0) as usize)
+            .map(to_job)
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::str::FromStr;
-    use uuid::Uuid;
+
+    fn collection(id: u128, last_compaction_time: i64, backlog: Option<u64>) -> CollectionRecord {
+        CollectionRecord {
+            id: Uuid::from_u128(id),
+            tenant_id: "test".to_string(),
+            last_compaction_time,
+            first_record_time: last_compaction_time,
+            offset: 0,
+            collection_version: 0,
+            backlog,
+        }
+    }
+
+    fn collection_ids(jobs: &[CompactionJob]) -> Vec<u128> {
+        jobs.iter().map(|job| job.collection_id.as_u128()).collect()
+    }
 
     #[test]
     fn test_scheduler_policy() {
         let collection_uuid_1 = Uuid::from_str("00000000-0000-0000-0000-000000000001").unwrap();
         let collection_uuid_2 = Uuid::from_str("00000000-0000-0000-0000-000000000002").unwrap();
-        let scheduler_policy = LasCompactionTimeSchedulerPolicy {};
+        let mut scheduler_policy = LasCompactionTimeSchedulerPolicy {};
         let collections = vec![
             CollectionRecord {
                 id: collection_uuid_1,
@@ -73,6 +190,7 @@ mod tests {
                 first_record_time: 1,
                 offset: 0,
                 collection_version: 0,
+                backlog: None,
             },
             CollectionRecord {
                 id: collection_uuid_2,
@@ -81,6 +199,7 @@ mod tests {
                 first_record_time: 0,
                 offset: 0,
                 collection_version: 0,
+                backlog: None,
             },
         ];
         let jobs = scheduler_policy.determine(collections.clone(), 1);
@@ -92,4 +211,65 @@ mod tests {
         assert_eq!(jobs[0].collection_id, collection_uuid_2);
         assert_eq!(jobs[1].collection_id, collection_uuid_1);
     }
+
+    #[test]
+    fn test_round_robin_scheduler_policy() {
+        let mut scheduler_policy = RoundRobinSchedulerPolicy::default();
+        // The log returns collections in any order, and the ones with a huge backlog
+        // don't go first.
+        let collections = vec![
+            collection(3, 0, Some(100000)),
+            collection(1, 0, Some(10)),
+            collection(5, 0, Some(10)),
+            collection(2, 0, Some(100000)),
+            collection(4, 0, Some(10)),
+        ];
+        let mut iterations = Vec::new();
+        for _ in 0..4 {
+            let jobs = scheduler_policy.determine(collections.clone(), 2);
+            iterations.push(collection_ids(&jobs));
+        }
+        assert_eq!(
+            iterations,
+            vec![vec![1, 2], vec![3, 4], vec![5, 1], vec![2, 3]]
+        );
+
+        // Collections that come and go don't reset the turns.
+        let collections = vec![collection(1, 0, None), collection(5, 0, None)];
+        let jobs = scheduler_policy.determine(collections, 1);
+        assert_eq!(collection_ids(&jobs), vec![5]);
+
+        let jobs = scheduler_policy.determine(vec![collection(1, 0, None)], 3);
+        assert_eq!(collection_ids(&jobs), vec![1]);
+        assert!(scheduler_policy.determine(vec![], 3).is_empty());
+        assert!(scheduler_policy
+            .determine(vec![collection(1, 0, None)], 0)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_weighted_scheduler_policy() {
+        let mut scheduler_policy = WeightedSchedulerPolicy::new(0.01);
+        let collections = vec![
+            // A bulk import, due 1000 seconds after its last compaction.
+            collection(1, 100, Some(100000)),
+            // Small collections, written to often.
+            collection(2, 200, Some(10)),
+            collection(3, 150, Some(20)),
+            // The log does not know its backlog.
+            collection(4, 300, None),
+            // Not compacted for so long that its backlog doesn't matter.
+            collection(5, -5000, Some(100000)),
+        ];
+        let jobs = scheduler_policy.determine(collections.clone(), 10);
+        assert_eq!(collection_ids(&jobs), vec![5, 3, 2, 4, 1]);
+
+        let jobs = scheduler_policy.determine(collections.clone(), 3);
+        assert_eq!(collection_ids(&jobs), vec![5, 3, 2]);
+
+        // Without a backlog weight, the policy schedules by last compaction time only.
+        let mut scheduler_policy = WeightedSchedulerPolicy::new(0.0);
+        let jobs = scheduler_policy.determine(collections, 10);
+        assert_eq!(collection_ids(&jobs), vec![5, 1, 3, 2, 4]);
+    }
 }
@@ -1,3 +1,5 @@
+use crate::errors::ChromaError;
+use crate::execution::orchestration::CompactionResponse;
 use uuid::Uuid;
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -10,3 +12,12 @@ pub(crate) struct CompactionJob {
 
 #[derive(Clone, Debug)]
 pub(crate) struct ScheduleMessage {}
+
+#[derive(Clone, Debug)]
+pub(crate) struct CheckBacklogMessage {}
+
+#[derive(Debug)]
+pub(crate) struct CompactionJobDone {
+    pub(crate) collection_id: Uuid,
+    pub(crate) result: Result<CompactionResponse, Box<dyn ChromaError>>,
+}
@@ -1,21 +1,66 @@
 use async_trait::async_trait;
 use figment::providers::{Env, Format, Serialized, Yaml};
+use figment::value::{Dict, Map, Value};
+use figment::{Figment, Metadata, Profile, Provider};
+use serde::de::{Deserializer, Visitor};
 use serde::Deserialize;
+use std::fmt::Write;
+use thiserror::Error;
 
-use crate::errors::ChromaError;
+use crate::errors::{ChromaError, ErrorCodes};
 
 const DEFAULT_CONFIG_PATH: &str = "./chroma_config.yaml";
 const ENV_PREFIX: &str = "CHROMA_";
+// Variables with this prefix configure every service, under CHROMA_ they configure one.
+const WORKER_ENV_PREFIX: &str = "CHROMA_WORKER__";
+// The values of keys containing any of these are left out when the config is logged.
+const SECRET_KEY_FRAGMENTS: [&str; 6] = [
+    "password",
+    "secret",
+    "token",
+    "api_key",
+    "access_key",
+    "private_key",
+];
+const REDACTED: &str = "<redacted>";
+const DEFAULT_SHUTDOWN_GRACE_PERIOD_MS: u64 = 25000;
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 30000;
+const DEFAULT_ADMIN_REQUEST_TIMEOUT_MS: u64 = 600000;
+
+fn default_shutdown_grace_period_ms() -> u64 {
+    DEFAULT_SHUTDOWN_GRACE_PERIOD_MS
+}
+
+fn default_request_timeout_ms() -> u64 {
+    DEFAULT_REQUEST_TIMEOUT_MS
+}
+
+fn default_admin_request_timeout_ms() -> u64 {
+    DEFAULT_ADMIN_REQUEST_TIMEOUT_MS
+}
 
 #[derive(Deserialize)]
 /// # Description
 /// The RootConfig for all chroma services this is a YAML file that
 /// is shared between all services, and secondarily, fields can be
-/// populated from environment variables. The environment variables
-/// are prefixed with CHROMA_ and are uppercase. Values in the envionment
-/// variables take precedence over values in the YAML file.
+/// populated from environment variables. The config is layered, and
+/// every layer takes precedence over the ones before it:
+/// - The defaults of the fields that have one.
+/// - The YAML file.
+/// - Environment variables prefixed with CHROMA_WORKER__, which set
+/// a field of every service, e.g. CHROMA_WORKER__SYSDB__GRPC__HOST.
+/// - Environment variables prefixed with CHROMA_ and the name of a
+/// service, which set a field of that service only, e.g.
+/// CHROMA_QUERY_SERVICE__SYSDB__GRPC__HOST.
+/// Nested fields are separated by `__` and names are case-insensitive.
+/// Values are parsed as integers, floats, booleans, lists (`[a, b]`)
+/// and maps (`{key=value}`) when they can be, and as strings otherwise.
 /// By default, it is read from the current working directory,
 /// with the filename chroma_config.yaml.
+/// # Validation
+/// Unknown fields of a service are rejected, but the file can have sections
+/// for other services. Once deserialized, every section is validated, and
+/// all the values that can't be used are reported together.
 pub(crate) struct RootConfig {
     // The root config object wraps the worker config object so that
     // we can share the same config file between multiple services.
@@ -28,18 +73,14 @@ impl RootConfig {
     /// Load the config from the default location.
     /// # Returns
     /// The config object.
-    /// # Panics
-    /// - If the config file cannot be read.
-    /// - If the config file is not valid YAML.
-    /// - If the config file does not contain the required fields.
-    /// - If the config file contains invalid values.
-    /// - If the environment variables contain invalid values.
+    /// # Errors
+    /// See [`RootConfig::load_from_path`].
     /// # Notes
     /// The default location is the current working directory, with the filename chroma_config.yaml.
     /// The environment variables are prefixed with CHROMA_ and are uppercase.
     /// Values in the envionment variables take precedence over values in the YAML file.
-    pub(crate) fn load() -> Self {
-        return Self::load_from_path(DEFAULT_CONFIG_PATH);
+    pub(crate) fn load() -> Result<Self, ConfigError> {
+        Self::load_from_path(DEFAULT_CONFIG_PATH)
     }
 
     /// # Description
@@ -48,79 +89,446 @@ impl RootConfig {
     /// - path: The path to the config file.
     /// # Returns
     /// The config object.
-    /// # Panics
-    /// - If the config file cannot be read.
-    /// - If the config file is not valid YAML.
-    /// - If the config file does not contain the required fields.
-    /// - If the config file contains invalid values.
-    /// - If the environment variables contain invalid values.
+    /// # Errors
+    /// - `Load` if the config can't be deserialized, e.g. because the config file is not
+    /// valid YAML, a required field is missing, a field is unknown or a value has the wrong
+    /// type. The error names the first such value, and the environment variable it came from
+    /// if it came from one.
+    /// - `Invalid` with every value that deserializes but can't be used, e.g. an empty
+    /// bucket or a queue size of 0.
     /// # Notes
-    /// The environment variables are prefixed with CHROMA_ and are uppercase.
-    /// Values in the envionment variables take precedence over values in the YAML file.
-    pub(crate) fn load_from_path(path: &str) -> Self {
-        // Unfortunately, figment doesn't support environment variables with underscores. So we have to map and replace them.
-        // Excluding our own environment variables, which are prefixed with CHROMA_.
-        let mut f = figment::Figment::from(Env::prefixed("CHROMA_").map(|k| match k {
-            k if k == "my_member_id" => k.into(),
-            k => k.as_str().replace("__", ".").into(),
-        }));
+    /// See [`RootConfig`] for the environment variables and their precedence.
+    /// The loaded config is logged, without the values of secrets.
+    pub(crate) fn load_from_path(path: &str) -> Result<Self, ConfigError> {
+        let f = Self::figment(path);
+        let config: RootConfig = f.extract().map_err(|e| ConfigError::Load(Box::new(e)))?;
+        let mut validator = ConfigValidator::default();
+        config.validate(&mut validator);
+        validator.finish()?;
+        // TODO: switch to logging when logging is implemented
+        println!("Loaded config: {}", redacted(&f));
+        Ok(config)
+    }
+
+    fn figment(path: &str) -> Figment {
+        let mut f = Figment::new();
         if std::path::Path::new(path).exists() {
-            f = figment::Figment::from(Yaml::file(path)).merge(f);
+            f = f.merge(Yaml::file(path));
         }
+        // An invalid file fails the extraction of the config instead.
+        let base: Dict = f.extract().unwrap_or_default();
         // Apply defaults - this seems to be the best way to do it.
         // https://github.com/SergioBenitez/Figment/issues/77#issuecomment-1642490298
         // f = f.join(Serialized::default(
         //     "worker.num_indexing_threads",
         //     num_cpus::get(),
         // ));
-        let res = f.extract();
-        match res {
-            Ok(config) => return config,
-            Err(e) => panic!("Error loading config: {}", e),
+        f.merge(EnvOverrides {
+            prefix: WORKER_ENV_PREFIX,
+            sections: vec![
+                ("query_service", field_names::<QueryServiceConfig>()),
+                (
+                    "compaction_service",
+                    field_names::<CompactionServiceConfig>(),
+                ),
+            ],
+            base: base.clone(),
+        })
+        .merge(EnvOverrides {
+            prefix: ENV_PREFIX,
+            sections: vec![],
+            base,
+        })
+    }
+}
+
+impl ValidateConfig for RootConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.section("query_service", &self.query_service);
+        validator.section("compaction_service", &self.compaction_service);
+    }
+}
+
+/// # Description
+/// A layer of the config read from the environment variables prefixed with `prefix`.
+/// # Fields
+/// - prefix: The prefix of the variables, which is not part of their key.
+/// - sections: The sections of the root config every variable sets its key in, with the
+/// fields of the section. A variable only sets its key in the sections that have the field,
+/// or in all of them if none does. When empty, the section is the first part of the key
+/// instead.
+/// - base: The config the variables override. Keys are spelled the way they are in it, so
+/// that CHROMA_WORKER__SYSDB__GRPC__HOST overrides the host of `Grpc` in the YAML file.
+/// # Notes
+/// Unfortunately, figment doesn't support environment variables with underscores. So we have
+/// to split keys on `__` instead of `_`. Errors name the variable a bad value came from,
+/// rather than its key in the config.
+struct EnvOverrides {
+    prefix: &'static str,
+    sections: Vec<(&'static str, &'static [&'static str])>,
+    base: Dict,
+}
+
+impl EnvOverrides {
+    fn env(&self) -> Env {
+        let env = Env::prefixed(self.prefix).split("__");
+        match self.prefix == ENV_PREFIX {
+            // The variables for every service are their own layer.
+            true => env.filter(|k| !k.starts_with(&WORKER_ENV_PREFIX[ENV_PREFIX.len()..])),
+            false => env,
         }
     }
 }
 
+impl Provider for EnvOverrides {
+    fn metadata(&self) -> Metadata {
+        let prefix = self.prefix;
+        let skip = match self.sections.is_empty() {
+            true => 0,
+            false => 1,
+        };
+        Metadata::named(format!("`{}` environment variable(s)", prefix)).interpolater(
+            move |_: &Profile, keys: &[&str]| {
+                let keys: Vec<_> = keys
+                    .iter()
+                    .skip(skip)
+                    .map(|k| k.to_ascii_uppercase())
+                    .collect();
+                format!("{}{}", prefix, keys.join("__"))
+            },
+        )
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
+        let mut dict = Dict::new();
+        for (key, value) in self.env().iter() {
+            let keys: Vec<&str> = key.as_str().split('.').collect();
+            match self.sections.is_empty() {
+                true => insert(&mut dict, Some(&self.base), &keys, &value),
+                false => {
+                    let has_field = |fields: &[&str]| {
+                        fields
+                            .iter()
+                            .any(|field| field.eq_ignore_ascii_case(keys[0]))
+                    };
+                    let sections: Vec<_> =
+                        match self.sections.iter().any(|(_, fields)| has_field(fields)) {
+                            true => self
+                                .sections
+                                .iter()
+                                .filter(|(_, fields)| has_field(fields))
+                                .collect(),
+                            // Every section rejects the unknown field, naming the variable.
+                            false => self.sections.iter().collect(),
+                        };
+                    for (section, _) in sections {
+                        let keys = [&[*section], &keys[..]].concat();
+                        insert(&mut dict, Some(&self.base), &keys, &value);
+                    }
+                }
+            }
+        }
+        Ok(Profile::Default.collect(dict))
+    }
+}
+
+// Sets the value at `keys` in `dict`. Every key is spelled the way the same key, ignoring
+// case, is spelled in `base`. Values are parsed the way figment parses environment
+// variables, except the ones that override a string in `base`, which stay strings.
+fn insert(dict: &mut Dict, base: Option<&Dict>, keys: &[&str], value: &str) {
+    let (key, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let base_entry = base.and_then(|base| {
+        base.get_key_value(*key)
+            .or_else(|| base.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)))
+    });
+    let key = match base_entry {
+        Some((base_key, _)) => base_key.clone(),
+        None => key.to_string(),
+    };
+    let base_value = base_entry.map(|(_, base_value)| base_value);
+    if rest.is_empty() {
+        let value = match base_value {
+            Some(Value::String(..)) => Value::from(value),
+            _ => value.parse().expect("infallible"),
+        };
+        dict.insert(key, value);
+        return;
+    }
+    let entry = dict.entry(key).or_insert_with(|| Value::from(Dict::new()));
+    if !matches!(entry, Value::Dict(..)) {
+        *entry = Value::from(Dict::new());
+    }
+    if let Value::Dict(_, entry) = entry {
+        let base = match base_value {
+            Some(Value::Dict(_, base)) => Some(base),
+            _ => None,
+        };
+        insert(entry, base, rest, value);
+    }
+}
+
+// The names of the fields of the config struct `T`, which its derived `Deserialize` hands to
+// the deserializer.
+fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldNames;
+
+    #[derive(Debug)]
+    struct Found(&'static [&'static str]);
+
+    impl std::fmt::Display for Found {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+
+    impl std::error::Error for Found {}
+
+    impl serde::de::Error for Found {
+        fn custom<E: std::fmt::Display>(_: E) -> Self {
+            Found(&[])
+        }
+    }
+
+    impl<'de> Deserializer<'de> for FieldNames {
+        type Error = Found;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Found> {
+            Err(Found(&[]))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Found> {
+            Err(Found(fields))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+            byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct map enum
+            identifier ignored_any
+        }
+    }
+
+    match T::deserialize(FieldNames) {
+        Ok(_) => &[],
+        Err(Found(fields)) => fields,
+    }
+}
+
+/// # Description
+/// A value of the config that deserializes but can't be used.
+/// # Fields
+/// - path: The key of the value, e.g. query_service.storage.S3.bucket.
+/// - expected: What the value is expected to be.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ConfigViolation {
+    pub(crate) path: String,
+    pub(crate) expected: String,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum ConfigError {
+    #[error("Error loading config: {0}")]
+    Load(Box<figment::Error>),
+    #[error("Invalid config:{}", format_violations(.0))]
+    Invalid(Vec<ConfigViolation>),
+}
+
+impl ChromaError for ConfigError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::InvalidArgument
+    }
+}
+
+fn format_violations(violations: &[ConfigViolation]) -> String {
+    violations
+        .iter()
+        .fold(String::new(), |mut list, violation| {
+            let _ = write!(
+                list,
+                "\n- {}: expected {}",
+                violation.path, violation.expected
+            );
+            list
+        })
+}
+
+/// # Description
+/// A section of the config that checks its values once it is deserialized.
+/// # Notes
+/// Every section validates its own values and then the sections nested in it, so that all
+/// the violations of a config are reported at once.
+pub(crate) trait ValidateConfig {
+    fn validate(&self, validator: &mut ConfigValidator);
+}
+
+/// # Description
+/// Collects the violations of a config, along with the key of every value.
+#[derive(Default)]
+pub(crate) struct ConfigValidator {
+    path: Vec<String>,
+    violations: Vec<ConfigViolation>,
+}
+
+impl ConfigValidator {
+    /// Validates a nested section of the config under `key`.
+    pub(crate) fn section<T: ValidateConfig + ?Sized>(&mut self, key: &str, section: &T) {
+        self.path.push(key.to_string());
+        section.validate(self);
+        self.path.pop();
+    }
+
+    /// Records a violation of the value under `key` if it is not valid.
+    pub(crate) fn check(&mut self, key: &str, valid: bool, expected: &str) {
+        if !valid {
+            let path = self
+                .path
+                .iter()
+                .map(|key| key.as_str())
+                .chain(std::iter::once(key))
+                .collect::<Vec<_>>()
+                .join(".");
+            self.violations.push(ConfigViolation {
+                path,
+                expected: expected.to_string(),
+            });
+        }
+    }
+
+    pub(crate) fn non_empty(&mut self, key: &str, value: &str) {
+        self.check(key, !value.trim().is_empty(), "a non-empty string");
+    }
+
+    pub(crate) fn positive<T: PartialOrd + Default>(&mut self, key: &str, value: T) {
+        self.check(key, value > T::default(), "a number greater than 0");
+    }
+
+    pub(crate) fn port(&mut self, key: &str, value: u16) {
+        self.check(key, value > 0, "a port between 1 and 65535");
+    }
+
+    fn finish(self) -> Result<(), ConfigError> {
+        match self.violations.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigError::Invalid(self.violations)),
+        }
+    }
+}
+
+/// # Description
+/// The config loaded by `f` as JSON, with the values of keys that look like secrets replaced.
+/// # Notes
+/// Fields that are left to their defaults are not part of any layer, and are not included.
+fn redacted(f: &Figment) -> String {
+    fn redact(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(object) => {
+                for (key, value) in object.iter_mut() {
+                    let key = key.to_ascii_lowercase();
+                    match SECRET_KEY_FRAGMENTS
+                        .iter()
+                        .any(|fragment| key.contains(fragment))
+                    {
+                        true => *value = serde_json::Value::String(REDACTED.to_string()),
+                        false => redact(value),
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(redact),
+            _ => {}
+        }
+    }
+
+    match f.extract::<serde_json::Value>() {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(e) => format!("<invalid config: {}>", e),
+    }
+}
+
 #[derive(Deserialize)]
 /// # Description
 /// The primary config for the worker service.
 /// ## Description of parameters
+/// - otel_endpoint: The OTLP collector spans are exported to. When unset, spans are only
+/// printed.
 /// - my_ip: The IP address of the worker service. Used for memberlist assignment. Must be provided.
 /// - assignment_policy: The assignment policy to use. Must be provided.
+/// - shutdown_grace_period_ms: How long in-flight queries have to finish after SIGTERM.
+/// Defaults to 25000, within the 30 seconds kubernetes waits before it kills the pod.
+/// - default_request_timeout_ms: The deadline of queries that arrive without a gRPC
+/// deadline. Defaults to 30000.
+/// - admin_request_timeout_ms: The deadline of index checks and rebuilds that arrive without
+/// a gRPC deadline. Rebuilding the index of a large collection takes far longer than a query,
+/// so this defaults to 600000.
 /// # Notes
-/// In order to set the enviroment variables, you must prefix them with CHROMA_WORKER__<FIELD_NAME>.
-/// For example, to set my_ip, you would set CHROMA_WORKER__MY_IP.
+/// In order to set the enviroment variables, you must prefix them with CHROMA_QUERY_SERVICE__<FIELD_NAME>,
+/// or with CHROMA_WORKER__<FIELD_NAME> to set the field of every service.
+/// For example, to set my_port, you would set CHROMA_QUERY_SERVICE__MY_PORT.
 /// Each submodule that needs to be configured from the config object should implement the Configurable trait and
 /// have its own field in this struct for its Config struct.
+#[serde(deny_unknown_fields)]
 pub(crate) struct QueryServiceConfig {
     pub(crate) service_name: String,
-    pub(crate) otel_endpoint: String,
+    #[serde(default)]
+    pub(crate) otel_endpoint: Option<String>,
     pub(crate) my_member_id: String,
     pub(crate) my_port: u16,
+    #[serde(default = "default_shutdown_grace_period_ms")]
+    pub(crate) shutdown_grace_period_ms: u64,
+    #[serde(default = "default_request_timeout_ms")]
+    pub(crate) default_request_timeout_ms: u64,
+    #[serde(default = "default_admin_request_timeout_ms")]
+    pub(crate) admin_request_timeout_ms: u64,
     pub(crate) assignment_policy: crate::assignment::config::AssignmentPolicyConfig,
     pub(crate) memberlist_provider: crate::memberlist::config::MemberlistProviderConfig,
     pub(crate) sysdb: crate::sysdb::config::SysDbConfig,
     pub(crate) storage: crate::storage::config::StorageConfig,
     pub(crate) log: crate::log::config::LogConfig,
     pub(crate) dispatcher: crate::execution::config::DispatcherConfig,
+    #[serde(default)]
+    pub(crate) distance: crate::distance::config::DistanceConfig,
+    #[serde(default)]
+    pub(crate) hnsw_provider: crate::index::config::HnswProviderConfig,
+    #[serde(default)]
+    pub(crate) blockfile_provider: crate::blockstore::config::BlockfileProviderConfig,
+    #[serde(default)]
+    pub(crate) metrics: crate::tracing::config::MetricsConfig,
 }
 
 #[derive(Deserialize)]
 /// # Description
 /// The primary config for the compaction service.
 /// ## Description of parameters
+/// - otel_endpoint: The OTLP collector spans are exported to. When unset, spans are only
+/// printed.
 /// - my_ip: The IP address of the worker service. Used for memberlist assignment. Must be provided.
 /// - assignment_policy: The assignment policy to use. Must be provided.
+/// - shutdown_grace_period_ms: How long in-flight compactions have to finish after SIGTERM.
+/// Defaults to 25000, within the 30 seconds kubernetes waits before it kills the pod.
 /// # Notes
-/// In order to set the enviroment variables, you must prefix them with CHROMA_COMPACTOR__<FIELD_NAME>.
-/// For example, to set my_ip, you would set CHROMA_COMPACTOR__MY_IP.
+/// In order to set the enviroment variables, you must prefix them with CHROMA_COMPACTION_SERVICE__<FIELD_NAME>,
+/// or with CHROMA_WORKER__<FIELD_NAME> to set the field of every service.
+/// For example, to set my_port, you would set CHROMA_COMPACTION_SERVICE__MY_PORT.
 /// Each submodule that needs to be configured from the config object should implement the Configurable trait and
 /// have its own field in this struct for its Config struct.
+#[serde(deny_unknown_fields)]
 pub(crate) struct CompactionServiceConfig {
     pub(crate) service_name: String,
-    pub(crate) otel_endpoint: String,
+    #[serde(default)]
+    pub(crate) otel_endpoint: Option<String>,
     pub(crate) my_member_id: String,
     pub(crate) my_port: u16,
+    #[serde(default = "default_shutdown_grace_period_ms")]
+    pub(crate) shutdown_grace_period_ms: u64,
     pub(crate) assignment_policy: crate::assignment::config::AssignmentPolicyConfig,
     pub(crate) memberlist_provider: crate::memberlist::config::MemberlistProviderConfig,
     pub(crate) sysdb: crate::sysdb::config::SysDbConfig,
@@ -128,6 +536,56 @@ pub(crate) struct CompactionServiceConfig {
     pub(crate) log: crate::log::config::LogConfig,
     pub(crate) dispatcher: crate::execution::config::DispatcherConfig,
     pub(crate) compactor: crate::compactor::config::CompactorConfig,
+    #[serde(default)]
+    pub(crate) hnsw_provider: crate::index::config::HnswProviderConfig,
+    #[serde(default)]
+    pub(crate) metrics: crate::tracing::config::MetricsConfig,
+}
+
+impl ValidateConfig for QueryServiceConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.non_empty("service_name", &self.service_name);
+        if let Some(otel_endpoint) = &self.otel_endpoint {
+            validator.non_empty("otel_endpoint", otel_endpoint);
+        }
+        validator.non_empty("my_member_id", &self.my_member_id);
+        validator.port("my_port", self.my_port);
+        validator.positive(
+            "default_request_timeout_ms",
+            self.default_request_timeout_ms,
+        );
+        validator.positive("admin_request_timeout_ms", self.admin_request_timeout_ms);
+        validator.section("assignment_policy", &self.assignment_policy);
+        validator.section("memberlist_provider", &self.memberlist_provider);
+        validator.section("sysdb", &self.sysdb);
+        validator.section("storage", &self.storage);
+        validator.section("log", &self.log);
+        validator.section("dispatcher", &self.dispatcher);
+        validator.section("distance", &self.distance);
+        validator.section("hnsw_provider", &self.hnsw_provider);
+        validator.section("blockfile_provider", &self.blockfile_provider);
+        validator.section("metrics", &self.metrics);
+    }
+}
+
+impl ValidateConfig for CompactionServiceConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.non_empty("service_name", &self.service_name);
+        if let Some(otel_endpoint) = &self.otel_endpoint {
+            validator.non_empty("otel_endpoint", otel_endpoint);
+        }
+        validator.non_empty("my_member_id", &self.my_member_id);
+        validator.port("my_port", self.my_port);
+        validator.section("assignment_policy", &self.assignment_policy);
+        validator.section("memberlist_provider", &self.memberlist_provider);
+        validator.section("sysdb", &self.sysdb);
+        validator.section("storage", &self.storage);
+        validator.section("log", &self.log);
+        validator.section("dispatcher", &self.dispatcher);
+        validator.section("compactor", &self.compactor);
+        validator.section("hnsw_provider", &self.hnsw_provider);
+        validator.section("metrics", &self.metrics);
+    }
 }
 
 /// # Description
@@ -147,6 +605,98 @@ mod tests {
     use super::*;
     use figment::Jail;
 
+    const TEST_CONFIG: &str = r#"
+        query_service:
+            service_name: "query-service"
+            otel_endpoint: "http://jaeger:4317"
+            my_member_id: "query-service-0"
+            my_port: 50051
+            assignment_policy:
+                RendezvousHashing:
+                    hasher: Murmur3
+            memberlist_provider:
+                CustomResource:
+                    kube_namespace: "chroma"
+                    memberlist_name: "query-service-memberlist"
+                    queue_size: 100
+            sysdb:
+                Grpc:
+                    host: "localhost"
+                    port: 50051
+                    connect_timeout_ms: 5000
+                    request_timeout_ms: 1000
+            storage:
+                S3:
+                    bucket: "chroma"
+                    credentials: Minio
+                    connect_timeout_ms: 5000
+                    request_timeout_ms: 1000
+            log:
+                Grpc:
+                    host: "localhost"
+                    port: 50051
+                    connect_timeout_ms: 5000
+                    request_timeout_ms: 1000
+            dispatcher:
+                num_worker_threads: 4
+                dispatcher_queue_size: 100
+                worker_queue_size: 100
+
+        compaction_service:
+            service_name: "compaction-service"
+            otel_endpoint: "http://jaeger:4317"
+            my_member_id: "compaction-service-0"
+            my_port: 50051
+            assignment_policy:
+                RendezvousHashing:
+                    hasher: Murmur3
+            memberlist_provider:
+                CustomResource:
+                    kube_namespace: "chroma"
+                    memberlist_name: "compaction-service-memberlist"
+                    queue_size: 100
+            sysdb:
+                Grpc:
+                    host: "localhost"
+                    port: 50051
+                    connect_timeout_ms: 5000
+                    request_timeout_ms: 1000
+            storage:
+                S3:
+                    bucket: "chroma"
+                    credentials: Minio
+                    connect_timeout_ms: 5000
+                    request_timeout_ms: 1000
+            log:
+                Grpc:
+                    host: "localhost"
+                    port: 50051
+                    connect_timeout_ms: 5000
+                    request_timeout_ms: 1000
+            dispatcher:
+                num_worker_threads: 4
+                dispatcher_queue_size: 100
+                worker_queue_size: 100
+            compactor:
+                compaction_manager_queue_size: 1000
+                max_concurrent_jobs: 100
+                compaction_interval_sec: 60
+                min_compaction_size: 10
+        "#;
+
+    fn sysdb_host(sysdb: &crate::sysdb::config::SysDbConfig) -> &str {
+        match sysdb {
+            crate::sysdb::config::SysDbConfig::Grpc(grpc) => &grpc.host,
+        }
+    }
+
+    fn bucket(storage: &crate::storage::config::StorageConfig) -> &str {
+        match storage {
+            crate::storage::config::StorageConfig::S3(s3) => &s3.bucket,
+            _ => panic!("Invalid storage config"),
+        }
+    }
+
     #[test]
     fn test_config_from_default_path() {
         Jail::expect_with(|jail| {
@@ -188,6 +738,11 @@ mod tests {
                         num_worker_threads: 4
                         dispatcher_queue_size: 100
                         worker_queue_size: 100
+                    distance:
+                        custom:
+                            - Minkowski:
+                                name: "l3"
+                                p: 3.0
 
                 compaction_service:
                     service_name: "compaction-service"
@@ -229,11 +784,35 @@ mod tests {
                         max_concurrent_jobs: 100
                         compaction_interval_sec: 60
                         min_compaction_size: 10
+                    hnsw_provider:
+                        capacity_growth_factor: 1.5
                 "#,
             );
-            let config = RootConfig::load();
+            let config = RootConfig::load().unwrap();
             assert_eq!(config.query_service.my_member_id, "query-service-0");
+            assert_eq!(
+                config.query_service.hnsw_provider.capacity_growth_factor,
+                2.0
+            );
+            assert_eq!(
+                config.query_service.hnsw_provider.brute_force_threshold,
+                1000
+            );
+            assert_eq!(
+                config
+                    .compaction_service
+                    .hnsw_provider
+                    .capacity_growth_factor,
+                1.5
+            );
             assert_eq!(config.query_service.my_port, 50051);
+            match &config.query_service.distance.custom[..] {
+                [crate::distance::config::CustomDistanceConfig::Minkowski(minkowski)] => {
+                    assert_eq!(minkowski.name, "l3");
+                    assert_eq!(minkowski.p, 3.0);
+                }
+                _ => panic!("Invalid distance config"),
+            }
 
             assert_eq!(
                 config.compaction_service.my_member_id,
@@ -326,17 +905,29 @@ mod tests {
                         max_concurrent_jobs: 100
                         compaction_interval_sec: 60
                         min_compaction_size: 10
+                        scheduler_policy:
+                            Weighted:
+                                backlog_weight: 0.5
                 "#,
             );
-            let config = RootConfig::load_from_path("random_path.yaml");
+            let config = RootConfig::load_from_path("random_path.yaml").unwrap();
             assert_eq!(config.query_service.my_member_id, "query-service-0");
             assert_eq!(config.query_service.my_port, 50051);
+            assert!(config.query_service.distance.custom.is_empty());
 
             assert_eq!(
                 config.compaction_service.my_member_id,
                 "compaction-service-0"
             );
             assert_eq!(config.compaction_service.my_port, 50051);
+            assert!(matches!(
+                config.compaction_service.compactor.scheduler_policy,
+                crate::compactor::config::SchedulerPolicyConfig::Weighted(
+                    crate::compactor::config::WeightedSchedulerPolicyConfig {
+                        backlog_weight
+                    }
+                ) if backlog_weight == 0.5
+            ));
             Ok(())
         });
     }
@@ -354,7 +945,7 @@ mod tests {
                             hasher: Murmur3
                 "#,
             );
-            let _ = RootConfig::load();
+            let _ = RootConfig::load().unwrap();
             Ok(())
         });
     }
@@ -441,10 +1032,23 @@ mod tests {
                         max_concurrent_jobs: 100
                         compaction_interval_sec: 60
                         min_compaction_size: 10
+                    hnsw_provider:
+                        capacity_growth_factor: 1.5
                 "#,
             );
-            let config = RootConfig::load();
+            let config = RootConfig::load().unwrap();
             assert_eq!(config.query_service.my_member_id, "query-service-0");
+            assert_eq!(
+                config.query_service.hnsw_provider.capacity_growth_factor,
+                2.0
+            );
+            assert_eq!(
+                config
+                    .compaction_service
+                    .hnsw_provider
+                    .capacity_growth_factor,
+                1.5
+            );
             assert_eq!(
                 config.compaction_service.my_member_id,
                 "compaction-service-0"
@@ -542,10 +1146,23 @@ mod tests {
                         max_concurrent_jobs: 100
                         compaction_interval_sec: 60
                         min_compaction_size: 10
+                    hnsw_provider:
+                        capacity_growth_factor: 1.5
                 "#,
             );
-            let config = RootConfig::load();
+            let config = RootConfig::load().unwrap();
             assert_eq!(config.query_service.my_member_id, "query-service-0");
+            assert_eq!(
+                config.query_service.hnsw_provider.capacity_growth_factor,
+                2.0
+            );
+            assert_eq!(
+                config
+                    .compaction_service
+                    .hnsw_provider
+                    .capacity_growth_factor,
+                1.5
+            );
             assert_eq!(config.query_service.my_port, 50051);
             assert_eq!(
                 config.compaction_service.my_member_id,
@@ -568,9 +1185,430 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_worker_env_overrides_every_service() {
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("chroma_config.yaml", TEST_CONFIG);
+            let _ = jail.set_env("CHROMA_WORKER__SYSDB__GRPC__HOST", "sysdb.prod");
+            let _ = jail.set_env("CHROMA_WORKER__STORAGE__S3__BUCKET", "prod-bucket");
+            let _ = jail.set_env(
+                "CHROMA_WORKER__MEMBERLIST_PROVIDER__CUSTOMRESOURCE__KUBE_NAMESPACE",
+                "prod",
+            );
+            let _ = jail.set_env("CHROMA_WORKER__DISPATCHER__NUM_WORKER_THREADS", 16);
+            // The variables of a service take precedence over the ones of every service.
+            let _ = jail.set_env(
+                "CHROMA_COMPACTION_SERVICE__SYSDB__GRPC__HOST",
+                "sysdb.compactor",
+            );
+            let config = RootConfig::load().unwrap();
+
+            assert_eq!(sysdb_host(&config.query_service.sysdb), "sysdb.prod");
+            assert_eq!(
+                sysdb_host(&config.compaction_service.sysdb),
+                "sysdb.compactor"
+            );
+            assert_eq!(bucket(&config.query_service.storage), "prod-bucket");
+            assert_eq!(bucket(&config.compaction_service.storage), "prod-bucket");
+            for memberlist_provider in [
+                &config.query_service.memberlist_provider,
+                &config.compaction_service.memberlist_provider,
+            ] {
+                let crate::memberlist::config::MemberlistProviderConfig::CustomResource(memberlist) =
+                    memberlist_provider;
+                assert_eq!(memberlist.kube_namespace, "prod");
+                // The rest of the section still comes from the YAML file.
+                assert_eq!(memberlist.queue_size, 100);
+            }
+            assert_eq!(config.query_service.dispatcher.num_worker_threads, 16);
+            assert_eq!(config.compaction_service.dispatcher.num_worker_threads, 16);
+            assert_eq!(config.query_service.dispatcher.worker_queue_size, 100);
+            assert_eq!(config.query_service.my_member_id, "query-service-0");
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_env_values_are_coerced() {
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("chroma_config.yaml", TEST_CONFIG);
+            let _ = jail.set_env("CHROMA_WORKER__STORAGE__S3__BUCKET", 12345);
+            let _ = jail.set_env("CHROMA_WORKER__HNSW_PROVIDER__CAPACITY_GROWTH_FACTOR", 1.25);
+            let _ = jail.set_env(
+                "CHROMA_QUERY_SERVICE__DISTANCE__CUSTOM",
+                "[{Minkowski={name=l4, p=4.0}}, {minkowski={name=l5, p=5}}]",
+            );
+            let config = RootConfig::load().unwrap();
+
+            assert_eq!(bucket(&config.compaction_service.storage), "12345");
+            assert_eq!(
+                config.query_service.hnsw_provider.capacity_growth_factor,
+                1.25
+            );
+            let custom: Vec<_> = config
+                .query_service
+                .distance
+                .custom
+                .iter()
+                .map(|custom| match custom {
+                    crate::distance::config::CustomDistanceConfig::Minkowski(minkowski) => {
+                        (minkowski.name.as_str(), minkowski.p)
+                    }
+                })
+                .collect();
+            assert_eq!(custom, vec![("l4", 4.0), ("l5", 5.0)]);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_invalid_env_value_names_the_variable() {
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("chroma_config.yaml", TEST_CONFIG);
+            let _ = jail.set_env("CHROMA_WORKER__DISPATCHER__NUM_WORKER_THREADS", "many");
+            let e = RootConfig::figment(DEFAULT_CONFIG_PATH)
+                .extract::<RootConfig>()
+                .err()
+                .unwrap();
+            assert!(
+                e.to_string()
+                    .contains("\"CHROMA_WORKER__DISPATCHER__NUM_WORKER_THREADS\""),
+                "{}",
+                e
+            );
+
+            let _ = jail.set_env("CHROMA_WORKER__DISPATCHER__NUM_WORKER_THREADS", 8);
+            let _ = jail.set_env("CHROMA_QUERY_SERVICE__MY_PORT", 70000);
+            let e = RootConfig::figment(DEFAULT_CONFIG_PATH)
+                .extract::<RootConfig>()
+                .err()
+                .unwrap();
+            assert!(
+                e.to_string().contains("\"CHROMA_QUERY_SERVICE__MY_PORT\""),
+                "{}",
+                e
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_redacted_config() {
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("chroma_config.yaml", TEST_CONFIG);
+            let _ = jail.set_env("CHROMA_WORKER__SYSDB__GRPC__HOST", "sysdb.prod");
+            let _ = jail.set_env("CHROMA_WORKER__SYSDB__GRPC__AUTH_TOKEN", "hunter2");
+            let _ = jail.set_env(
+                "CHROMA_QUERY_SERVICE__STORAGE__S3__SECRET_ACCESS_KEY",
+                "s3cr3t",
+            );
+            let redacted = redacted(&RootConfig::figment(DEFAULT_CONFIG_PATH));
+            assert!(!redacted.contains("hunter2"), "{}", redacted);
+            assert!(!redacted.contains("s3cr3t"), "{}", redacted);
+            assert!(
+                redacted.contains("\"auth_token\":\"<redacted>\""),
+                "{}",
+                redacted
+            );
+            assert!(redacted.contains("sysdb.prod"), "{}", redacted);
+            assert!(
+                redacted.contains("query-service-memberlist"),
+                "{}",
+                redacted
+            );
+            Ok(())
+        });
+    }
+
+    const TEST_S3_STORAGE: &str = r#"
+                S3:
+                    bucket: "chroma"
+                    credentials: Minio
+                    connect_timeout_ms: 5000
+                    request_timeout_ms: 1000"#;
+
+    fn load_error(yaml: &str) -> ConfigError {
+        let mut error = None;
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("chroma_config.yaml", yaml);
+            error = RootConfig::load().err();
+            Ok(())
+        });
+        error.expect("Expected the config to be rejected")
+    }
+
+    fn violation_paths(error: ConfigError) -> Vec<String> {
+        match error {
+            ConfigError::Invalid(violations) => violations
+                .into_iter()
+                .map(|violation| violation.path)
+                .collect(),
+            ConfigError::Load(e) => panic!("Expected violations, got {}", e),
+        }
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() {
+        let error = load_error(&TEST_CONFIG.replacen("bucket:", "bukcet:", 1));
+        assert_eq!(error.code(), ErrorCodes::InvalidArgument);
+        match &error {
+            ConfigError::Load(e) => {
+                let message = e.to_string();
+                assert!(
+                    message.contains("unknown field: found `bukcet`"),
+                    "{}",
+                    message
+                );
+                assert!(
+                    message.contains("query_service.storage.S3.bukcet"),
+                    "{}",
+                    message
+                );
+            }
+            ConfigError::Invalid(_) => panic!("Expected a load error, got {}", error),
+        }
+
+        let error = load_error(&TEST_CONFIG.replacen(
+            "num_worker_threads: 4",
+            "num_worker_threads: 4\n                num_worker_thread: 8",
+            1,
+        ));
+        assert!(error
+            .to_string()
+            .contains("unknown field: found `num_worker_thread`"));
+    }
+
+    #[test]
+    fn test_all_violations_are_reported() {
+        let yaml = TEST_CONFIG
+            .replacen(
+                "my_port: 50051",
+                "my_port: 0\n            hnsw_provider:\n                capacity_growth_factor: 1.0\n            distance:\n                custom:\n                    - Minkowski:\n                        name: \"l0\"\n                        p: 0.0",
+                1,
+            )
+            .replacen("bucket: \"chroma\"", "bucket: \"\"", 1)
+            .replace("queue_size: 100\n", "queue_size: 0\n")
+            .replacen(
+                "min_compaction_size: 10",
+                "min_compaction_size: 10\n                scheduler_policy:\n                    Weighted:\n                        backlog_weight: -1.0",
+                1,
+            );
+        let error = load_error(&yaml);
+        assert!(error.to_string().starts_with(
+            "Invalid config:\n- query_service.my_port: expected a port between 1 and 65535\n"
+        ));
+        assert_eq!(
+            violation_paths(error),
+            vec![
+                "query_service.my_port",
+                "query_service.memberlist_provider.CustomResource.queue_size",
+                "query_service.storage.S3.bucket",
+                "query_service.dispatcher.dispatcher_queue_size",
+                "query_service.dispatcher.worker_queue_size",
+                "query_service.distance.custom.0.Minkowski.p",
+                "query_service.hnsw_provider.capacity_growth_factor",
+                "compaction_service.memberlist_provider.CustomResource.queue_size",
+                "compaction_service.dispatcher.dispatcher_queue_size",
+                "compaction_service.dispatcher.worker_queue_size",
+                "compaction_service.compactor.scheduler_policy.Weighted.backlog_weight",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_local_storage_root_must_be_writable() {
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("not_a_directory", "");
+            let local = |root: &str| {
+                format!(
+                    "\n                Local:\n                    root: \"{}\"",
+                    root
+                )
+            };
+            for (root, valid) in [
+                ("storage", true),
+                ("storage/that/does/not/exist/yet", true),
+                ("not_a_directory", false),
+                ("not_a_directory/storage", false),
+            ] {
+                let yaml = TEST_CONFIG.replace(TEST_S3_STORAGE, &local(root));
+                let _ = jail.create_file("chroma_config.yaml", &yaml);
+                match RootConfig::load() {
+                    Ok(_) => assert!(valid, "{} should be rejected", root),
+                    Err(e) => {
+                        assert!(!valid, "{} should be accepted: {}", root, e);
+                        assert_eq!(
+                            violation_paths(e),
+                            vec![
+                                "query_service.storage.Local.root",
+                                "compaction_service.storage.Local.root"
+                            ]
+                        );
+                    }
+                }
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_blockfile_mmap_is_opt_in() {
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("chroma_config.yaml", TEST_CONFIG);
+            let _ = jail.create_file("not_a_directory", "");
+            let config = RootConfig::load().unwrap();
+            assert_eq!(config.query_service.blockfile_provider.mmap_path, None);
+
+            let _ = jail.set_env(
+                "CHROMA_QUERY_SERVICE__BLOCKFILE_PROVIDER__MMAP_PATH",
+                "blocks",
+            );
+            let config = RootConfig::load().unwrap();
+            assert_eq!(
+                config.query_service.blockfile_provider.mmap_path,
+                Some("blocks".to_string())
+            );
+
+            let _ = jail.set_env(
+                "CHROMA_QUERY_SERVICE__BLOCKFILE_PROVIDER__MMAP_PATH",
+                "not_a_directory/blocks",
+            );
+            let e = RootConfig::load().err().unwrap();
+            assert_eq!(
+                violation_paths(e),
+                vec!["query_service.blockfile_provider.mmap_path"]
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_metrics_are_off_by_default() {
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("chroma_config.yaml", TEST_CONFIG);
+            let config = RootConfig::load().unwrap();
+            assert_eq!(config.query_service.metrics.otlp_endpoint, None);
+            assert_eq!(config.compaction_service.metrics.export_interval_ms, 30000);
+
+            let _ = jail.set_env(
+                "CHROMA_WORKER__METRICS__OTLP_ENDPOINT",
+                "http://otel-collector:4317",
+            );
+            let _ = jail.set_env("CHROMA_COMPACTION_SERVICE__METRICS__EXPORT_INTERVAL_MS", 0);
+            let e = RootConfig::load().err().unwrap();
+            assert_eq!(
+                violation_paths(e),
+                vec!["compaction_service.metrics.export_interval_ms"]
+            );
+
+            let _ = jail.set_env(
+                "CHROMA_COMPACTION_SERVICE__METRICS__EXPORT_INTERVAL_MS",
+                5000,
+            );
+            let config = RootConfig::load().unwrap();
+            for metrics in [
+                &config.query_service.metrics,
+                &config.compaction_service.metrics,
+            ] {
+                assert_eq!(
+                    metrics.otlp_endpoint.as_deref(),
+                    Some("http://otel-collector:4317")
+                );
+            }
+            assert_eq!(config.compaction_service.metrics.export_interval_ms, 5000);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_compaction_trigger_is_off_by_default() {
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("chroma_config.yaml", TEST_CONFIG);
+            let config = RootConfig::load().unwrap();
+            let trigger = &config.compaction_service.compactor.compaction_trigger;
+            assert!(!trigger.is_enabled());
+            assert_eq!(trigger.check_interval_ms, 1000);
+
+            let _ = jail.set_env(
+                "CHROMA_COMPACTION_SERVICE__COMPACTOR__COMPACTION_TRIGGER__RECORD_THRESHOLD",
+                0,
+            );
+            let e = RootConfig::load().err().unwrap();
+            assert_eq!(
+                violation_paths(e),
+                vec!["compaction_service.compactor.compaction_trigger.record_threshold"]
+            );
+
+            let _ = jail.set_env(
+                "CHROMA_COMPACTION_SERVICE__COMPACTOR__COMPACTION_TRIGGER__RECORD_THRESHOLD",
+                5000,
+            );
+            let config = RootConfig::load().unwrap();
+            let trigger = &config.compaction_service.compactor.compaction_trigger;
+            assert!(trigger.is_enabled());
+            assert_eq!(trigger.record_threshold, Some(5000));
+            assert_eq!(trigger.byte_threshold, None);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_orchestrator_queue_size() {
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("chroma_config.yaml", TEST_CONFIG);
+            let config = RootConfig::load().unwrap();
+            assert_eq!(
+                config.compaction_service.compactor.orchestrator_queue_size,
+                1000
+            );
+
+            let _ = jail.set_env(
+                "CHROMA_COMPACTION_SERVICE__COMPACTOR__ORCHESTRATOR_QUEUE_SIZE",
+                0,
+            );
+            let e = RootConfig::load().err().unwrap();
+            assert_eq!(
+                violation_paths(e),
+                vec!["compaction_service.compactor.orchestrator_queue_size"]
+            );
+
+            let _ = jail.set_env(
+                "CHROMA_COMPACTION_SERVICE__COMPACTOR__ORCHESTRATOR_QUEUE_SIZE",
+                64,
+            );
+            let config = RootConfig::load().unwrap();
+            assert_eq!(
+                config.compaction_service.compactor.orchestrator_queue_size,
+                64
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_worker_env_only_sets_the_fields_of_a_service() {
+        Jail::expect_with(|jail| {
+            let _ = jail.create_file("chroma_config.yaml", TEST_CONFIG);
+            // Only the compaction service has a compactor, and only the query service has
+            // a request timeout.
+            let _ = jail.set_env("CHROMA_WORKER__COMPACTOR__MAX_CONCURRENT_JOBS", 3);
+            let _ = jail.set_env("CHROMA_WORKER__DEFAULT_REQUEST_TIMEOUT_MS", 1000);
+            let config = RootConfig::load().unwrap();
+            assert_eq!(config.compaction_service.compactor.max_concurrent_jobs, 3);
+            assert_eq!(config.query_service.default_request_timeout_ms, 1000);
+            // Index checks and rebuilds keep their own, longer, timeout.
+            assert_eq!(config.query_service.admin_request_timeout_ms, 600000);
+
+            let _ = jail.set_env("CHROMA_WORKER__SYSDB__GRPC__HOTS", "sysdb.prod");
+            let e = RootConfig::load().err().unwrap().to_string();
+            assert!(e.contains("\"CHROMA_WORKER__SYSDB__GRPC__HOTS\""), "{}", e);
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_default_config_path() {
         // Sanity check that root config loads from default path correctly
-        let _ = RootConfig::load();
+        let _ = RootConfig::load().unwrap();
     }
 }
@@ -0,0 +1,97 @@
+use super::knn::top_k_by;
+
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// Binarizes `vector` into packed bits, one bit per dimension.
+/// # Description
+/// Bit `i % 64` of word `i / 64` is set if element `i` is greater than zero. Unused bits in
+/// the last word are left unset, so vectors of the same dimension can be compared word by
+/// word.
+pub fn pack_bits(vector: &[f32]) -> Vec<u64> {
+    vector
+        .chunks(BITS_PER_WORD)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0_u64, |word, (bit, x)| word | ((*x > 0.0) as u64) << bit)
+        })
+        .collect()
+}
+
+/// Computes the Hamming distance between two packed bit vectors, the number of bits that
+/// differ.
+/// # Notes
+/// Like `DistanceFunction::distance`, only the overlapping words are compared if the
+/// lengths differ.
+pub fn hamming_distance(a: &[u64], b: &[u64]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
+
+/// Finds the `k` packed bit vectors closest to `query` by Hamming distance.
+/// # Returns
+/// Up to `k` `(offset_id, distance)` pairs sorted the same way as `DistanceFunction::top_k`.
+pub fn top_k_hamming<'a>(
+    query: &[u64],
+    candidates: impl Iterator<Item = (u32, &'a [u64])>,
+    k: usize,
+) -> Vec<(u32, f32)> {
+    top_k_by(
+        candidates
+            .map(|(offset_id, candidate)| (offset_id, hamming_distance(query, candidate) as f32)),
+        k,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::DistanceFunction;
+
+    #[test]
+    fn test_pack_bits() {
+        assert_eq!(pack_bits(&[]), Vec::<u64>::new());
+        assert_eq!(pack_bits(&[1.0, 0.0, -1.0, 0.5]), vec![0b1001]);
+        let mut vector = vec![-1.0; 130];
+        vector[0] = 1.0;
+        vector[63] = 1.0;
+        vector[64] = 1.0;
+        vector[129] = 1.0;
+        assert_eq!(pack_bits(&vector), vec![1 | 1 << 63, 1, 0b10]);
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        assert_eq!(hamming_distance(&[0b1010], &[0b1010]), 0);
+        assert_eq!(hamming_distance(&[0b1010], &[0b0101]), 4);
+        assert_eq!(hamming_distance(&[u64::MAX, 0], &[0, u64::MAX]), 128);
+        assert_eq!(hamming_distance(&[0, 1 << 63], &[0, 0]), 1);
+        assert_eq!(hamming_distance(&[], &[]), 0);
+    }
+
+    #[test]
+    fn test_hamming_distance_matches_f32() {
+        for n in [1, 63, 64, 65, 1536] {
+            let a: Vec<f32> = (0..n).map(|_| rand::random::<f32>() - 0.5).collect();
+            let b: Vec<f32> = (0..n).map(|_| rand::random::<f32>() - 0.5).collect();
+            assert_eq!(
+                hamming_distance(&pack_bits(&a), &pack_bits(&b)) as f32,
+                DistanceFunction::Hamming.distance(&a, &b)
+            );
+        }
+    }
+
+    #[test]
+    fn test_top_k_hamming() {
+        let candidates = [vec![0b1111_u64], vec![0b0001], vec![0b0111], vec![0b0000]];
+        let result = top_k_hamming(
+            &[0b0000],
+            candidates
+                .iter()
+                .enumerate()
+                .map(|(i, candidate)| (i as u32, candidate.as_slice())),
+            3,
+        );
+        assert_eq!(result, vec![(3, 0.0), (1, 1.0), (2, 3.0)]);
+    }
+}
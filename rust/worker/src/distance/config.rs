@@ -0,0 +1,61 @@
+use crate::config::{ConfigValidator, ValidateConfig};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Default)]
+/// The configuration for the distance functions queries can use, in addition to the
+/// built-in ones.
+/// # Fields
+/// - custom: Additional distance functions to register, referenced by name from the
+/// `hnsw:space` of a collection.
+/// # Notes
+/// See config.rs in the root of the worker crate for an example of how to use
+/// config files to configure the worker.
+#[serde(deny_unknown_fields)]
+pub(crate) struct DistanceConfig {
+    #[serde(default)]
+    pub(crate) custom: Vec<CustomDistanceConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+/// A configurable distance function.
+/// # Options
+/// - Minkowski: The Minkowski distance of order `p`.
+pub(crate) enum CustomDistanceConfig {
+    // case-insensitive
+    #[serde(alias = "minkowski")]
+    Minkowski(MinkowskiDistanceConfig),
+}
+
+#[derive(Deserialize, Debug)]
+/// The configuration for a Minkowski distance.
+/// # Fields
+/// - name: The name collections refer to the distance by.
+/// - p: The order of the distance. Must be positive and finite.
+#[serde(deny_unknown_fields)]
+pub(crate) struct MinkowskiDistanceConfig {
+    pub(crate) name: String,
+    pub(crate) p: f32,
+}
+
+impl ValidateConfig for DistanceConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        for (index, custom) in self.custom.iter().enumerate() {
+            match custom {
+                CustomDistanceConfig::Minkowski(minkowski) => {
+                    validator.section(&format!("custom.{}.Minkowski", index), minkowski)
+                }
+            }
+        }
+    }
+}
+
+impl ValidateConfig for MinkowskiDistanceConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.non_empty("name", &self.name);
+        validator.check(
+            "p",
+            self.p > 0.0 && self.p.is_finite(),
+            "a finite number greater than 0",
+        );
+    }
+}
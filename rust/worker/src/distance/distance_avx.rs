@@ -200,10 +200,19 @@ copyright Qdrant, licensed under the Apache 2.0 license.
    limitations under the License.
 */
 
+// These kernels are compiled for every x86_64 target and enabled per function, so callers
+// must check that the CPU supports avx2 and fma (see DistanceKernels) before calling them.
+
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
-#[cfg(all(target_feature = "avx", target_feature = "fma"))]
+#[cfg(target_arch = "x86_64")]
+use crate::distance::distance_scalar::cosine_distance_from_parts;
+
+/// # Safety
+/// The CPU must support avx.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
 pub unsafe fn hsum256_ps_avx(x: __m256) -> f32 {
     let x128: __m128 = _mm_add_ps(_mm256_extractf128_ps(x, 1), _mm256_castps256_ps128(x));
     let x64: __m128 = _mm_add_ps(x128, _mm_movehl_ps(x128, x128));
@@ -211,52 +220,95 @@ pub unsafe fn hsum256_ps_avx(x: __m256) -> f32 {
     _mm_cvtss_f32(x32)
 }
 
-#[cfg(all(target_feature = "avx", target_feature = "fma"))]
+/// # Safety
+/// The CPU must support avx2 and fma, and `a` and `b` must have the same length.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
 pub unsafe fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
-    let m = n - (n % 32);
+    let m = n - (n % 16);
     let mut ptr1: *const f32 = a.as_ptr();
     let mut ptr2: *const f32 = b.as_ptr();
-    let mut sum256_1: __m256 = _mm256_setzero_ps();
-    let mut sum256_2: __m256 = _mm256_setzero_ps();
-    let mut sum256_3: __m256 = _mm256_setzero_ps();
-    let mut sum256_4: __m256 = _mm256_setzero_ps();
+    let mut dot256_1: __m256 = _mm256_setzero_ps();
+    let mut dot256_2: __m256 = _mm256_setzero_ps();
+    let mut norm_a256_1: __m256 = _mm256_setzero_ps();
+    let mut norm_a256_2: __m256 = _mm256_setzero_ps();
+    let mut norm_b256_1: __m256 = _mm256_setzero_ps();
+    let mut norm_b256_2: __m256 = _mm256_setzero_ps();
     let mut i: usize = 0;
     while i < m {
-        sum256_1 = _mm256_fmadd_ps(_mm256_loadu_ps(ptr1), _mm256_loadu_ps(ptr2), sum256_1);
-        sum256_2 = _mm256_fmadd_ps(
-            _mm256_loadu_ps(ptr1.add(8)),
-            _mm256_loadu_ps(ptr2.add(8)),
-            sum256_2,
-        );
-        sum256_3 = _mm256_fmadd_ps(
-            _mm256_loadu_ps(ptr1.add(16)),
-            _mm256_loadu_ps(ptr2.add(16)),
-            sum256_3,
-        );
-        sum256_4 = _mm256_fmadd_ps(
-            _mm256_loadu_ps(ptr1.add(24)),
-            _mm256_loadu_ps(ptr2.add(24)),
-            sum256_4,
-        );
+        let a256_1 = _mm256_loadu_ps(ptr1);
+        let b256_1 = _mm256_loadu_ps(ptr2);
+        dot256_1 = _mm256_fmadd_ps(a256_1, b256_1, dot256_1);
+        norm_a256_1 = _mm256_fmadd_ps(a256_1, a256_1, norm_a256_1);
+        norm_b256_1 = _mm256_fmadd_ps(b256_1, b256_1, norm_b256_1);
+
+        let a256_2 = _mm256_loadu_ps(ptr1.add(8));
+        let b256_2 = _mm256_loadu_ps(ptr2.add(8));
+        dot256_2 = _mm256_fmadd_ps(a256_2, b256_2, dot256_2);
+        norm_a256_2 = _mm256_fmadd_ps(a256_2, a256_2, norm_a256_2);
+        norm_b256_2 = _mm256_fmadd_ps(b256_2, b256_2, norm_b256_2);
+
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
 
-        ptr1 = ptr1.add(32);
-        ptr2 = ptr2.add(32);
-        i += 32;
+    let mut dot = hsum256_ps_avx(dot256_1) + hsum256_ps_avx(dot256_2);
+    let mut norm_a = hsum256_ps_avx(norm_a256_1) + hsum256_ps_avx(norm_a256_2);
+    let mut norm_b = hsum256_ps_avx(norm_b256_1) + hsum256_ps_avx(norm_b256_2);
+    for i in 0..n - m {
+        let x = *ptr1.add(i);
+        let y = *ptr2.add(i);
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
     }
+    cosine_distance_from_parts(dot, norm_a, norm_b)
+}
 
-    let mut result = hsum256_ps_avx(sum256_1)
-        + hsum256_ps_avx(sum256_2)
-        + hsum256_ps_avx(sum256_3)
-        + hsum256_ps_avx(sum256_4);
+/// # Safety
+/// The CPU must support avx2 and fma, and `a` and `b` must have the same length.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+pub unsafe fn cosine_distance_with_norm(a: &[f32], b: &[f32], norm_a_squared: f32) -> f32 {
+    let n = a.len();
+    let m = n - (n % 16);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut dot256_1: __m256 = _mm256_setzero_ps();
+    let mut dot256_2: __m256 = _mm256_setzero_ps();
+    let mut norm_b256_1: __m256 = _mm256_setzero_ps();
+    let mut norm_b256_2: __m256 = _mm256_setzero_ps();
+    let mut i: usize = 0;
+    while i < m {
+        let b256_1 = _mm256_loadu_ps(ptr2);
+        dot256_1 = _mm256_fmadd_ps(_mm256_loadu_ps(ptr1), b256_1, dot256_1);
+        norm_b256_1 = _mm256_fmadd_ps(b256_1, b256_1, norm_b256_1);
+
+        let b256_2 = _mm256_loadu_ps(ptr2.add(8));
+        dot256_2 = _mm256_fmadd_ps(_mm256_loadu_ps(ptr1.add(8)), b256_2, dot256_2);
+        norm_b256_2 = _mm256_fmadd_ps(b256_2, b256_2, norm_b256_2);
 
+        ptr1 = ptr1.add(16);
+        ptr2 = ptr2.add(16);
+        i += 16;
+    }
+
+    let mut dot = hsum256_ps_avx(dot256_1) + hsum256_ps_avx(dot256_2);
+    let mut norm_b = hsum256_ps_avx(norm_b256_1) + hsum256_ps_avx(norm_b256_2);
     for i in 0..n - m {
-        result += (*ptr1.add(i)) * (*ptr2.add(i));
+        let y = *ptr2.add(i);
+        dot += *ptr1.add(i) * y;
+        norm_b += y * y;
     }
-    1.0_f32 - result
+    cosine_distance_from_parts(dot, norm_a_squared, norm_b)
 }
 
-#[cfg(all(target_feature = "avx", target_feature = "fma"))]
+/// # Safety
+/// The CPU must support avx2 and fma, and `a` and `b` must have the same length.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
 pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 32);
@@ -301,7 +353,10 @@ pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     1.0_f32 - result
 }
 
-#[cfg(all(target_feature = "avx", target_feature = "fma"))]
+/// # Safety
+/// The CPU must support avx2 and fma, and `a` and `b` must have the same length.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
 pub unsafe fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 32);
@@ -200,38 +200,105 @@ copyright Qdrant, licensed under the Apache 2.0 license.
    limitations under the License.
 */
 
-#[cfg(target_feature = "neon")]
+// These kernels are compiled for every aarch64 target and enabled per function, so callers
+// must check that the CPU supports neon (see DistanceKernels) before calling them. Elements
+// left over after the last full block of lanes are handled with scalar code.
+
+#[cfg(target_arch = "aarch64")]
 use std::arch::aarch64::*;
 
-#[cfg(target_feature = "neon")]
+#[cfg(target_arch = "aarch64")]
+use crate::distance::distance_scalar::cosine_distance_from_parts;
+
+/// # Safety
+/// The CPU must support neon, and `a` and `b` must have the same length.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
 pub unsafe fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
-    let m = n - (n % 16);
+    let m = n - (n % 8);
     let mut ptr1: *const f32 = a.as_ptr();
     let mut ptr2: *const f32 = b.as_ptr();
-    let mut sum1 = vdupq_n_f32(0.);
-    let mut sum2 = vdupq_n_f32(0.);
-    let mut sum3 = vdupq_n_f32(0.);
-    let mut sum4 = vdupq_n_f32(0.);
+    let mut dot1 = vdupq_n_f32(0.);
+    let mut dot2 = vdupq_n_f32(0.);
+    let mut norm_a1 = vdupq_n_f32(0.);
+    let mut norm_a2 = vdupq_n_f32(0.);
+    let mut norm_b1 = vdupq_n_f32(0.);
+    let mut norm_b2 = vdupq_n_f32(0.);
 
     let mut i: usize = 0;
     while i < m {
-        sum1 = vfmaq_f32(sum1, vld1q_f32(ptr1), vld1q_f32(ptr2));
-        sum2 = vfmaq_f32(sum2, vld1q_f32(ptr1.add(4)), vld1q_f32(ptr2.add(4)));
-        sum3 = vfmaq_f32(sum3, vld1q_f32(ptr1.add(8)), vld1q_f32(ptr2.add(8)));
-        sum4 = vfmaq_f32(sum4, vld1q_f32(ptr1.add(12)), vld1q_f32(ptr2.add(12)));
-        ptr1 = ptr1.add(16);
-        ptr2 = ptr2.add(16);
-        i += 16;
+        let a1 = vld1q_f32(ptr1);
+        let b1 = vld1q_f32(ptr2);
+        dot1 = vfmaq_f32(dot1, a1, b1);
+        norm_a1 = vfmaq_f32(norm_a1, a1, a1);
+        norm_b1 = vfmaq_f32(norm_b1, b1, b1);
+
+        let a2 = vld1q_f32(ptr1.add(4));
+        let b2 = vld1q_f32(ptr2.add(4));
+        dot2 = vfmaq_f32(dot2, a2, b2);
+        norm_a2 = vfmaq_f32(norm_a2, a2, a2);
+        norm_b2 = vfmaq_f32(norm_b2, b2, b2);
+
+        ptr1 = ptr1.add(8);
+        ptr2 = ptr2.add(8);
+        i += 8;
     }
-    let mut result = vaddvq_f32(sum1) + vaddvq_f32(sum2) + vaddvq_f32(sum3) + vaddvq_f32(sum4);
+    let mut dot = vaddvq_f32(dot1) + vaddvq_f32(dot2);
+    let mut norm_a = vaddvq_f32(norm_a1) + vaddvq_f32(norm_a2);
+    let mut norm_b = vaddvq_f32(norm_b1) + vaddvq_f32(norm_b2);
     for i in 0..n - m {
-        result += (*ptr1.add(i)) * (*ptr2.add(i));
+        let x = *ptr1.add(i);
+        let y = *ptr2.add(i);
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
     }
-    1.0_f32 - result
+    cosine_distance_from_parts(dot, norm_a, norm_b)
+}
+
+/// # Safety
+/// The CPU must support neon, and `a` and `b` must have the same length.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+pub unsafe fn cosine_distance_with_norm(a: &[f32], b: &[f32], norm_a_squared: f32) -> f32 {
+    let n = a.len();
+    let m = n - (n % 8);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut dot1 = vdupq_n_f32(0.);
+    let mut dot2 = vdupq_n_f32(0.);
+    let mut norm_b1 = vdupq_n_f32(0.);
+    let mut norm_b2 = vdupq_n_f32(0.);
+
+    let mut i: usize = 0;
+    while i < m {
+        let b1 = vld1q_f32(ptr2);
+        dot1 = vfmaq_f32(dot1, vld1q_f32(ptr1), b1);
+        norm_b1 = vfmaq_f32(norm_b1, b1, b1);
+
+        let b2 = vld1q_f32(ptr2.add(4));
+        dot2 = vfmaq_f32(dot2, vld1q_f32(ptr1.add(4)), b2);
+        norm_b2 = vfmaq_f32(norm_b2, b2, b2);
+
+        ptr1 = ptr1.add(8);
+        ptr2 = ptr2.add(8);
+        i += 8;
+    }
+    let mut dot = vaddvq_f32(dot1) + vaddvq_f32(dot2);
+    let mut norm_b = vaddvq_f32(norm_b1) + vaddvq_f32(norm_b2);
+    for i in 0..n - m {
+        let y = *ptr2.add(i);
+        dot += *ptr1.add(i) * y;
+        norm_b += y * y;
+    }
+    cosine_distance_from_parts(dot, norm_a_squared, norm_b)
 }
 
-#[cfg(target_feature = "neon")]
+/// # Safety
+/// The CPU must support neon, and `a` and `b` must have the same length.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
 pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 16);
@@ -259,7 +326,10 @@ pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     1.0_f32 - result
 }
 
-#[cfg(target_feature = "neon")]
+/// # Safety
+/// The CPU must support neon, and `a` and `b` must have the same length.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
 pub unsafe fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 16);
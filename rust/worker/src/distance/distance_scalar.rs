@@ -0,0 +1,103 @@
+// Portable distance kernels. These are used when the CPU does not support any of the
+// SIMD instruction sets we have kernels for, and serve as the reference implementation
+// the SIMD kernels are tested against.
+// All kernels assume that `a` and `b` have the same length.
+
+pub fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..a.len() {
+        sum += (a[i] - b[i]).powi(2);
+    }
+    sum
+}
+
+pub fn manhattan_distance(a: &[f32], b: &[f32]) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..a.len() {
+        sum += (a[i] - b[i]).abs();
+    }
+    sum
+}
+
+/// The number of dimensions whose sign differs, treating each element as the bit `x > 0`.
+/// This is the Hamming distance of the vectors once binarized with `binary::pack_bits`.
+pub fn hamming_distance(a: &[f32], b: &[f32]) -> f32 {
+    let mut count = 0_u32;
+    for i in 0..a.len() {
+        if (a[i] > 0.0) != (b[i] > 0.0) {
+            count += 1;
+        }
+    }
+    count as f32
+}
+
+/// The weighted Jaccard distance, 1 - sum(min(a_i, b_i)) / sum(max(a_i, b_i)), treating
+/// elements that are not greater than zero as absent. This is `sparse::jaccard_distance` of
+/// the vectors made of their positive elements.
+pub fn jaccard_distance(a: &[f32], b: &[f32]) -> f32 {
+    let (mut intersection, mut union) = (0.0_f32, 0.0_f32);
+    for i in 0..a.len() {
+        let (x, y) = (a[i].max(0.0), b[i].max(0.0));
+        intersection += x.min(y);
+        union += x.max(y);
+    }
+    if union == 0.0 {
+        return 0.0;
+    }
+    1.0 - intersection / union
+}
+
+pub fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
+    let mut dot = 0.0_f32;
+    let mut norm_a = 0.0_f32;
+    let mut norm_b = 0.0_f32;
+    for i in 0..a.len() {
+        dot += a[i] * b[i];
+        norm_a += a[i] * a[i];
+        norm_b += b[i] * b[i];
+    }
+    cosine_distance_from_parts(dot, norm_a, norm_b)
+}
+
+/// Cosine distance for when the squared norm of `a` is already known, e.g. when one query
+/// is scored against many candidates.
+pub fn cosine_distance_with_norm(a: &[f32], b: &[f32], norm_a_squared: f32) -> f32 {
+    let mut dot = 0.0_f32;
+    let mut norm_b = 0.0_f32;
+    for i in 0..a.len() {
+        dot += a[i] * b[i];
+        norm_b += b[i] * b[i];
+    }
+    cosine_distance_from_parts(dot, norm_a_squared, norm_b)
+}
+
+pub fn norm_squared(a: &[f32]) -> f32 {
+    let mut sum = 0.0;
+    for x in a {
+        sum += x * x;
+    }
+    sum
+}
+
+pub fn inner_product(a: &[f32], b: &[f32]) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..a.len() {
+        sum += a[i] * b[i];
+    }
+    1.0_f32 - sum
+}
+
+/// Computes 1 - a.b / (|a| * |b|) given the dot product and the squared norms of the two
+/// vectors. Shared by every cosine kernel so that they agree on the zero vector case.
+/// # Notes
+/// The cosine of a zero vector is undefined. We treat it as orthogonal to everything,
+/// which is what the python implementation (and hnswlib with its epsilon-guarded
+/// normalization) produces.
+#[inline]
+pub fn cosine_distance_from_parts(dot: f32, norm_a_squared: f32, norm_b_squared: f32) -> f32 {
+    let norm = norm_a_squared.sqrt() * norm_b_squared.sqrt();
+    if norm == 0.0 {
+        return 1.0;
+    }
+    1.0_f32 - dot / norm
+}
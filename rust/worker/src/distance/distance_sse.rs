@@ -200,64 +200,118 @@ copyright Qdrant, licensed under the Apache 2.0 license.
    limitations under the License.
 */
 
+// These kernels are compiled for every x86 target and enabled per function, so callers
+// must check that the CPU supports sse4.1 (see DistanceKernels) before calling them.
+
 #[cfg(target_arch = "x86")]
 use std::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::*;
 
-#[cfg(target_feature = "sse")]
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+use crate::distance::distance_scalar::cosine_distance_from_parts;
+
+/// # Safety
+/// The CPU must support sse.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+#[target_feature(enable = "sse")]
 pub unsafe fn hsum128_ps_sse(x: __m128) -> f32 {
     let x64: __m128 = _mm_add_ps(x, _mm_movehl_ps(x, x));
     let x32: __m128 = _mm_add_ss(x64, _mm_shuffle_ps(x64, x64, 0x55));
     _mm_cvtss_f32(x32)
 }
 
-#[cfg(target_feature = "sse")]
+/// # Safety
+/// The CPU must support sse4.1, and `a` and `b` must have the same length.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+#[target_feature(enable = "sse4.1")]
 pub unsafe fn cosine_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
-    let m = n - (n % 16);
+    let m = n - (n % 8);
     let mut ptr1: *const f32 = a.as_ptr();
     let mut ptr2: *const f32 = b.as_ptr();
-    let mut sum128_1: __m128 = _mm_setzero_ps();
-    let mut sum128_2: __m128 = _mm_setzero_ps();
-    let mut sum128_3: __m128 = _mm_setzero_ps();
-    let mut sum128_4: __m128 = _mm_setzero_ps();
+    let mut dot128_1: __m128 = _mm_setzero_ps();
+    let mut dot128_2: __m128 = _mm_setzero_ps();
+    let mut norm_a128_1: __m128 = _mm_setzero_ps();
+    let mut norm_a128_2: __m128 = _mm_setzero_ps();
+    let mut norm_b128_1: __m128 = _mm_setzero_ps();
+    let mut norm_b128_2: __m128 = _mm_setzero_ps();
 
     let mut i: usize = 0;
     while i < m {
-        sum128_1 = _mm_add_ps(_mm_mul_ps(_mm_loadu_ps(ptr1), _mm_loadu_ps(ptr2)), sum128_1);
+        let a128_1 = _mm_loadu_ps(ptr1);
+        let b128_1 = _mm_loadu_ps(ptr2);
+        dot128_1 = _mm_add_ps(_mm_mul_ps(a128_1, b128_1), dot128_1);
+        norm_a128_1 = _mm_add_ps(_mm_mul_ps(a128_1, a128_1), norm_a128_1);
+        norm_b128_1 = _mm_add_ps(_mm_mul_ps(b128_1, b128_1), norm_b128_1);
+
+        let a128_2 = _mm_loadu_ps(ptr1.add(4));
+        let b128_2 = _mm_loadu_ps(ptr2.add(4));
+        dot128_2 = _mm_add_ps(_mm_mul_ps(a128_2, b128_2), dot128_2);
+        norm_a128_2 = _mm_add_ps(_mm_mul_ps(a128_2, a128_2), norm_a128_2);
+        norm_b128_2 = _mm_add_ps(_mm_mul_ps(b128_2, b128_2), norm_b128_2);
+
+        ptr1 = ptr1.add(8);
+        ptr2 = ptr2.add(8);
+        i += 8;
+    }
 
-        sum128_2 = _mm_add_ps(
-            _mm_mul_ps(_mm_loadu_ps(ptr1.add(4)), _mm_loadu_ps(ptr2.add(4))),
-            sum128_2,
-        );
+    let mut dot = hsum128_ps_sse(dot128_1) + hsum128_ps_sse(dot128_2);
+    let mut norm_a = hsum128_ps_sse(norm_a128_1) + hsum128_ps_sse(norm_a128_2);
+    let mut norm_b = hsum128_ps_sse(norm_b128_1) + hsum128_ps_sse(norm_b128_2);
+    for i in 0..n - m {
+        let x = *ptr1.add(i);
+        let y = *ptr2.add(i);
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    cosine_distance_from_parts(dot, norm_a, norm_b)
+}
 
-        sum128_3 = _mm_add_ps(
-            _mm_mul_ps(_mm_loadu_ps(ptr1.add(8)), _mm_loadu_ps(ptr2.add(8))),
-            sum128_3,
-        );
+/// # Safety
+/// The CPU must support sse4.1, and `a` and `b` must have the same length.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+#[target_feature(enable = "sse4.1")]
+pub unsafe fn cosine_distance_with_norm(a: &[f32], b: &[f32], norm_a_squared: f32) -> f32 {
+    let n = a.len();
+    let m = n - (n % 8);
+    let mut ptr1: *const f32 = a.as_ptr();
+    let mut ptr2: *const f32 = b.as_ptr();
+    let mut dot128_1: __m128 = _mm_setzero_ps();
+    let mut dot128_2: __m128 = _mm_setzero_ps();
+    let mut norm_b128_1: __m128 = _mm_setzero_ps();
+    let mut norm_b128_2: __m128 = _mm_setzero_ps();
 
-        sum128_4 = _mm_add_ps(
-            _mm_mul_ps(_mm_loadu_ps(ptr1.add(12)), _mm_loadu_ps(ptr2.add(12))),
-            sum128_4,
-        );
+    let mut i: usize = 0;
+    while i < m {
+        let b128_1 = _mm_loadu_ps(ptr2);
+        dot128_1 = _mm_add_ps(_mm_mul_ps(_mm_loadu_ps(ptr1), b128_1), dot128_1);
+        norm_b128_1 = _mm_add_ps(_mm_mul_ps(b128_1, b128_1), norm_b128_1);
 
-        ptr1 = ptr1.add(16);
-        ptr2 = ptr2.add(16);
-        i += 16;
+        let b128_2 = _mm_loadu_ps(ptr2.add(4));
+        dot128_2 = _mm_add_ps(_mm_mul_ps(_mm_loadu_ps(ptr1.add(4)), b128_2), dot128_2);
+        norm_b128_2 = _mm_add_ps(_mm_mul_ps(b128_2, b128_2), norm_b128_2);
+
+        ptr1 = ptr1.add(8);
+        ptr2 = ptr2.add(8);
+        i += 8;
     }
 
-    let mut result = hsum128_ps_sse(sum128_1)
-        + hsum128_ps_sse(sum128_2)
-        + hsum128_ps_sse(sum128_3)
-        + hsum128_ps_sse(sum128_4);
+    let mut dot = hsum128_ps_sse(dot128_1) + hsum128_ps_sse(dot128_2);
+    let mut norm_b = hsum128_ps_sse(norm_b128_1) + hsum128_ps_sse(norm_b128_2);
     for i in 0..n - m {
-        result += (*ptr1.add(i)) * (*ptr2.add(i));
+        let y = *ptr2.add(i);
+        dot += *ptr1.add(i) * y;
+        norm_b += y * y;
     }
-    1.0_f32 - result
+    cosine_distance_from_parts(dot, norm_a_squared, norm_b)
 }
 
-#[cfg(target_feature = "sse")]
+/// # Safety
+/// The CPU must support sse4.1, and `a` and `b` must have the same length.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+#[target_feature(enable = "sse4.1")]
 pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 16);
@@ -302,7 +356,10 @@ pub unsafe fn inner_product(a: &[f32], b: &[f32]) -> f32 {
     1.0_f32 - result
 }
 
-#[cfg(target_feature = "sse")]
+/// # Safety
+/// The CPU must support sse4.1, and `a` and `b` must have the same length.
+#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+#[target_feature(enable = "sse4.1")]
 pub unsafe fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
     let n = a.len();
     let m = n - (n % 16);
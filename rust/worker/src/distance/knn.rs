@@ -0,0 +1,422 @@
+use super::{Distance, DistanceFunction, VectorSlice};
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+#[derive(Debug)]
+struct Neighbor {
+    offset_id: u32,
+    distance: f32,
+}
+
+impl Ord for Neighbor {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // The heap keeps the worst neighbor on top, so a larger distance compares greater.
+        // Ties are broken on the offset id so that the result does not depend on input order.
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.offset_id.cmp(&other.offset_id))
+    }
+}
+
+impl PartialOrd for Neighbor {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Neighbor {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Neighbor {}
+
+impl DistanceFunction {
+    /// Finds the `k` candidates closest to `query`.
+    /// # Description
+    /// Candidates are scored one at a time against a bounded max-heap of the best `k` seen so
+    /// far, so this runs in O(n log k) and never holds more than `k` scores.
+    /// # Returns
+    /// Up to `k` `(offset_id, distance)` pairs sorted by ascending distance. Equal distances
+    /// are ordered by ascending offset id.
+    /// # Notes
+    /// - NaN distances sort after every other distance.
+    /// - Like `distance`, this expects every candidate to have the same dimension as the query.
+    pub fn top_k<'a>(
+        &self,
+        query: &[f32],
+        candidates: impl Iterator<Item = (u32, &'a [f32])>,
+        k: usize,
+    ) -> Vec<(u32, f32)> {
+        let score = self.scorer(query);
+        top_k_by(
+            candidates.map(|(offset_id, candidate)| (offset_id, score(candidate))),
+            k,
+        )
+    }
+}
+
+/// Keeps the `k` smallest of already scored `(offset_id, distance)` pairs, sorted and tie
+/// broken as described on `DistanceFunction::top_k`.
+pub(crate) fn top_k_by(scored: impl Iterator<Item = (u32, f32)>, k: usize) -> Vec<(u32, f32)> {
+    if k == 0 {
+        return Vec::new();
+    }
+    let mut heap = BinaryHeap::with_capacity(k);
+    for (offset_id, distance) in scored {
+        let neighbor = Neighbor {
+            offset_id,
+            distance,
+        };
+        if heap.len() < k {
+            heap.push(neighbor);
+        } else if let Some(mut worst) = heap.peek_mut() {
+            if neighbor < *worst {
+                *worst = neighbor;
+            }
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|neighbor| (neighbor.offset_id, neighbor.distance))
+        .collect()
+}
+
+/// Finds the `k` candidates closest to `query`, scoring chunks of `chunk_size` candidates in
+/// parallel on the threads of the current rayon pool.
+/// # Description
+/// Every chunk is scored with the batch kernels of `distance` and keeps its own `k` nearest,
+/// and the `k` nearest of those are the `k` nearest of all candidates. `check` is called
+/// before a chunk is scored, and an error it returns gives up the scan.
+/// # Returns
+/// The same as `DistanceFunction::top_k`, with the position of a candidate as its offset id.
+/// Because ties are broken on the offset id, the result does not depend on how the
+/// candidates are chunked or on which thread scores which chunk.
+pub fn par_top_k<E: Send>(
+    distance: &dyn Distance,
+    query: &[f32],
+    candidates: &[&[f32]],
+    k: usize,
+    chunk_size: usize,
+    check: impl Fn() -> Result<(), E> + Sync,
+) -> Result<Vec<(u32, f32)>, E> {
+    let mut nearest = par_top_k_batch(distance, &[(query, k)], candidates, chunk_size, check)?;
+    Ok(nearest.remove(0))
+}
+
+/// Finds the nearest candidates of each of `queries`, given with how many to find for it, in a
+/// single scan of the candidates.
+/// # Description
+/// Like `par_top_k`, but a chunk is scored against every query while it is in cache, rather
+/// than going over all candidates once per query. Chunks are given to the
+/// `nearest_neighbors_batch` of `distance`, so anything it derives from the candidates, like
+/// the packed bits of Hamming, is derived once per chunk rather than once per query.
+/// # Returns
+/// The nearest candidates of each query in the order of `queries`, each the same as
+/// `par_top_k` would return for that query alone.
+pub fn par_top_k_batch<E: Send>(
+    distance: &dyn Distance,
+    queries: &[(&[f32], usize)],
+    candidates: &[&[f32]],
+    chunk_size: usize,
+    check: impl Fn() -> Result<(), E> + Sync,
+) -> Result<Vec<Vec<(u32, f32)>>, E> {
+    par_scan(queries, candidates, chunk_size, check, |queries, chunk| {
+        distance.nearest_neighbors_batch(queries, chunk)
+    })
+}
+
+/// Like `par_top_k_batch`, for candidates in any representation. Quantized candidates are
+/// scored as they are, without an f32 copy of them being made.
+pub fn par_top_k_batch_slices<E: Send>(
+    distance: &dyn Distance,
+    queries: &[(&[f32], usize)],
+    candidates: &[VectorSlice],
+    chunk_size: usize,
+    check: impl Fn() -> Result<(), E> + Sync,
+) -> Result<Vec<Vec<(u32, f32)>>, E> {
+    par_scan(
+        queries,
+        candidates,
+        chunk_size,
+        check,
+        |queries, chunk| match VectorSlice::all_f32(chunk) {
+            Some(chunk) => distance.nearest_neighbors_batch(queries, &chunk),
+            None => queries
+                .iter()
+                .map(|(query, k)| distance.nearest_neighbors_slices(query, chunk, *k))
+                .collect(),
+        },
+    )
+}
+
+// Scores chunks of candidates in parallel with `nearest`, which finds the nearest candidates
+// of a chunk to each query, and merges the nearest of every chunk.
+pub(super) fn par_scan<Q: Sync, C: Sync, E: Send>(
+    queries: &[(Q, usize)],
+    candidates: &[C],
+    chunk_size: usize,
+    check: impl Fn() -> Result<(), E> + Sync,
+    nearest: impl Fn(&[(Q, usize)], &[C]) -> Vec<Vec<(u32, f32)>> + Sync,
+) -> Result<Vec<Vec<(u32, f32)>>, E> {
+    let chunk_size = chunk_size.max(1);
+    let nearest_in_chunks = candidates
+        .par_chunks(chunk_size)
+        .enumerate()
+        .map(|(chunk_index, chunk)| {
+            check()?;
+            let chunk_start = (chunk_index * chunk_size) as u32;
+            Ok(nearest(queries, chunk)
+                .into_iter()
+                .map(|nearest| {
+                    nearest
+                        .into_iter()
+                        .map(|(index, distance)| (chunk_start + index, distance))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>())
+        })
+        .collect::<Result<Vec<_>, E>>()?;
+    Ok(queries
+        .iter()
+        .enumerate()
+        .map(|(query_index, (_, k))| {
+            top_k_by(
+                nearest_in_chunks
+                    .iter()
+                    .flat_map(|chunk| chunk[query_index].iter().copied()),
+                *k,
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(embeddings: &[Vec<f32>]) -> impl Iterator<Item = (u32, &[f32])> {
+        embeddings
+            .iter()
+            .enumerate()
+            .map(|(i, embedding)| (i as u32, embedding.as_slice()))
+    }
+
+    #[test]
+    fn test_top_k_matches_full_sort() {
+        let query = vec![0.25, -0.5, 1.0];
+        let embeddings: Vec<Vec<f32>> = (0..200)
+            .map(|_| (0..3).map(|_| rand::random::<f32>() * 2.0 - 1.0).collect())
+            .collect();
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+        ] {
+            let mut expected: Vec<(u32, f32)> = candidates(&embeddings)
+                .map(|(id, embedding)| (id, distance_function.distance(&query, embedding)))
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            let result = distance_function.top_k(&query, candidates(&embeddings), 10);
+            assert_eq!(result.len(), 10);
+            for ((id, distance), (expected_id, expected_distance)) in
+                result.iter().zip(expected.iter())
+            {
+                assert_eq!(id, expected_id);
+                assert!((distance - expected_distance).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_top_k_larger_than_candidates() {
+        let embeddings = vec![vec![3.0, 0.0], vec![1.0, 0.0], vec![2.0, 0.0]];
+        let result = DistanceFunction::Euclidean.top_k(&[0.0, 0.0], candidates(&embeddings), 10);
+        assert_eq!(result, vec![(1, 1.0), (2, 4.0), (0, 9.0)]);
+    }
+
+    #[test]
+    fn test_top_k_zero() {
+        let embeddings = vec![vec![1.0, 0.0]];
+        let result = DistanceFunction::Euclidean.top_k(&[0.0, 0.0], candidates(&embeddings), 0);
+        assert!(result.is_empty());
+        let result = DistanceFunction::Euclidean.top_k(&[0.0, 0.0], std::iter::empty(), 5);
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_top_k_ties_break_on_offset_id() {
+        let embedding = vec![1.0, 1.0];
+        let tied = [
+            (7, embedding.as_slice()),
+            (3, embedding.as_slice()),
+            (5, embedding.as_slice()),
+            (1, embedding.as_slice()),
+        ];
+        let result = DistanceFunction::Euclidean.top_k(&[0.0, 0.0], tied.iter().copied(), 3);
+        assert_eq!(result, vec![(1, 2.0), (3, 2.0), (5, 2.0)]);
+        // The input order does not change which tied candidates are kept.
+        let result = DistanceFunction::Euclidean.top_k(&[0.0, 0.0], tied.iter().rev().copied(), 3);
+        assert_eq!(result, vec![(1, 2.0), (3, 2.0), (5, 2.0)]);
+    }
+
+    #[test]
+    fn test_par_top_k_matches_sequential() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        // Coordinates are drawn from a few values so that many candidates are tied.
+        let embeddings: Vec<Vec<f32>> = (0..5000)
+            .map(|_| (0..8).map(|_| rng.gen_range(0..4) as f32).collect())
+            .collect();
+        let slices: Vec<&[f32]> = embeddings.iter().map(|e| e.as_slice()).collect();
+        let query: Vec<f32> = (0..8).map(|_| rng.gen_range(0..4) as f32).collect();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+        ] {
+            for k in [1, 10, 100] {
+                // Scored sequentially, in one batch.
+                let expected = distance_function.nearest_neighbors(&query, &slices, k);
+                for chunk_size in [1, 7, 1024, 10000] {
+                    let result = pool
+                        .install(|| {
+                            par_top_k(&distance_function, &query, &slices, k, chunk_size, || {
+                                Ok::<(), ()>(())
+                            })
+                        })
+                        .unwrap();
+                    assert_eq!(result, expected, "k = {}, chunk_size = {}", k, chunk_size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_par_top_k_batch_matches_each_query_alone() {
+        let embeddings: Vec<Vec<f32>> = (0..3000)
+            .map(|_| (0..8).map(|_| rand::random::<f32>() * 2.0 - 1.0).collect())
+            .collect();
+        let slices: Vec<&[f32]> = embeddings.iter().map(|e| e.as_slice()).collect();
+        let query_vectors: Vec<Vec<f32>> = (0..5)
+            .map(|_| (0..8).map(|_| rand::random::<f32>() * 2.0 - 1.0).collect())
+            .collect();
+        // Every query asks for a different number of neighbors.
+        let queries: Vec<(&[f32], usize)> = query_vectors
+            .iter()
+            .zip([1, 10, 0, 3000, 50])
+            .map(|(query, k)| (query.as_slice(), k))
+            .collect();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        for distance_function in [DistanceFunction::Euclidean, DistanceFunction::Hamming] {
+            let result = pool
+                .install(|| {
+                    par_top_k_batch(&distance_function, &queries, &slices, 256, || {
+                        Ok::<(), ()>(())
+                    })
+                })
+                .unwrap();
+            assert_eq!(result.len(), queries.len());
+            for ((query, k), result) in queries.iter().zip(result) {
+                assert_eq!(result.len(), *k);
+                // Scored one candidate at a time, without any batch kernels.
+                let expected = top_k_by(
+                    slices
+                        .iter()
+                        .enumerate()
+                        .map(|(i, slice)| (i as u32, distance_function.distance(query, slice))),
+                    *k,
+                );
+                assert_eq!(result, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_par_top_k_batch_slices_scores_float16_candidates() {
+        use half::f16;
+
+        let embeddings: Vec<Vec<u16>> = (0..1000)
+            .map(|_| {
+                (0..8)
+                    .map(|_| f16::from_f32(rand::random::<f32>() * 2.0 - 1.0).to_bits())
+                    .collect()
+            })
+            .collect();
+        let dequantized: Vec<Vec<f32>> = embeddings
+            .iter()
+            .map(|e| e.iter().map(|x| f16::from_bits(*x).to_f32()).collect())
+            .collect();
+        let query: Vec<f32> = (0..8).map(|_| rand::random::<f32>() * 2.0 - 1.0).collect();
+        // Some chunks are all float32s and some are not.
+        let slices: Vec<VectorSlice> = embeddings
+            .iter()
+            .zip(&dequantized)
+            .enumerate()
+            .map(|(i, (bits, values))| match i < 500 {
+                true => VectorSlice::F32(values),
+                false => VectorSlice::F16(bits),
+            })
+            .collect();
+        let f32_slices: Vec<&[f32]> = dequantized.iter().map(|e| e.as_slice()).collect();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+        ] {
+            let result = pool
+                .install(|| {
+                    par_top_k_batch_slices(
+                        &distance_function,
+                        &[(&query, 20)],
+                        &slices,
+                        100,
+                        || Ok::<(), ()>(()),
+                    )
+                })
+                .unwrap();
+            let expected = distance_function.nearest_neighbors(&query, &f32_slices, 20);
+            // The kernels round differently, so nearly tied candidates may swap places.
+            assert_eq!(result[0].len(), expected.len());
+            for ((_, distance), (_, expected_distance)) in result[0].iter().zip(&expected) {
+                assert!((distance - expected_distance).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_par_top_k_gives_up_when_checked() {
+        let embeddings = vec![vec![1.0, 0.0]; 100];
+        let slices: Vec<&[f32]> = embeddings.iter().map(|e| e.as_slice()).collect();
+        // The global rayon pool is left to the tests that configure it.
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let result = pool.install(|| {
+            par_top_k(
+                &DistanceFunction::Euclidean,
+                &[0.0, 0.0],
+                &slices,
+                5,
+                10,
+                || Err("exceeded"),
+            )
+        });
+        assert_eq!(result, Err("exceeded"));
+    }
+}
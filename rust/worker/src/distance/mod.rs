@@ -1,9 +1,15 @@
+pub mod binary;
+pub(crate) mod config;
 pub mod distance_avx;
 pub mod distance_neon;
+pub mod distance_scalar;
 pub mod distance_sse;
+pub mod knn;
+pub mod quantized;
+pub mod registry;
+pub mod sparse;
 pub mod types;
 
-pub use distance_avx::*;
-pub use distance_neon::*;
-pub use distance_sse::*;
+pub use quantized::VectorSlice;
+pub use registry::DistanceRegistry;
 pub use types::*;
@@ -0,0 +1,368 @@
+use super::distance_scalar::cosine_distance_from_parts;
+use super::DistanceFunction;
+use half::f16;
+use std::borrow::Cow;
+
+/// A borrowed embedding in one of the representations the distance functions can score
+/// without first materializing an f32 copy of it.
+/// # Variants
+/// - `F32` - Full precision values.
+/// - `F16` - IEEE 754 half precision values, stored as their raw bits.
+/// - `I8` - Symmetrically quantized values, where each element represents `value * scale`.
+/// # Notes
+/// Quantized values are only dequantized inside the distance loop, one element at a time.
+/// The error this introduces is that of the representation itself: half precision keeps
+/// about three significant decimal digits per element, and int8 is off by at most
+/// `scale / 2` per element.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VectorSlice<'a> {
+    F32(&'a [f32]),
+    F16(&'a [u16]),
+    I8 { values: &'a [i8], scale: f32 },
+}
+
+impl<'a> VectorSlice<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            VectorSlice::F32(values) => values.len(),
+            VectorSlice::F16(values) => values.len(),
+            VectorSlice::I8 { values, .. } => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The values as f32s, borrowed when they already are and dequantized otherwise.
+    pub fn to_f32(&self) -> Cow<'a, [f32]> {
+        match *self {
+            VectorSlice::F32(values) => Cow::Borrowed(values),
+            _ => Cow::Owned((0..self.len()).map(|i| self.get(i)).collect()),
+        }
+    }
+
+    /// The values of `slices` if they are all f32s, and `None` otherwise.
+    pub fn all_f32(slices: &[VectorSlice<'a>]) -> Option<Vec<&'a [f32]>> {
+        slices
+            .iter()
+            .map(|slice| match slice {
+                VectorSlice::F32(values) => Some(*values),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> f32 {
+        match self {
+            VectorSlice::F32(values) => values[i],
+            VectorSlice::F16(values) => f16::from_bits(values[i]).to_f32(),
+            VectorSlice::I8 { values, scale } => values[i] as f32 * scale,
+        }
+    }
+}
+
+impl DistanceFunction {
+    /// Computes the distance between two half precision vectors given as their IEEE 754 bits.
+    pub fn distance_f16(&self, a: &[u16], b: &[u16]) -> f32 {
+        self.distance_dequantized(VectorSlice::F16(a), VectorSlice::F16(b))
+    }
+
+    /// Computes the distance between two int8 vectors quantized with the same `scale`.
+    /// # Description
+    /// The sums are accumulated exactly in integers and scaled once at the end, so the only
+    /// error relative to the dequantized f32 vectors is the final conversion to f32.
+    pub fn distance_i8(&self, a: &[i8], b: &[i8], scale: f32) -> f32 {
+        let len = std::cmp::min(a.len(), b.len());
+        let (a, b) = (&a[..len], &b[..len]);
+        match self {
+            DistanceFunction::Euclidean => {
+                let sum: i64 = a
+                    .iter()
+                    .zip(b)
+                    .map(|(x, y)| {
+                        let d = *x as i64 - *y as i64;
+                        d * d
+                    })
+                    .sum();
+                sum as f32 * scale * scale
+            }
+            DistanceFunction::Manhattan => {
+                let sum: i64 = a
+                    .iter()
+                    .zip(b)
+                    .map(|(x, y)| (*x as i64 - *y as i64).abs())
+                    .sum();
+                sum as f32 * scale.abs()
+            }
+            DistanceFunction::Hamming => {
+                let count = a
+                    .iter()
+                    .zip(b)
+                    .filter(|(x, y)| (**x as f32 * scale > 0.0) != (**y as f32 * scale > 0.0))
+                    .count();
+                count as f32
+            }
+            DistanceFunction::InnerProduct => {
+                let dot: i64 = a.iter().zip(b).map(|(x, y)| *x as i64 * *y as i64).sum();
+                1.0_f32 - dot as f32 * scale * scale
+            }
+            DistanceFunction::Cosine => {
+                // The scale cancels out of the cosine.
+                let mut dot: i64 = 0;
+                let mut norm_a: i64 = 0;
+                let mut norm_b: i64 = 0;
+                for (x, y) in a.iter().zip(b) {
+                    let (x, y) = (*x as i64, *y as i64);
+                    dot += x * y;
+                    norm_a += x * x;
+                    norm_b += y * y;
+                }
+                cosine_distance_from_parts(dot as f32, norm_a as f32, norm_b as f32)
+            }
+            DistanceFunction::Jaccard => self.distance_dequantized(
+                VectorSlice::I8 { values: a, scale },
+                VectorSlice::I8 { values: b, scale },
+            ),
+        }
+    }
+
+    /// Computes the distance between two vectors in any representation, including mixed ones
+    /// such as an f32 query against int8 embeddings.
+    pub fn distance_slices(&self, a: VectorSlice, b: VectorSlice) -> f32 {
+        match (a, b) {
+            (VectorSlice::F32(a), VectorSlice::F32(b)) => self.distance(a, b),
+            (
+                VectorSlice::I8 {
+                    values: a,
+                    scale: scale_a,
+                },
+                VectorSlice::I8 {
+                    values: b,
+                    scale: scale_b,
+                },
+            ) if scale_a == scale_b => self.distance_i8(a, b, scale_a),
+            (a, b) => self.distance_dequantized(a, b),
+        }
+    }
+
+    fn distance_dequantized(&self, a: VectorSlice, b: VectorSlice) -> f32 {
+        let len = std::cmp::min(a.len(), b.len());
+        match self {
+            DistanceFunction::Euclidean => {
+                let mut sum = 0.0_f32;
+                for i in 0..len {
+                    let d = a.get(i) - b.get(i);
+                    sum += d * d;
+                }
+                sum
+            }
+            DistanceFunction::Manhattan => {
+                let mut sum = 0.0_f32;
+                for i in 0..len {
+                    sum += (a.get(i) - b.get(i)).abs();
+                }
+                sum
+            }
+            DistanceFunction::Hamming => {
+                let mut count = 0_u32;
+                for i in 0..len {
+                    if (a.get(i) > 0.0) != (b.get(i) > 0.0) {
+                        count += 1;
+                    }
+                }
+                count as f32
+            }
+            DistanceFunction::InnerProduct => {
+                let mut dot = 0.0_f32;
+                for i in 0..len {
+                    dot += a.get(i) * b.get(i);
+                }
+                1.0_f32 - dot
+            }
+            DistanceFunction::Cosine => {
+                let mut dot = 0.0_f32;
+                let mut norm_a = 0.0_f32;
+                let mut norm_b = 0.0_f32;
+                for i in 0..len {
+                    let (x, y) = (a.get(i), b.get(i));
+                    dot += x * y;
+                    norm_a += x * x;
+                    norm_b += y * y;
+                }
+                cosine_distance_from_parts(dot, norm_a, norm_b)
+            }
+            DistanceFunction::Jaccard => {
+                let (mut intersection, mut union) = (0.0_f32, 0.0_f32);
+                for i in 0..len {
+                    let (x, y) = (a.get(i).max(0.0), b.get(i).max(0.0));
+                    intersection += x.min(y);
+                    union += x.max(y);
+                }
+                if union == 0.0 {
+                    return 0.0;
+                }
+                1.0 - intersection / union
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISTANCE_FUNCTIONS: [DistanceFunction; 4] = [
+        DistanceFunction::Euclidean,
+        DistanceFunction::Cosine,
+        DistanceFunction::InnerProduct,
+        DistanceFunction::Manhattan,
+    ];
+
+    fn random_vector(n: usize) -> Vec<f32> {
+        (0..n).map(|_| rand::random::<f32>() * 2.0 - 1.0).collect()
+    }
+
+    fn to_f16(vector: &[f32]) -> Vec<u16> {
+        vector.iter().map(|x| f16::from_f32(*x).to_bits()).collect()
+    }
+
+    fn to_i8(vector: &[f32], scale: f32) -> Vec<i8> {
+        vector
+            .iter()
+            .map(|x| (x / scale).round().clamp(-127.0, 127.0) as i8)
+            .collect()
+    }
+
+    fn norm(vector: &[f32]) -> f32 {
+        vector.iter().map(|x| x * x).sum::<f32>().sqrt()
+    }
+
+    // The error of an inner product is bounded by the magnitude of the terms being summed
+    // rather than by the result, which can be close to zero for unrelated vectors.
+    fn tolerance_scale(distance_function: &DistanceFunction, a: &[f32], b: &[f32]) -> f32 {
+        match distance_function {
+            DistanceFunction::InnerProduct => norm(a) * norm(b),
+            _ => 0.0,
+        }
+    }
+
+    fn assert_close_scaled(actual: f32, expected: f32, tolerance: f32, scale: f32, context: &str) {
+        assert!(
+            (actual - expected).abs() <= tolerance * (1.0 + expected.abs().max(scale)),
+            "{}: {} != {}",
+            context,
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_f16_matches_f32_within_tolerance() {
+        for n in [1, 3, 17, 128, 1536] {
+            let a = random_vector(n);
+            let b = random_vector(n);
+            let (a_f16, b_f16) = (to_f16(&a), to_f16(&b));
+            let a_dequantized: Vec<f32> =
+                a_f16.iter().map(|x| f16::from_bits(*x).to_f32()).collect();
+            let b_dequantized: Vec<f32> =
+                b_f16.iter().map(|x| f16::from_bits(*x).to_f32()).collect();
+            for distance_function in DISTANCE_FUNCTIONS {
+                let actual = distance_function.distance_f16(&a_f16, &b_f16);
+                let context = format!("{:?} n = {}", distance_function, n);
+                let scale = tolerance_scale(&distance_function, &a, &b);
+                // Scoring the quantized data adds no error beyond f32 summation order.
+                let dequantized = distance_function.distance(&a_dequantized, &b_dequantized);
+                assert_close_scaled(actual, dequantized, 1e-5, scale, &context);
+                // Half precision is within 1e-3 relative of the full precision result.
+                let expected = distance_function.distance(&a, &b);
+                assert_close_scaled(actual, expected, 1e-3, scale, &context);
+            }
+        }
+    }
+
+    #[test]
+    fn test_i8_matches_f32_within_tolerance() {
+        let scale = 1.0 / 127.0;
+        // Single element vectors are left out, since an element that rounds to zero turns a
+        // cosine distance of 0 or 2 into 1.
+        for n in [3, 17, 128, 1536] {
+            let a = random_vector(n);
+            let b = random_vector(n);
+            let (a_i8, b_i8) = (to_i8(&a, scale), to_i8(&b, scale));
+            let a_dequantized: Vec<f32> = a_i8.iter().map(|x| *x as f32 * scale).collect();
+            let b_dequantized: Vec<f32> = b_i8.iter().map(|x| *x as f32 * scale).collect();
+            for distance_function in DISTANCE_FUNCTIONS {
+                let actual = distance_function.distance_i8(&a_i8, &b_i8, scale);
+                let context = format!("{:?} n = {}", distance_function, n);
+                let magnitude = tolerance_scale(&distance_function, &a, &b);
+                let dequantized = distance_function.distance(&a_dequantized, &b_dequantized);
+                assert_close_scaled(actual, dequantized, 1e-5, magnitude, &context);
+                // Each element is off by at most scale / 2, which for values in [-1, 1]
+                // keeps every distance within 2e-2 relative of the full precision result.
+                let expected = distance_function.distance(&a, &b);
+                assert_close_scaled(actual, expected, 2e-2, magnitude, &context);
+            }
+        }
+    }
+
+    #[test]
+    fn test_distance_slices_mixed_representations() {
+        let a = random_vector(64);
+        let b = random_vector(64);
+        let b_f16 = to_f16(&b);
+        let b_i8 = to_i8(&b, 1.0 / 127.0);
+        for distance_function in DISTANCE_FUNCTIONS {
+            let expected = distance_function.distance(&a, &b);
+            let scale = tolerance_scale(&distance_function, &a, &b);
+            let context = format!("{:?}", distance_function);
+            assert_eq!(
+                distance_function.distance_slices(VectorSlice::F32(&a), VectorSlice::F32(&b)),
+                expected
+            );
+            let actual =
+                distance_function.distance_slices(VectorSlice::F32(&a), VectorSlice::F16(&b_f16));
+            assert_close_scaled(actual, expected, 1e-3, scale, &context);
+            let actual = distance_function.distance_slices(
+                VectorSlice::F32(&a),
+                VectorSlice::I8 {
+                    values: &b_i8,
+                    scale: 1.0 / 127.0,
+                },
+            );
+            assert_close_scaled(actual, expected, 2e-2, scale, &context);
+        }
+    }
+
+    #[test]
+    fn test_hamming_matches_dequantized() {
+        // Quantization can round small values to zero and flip their bit, so these are only
+        // compared against the dequantized vectors, which they match exactly.
+        let a = random_vector(100);
+        let b = random_vector(100);
+        let scale = 1.0 / 127.0;
+        let (a_i8, b_i8) = (to_i8(&a, scale), to_i8(&b, scale));
+        let a_dequantized: Vec<f32> = a_i8.iter().map(|x| *x as f32 * scale).collect();
+        let b_dequantized: Vec<f32> = b_i8.iter().map(|x| *x as f32 * scale).collect();
+        assert_eq!(
+            DistanceFunction::Hamming.distance_i8(&a_i8, &b_i8, scale),
+            DistanceFunction::Hamming.distance(&a_dequantized, &b_dequantized)
+        );
+        let (a_f16, b_f16) = (to_f16(&a), to_f16(&b));
+        let a_dequantized: Vec<f32> = a_f16.iter().map(|x| f16::from_bits(*x).to_f32()).collect();
+        let b_dequantized: Vec<f32> = b_f16.iter().map(|x| f16::from_bits(*x).to_f32()).collect();
+        assert_eq!(
+            DistanceFunction::Hamming.distance_f16(&a_f16, &b_f16),
+            DistanceFunction::Hamming.distance(&a_dequantized, &b_dequantized)
+        );
+    }
+
+    #[test]
+    fn test_i8_zero_vector_cosine() {
+        let zero = vec![0_i8; 4];
+        let a = vec![1_i8, 2, 3, 4];
+        assert_eq!(DistanceFunction::Cosine.distance_i8(&zero, &a, 0.5), 1.0);
+        assert_eq!(DistanceFunction::Cosine.distance_i8(&zero, &zero, 0.5), 1.0);
+    }
+}
@@ -0,0 +1,220 @@
+use super::config::{CustomDistanceConfig, DistanceConfig};
+use super::{Distance, DistanceFunction, DistanceFunctionError};
+use crate::config::Configurable;
+use crate::errors::ChromaError;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The distances queries can be run with, by name.
+/// # Description
+/// The registry starts out with the built-in distance functions registered under their
+/// canonical names. The query service populates it from its config at startup, and further
+/// distances can be added with `register`.
+#[derive(Clone, Debug)]
+pub struct DistanceRegistry {
+    distances: HashMap<String, Arc<dyn Distance>>,
+}
+
+impl DistanceRegistry {
+    pub fn new() -> Self {
+        let mut registry = DistanceRegistry {
+            distances: HashMap::new(),
+        };
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+            DistanceFunction::Manhattan,
+            DistanceFunction::Hamming,
+            DistanceFunction::Jaccard,
+        ] {
+            registry.distances.insert(
+                distance_function.name().to_string(),
+                Arc::new(distance_function),
+            );
+        }
+        registry
+    }
+
+    /// Makes `distance` available under its name.
+    /// # Errors
+    /// Returns `AlreadyRegistered` if a distance with the same name, including a built-in
+    /// one, is already registered.
+    pub fn register(&mut self, distance: Arc<dyn Distance>) -> Result<(), DistanceFunctionError> {
+        let name = distance.name().to_string();
+        if self.distances.contains_key(&name) {
+            return Err(DistanceFunctionError::AlreadyRegistered(name));
+        }
+        self.distances.insert(name, distance);
+        Ok(())
+    }
+
+    /// Looks up a distance by name.
+    /// # Errors
+    /// Returns `InvalidDistanceFunction` if no distance is registered under `name`.
+    pub fn get(&self, name: &str) -> Result<Arc<dyn Distance>, DistanceFunctionError> {
+        match self.distances.get(name) {
+            Some(distance) => Ok(distance.clone()),
+            None => Err(DistanceFunctionError::InvalidDistanceFunction(
+                name.to_string(),
+            )),
+        }
+    }
+}
+
+impl Default for DistanceRegistry {
+    fn default() -> Self {
+        DistanceRegistry::new()
+    }
+}
+
+#[async_trait]
+impl Configurable<DistanceConfig> for DistanceRegistry {
+    async fn try_from_config(config: &DistanceConfig) -> Result<Self, Box<dyn ChromaError>> {
+        let mut registry = DistanceRegistry::new();
+        for custom in &config.custom {
+            let distance: Arc<dyn Distance> = match custom {
+                CustomDistanceConfig::Minkowski(config) => {
+                    match Minkowski::new(config.name.clone(), config.p) {
+                        Some(minkowski) => Arc::new(minkowski),
+                        None => {
+                            return Err(Box::new(DistanceFunctionError::InvalidDistanceFunction(
+                                config.name.clone(),
+                            )))
+                        }
+                    }
+                }
+            };
+            if let Err(e) = registry.register(distance) {
+                return Err(Box::new(e));
+            }
+        }
+        Ok(registry)
+    }
+}
+
+/// The Minkowski distance of order `p`, (sum |a_i - b_i|^p)^(1/p). An order of 1 is the
+/// l1 distance and an order of 2 the (not squared) l2 distance.
+#[derive(Debug)]
+pub struct Minkowski {
+    name: String,
+    p: f32,
+}
+
+impl Minkowski {
+    /// Returns `None` unless `p` is positive and finite.
+    pub fn new(name: String, p: f32) -> Option<Self> {
+        if !(p > 0.0 && p.is_finite()) {
+            return None;
+        }
+        Some(Minkowski { name, p })
+    }
+}
+
+impl Distance for Minkowski {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        let sum: f32 = a
+            .iter()
+            .zip(b)
+            .map(|(x, y)| (x - y).abs().powf(self.p))
+            .sum();
+        sum.powf(1.0 / self.p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Configurable;
+    use crate::distance::config::MinkowskiDistanceConfig;
+    use crate::errors::ErrorCodes;
+
+    // Counts the dimensions where the two embeddings differ.
+    #[derive(Debug)]
+    struct Mismatches;
+
+    impl Distance for Mismatches {
+        fn name(&self) -> &str {
+            "mismatches"
+        }
+
+        fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+            a.iter().zip(b).filter(|(x, y)| x != y).count() as f32
+        }
+    }
+
+    #[test]
+    fn test_registry_has_built_in_distance_functions() {
+        let registry = DistanceRegistry::new();
+        for name in ["l2", "cosine", "ip", "l1", "hamming"] {
+            let distance = registry.get(name).unwrap();
+            assert_eq!(distance.name(), name);
+            let expected = DistanceFunction::try_from(name).unwrap();
+            assert_eq!(
+                distance.distance(&[1.0, 2.0], &[3.0, -1.0]),
+                expected.distance(&[1.0, 2.0], &[3.0, -1.0])
+            );
+        }
+    }
+
+    #[test]
+    fn test_registry_custom_distance() {
+        let mut registry = DistanceRegistry::new();
+        registry.register(Arc::new(Mismatches)).unwrap();
+        let distance = registry.get("mismatches").unwrap();
+        assert_eq!(distance.distance(&[1.0, 2.0, 3.0], &[1.0, 0.0, 0.0]), 2.0);
+        let nearest = distance.nearest_neighbors(
+            &[1.0, 2.0, 3.0],
+            &[&[0.0, 0.0, 0.0], &[1.0, 2.0, 0.0], &[1.0, 0.0, 0.0]],
+            2,
+        );
+        assert_eq!(nearest, vec![(1, 1.0), (2, 2.0)]);
+    }
+
+    #[test]
+    fn test_registry_unknown_and_duplicate_names() {
+        let mut registry = DistanceRegistry::new();
+        match registry.get("mismatches") {
+            Err(DistanceFunctionError::InvalidDistanceFunction(name)) => {
+                assert_eq!(name, "mismatches")
+            }
+            other => panic!("Expected an invalid distance function, got {:?}", other),
+        }
+        registry.register(Arc::new(Mismatches)).unwrap();
+        let err = registry.register(Arc::new(Mismatches)).unwrap_err();
+        assert_eq!(err.code(), ErrorCodes::AlreadyExists);
+        let err = registry
+            .register(Arc::new(DistanceFunction::Cosine))
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCodes::AlreadyExists);
+    }
+
+    #[tokio::test]
+    async fn test_registry_from_config() {
+        let config = DistanceConfig {
+            custom: vec![CustomDistanceConfig::Minkowski(MinkowskiDistanceConfig {
+                name: "l3".to_string(),
+                p: 3.0,
+            })],
+        };
+        let registry = DistanceRegistry::try_from_config(&config).await.unwrap();
+        let distance = registry.get("l3").unwrap();
+        assert!((distance.distance(&[0.0, 0.0], &[1.0, 2.0]) - 9.0_f32.cbrt()).abs() < 1e-6);
+
+        let config = DistanceConfig {
+            custom: vec![CustomDistanceConfig::Minkowski(MinkowskiDistanceConfig {
+                name: "l0".to_string(),
+                p: 0.0,
+            })],
+        };
+        match DistanceRegistry::try_from_config(&config).await {
+            Ok(_) => panic!("Expected an order of 0 to be rejected"),
+            Err(e) => assert_eq!(e.code(), ErrorCodes::InvalidArgument),
+        }
+    }
+}
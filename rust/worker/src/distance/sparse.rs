@@ -0,0 +1,364 @@
+use super::knn::{par_scan, top_k_by};
+use crate::errors::{ChromaError, ErrorCodes};
+use thiserror::Error;
+
+/// A sparse vector, such as the hashed keywords of a document.
+/// # Fields
+/// - `indices` - The indices of the components that are present, in strictly increasing
+/// order.
+/// - `weights` - The weights of the components, one per index. A vector without weights is
+/// a set, every component it has has weight 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseVector {
+    indices: Vec<u32>,
+    weights: Option<Vec<f32>>,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SparseVectorError {
+    #[error("Sparse vector indices must be strictly increasing, index {0} is not")]
+    UnsortedIndices(usize),
+    #[error("Sparse vector has {indices} indices but {weights} weights")]
+    WeightCountMismatch { indices: usize, weights: usize },
+    #[error("Sparse vector weight {0} is negative, NaN or infinite")]
+    InvalidWeight(usize),
+    #[error("Embedding of length {0} is not a sparse vector")]
+    InvalidEmbedding(usize),
+}
+
+impl ChromaError for SparseVectorError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::InvalidArgument
+    }
+}
+
+impl SparseVector {
+    pub fn new(indices: Vec<u32>, weights: Option<Vec<f32>>) -> Result<Self, SparseVectorError> {
+        if let Some(position) = indices.windows(2).position(|pair| pair[0] >= pair[1]) {
+            return Err(SparseVectorError::UnsortedIndices(position + 1));
+        }
+        if let Some(weights) = &weights {
+            if weights.len() != indices.len() {
+                return Err(SparseVectorError::WeightCountMismatch {
+                    indices: indices.len(),
+                    weights: weights.len(),
+                });
+            }
+            // Jaccard distances are only defined for non-negative weights.
+            if let Some(position) = weights.iter().position(|w| !(w.is_finite() && *w >= 0.0)) {
+                return Err(SparseVectorError::InvalidWeight(position));
+            }
+        }
+        Ok(SparseVector { indices, weights })
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    pub fn weights(&self) -> Option<&[f32]> {
+        self.weights.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    fn weight(&self, position: usize) -> f32 {
+        match &self.weights {
+            Some(weights) => weights[position],
+            None => 1.0,
+        }
+    }
+
+    /// Lays the vector out as an embedding, so that it can be stored and passed around like
+    /// any other one.
+    /// # Description
+    /// The first element holds the number of indices `n`, and the next `n` the indices,
+    /// all bit cast from u32s. The weights follow if the vector has any, so the embedding
+    /// has `1 + n` or `1 + 2n` elements.
+    /// # Notes
+    /// The bit cast elements are not meaningful as floats and may be NaN. They are only
+    /// ever copied, never computed with, until `from_embedding` reads them back.
+    pub fn to_embedding(&self) -> Vec<f32> {
+        let mut embedding = Vec::with_capacity(1 + 2 * self.len());
+        embedding.push(f32::from_bits(self.len() as u32));
+        embedding.extend(self.indices.iter().map(|index| f32::from_bits(*index)));
+        if let Some(weights) = &self.weights {
+            embedding.extend(weights);
+        }
+        embedding
+    }
+
+    /// Reads back a vector laid out by `to_embedding`.
+    pub fn from_embedding(embedding: &[f32]) -> Result<Self, SparseVectorError> {
+        let invalid = || SparseVectorError::InvalidEmbedding(embedding.len());
+        let (len, rest) = match embedding.split_first() {
+            Some((len, rest)) => (len.to_bits() as usize, rest),
+            None => return Err(invalid()),
+        };
+        let (indices, weights) = if rest.len() == len {
+            (rest, None)
+        } else if rest.len() == 2 * len {
+            let (indices, weights) = rest.split_at(len);
+            (indices, Some(weights.to_vec()))
+        } else {
+            return Err(invalid());
+        };
+        SparseVector::new(
+            indices.iter().map(|index| index.to_bits()).collect(),
+            weights,
+        )
+    }
+}
+
+/// Computes the Jaccard distance between `a` and `b`, 1 - |A ∩ B| / |A ∪ B|.
+/// # Description
+/// With weights this is the weighted Jaccard distance, 1 - sum(min(a_i, b_i)) /
+/// sum(max(a_i, b_i)) over the union of the indices, where a missing component has weight
+/// 0. A vector without weights has weight 1 for each of its components, for which the two
+/// agree.
+/// # Notes
+/// Two empty vectors are the same set, so their distance is 0. An empty vector is at
+/// distance 1 from any other.
+pub fn jaccard_distance(a: &SparseVector, b: &SparseVector) -> f32 {
+    let (mut i, mut j) = (0, 0);
+    let (mut intersection, mut union) = (0.0, 0.0);
+    while i < a.len() && j < b.len() {
+        match a.indices[i].cmp(&b.indices[j]) {
+            std::cmp::Ordering::Less => {
+                union += a.weight(i);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                union += b.weight(j);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                let (x, y) = (a.weight(i), b.weight(j));
+                intersection += x.min(y);
+                union += x.max(y);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    union += (i..a.len()).map(|i| a.weight(i)).sum::<f32>();
+    union += (j..b.len()).map(|j| b.weight(j)).sum::<f32>();
+    if union == 0.0 {
+        return 0.0;
+    }
+    1.0 - intersection / union
+}
+
+/// Finds the `k` sparse vectors closest to `query` by Jaccard distance.
+/// # Returns
+/// Up to `k` `(offset_id, distance)` pairs sorted the same way as `DistanceFunction::top_k`.
+pub fn top_k_jaccard<'a>(
+    query: &SparseVector,
+    candidates: impl Iterator<Item = (u32, &'a SparseVector)>,
+    k: usize,
+) -> Vec<(u32, f32)> {
+    top_k_by(
+        candidates.map(|(offset_id, candidate)| (offset_id, jaccard_distance(query, candidate))),
+        k,
+    )
+}
+
+/// Finds the nearest sparse vectors of each of `queries`, given with how many to find for it,
+/// in a single scan of the candidates.
+/// # Description
+/// Like `par_top_k_batch`, chunks of `chunk_size` candidates are scored in parallel on the
+/// threads of the current rayon pool. `check` is called before a chunk is scored, and an
+/// error it returns gives up the scan.
+/// # Returns
+/// The nearest candidates of each query in the order of `queries`, each the same as
+/// `top_k_jaccard` would return for that query, with the position of a candidate as its
+/// offset id.
+pub fn par_top_k_jaccard_batch<E: Send>(
+    queries: &[(&SparseVector, usize)],
+    candidates: &[SparseVector],
+    chunk_size: usize,
+    check: impl Fn() -> Result<(), E> + Sync,
+) -> Result<Vec<Vec<(u32, f32)>>, E> {
+    par_scan(queries, candidates, chunk_size, check, |queries, chunk| {
+        queries
+            .iter()
+            .map(|(query, k)| {
+                top_k_jaccard(
+                    query,
+                    chunk
+                        .iter()
+                        .enumerate()
+                        .map(|(i, candidate)| (i as u32, candidate)),
+                    *k,
+                )
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::DistanceFunction;
+
+    fn set(indices: &[u32]) -> SparseVector {
+        SparseVector::new(indices.to_vec(), None).unwrap()
+    }
+
+    fn weighted(indices: &[u32], weights: &[f32]) -> SparseVector {
+        SparseVector::new(indices.to_vec(), Some(weights.to_vec())).unwrap()
+    }
+
+    #[test]
+    fn test_jaccard_distance() {
+        // Disjoint and identical sets.
+        assert_eq!(jaccard_distance(&set(&[1, 2]), &set(&[3, 4, 5])), 1.0);
+        assert_eq!(jaccard_distance(&set(&[1, 7, 9]), &set(&[1, 7, 9])), 0.0);
+        // |{2, 3}| / |{1, 2, 3, 4}|
+        assert_eq!(jaccard_distance(&set(&[1, 2, 3]), &set(&[2, 3, 4])), 0.5);
+        // (1 + 0.5) / (2 + 3 + 1)
+        assert_eq!(
+            jaccard_distance(
+                &weighted(&[1, 2, 3], &[2.0, 0.5, 1.0]),
+                &weighted(&[1, 2], &[1.0, 3.0])
+            ),
+            0.75
+        );
+        // Empty vectors.
+        assert_eq!(jaccard_distance(&set(&[]), &set(&[])), 0.0);
+        assert_eq!(jaccard_distance(&set(&[]), &set(&[u32::MAX])), 1.0);
+        assert_eq!(jaccard_distance(&weighted(&[4], &[0.0]), &set(&[])), 0.0);
+    }
+
+    #[test]
+    fn test_weighted_and_unweighted_agree_on_binary_weights() {
+        for _ in 0..100 {
+            let random_set =
+                || -> Vec<u32> { (0..50).filter(|_| rand::random::<bool>()).collect() };
+            let (a, b) = (random_set(), random_set());
+            let ones = |indices: &[u32]| weighted(indices, &vec![1.0; indices.len()]);
+            let unweighted = jaccard_distance(&set(&a), &set(&b));
+            assert_eq!(jaccard_distance(&ones(&a), &ones(&b)), unweighted);
+            assert_eq!(jaccard_distance(&ones(&a), &set(&b)), unweighted);
+
+            // Dense vectors with a 1 for each index are the same sets.
+            let dense = |indices: &[u32]| {
+                let mut dense = vec![0.0; 50];
+                for index in indices {
+                    dense[*index as usize] = 1.0;
+                }
+                dense
+            };
+            assert_eq!(
+                DistanceFunction::Jaccard.distance(&dense(&a), &dense(&b)),
+                unweighted
+            );
+        }
+    }
+
+    #[test]
+    fn test_sparse_vector_validation() {
+        assert_eq!(
+            SparseVector::new(vec![1, 3, 3], None),
+            Err(SparseVectorError::UnsortedIndices(2))
+        );
+        assert_eq!(
+            SparseVector::new(vec![2, 1], None),
+            Err(SparseVectorError::UnsortedIndices(1))
+        );
+        assert_eq!(
+            SparseVector::new(vec![1, 2], Some(vec![1.0])),
+            Err(SparseVectorError::WeightCountMismatch {
+                indices: 2,
+                weights: 1
+            })
+        );
+        assert_eq!(
+            SparseVector::new(vec![1, 2], Some(vec![1.0, -1.0])),
+            Err(SparseVectorError::InvalidWeight(1))
+        );
+        assert_eq!(
+            SparseVector::new(vec![1], Some(vec![f32::NAN])),
+            Err(SparseVectorError::InvalidWeight(0))
+        );
+    }
+
+    #[test]
+    fn test_embedding_round_trip() {
+        for vector in [
+            set(&[]),
+            set(&[0, 5, u32::MAX]),
+            // Bit patterns that are NaN as floats.
+            set(&[0x7fc0_0001, 0xffff_fff0]),
+            weighted(&[3, 8], &[0.25, 4.0]),
+        ] {
+            let embedding = vector.to_embedding();
+            assert_eq!(SparseVector::from_embedding(&embedding).unwrap(), vector);
+        }
+        assert_eq!(
+            SparseVector::from_embedding(&[]),
+            Err(SparseVectorError::InvalidEmbedding(0))
+        );
+        // A dense embedding is not a sparse vector.
+        assert!(SparseVector::from_embedding(&[0.5, 1.0, 2.0]).is_err());
+    }
+
+    #[test]
+    fn test_top_k_jaccard() {
+        let candidates = [set(&[1, 2, 3]), set(&[7]), set(&[1, 2]), set(&[1])];
+        let result = top_k_jaccard(
+            &set(&[1, 2]),
+            candidates
+                .iter()
+                .enumerate()
+                .map(|(i, candidate)| (i as u32, candidate)),
+            3,
+        );
+        assert_eq!(result, vec![(2, 0.0), (0, 1.0 - 2.0 / 3.0), (3, 0.5)]);
+    }
+
+    #[test]
+    fn test_par_top_k_jaccard_batch() {
+        let random_set = || -> SparseVector {
+            set(&(0..20)
+                .filter(|_| rand::random::<bool>())
+                .collect::<Vec<_>>())
+        };
+        let candidates: Vec<_> = (0..100).map(|_| random_set()).collect();
+        let queries: Vec<_> = (0..4).map(|_| random_set()).collect();
+        let queries: Vec<_> = queries.iter().zip([1, 5, 10, 200]).collect();
+        let expected: Vec<_> = queries
+            .iter()
+            .map(|(query, k)| {
+                top_k_jaccard(
+                    query,
+                    candidates
+                        .iter()
+                        .enumerate()
+                        .map(|(i, candidate)| (i as u32, candidate)),
+                    *k,
+                )
+            })
+            .collect();
+        // The global rayon pool is left to the tests that configure it.
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        for chunk_size in [1, 7, 100, 1000] {
+            let result = pool.install(|| {
+                par_top_k_jaccard_batch(&queries, &candidates, chunk_size, || Ok::<_, ()>(()))
+            });
+            assert_eq!(result, Ok(expected.clone()));
+        }
+        let result =
+            pool.install(|| par_top_k_jaccard_batch(&queries, &candidates, 10, || Err("exceeded")));
+        assert_eq!(result, Err("exceeded"));
+    }
+}
@@ -200,16 +200,36 @@ copyright Qdrant, licensed under the Apache 2.0 license.
    limitations under the License.
 */
 
+use crate::distance::binary::{pack_bits, top_k_hamming};
+use crate::distance::knn::top_k_by;
+use crate::distance::VectorSlice;
 use crate::errors::{ChromaError, ErrorCodes};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::Display;
+use std::str::FromStr;
 use thiserror::Error;
 
 /// The distance function enum.
 /// # Description
 /// This enum defines the distance functions supported by indices in Chroma.
 /// # Variants
-/// - `Euclidean` - The Euclidean or l2 norm.
-/// - `Cosine` - The cosine distance. Specifically, 1 - cosine.
-/// - `InnerProduct` - The inner product. Specifically, 1 - inner product.
+/// - `Euclidean` - The squared Euclidean or l2 distance. The square root is not taken, which
+/// matches what hnswlib's l2 space reports so scores from the index and from brute force are
+/// comparable.
+/// - `Cosine` - The cosine distance. Specifically, 1 - cosine. If either vector is the
+/// zero vector the distance is defined to be 1.0. hnswlib computes this as 1 - inner
+/// product over normalized vectors, which is why cosine embeddings are normalized on write.
+/// - `InnerProduct` - The inner product. Specifically, 1 - inner product, for any vectors and
+/// not only normalized ones. Unlike the other distances this can be negative.
+/// - `Manhattan` - The Manhattan or l1 distance, the sum of absolute differences. hnswlib has
+/// no l1 space, so this is only supported by the brute force paths.
+/// - `Hamming` - The Hamming distance between binarized vectors, where each dimension is the
+/// bit `x > 0`. Embeddings stored as packed bits are scored with `binary::hamming_distance`.
+/// Like l1 this is only supported by the brute force paths.
+/// - `Jaccard` - The weighted Jaccard distance, 1 - sum(min(a_i, b_i)) / sum(max(a_i, b_i)),
+/// which for sets is 1 - |A ∩ B| / |A ∪ B|. Dense vectors are the sets of their positive
+/// elements, sparse embeddings are scored with `sparse::jaccard_distance`. Two empty sets
+/// are at distance 0. Only supported by the brute force paths.
 /// # Notes
 /// See https://docs.trychroma.com/guides#changing-the-distance-function
 #[derive(Clone, Debug, PartialEq)]
@@ -217,116 +237,426 @@ pub enum DistanceFunction {
     Euclidean,
     Cosine,
     InnerProduct,
+    Manhattan,
+    Hamming,
+    Jaccard,
 }
 
 impl DistanceFunction {
-    // TOOD: Should we error if mismatched dimensions?
+    /// Computes the distance between `a` and `b`.
+    /// # Notes
+    /// Callers are expected to have validated that both vectors have the same dimension.
+    /// If they do not, only the overlapping prefix is compared. Use `checked_distance`
+    /// when the inputs come from an untrusted source such as a query.
     pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
-        // TODO: Figure out why the compiler is not auto vectorizing these
-        // by default.
-        match self {
-            DistanceFunction::Euclidean => {
-                #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
-                {
-                    if std::arch::is_aarch64_feature_detected!("neon") {
-                        return unsafe { crate::distance::distance_neon::euclidean_distance(a, b) };
-                    }
-                }
-                #[cfg(all(
-                    any(target_arch = "x86_64", target_arch = "x86"),
-                    target_feature = "sse"
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("sse") {
-                        return unsafe { crate::distance::distance_sse::euclidean_distance(a, b) };
-                    }
+        // The SIMD kernels would otherwise read past the end of the shorter slice.
+        let len = std::cmp::min(a.len(), b.len());
+        let (a, b) = (&a[..len], &b[..len]);
+        let kernels = DistanceKernels::get();
+        // Safety: the kernels were selected based on the features the CPU supports
+        // and both slices have the same length.
+        unsafe {
+            match self {
+                DistanceFunction::Euclidean => (kernels.euclidean)(a, b),
+                DistanceFunction::Cosine => (kernels.cosine)(a, b),
+                DistanceFunction::InnerProduct => (kernels.inner_product)(a, b),
+                // There are no SIMD kernels for l1 yet.
+                DistanceFunction::Manhattan => {
+                    crate::distance::distance_scalar::manhattan_distance(a, b)
                 }
-                #[cfg(all(
-                    target_arch = "x86_64",
-                    all(target_feature = "avx", target_feature = "fma")
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("avx")
-                        && std::arch::is_x86_feature_detected!("fma")
-                    {
-                        return unsafe { crate::distance::distance_avx::euclidean_distance(a, b) };
-                    }
+                DistanceFunction::Hamming => {
+                    crate::distance::distance_scalar::hamming_distance(a, b)
                 }
-                let mut sum = 0.0;
-                for i in 0..a.len() {
-                    sum += (a[i] - b[i]).powi(2);
+                DistanceFunction::Jaccard => {
+                    crate::distance::distance_scalar::jaccard_distance(a, b)
                 }
-                sum
             }
-            DistanceFunction::Cosine => {
-                #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
-                {
-                    if std::arch::is_aarch64_feature_detected!("neon") {
-                        return unsafe { crate::distance::distance_neon::cosine_distance(a, b) };
-                    }
-                }
-                #[cfg(all(
-                    any(target_arch = "x86_64", target_arch = "x86"),
-                    target_feature = "sse"
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("sse") {
-                        return unsafe { crate::distance::distance_sse::cosine_distance(a, b) };
-                    }
-                }
-                #[cfg(all(
-                    target_arch = "x86_64",
-                    all(target_feature = "avx", target_feature = "fma")
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("avx")
-                        && std::arch::is_x86_feature_detected!("fma")
-                    {
-                        return unsafe { crate::distance::distance_avx::cosine_distance(a, b) };
-                    }
-                }
-                // For cosine we just assume the vectors have been normalized, since that
-                // is what our indices expect.
-                let mut sum = 0.0;
-                for i in 0..a.len() {
-                    sum += a[i] * b[i];
-                }
-                1.0_f32 - sum
+        }
+    }
+
+    /// Computes the distance between `a` and `b`, assuming both have unit norm.
+    /// # Description
+    /// For cosine this skips computing the norms and is just 1 - dot, which is also what the
+    /// HNSW index computes for its normalized cosine space. The other distance functions do
+    /// not depend on the norms and are computed as usual.
+    /// # Notes
+    /// The result is only meaningful for unit vectors, or the zero vector, which gives 1.0 like
+    /// `distance` does. Use `normalize` first if the inputs may not be normalized.
+    pub fn distance_normalized(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            DistanceFunction::Cosine => DistanceFunction::InnerProduct.distance(a, b),
+            _ => self.distance(a, b),
+        }
+    }
+
+    /// Converts a score reported by the HNSW index into a distance on the same scale as
+    /// `distance`, so that results from the index and from brute force can be merged.
+    /// # Description
+    /// hnswlib reports squared l2, and 1 - inner product for both its ip and cosine spaces, which
+    /// are already the distances defined above. Rounding can however push a score slightly
+    /// outside the range the distance can take, e.g. 1 - dot of a normalized vector with
+    /// itself can come out a little below 0. Scores are clamped to that range so that equal
+    /// vectors compare equal whichever path scored them.
+    /// # Notes
+    /// NaN scores are mapped to infinity so that they sort after every other result.
+    pub fn raw_to_distance(&self, raw: f32) -> f32 {
+        if raw.is_nan() {
+            return f32::INFINITY;
+        }
+        match self {
+            DistanceFunction::Euclidean
+            | DistanceFunction::Manhattan
+            | DistanceFunction::Hamming => raw.max(0.0),
+            DistanceFunction::Cosine => raw.clamp(0.0, 2.0),
+            DistanceFunction::Jaccard => raw.clamp(0.0, 1.0),
+            DistanceFunction::InnerProduct => raw,
+        }
+    }
+
+    /// Converts a distance into a similarity, where larger means more similar.
+    /// # Description
+    /// For cosine, inner product and Jaccard this recovers the cosine, the inner product and
+    /// the Jaccard index respectively. The other distances are unbounded, and are mapped to
+    /// 1 / (1 + d), which is 1 for identical vectors and tends to 0 as they get further apart.
+    pub fn distance_to_similarity(&self, d: f32) -> f32 {
+        match self {
+            DistanceFunction::Cosine
+            | DistanceFunction::InnerProduct
+            | DistanceFunction::Jaccard => 1.0 - d,
+            DistanceFunction::Euclidean
+            | DistanceFunction::Manhattan
+            | DistanceFunction::Hamming => 1.0 / (1.0 + d),
+        }
+    }
+
+    /// Whether embeddings should be normalized once at write time for this distance function,
+    /// so that `distance_normalized` can be used on them.
+    pub fn normalizes_embeddings(&self) -> bool {
+        matches!(self, DistanceFunction::Cosine)
+    }
+
+    /// Whether the HNSW index has a space for this distance function. Collections using a
+    /// distance function it does not support can only be served by brute force.
+    pub fn supported_by_hnsw(&self) -> bool {
+        !matches!(
+            self,
+            DistanceFunction::Manhattan | DistanceFunction::Hamming | DistanceFunction::Jaccard
+        )
+    }
+
+    /// Computes the distance between `a` and `b`, returning an error if their dimensions differ.
+    /// `a` is treated as the reference when reporting the mismatch, so callers comparing a
+    /// query against stored embeddings should pass the stored embedding first.
+    pub fn checked_distance(&self, a: &[f32], b: &[f32]) -> Result<f32, DistanceFunctionError> {
+        if a.len() != b.len() {
+            return Err(DistanceFunctionError::DimensionMismatch {
+                expected: a.len(),
+                got: b.len(),
+            });
+        }
+        Ok(self.distance(a, b))
+    }
+
+    /// Computes the distance between `query` and each of `candidates`.
+    /// # Description
+    /// Equivalent to calling `distance(query, candidate)` for every candidate, but work that
+    /// only depends on the query (such as its norm for cosine) is done once for the batch.
+    /// The result has one distance per candidate, in the same order.
+    /// # Notes
+    /// Like `distance`, this expects every candidate to have the same dimension as the query.
+    pub fn distance_batch(&self, query: &[f32], candidates: &[&[f32]]) -> Vec<f32> {
+        self.distance_batch_iter(query, candidates.iter().copied())
+    }
+
+    /// Computes the distance between `query` and each row of a row-major `candidates` matrix
+    /// with `dimension` columns.
+    /// # Notes
+    /// A trailing partial row is ignored, and a `dimension` of zero yields no distances.
+    pub fn distance_batch_contiguous(
+        &self,
+        query: &[f32],
+        candidates: &[f32],
+        dimension: usize,
+    ) -> Vec<f32> {
+        if dimension == 0 {
+            return Vec::new();
+        }
+        self.distance_batch_iter(query, candidates.chunks_exact(dimension))
+    }
+
+    fn distance_batch_iter<'a>(
+        &self,
+        query: &[f32],
+        candidates: impl Iterator<Item = &'a [f32]>,
+    ) -> Vec<f32> {
+        let score = self.scorer(query);
+        candidates.map(score).collect()
+    }
+
+    /// Returns a function computing the distance from `query` to a candidate, with the work
+    /// that only depends on the query (such as its norm for cosine) done once up front.
+    pub(crate) fn scorer<'q>(&self, query: &'q [f32]) -> impl Fn(&[f32]) -> f32 + 'q {
+        let distance_function = self.clone();
+        let kernels = DistanceKernels::get();
+        let query_norm_squared = match self {
+            DistanceFunction::Cosine => Some(crate::distance::distance_scalar::norm_squared(query)),
+            _ => None,
+        };
+        move |candidate: &[f32]| match query_norm_squared {
+            // Safety: the kernels were selected for this CPU and the lengths match.
+            Some(query_norm_squared) if candidate.len() == query.len() => unsafe {
+                (kernels.cosine_with_norm)(query, candidate, query_norm_squared)
+            },
+            _ => distance_function.distance(query, candidate),
+        }
+    }
+}
+
+/// A distance between embeddings that collections can be queried with.
+/// # Description
+/// The built-in `DistanceFunction`s implement this trait, and other metrics can be made
+/// available to queries by implementing it and adding them to a `DistanceRegistry`.
+/// Collections refer to a distance by its name.
+/// # Methods
+/// - `name` - The name collections use to refer to this distance.
+/// - `distance` - Computes the distance between two embeddings. Smaller means closer.
+/// - `nearest_neighbors` - Finds the `k` candidates closest to a query. The default
+/// implementation scores every candidate with `distance`.
+/// - `nearest_neighbors_batch` - The same for several queries against the same candidates.
+/// The default implementation calls `nearest_neighbors` once per query.
+/// - `distance_slice` and `nearest_neighbors_slices` - The same for embeddings in any
+/// representation. The default implementations dequantize them to f32s first.
+pub trait Distance: std::fmt::Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    fn distance(&self, a: &[f32], b: &[f32]) -> f32;
+
+    /// Returns up to `k` `(index, distance)` pairs, where `index` is the position of the
+    /// candidate in `candidates`, sorted like `DistanceFunction::top_k`.
+    fn nearest_neighbors(&self, query: &[f32], candidates: &[&[f32]], k: usize) -> Vec<(u32, f32)> {
+        top_k_by(
+            candidates
+                .iter()
+                .enumerate()
+                .map(|(i, candidate)| (i as u32, self.distance(query, candidate))),
+            k,
+        )
+    }
+
+    /// Returns what `nearest_neighbors` returns for each of `queries`, given with how many
+    /// to find for it, in the order of `queries`.
+    fn nearest_neighbors_batch(
+        &self,
+        queries: &[(&[f32], usize)],
+        candidates: &[&[f32]],
+    ) -> Vec<Vec<(u32, f32)>> {
+        queries
+            .iter()
+            .map(|(query, k)| self.nearest_neighbors(query, candidates, *k))
+            .collect()
+    }
+
+    fn distance_slice(&self, a: &[f32], b: VectorSlice) -> f32 {
+        self.distance(a, &b.to_f32())
+    }
+
+    /// Like `nearest_neighbors`, for candidates in any representation.
+    fn nearest_neighbors_slices(
+        &self,
+        query: &[f32],
+        candidates: &[VectorSlice],
+        k: usize,
+    ) -> Vec<(u32, f32)> {
+        top_k_by(
+            candidates
+                .iter()
+                .enumerate()
+                .map(|(i, candidate)| (i as u32, self.distance_slice(query, *candidate))),
+            k,
+        )
+    }
+}
+
+impl Distance for DistanceFunction {
+    fn name(&self) -> &str {
+        match self {
+            DistanceFunction::Euclidean => "l2",
+            DistanceFunction::Cosine => "cosine",
+            DistanceFunction::InnerProduct => "ip",
+            DistanceFunction::Manhattan => "l1",
+            DistanceFunction::Hamming => "hamming",
+            DistanceFunction::Jaccard => "jaccard",
+        }
+    }
+
+    fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        DistanceFunction::distance(self, a, b)
+    }
+
+    fn nearest_neighbors(&self, query: &[f32], candidates: &[&[f32]], k: usize) -> Vec<(u32, f32)> {
+        self.nearest_neighbors_batch(&[(query, k)], candidates)
+            .remove(0)
+    }
+
+    fn nearest_neighbors_batch(
+        &self,
+        queries: &[(&[f32], usize)],
+        candidates: &[&[f32]],
+    ) -> Vec<Vec<(u32, f32)>> {
+        let candidates = || {
+            candidates
+                .iter()
+                .enumerate()
+                .map(|(i, candidate)| (i as u32, *candidate))
+        };
+        match self {
+            // Binarized embeddings are packed so that they can be compared with popcount.
+            // They are packed once for all of the queries.
+            DistanceFunction::Hamming => {
+                let packed: Vec<(u32, Vec<u64>)> = candidates()
+                    .map(|(i, candidate)| (i, pack_bits(candidate)))
+                    .collect();
+                queries
+                    .iter()
+                    .map(|(query, k)| {
+                        top_k_hamming(
+                            &pack_bits(query),
+                            packed
+                                .iter()
+                                .map(|(i, candidate)| (*i, candidate.as_slice())),
+                            *k,
+                        )
+                    })
+                    .collect()
             }
-            DistanceFunction::InnerProduct => {
-                #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
-                {
-                    if std::arch::is_aarch64_feature_detected!("neon") {
-                        return unsafe { crate::distance::distance_neon::inner_product(a, b) };
-                    }
-                }
-                #[cfg(all(
-                    any(target_arch = "x86_64", target_arch = "x86"),
-                    target_feature = "sse"
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("sse") {
-                        return unsafe { crate::distance::distance_sse::inner_product(a, b) };
-                    }
-                }
-                #[cfg(all(
-                    target_arch = "x86_64",
-                    all(target_feature = "avx", target_feature = "fma")
-                ))]
-                {
-                    if std::arch::is_x86_feature_detected!("avx")
-                        && std::arch::is_x86_feature_detected!("fma")
-                    {
-                        return unsafe { crate::distance::distance_avx::inner_product(a, b) };
-                    }
-                }
-                let mut sum = 0.0;
-                for i in 0..a.len() {
-                    sum += a[i] * b[i];
-                }
-                1.0_f32 - sum
+            _ => queries
+                .iter()
+                .map(|(query, k)| self.top_k(query, candidates(), *k))
+                .collect(),
+        }
+    }
+
+    fn distance_slice(&self, a: &[f32], b: VectorSlice) -> f32 {
+        self.distance_slices(VectorSlice::F32(a), b)
+    }
+
+    fn nearest_neighbors_slices(
+        &self,
+        query: &[f32],
+        candidates: &[VectorSlice],
+        k: usize,
+    ) -> Vec<(u32, f32)> {
+        // Float32 candidates can use the batch kernels as they are, anything else is
+        // dequantized one element at a time by `distance_slices`.
+        match VectorSlice::all_f32(candidates) {
+            Some(f32_candidates) => Distance::nearest_neighbors(self, query, &f32_candidates, k),
+            None => top_k_by(
+                candidates.iter().enumerate().map(|(i, candidate)| {
+                    (
+                        i as u32,
+                        self.distance_slices(VectorSlice::F32(query), *candidate),
+                    )
+                }),
+                k,
+            ),
+        }
+    }
+}
+
+// Matches the epsilon hnswlib uses, so vectors normalized here and by the index agree.
+const NORMALIZE_EPS: f32 = 1e-30;
+
+/// Scales `vector` to unit length in place.
+/// # Notes
+/// The zero vector is left as is rather than being turned into NaNs.
+pub fn normalize(vector: &mut [f32]) {
+    let norm = crate::distance::distance_scalar::norm_squared(vector).sqrt();
+    let scale = 1.0 / (norm + NORMALIZE_EPS);
+    for x in vector.iter_mut() {
+        *x *= scale;
+    }
+}
+
+type DistanceKernel = unsafe fn(&[f32], &[f32]) -> f32;
+type CosineWithNormKernel = unsafe fn(&[f32], &[f32], f32) -> f32;
+
+/// The set of distance kernels used on this machine.
+/// # Description
+/// Which SIMD instruction sets are available is detected at runtime the first time a
+/// distance is computed, and the chosen kernels are cached for the lifetime of the process
+/// so the feature checks are not repeated on every call.
+/// # Notes
+/// - On x86 avx2 + fma is preferred over sse4.1, and on aarch64 neon is used. The scalar
+/// kernels are the fallback everywhere else.
+/// - All kernels assume their inputs have the same length.
+pub(crate) struct DistanceKernels {
+    pub(crate) name: &'static str,
+    pub(crate) euclidean: DistanceKernel,
+    pub(crate) cosine: DistanceKernel,
+    pub(crate) cosine_with_norm: CosineWithNormKernel,
+    pub(crate) inner_product: DistanceKernel,
+}
+
+impl DistanceKernels {
+    pub(crate) fn get() -> &'static DistanceKernels {
+        static KERNELS: std::sync::OnceLock<DistanceKernels> = std::sync::OnceLock::new();
+        KERNELS.get_or_init(|| {
+            let kernels = DistanceKernels::detect();
+            tracing::info!("Using {} distance kernels", kernels.name);
+            kernels
+        })
+    }
+
+    fn detect() -> DistanceKernels {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if std::arch::is_x86_feature_detected!("avx2")
+                && std::arch::is_x86_feature_detected!("fma")
+            {
+                return DistanceKernels {
+                    name: "avx2",
+                    euclidean: crate::distance::distance_avx::euclidean_distance,
+                    cosine: crate::distance::distance_avx::cosine_distance,
+                    cosine_with_norm: crate::distance::distance_avx::cosine_distance_with_norm,
+                    inner_product: crate::distance::distance_avx::inner_product,
+                };
             }
         }
+        #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+        {
+            if std::arch::is_x86_feature_detected!("sse4.1") {
+                return DistanceKernels {
+                    name: "sse4.1",
+                    euclidean: crate::distance::distance_sse::euclidean_distance,
+                    cosine: crate::distance::distance_sse::cosine_distance,
+                    cosine_with_norm: crate::distance::distance_sse::cosine_distance_with_norm,
+                    inner_product: crate::distance::distance_sse::inner_product,
+                };
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                return DistanceKernels {
+                    name: "neon",
+                    euclidean: crate::distance::distance_neon::euclidean_distance,
+                    cosine: crate::distance::distance_neon::cosine_distance,
+                    cosine_with_norm: crate::distance::distance_neon::cosine_distance_with_norm,
+                    inner_product: crate::distance::distance_neon::inner_product,
+                };
+            }
+        }
+        DistanceKernels::scalar()
+    }
+
+    pub(crate) fn scalar() -> DistanceKernels {
+        DistanceKernels {
+            name: "scalar",
+            euclidean: crate::distance::distance_scalar::euclidean_distance,
+            cosine: crate::distance::distance_scalar::cosine_distance,
+            cosine_with_norm: crate::distance::distance_scalar::cosine_distance_with_norm,
+            inner_product: crate::distance::distance_scalar::inner_product,
+        }
     }
 }
 
@@ -334,12 +664,21 @@ impl DistanceFunction {
 pub enum DistanceFunctionError {
     #[error("Invalid distance function `{0}`")]
     InvalidDistanceFunction(String),
+    #[error("Dimension mismatch: expected {expected}, got {got}")]
+    DimensionMismatch { expected: usize, got: usize },
+    #[error("Distance function `{0}` is not supported by the HNSW index")]
+    UnsupportedByHnsw(DistanceFunction),
+    #[error("Distance function `{0}` is already registered")]
+    AlreadyRegistered(String),
 }
 
 impl ChromaError for DistanceFunctionError {
     fn code(&self) -> ErrorCodes {
         match self {
             DistanceFunctionError::InvalidDistanceFunction(_) => ErrorCodes::InvalidArgument,
+            DistanceFunctionError::DimensionMismatch { .. } => ErrorCodes::InvalidArgument,
+            DistanceFunctionError::UnsupportedByHnsw(_) => ErrorCodes::InvalidArgument,
+            DistanceFunctionError::AlreadyRegistered(_) => ErrorCodes::AlreadyExists,
         }
     }
 }
@@ -352,6 +691,9 @@ impl TryFrom<&str> for DistanceFunction {
             "l2" => Ok(DistanceFunction::Euclidean),
             "cosine" => Ok(DistanceFunction::Cosine),
             "ip" => Ok(DistanceFunction::InnerProduct),
+            "l1" => Ok(DistanceFunction::Manhattan),
+            "hamming" => Ok(DistanceFunction::Hamming),
+            "jaccard" => Ok(DistanceFunction::Jaccard),
             _ => Err(DistanceFunctionError::InvalidDistanceFunction(
                 value.to_string(),
             )),
@@ -359,19 +701,46 @@ impl TryFrom<&str> for DistanceFunction {
     }
 }
 
+impl FromStr for DistanceFunction {
+    type Err = DistanceFunctionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DistanceFunction::try_from(s)
+    }
+}
+
+impl Display for DistanceFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 impl Into<String> for DistanceFunction {
     fn into(self) -> String {
-        match self {
-            DistanceFunction::Euclidean => "l2".to_string(),
-            DistanceFunction::Cosine => "cosine".to_string(),
-            DistanceFunction::InnerProduct => "ip".to_string(),
-        }
+        self.to_string()
+    }
+}
+
+// Distance functions are (de)serialized as their canonical names, the same strings that
+// are stored in collection metadata.
+impl Serialize for DistanceFunction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DistanceFunction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        DistanceFunction::try_from(name.as_str()).map_err(serde::de::Error::custom)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+    use proptest::test_runner::Config;
 
     #[test]
     fn test_distance_function_try_from() {
@@ -381,6 +750,12 @@ mod tests {
         assert_eq!(distance_function, DistanceFunction::Cosine);
         let distance_function: DistanceFunction = "ip".try_into().unwrap();
         assert_eq!(distance_function, DistanceFunction::InnerProduct);
+        let distance_function: DistanceFunction = "l1".try_into().unwrap();
+        assert_eq!(distance_function, DistanceFunction::Manhattan);
+        let distance_function: DistanceFunction = "hamming".try_into().unwrap();
+        assert_eq!(distance_function, DistanceFunction::Hamming);
+        let distance_function: DistanceFunction = "jaccard".try_into().unwrap();
+        assert_eq!(distance_function, DistanceFunction::Jaccard);
     }
 
     #[test]
@@ -391,6 +766,167 @@ mod tests {
         assert_eq!(distance_function, "cosine");
         let distance_function: String = DistanceFunction::InnerProduct.into();
         assert_eq!(distance_function, "ip");
+        let distance_function: String = DistanceFunction::Manhattan.into();
+        assert_eq!(distance_function, "l1");
+        let distance_function: String = DistanceFunction::Hamming.into();
+        assert_eq!(distance_function, "hamming");
+        let distance_function: String = DistanceFunction::Jaccard.into();
+        assert_eq!(distance_function, "jaccard");
+    }
+
+    #[test]
+    fn test_distance_function_from_str_and_display() {
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+            DistanceFunction::Manhattan,
+            DistanceFunction::Hamming,
+            DistanceFunction::Jaccard,
+        ] {
+            let name = distance_function.to_string();
+            assert_eq!(name.parse::<DistanceFunction>().unwrap(), distance_function);
+        }
+        match "manhattan".parse::<DistanceFunction>() {
+            Err(DistanceFunctionError::InvalidDistanceFunction(name)) => {
+                assert_eq!(name, "manhattan")
+            }
+            other => panic!("Expected an invalid distance function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_distance_function_serde_round_trip() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Config {
+            space: DistanceFunction,
+        }
+
+        for (distance_function, name) in [
+            (DistanceFunction::Euclidean, "l2"),
+            (DistanceFunction::Cosine, "cosine"),
+            (DistanceFunction::InnerProduct, "ip"),
+            (DistanceFunction::Manhattan, "l1"),
+            (DistanceFunction::Hamming, "hamming"),
+            (DistanceFunction::Jaccard, "jaccard"),
+        ] {
+            let json = serde_json::to_string(&distance_function).unwrap();
+            assert_eq!(json, format!("\"{}\"", name));
+            let parsed: DistanceFunction = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, distance_function);
+
+            let config = Config {
+                space: distance_function,
+            };
+            let json = serde_json::to_string(&config).unwrap();
+            assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn test_distance_function_deserialize_unknown() {
+        let err = serde_json::from_str::<DistanceFunction>("\"manhattan\"").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Invalid distance function `manhattan`"));
+        assert!(serde_json::from_str::<DistanceFunction>("1").is_err());
+    }
+
+    #[test]
+    fn test_distance_function_manhattan() {
+        let distance_function = DistanceFunction::Manhattan;
+        assert_eq!(
+            distance_function.distance(&[1.0, 2.0, 3.0], &[4.0, 0.0, 3.5]),
+            5.5
+        );
+        assert_eq!(distance_function.distance(&[1.0, -1.0], &[1.0, -1.0]), 0.0);
+        assert_eq!(
+            distance_function.distance_batch(&[0.0, 0.0], &[&[1.0, -1.0], &[-2.0, 0.5]]),
+            vec![2.0, 2.5]
+        );
+        assert!(!distance_function.supported_by_hnsw());
+        assert!(DistanceFunction::Euclidean.supported_by_hnsw());
+
+        let err = DistanceFunctionError::UnsupportedByHnsw(distance_function);
+        assert_eq!(err.code(), ErrorCodes::InvalidArgument);
+        assert_eq!(
+            err.to_string(),
+            "Distance function `l1` is not supported by the HNSW index"
+        );
+    }
+
+    #[test]
+    fn test_distance_function_hamming() {
+        let distance_function = DistanceFunction::Hamming;
+        assert_eq!(
+            distance_function.distance(&[1.0, -1.0, 0.0, 0.5], &[1.0, 1.0, 0.5, -0.5]),
+            3.0
+        );
+        // Only the sign matters, and zero counts as an unset bit.
+        assert_eq!(distance_function.distance(&[0.1, 0.0], &[7.0, -3.0]), 0.0);
+        assert!(!distance_function.supported_by_hnsw());
+        assert!(!distance_function.normalizes_embeddings());
+    }
+
+    #[test]
+    fn test_distance_function_jaccard() {
+        let distance_function = DistanceFunction::Jaccard;
+        // (1 + 0.5) / (2 + 3 + 1), with non-positive elements absent.
+        assert_eq!(
+            distance_function.distance(&[2.0, 0.5, 1.0, -1.0], &[1.0, 3.0, 0.0, -2.0]),
+            0.75
+        );
+        assert_eq!(distance_function.distance(&[1.0, 0.0], &[0.0, 1.0]), 1.0);
+        assert_eq!(distance_function.distance(&[0.0, -1.0], &[0.0, 0.0]), 0.0);
+        assert!(!distance_function.supported_by_hnsw());
+        assert_eq!(distance_function.distance_to_similarity(0.25), 0.75);
+    }
+
+    #[test]
+    fn test_raw_to_distance() {
+        assert_eq!(DistanceFunction::Euclidean.raw_to_distance(-1e-7), 0.0);
+        assert_eq!(DistanceFunction::Euclidean.raw_to_distance(2.5), 2.5);
+        assert_eq!(DistanceFunction::Cosine.raw_to_distance(-1e-7), 0.0);
+        assert_eq!(DistanceFunction::Cosine.raw_to_distance(2.0000002), 2.0);
+        assert_eq!(DistanceFunction::Cosine.raw_to_distance(0.25), 0.25);
+        // Inner product distances are legitimately negative.
+        assert_eq!(DistanceFunction::InnerProduct.raw_to_distance(-3.5), -3.5);
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+        ] {
+            assert_eq!(distance_function.raw_to_distance(f32::NAN), f32::INFINITY);
+        }
+    }
+
+    #[test]
+    fn test_distance_to_similarity() {
+        let a = [0.6, 0.8];
+        let b = [0.8, 0.6];
+        let cosine = DistanceFunction::Cosine;
+        assert!((cosine.distance_to_similarity(cosine.distance(&a, &b)) - 0.96).abs() < 1e-6);
+        let ip = DistanceFunction::InnerProduct;
+        assert!(
+            (ip.distance_to_similarity(ip.distance(&[2.0, 0.0], &[3.0, 1.0])) - 6.0).abs() < 1e-6
+        );
+        let l2 = DistanceFunction::Euclidean;
+        assert_eq!(l2.distance_to_similarity(0.0), 1.0);
+        assert_eq!(l2.distance_to_similarity(3.0), 0.25);
+        // Similarity decreases as distance increases for every distance function.
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+            DistanceFunction::Manhattan,
+            DistanceFunction::Hamming,
+            DistanceFunction::Jaccard,
+        ] {
+            assert!(
+                distance_function.distance_to_similarity(0.5)
+                    > distance_function.distance_to_similarity(1.5)
+            );
+        }
     }
 
     #[test]
@@ -418,4 +954,515 @@ mod tests {
             inner_product_sim
         );
     }
+
+    #[test]
+    fn test_distance_function_cosine() {
+        let distance_function: DistanceFunction = "cosine".try_into().unwrap();
+
+        // Orthogonal, identical and opposite vectors.
+        assert!((distance_function.distance(&[1.0, 0.0], &[0.0, 1.0]) - 1.0).abs() < 1e-6);
+        assert!(distance_function.distance(&[1.0, 2.0], &[1.0, 2.0]).abs() < 1e-6);
+        assert!((distance_function.distance(&[1.0, 1.0], &[-1.0, -1.0]) - 2.0).abs() < 1e-6);
+
+        // Scaling either input should not change the distance.
+        let a = vec![1.0, 2.0, 3.0];
+        let b = vec![4.0, 5.0, 6.0];
+        let expected = 1.0 - 32.0 / (14.0_f32.sqrt() * 77.0_f32.sqrt());
+        assert!((distance_function.distance(&a, &b) - expected).abs() < 1e-6);
+        let b_scaled: Vec<f32> = b.iter().map(|x| x * 10.0).collect();
+        assert!((distance_function.distance(&a, &b_scaled) - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_distance_function_cosine_matches_python() {
+        // Expected values computed with chromadb.utils.distance_functions.cosine
+        let cases: Vec<(Vec<f32>, Vec<f32>, f32)> = vec![
+            (
+                vec![1.0, 2.0, 3.0],
+                vec![4.0, 5.0, 6.0],
+                0.025368153802923787,
+            ),
+            (
+                vec![0.5, -0.25, 2.0],
+                vec![-1.0, 3.0, 0.1],
+                1.159811381677247,
+            ),
+            (
+                vec![3.0, -4.0, 0.0, 1.0],
+                vec![-2.0, 1.0, 5.0, 0.5],
+                1.338746051602318,
+            ),
+        ];
+        let distance_function = DistanceFunction::Cosine;
+        for (a, b, expected) in cases {
+            let distance = distance_function.distance(&a, &b);
+            assert!(
+                (distance - expected).abs() < 1e-6,
+                "{:?} vs {:?}: {} != {}",
+                a,
+                b,
+                distance,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_distance_function_cosine_normalized_inputs() {
+        let a = vec![1.0, 2.0, 3.0];
+        let a_mag = (1.0_f32.powi(2) + 2.0_f32.powi(2) + 3.0_f32.powi(2)).sqrt();
+        let a_norm: Vec<f32> = a.iter().map(|x| x / a_mag).collect();
+        let b = vec![4.0, 5.0, 6.0];
+        let b_mag = (4.0_f32.powi(2) + 5.0_f32.powi(2) + 6.0_f32.powi(2)).sqrt();
+        let b_norm: Vec<f32> = b.iter().map(|x| x / b_mag).collect();
+
+        let distance_function = DistanceFunction::Cosine;
+        let one_minus_dot = 1.0_f32
+            - a_norm
+                .iter()
+                .zip(b_norm.iter())
+                .map(|(a, b)| a * b)
+                .sum::<f32>();
+        // Normalized inputs reduce to 1 - dot, and mixing normalized and
+        // unnormalized inputs gives the same answer.
+        assert!((distance_function.distance(&a_norm, &b_norm) - one_minus_dot).abs() < 1e-6);
+        assert!((distance_function.distance(&a_norm, &b) - one_minus_dot).abs() < 1e-6);
+        assert!((distance_function.distance(&a, &b_norm) - one_minus_dot).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_distance_function_cosine_zero_vector() {
+        let distance_function = DistanceFunction::Cosine;
+        let zero = vec![0.0, 0.0, 0.0];
+        let a = vec![1.0, 2.0, 3.0];
+        assert_eq!(distance_function.distance(&zero, &a), 1.0);
+        assert_eq!(distance_function.distance(&a, &zero), 1.0);
+        assert_eq!(distance_function.distance(&zero, &zero), 1.0);
+    }
+
+    #[test]
+    fn test_checked_distance_dimension_mismatch() {
+        let a = vec![1.0, 2.0, 3.0];
+        let b = vec![1.0, 2.0, 3.0, 4.0];
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+        ] {
+            match distance_function.checked_distance(&a, &b) {
+                Err(DistanceFunctionError::DimensionMismatch { expected, got }) => {
+                    assert_eq!(expected, 3);
+                    assert_eq!(got, 4);
+                }
+                other => panic!("Expected a dimension mismatch, got {:?}", other),
+            }
+            match distance_function.checked_distance(&b, &a) {
+                Err(e) => {
+                    assert_eq!(e.code(), ErrorCodes::InvalidArgument);
+                    assert_eq!(e.to_string(), "Dimension mismatch: expected 4, got 3");
+                }
+                Ok(d) => panic!("Expected a dimension mismatch, got distance {}", d),
+            }
+            assert!(distance_function.checked_distance(&a, &[]).is_err());
+            assert_eq!(
+                distance_function.checked_distance(&a, &a).unwrap(),
+                distance_function.distance(&a, &a)
+            );
+        }
+    }
+
+    #[test]
+    fn test_distance_batch_matches_pairwise() {
+        let dimension = 37;
+        let query = random_vector(dimension);
+        let candidates: Vec<Vec<f32>> = (0..50).map(|_| random_vector(dimension)).collect();
+        let candidate_refs: Vec<&[f32]> = candidates.iter().map(|c| c.as_slice()).collect();
+        let matrix: Vec<f32> = candidates.iter().flatten().copied().collect();
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+        ] {
+            let batch = distance_function.distance_batch(&query, &candidate_refs);
+            let contiguous =
+                distance_function.distance_batch_contiguous(&query, &matrix, dimension);
+            assert_eq!(batch.len(), candidates.len());
+            assert_eq!(batch, contiguous);
+            for (candidate, distance) in candidates.iter().zip(batch.iter()) {
+                let expected = distance_function.distance(&query, candidate);
+                assert!(
+                    (distance - expected).abs() <= 1e-6 * (1.0 + expected.abs()),
+                    "{:?}: {} != {}",
+                    distance_function,
+                    distance,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_distance_batch_edge_cases() {
+        let query = vec![1.0, 2.0, 3.0];
+        let distance_function = DistanceFunction::Cosine;
+        assert!(distance_function.distance_batch(&query, &[]).is_empty());
+        assert!(distance_function
+            .distance_batch_contiguous(&query, &[1.0, 2.0, 3.0], 0)
+            .is_empty());
+        // A trailing partial row is ignored.
+        assert_eq!(
+            distance_function
+                .distance_batch_contiguous(&query, &[1.0, 2.0, 3.0, 4.0], 3)
+                .len(),
+            1
+        );
+        // The zero vector is orthogonal to everything, on either side.
+        let zero = vec![0.0, 0.0, 0.0];
+        assert_eq!(
+            distance_function.distance_batch(&zero, &[&query]),
+            vec![1.0]
+        );
+        assert_eq!(
+            distance_function.distance_batch(&query, &[&zero]),
+            vec![1.0]
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut a = vec![3.0, 4.0];
+        normalize(&mut a);
+        assert_eq!(a, vec![0.6, 0.8]);
+
+        let mut zero = vec![0.0, 0.0, 0.0];
+        normalize(&mut zero);
+        assert_eq!(zero, vec![0.0, 0.0, 0.0]);
+
+        let mut tiny = vec![1e-20, 0.0];
+        normalize(&mut tiny);
+        assert!(tiny.iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn test_distance_normalized_matches_distance_for_unit_vectors() {
+        for n in [1, 3, 16, 100, 1536] {
+            let mut a = random_vector(n);
+            let mut b = random_vector(n);
+            normalize(&mut a);
+            normalize(&mut b);
+            for distance_function in [
+                DistanceFunction::Euclidean,
+                DistanceFunction::Cosine,
+                DistanceFunction::InnerProduct,
+            ] {
+                let expected = distance_function.distance(&a, &b);
+                let actual = distance_function.distance_normalized(&a, &b);
+                assert!(
+                    (actual - expected).abs() < 1e-5,
+                    "{:?} for n = {}: {} != {}",
+                    distance_function,
+                    n,
+                    actual,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_distance_normalized_zero_vector() {
+        let mut zero = vec![0.0, 0.0, 0.0];
+        let mut a = vec![1.0, 2.0, 3.0];
+        normalize(&mut zero);
+        normalize(&mut a);
+        let distance_function = DistanceFunction::Cosine;
+        assert_eq!(distance_function.distance_normalized(&zero, &a), 1.0);
+        assert_eq!(distance_function.distance_normalized(&zero, &zero), 1.0);
+        assert_eq!(
+            distance_function.distance_normalized(&zero, &a),
+            distance_function.distance(&zero, &a)
+        );
+    }
+
+    #[test]
+    fn test_checked_distance_empty_vectors() {
+        let empty: Vec<f32> = vec![];
+        assert_eq!(
+            DistanceFunction::Euclidean
+                .checked_distance(&empty, &empty)
+                .unwrap(),
+            0.0
+        );
+        // Empty vectors behave like the zero vector for cosine.
+        assert_eq!(
+            DistanceFunction::Cosine
+                .checked_distance(&empty, &empty)
+                .unwrap(),
+            1.0
+        );
+        assert_eq!(
+            DistanceFunction::InnerProduct
+                .checked_distance(&empty, &empty)
+                .unwrap(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_distance_function_inner_product() {
+        let distance_function: DistanceFunction = "ip".try_into().unwrap();
+        // Positive dot product: 1 - 11
+        assert_eq!(distance_function.distance(&[1.0, 2.0], &[3.0, 4.0]), -10.0);
+        // Negative dot product: 1 - (-5)
+        assert_eq!(distance_function.distance(&[1.0, -2.0], &[3.0, 4.0]), 6.0);
+        // Orthogonal vectors
+        assert_eq!(distance_function.distance(&[1.0, 0.0], &[0.0, 1.0]), 1.0);
+    }
+
+    #[test]
+    fn test_distance_function_inner_product_ordering() {
+        // hnswlib's ip space ranks by 1 - dot, so a larger dot product must always
+        // produce a smaller distance, regardless of sign.
+        let query = vec![0.5, -1.0, 2.0];
+        let candidates = vec![
+            vec![1.0, 1.0, 1.0],
+            vec![-1.0, 2.0, -3.0],
+            vec![0.0, 0.0, 4.0],
+            vec![2.0, -2.0, 0.0],
+            vec![0.0, 0.0, 0.0],
+        ];
+        let distance_function = DistanceFunction::InnerProduct;
+        let dot = |v: &Vec<f32>| v.iter().zip(query.iter()).map(|(a, b)| a * b).sum::<f32>();
+
+        let mut by_distance: Vec<usize> = (0..candidates.len()).collect();
+        by_distance.sort_by(|a, b| {
+            distance_function
+                .distance(&query, &candidates[*a])
+                .partial_cmp(&distance_function.distance(&query, &candidates[*b]))
+                .unwrap()
+        });
+        let mut by_dot: Vec<usize> = (0..candidates.len()).collect();
+        by_dot.sort_by(|a, b| {
+            dot(&candidates[*b])
+                .partial_cmp(&dot(&candidates[*a]))
+                .unwrap()
+        });
+
+        assert_eq!(by_distance, by_dot);
+        assert_eq!(by_distance, vec![2, 3, 0, 4, 1]);
+    }
+
+    #[test]
+    fn test_distance_function_l2sqr_known_values() {
+        let distance_function = DistanceFunction::Euclidean;
+        assert_eq!(distance_function.distance(&[0.0, 0.0], &[3.0, 4.0]), 25.0);
+        assert_eq!(distance_function.distance(&[1.0, -1.0], &[-1.0, 1.0]), 8.0);
+        assert_eq!(distance_function.distance(&[2.5], &[2.5]), 0.0);
+
+        // Lengths that are not a multiple of the SIMD width exercise the tail handling.
+        for n in [1, 7, 15, 16, 17, 33, 100] {
+            let a: Vec<f32> = (0..n).map(|i| i as f32).collect();
+            let b: Vec<f32> = (0..n).map(|i| i as f32 + 2.0).collect();
+            // Every component differs by 2, so the squared distance is 4 * n
+            assert_eq!(distance_function.distance(&a, &b), 4.0 * n as f32);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(Config::with_cases(64))]
+        #[test]
+        fn test_l2sqr_is_consistent_with_euclidean_ordering(
+            query in proptest::collection::vec(-100.0f32..100.0, 24),
+            candidates in proptest::collection::vec(proptest::collection::vec(-100.0f32..100.0, 24), 2..20),
+        ) {
+            let distance_function = DistanceFunction::Euclidean;
+            let exact = |v: &Vec<f32>| {
+                v.iter()
+                    .zip(query.iter())
+                    .map(|(a, b)| (*a as f64 - *b as f64).powi(2))
+                    .sum::<f64>()
+            };
+
+            for candidate in candidates.iter() {
+                let distance = distance_function.distance(&query, candidate) as f64;
+                let expected = exact(candidate);
+                prop_assert!((distance - expected).abs() <= expected * 1e-5 + 1e-3);
+            }
+
+            // The square root of the returned distance is a metric, so the triangle
+            // inequality must hold for it.
+            for pair in candidates.windows(2) {
+                let ab = distance_function.distance(&query, &pair[0]).sqrt();
+                let bc = distance_function.distance(&pair[0], &pair[1]).sqrt();
+                let ac = distance_function.distance(&query, &pair[1]).sqrt();
+                prop_assert!(ac <= (ab + bc) * (1.0 + 1e-5) + 1e-3);
+            }
+        }
+    }
+
+    fn random_vector(n: usize) -> Vec<f32> {
+        (0..n).map(|_| rand::random::<f32>() * 2.0 - 1.0).collect()
+    }
+
+    // Lengths that are not multiples of the SIMD widths exercise the tail handling.
+    const KERNEL_TEST_LENGTHS: [usize; 12] = [0, 1, 3, 7, 9, 15, 17, 31, 33, 100, 257, 1536];
+
+    // The distances between `a` and `b` computed in f64, each with the sum of the magnitudes
+    // of the terms it adds up. Rounding errors are bounded relative to the latter, which stays
+    // meaningful when the terms cancel out.
+    struct ExactDistances {
+        euclidean: (f64, f64),
+        cosine: (f64, f64),
+        inner_product: (f64, f64),
+    }
+
+    fn exact_distances(a: &[f32], b: &[f32]) -> ExactDistances {
+        let (mut squared, mut dot, mut dot_magnitude) = (0.0_f64, 0.0_f64, 0.0_f64);
+        let (mut norm_a, mut norm_b) = (0.0_f64, 0.0_f64);
+        for (x, y) in a.iter().zip(b.iter()) {
+            let (x, y) = (*x as f64, *y as f64);
+            squared += (x - y).powi(2);
+            dot += x * y;
+            dot_magnitude += (x * y).abs();
+            norm_a += x * x;
+            norm_b += y * y;
+        }
+        let norm = norm_a.sqrt() * norm_b.sqrt();
+        let cosine = if norm == 0.0 {
+            (1.0, 0.0)
+        } else {
+            (1.0 - dot / norm, dot_magnitude / norm)
+        };
+        ExactDistances {
+            euclidean: (squared, squared),
+            cosine,
+            inner_product: (1.0 - dot, dot_magnitude),
+        }
+    }
+
+    fn assert_kernels_agree(
+        kernels: &DistanceKernels,
+        reference: &DistanceKernels,
+        lengths: impl IntoIterator<Item = usize>,
+    ) {
+        for n in lengths {
+            let a = random_vector(n);
+            let b = random_vector(n);
+            let norm_a_squared = crate::distance::distance_scalar::norm_squared(&a);
+            let exact = exact_distances(&a, &b);
+            let results = unsafe {
+                [
+                    (
+                        "euclidean",
+                        exact.euclidean,
+                        (kernels.euclidean)(&a, &b),
+                        (reference.euclidean)(&a, &b),
+                    ),
+                    (
+                        "cosine",
+                        exact.cosine,
+                        (kernels.cosine)(&a, &b),
+                        (reference.cosine)(&a, &b),
+                    ),
+                    (
+                        "cosine_with_norm",
+                        exact.cosine,
+                        (kernels.cosine_with_norm)(&a, &b, norm_a_squared),
+                        (reference.cosine_with_norm)(&a, &b, norm_a_squared),
+                    ),
+                    (
+                        "inner_product",
+                        exact.inner_product,
+                        (kernels.inner_product)(&a, &b),
+                        (reference.inner_product)(&a, &b),
+                    ),
+                ]
+            };
+            for (distance, (expected, magnitude), actual, scalar) in results {
+                for (name, actual) in [(kernels.name, actual), (reference.name, scalar)] {
+                    assert!(
+                        (actual as f64 - expected).abs() <= 1e-5 * (1.0 + magnitude),
+                        "{} {} kernel is off for n = {}: {} != {}",
+                        name,
+                        distance,
+                        n,
+                        actual,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_detected_kernels_match_scalar() {
+        assert_kernels_agree(
+            DistanceKernels::get(),
+            &DistanceKernels::scalar(),
+            KERNEL_TEST_LENGTHS,
+        );
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+    #[test]
+    fn test_x86_kernels_match_scalar() {
+        let scalar = DistanceKernels::scalar();
+        #[cfg(target_arch = "x86_64")]
+        if std::arch::is_x86_feature_detected!("avx2") && std::arch::is_x86_feature_detected!("fma")
+        {
+            let avx = DistanceKernels {
+                name: "avx2",
+                euclidean: crate::distance::distance_avx::euclidean_distance,
+                cosine: crate::distance::distance_avx::cosine_distance,
+                cosine_with_norm: crate::distance::distance_avx::cosine_distance_with_norm,
+                inner_product: crate::distance::distance_avx::inner_product,
+            };
+            assert_kernels_agree(&avx, &scalar, KERNEL_TEST_LENGTHS);
+        }
+        if std::arch::is_x86_feature_detected!("sse4.1") {
+            let sse = DistanceKernels {
+                name: "sse4.1",
+                euclidean: crate::distance::distance_sse::euclidean_distance,
+                cosine: crate::distance::distance_sse::cosine_distance,
+                cosine_with_norm: crate::distance::distance_sse::cosine_distance_with_norm,
+                inner_product: crate::distance::distance_sse::inner_product,
+            };
+            assert_kernels_agree(&sse, &scalar, KERNEL_TEST_LENGTHS);
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn test_neon_kernels_match_scalar() {
+        let neon = DistanceKernels {
+            name: "neon",
+            euclidean: crate::distance::distance_neon::euclidean_distance,
+            cosine: crate::distance::distance_neon::cosine_distance,
+            cosine_with_norm: crate::distance::distance_neon::cosine_distance_with_norm,
+            inner_product: crate::distance::distance_neon::inner_product,
+        };
+        assert_kernels_agree(&neon, &DistanceKernels::scalar(), 1..=2048);
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn test_aarch64_dispatch_uses_neon() {
+        // Every aarch64 CPU we deploy on has neon, so falling back to scalar is a bug.
+        assert_eq!(DistanceKernels::get().name, "neon");
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_x86_dispatch_prefers_widest_kernels() {
+        let expected = if std::arch::is_x86_feature_detected!("avx2")
+            && std::arch::is_x86_feature_detected!("fma")
+        {
+            "avx2"
+        } else if std::arch::is_x86_feature_detected!("sse4.1") {
+            "sse4.1"
+        } else {
+            "scalar"
+        };
+        assert_eq!(DistanceKernels::get().name, expected);
+    }
 }
@@ -2,6 +2,7 @@
 // gRPC spec. https://grpc.github.io/grpc/core/md_doc_statuscodes.html
 // Custom errors can use these codes in order to allow for generic handling
 use std::error::Error;
+use tonic::{Code, Status};
 
 #[derive(PartialEq, Debug)]
 pub(crate) enum ErrorCodes {
@@ -41,8 +42,179 @@ pub(crate) enum ErrorCodes {
     DataLoss = 15,
 }
 
+impl ErrorCodes {
+    // Whether an error with this code is transient, so that the same request may succeed
+    // if it is sent again after a short wait.
+    pub(crate) fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorCodes::Unavailable
+                | ErrorCodes::DeadlineExceeded
+                | ErrorCodes::Aborted
+                | ErrorCodes::ResourceExhausted
+        )
+    }
+}
+
 pub(crate) trait ChromaError: Error + Send {
     fn code(&self) -> ErrorCodes;
+
+    // Errors that wrap a remote failure can override this when the code they report to
+    // our own callers does not say whether the underlying failure was transient.
+    fn is_retryable(&self) -> bool {
+        self.code().is_retryable()
+    }
 }
 
 impl Error for Box<dyn ChromaError> {}
+
+impl From<ErrorCodes> for Code {
+    fn from(code: ErrorCodes) -> Self {
+        match code {
+            ErrorCodes::Success => Code::Ok,
+            ErrorCodes::Cancelled => Code::Cancelled,
+            ErrorCodes::UNKNOWN => Code::Unknown,
+            ErrorCodes::InvalidArgument => Code::InvalidArgument,
+            ErrorCodes::DeadlineExceeded => Code::DeadlineExceeded,
+            ErrorCodes::NotFound => Code::NotFound,
+            ErrorCodes::AlreadyExists => Code::AlreadyExists,
+            ErrorCodes::PermissionDenied => Code::PermissionDenied,
+            ErrorCodes::UNAUTHENTICATED => Code::Unauthenticated,
+            ErrorCodes::ResourceExhausted => Code::ResourceExhausted,
+            ErrorCodes::FailedPrecondition => Code::FailedPrecondition,
+            ErrorCodes::Aborted => Code::Aborted,
+            ErrorCodes::OutOfRange => Code::OutOfRange,
+            ErrorCodes::Unimplemented => Code::Unimplemented,
+            ErrorCodes::Internal => Code::Internal,
+            ErrorCodes::Unavailable => Code::Unavailable,
+            ErrorCodes::DataLoss => Code::DataLoss,
+        }
+    }
+}
+
+impl From<Code> for ErrorCodes {
+    fn from(code: Code) -> Self {
+        match code {
+            Code::Ok => ErrorCodes::Success,
+            Code::Cancelled => ErrorCodes::Cancelled,
+            Code::Unknown => ErrorCodes::UNKNOWN,
+            Code::InvalidArgument => ErrorCodes::InvalidArgument,
+            Code::DeadlineExceeded => ErrorCodes::DeadlineExceeded,
+            Code::NotFound => ErrorCodes::NotFound,
+            Code::AlreadyExists => ErrorCodes::AlreadyExists,
+            Code::PermissionDenied => ErrorCodes::PermissionDenied,
+            Code::Unauthenticated => ErrorCodes::UNAUTHENTICATED,
+            Code::ResourceExhausted => ErrorCodes::ResourceExhausted,
+            Code::FailedPrecondition => ErrorCodes::FailedPrecondition,
+            Code::Aborted => ErrorCodes::Aborted,
+            Code::OutOfRange => ErrorCodes::OutOfRange,
+            Code::Unimplemented => ErrorCodes::Unimplemented,
+            Code::Internal => ErrorCodes::Internal,
+            Code::Unavailable => ErrorCodes::Unavailable,
+            Code::DataLoss => ErrorCodes::DataLoss,
+        }
+    }
+}
+
+// Whether a status returned by another service is worth retrying. Errors wrapping a
+// status use this to override `ChromaError::is_retryable`.
+pub(crate) fn is_retryable_status(status: &Status) -> bool {
+    ErrorCodes::from(status.code()).is_retryable()
+}
+
+// Converts an error into the gRPC status with the matching code, carrying the error's
+// message, so that handlers do not have to translate codes by hand.
+pub(crate) fn into_status<E: ChromaError + ?Sized>(err: &E) -> Status {
+    Status::new(err.code().into(), err.to_string())
+}
+
+impl From<Box<dyn ChromaError>> for Status {
+    fn from(err: Box<dyn ChromaError>) -> Self {
+        into_status(err.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use thiserror::Error;
+
+    #[derive(Error, Debug)]
+    #[error("Collection foo not found")]
+    struct CollectionNotFound;
+
+    impl ChromaError for CollectionNotFound {
+        fn code(&self) -> ErrorCodes {
+            ErrorCodes::NotFound
+        }
+    }
+
+    #[test]
+    fn test_into_status() {
+        let status = into_status(&CollectionNotFound);
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "Collection foo not found");
+
+        let err: Box<dyn ChromaError> = Box::new(CollectionNotFound);
+        let status = Status::from(err);
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "Collection foo not found");
+    }
+
+    #[test]
+    fn test_error_codes_into_code() {
+        assert_eq!(
+            Code::from(ErrorCodes::InvalidArgument),
+            Code::InvalidArgument
+        );
+        assert_eq!(Code::from(ErrorCodes::Internal), Code::Internal);
+        assert_eq!(
+            Code::from(ErrorCodes::UNAUTHENTICATED),
+            Code::Unauthenticated
+        );
+        assert_eq!(Code::from(ErrorCodes::DataLoss), Code::DataLoss);
+    }
+
+    #[test]
+    fn test_error_codes_is_retryable() {
+        let retryable = [
+            ErrorCodes::Unavailable,
+            ErrorCodes::DeadlineExceeded,
+            ErrorCodes::Aborted,
+            ErrorCodes::ResourceExhausted,
+        ];
+        for code in retryable {
+            assert!(code.is_retryable(), "{:?} should be retryable", code);
+        }
+        let not_retryable = [
+            ErrorCodes::Success,
+            ErrorCodes::Cancelled,
+            ErrorCodes::UNKNOWN,
+            ErrorCodes::InvalidArgument,
+            ErrorCodes::NotFound,
+            ErrorCodes::AlreadyExists,
+            ErrorCodes::PermissionDenied,
+            ErrorCodes::UNAUTHENTICATED,
+            ErrorCodes::FailedPrecondition,
+            ErrorCodes::OutOfRange,
+            ErrorCodes::Unimplemented,
+            ErrorCodes::Internal,
+            ErrorCodes::DataLoss,
+        ];
+        for code in not_retryable {
+            assert!(!code.is_retryable(), "{:?} should not be retryable", code);
+        }
+        // The default classification follows the error's code.
+        assert!(!CollectionNotFound.is_retryable());
+    }
+
+    #[test]
+    fn test_is_retryable_status() {
+        assert!(is_retryable_status(&Status::unavailable("sysdb is down")));
+        assert!(is_retryable_status(&Status::deadline_exceeded("timed out")));
+        assert!(!is_retryable_status(&Status::not_found(
+            "no such collection"
+        )));
+        assert!(!is_retryable_status(&Status::internal("boom")));
+    }
+}
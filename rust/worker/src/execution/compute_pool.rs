@@ -0,0 +1,43 @@
+use crate::errors::{ChromaError, ErrorCodes};
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("Failed to start the compute pool: {0}")]
+pub(crate) struct ComputePoolError(#[from] rayon::ThreadPoolBuildError);
+
+impl ChromaError for ComputePoolError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::Internal
+    }
+}
+
+/// The threads that operators spread CPU bound work over.
+/// # Description
+/// An operator runs on a single worker thread of the dispatcher. Work that can be split,
+/// like scoring the candidates of a brute force scan, is handed to this pool instead so that
+/// one large query does not leave the other cores idle. Clones share the threads.
+/// # Notes
+/// The calling thread blocks until the work is done, as it would if it did the work itself.
+#[derive(Clone, Debug)]
+pub(crate) struct ComputePool {
+    pool: Arc<rayon::ThreadPool>,
+}
+
+impl ComputePool {
+    pub(crate) fn new(num_threads: usize) -> Result<Self, ComputePoolError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|index| format!("compute-{}", index))
+            .build()?;
+        Ok(ComputePool {
+            pool: Arc::new(pool),
+        })
+    }
+
+    /// Runs `op` in the pool, so that the parallel iterators it uses are run on the threads
+    /// of the pool.
+    pub(crate) fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        self.pool.install(op)
+    }
+}
@@ -1,8 +1,33 @@
+use crate::config::{ConfigValidator, ValidateConfig};
 use serde::Deserialize;
 
+fn default_num_compute_threads() -> usize {
+    num_cpus::get()
+}
+
 #[derive(Deserialize)]
+/// The configuration for the dispatcher and the threads it runs operators on.
+/// # Fields
+/// - num_worker_threads: The number of worker threads operators are run on.
+/// - dispatcher_queue_size: The size of the message queue of the dispatcher.
+/// - worker_queue_size: The size of the message queue of each worker thread.
+/// - num_compute_threads: How many threads of the compute pool operators spread CPU bound
+/// work over, like scoring the candidates of a brute force scan. Defaults to the number of
+/// cores. Only the query service has a compute pool.
+#[serde(deny_unknown_fields)]
 pub(crate) struct DispatcherConfig {
     pub(crate) num_worker_threads: usize,
     pub(crate) dispatcher_queue_size: usize,
     pub(crate) worker_queue_size: usize,
+    #[serde(default = "default_num_compute_threads")]
+    pub(crate) num_compute_threads: usize,
+}
+
+impl ValidateConfig for DispatcherConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.positive("num_worker_threads", self.num_worker_threads);
+        validator.positive("dispatcher_queue_size", self.dispatcher_queue_size);
+        validator.positive("worker_queue_size", self.worker_queue_size);
+        validator.positive("num_compute_threads", self.num_compute_threads);
+    }
 }
@@ -0,0 +1,117 @@
+use crate::errors::{ChromaError, ErrorCodes};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::Instant;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+#[derive(Error, Debug)]
+#[error("Deadline exceeded")]
+pub(crate) struct DeadlineExceededError;
+
+impl ChromaError for DeadlineExceededError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::DeadlineExceeded
+    }
+}
+
+/// When a request has to be answered by.
+/// # Description
+/// A deadline is exceeded once its time has passed, or once it is cancelled because nobody
+/// is waiting for the answer anymore. Clones share the cancellation, so that the stages of
+/// a query and the operators they run can all give up on it.
+/// # Notes
+/// The default deadline is only exceeded when it is cancelled.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Deadline {
+    at: Option<Instant>,
+    cancelled: CancellationToken,
+}
+
+impl Deadline {
+    pub(crate) fn after(timeout: Duration) -> Self {
+        Deadline {
+            at: Instant::now().checked_add(timeout),
+            cancelled: CancellationToken::new(),
+        }
+    }
+
+    pub(crate) fn is_exceeded(&self) -> bool {
+        self.cancelled.is_cancelled() || self.at.is_some_and(|at| Instant::now() >= at)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), DeadlineExceededError> {
+        match self.is_exceeded() {
+            true => Err(DeadlineExceededError),
+            false => Ok(()),
+        }
+    }
+
+    /// The time left until the deadline, which is zero once it is exceeded.
+    pub(crate) fn remaining(&self) -> Duration {
+        if self.cancelled.is_cancelled() {
+            return Duration::ZERO;
+        }
+        match self.at {
+            Some(at) => at.saturating_duration_since(Instant::now()),
+            None => Duration::MAX,
+        }
+    }
+
+    /// Completes once the deadline is exceeded.
+    pub(crate) async fn exceeded(&self) {
+        match self.at {
+            Some(at) => {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(at) => {},
+                    _ = self.cancelled.cancelled() => {},
+                }
+            }
+            None => self.cancelled.cancelled().await,
+        }
+    }
+
+    /// Exceeds the deadline when the returned guard is dropped, such as when the future of a
+    /// request is dropped because its client went away.
+    pub(crate) fn cancel_on_drop(&self) -> DropGuard {
+        self.cancelled.clone().drop_guard()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deadline_is_exceeded_once_its_time_has_passed() {
+        let deadline = Deadline::after(Duration::from_millis(50));
+        assert!(deadline.check().is_ok());
+        assert!(deadline.remaining() <= Duration::from_millis(50));
+        assert!(deadline.remaining() > Duration::ZERO);
+        let start = Instant::now();
+        deadline.exceeded().await;
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert!(deadline.is_exceeded());
+        assert_eq!(deadline.remaining(), Duration::ZERO);
+        assert_eq!(
+            deadline.check().unwrap_err().code(),
+            ErrorCodes::DeadlineExceeded
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dropping_the_guard_exceeds_every_clone() {
+        let deadline = Deadline::default();
+        let clone = deadline.clone();
+        let guard = deadline.cancel_on_drop();
+        assert!(!clone.is_exceeded());
+        assert_eq!(clone.remaining(), Duration::MAX);
+        drop(guard);
+        assert!(clone.is_exceeded());
+        assert_eq!(clone.remaining(), Duration::ZERO);
+        clone.exceeded().await;
+
+        let far = Deadline::after(Duration::MAX);
+        assert!(!far.is_exceeded());
+        assert_eq!(far.remaining(), Duration::MAX);
+    }
+}
@@ -207,8 +207,10 @@ mod tests {
     use super::*;
     use crate::{
         execution::operator::{wrap, Operator, TaskResult},
-        system::System,
+        system::{ChannelError, System},
+        tracing::util::{init_test_tracing, wrap_span_with_parent_context},
     };
+    use opentelemetry::trace::{TraceContextExt, TraceId};
     use std::{
         collections::HashSet,
         sync::{
@@ -216,6 +218,9 @@ mod tests {
             Arc,
         },
     };
+    use tonic::metadata::MetadataMap;
+    use tracing::trace_span;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
 
     // Create a component that will schedule DISPATCH_COUNT invocations of the MockOperator
     // on an interval of DISPATCH_FREQUENCY_MS.
@@ -297,6 +302,78 @@ mod tests {
         }
     }
 
+    // Returns the id of the trace it runs in, after sleeping for its input in milliseconds,
+    // so that the tasks dispatched after it are queued.
+    #[derive(Debug)]
+    struct TraceIdOperator {}
+    #[async_trait]
+    impl Operator<u64, TraceId> for TraceIdOperator {
+        type Error = ();
+        async fn run(&self, sleep_ms: &u64) -> Result<TraceId, Self::Error> {
+            tokio::time::sleep(tokio::time::Duration::from_millis(*sleep_ms)).await;
+            let span = trace_span!("Inside operator");
+            Ok(span.context().span().span_context().trace_id())
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct TaskResults {
+        results: tokio::sync::mpsc::Sender<TaskResult<TraceId, ()>>,
+    }
+    #[async_trait]
+    impl Receiver<TaskResult<TraceId, ()>> for TaskResults {
+        async fn send(
+            &self,
+            message: TaskResult<TraceId, ()>,
+            _tracing_context: Option<Span>,
+        ) -> Result<(), ChannelError> {
+            self.results
+                .send(message)
+                .await
+                .map_err(|_| ChannelError::SendError)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_operators_run_in_the_trace_of_their_request() {
+        init_test_tracing();
+        let system = System::new();
+        // A single worker thread, so that the tasks after the first wait in the queue and
+        // are handed to the worker when it asks for more work.
+        let dispatcher = Dispatcher::new(1, 100, 100);
+        let dispatcher_handle = system.start_component(dispatcher);
+        let (results, mut received) = tokio::sync::mpsc::channel(10);
+        let mut trace_ids = HashSet::new();
+        for _ in 0..3 {
+            let trace_id = Uuid::new_v4().simple().to_string();
+            let mut metadata = MetadataMap::new();
+            metadata.insert(
+                "traceparent",
+                format!("00-{}-b7ad6b7169203331-01", trace_id)
+                    .parse()
+                    .unwrap(),
+            );
+            let request_span = wrap_span_with_parent_context(trace_span!("Request"), &metadata);
+            let task = request_span.in_scope(|| {
+                wrap(
+                    Box::new(TraceIdOperator {}),
+                    50,
+                    Box::new(TaskResults {
+                        results: results.clone(),
+                    }),
+                )
+            });
+            dispatcher_handle.receiver().send(task, None).await.unwrap();
+            trace_ids.insert(TraceId::from_hex(&trace_id).unwrap());
+        }
+        let mut operator_trace_ids = HashSet::new();
+        for _ in 0..3 {
+            let result = received.recv().await.unwrap();
+            operator_trace_ids.insert(result.into_inner().unwrap());
+        }
+        assert_eq!(operator_trace_ids, trace_ids);
+    }
+
     #[tokio::test]
     async fn test_dispatcher() {
         let system = System::new();
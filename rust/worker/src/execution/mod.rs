@@ -1,5 +1,7 @@
+pub(crate) mod compute_pool;
 pub(crate) mod config;
 pub(crate) mod data;
+pub(crate) mod deadline;
 pub(crate) mod dispatcher;
 pub(crate) mod operator;
 mod operators;
@@ -1,6 +1,9 @@
 use crate::system::Receiver;
+use crate::tracing::metrics::metrics;
 use async_trait::async_trait;
 use std::fmt::Debug;
+use std::time::Instant;
+use tracing::{trace_span, Instrument, Span};
 use uuid::Uuid;
 
 /// An operator takes a generic input and returns a generic output.
@@ -15,6 +18,12 @@ where
     // It would have been nice to do this with a default trait for result
     // but that's not stable in rust yet.
     async fn run(&self, input: &I) -> Result<O, Self::Error>;
+
+    /// The name the runs of the operator are recorded under, the name of its type.
+    fn get_name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
 }
 
 /// A task result is a wrapper around the result of a task.
@@ -37,6 +46,8 @@ impl<Output, Error> TaskResult<Output, Error> {
 
 /// A task is a wrapper around an operator and its input.
 /// It is a description of a function to be run.
+/// The span the task was created in is kept with it, so that the operator runs in the
+/// trace of the request that needs it, whichever worker thread picks the task up.
 #[derive(Debug)]
 struct Task<Input, Output, Error>
 where
@@ -47,6 +58,7 @@ where
     input: Input,
     reply_channel: Box<dyn Receiver<TaskResult<Output, Error>>>,
     task_id: Uuid,
+    span: Span,
 }
 
 /// A message type used by the dispatcher to send tasks to worker threads.
@@ -71,7 +83,14 @@ where
     Output: Send + Sync + Debug,
 {
     async fn run(&self) {
-        let result = self.operator.run(&self.input).await;
+        let start = Instant::now();
+        let span = trace_span!(
+            parent: &self.span,
+            "Operator",
+            operator = self.operator.get_name()
+        );
+        let result = self.operator.run(&self.input).instrument(span).await;
+        metrics().record_operator(self.operator.get_name(), start.elapsed());
         let task_result = TaskResult {
             result,
             task_id: self.task_id,
@@ -102,5 +121,6 @@ where
         input,
         reply_channel,
         task_id: id,
+        span: Span::current(),
     })
 }
@@ -1,100 +1,98 @@
 use crate::blockstore::provider::BlockfileProvider;
+use crate::distance::knn::par_top_k_batch_slices;
+use crate::distance::sparse::{par_top_k_jaccard_batch, SparseVector, SparseVectorError};
+use crate::distance::DistanceFunctionError;
+use crate::distance::{Distance, VectorSlice};
 use crate::errors::ChromaError;
 use crate::errors::ErrorCodes;
+use crate::execution::compute_pool::ComputePool;
 use crate::execution::data::data_chunk::Chunk;
-use crate::execution::operators::normalize_vectors::normalize;
+use crate::execution::deadline::{Deadline, DeadlineExceededError};
+use crate::execution::operator::Operator;
 use crate::segment::record_segment::RecordSegmentReader;
 use crate::segment::LogMaterializer;
 use crate::segment::LogMaterializerError;
 use crate::segment::MaterializedLogRecord;
+use crate::tracing::metrics::metrics;
+use crate::types::KnnQuery;
 use crate::types::LogRecord;
+use crate::types::Metadata;
 use crate::types::Operation;
+use crate::types::QueryInclude;
 use crate::types::Segment;
-use crate::{distance::DistanceFunction, execution::operator::Operator};
 use async_trait::async_trait;
-use std::cmp::Ordering;
-use std::collections::BinaryHeap;
+use std::collections::HashSet;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
 use thiserror::Error;
 use tracing::trace;
 
+// How many candidates are scored between two checks of the deadline of the query. A batch
+// is also the unit of work of the compute pool.
+const SCAN_BATCH_SIZE: usize = 1024;
+
 /// The brute force k-nearest neighbors operator is responsible for computing the k-nearest neighbors
-/// of a given query vector against a set of vectors using brute force calculation.
-/// # Note
-/// - Callers should ensure that the input vectors are normalized if using the cosine similarity metric.
+/// of a batch of query vectors against a set of vectors using brute force calculation.
+/// The log is materialized and filtered once for the whole batch.
 #[derive(Debug)]
 pub struct BruteForceKnnOperator {}
 
 /// The input to the brute force k-nearest neighbors operator.
 /// # Parameters
-/// * `data` - The vectors to query against.
-/// * `query` - The query vector.
-/// * `k` - The number of nearest neighbors to find.
+/// * `log` - The log records to query against.
+/// * `queries` - The query vectors, each with the number of nearest neighbors to find for it.
 /// * `distance_metric` - The distance metric to use.
 #[derive(Debug)]
 pub struct BruteForceKnnOperatorInput {
     pub log: Chunk<LogRecord>,
-    pub query: Vec<f32>,
-    pub k: usize,
-    pub distance_metric: DistanceFunction,
+    pub queries: Arc<[KnnQuery]>,
+    pub distance_metric: Arc<dyn Distance>,
     pub allowed_ids: Arc<[String]>,
     // This is just a subset of allowed_ids containing
     // only the ids that are allowed and present in the log.
     pub allowed_ids_brute_force: Arc<[String]>,
+    // Sorted offset ids of the records that match the query's where and where_document
+    // clauses. None when the query has neither.
+    pub allowed_offset_ids: Option<Arc<[u32]>>,
+    // Only the metadatas and documents that are included are returned.
+    pub include: QueryInclude,
+    // The scan is given up once the deadline of the query is exceeded.
+    pub deadline: Deadline,
+    // The candidates are scored on the threads of the compute pool.
+    pub compute_pool: ComputePool,
+    // Set for collections with sparse embeddings, which are read back as `SparseVector`s and
+    // scored by Jaccard distance whatever the distance metric is. They have no HNSW index, so
+    // the records compacted into the record segment are scored along with the log.
+    pub sparse_embeddings: bool,
     // Deps to create the log materializer
     pub record_segment_definition: Segment,
     pub blockfile_provider: BlockfileProvider,
 }
 
-/// The output of the brute force k-nearest neighbors operator.
+/// The output of the brute force k-nearest neighbors operator, a result for each of the
+/// query vectors in their order.
+#[derive(Debug)]
+pub struct BruteForceKnnOperatorOutput {
+    pub results: Vec<BruteForceKnnResult>,
+}
+
+/// The nearest neighbors of one query vector.
 /// # Parameters
 /// * `user_ids` - The user ids of the nearest neighbors.
 /// * `embeddings` - The embeddings of the nearest neighbors.
 /// * `distances` - The distances of the nearest neighbors.
-/// One row for each query vector.
+/// * `metadatas` - The metadatas of the nearest neighbors, empty unless included.
+/// * `documents` - The documents of the nearest neighbors, empty unless included.
+/// One row for each neighbor.
 #[derive(Debug)]
-pub struct BruteForceKnnOperatorOutput {
+pub struct BruteForceKnnResult {
     pub user_ids: Vec<String>,
     pub embeddings: Vec<Vec<f32>>,
     pub distances: Vec<f32>,
+    pub metadatas: Vec<Option<Metadata>>,
+    pub documents: Vec<Option<String>>,
 }
 
-#[derive(Debug)]
-struct Entry<'record> {
-    user_id: &'record str,
-    embedding: &'record [f32],
-    distance: f32,
-}
-
-impl Ord for Entry<'_> {
-    fn cmp(&self, other: &Self) -> Ordering {
-        if self.distance == other.distance {
-            Ordering::Equal
-        } else if self.distance > other.distance {
-            // This is a min heap, so we need to reverse the ordering.
-            Ordering::Less
-        } else {
-            // This is a min heap, so we need to reverse the ordering.
-            Ordering::Greater
-        }
-    }
-}
-
-impl PartialOrd for Entry<'_> {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-impl PartialEq for Entry<'_> {
-    fn eq(&self, other: &Self) -> bool {
-        self.distance == other.distance
-    }
-}
-
-impl Eq for Entry<'_> {}
-
 #[derive(Debug, Error)]
 pub enum BruteForceKnnOperatorError {
     #[error(transparent)]
@@ -103,6 +101,14 @@ pub enum BruteForceKnnOperatorError {
     ),
     #[error("Error while materializing log records: {0}")]
     LogMaterializationError(#[from] LogMaterializerError),
+    #[error("Error computing distance: {0}")]
+    DistanceError(#[from] DistanceFunctionError),
+    #[error(transparent)]
+    DeadlineExceeded(#[from] DeadlineExceededError),
+    #[error("Invalid sparse vector: {0}")]
+    SparseVectorError(#[from] SparseVectorError),
+    #[error("Error reading record segment")]
+    RecordSegmentReadError(#[from] Box<dyn ChromaError>),
 }
 
 impl ChromaError for BruteForceKnnOperatorError {
@@ -110,10 +116,38 @@ impl ChromaError for BruteForceKnnOperatorError {
         match self {
             BruteForceKnnOperatorError::RecordSegmentReaderCreationError(e) => e.code(),
             BruteForceKnnOperatorError::LogMaterializationError(e) => e.code(),
+            BruteForceKnnOperatorError::DistanceError(e) => e.code(),
+            BruteForceKnnOperatorError::DeadlineExceeded(e) => e.code(),
+            BruteForceKnnOperatorError::SparseVectorError(e) => e.code(),
+            BruteForceKnnOperatorError::RecordSegmentReadError(e) => e.code(),
         }
     }
 }
 
+// Finds the nearest sparse embeddings of each query by Jaccard distance. The candidates are
+// read back once for all of the queries, and scored in parallel in batches.
+fn sparse_top_k(
+    queries: &[KnnQuery],
+    embeddings: &[VectorSlice],
+    deadline: &Deadline,
+) -> Result<Vec<Vec<(u32, f32)>>, BruteForceKnnOperatorError> {
+    let queries = queries
+        .iter()
+        .map(|query| Ok((SparseVector::from_embedding(&query.embedding)?, query.k)))
+        .collect::<Result<Vec<_>, SparseVectorError>>()?;
+    let candidates = embeddings
+        .iter()
+        .map(|embedding| SparseVector::from_embedding(&embedding.to_f32()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let queries: Vec<_> = queries.iter().map(|(query, k)| (query, *k)).collect();
+    Ok(par_top_k_jaccard_batch(
+        &queries,
+        &candidates,
+        SCAN_BATCH_SIZE,
+        || deadline.check(),
+    )?)
+}
+
 #[async_trait]
 impl Operator<BruteForceKnnOperatorInput, BruteForceKnnOperatorOutput> for BruteForceKnnOperator {
     type Error = BruteForceKnnOperatorError;
@@ -122,6 +156,7 @@ impl Operator<BruteForceKnnOperatorInput, BruteForceKnnOperatorOutput> for Brute
         &self,
         input: &BruteForceKnnOperatorInput,
     ) -> Result<BruteForceKnnOperatorOutput, Self::Error> {
+        input.deadline.check()?;
         // Materialize the log records
         let record_segment_reader = match RecordSegmentReader::from_segment(
             &input.record_segment_definition,
@@ -144,21 +179,37 @@ impl Operator<BruteForceKnnOperatorInput, BruteForceKnnOperatorOutput> for Brute
             }
         };
 
-        let should_normalize = match input.distance_metric {
-            DistanceFunction::Cosine => true,
-            _ => false,
-        };
+        input.deadline.check()?;
 
-        let normalized_query = match should_normalize {
-            true => Some(normalize(&input.query)),
-            false => None,
-        };
+        // The records of the segment the log does not touch, which are only read for sparse
+        // collections. Those the log does touch are materialized with it.
+        let mut segment_records = Vec::new();
+        if let (true, Some(reader)) = (
+            input.sparse_embeddings,
+            &log_materializer.record_segment_reader,
+        ) {
+            let in_log: HashSet<u32> = logs.iter().map(|(record, _)| record.offset_id).collect();
+            let allowed_ids: HashSet<&str> = input.allowed_ids.iter().map(String::as_str).collect();
+            for (offset_id, data_record) in reader.get_data_page(0, None).await? {
+                if in_log.contains(&offset_id)
+                    || (!allowed_ids.is_empty() && !allowed_ids.contains(data_record.id))
+                {
+                    continue;
+                }
+                segment_records.push(MaterializedLogRecord::from((data_record, offset_id)));
+            }
+            input.deadline.check()?;
+        }
 
-        let mut heap = BinaryHeap::with_capacity(input.k);
+        let mut user_ids = Vec::new();
+        let mut embeddings = Vec::new();
+        let mut records = Vec::new();
         let data_chunk = logs;
-        for data in data_chunk.iter() {
-            let log_record = data.0;
-
+        let candidates = data_chunk
+            .iter()
+            .map(|(log_record, _)| (log_record, true))
+            .chain(segment_records.iter().map(|record| (record, false)));
+        for (log_record, from_log) in candidates {
             if log_record.final_operation == Operation::Delete {
                 // Explicitly skip deleted records.
                 continue;
@@ -167,71 +218,123 @@ impl Operator<BruteForceKnnOperatorInput, BruteForceKnnOperatorOutput> for Brute
             // Skip records that are disallowed. If allowed list is empty then
             // don't exclude anything.
             // Empty allowed list is passed when where filtering is absent.
+            // The records of the segment were checked against it as they were read.
             // TODO: This should not need to use merged_user_id, which clones the id.
-            if !input.allowed_ids.is_empty()
+            if from_log
+                && !input.allowed_ids.is_empty()
                 && !input
                     .allowed_ids_brute_force
                     .contains(&log_record.merged_user_id())
             {
                 continue;
             }
-            let embedding = &log_record.merged_embeddings();
-            if should_normalize {
-                let normalized_query = normalized_query.as_ref().expect("Invariant violation. Should have set normalized query if should_normalize is true.");
-                let normalized_embedding = normalize(&embedding[..]);
-                let distance = input
-                    .distance_metric
-                    .distance(&normalized_embedding[..], &normalized_query[..]);
-                heap.push(Entry {
-                    user_id: log_record.merged_user_id_ref(),
-                    embedding,
-                    distance,
-                });
-            } else {
-                let distance = input.distance_metric.distance(&embedding[..], &input.query);
-                heap.push(Entry {
-                    user_id: log_record.merged_user_id_ref(),
-                    embedding,
-                    distance,
-                });
+            if let Some(allowed_offset_ids) = &input.allowed_offset_ids {
+                if allowed_offset_ids
+                    .binary_search(&log_record.offset_id)
+                    .is_err()
+                {
+                    continue;
+                }
+            }
+            let embedding = log_record.merged_embeddings();
+            // Sparse embeddings have as many elements as they have components, so only dense
+            // ones have to match the query.
+            let mismatch = match input.sparse_embeddings {
+                true => None,
+                false => input
+                    .queries
+                    .iter()
+                    .find(|query| query.embedding.len() != embedding.len()),
+            };
+            if let Some(query) = mismatch {
+                return Err(BruteForceKnnOperatorError::DistanceError(
+                    DistanceFunctionError::DimensionMismatch {
+                        expected: embedding.len(),
+                        got: query.embedding.len(),
+                    },
+                ));
             }
+            user_ids.push(log_record.merged_user_id_ref());
+            embeddings.push(embedding);
+            records.push(log_record);
         }
 
-        let mut sorted_embeddings = Vec::with_capacity(input.k);
-        let mut sorted_distances = Vec::with_capacity(input.k);
-        let mut sorted_user_ids = Vec::with_capacity(input.k);
-        let mut i = 0;
-        while i < input.k {
-            let entry = match heap.pop() {
-                Some(entry) => entry,
-                None => {
-                    break;
+        // The cosine kernels normalize internally, so neither side needs to be normalized here.
+        // Candidates are identified by their position, so ties are broken on log order.
+        // Batches are scored in parallel against every query, and the result does not depend
+        // on how they are scheduled.
+        metrics().record_brute_force_candidates(embeddings.len());
+        let queries: Vec<(&[f32], usize)> = input
+            .queries
+            .iter()
+            .map(|query| (query.embedding.as_slice(), query.k))
+            .collect();
+        let nearest = input
+            .compute_pool
+            .install(|| match input.sparse_embeddings {
+                true => sparse_top_k(&input.queries, &embeddings, &input.deadline),
+                false => Ok(par_top_k_batch_slices(
+                    input.distance_metric.as_ref(),
+                    &queries,
+                    &embeddings,
+                    SCAN_BATCH_SIZE,
+                    || input.deadline.check(),
+                )?),
+            })?;
+        let mut results = Vec::with_capacity(nearest.len());
+        for nearest in nearest {
+            let mut sorted_embeddings = Vec::with_capacity(nearest.len());
+            let mut sorted_distances = Vec::with_capacity(nearest.len());
+            let mut sorted_user_ids = Vec::with_capacity(nearest.len());
+            let mut sorted_metadatas = Vec::new();
+            let mut sorted_documents = Vec::new();
+            for (index, distance) in nearest {
+                let index = index as usize;
+                sorted_user_ids.push(user_ids[index].to_string());
+                sorted_embeddings.push(embeddings[index].to_f32().into_owned());
+                sorted_distances.push(distance);
+                if input.include.metadatas {
+                    let metadata = records[index].merged_metadata();
+                    sorted_metadatas.push(if metadata.is_empty() {
+                        None
+                    } else {
+                        Some(metadata)
+                    });
                 }
-            };
-            sorted_user_ids.push(entry.user_id.to_string());
-            sorted_embeddings.push(entry.embedding.to_vec());
-            sorted_distances.push(entry.distance);
-            i += 1;
+                if input.include.documents {
+                    sorted_documents.push(records[index].merged_document());
+                }
+            }
+            tracing::info!("Brute force Knn result. distances: {:?}", sorted_distances);
+            results.push(BruteForceKnnResult {
+                user_ids: sorted_user_ids,
+                embeddings: sorted_embeddings,
+                distances: sorted_distances,
+                metadatas: sorted_metadatas,
+                documents: sorted_documents,
+            });
         }
 
-        tracing::info!("Brute force Knn result. distances: {:?}", sorted_distances);
-        Ok(BruteForceKnnOperatorOutput {
-            user_ids: sorted_user_ids,
-            embeddings: sorted_embeddings,
-            distances: sorted_distances,
-        })
+        Ok(BruteForceKnnOperatorOutput { results })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::distance::{DistanceFunction, DistanceRegistry};
+    use crate::types::Embedding;
     use crate::types::LogRecord;
     use crate::types::Operation;
     use crate::types::OperationRecord;
+    use half::f16;
     use std::collections::HashMap;
     use uuid::uuid;
 
+    fn single_query(embedding: Vec<f32>, k: usize) -> Arc<[KnnQuery]> {
+        Arc::new([KnnQuery { embedding, k }])
+    }
+
     // Helper for tests
     fn get_blockfile_provider_and_record_segment_definition() -> (BlockfileProvider, Segment) {
         // Create a blockfile provider for the log materializer
@@ -259,7 +362,7 @@ mod tests {
                 log_offset: 1,
                 record: OperationRecord {
                     id: "embedding_id_1".to_string(),
-                    embedding: Some(vec![0.0, 0.0, 0.0]),
+                    embedding: Some(vec![0.0, 0.0, 0.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -270,7 +373,7 @@ mod tests {
                 log_offset: 2,
                 record: OperationRecord {
                     id: "embedding_id_2".to_string(),
-                    embedding: Some(vec![0.0, 1.0, 1.0]),
+                    embedding: Some(vec![0.0, 1.0, 1.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -281,7 +384,7 @@ mod tests {
                 log_offset: 3,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![7.0, 8.0, 9.0]),
+                    embedding: Some(vec![7.0, 8.0, 9.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -293,16 +396,20 @@ mod tests {
 
         let input = BruteForceKnnOperatorInput {
             log: data_chunk,
-            query: vec![0.0, 0.0, 0.0],
-            k: 2,
-            distance_metric: DistanceFunction::Euclidean,
+            queries: single_query(vec![0.0, 0.0, 0.0], 2),
+            distance_metric: Arc::new(DistanceFunction::Euclidean),
             allowed_ids: Arc::new([]),
             allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
             blockfile_provider,
             record_segment_definition,
         };
 
-        let output = operator.run(&input).await.unwrap();
+        let output = operator.run(&input).await.unwrap().results.remove(0);
         assert_eq!(output.user_ids, vec!["embedding_id_1", "embedding_id_2"]);
         let distance_1 = 0.0_f32.powi(2) + 1.0_f32.powi(2) + 1.0_f32.powi(2);
         assert_eq!(output.distances, vec![0.0, distance_1]);
@@ -312,6 +419,108 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_brute_force_knn_float16_embeddings() {
+        let operator = BruteForceKnnOperator {};
+        let (blockfile_provider, record_segment_definition) =
+            get_blockfile_provider_and_record_segment_definition();
+        let float16 = |values: &[f32]| {
+            Embedding::F16(values.iter().map(|x| f16::from_f32(*x).to_bits()).collect())
+        };
+        // Float16 records are scored as they are, next to float32 ones.
+        let data: Vec<LogRecord> = [
+            ("embedding_id_1", float16(&[0.5, 0.0, 0.0])),
+            ("embedding_id_2", Embedding::F32(vec![0.0, 0.25, 0.0])),
+            ("embedding_id_3", float16(&[1.5, 3.0, -2.0])),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (id, embedding))| LogRecord {
+            log_offset: i as i64 + 1,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: Some(embedding),
+                encoding: None,
+                metadata: None,
+                document: None,
+                operation: Operation::Add,
+            },
+        })
+        .collect();
+
+        let input = BruteForceKnnOperatorInput {
+            log: Chunk::new(data.into()),
+            queries: single_query(vec![0.0, 0.0, 0.0], 3),
+            distance_metric: Arc::new(DistanceFunction::Euclidean),
+            allowed_ids: Arc::new([]),
+            allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
+            blockfile_provider,
+            record_segment_definition,
+        };
+
+        let output = operator.run(&input).await.unwrap().results.remove(0);
+        assert_eq!(
+            output.user_ids,
+            vec!["embedding_id_2", "embedding_id_1", "embedding_id_3"]
+        );
+        assert_eq!(output.distances, vec![0.0625, 0.25, 15.25]);
+        assert_eq!(
+            output.embeddings,
+            vec![
+                vec![0.0, 0.25, 0.0],
+                vec![0.5, 0.0, 0.0],
+                vec![1.5, 3.0, -2.0]
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_brute_force_knn_allowed_offset_ids() {
+        let operator = BruteForceKnnOperator {};
+        let (blockfile_provider, record_segment_definition) =
+            get_blockfile_provider_and_record_segment_definition();
+        let data: Vec<LogRecord> = [vec![0.0, 0.0], vec![0.0, 1.0], vec![0.0, 2.0]]
+            .into_iter()
+            .enumerate()
+            .map(|(i, embedding)| LogRecord {
+                log_offset: i as i64 + 1,
+                record: OperationRecord {
+                    id: format!("embedding_id_{}", i + 1),
+                    embedding: Some(embedding.into()),
+                    encoding: None,
+                    metadata: None,
+                    document: None,
+                    operation: Operation::Add,
+                },
+            })
+            .collect();
+
+        // Offset ids are handed out from 1 in log order, so this skips the closest record.
+        let input = BruteForceKnnOperatorInput {
+            log: Chunk::new(data.into()),
+            queries: single_query(vec![0.0, 0.0], 3),
+            distance_metric: Arc::new(DistanceFunction::Euclidean),
+            allowed_ids: Arc::new([]),
+            allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: Some(Arc::new([2, 3])),
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
+            blockfile_provider,
+            record_segment_definition,
+        };
+
+        let output = operator.run(&input).await.unwrap().results.remove(0);
+        assert_eq!(output.user_ids, vec!["embedding_id_2", "embedding_id_3"]);
+        assert_eq!(output.distances, vec![1.0, 4.0]);
+    }
+
     #[tokio::test]
     async fn test_brute_force_knn_cosine() {
         let operator = BruteForceKnnOperator {};
@@ -328,7 +537,7 @@ mod tests {
                 log_offset: 1,
                 record: OperationRecord {
                     id: "embedding_id_1".to_string(),
-                    embedding: Some(vec![0.0, 1.0, 0.0]),
+                    embedding: Some(vec![0.0, 1.0, 0.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -339,7 +548,7 @@ mod tests {
                 log_offset: 2,
                 record: OperationRecord {
                     id: "embedding_id_2".to_string(),
-                    embedding: Some(data_1.clone()),
+                    embedding: Some(data_1.clone().into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -350,7 +559,7 @@ mod tests {
                 log_offset: 3,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(data_2.clone()),
+                    embedding: Some(data_2.clone().into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -362,15 +571,19 @@ mod tests {
 
         let input = BruteForceKnnOperatorInput {
             log: data_chunk,
-            query: vec![0.0, 1.0, 0.0],
-            k: 2,
-            distance_metric: DistanceFunction::InnerProduct,
+            queries: single_query(vec![0.0, 1.0, 0.0], 2),
+            distance_metric: Arc::new(DistanceFunction::InnerProduct),
             allowed_ids: Arc::new([]),
             allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
             blockfile_provider,
             record_segment_definition,
         };
-        let output = operator.run(&input).await.unwrap();
+        let output = operator.run(&input).await.unwrap().results.remove(0);
 
         assert_eq!(output.user_ids, vec!["embedding_id_1", "embedding_id_2"]);
         let expected_distance_1 = 1.0 - ((data_1[0] * 0.0) + (data_1[1] * 1.0) + (data_1[2] * 0.0));
@@ -395,7 +608,7 @@ mod tests {
             log_offset: 1,
             record: OperationRecord {
                 id: "embedding_id_1".to_string(),
-                embedding: Some(vec![0.0, 0.0, 0.0]),
+                embedding: Some(vec![0.0, 0.0, 0.0].into()),
                 encoding: None,
                 metadata: None,
                 document: None,
@@ -407,15 +620,19 @@ mod tests {
 
         let input = BruteForceKnnOperatorInput {
             log: data_chunk,
-            query: vec![0.0, 0.0, 0.0],
-            k: 2,
-            distance_metric: DistanceFunction::Euclidean,
+            queries: single_query(vec![0.0, 0.0, 0.0], 2),
+            distance_metric: Arc::new(DistanceFunction::Euclidean),
             allowed_ids: Arc::new([]),
             allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
             blockfile_provider,
             record_segment_definition,
         };
-        let output = operator.run(&input).await.unwrap();
+        let output = operator.run(&input).await.unwrap().results.remove(0);
 
         assert_eq!(output.user_ids, vec!["embedding_id_1"]);
         assert_eq!(output.distances, vec![0.0]);
@@ -432,7 +649,7 @@ mod tests {
                 log_offset: 1,
                 record: OperationRecord {
                     id: "embedding_id_1".to_string(),
-                    embedding: Some(vec![7.0, 8.0, 9.0]),
+                    embedding: Some(vec![7.0, 8.0, 9.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -454,7 +671,7 @@ mod tests {
                 log_offset: 3,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![7.0, 8.0, 9.0]),
+                    embedding: Some(vec![7.0, 8.0, 9.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -466,11 +683,15 @@ mod tests {
 
         let input = BruteForceKnnOperatorInput {
             log: data_chunk,
-            query: vec![0.0, 0.0, 0.0],
-            k: 2,
-            distance_metric: DistanceFunction::Euclidean,
+            queries: single_query(vec![0.0, 0.0, 0.0], 2),
+            distance_metric: Arc::new(DistanceFunction::Euclidean),
             allowed_ids: Arc::new([]),
             allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
             blockfile_provider,
             record_segment_definition,
         };
@@ -496,7 +717,7 @@ mod tests {
                 log_offset: 1,
                 record: OperationRecord {
                     id: "embedding_id_1".to_string(),
-                    embedding: Some(vec![0.0, 0.0, 0.0]),
+                    embedding: Some(vec![0.0, 0.0, 0.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -518,7 +739,7 @@ mod tests {
                 log_offset: 3,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![0.0, 0.0, 0.0]),
+                    embedding: Some(vec![0.0, 0.0, 0.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -530,18 +751,618 @@ mod tests {
 
         let input = BruteForceKnnOperatorInput {
             log: data_chunk,
-            query: vec![0.0, 0.0, 0.0],
-            k: 2,
-            distance_metric: DistanceFunction::Euclidean,
+            queries: single_query(vec![0.0, 0.0, 0.0], 2),
+            distance_metric: Arc::new(DistanceFunction::Euclidean),
             allowed_ids: Arc::new([]),
             allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
             blockfile_provider,
             record_segment_definition,
         };
-        let output = operator.run(&input).await.unwrap();
+        let output = operator.run(&input).await.unwrap().results.remove(0);
 
         assert_eq!(output.user_ids, vec!["embedding_id_3"]);
         assert_eq!(output.distances, vec![0.0]);
         assert_eq!(output.embeddings, vec![vec![0.0, 0.0, 0.0]]);
     }
+
+    #[tokio::test]
+    async fn test_brute_force_knn_cosine_unnormalized() {
+        let operator = BruteForceKnnOperator {};
+        let (blockfile_provider, record_segment_definition) =
+            get_blockfile_provider_and_record_segment_definition();
+        let embeddings = vec![
+            vec![0.0, 10.0, 0.0],
+            vec![3.0, 2.0, 1.0],
+            vec![-4.0, -5.0, 6.0],
+        ];
+        let data = embeddings
+            .iter()
+            .enumerate()
+            .map(|(i, embedding)| LogRecord {
+                log_offset: i as i64 + 1,
+                record: OperationRecord {
+                    id: format!("embedding_id_{}", i + 1),
+                    embedding: Some(embedding.clone().into()),
+                    encoding: None,
+                    metadata: None,
+                    document: None,
+                    operation: Operation::Add,
+                },
+            })
+            .collect::<Vec<_>>();
+        let data_chunk = Chunk::new(data.into());
+
+        let query = vec![0.0, 2.0, 0.5];
+        let input = BruteForceKnnOperatorInput {
+            log: data_chunk,
+            queries: single_query(query.clone(), 3),
+            distance_metric: Arc::new(DistanceFunction::Cosine),
+            allowed_ids: Arc::new([]),
+            allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
+            blockfile_provider,
+            record_segment_definition,
+        };
+        let output = operator.run(&input).await.unwrap().results.remove(0);
+
+        assert_eq!(
+            output.user_ids,
+            vec!["embedding_id_1", "embedding_id_2", "embedding_id_3"]
+        );
+        for (embedding, distance) in output.embeddings.iter().zip(output.distances.iter()) {
+            let expected = DistanceFunction::Cosine.distance(&query, embedding);
+            assert!((distance - expected).abs() < 1e-6);
+        }
+        // Embeddings are returned as stored, not normalized.
+        assert_eq!(output.embeddings[0], vec![0.0, 10.0, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn test_brute_force_knn_hamming() {
+        let operator = BruteForceKnnOperator {};
+        let (blockfile_provider, record_segment_definition) =
+            get_blockfile_provider_and_record_segment_definition();
+        let embeddings = vec![
+            vec![1.0, 1.0, 1.0, 1.0],
+            vec![1.0, 0.0, 0.0, 0.0],
+            vec![0.0, 0.0, 0.0, 0.0],
+        ];
+        let data = embeddings
+            .iter()
+            .enumerate()
+            .map(|(i, embedding)| LogRecord {
+                log_offset: i as i64 + 1,
+                record: OperationRecord {
+                    id: format!("embedding_id_{}", i + 1),
+                    embedding: Some(embedding.clone().into()),
+                    encoding: None,
+                    metadata: None,
+                    document: None,
+                    operation: Operation::Add,
+                },
+            })
+            .collect::<Vec<_>>();
+        let data_chunk = Chunk::new(data.into());
+
+        let input = BruteForceKnnOperatorInput {
+            log: data_chunk,
+            queries: single_query(vec![1.0, 1.0, 0.0, 0.0], 2),
+            distance_metric: Arc::new(DistanceFunction::Hamming),
+            allowed_ids: Arc::new([]),
+            allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
+            blockfile_provider,
+            record_segment_definition,
+        };
+        let output = operator.run(&input).await.unwrap().results.remove(0);
+
+        assert_eq!(output.user_ids, vec!["embedding_id_2", "embedding_id_1"]);
+        assert_eq!(output.distances, vec![1.0, 2.0]);
+        assert_eq!(output.embeddings[0], vec![1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[tokio::test]
+    async fn test_brute_force_knn_sparse_jaccard() {
+        let operator = BruteForceKnnOperator {};
+        let (blockfile_provider, record_segment_definition) =
+            get_blockfile_provider_and_record_segment_definition();
+        let sparse = |indices: &[u32], weights: Option<Vec<f32>>| {
+            SparseVector::new(indices.to_vec(), weights).unwrap()
+        };
+        // Sparse embeddings of different lengths share a collection.
+        let embeddings = vec![
+            sparse(&[1, 2, 3, 4], None),
+            sparse(&[100_000], None),
+            sparse(&[2, 3], Some(vec![1.0, 3.0])),
+            sparse(&[], None),
+        ];
+        let data = embeddings
+            .iter()
+            .enumerate()
+            .map(|(i, embedding)| LogRecord {
+                log_offset: i as i64 + 1,
+                record: OperationRecord {
+                    id: format!("embedding_id_{}", i + 1),
+                    embedding: Some(embedding.to_embedding().into()),
+                    encoding: Some(crate::types::ScalarEncoding::SPARSE),
+                    metadata: None,
+                    document: None,
+                    operation: Operation::Add,
+                },
+            })
+            .collect::<Vec<_>>();
+        let mut input = BruteForceKnnOperatorInput {
+            log: Chunk::new(data.into()),
+            queries: Arc::new([
+                KnnQuery {
+                    embedding: sparse(&[2, 3], None).to_embedding(),
+                    k: 3,
+                },
+                KnnQuery {
+                    embedding: sparse(&[], None).to_embedding(),
+                    k: 1,
+                },
+            ]),
+            // Sparse embeddings are scored by Jaccard distance whatever the metric is.
+            distance_metric: Arc::new(DistanceFunction::Euclidean),
+            allowed_ids: Arc::new([]),
+            allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: true,
+            blockfile_provider,
+            record_segment_definition,
+        };
+        let mut results = operator.run(&input).await.unwrap().results;
+
+        // 1 - 2 / 4, 1 - 2 / 4, 1 - 0 / 1
+        let nearest = results.remove(0);
+        assert_eq!(
+            nearest.user_ids,
+            vec!["embedding_id_1", "embedding_id_3", "embedding_id_2"]
+        );
+        assert_eq!(nearest.distances, vec![0.5, 0.5, 1.0]);
+        assert_eq!(
+            SparseVector::from_embedding(&nearest.embeddings[1]).unwrap(),
+            embeddings[2]
+        );
+        let nearest = results.remove(0);
+        assert_eq!(nearest.user_ids, vec!["embedding_id_4"]);
+        assert_eq!(nearest.distances, vec![0.0]);
+
+        input.queries = single_query(vec![0.5, 1.0], 1);
+        let error = operator.run(&input).await.unwrap_err();
+        assert!(matches!(
+            error,
+            BruteForceKnnOperatorError::SparseVectorError(_)
+        ));
+        assert_eq!(error.code(), ErrorCodes::InvalidArgument);
+    }
+
+    // Scores candidates by how far their first dimension is from the query's.
+    #[derive(Debug)]
+    struct FirstDimension;
+
+    impl Distance for FirstDimension {
+        fn name(&self) -> &str {
+            "first_dimension"
+        }
+
+        fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+            (a[0] - b[0]).abs()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_brute_force_knn_registered_distance() {
+        let mut registry = DistanceRegistry::new();
+        registry.register(Arc::new(FirstDimension)).unwrap();
+
+        let operator = BruteForceKnnOperator {};
+        let (blockfile_provider, record_segment_definition) =
+            get_blockfile_provider_and_record_segment_definition();
+        let embeddings = vec![
+            vec![0.0, 0.0, 0.0],
+            vec![4.0, 100.0, 100.0],
+            vec![1.5, -100.0, 0.0],
+        ];
+        let data = embeddings
+            .iter()
+            .enumerate()
+            .map(|(i, embedding)| LogRecord {
+                log_offset: i as i64 + 1,
+                record: OperationRecord {
+                    id: format!("embedding_id_{}", i + 1),
+                    embedding: Some(embedding.clone().into()),
+                    encoding: None,
+                    metadata: None,
+                    document: None,
+                    operation: Operation::Add,
+                },
+            })
+            .collect::<Vec<_>>();
+        let data_chunk = Chunk::new(data.into());
+
+        let input = BruteForceKnnOperatorInput {
+            log: data_chunk,
+            queries: single_query(vec![3.0, 0.0, 0.0], 2),
+            distance_metric: registry.get("first_dimension").unwrap(),
+            allowed_ids: Arc::new([]),
+            allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
+            blockfile_provider,
+            record_segment_definition,
+        };
+        let output = operator.run(&input).await.unwrap().results.remove(0);
+
+        assert_eq!(output.user_ids, vec!["embedding_id_2", "embedding_id_3"]);
+        assert_eq!(output.distances, vec![1.0, 1.5]);
+    }
+
+    #[tokio::test]
+    async fn test_query_dimension_mismatch_errors() {
+        let operator = BruteForceKnnOperator {};
+        let (blockfile_provider, record_segment_definition) =
+            get_blockfile_provider_and_record_segment_definition();
+        let data = vec![LogRecord {
+            log_offset: 1,
+            record: OperationRecord {
+                id: "embedding_id_1".to_string(),
+                embedding: Some(vec![0.0, 0.0, 0.0].into()),
+                encoding: None,
+                metadata: None,
+                document: None,
+                operation: Operation::Add,
+            },
+        }];
+        let data_chunk = Chunk::new(data.into());
+
+        let input = BruteForceKnnOperatorInput {
+            log: data_chunk,
+            queries: single_query(vec![0.0, 0.0], 1),
+            distance_metric: Arc::new(DistanceFunction::Euclidean),
+            allowed_ids: Arc::new([]),
+            allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
+            blockfile_provider,
+            record_segment_definition,
+        };
+        match operator.run(&input).await {
+            Ok(_) => panic!("Expected error"),
+            Err(e) => {
+                assert_eq!(e.code(), ErrorCodes::InvalidArgument);
+                match e {
+                    BruteForceKnnOperatorError::DistanceError(
+                        DistanceFunctionError::DimensionMismatch { expected, got },
+                    ) => {
+                        assert_eq!(expected, 3);
+                        assert_eq!(got, 2);
+                    }
+                    _ => panic!("Unexpected error"),
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_brute_force_knn_inner_product_matches_hnsw() {
+        use crate::index::{HnswIndex, HnswIndexConfig, Index, IndexConfig};
+        use rand::Rng;
+
+        let n = 100;
+        let d = 8;
+        let k = 5;
+        let mut rng = rand::thread_rng();
+        let embeddings: Vec<Vec<f32>> = (0..n)
+            .map(|_| (0..d).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+        let query: Vec<f32> = (0..d).map(|_| rng.gen_range(-1.0..1.0)).collect();
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let index = HnswIndex::init(
+            &IndexConfig {
+                dimensionality: d as i32,
+                distance_function: DistanceFunction::InnerProduct,
+            },
+            Some(&HnswIndexConfig {
+                max_elements: n,
+                m: 16,
+                ef_construction: 200,
+                ef_search: 200,
+                random_seed: 0,
+                persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+            }),
+            uuid::Uuid::new_v4(),
+        )
+        .unwrap();
+        for (i, embedding) in embeddings.iter().enumerate() {
+            index.add(i, embedding);
+        }
+        let (hnsw_ids, hnsw_distances) = index.query(&query, k, &[], &[]);
+
+        let (blockfile_provider, record_segment_definition) =
+            get_blockfile_provider_and_record_segment_definition();
+        let data: Vec<LogRecord> = embeddings
+            .iter()
+            .enumerate()
+            .map(|(i, embedding)| LogRecord {
+                log_offset: i as i64 + 1,
+                record: OperationRecord {
+                    id: format!("embedding_id_{}", i),
+                    embedding: Some(embedding.clone().into()),
+                    encoding: None,
+                    metadata: None,
+                    document: None,
+                    operation: Operation::Add,
+                },
+            })
+            .collect();
+        let input = BruteForceKnnOperatorInput {
+            log: Chunk::new(data.into()),
+            queries: single_query(query.clone(), k),
+            distance_metric: Arc::new(DistanceFunction::InnerProduct),
+            allowed_ids: Arc::new([]),
+            allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline: Deadline::default(),
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
+            blockfile_provider,
+            record_segment_definition,
+        };
+        let output = BruteForceKnnOperator {}
+            .run(&input)
+            .await
+            .unwrap()
+            .results
+            .remove(0);
+
+        let expected_user_ids: Vec<String> = hnsw_ids
+            .iter()
+            .map(|id| format!("embedding_id_{}", id))
+            .collect();
+        assert_eq!(output.user_ids, expected_user_ids);
+        for (brute_force, hnsw) in output.distances.iter().zip(hnsw_distances.iter()) {
+            assert!((brute_force - hnsw).abs() < 1e-5);
+        }
+    }
+
+    fn one_dimensional_input(
+        values: &[f32],
+        k: usize,
+        deadline: Deadline,
+    ) -> BruteForceKnnOperatorInput {
+        let (blockfile_provider, record_segment_definition) =
+            get_blockfile_provider_and_record_segment_definition();
+        let data: Vec<LogRecord> = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| LogRecord {
+                log_offset: i as i64 + 1,
+                record: OperationRecord {
+                    id: format!("embedding_id_{}", i),
+                    embedding: Some(vec![*value].into()),
+                    encoding: None,
+                    metadata: None,
+                    document: None,
+                    operation: Operation::Add,
+                },
+            })
+            .collect();
+        BruteForceKnnOperatorInput {
+            log: Chunk::new(data.into()),
+            queries: single_query(vec![0.0], k),
+            distance_metric: Arc::new(DistanceFunction::Euclidean),
+            allowed_ids: Arc::new([]),
+            allowed_ids_brute_force: Arc::new([]),
+            allowed_offset_ids: None,
+            include: QueryInclude::default(),
+            deadline,
+            compute_pool: ComputePool::new(2).unwrap(),
+            sparse_embeddings: false,
+            blockfile_provider,
+            record_segment_definition,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nearest_are_found_across_batches() {
+        // Ties across batches are still broken on log order.
+        let mut values = vec![5.0; SCAN_BATCH_SIZE * 3 + 10];
+        values[SCAN_BATCH_SIZE * 3 + 5] = 1.0;
+        values[SCAN_BATCH_SIZE * 2] = 2.0;
+        values[SCAN_BATCH_SIZE + 1] = 2.0;
+        values[3] = 3.0;
+        let input = one_dimensional_input(&values, 5, Deadline::default());
+        let output = BruteForceKnnOperator {}
+            .run(&input)
+            .await
+            .unwrap()
+            .results
+            .remove(0);
+        let expected_indices = [
+            SCAN_BATCH_SIZE * 3 + 5,
+            SCAN_BATCH_SIZE + 1,
+            SCAN_BATCH_SIZE * 2,
+            3,
+            0,
+        ];
+        let expected_user_ids: Vec<String> = expected_indices
+            .iter()
+            .map(|i| format!("embedding_id_{}", i))
+            .collect();
+        assert_eq!(output.user_ids, expected_user_ids);
+        assert_eq!(output.distances, vec![1.0, 4.0, 4.0, 9.0, 25.0]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_matches_each_query_alone() {
+        let values: Vec<f32> = (0..SCAN_BATCH_SIZE * 2 + 100)
+            .map(|_| rand::random::<f32>() * 100.0)
+            .collect();
+        // The queries ask for different numbers of neighbors, one for more than there are.
+        let queries: Vec<KnnQuery> = [(50.0, 1), (0.0, 10), (75.0, 0), (20.0, values.len() + 1)]
+            .into_iter()
+            .map(|(query, k)| KnnQuery {
+                embedding: vec![query],
+                k,
+            })
+            .collect();
+        let mut input = one_dimensional_input(&values, 0, Deadline::default());
+        input.queries = queries.clone().into();
+        let batch = BruteForceKnnOperator {}.run(&input).await.unwrap().results;
+        assert_eq!(batch.len(), queries.len());
+        for (query, batch) in queries.into_iter().zip(batch) {
+            let expected_len = query.k.min(values.len());
+            input.queries = Arc::new([query]);
+            let alone = BruteForceKnnOperator {}
+                .run(&input)
+                .await
+                .unwrap()
+                .results
+                .remove(0);
+            assert_eq!(batch.user_ids.len(), expected_len);
+            assert_eq!(batch.user_ids, alone.user_ids);
+            assert_eq!(batch.distances, alone.distances);
+            assert_eq!(batch.embeddings, alone.embeddings);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exceeded_deadline_stops_the_scan() {
+        let deadline = Deadline::default();
+        let input = one_dimensional_input(&[1.0; SCAN_BATCH_SIZE * 4], 1, deadline.clone());
+        drop(deadline.cancel_on_drop());
+        let error = BruteForceKnnOperator {}.run(&input).await.unwrap_err();
+        assert!(matches!(
+            error,
+            BruteForceKnnOperatorError::DeadlineExceeded(_)
+        ));
+        assert_eq!(error.code(), ErrorCodes::DeadlineExceeded);
+    }
+
+    #[tokio::test]
+    async fn test_encoded_embeddings_match_f32_ground_truth() {
+        use crate::chroma_proto;
+        use crate::types::ScalarEncoding;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let dimension = 16;
+        let embeddings: Vec<Vec<f32>> = (0..200)
+            .map(|_| (0..dimension).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+        let query: Vec<f32> = (0..dimension).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        let k = 10;
+
+        // Integers are scaled up so that they are still spread out after rounding.
+        let scaled = |embedding: &Vec<f32>| -> Vec<f32> {
+            embedding.iter().map(|x| (x * 1000.0).round()).collect()
+        };
+        for (encoding, scale) in [
+            (ScalarEncoding::FLOAT16, 1.0),
+            (ScalarEncoding::INT32, 1000.0),
+        ] {
+            let (encoded_query, ground_truth_embeddings): (Vec<f32>, Vec<Vec<f32>>) = match encoding
+            {
+                ScalarEncoding::INT32 => (scaled(&query), embeddings.iter().map(scaled).collect()),
+                _ => (query.clone(), embeddings.clone()),
+            };
+            // The records go through the same conversion as the ones pulled from the log.
+            let data: Vec<LogRecord> = ground_truth_embeddings
+                .iter()
+                .enumerate()
+                .map(|(i, embedding)| {
+                    let vector: chroma_proto::Vector =
+                        (embedding.clone(), encoding.clone(), dimension)
+                            .try_into()
+                            .unwrap();
+                    LogRecord::try_from(chroma_proto::LogRecord {
+                        log_offset: i as i64 + 1,
+                        record: Some(chroma_proto::OperationRecord {
+                            id: format!("embedding_id_{}", i),
+                            vector: Some(vector),
+                            metadata: None,
+                            operation: chroma_proto::Operation::Add as i32,
+                        }),
+                    })
+                    .unwrap()
+                })
+                .collect();
+            assert_eq!(data[0].record.encoding, Some(encoding.clone()));
+
+            let (blockfile_provider, record_segment_definition) =
+                get_blockfile_provider_and_record_segment_definition();
+            let input = BruteForceKnnOperatorInput {
+                log: Chunk::new(data.into()),
+                queries: single_query(encoded_query.clone(), k),
+                distance_metric: Arc::new(DistanceFunction::Euclidean),
+                allowed_ids: Arc::new([]),
+                allowed_ids_brute_force: Arc::new([]),
+                allowed_offset_ids: None,
+                include: QueryInclude::default(),
+                deadline: Deadline::default(),
+                compute_pool: ComputePool::new(2).unwrap(),
+                sparse_embeddings: false,
+                blockfile_provider,
+                record_segment_definition,
+            };
+            let output = BruteForceKnnOperator {}
+                .run(&input)
+                .await
+                .unwrap()
+                .results
+                .remove(0);
+
+            // The ground truth is computed on the f32 embeddings, before they were encoded.
+            let ground_truth = DistanceFunction::Euclidean.top_k(
+                &encoded_query,
+                ground_truth_embeddings
+                    .iter()
+                    .enumerate()
+                    .map(|(i, embedding)| (i as u32, embedding.as_slice())),
+                k,
+            );
+            let tolerance = 1e-2 * scale * scale;
+            assert_eq!(output.user_ids.len(), k);
+            for (rank, user_id) in output.user_ids.iter().enumerate() {
+                let index: usize = user_id["embedding_id_".len()..].parse().unwrap();
+                let true_distance = DistanceFunction::Euclidean
+                    .distance(&encoded_query, &ground_truth_embeddings[index]);
+                assert!(
+                    (true_distance - ground_truth[rank].1).abs() <= tolerance,
+                    "{:?} rank {}: {} vs {}",
+                    encoding,
+                    rank,
+                    true_distance,
+                    ground_truth[rank].1
+                );
+                assert!((output.distances[rank] - true_distance).abs() <= tolerance);
+            }
+        }
+    }
 }
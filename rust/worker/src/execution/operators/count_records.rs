@@ -228,7 +228,7 @@ mod tests {
                     log_offset: 1,
                     record: OperationRecord {
                         id: "embedding_id_1".to_string(),
-                        embedding: Some(vec![1.0, 2.0, 3.0]),
+                        embedding: Some(vec![1.0, 2.0, 3.0].into()),
                         encoding: None,
                         metadata: None,
                         document: None,
@@ -239,7 +239,7 @@ mod tests {
                     log_offset: 2,
                     record: OperationRecord {
                         id: "embedding_id_2".to_string(),
-                        embedding: Some(vec![4.0, 5.0, 6.0]),
+                        embedding: Some(vec![4.0, 5.0, 6.0].into()),
                         encoding: None,
                         metadata: None,
                         document: None,
@@ -301,7 +301,7 @@ mod tests {
                 log_offset: 4,
                 record: OperationRecord {
                     id: "embedding_id_1".to_string(),
-                    embedding: Some(vec![1.0, 2.0, 3.0]),
+                    embedding: Some(vec![1.0, 2.0, 3.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -312,7 +312,7 @@ mod tests {
                 log_offset: 5,
                 record: OperationRecord {
                     id: "embedding_id_4".to_string(),
-                    embedding: Some(vec![4.0, 5.0, 6.0]),
+                    embedding: Some(vec![4.0, 5.0, 6.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -365,7 +365,7 @@ mod tests {
                 log_offset: 1,
                 record: OperationRecord {
                     id: "embedding_id_1".to_string(),
-                    embedding: Some(vec![1.0, 2.0, 3.0]),
+                    embedding: Some(vec![1.0, 2.0, 3.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -376,7 +376,7 @@ mod tests {
                 log_offset: 2,
                 record: OperationRecord {
                     id: "embedding_id_2".to_string(),
-                    embedding: Some(vec![4.0, 5.0, 6.0]),
+                    embedding: Some(vec![4.0, 5.0, 6.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -398,7 +398,7 @@ mod tests {
                 log_offset: 4,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![1.0, 2.0, 3.0]),
+                    embedding: Some(vec![1.0, 2.0, 3.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -409,7 +409,7 @@ mod tests {
                 log_offset: 5,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![4.0, 5.0, 6.0]),
+                    embedding: Some(vec![4.0, 5.0, 6.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -13,6 +13,13 @@ use crate::{
 };
 use async_trait::async_trait;
 
+/// The flush s3 operator commits the segment writers of a compaction and flushes their
+/// blockfiles and index files to storage, returning the new file paths of each segment.
+/// # Notes
+/// Flushes write new files rather than overwriting the ones the segments point at. The
+/// output is only produced once every segment has been flushed, and the paths are only
+/// attached to the segments when it is registered with the sysdb, so a failure part way
+/// leaves all segments on their previous files.
 #[derive(Debug)]
 pub struct FlushS3Operator {}
 
@@ -25,14 +32,15 @@ impl FlushS3Operator {
 #[derive(Debug)]
 pub struct FlushS3Input {
     record_segment_writer: RecordSegmentWriter,
-    hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+    // None for collections with sparse embeddings, which have no HNSW index to flush.
+    hnsw_segment_writer: Option<Box<DistributedHNSWSegmentWriter>>,
     metadata_segment_writer: MetadataSegmentWriter<'static>,
 }
 
 impl FlushS3Input {
     pub fn new(
         record_segment_writer: RecordSegmentWriter,
-        hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+        hnsw_segment_writer: Option<Box<DistributedHNSWSegmentWriter>>,
         metadata_segment_writer: MetadataSegmentWriter<'static>,
     ) -> Self {
         Self {
@@ -67,7 +75,7 @@ impl Operator<FlushS3Input, FlushS3Output> for FlushS3Operator {
                         }
                     }
                     Err(e) => {
-                        tracing::error!("Error flushing metadata Segment: {:?}", e);
+                        tracing::error!("Error flushing record Segment: {:?}", e);
                         return Err(e);
                     }
                 }
@@ -78,29 +86,33 @@ impl Operator<FlushS3Input, FlushS3Output> for FlushS3Operator {
             }
         };
 
-        let hnsw_segment_flusher = input.hnsw_segment_writer.clone().commit();
-        let hnsw_segment_flush_info = match hnsw_segment_flusher {
-            Ok(flusher) => {
-                let segment_id = input.hnsw_segment_writer.id;
-                let res = flusher.flush().await;
-                match res {
-                    Ok(res) => {
-                        println!("HNSW Segment Flushed");
-                        SegmentFlushInfo {
-                            segment_id,
-                            file_paths: res,
+        // The HNSW segment of a sparse collection keeps no files and is left out of the
+        // flush info, so the register does not touch it.
+        let hnsw_segment_flush_info = match &input.hnsw_segment_writer {
+            Some(hnsw_segment_writer) => match hnsw_segment_writer.clone().commit() {
+                Ok(flusher) => {
+                    let segment_id = hnsw_segment_writer.id;
+                    let res = flusher.flush().await;
+                    match res {
+                        Ok(res) => {
+                            println!("HNSW Segment Flushed");
+                            Some(SegmentFlushInfo {
+                                segment_id,
+                                file_paths: res,
+                            })
+                        }
+                        Err(e) => {
+                            tracing::error!("Error Flushing HNSW Segment: {:?}", e);
+                            return Err(e);
                         }
-                    }
-                    Err(e) => {
-                        tracing::error!("Error Flushing HNSW Segment: {:?}", e);
-                        return Err(e);
                     }
                 }
-            }
-            Err(e) => {
-                tracing::error!("Error Commiting HNSW Segment: {:?}", e);
-                return Err(e);
-            }
+                Err(e) => {
+                    tracing::error!("Error Commiting HNSW Segment: {:?}", e);
+                    return Err(e);
+                }
+            },
+            None => None,
         };
 
         let metadata_segment_flusher = input.metadata_segment_writer.clone().commit();
@@ -130,11 +142,217 @@ impl Operator<FlushS3Input, FlushS3Output> for FlushS3Operator {
 
         tracing::info!("Flush to S3 complete");
         Ok(FlushS3Output {
-            segment_flush_info: Arc::new([
-                record_segment_flush_info,
-                hnsw_segment_flush_info,
-                metadata_segment_flush_info,
-            ]),
+            segment_flush_info: [record_segment_flush_info]
+                .into_iter()
+                .chain(hnsw_segment_flush_info)
+                .chain([metadata_segment_flush_info])
+                .collect(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockstore::arrow::provider::ArrowBlockfileProvider;
+    use crate::blockstore::provider::BlockfileProvider;
+    use crate::execution::compute_pool::ComputePool;
+    use crate::execution::data::data_chunk::Chunk;
+    use crate::execution::operators::write_segments::{WriteSegmentsInput, WriteSegmentsOperator};
+    use crate::index::config::HnswProviderConfig;
+    use crate::index::hnsw_provider::HnswIndexProvider;
+    use crate::segment::distributed_hnsw_segment::DistributedHNSWSegmentReader;
+    use crate::segment::metadata_segment::MetadataSegmentReader;
+    use crate::segment::record_segment::RecordSegmentReader;
+    use crate::storage::{local::LocalStorage, Storage};
+    use crate::types::{
+        KnnQuery, LogRecord, Operation, OperationRecord, Segment, SegmentScope, SegmentType,
+    };
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicU32;
+    use uuid::Uuid;
+
+    fn new_segment(r#type: SegmentType, scope: SegmentScope, collection_id: Uuid) -> Segment {
+        Segment {
+            id: Uuid::new_v4(),
+            r#type,
+            scope,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flushed_segments_can_be_reopened() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(ArrowBlockfileProvider::new(storage.clone()));
+        // Query the flushed HNSW index rather than a brute force copy of the record segment.
+        let hnsw_provider_config = HnswProviderConfig {
+            brute_force_threshold: 0,
+            ..Default::default()
+        };
+        let hnsw_tmp_dir = tempfile::tempdir().unwrap();
+        let hnsw_provider = HnswIndexProvider::new(
+            storage.clone(),
+            hnsw_tmp_dir.path().to_path_buf(),
+            &hnsw_provider_config,
+        );
+        let collection_id = Uuid::new_v4();
+        let mut record_segment = new_segment(
+            SegmentType::BlockfileRecord,
+            SegmentScope::RECORD,
+            collection_id,
+        );
+        let mut hnsw_segment = new_segment(
+            SegmentType::HnswDistributed,
+            SegmentScope::VECTOR,
+            collection_id,
+        );
+        let mut metadata_segment = new_segment(
+            SegmentType::BlockfileMetadata,
+            SegmentScope::METADATA,
+            collection_id,
+        );
+
+        let record_segment_writer =
+            RecordSegmentWriter::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        let hnsw_segment_writer = DistributedHNSWSegmentWriter::from_segment(
+            &hnsw_segment,
+            2,
+            hnsw_provider.clone(),
+            &record_segment,
+            &blockfile_provider,
+        )
+        .await
+        .unwrap();
+        let metadata_segment_writer =
+            MetadataSegmentWriter::from_segment(&metadata_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        let logs: Vec<LogRecord> = (0..10)
+            .map(|i| LogRecord {
+                log_offset: i + 1,
+                record: OperationRecord {
+                    id: format!("embedding_id_{}", i),
+                    embedding: Some(vec![i as f32, 1.0].into()),
+                    encoding: None,
+                    metadata: None,
+                    document: Some(format!("document number {}", i)),
+                    operation: Operation::Add,
+                },
+            })
+            .collect();
+        let write_input = WriteSegmentsInput::new(
+            record_segment_writer,
+            Some(hnsw_segment_writer),
+            metadata_segment_writer,
+            Chunk::new(logs.into()),
+            blockfile_provider.clone(),
+            record_segment.clone(),
+            Arc::new(AtomicU32::new(0)),
+        );
+        let written = WriteSegmentsOperator::new()
+            .run(&write_input)
+            .await
+            .unwrap();
+        let mut metadata_segment_writer = written.metadata_segment_writer;
+        metadata_segment_writer.write_to_blockfiles().await.unwrap();
+
+        let flush_input = FlushS3Input::new(
+            written.record_segment_writer,
+            written.hnsw_segment_writer,
+            metadata_segment_writer,
+        );
+        let flushed = FlushS3Operator::new().run(&flush_input).await.unwrap();
+        assert_eq!(flushed.segment_flush_info.len(), 3);
+        for segment in [
+            &mut record_segment,
+            &mut hnsw_segment,
+            &mut metadata_segment,
+        ] {
+            let flush_info = flushed
+                .segment_flush_info
+                .iter()
+                .find(|info| info.segment_id == segment.id)
+                .unwrap();
+            assert!(!flush_info.file_paths.is_empty());
+            segment.file_path = flush_info.file_paths.clone();
+        }
+
+        // Readers over fresh providers only see what was flushed to storage.
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(ArrowBlockfileProvider::new(storage.clone()));
+        let hnsw_tmp_dir = tempfile::tempdir().unwrap();
+        let hnsw_provider = HnswIndexProvider::new(
+            storage,
+            hnsw_tmp_dir.path().to_path_buf(),
+            &hnsw_provider_config,
+        );
+
+        let record_segment_reader =
+            RecordSegmentReader::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        let mut data = record_segment_reader.get_all_data().await.unwrap();
+        data.sort_by_key(|record| record.id.to_string());
+        assert_eq!(data.len(), 10);
+        for (i, record) in data.iter().enumerate() {
+            assert_eq!(record.id, format!("embedding_id_{}", i));
+            assert_eq!(record.embedding, &[i as f32, 1.0]);
+            assert_eq!(
+                record.document,
+                Some(format!("document number {}", i).as_str())
+            );
+        }
+
+        let hnsw_segment_reader = DistributedHNSWSegmentReader::from_segment(
+            &hnsw_segment,
+            2,
+            hnsw_provider,
+            &record_segment,
+            &blockfile_provider,
+        )
+        .await
+        .unwrap();
+        let query = KnnQuery {
+            embedding: vec![3.0, 1.0],
+            k: 1,
+        };
+        let (offset_ids, _) = hnsw_segment_reader
+            .query_batch(&[query], &[], &[], None, &ComputePool::new(1).unwrap())
+            .remove(0);
+        assert_eq!(offset_ids.len(), 1);
+        assert_eq!(
+            record_segment_reader
+                .get_user_id_for_offset_id(offset_ids[0] as u32)
+                .await
+                .unwrap(),
+            "embedding_id_3"
+        );
+
+        let metadata_segment_reader =
+            MetadataSegmentReader::from_segment(&metadata_segment, &blockfile_provider)
+                .await
+                .unwrap();
+        let matches = metadata_segment_reader
+            .full_text_index_reader
+            .as_ref()
+            .unwrap()
+            .search("number 7")
+            .await
+            .unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(
+            record_segment_reader
+                .get_user_id_for_offset_id(matches[0] as u32)
+                .await
+                .unwrap(),
+            "embedding_id_7"
+        );
+    }
+}
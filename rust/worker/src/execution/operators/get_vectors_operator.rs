@@ -2,8 +2,11 @@ use crate::{
     blockstore::provider::BlockfileProvider,
     errors::{ChromaError, ErrorCodes},
     execution::{data::data_chunk::Chunk, operator::Operator},
-    segment::record_segment::{self, RecordSegmentReader},
-    types::{LogRecord, Segment},
+    segment::{
+        record_segment::{self, RecordSegmentReader},
+        LogMaterializer, LogMaterializerError,
+    },
+    types::{LogRecord, Operation, Segment},
 };
 use async_trait::async_trait;
 use std::collections::{HashMap, HashSet};
@@ -51,13 +54,13 @@ impl GetVectorsOperatorInput {
 /// The output of the get vectors operator.
 /// # Parameters
 /// * `ids` - The ids of the vectors.
-/// * `vectors` - The vectors.
+/// * `vectors` - The vectors, None for the ids that have no record.
 /// # Notes
-/// The vectors are in the same order as the ids.
+/// There is an id and a vector for every search user id, in the same order.
 #[derive(Debug)]
 pub struct GetVectorsOperatorOutput {
     pub(crate) ids: Vec<String>,
-    pub(crate) vectors: Vec<Vec<f32>>,
+    pub(crate) vectors: Vec<Option<Vec<f32>>>,
 }
 
 #[derive(Debug, Error)]
@@ -68,11 +71,16 @@ pub enum GetVectorsOperatorError {
     ),
     #[error(transparent)]
     RecordSegmentReaderError(#[from] Box<dyn ChromaError>),
+    #[error("Error materializing log records {0}")]
+    LogMaterializationError(#[from] LogMaterializerError),
 }
 
 impl ChromaError for GetVectorsOperatorError {
     fn code(&self) -> ErrorCodes {
-        ErrorCodes::Internal
+        match self {
+            GetVectorsOperatorError::LogMaterializationError(e) => e.code(),
+            _ => ErrorCodes::Internal,
+        }
     }
 }
 
@@ -84,200 +92,230 @@ impl Operator<GetVectorsOperatorInput, GetVectorsOperatorOutput> for GetVectorsO
         &self,
         input: &GetVectorsOperatorInput,
     ) -> Result<GetVectorsOperatorOutput, Self::Error> {
-        let mut output_vectors = HashMap::new();
-
-        let record_segment_reader = match RecordSegmentReader::from_segment(
-            &input.record_segment_definition,
-            &input.blockfile_provider,
-        )
-        .await
-        {
-            Ok(reader) => Some(reader),
-            Err(e) => match *e {
-                record_segment::RecordSegmentReaderCreationError::UninitializedSegment => None,
-                record_segment::RecordSegmentReaderCreationError::BlockfileOpenError(_) => {
-                    return Err(GetVectorsOperatorError::RecordSegmentReaderCreationError(
-                        *e,
-                    ))
-                }
-                record_segment::RecordSegmentReaderCreationError::InvalidNumberOfFiles => {
-                    return Err(GetVectorsOperatorError::RecordSegmentReaderCreationError(
+        let open_record_segment = || async {
+            match RecordSegmentReader::from_segment(
+                &input.record_segment_definition,
+                &input.blockfile_provider,
+            )
+            .await
+            {
+                Ok(reader) => Ok(Some(reader)),
+                Err(e) => match *e {
+                    record_segment::RecordSegmentReaderCreationError::UninitializedSegment => {
+                        Ok(None)
+                    }
+                    _ => Err(GetVectorsOperatorError::RecordSegmentReaderCreationError(
                         *e,
-                    ))
-                }
-            },
+                    )),
+                },
+            }
         };
 
-        // Search the log records for the user ids
-        let logs = input.log_records.clone();
-        let mut remaining_search_user_ids: HashSet<String> =
-            HashSet::from_iter(input.search_user_ids.iter().cloned());
-        let mut original_search_user_ids: HashSet<String> =
-            HashSet::from_iter(input.search_user_ids.iter().cloned());
-        for (log_record, _) in logs.iter() {
-            if original_search_user_ids.contains(&log_record.record.id) {
-                match log_record.record.operation {
-                    crate::types::Operation::Add => {
-                        // If there is a record segment, validate the add
-                        if let Some(ref reader) = record_segment_reader {
-                            match reader.data_exists_for_user_id(&log_record.record.id).await {
-                                Ok(true) => {
-                                    // The record exists in the record segment, so this add is faulty
-                                    // and we should skip it
-                                    continue;
-                                }
-                                Ok(false) => {
-                                    // The record does not exist in the record segment,
-                                    // the add is valid
+        // Only the log records of the search user ids can change their vectors.
+        let search_user_ids: HashSet<&str> =
+            input.search_user_ids.iter().map(String::as_str).collect();
+        let logs: Vec<LogRecord> = input
+            .log_records
+            .iter()
+            .filter(|(log_record, _)| search_user_ids.contains(log_record.record.id.as_str()))
+            .map(|(log_record, _)| log_record.clone())
+            .collect();
 
-                                    // If the user id is already present in the log set, skip it
-                                    // We use the first add log entry for a user id if a
-                                    // user has multiple log entries
-                                    if output_vectors.contains_key(&log_record.record.id) {
-                                        continue;
-                                    }
-                                    let vector = log_record.record.embedding.as_ref().expect("Invariant violation. The log record for an add does not have an embedding.");
-                                    output_vectors
-                                        .insert(log_record.record.id.clone(), vector.clone());
-                                    remaining_search_user_ids.remove(&log_record.record.id);
-                                }
-                                Err(e) => {
-                                    // If there is an error, we skip the add
-                                    return Err(GetVectorsOperatorError::RecordSegmentReaderError(
-                                        e.into(),
-                                    ));
-                                }
-                            }
-                        } else {
-                            // Record segment is uninitialized.
-                            // If the user id is already present in the log set, skip it
-                            // We use the first add log entry for a user id if a
-                            // user has multiple log entries
-                            if output_vectors.contains_key(&log_record.record.id) {
-                                continue;
-                            }
-                            let vector = log_record.record.embedding.as_ref().expect("Invariant violation. The log record for an add does not have an embedding.");
-                            output_vectors.insert(log_record.record.id.clone(), vector.clone());
-                            remaining_search_user_ids.remove(&log_record.record.id);
-                        }
-                    }
-                    crate::types::Operation::Update => {
-                        // If there is a record segment, validate the update
-                        if let Some(ref reader) = record_segment_reader {
-                            match reader.data_exists_for_user_id(&log_record.record.id).await {
-                                Ok(true) => {
-                                    // The record exists in the record segment, so this update is valid
-                                    // and we should include it in the output
-
-                                    // If the update mutates the vector, we need to update the output
-                                    match &log_record.record.embedding {
-                                        Some(vector) => {
-                                            // This will overwrite the vector if it already exists
-                                            // (e.g if it was added previously in the log)
-                                            output_vectors.insert(
-                                                log_record.record.id.clone(),
-                                                vector.clone(),
-                                            );
-                                            remaining_search_user_ids.remove(&log_record.record.id);
-                                        }
-                                        None => {
-                                            // Nothing to do with this as the vector was not updated
-                                        }
-                                    }
-                                }
-                                Ok(false) => {
-                                    // The record does not exist in the record segment,
-                                    // If the user id is present in the output set then it means
-                                    // that it was inserted previously. Update the embedding
-                                    // if it exists.
-                                    if !output_vectors.contains_key(&log_record.record.id) {
-                                        continue;
-                                    }
-                                    match &log_record.record.embedding {
-                                        Some(vector) => {
-                                            // This will overwrite the vector if it already exists
-                                            // (e.g if it was added previously in the log)
-                                            output_vectors.insert(
-                                                log_record.record.id.clone(),
-                                                vector.clone(),
-                                            );
-                                            remaining_search_user_ids.remove(&log_record.record.id);
-                                        }
-                                        None => {
-                                            // Nothing to do with this as the vector was not updated
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    // If there is an error, we skip the update
-                                    return Err(GetVectorsOperatorError::RecordSegmentReaderError(
-                                        e.into(),
-                                    ));
-                                }
-                            }
-                        } else {
-                            // Record segment is uninitialized.
-                            // If the user id is present in the output set then it means
-                            // that it was inserted previously. Update the embedding
-                            // if it exists.
-                            if !output_vectors.contains_key(&log_record.record.id) {
-                                continue;
-                            }
-                            match &log_record.record.embedding {
-                                Some(vector) => {
-                                    // This will overwrite the vector if it already exists
-                                    // (e.g if it was added previously in the log)
-                                    output_vectors
-                                        .insert(log_record.record.id.clone(), vector.clone());
-                                    remaining_search_user_ids.remove(&log_record.record.id);
-                                }
-                                None => {
-                                    // Nothing to do with this as the vector was not updated
-                                }
-                            }
-                        }
-                    }
-                    crate::types::Operation::Upsert => {
-                        // The upsert operation does not allow embeddings to be None
-                        // So the final value is always present in the log
-                        let vector = log_record.record.embedding.as_ref().expect("Invariant violation. The log record for an upsert does not have an embedding.");
-                        output_vectors.insert(log_record.record.id.clone(), vector.clone());
-                        remaining_search_user_ids.remove(&log_record.record.id);
-                    }
-                    crate::types::Operation::Delete => {
-                        // If the user id is present in the output, remove it
-                        output_vectors.remove(&log_record.record.id);
-                        remaining_search_user_ids.remove(&log_record.record.id);
-                    }
-                }
+        // The log has the final say for the ids it has records of, including the ones it
+        // deleted. A record materializes as a delete of the id in the record segment and,
+        // if the id was added again afterwards, as a new record.
+        let mut vectors: HashMap<String, Option<Vec<f32>>> = HashMap::new();
+        let materializer =
+            LogMaterializer::new(open_record_segment().await?, Chunk::new(logs.into()), None);
+        let materialized = materializer.materialize().await?;
+        for (record, _) in materialized.iter() {
+            if record.final_operation == Operation::Delete {
+                vectors.entry(record.merged_user_id()).or_insert(None);
+            } else {
+                vectors.insert(
+                    record.merged_user_id(),
+                    Some(record.merged_embeddings().to_f32().into_owned()),
+                );
             }
         }
 
-        // Search the record segment for the remaining user ids
-        if !remaining_search_user_ids.is_empty() {
-            if let Some(reader) = record_segment_reader {
-                for user_id in remaining_search_user_ids.iter() {
-                    let read_data = reader.get_data_and_offset_id_for_user_id(user_id).await;
-                    match read_data {
-                        Ok((record, _)) => {
-                            output_vectors.insert(record.id.to_string(), record.embedding.to_vec());
-                        }
-                        Err(_) => {
-                            // If the user id is not found in the record segment, we do not add it to the output
-                        }
-                    }
+        // The other ids are read from the record segment.
+        if let Some(reader) = open_record_segment().await? {
+            for user_id in &input.search_user_ids {
+                if vectors.contains_key(user_id) {
+                    continue;
                 }
+                let vector = match reader.data_exists_for_user_id(user_id).await? {
+                    true => {
+                        let (record, _) =
+                            reader.get_data_and_offset_id_for_user_id(user_id).await?;
+                        Some(record.embedding.to_vec())
+                    }
+                    false => None,
+                };
+                vectors.insert(user_id.clone(), vector);
             }
         }
 
-        let mut ids = Vec::new();
-        let mut vectors = Vec::new();
-        for id in &input.search_user_ids {
-            if output_vectors.contains_key(id) {
-                ids.push(id.clone());
-                vectors.push(output_vectors.remove(id).unwrap());
-            }
+        let ids = input.search_user_ids.clone();
+        let vectors = ids
+            .iter()
+            .map(|id| vectors.get(id).cloned().flatten())
+            .collect();
+        Ok(GetVectorsOperatorOutput { ids, vectors })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::record_segment::RecordSegmentWriter;
+    use crate::segment::{SegmentFlusher, SegmentWriter};
+    use crate::storage::{local::LocalStorage, Storage};
+    use crate::types::{OperationRecord, SegmentScope, SegmentType};
+    use uuid::Uuid;
+
+    fn log_record(log_offset: i64, id: &str, operation: Operation, value: f32) -> LogRecord {
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: match operation {
+                    Operation::Delete => None,
+                    _ => Some(vec![value, value].into()),
+                },
+                encoding: None,
+                metadata: None,
+                document: None,
+                operation,
+            },
         }
-        return Ok(GetVectorsOperatorOutput { ids, vectors });
+    }
+
+    // A record segment with the given records compacted into it.
+    async fn record_segment(
+        blockfile_provider: &BlockfileProvider,
+        records: Vec<LogRecord>,
+    ) -> Segment {
+        let mut segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::BlockfileRecord,
+            scope: SegmentScope::RECORD,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let writer = RecordSegmentWriter::from_segment(&segment, blockfile_provider)
+            .await
+            .unwrap();
+        let materializer = LogMaterializer::new(None, Chunk::new(records.into()), None);
+        let materialized = materializer.materialize().await.unwrap();
+        writer
+            .apply_materialized_log_chunk(materialized)
+            .await
+            .unwrap();
+        segment.file_path = writer.commit().unwrap().flush().await.unwrap();
+        segment
+    }
+
+    async fn get_vectors(
+        blockfile_provider: BlockfileProvider,
+        segment: Segment,
+        logs: Vec<LogRecord>,
+        ids: &[&str],
+    ) -> Vec<Option<Vec<f32>>> {
+        let input = GetVectorsOperatorInput::new(
+            segment,
+            blockfile_provider,
+            Chunk::new(logs.into()),
+            ids.iter().map(|id| id.to_string()).collect(),
+        );
+        let output = GetVectorsOperator::new().run(&input).await.unwrap();
+        assert_eq!(output.ids, ids);
+        output.vectors
+    }
+
+    #[tokio::test]
+    async fn test_get_vectors_of_found_and_missing_ids() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let segment = record_segment(
+            &blockfile_provider,
+            vec![
+                log_record(1, "a", Operation::Add, 1.0),
+                log_record(2, "b", Operation::Add, 2.0),
+            ],
+        )
+        .await;
+        let logs = vec![log_record(3, "c", Operation::Add, 3.0)];
+
+        let vectors = get_vectors(
+            blockfile_provider,
+            segment,
+            logs,
+            &["c", "missing", "a", "missing"],
+        )
+        .await;
+        assert_eq!(
+            vectors,
+            vec![Some(vec![3.0, 3.0]), None, Some(vec![1.0, 1.0]), None]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_vectors_of_ids_changed_after_compaction() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let segment = record_segment(
+            &blockfile_provider,
+            vec![
+                log_record(1, "a", Operation::Add, 1.0),
+                log_record(2, "b", Operation::Add, 2.0),
+                log_record(3, "c", Operation::Add, 3.0),
+            ],
+        )
+        .await;
+        let logs = vec![
+            log_record(4, "a", Operation::Delete, 0.0),
+            log_record(5, "b", Operation::Delete, 0.0),
+            log_record(6, "b", Operation::Add, 20.0),
+            log_record(7, "c", Operation::Update, 30.0),
+            // Adding a record that exists is ignored.
+            log_record(8, "c", Operation::Add, 300.0),
+        ];
+
+        let vectors = get_vectors(blockfile_provider, segment, logs, &["a", "b", "c"]).await;
+        assert_eq!(
+            vectors,
+            vec![None, Some(vec![20.0, 20.0]), Some(vec![30.0, 30.0])]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_vectors_before_the_first_compaction() {
+        let segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::BlockfileRecord,
+            scope: SegmentScope::RECORD,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let logs = vec![
+            log_record(1, "a", Operation::Add, 1.0),
+            log_record(2, "b", Operation::Add, 2.0),
+            log_record(3, "a", Operation::Delete, 0.0),
+        ];
+
+        let vectors = get_vectors(
+            BlockfileProvider::new_memory(),
+            segment,
+            logs,
+            &["a", "b", "c"],
+        )
+        .await;
+        assert_eq!(vectors, vec![None, Some(vec![2.0, 2.0]), None]);
     }
 }
@@ -1,5 +1,6 @@
+use crate::execution::compute_pool::ComputePool;
 use crate::execution::data::data_chunk::Chunk;
-use crate::types::{LogRecord, Operation};
+use crate::types::{KnnQuery, LogRecord, Operation};
 use crate::{
     blockstore::provider::BlockfileProvider,
     errors::{ChromaError, ErrorCodes},
@@ -10,6 +11,7 @@ use crate::{
     types::Segment,
 };
 use async_trait::async_trait;
+use std::collections::HashSet;
 use std::sync::Arc;
 use thiserror::Error;
 
@@ -19,8 +21,10 @@ pub struct HnswKnnOperator {}
 #[derive(Debug)]
 pub struct HnswKnnOperatorInput {
     pub segment: Box<DistributedHNSWSegmentReader>,
-    pub query: Vec<f32>,
-    pub k: usize,
+    // Searched together, sharing the filtering below and a single hold of the index lock.
+    pub queries: Arc<[KnnQuery]>,
+    // Overrides the index's configured ef when set.
+    pub ef_search: Option<usize>,
     pub record_segment: Segment,
     pub blockfile_provider: BlockfileProvider,
     pub allowed_ids: Arc<[String]>,
@@ -28,15 +32,38 @@ pub struct HnswKnnOperatorInput {
     // ids that are allowed but not present in the log
     // thus present in the segment.
     pub allowed_ids_hnsw: Arc<[String]>,
+    // Sorted offset ids of the records that match the query's where and where_document
+    // clauses. None when the query has neither.
+    pub allowed_offset_ids: Option<Arc<[u32]>>,
     pub logs: Chunk<LogRecord>,
+    pub compute_pool: ComputePool,
 }
 
 #[derive(Debug)]
-pub struct HnswKnnOperatorOutput {
+pub struct HnswKnnResult {
     pub offset_ids: Vec<usize>,
     pub distances: Vec<f32>,
 }
 
+#[derive(Debug)]
+pub struct HnswKnnOperatorOutput {
+    // One result per query, in the order of the input's queries.
+    pub results: Vec<HnswKnnResult>,
+}
+
+impl HnswKnnOperatorOutput {
+    fn empty(queries: usize) -> Self {
+        HnswKnnOperatorOutput {
+            results: (0..queries)
+                .map(|_| HnswKnnResult {
+                    offset_ids: vec![],
+                    distances: vec![],
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum HnswKnnOperatorError {
     #[error("Error creating Record Segment")]
@@ -58,26 +85,39 @@ impl ChromaError for HnswKnnOperatorError {
 }
 
 impl HnswKnnOperator {
+    // The offset ids of the compacted records that the log deletes or changes. The log has
+    // the final say on them, they are scored by brute force from it if they still exist.
+    // Records that were only ever added to the log are not in the segment and are skipped.
     async fn get_disallowed_ids(
         &self,
         logs: Chunk<LogRecord>,
         record_segment_reader: &RecordSegmentReader<'_>,
     ) -> Result<Vec<u32>, Box<dyn ChromaError>> {
         let mut disallowed_ids = Vec::new();
+        let mut seen = HashSet::new();
         for item in logs.iter() {
             let log = item.0;
             let operation_record = &log.record;
-            if operation_record.operation == Operation::Delete
-                || operation_record.operation == Operation::Update
+            if operation_record.operation == Operation::Add
+                || !seen.insert(operation_record.id.as_str())
             {
-                let offset_id = record_segment_reader
-                    .get_offset_id_for_user_id(&operation_record.id)
-                    .await;
-                match offset_id {
-                    Ok(offset_id) => disallowed_ids.push(offset_id),
-                    Err(e) => {
-                        return Err(e);
-                    }
+                continue;
+            }
+            match record_segment_reader
+                .data_exists_for_user_id(&operation_record.id)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => return Err(e),
+            }
+            let offset_id = record_segment_reader
+                .get_offset_id_for_user_id(&operation_record.id)
+                .await;
+            match offset_id {
+                Ok(offset_id) => disallowed_ids.push(offset_id),
+                Err(e) => {
+                    return Err(e);
                 }
             }
         }
@@ -112,10 +152,7 @@ impl Operator<HnswKnnOperatorInput, HnswKnnOperatorOutput> for HnswKnnOperator {
         // If a filter list is supplied but it does not have anything for the segment, as it implies the data is all in the log
         // then return an empty response.
         if !input.allowed_ids.is_empty() && input.allowed_ids_hnsw.is_empty() {
-            return Ok(HnswKnnOperatorOutput {
-                offset_ids: vec![],
-                distances: vec![],
-            });
+            return Ok(HnswKnnOperatorOutput::empty(input.queries.len()));
         }
         let record_segment_reader = match RecordSegmentReader::from_segment(
             &input.record_segment,
@@ -178,21 +215,132 @@ impl Operator<HnswKnnOperatorInput, HnswKnnOperatorOutput> for HnswKnnOperator {
             }
         };
 
+        // Narrow the search down to the records matching the where clauses. Records that
+        // were changed in the log are searched by brute force instead.
+        if let Some(filtered_offset_ids) = &input.allowed_offset_ids {
+            let disallowed: HashSet<u32> = disallowed_offset_ids.iter().copied().collect();
+            let mut filtered_offset_ids: Vec<u32> = filtered_offset_ids
+                .iter()
+                .copied()
+                .filter(|offset_id| !disallowed.contains(offset_id))
+                .collect();
+            if !input.allowed_ids.is_empty() {
+                let allowed: HashSet<u32> = allowed_offset_ids.iter().copied().collect();
+                filtered_offset_ids.retain(|offset_id| allowed.contains(offset_id));
+            }
+            // An empty allowed list would not filter anything, so nothing matches.
+            if filtered_offset_ids.is_empty() {
+                return Ok(HnswKnnOperatorOutput::empty(input.queries.len()));
+            }
+            allowed_offset_ids = filtered_offset_ids;
+        }
+
         // Convert to usize
         let allowed_offset_ids: Vec<usize> =
             allowed_offset_ids.iter().map(|&x| x as usize).collect();
         let disallowed_offset_ids: Vec<usize> =
             disallowed_offset_ids.iter().map(|&x| x as usize).collect();
 
-        let (offset_ids, distances) = input.segment.query(
-            &input.query,
-            input.k,
-            &allowed_offset_ids,
-            &disallowed_offset_ids,
+        let results = input
+            .segment
+            .query_batch(
+                &input.queries,
+                &allowed_offset_ids,
+                &disallowed_offset_ids,
+                input.ef_search,
+                &input.compute_pool,
+            )
+            .into_iter()
+            .map(|(offset_ids, distances)| HnswKnnResult {
+                offset_ids,
+                distances,
+            })
+            .collect();
+        Ok(HnswKnnOperatorOutput { results })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::record_segment::RecordSegmentWriter;
+    use crate::segment::{LogMaterializer, SegmentFlusher, SegmentWriter};
+    use crate::storage::{local::LocalStorage, Storage};
+    use crate::types::{OperationRecord, SegmentScope, SegmentType};
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn log_record(log_offset: i64, id: &str, operation: Operation) -> LogRecord {
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: match operation {
+                    Operation::Delete => None,
+                    _ => Some(vec![log_offset as f32, 0.0].into()),
+                },
+                encoding: None,
+                metadata: None,
+                document: None,
+                operation,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_changed_in_the_log_are_disallowed() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let mut record_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::BlockfileRecord,
+            scope: SegmentScope::RECORD,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let compacted = Chunk::new(
+            vec![
+                log_record(1, "segment_1", Operation::Add),
+                log_record(2, "segment_2", Operation::Add),
+                log_record(3, "segment_3", Operation::Add),
+            ]
+            .into(),
+        );
+        let writer = RecordSegmentWriter::from_segment(&record_segment, &blockfile_provider)
+            .await
+            .unwrap();
+        let materializer = LogMaterializer::new(None, compacted, None);
+        let materialized = materializer.materialize().await.unwrap();
+        writer
+            .apply_materialized_log_chunk(materialized)
+            .await
+            .unwrap();
+        record_segment.file_path = writer.commit().unwrap().flush().await.unwrap();
+        let reader = RecordSegmentReader::from_segment(&record_segment, &blockfile_provider)
+            .await
+            .unwrap();
+
+        // Records that are only in the log are not in the segment to be disallowed, and
+        // adding a record that was compacted changes nothing.
+        let log = Chunk::new(
+            vec![
+                log_record(4, "log_1", Operation::Add),
+                log_record(5, "log_1", Operation::Update),
+                log_record(6, "log_1", Operation::Delete),
+                log_record(7, "segment_1", Operation::Upsert),
+                log_record(8, "segment_2", Operation::Delete),
+                log_record(9, "segment_2", Operation::Upsert),
+                log_record(10, "segment_3", Operation::Add),
+                log_record(11, "missing", Operation::Update),
+            ]
+            .into(),
         );
-        Ok(HnswKnnOperatorOutput {
-            offset_ids,
-            distances,
-        })
+        let disallowed = HnswKnnOperator {}
+            .get_disallowed_ids(log, &reader)
+            .await
+            .unwrap();
+        assert_eq!(disallowed, vec![1, 2]);
     }
 }
@@ -1,25 +1,48 @@
+use super::brute_force_knn::BruteForceKnnResult;
 use crate::{
     blockstore::provider::BlockfileProvider,
+    distance::DistanceFunction,
     errors::ChromaError,
     execution::operator::Operator,
     segment::record_segment::{RecordSegmentReader, RecordSegmentReaderCreationError},
-    types::Segment,
+    types::{QueryInclude, Segment, VectorQueryResult},
 };
 use async_trait::async_trait;
+use std::collections::HashSet;
 use thiserror::Error;
 
 #[derive(Debug)]
 pub struct MergeKnnResultsOperator {}
 
+/// What the merged results of a query are made of.
+/// # Fields
+/// - include: The fields to return with each result.
+/// - k: The number of results to return.
+/// - distance_function: The distance function of the collection, None when the HNSW index
+///   can not be built with it, in which case there are no HNSW results to convert.
+/// - return_similarity: Whether to return similarities instead of distances, only set for
+///   distance functions that define one.
+#[derive(Debug)]
+pub struct MergeKnnOptions {
+    pub include: QueryInclude,
+    pub k: usize,
+    pub distance_function: Option<DistanceFunction>,
+    pub return_similarity: bool,
+}
+
 #[derive(Debug)]
 pub struct MergeKnnResultsOperatorInput {
     hnsw_result_offset_ids: Vec<usize>,
     hnsw_result_distances: Vec<f32>,
-    brute_force_result_user_ids: Vec<String>,
-    brute_force_result_distances: Vec<f32>,
-    brute_force_result_vectors: Option<Vec<Vec<f32>>>,
-    include_vectors: bool,
+    brute_force_result: BruteForceKnnResult,
+    include: QueryInclude,
     k: usize,
+    // None when the distance is one the HNSW index can not be built with, in which case there
+    // are no HNSW results to convert.
+    distance_function: Option<DistanceFunction>,
+    // Whether to return similarities instead of distances, only set for distance functions
+    // that define one.
+    return_similarity: bool,
     record_segment_definition: Segment,
     blockfile_provider: BlockfileProvider,
 }
@@ -28,22 +51,25 @@ impl MergeKnnResultsOperatorInput {
     pub fn new(
         hnsw_result_offset_ids: Vec<usize>,
         hnsw_result_distances: Vec<f32>,
-        brute_force_result_user_ids: Vec<String>,
-        brute_force_result_distances: Vec<f32>,
-        brute_force_result_vectors: Option<Vec<Vec<f32>>>,
-        include_vectors: bool,
-        k: usize,
+        brute_force_result: BruteForceKnnResult,
+        options: MergeKnnOptions,
         record_segment_definition: Segment,
         blockfile_provider: BlockfileProvider,
     ) -> Self {
+        let MergeKnnOptions {
+            include,
+            k,
+            distance_function,
+            return_similarity,
+        } = options;
         Self {
             hnsw_result_offset_ids,
             hnsw_result_distances,
-            brute_force_result_user_ids,
-            brute_force_result_distances,
-            brute_force_result_vectors,
-            include_vectors,
+            brute_force_result,
+            include,
             k,
+            distance_function,
+            return_similarity,
             record_segment_definition,
             blockfile_provider: blockfile_provider,
         }
@@ -52,9 +78,7 @@ impl MergeKnnResultsOperatorInput {
 
 #[derive(Debug)]
 pub struct MergeKnnResultsOperatorOutput {
-    pub user_ids: Vec<String>,
-    pub distances: Vec<f32>,
-    pub vectors: Option<Vec<Vec<f32>>>,
+    pub results: Vec<VectorQueryResult>,
 }
 
 #[derive(Error, Debug)]
@@ -76,174 +100,696 @@ impl Operator<MergeKnnResultsOperatorInput, MergeKnnResultsOperatorOutput>
         &self,
         input: &MergeKnnResultsOperatorInput,
     ) -> Result<MergeKnnResultsOperatorOutput, Self::Error> {
-        let (result_user_ids, result_distances, result_vectors) =
-            match RecordSegmentReader::from_segment(
-                &input.record_segment_definition,
-                &input.blockfile_provider,
-            )
-            .await
+        let reader = match RecordSegmentReader::from_segment(
+            &input.record_segment_definition,
+            &input.blockfile_provider,
+        )
+        .await
+        {
+            Ok(reader) => Some(reader),
+            Err(e) => match *e {
+                RecordSegmentReaderCreationError::BlockfileOpenError(e) => {
+                    return Err(e);
+                }
+                RecordSegmentReaderCreationError::InvalidNumberOfFiles => {
+                    return Err(e);
+                }
+                // The record segment doesn't exist - which implies no HNSW results
+                RecordSegmentReaderCreationError::UninitializedSegment => None,
+            },
+        };
+        let brute_force_result = &input.brute_force_result;
+        // The log has the final say on a record. The HNSW search already skips the records
+        // the log changes, but should a user id still be in both lists, its HNSW result is
+        // dropped whatever its distance.
+        let brute_force_user_ids: HashSet<&str> = brute_force_result
+            .user_ids
+            .iter()
+            .map(String::as_str)
+            .collect();
+        let mut hnsw_results = Vec::new();
+        if let Some(reader) = &reader {
+            for (offset_id, distance) in input
+                .hnsw_result_offset_ids
+                .iter()
+                .zip(input.hnsw_result_distances.iter())
             {
-                Ok(reader) => {
-                    println!("Record Segment Reader created successfully");
-                    // Convert the HNSW result offset IDs to user IDs
-                    let mut hnsw_result_user_ids = Vec::new();
-                    let mut hnsw_result_vectors = None;
-                    if input.include_vectors {
-                        hnsw_result_vectors = Some(Vec::new());
-                    }
+                let offset_id = *offset_id as u32;
+                let id = reader.get_user_id_for_offset_id(offset_id).await?;
+                if !brute_force_user_ids.contains(id) {
+                    hnsw_results.push((offset_id, id.to_string(), *distance));
+                }
+            }
+        }
+        let hnsw_result_distances: Vec<f32> = hnsw_results
+            .iter()
+            .map(|(_, _, distance)| *distance)
+            .collect();
+        let merged = merge_results(
+            &hnsw_result_distances,
+            &brute_force_result.distances,
+            input.k,
+            input.distance_function.as_ref(),
+        );
 
-                    for offset_id in &input.hnsw_result_offset_ids {
-                        let user_id = reader.get_user_id_for_offset_id(*offset_id as u32).await;
-                        match user_id {
-                            Ok(user_id) => hnsw_result_user_ids.push(user_id),
-                            Err(e) => return Err(e),
+        // Only the k merged results are hydrated, the documents and metadatas of the HNSW
+        // results are read from the record segment, those of the brute force results come
+        // from the log.
+        let include = input.include;
+        let mut results = Vec::with_capacity(merged.len());
+        for (source, distance) in merged {
+            let distance = match (&input.distance_function, input.return_similarity) {
+                (Some(distance_function), true) => {
+                    distance_function.distance_to_similarity(distance)
+                }
+                _ => distance,
+            };
+            let result = match source {
+                MergedResult::Hnsw(index) => {
+                    let reader = reader
+                        .as_ref()
+                        .expect("HNSW results are only merged when there is a record segment");
+                    let (offset_id, id, _) = &hnsw_results[index];
+                    let offset_id = *offset_id;
+                    let mut result = VectorQueryResult {
+                        id: id.clone(),
+                        distance,
+                        vector: None,
+                        metadata: None,
+                        document: None,
+                    };
+                    if include.embeddings || include.metadatas || include.documents {
+                        let record = reader.get_data_for_offset_id(offset_id).await?;
+                        if include.embeddings {
+                            result.vector = Some(record.embedding.to_vec());
                         }
-                        if let Some(hnsw_result_vectors) = &mut hnsw_result_vectors {
-                            let record = reader.get_data_for_offset_id(*offset_id as u32).await;
-                            match record {
-                                Ok(record) => hnsw_result_vectors.push(record.embedding.to_vec()),
-                                Err(e) => return Err(e),
-                            }
+                        if include.metadatas {
+                            result.metadata = record.metadata;
+                        }
+                        if include.documents {
+                            result.document = record.document.map(str::to_string);
                         }
                     }
-                    merge_results(
-                        &hnsw_result_user_ids,
-                        &input.hnsw_result_distances,
-                        &hnsw_result_vectors,
-                        &input.brute_force_result_user_ids,
-                        &input.brute_force_result_distances,
-                        &input.brute_force_result_vectors,
-                        input.include_vectors,
-                        input.k,
-                    )
+                    result
                 }
-                Err(e) => match *e {
-                    RecordSegmentReaderCreationError::BlockfileOpenError(e) => {
-                        return Err(e);
-                    }
-                    RecordSegmentReaderCreationError::InvalidNumberOfFiles => {
-                        return Err(e);
-                    }
-                    RecordSegmentReaderCreationError::UninitializedSegment => {
-                        // The record segment doesn't exist - which implies no HNSW results
-                        let hnsw_result_user_ids = Vec::new();
-                        let hnsw_result_distances = Vec::new();
-                        let hnsw_result_vectors = None;
-                        merge_results(
-                            &hnsw_result_user_ids,
-                            &hnsw_result_distances,
-                            &hnsw_result_vectors,
-                            &input.brute_force_result_user_ids,
-                            &input.brute_force_result_distances,
-                            &input.brute_force_result_vectors,
-                            input.include_vectors,
-                            input.k,
-                        )
-                    }
+                MergedResult::BruteForce(index) => VectorQueryResult {
+                    id: brute_force_result.user_ids[index].clone(),
+                    distance,
+                    vector: match include.embeddings {
+                        true => Some(brute_force_result.embeddings[index].clone()),
+                        false => None,
+                    },
+                    metadata: brute_force_result.metadatas.get(index).cloned().flatten(),
+                    document: brute_force_result.documents.get(index).cloned().flatten(),
                 },
             };
+            results.push(result);
+        }
 
-        Ok(MergeKnnResultsOperatorOutput {
-            user_ids: result_user_ids,
-            distances: result_distances,
-            vectors: result_vectors,
-        })
+        Ok(MergeKnnResultsOperatorOutput { results })
     }
 }
 
+// Which of the two result lists a merged result comes from, and its position in that list.
+#[derive(Debug)]
+enum MergedResult {
+    Hnsw(usize),
+    BruteForce(usize),
+}
+
 fn merge_results(
-    hnsw_result_user_ids: &Vec<&str>,
-    hnsw_result_distances: &Vec<f32>,
-    hnsw_result_vectors: &Option<Vec<Vec<f32>>>,
-    brute_force_result_user_ids: &Vec<String>,
-    brute_force_result_distances: &Vec<f32>,
-    brute_force_result_vectors: &Option<Vec<Vec<f32>>>,
-    include_vectors: bool,
+    hnsw_result_distances: &[f32],
+    brute_force_result_distances: &[f32],
     k: usize,
-) -> (Vec<String>, Vec<f32>, Option<Vec<Vec<f32>>>) {
-    let mut result_user_ids = Vec::with_capacity(k);
-    let mut result_distances = Vec::with_capacity(k);
-    let mut result_vectors = None;
-    if include_vectors {
-        result_vectors = Some(Vec::with_capacity(k));
-    }
+    distance_function: Option<&DistanceFunction>,
+) -> Vec<(MergedResult, f32)> {
+    // Bring the raw HNSW scores onto the brute force scale before comparing them. The brute
+    // force distances go through the same conversion so that both are clamped alike.
+    let to_distance = |raw: &f32| match distance_function {
+        Some(distance_function) => distance_function.raw_to_distance(*raw),
+        None => *raw,
+    };
+    let hnsw_result_distances: Vec<f32> = hnsw_result_distances.iter().map(to_distance).collect();
+    let brute_force_result_distances: Vec<f32> = brute_force_result_distances
+        .iter()
+        .map(to_distance)
+        .collect();
 
     // Merge the HNSW and brute force results together by the minimum distance top k
+    let mut results = Vec::with_capacity(k);
     let mut hnsw_index = 0;
     let mut brute_force_index = 0;
+    while results.len() < k {
+        let take_hnsw = match (
+            hnsw_result_distances.get(hnsw_index),
+            brute_force_result_distances.get(brute_force_index),
+        ) {
+            (Some(hnsw), Some(brute_force)) => hnsw.total_cmp(brute_force).is_lt(),
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        if take_hnsw {
+            results.push((
+                MergedResult::Hnsw(hnsw_index),
+                hnsw_result_distances[hnsw_index],
+            ));
+            hnsw_index += 1;
+        } else {
+            results.push((
+                MergedResult::BruteForce(brute_force_index),
+                brute_force_result_distances[brute_force_index],
+            ));
+            brute_force_index += 1;
+        }
+    }
 
-    // TODO: This doesn't have to clone the user IDs, but it's easier for now
-    while (result_user_ids.len() <= k)
-        && (hnsw_index < hnsw_result_user_ids.len()
-            || brute_force_index < brute_force_result_user_ids.len())
-    {
-        if hnsw_index < hnsw_result_user_ids.len()
-            && brute_force_index < brute_force_result_user_ids.len()
-        {
-            if hnsw_result_distances[hnsw_index] < brute_force_result_distances[brute_force_index] {
-                result_user_ids.push(hnsw_result_user_ids[hnsw_index].to_string());
-                result_distances.push(hnsw_result_distances[hnsw_index]);
-                if include_vectors {
-                    result_vectors
-                        .as_mut()
-                        .expect("Include vectors is true, result_vectors should be Some")
-                        .push(
-                            hnsw_result_vectors.as_ref().expect(
-                                "Include vectors is true, hnsw_result_vectors should be Some",
-                            )[hnsw_index]
-                                .to_vec(),
-                        );
-                }
-                hnsw_index += 1;
-            } else {
-                result_user_ids.push(brute_force_result_user_ids[brute_force_index].to_string());
-                result_distances.push(brute_force_result_distances[brute_force_index]);
-                if include_vectors {
-                    result_vectors
-                        .as_mut()
-                        .expect("Include vectors is true, result_vectors should be Some")
-                        .push(
-                            brute_force_result_vectors.as_ref().expect(
-                                "Include vectors is true, brute_force_result_vectors should be Some",
-                            )[brute_force_index]
-                                .to_vec(),
-                        );
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::normalize;
+    use crate::execution::compute_pool::ComputePool;
+    use crate::execution::data::data_chunk::Chunk;
+    use crate::execution::deadline::Deadline;
+    use crate::execution::operators::brute_force_knn::{
+        BruteForceKnnOperator, BruteForceKnnOperatorInput,
+    };
+    use crate::index::{BruteForceIndex, HnswIndex, HnswIndexConfig, Index, IndexConfig};
+    use crate::segment::record_segment::RecordSegmentWriter;
+    use crate::segment::{LogMaterializer, SegmentFlusher, SegmentWriter};
+    use crate::storage::{local::LocalStorage, Storage};
+    use crate::types::{
+        KnnQuery, LogRecord, MetadataValue, Operation, OperationRecord, SegmentScope, SegmentType,
+        UpdateMetadataValue,
+    };
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn merge(
+        distance_function: &DistanceFunction,
+        hnsw_result_distances: &[f32],
+        brute_force_result_distances: &[f32],
+        k: usize,
+    ) -> (Vec<String>, Vec<f32>) {
+        merge_results(
+            hnsw_result_distances,
+            brute_force_result_distances,
+            k,
+            Some(distance_function),
+        )
+        .into_iter()
+        .map(|(source, distance)| match source {
+            MergedResult::Hnsw(index) => (format!("hnsw_{}", index), distance),
+            MergedResult::BruteForce(index) => (format!("brute_force_{}", index), distance),
+        })
+        .unzip()
+    }
+
+    #[test]
+    fn test_merge_orders_both_paths_consistently() {
+        // Raw HNSW scores, as the index reports them, and brute force distances for each of the
+        // distance functions the index supports. Both lists are sorted, as each path returns.
+        let cases = [
+            (
+                DistanceFunction::Euclidean,
+                vec![-1e-7, 0.5, 2.0, f32::NAN],
+                vec![0.0, 1.0, 1.5],
+            ),
+            (
+                DistanceFunction::Cosine,
+                vec![-1e-7, 0.2, 1.0, 2.0000002],
+                vec![0.0, 0.1, 1.2],
+            ),
+            (
+                DistanceFunction::InnerProduct,
+                vec![-5.0, -1.0, 0.5],
+                vec![-3.0, -1.0, 2.0],
+            ),
+        ];
+        for (distance_function, hnsw_result_distances, brute_force_result_distances) in cases {
+            let k = hnsw_result_distances.len() + brute_force_result_distances.len();
+            let (user_ids, distances) = merge(
+                &distance_function,
+                &hnsw_result_distances,
+                &brute_force_result_distances,
+                k,
+            );
+            assert_eq!(user_ids.len(), k);
+            for pair in distances.windows(2) {
+                assert!(
+                    pair[0] <= pair[1],
+                    "{:?} results are out of order: {:?}",
+                    distance_function,
+                    distances
+                );
+            }
+            let mut expected: Vec<f32> = hnsw_result_distances
+                .iter()
+                .chain(brute_force_result_distances.iter())
+                .map(|raw| distance_function.raw_to_distance(*raw))
+                .collect();
+            expected.sort_by(|a, b| a.total_cmp(b));
+            assert_eq!(distances, expected);
+        }
+    }
+
+    #[test]
+    fn test_merge_hnsw_and_brute_force_index_results() {
+        let (n, d, k) = (200, 8, 20);
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+        ] {
+            let mut data: Vec<Vec<f32>> = (0..n)
+                .map(|_| (0..d).map(|_| rand::random::<f32>() * 2.0 - 1.0).collect())
+                .collect();
+            if distance_function == DistanceFunction::Cosine {
+                data.iter_mut().for_each(|embedding| normalize(embedding));
+            }
+            let index_config = IndexConfig {
+                dimensionality: d as i32,
+                distance_function: distance_function.clone(),
+            };
+            // The first half of the records is in an HNSW index, the second in a brute force
+            // index.
+            let tmp_dir = tempfile::tempdir().unwrap();
+            let hnsw_index = HnswIndex::init(
+                &index_config,
+                Some(&HnswIndexConfig {
+                    max_elements: n,
+                    m: 16,
+                    ef_construction: 200,
+                    ef_search: 200,
+                    random_seed: 0,
+                    persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+                }),
+                Uuid::new_v4(),
+            )
+            .unwrap();
+            let brute_force_index =
+                BruteForceIndex::init(&index_config, None, Uuid::new_v4()).unwrap();
+            for (i, embedding) in data.iter().enumerate() {
+                if i < n / 2 {
+                    hnsw_index.add(i, embedding);
+                } else {
+                    brute_force_index.add(i, embedding);
                 }
-                brute_force_index += 1;
             }
-        } else if hnsw_index < hnsw_result_user_ids.len() {
-            result_user_ids.push(hnsw_result_user_ids[hnsw_index].to_string());
-            result_distances.push(hnsw_result_distances[hnsw_index]);
-            if include_vectors {
-                result_vectors
-                    .as_mut()
-                    .expect("Include vectors is true, result_vectors should be Some")
-                    .push(
-                        hnsw_result_vectors
-                            .as_ref()
-                            .expect("Include vectors is true, hnsw_result_vectors should be Some")
-                            [hnsw_index]
-                            .to_vec(),
-                    );
+
+            let query = &data[0];
+            let (_, hnsw_result_distances) = hnsw_index.query(query, k, &[], &[]);
+            let (_, brute_force_result_distances) = brute_force_index.query(query, k, &[], &[]);
+            let (user_ids, distances) = merge(
+                &distance_function,
+                &hnsw_result_distances,
+                &brute_force_result_distances,
+                k,
+            );
+            assert_eq!(user_ids.len(), k);
+
+            let mut expected: Vec<f32> = data
+                .iter()
+                .map(|embedding| distance_function.distance(query, embedding))
+                .collect();
+            expected.sort_by(|a, b| a.total_cmp(b));
+            for (distance, expected) in distances.iter().zip(expected.iter()) {
+                assert!(
+                    (distance - expected).abs() < 1e-5,
+                    "{:?} merged {:?}, expected {:?}",
+                    distance_function,
+                    distances,
+                    &expected
+                );
             }
-            hnsw_index += 1;
-        } else if brute_force_index < brute_force_result_user_ids.len() {
-            result_user_ids.push(brute_force_result_user_ids[brute_force_index].to_string());
-            result_distances.push(brute_force_result_distances[brute_force_index]);
-            if include_vectors {
-                result_vectors
-                    .as_mut()
-                    .expect("Include vectors is true, result_vectors should be Some")
-                    .push(
-                        brute_force_result_vectors.as_ref().expect(
-                            "Include vectors is true, brute_force_result_vectors should be Some",
-                        )[brute_force_index]
-                            .to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_merge_breaks_ties_in_favor_of_brute_force() {
+        // Identical vectors scored by both paths may differ by rounding on the HNSW side.
+        let (user_ids, distances) = merge(&DistanceFunction::Cosine, &[-1e-7], &[0.0], 2);
+        assert_eq!(user_ids, vec!["brute_force_0", "hnsw_0"]);
+        assert_eq!(distances, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_merge_returns_at_most_k() {
+        let (user_ids, distances) = merge(
+            &DistanceFunction::Euclidean,
+            &[0.1, 0.3, 0.5],
+            &[0.2, 0.4, 0.6],
+            4,
+        );
+        assert_eq!(
+            user_ids,
+            vec!["hnsw_0", "brute_force_0", "hnsw_1", "brute_force_1"]
+        );
+        assert_eq!(distances, vec![0.1, 0.2, 0.3, 0.4]);
+    }
+
+    fn log_record(
+        log_offset: i64,
+        id: &str,
+        embedding: Vec<f32>,
+        document: Option<&str>,
+    ) -> LogRecord {
+        // Only the records with a document have a metadata.
+        let metadata = document.map(|_| {
+            HashMap::from([(
+                "color".to_string(),
+                UpdateMetadataValue::Str("red".to_string()),
+            )])
+        });
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: Some(embedding.into()),
+                encoding: None,
+                metadata,
+                document: document.map(str::to_string),
+                operation: Operation::Add,
+            },
+        }
+    }
+
+    // A record segment with the records segment_1 at offset id 1 and segment_2 at offset id 2.
+    async fn compacted_record_segment(blockfile_provider: &BlockfileProvider) -> Segment {
+        // The compacted records are at offset ids 1 and 2, the ones still in the log are
+        // brute forced.
+        record_segment(
+            blockfile_provider,
+            vec![
+                log_record(1, "segment_1", vec![0.0, 1.0], Some("segment document")),
+                log_record(2, "segment_2", vec![0.0, 3.0], None),
+            ],
+        )
+        .await
+    }
+
+    // A record segment with `records` compacted into it, in order from offset id 1.
+    async fn record_segment(
+        blockfile_provider: &BlockfileProvider,
+        records: Vec<LogRecord>,
+    ) -> Segment {
+        let mut record_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::BlockfileRecord,
+            scope: SegmentScope::RECORD,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let compacted = Chunk::new(records.into());
+        let writer = RecordSegmentWriter::from_segment(&record_segment, blockfile_provider)
+            .await
+            .unwrap();
+        let materializer = LogMaterializer::new(None, compacted, None);
+        let materialized = materializer.materialize().await.unwrap();
+        writer
+            .apply_materialized_log_chunk(materialized)
+            .await
+            .unwrap();
+        record_segment.file_path = writer.commit().unwrap().flush().await.unwrap();
+        record_segment
+    }
+
+    #[tokio::test]
+    async fn test_merge_returns_the_documented_distances() {
+        // The query is [1, 0], "compacted" is [3, 4] and scored by the HNSW index, "logged"
+        // is [0, 2] and scored by brute force. For each distance function, the distances
+        // and the similarities of compacted and logged, worked out by hand:
+        // - l2, the squared euclidean distance: 2² + 4² = 20 and 1² + 2² = 5.
+        // - cosine, 1 - cosine similarity: 1 - 3 / 5 = 0.4 and 1 - 0 = 1.
+        // - ip, 1 - inner product: 1 - 3 = -2 and 1 - 0 = 1.
+        let cases = [
+            (DistanceFunction::Euclidean, [20.0, 5.0], None),
+            (DistanceFunction::Cosine, [0.4, 1.0], Some([0.6, 0.0])),
+            (
+                DistanceFunction::InnerProduct,
+                [-2.0, 1.0],
+                Some([3.0, 0.0]),
+            ),
+        ];
+        for (distance_function, distances, similarities) in cases {
+            let tmp_dir = tempfile::tempdir().unwrap();
+            let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+            let blockfile_provider = BlockfileProvider::new_arrow(storage);
+            let mut compacted = vec![3.0, 4.0];
+            let mut query = vec![1.0, 0.0];
+            // The index is written and queried with normalized vectors for cosine, like
+            // the HNSW segment writer and the query orchestrator do.
+            if distance_function == DistanceFunction::Cosine {
+                normalize(&mut compacted);
+                normalize(&mut query);
+            }
+            let record_segment = record_segment(
+                &blockfile_provider,
+                vec![log_record(1, "compacted", compacted.clone(), None)],
+            )
+            .await;
+            let hnsw_index = HnswIndex::init(
+                &IndexConfig {
+                    dimensionality: 2,
+                    distance_function: distance_function.clone(),
+                },
+                Some(&HnswIndexConfig {
+                    max_elements: 10,
+                    m: 16,
+                    ef_construction: 100,
+                    ef_search: 10,
+                    random_seed: 0,
+                    persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+                }),
+                Uuid::new_v4(),
+            )
+            .unwrap();
+            hnsw_index.add(1, &compacted);
+            let (hnsw_result_offset_ids, hnsw_result_distances) =
+                hnsw_index.query(&query, 1, &[], &[]);
+
+            for return_similarity in [false, true] {
+                let expected = match (return_similarity, similarities) {
+                    (false, _) => distances,
+                    (true, Some(similarities)) => similarities,
+                    // The query orchestrator refuses similarities for l2.
+                    (true, None) => continue,
+                };
+                let brute_force_result = BruteForceKnnOperator {}
+                    .run(&BruteForceKnnOperatorInput {
+                        log: Chunk::new(vec![log_record(2, "logged", vec![0.0, 2.0], None)].into()),
+                        queries: Arc::new([KnnQuery {
+                            embedding: query.clone(),
+                            k: 2,
+                        }]),
+                        distance_metric: Arc::new(distance_function.clone()),
+                        allowed_ids: Arc::new([]),
+                        allowed_ids_brute_force: Arc::new([]),
+                        allowed_offset_ids: None,
+                        include: QueryInclude::default(),
+                        deadline: Deadline::default(),
+                        compute_pool: ComputePool::new(1).unwrap(),
+                        sparse_embeddings: false,
+                        record_segment_definition: record_segment.clone(),
+                        blockfile_provider: blockfile_provider.clone(),
+                    })
+                    .await
+                    .unwrap()
+                    .results
+                    .remove(0);
+                let input = MergeKnnResultsOperatorInput::new(
+                    hnsw_result_offset_ids.clone(),
+                    hnsw_result_distances.clone(),
+                    brute_force_result,
+                    MergeKnnOptions {
+                        include: QueryInclude::default(),
+                        k: 2,
+                        distance_function: Some(distance_function.clone()),
+                        return_similarity,
+                    },
+                    record_segment.clone(),
+                    blockfile_provider.clone(),
+                );
+                let results = MergeKnnResultsOperator {}
+                    .run(&input)
+                    .await
+                    .unwrap()
+                    .results;
+                let mut results: Vec<(&str, f32)> = results
+                    .iter()
+                    .map(|result| (result.id.as_str(), result.distance))
+                    .collect();
+                results.sort_by(|a, b| a.0.cmp(b.0));
+                assert_eq!(results[0].0, "compacted");
+                assert_eq!(results[1].0, "logged");
+                for ((id, got), expected) in results.iter().zip(expected) {
+                    assert!(
+                        (got - expected).abs() < 1e-6,
+                        "{:?} {} of {} is {}, expected {}",
+                        distance_function,
+                        match return_similarity {
+                            true => "similarity",
+                            false => "distance",
+                        },
+                        id,
+                        got,
+                        expected
                     );
+                }
             }
-            brute_force_index += 1;
         }
     }
 
-    (result_user_ids, result_distances, result_vectors)
+    #[tokio::test]
+    async fn test_merge_hydrates_what_is_included() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let record_segment = compacted_record_segment(&blockfile_provider).await;
+        let log = Chunk::new(
+            vec![
+                log_record(3, "log_1", vec![0.0, 2.0], Some("log document")),
+                log_record(4, "log_2", vec![0.0, 4.0], None),
+            ]
+            .into(),
+        );
+
+        for bits in 0..8 {
+            let include = QueryInclude {
+                embeddings: bits & 1 != 0,
+                metadatas: bits & 2 != 0,
+                documents: bits & 4 != 0,
+            };
+            let brute_force_result = BruteForceKnnOperator {}
+                .run(&BruteForceKnnOperatorInput {
+                    log: log.clone(),
+                    queries: Arc::new([KnnQuery {
+                        embedding: vec![0.0, 0.0],
+                        k: 2,
+                    }]),
+                    distance_metric: Arc::new(DistanceFunction::Euclidean),
+                    allowed_ids: Arc::new([]),
+                    allowed_ids_brute_force: Arc::new([]),
+                    allowed_offset_ids: None,
+                    include,
+                    deadline: Deadline::default(),
+                    compute_pool: ComputePool::new(1).unwrap(),
+                    sparse_embeddings: false,
+                    record_segment_definition: record_segment.clone(),
+                    blockfile_provider: blockfile_provider.clone(),
+                })
+                .await
+                .unwrap()
+                .results
+                .remove(0);
+            let input = MergeKnnResultsOperatorInput::new(
+                vec![1, 2],
+                vec![1.0, 9.0],
+                brute_force_result,
+                MergeKnnOptions {
+                    include,
+                    k: 4,
+                    distance_function: Some(DistanceFunction::Euclidean),
+                    return_similarity: false,
+                },
+                record_segment.clone(),
+                blockfile_provider.clone(),
+            );
+            let results = MergeKnnResultsOperator {}
+                .run(&input)
+                .await
+                .unwrap()
+                .results;
+
+            let ids: Vec<_> = results.iter().map(|result| result.id.as_str()).collect();
+            assert_eq!(ids, vec!["segment_1", "log_1", "segment_2", "log_2"]);
+            let distances: Vec<_> = results.iter().map(|result| result.distance).collect();
+            assert_eq!(distances, vec![1.0, 4.0, 9.0, 16.0]);
+            for (i, result) in results.iter().enumerate() {
+                let has_document = i < 2;
+                assert_eq!(
+                    result.vector,
+                    include.embeddings.then(|| vec![0.0, (i + 1) as f32])
+                );
+                assert_eq!(
+                    result.metadata,
+                    (include.metadatas && has_document).then(|| HashMap::from([(
+                        "color".to_string(),
+                        MetadataValue::Str("red".to_string())
+                    )]))
+                );
+                // Records without a document have none, rather than an empty one.
+                let document = match i {
+                    0 => "segment document",
+                    _ => "log document",
+                };
+                assert_eq!(
+                    result.document.as_deref(),
+                    (include.documents && has_document).then_some(document)
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_prefers_the_log_for_the_same_user_id() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage);
+        let record_segment = compacted_record_segment(&blockfile_provider).await;
+        // segment_1 is moved away by the log, but the HNSW results still have it where it
+        // was compacted.
+        let mut update = log_record(3, "segment_1", vec![0.0, 5.0], None);
+        update.record.operation = Operation::Update;
+        let brute_force_result = BruteForceKnnOperator {}
+            .run(&BruteForceKnnOperatorInput {
+                log: Chunk::new(vec![update].into()),
+                queries: Arc::new([KnnQuery {
+                    embedding: vec![0.0, 0.0],
+                    k: 10,
+                }]),
+                distance_metric: Arc::new(DistanceFunction::Euclidean),
+                allowed_ids: Arc::new([]),
+                allowed_ids_brute_force: Arc::new([]),
+                allowed_offset_ids: None,
+                include: QueryInclude::default(),
+                deadline: Deadline::default(),
+                compute_pool: ComputePool::new(1).unwrap(),
+                sparse_embeddings: false,
+                record_segment_definition: record_segment.clone(),
+                blockfile_provider: blockfile_provider.clone(),
+            })
+            .await
+            .unwrap()
+            .results
+            .remove(0);
+        // k is larger than the number of records.
+        let input = MergeKnnResultsOperatorInput::new(
+            vec![1, 2],
+            vec![1.0, 9.0],
+            brute_force_result,
+            MergeKnnOptions {
+                include: QueryInclude::default(),
+                k: 10,
+                distance_function: Some(DistanceFunction::Euclidean),
+                return_similarity: false,
+            },
+            record_segment,
+            blockfile_provider,
+        );
+        let results = MergeKnnResultsOperator {}
+            .run(&input)
+            .await
+            .unwrap()
+            .results;
+        let ids: Vec<_> = results.iter().map(|result| result.id.as_str()).collect();
+        assert_eq!(ids, vec!["segment_2", "segment_1"]);
+        let distances: Vec<_> = results.iter().map(|result| result.distance).collect();
+        assert_eq!(distances, vec![9.0, 25.0]);
+    }
 }
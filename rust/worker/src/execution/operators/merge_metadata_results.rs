@@ -4,7 +4,7 @@ use crate::{
     execution::{data::data_chunk::Chunk, operator::Operator},
     segment::{
         record_segment::{RecordSegmentReader, RecordSegmentReaderCreationError},
-        LogMaterializer, LogMaterializerError,
+        DataRecord, LogMaterializer, LogMaterializerError, MaterializedLogRecord,
     },
     types::{LogRecord, Metadata, MetadataValueConversionError, Operation, Segment},
     utils::merge_sorted_vecs_conjunction,
@@ -12,7 +12,10 @@ use crate::{
 use async_trait::async_trait;
 use std::{
     collections::{HashMap, HashSet},
-    sync::{atomic::AtomicU32, Arc},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
 };
 use thiserror::Error;
 use tracing::{error, trace};
@@ -26,6 +29,16 @@ impl MergeMetadataResultsOperator {
     }
 }
 
+/// The records of a result to return, in offset id order.
+/// # Fields
+/// - offset: The number of records to skip.
+/// - limit: The most records to return, all of them when None.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
 #[derive(Debug)]
 pub struct MergeMetadataResultsOperatorInput {
     // Result of PullLogs.
@@ -36,6 +49,7 @@ pub struct MergeMetadataResultsOperatorInput {
     filtered_offset_ids: Option<Vec<u32>>,
     record_segment_definition: Segment,
     blockfile_provider: BlockfileProvider,
+    page: Page,
 }
 
 impl MergeMetadataResultsOperatorInput {
@@ -45,6 +59,7 @@ impl MergeMetadataResultsOperatorInput {
         filtered_offset_ids: Option<Vec<u32>>,
         record_segment_definition: Segment,
         blockfile_provider: BlockfileProvider,
+        page: Page,
     ) -> Self {
         Self {
             filtered_log,
@@ -52,6 +67,7 @@ impl MergeMetadataResultsOperatorInput {
             filtered_offset_ids,
             record_segment_definition,
             blockfile_provider: blockfile_provider,
+            page,
         }
     }
 }
@@ -86,6 +102,12 @@ impl ChromaError for MergeMetadataResultsOperatorError {
     }
 }
 
+// A record of a page, as the log has it or, when the log does not have it, the segment.
+enum PageRecord<'log, 'segment> {
+    Log(&'log MaterializedLogRecord<'segment>),
+    Segment(DataRecord<'segment>),
+}
+
 #[async_trait]
 impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOutput>
     for MergeMetadataResultsOperator
@@ -161,8 +183,17 @@ impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOut
         };
 
         // Step 1: Materialize the logs.
-        let materializer =
-            LogMaterializer::new(record_segment_reader, input.filtered_log.clone(), None);
+        // Records added in the log take the offset ids after the last one of the segment,
+        // even those that are deleted again.
+        let max_offset_id = record_segment_reader.as_ref().map_or(0, |reader| {
+            reader.get_current_max_offset_id().load(Ordering::SeqCst)
+        });
+        let next_offset_id = Arc::new(AtomicU32::new(max_offset_id));
+        let materializer = LogMaterializer::new(
+            record_segment_reader,
+            input.filtered_log.clone(),
+            Some(next_offset_id.clone()),
+        );
         let mat_records = match materializer.materialize().await {
             Ok(records) => records,
             Err(e) => {
@@ -172,180 +203,139 @@ impl Operator<MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOut
             }
         };
 
-        // Step 2: Hydrate data.
-        let mut ids: Vec<String> = Vec::new();
-        let mut metadata = Vec::new();
-        let mut documents = Vec::new();
-        let mut visited_ids: HashSet<u32> = HashSet::new();
+        // Step 2: Take the page of the live records, in offset id order. Records deleted in
+        // the log are left out, so that every page but the last one has `limit` records and
+        // the positions agree with the count of the collection.
+        let record_segment_reader = match RecordSegmentReader::from_segment(
+            &input.record_segment_definition,
+            &input.blockfile_provider,
+        )
+        .await
+        {
+            Ok(reader) => Some(reader),
+            Err(e) => match *e {
+                // This means no compaction has occured, so every record is in the log.
+                RecordSegmentReaderCreationError::UninitializedSegment => None,
+                RecordSegmentReaderCreationError::BlockfileOpenError(_)
+                | RecordSegmentReaderCreationError::InvalidNumberOfFiles => {
+                    error!("Error creating Record Segment: {:?}", e);
+                    return Err(MergeMetadataResultsOperatorError::RecordSegmentCreationError(*e));
+                }
+            },
+        };
+        // The log is the source of truth for the records present in both places.
+        let logs: HashMap<u32, _> = mat_records
+            .iter()
+            .map(|(log, _)| (log.offset_id, log))
+            .collect();
+        // Records added and deleted again in the log are not materialized at all.
+        let is_live = |offset_id: &u32| match logs.get(offset_id) {
+            Some(log) => log.final_operation != Operation::Delete,
+            None => *offset_id <= max_offset_id,
+        };
+        let offset = input.page.offset;
+        let limit = input.page.limit.unwrap_or(usize::MAX);
+
+        let mut page = Vec::new();
         match merged_offset_ids {
             Some(merged_ids) => {
-                // Hydrate from the logs first.
-                for (log, _) in mat_records.iter() {
-                    if merged_ids.contains(&log.offset_id) {
-                        // It's important to account for the records that are
-                        // deleted also here so that we can subsequently ignore
-                        // them when reading the record segment.
-                        visited_ids.insert(log.offset_id);
-                        if log.final_operation != Operation::Delete {
-                            documents.push(log.merged_document());
-                            let final_metadata = log.merged_metadata();
-                            if !final_metadata.is_empty() {
-                                metadata.push(Some(final_metadata));
-                            } else {
-                                metadata.push(None);
-                            }
-                            ids.push(log.merged_user_id());
-                        }
-                    }
-                }
-                // Hydrate remaining from record segment.
-                let record_segment_reader = match RecordSegmentReader::from_segment(
-                    &input.record_segment_definition,
-                    &input.blockfile_provider,
-                )
-                .await
-                {
-                    Ok(reader) => reader,
-                    Err(e) => {
-                        match *e {
-                            RecordSegmentReaderCreationError::UninitializedSegment => {
-                                // This means no compaction has occured, so we can just return whats on the log.
-                                return Ok(MergeMetadataResultsOperatorOutput {
-                                    ids,
-                                    metadata,
-                                    documents,
-                                });
+                let mut offset_ids: Vec<u32> = merged_ids.into_iter().filter(is_live).collect();
+                offset_ids.sort_unstable();
+                for offset_id in offset_ids.into_iter().skip(offset).take(limit) {
+                    let record = match (logs.get(&offset_id), &record_segment_reader) {
+                        (Some(log), _) => PageRecord::Log(log),
+                        // Every other live offset id is in the segment.
+                        (None, Some(reader)) => {
+                            match reader.get_data_for_offset_id(offset_id).await {
+                                Ok(record) => PageRecord::Segment(record),
+                                Err(e) => {
+                                    tracing::error!("Error reading Record Segment: {:?}", e);
+                                    return Err(
+                                        MergeMetadataResultsOperatorError::RecordSegmentReadError,
+                                    );
+                                }
                             }
-                            RecordSegmentReaderCreationError::BlockfileOpenError(_) => {
-                                error!("Error creating Record Segment: {:?}", e);
-                                return Err(
-                                    MergeMetadataResultsOperatorError::RecordSegmentCreationError(
-                                        *e,
-                                    ),
-                                );
-                            }
-                            RecordSegmentReaderCreationError::InvalidNumberOfFiles => {
-                                error!("Error creating Record Segment: {:?}", e);
-                                return Err(
-                                    MergeMetadataResultsOperatorError::RecordSegmentCreationError(
-                                        *e,
-                                    ),
-                                );
-                            }
-                        }
-                    }
-                };
-                for merged_id in merged_ids {
-                    // Skip already taken from log.
-                    if visited_ids.contains(&merged_id) {
-                        continue;
-                    }
-                    let record = match record_segment_reader
-                        .get_data_for_offset_id(merged_id)
-                        .await
-                    {
-                        Ok(record) => record,
-                        Err(e) => {
-                            tracing::error!("Error reading Record Segment: {:?}", e);
-                            return Err(MergeMetadataResultsOperatorError::RecordSegmentReadError);
                         }
-                    };
-
-                    let user_id = match record_segment_reader
-                        .get_user_id_for_offset_id(merged_id)
-                        .await
-                    {
-                        Ok(user_id) => user_id,
-                        Err(e) => {
-                            println!("Error reading Record Segment: {:?}", e);
+                        (None, None) => {
+                            tracing::error!(
+                                "Offset id {} is in neither the log nor the segment",
+                                offset_id
+                            );
                             return Err(MergeMetadataResultsOperatorError::RecordSegmentReadError);
                         }
                     };
-
-                    ids.push(user_id.to_string());
-                    metadata.push(record.metadata.clone());
-                    match record.document {
-                        Some(document) => documents.push(Some(document.to_string())),
-                        None => documents.push(None),
-                    }
+                    page.push(record);
                 }
             }
-            // Full scan over the log record and segment.
+            // The live records of the segment come first, then those added in the log. The
+            // page of the segment is read without listing the whole segment.
             None => {
-                // ids_in_log is used to ignore processing the records
-                // present in log when reading from the segment.
-                let mut ids_in_log = HashSet::new();
-                // First: full scan over the log record.
-                for (log, _) in mat_records.iter() {
-                    // It's important to insert even the deleted records here
-                    // so that they can be ignored when reading from the segment.
-                    ids_in_log.insert(log.merged_user_id());
-                    if log.final_operation != Operation::Delete {
-                        documents.push(log.merged_document());
-                        let final_metadata = log.merged_metadata();
-                        if !final_metadata.is_empty() {
-                            metadata.push(Some(final_metadata));
-                        } else {
-                            metadata.push(None);
+                let mut deleted: Vec<u32> = logs
+                    .iter()
+                    .filter(|(offset_id, log)| {
+                        **offset_id <= max_offset_id && log.final_operation == Operation::Delete
+                    })
+                    .map(|(offset_id, _)| *offset_id)
+                    .collect();
+                deleted.sort_unstable();
+                let mut num_live_in_segment = 0;
+                if let Some(reader) = &record_segment_reader {
+                    let records = reader
+                        .get_data_page_excluding(offset, Some(limit), &deleted)
+                        .await;
+                    let count = reader.count().await;
+                    let (records, count) = match (records, count) {
+                        (Ok(records), Ok(count)) => (records, count),
+                        (Err(e), _) | (_, Err(e)) => {
+                            tracing::error!("Error reading Record Segment: {:?}", e);
+                            return Err(MergeMetadataResultsOperatorError::RecordSegmentReadError);
                         }
-                        ids.push(log.merged_user_id());
+                    };
+                    num_live_in_segment = count - deleted.len();
+                    for (offset_id, record) in records {
+                        page.push(match logs.get(&offset_id) {
+                            Some(log) => PageRecord::Log(log),
+                            None => PageRecord::Segment(record),
+                        });
                     }
                 }
-                // Second: Hydrate remaining records from record segment.
-                let record_segment_reader = match RecordSegmentReader::from_segment(
-                    &input.record_segment_definition,
-                    &input.blockfile_provider,
-                )
-                .await
+                let mut added: Vec<u32> = logs
+                    .keys()
+                    .filter(|offset_id| **offset_id > max_offset_id && is_live(offset_id))
+                    .cloned()
+                    .collect();
+                added.sort_unstable();
+                let remaining = limit - page.len();
+                for offset_id in added
+                    .into_iter()
+                    .skip(offset.saturating_sub(num_live_in_segment))
+                    .take(remaining)
                 {
-                    Ok(reader) => reader,
-                    Err(e) => {
-                        match *e {
-                            RecordSegmentReaderCreationError::UninitializedSegment => {
-                                // This means no compaction has occured, so we can just return whats on the log.
-                                return Ok(MergeMetadataResultsOperatorOutput {
-                                    ids,
-                                    metadata,
-                                    documents,
-                                });
-                            }
-                            RecordSegmentReaderCreationError::BlockfileOpenError(_) => {
-                                error!("Error creating Record Segment: {:?}", e);
-                                return Err(
-                                    MergeMetadataResultsOperatorError::RecordSegmentCreationError(
-                                        *e,
-                                    ),
-                                );
-                            }
-                            RecordSegmentReaderCreationError::InvalidNumberOfFiles => {
-                                error!("Error creating Record Segment: {:?}", e);
-                                return Err(
-                                    MergeMetadataResultsOperatorError::RecordSegmentCreationError(
-                                        *e,
-                                    ),
-                                );
-                            }
-                        }
-                    }
-                };
-                let data = match record_segment_reader.get_all_data().await {
-                    Ok(data) => data,
-                    Err(e) => {
-                        tracing::error!("[Mergemetadata]: Error reading Record Segment: {:?}", e);
-                        return Err(MergeMetadataResultsOperatorError::RecordSegmentReadError);
-                    }
-                };
-                for record in data.iter() {
-                    // Ignore records processed from the log.
-                    if ids_in_log.contains(record.id) {
-                        continue;
+                    page.push(PageRecord::Log(logs[&offset_id]));
+                }
+            }
+        }
+
+        // Step 3: Hydrate the page.
+        let mut ids: Vec<String> = Vec::with_capacity(page.len());
+        let mut metadata = Vec::with_capacity(page.len());
+        let mut documents = Vec::with_capacity(page.len());
+        for record in page {
+            match record {
+                PageRecord::Log(log) => {
+                    documents.push(log.merged_document());
+                    let final_metadata = log.merged_metadata();
+                    if !final_metadata.is_empty() {
+                        metadata.push(Some(final_metadata));
+                    } else {
+                        metadata.push(None);
                     }
+                    ids.push(log.merged_user_id());
+                }
+                PageRecord::Segment(record) => {
                     ids.push(record.id.to_string());
-                    metadata.push(record.metadata.clone());
-                    match record.document {
-                        Some(document) => documents.push(Some(document.to_string())),
-                        None => documents.push(None),
-                    }
+                    metadata.push(record.metadata);
+                    documents.push(record.document.map(|document| document.to_string()));
                 }
             }
         }
@@ -374,7 +364,7 @@ mod test {
             data::data_chunk::Chunk,
             operator::Operator,
             operators::merge_metadata_results::{
-                MergeMetadataResultsOperator, MergeMetadataResultsOperatorInput,
+                MergeMetadataResultsOperator, MergeMetadataResultsOperatorInput, Page,
             },
         },
         segment::{
@@ -438,7 +428,7 @@ mod test {
                     log_offset: 1,
                     record: OperationRecord {
                         id: "embedding_id_1".to_string(),
-                        embedding: Some(vec![1.0, 2.0, 3.0]),
+                        embedding: Some(vec![1.0, 2.0, 3.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata.clone()),
                         document: Some(String::from("This is a document about cats.")),
@@ -449,7 +439,7 @@ mod test {
                     log_offset: 2,
                     record: OperationRecord {
                         id: "embedding_id_2".to_string(),
-                        embedding: Some(vec![4.0, 5.0, 6.0]),
+                        embedding: Some(vec![4.0, 5.0, 6.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata),
                         document: Some(String::from("This is a document about dogs.")),
@@ -525,7 +515,7 @@ mod test {
                 log_offset: 3,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![7.0, 8.0, 9.0]),
+                    embedding: Some(vec![7.0, 8.0, 9.0].into()),
                     encoding: None,
                     metadata: Some(update_metadata.clone()),
                     document: Some(String::from("This is a document about dogs.")),
@@ -552,6 +542,10 @@ mod test {
             Some(vec![1, 2, 3]),
             record_segment,
             blockfile_provider,
+            Page {
+                offset: 0,
+                limit: None,
+            },
         );
         let output = op.run(&input).await.expect("Error running operator");
         assert_eq!(2, output.ids.len());
@@ -726,7 +720,7 @@ mod test {
                     log_offset: 1,
                     record: OperationRecord {
                         id: "embedding_id_1".to_string(),
-                        embedding: Some(vec![1.0, 2.0, 3.0]),
+                        embedding: Some(vec![1.0, 2.0, 3.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata.clone()),
                         document: Some(String::from("This is a document about cats.")),
@@ -737,7 +731,7 @@ mod test {
                     log_offset: 2,
                     record: OperationRecord {
                         id: "embedding_id_2".to_string(),
-                        embedding: Some(vec![4.0, 5.0, 6.0]),
+                        embedding: Some(vec![4.0, 5.0, 6.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata),
                         document: Some(String::from("This is a document about dogs.")),
@@ -813,7 +807,7 @@ mod test {
                 log_offset: 3,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![7.0, 8.0, 9.0]),
+                    embedding: Some(vec![7.0, 8.0, 9.0].into()),
                     encoding: None,
                     metadata: Some(update_metadata.clone()),
                     document: Some(String::from("This is a document about dogs.")),
@@ -840,6 +834,10 @@ mod test {
             None,
             record_segment,
             blockfile_provider,
+            Page {
+                offset: 0,
+                limit: None,
+            },
         );
         let output = op.run(&input).await.expect("Error running operator");
         assert_eq!(3, output.ids.len());
@@ -1012,4 +1010,166 @@ mod test {
             &String::from("This is a document about dogs.")
         );
     }
+
+    #[tokio::test]
+    async fn test_pages_in_offset_id_order() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(ArrowBlockfileProvider::new(storage));
+        let mut record_segment = crate::types::Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::BlockfileRecord,
+            scope: crate::types::SegmentScope::RECORD,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let record = |log_offset: i64, id: String, n: Option<i32>, operation| LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id,
+                embedding: match operation {
+                    Operation::Delete => None,
+                    _ => Some(vec![1.0, 2.0, 3.0].into()),
+                },
+                encoding: None,
+                metadata: n
+                    .map(|n| HashMap::from([("n".to_string(), UpdateMetadataValue::Int(n))])),
+                document: None,
+                operation,
+            },
+        };
+        // id_0 to id_19 are compacted and get offset ids 1 to 20.
+        {
+            let segment_writer =
+                RecordSegmentWriter::from_segment(&record_segment, &blockfile_provider)
+                    .await
+                    .expect("Error creating segment writer");
+            let data: Vec<LogRecord> = (0..20)
+                .map(|i| record(i, format!("id_{}", i), Some(i as i32), Operation::Add))
+                .collect();
+            let materializer = LogMaterializer::new(None, Chunk::new(data.into()), None);
+            let mat_records = materializer
+                .materialize()
+                .await
+                .expect("Log materialization failed");
+            segment_writer
+                .apply_materialized_log_chunk(mat_records)
+                .await
+                .expect("Apply materialized log to record segment failed");
+            let record_flusher = segment_writer
+                .commit()
+                .expect("Commit for segment writer failed");
+            record_segment.file_path = record_flusher
+                .flush()
+                .await
+                .expect("Flush record segment writer failed");
+        }
+        // The log deletes three of them, updates one and adds id_20 and id_21.
+        let mut log = vec![
+            record(20, "id_3".to_string(), None, Operation::Delete),
+            record(21, "id_20".to_string(), Some(20), Operation::Add),
+            record(22, "id_7".to_string(), Some(70), Operation::Update),
+            record(23, "id_8".to_string(), None, Operation::Delete),
+            record(24, "id_21".to_string(), Some(21), Operation::Add),
+            record(25, "id_9".to_string(), None, Operation::Delete),
+        ];
+        let run = |log: &[LogRecord], offset: usize, limit: Option<usize>| {
+            let input = MergeMetadataResultsOperatorInput::new(
+                Chunk::new(log.to_vec().into()),
+                None,
+                None,
+                record_segment.clone(),
+                blockfile_provider.clone(),
+                Page { offset, limit },
+            );
+            async move {
+                MergeMetadataResultsOperator::new()
+                    .run(&input)
+                    .await
+                    .expect("Error running operator")
+            }
+        };
+
+        let all = run(&log, 0, None).await;
+        let expected: Vec<String> = [0, 1, 2, 4, 5, 6, 7, 10, 11, 12, 13, 14]
+            .into_iter()
+            .chain(15..22)
+            .map(|i| format!("id_{}", i))
+            .collect();
+        assert_eq!(all.ids, expected);
+        let n = |metadata: &Option<crate::types::Metadata>| match metadata.as_ref().unwrap()["n"] {
+            MetadataValue::Int(n) => n,
+            _ => panic!("Expected an int"),
+        };
+        assert_eq!(n(&all.metadata[6]), 70);
+        assert_eq!(n(&all.metadata[18]), 21);
+
+        // Only the live records take positions, so every page but the last is full and the
+        // pages put together are the collection.
+        let pages = |log: Vec<LogRecord>, limit: usize| {
+            let run = &run;
+            async move {
+                let mut paged = Vec::new();
+                let mut offset = 0;
+                loop {
+                    let page = run(&log, offset, Some(limit)).await;
+                    assert!(page.ids.len() <= limit);
+                    let full = page.ids.len() == limit;
+                    offset += page.ids.len();
+                    paged.extend(page.ids);
+                    if !full {
+                        return paged;
+                    }
+                }
+            }
+        };
+        assert_eq!(pages(log.clone(), 3).await, expected);
+        assert_eq!(pages(log.clone(), 19).await, expected);
+        assert_eq!(
+            run(&log, 3, Some(3)).await.ids,
+            vec!["id_4", "id_5", "id_6"]
+        );
+        assert_eq!(
+            run(&log, 16, Some(3)).await.ids,
+            vec!["id_19", "id_20", "id_21"]
+        );
+        assert!(run(&log, 19, Some(3)).await.ids.is_empty());
+        assert!(run(&log, 1000, None).await.ids.is_empty());
+        assert!(run(&log, 0, Some(0)).await.ids.is_empty());
+
+        // A first page that is deleted in the log altogether is taken by the next records.
+        log.extend((0..3).map(|i| record(26 + i, format!("id_{}", i), None, Operation::Delete)));
+        assert_eq!(
+            run(&log, 0, Some(3)).await.ids,
+            vec!["id_4", "id_5", "id_6"]
+        );
+        assert_eq!(pages(log.clone(), 3).await, expected[3..].to_vec());
+
+        // Filtered offset ids page over the live ones too. Offset ids 2 and 4 are id_1 and
+        // id_3, which are deleted.
+        let filtered_page = |offset: usize, limit: usize| {
+            let input = MergeMetadataResultsOperatorInput::new(
+                Chunk::new(log.clone().into()),
+                Some(vec![2, 4, 5, 6, 7, 8, 21]),
+                None,
+                record_segment.clone(),
+                blockfile_provider.clone(),
+                Page {
+                    offset,
+                    limit: Some(limit),
+                },
+            );
+            async move {
+                MergeMetadataResultsOperator::new()
+                    .run(&input)
+                    .await
+                    .expect("Error running operator")
+                    .ids
+            }
+        };
+        assert_eq!(filtered_page(0, 3).await, vec!["id_4", "id_5", "id_6"]);
+        assert_eq!(filtered_page(3, 10).await, vec!["id_7", "id_20"]);
+    }
 }
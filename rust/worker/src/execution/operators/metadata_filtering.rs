@@ -152,6 +152,27 @@ impl Operator<MetadataFilteringInput, MetadataFilteringOutput> for MetadataFilte
                 };
             }
         };
+        // $not_contains matches every record in the segment whose document does not
+        // contain the text, so it needs the offset ids of all of them.
+        let mut all_offset_ids_in_segment = vec![];
+        if let (Some(where_document_clause), Some(reader)) =
+            (&input.where_document_clause, &record_segment_reader)
+        {
+            if has_not_contains(where_document_clause) {
+                all_offset_ids_in_segment = match reader.get_all_offset_ids().await {
+                    Ok(offset_ids) => offset_ids
+                        .into_iter()
+                        .map(|offset_id| offset_id as usize)
+                        .collect(),
+                    Err(e) => {
+                        tracing::error!("Error reading offset ids from record segment {}", e);
+                        return Err(
+                            MetadataFilteringError::MetadataFilteringRecordSegmentReaderError,
+                        );
+                    }
+                };
+            }
+        }
         // Step 1: Materialize the logs.
         let materializer =
             LogMaterializer::new(record_segment_reader, input.log_record.clone(), None);
@@ -201,21 +222,27 @@ impl Operator<MetadataFilteringInput, MetadataFilteringOutput> for MetadataFilte
                         return result;
                     }
                     WhereClauseComparator::NotEqual => {
-                        todo!();
-                    }
-                    // We don't allow these comparators for strings.
-                    WhereClauseComparator::LessThan => {
-                        unimplemented!();
-                    }
-                    WhereClauseComparator::LessThanOrEqual => {
-                        unimplemented!();
-                    }
-                    WhereClauseComparator::GreaterThan => {
-                        unimplemented!();
-                    }
-                    WhereClauseComparator::GreaterThanOrEqual => {
-                        unimplemented!();
+                        let mut result = RoaringBitmap::new();
+                        // Construct a bitmap consisting of all offset ids
+                        // that have this key set to a different value.
+                        for (offset_id, meta_map) in &ids_to_metadata {
+                            if let (
+                                Some(MetadataValue::Str(string_value)),
+                                KeyWrapper::String(where_value),
+                            ) = (meta_map.get(metadata_key), metadata_value)
+                            {
+                                if *string_value != *where_value {
+                                    result.insert(*offset_id);
+                                }
+                            }
+                        }
+                        result
                     }
+                    // Strings are not ordered, so no record matches these.
+                    WhereClauseComparator::LessThan
+                    | WhereClauseComparator::LessThanOrEqual
+                    | WhereClauseComparator::GreaterThan
+                    | WhereClauseComparator::GreaterThanOrEqual => RoaringBitmap::new(),
                 },
                 crate::types::MetadataType::BoolType => match comparator {
                     WhereClauseComparator::Equal => {
@@ -239,21 +266,27 @@ impl Operator<MetadataFilteringInput, MetadataFilteringOutput> for MetadataFilte
                         return result;
                     }
                     WhereClauseComparator::NotEqual => {
-                        todo!();
-                    }
-                    // We don't allow these comparators for booleans.
-                    WhereClauseComparator::LessThan => {
-                        unimplemented!();
-                    }
-                    WhereClauseComparator::LessThanOrEqual => {
-                        unimplemented!();
-                    }
-                    WhereClauseComparator::GreaterThan => {
-                        unimplemented!();
-                    }
-                    WhereClauseComparator::GreaterThanOrEqual => {
-                        unimplemented!();
+                        let mut result = RoaringBitmap::new();
+                        // Construct a bitmap consisting of all offset ids
+                        // that have this key set to a different value.
+                        for (offset_id, meta_map) in &ids_to_metadata {
+                            if let (
+                                Some(MetadataValue::Bool(bool_value)),
+                                KeyWrapper::Bool(where_value),
+                            ) = (meta_map.get(metadata_key), metadata_value)
+                            {
+                                if *bool_value != *where_value {
+                                    result.insert(*offset_id);
+                                }
+                            }
+                        }
+                        result
                     }
+                    // Booleans are not ordered, so no record matches these.
+                    WhereClauseComparator::LessThan
+                    | WhereClauseComparator::LessThanOrEqual
+                    | WhereClauseComparator::GreaterThan
+                    | WhereClauseComparator::GreaterThanOrEqual => RoaringBitmap::new(),
                 },
                 crate::types::MetadataType::IntType => match comparator {
                     WhereClauseComparator::Equal => {
@@ -277,7 +310,21 @@ impl Operator<MetadataFilteringInput, MetadataFilteringOutput> for MetadataFilte
                         return result;
                     }
                     WhereClauseComparator::NotEqual => {
-                        todo!();
+                        let mut result = RoaringBitmap::new();
+                        // Construct a bitmap consisting of all offset ids
+                        // that have this key set to a different value.
+                        for (offset_id, meta_map) in &ids_to_metadata {
+                            if let (
+                                Some(MetadataValue::Int(int_value)),
+                                KeyWrapper::Uint32(where_value),
+                            ) = (meta_map.get(metadata_key), metadata_value)
+                            {
+                                if *int_value as u32 != *where_value {
+                                    result.insert(*offset_id);
+                                }
+                            }
+                        }
+                        result
                     }
                     WhereClauseComparator::LessThan => {
                         let mut result = RoaringBitmap::new();
@@ -382,7 +429,21 @@ impl Operator<MetadataFilteringInput, MetadataFilteringOutput> for MetadataFilte
                         return result;
                     }
                     WhereClauseComparator::NotEqual => {
-                        todo!();
+                        let mut result = RoaringBitmap::new();
+                        // Construct a bitmap consisting of all offset ids
+                        // that have this key set to a different value.
+                        for (offset_id, meta_map) in &ids_to_metadata {
+                            if let (
+                                Some(MetadataValue::Float(float_value)),
+                                KeyWrapper::Float32(where_value),
+                            ) = (meta_map.get(metadata_key), metadata_value)
+                            {
+                                if ((*float_value) as f32) != (*where_value) {
+                                    result.insert(*offset_id);
+                                }
+                            }
+                        }
+                        result
                     }
                     WhereClauseComparator::LessThan => {
                         let mut result = RoaringBitmap::new();
@@ -510,7 +571,7 @@ impl Operator<MetadataFilteringInput, MetadataFilteringOutput> for MetadataFilte
                     // Upstream sorts materialized records by offset id so matching_contains
                     // will be sorted.
                     // Emulate sqlite behavior. _ and % match to any character in sqlite.
-                    let normalized_query = query.replace("_", ".").replace("%", ".");
+                    let normalized_query = regex::escape(query).replace("_", ".").replace("%", ".");
                     let re = Regex::new(normalized_query.as_str()).unwrap();
                     for (record, _) in mat_records.iter() {
                         if record.final_operation == Operation::Delete {
@@ -528,7 +589,20 @@ impl Operator<MetadataFilteringInput, MetadataFilteringOutput> for MetadataFilte
                     return matching_contains;
                 }
                 WhereDocumentOperator::NotContains => {
-                    todo!()
+                    let mut matching_not_contains = vec![];
+                    let normalized_query = regex::escape(query).replace(['_', '%'], ".");
+                    let re = Regex::new(normalized_query.as_str()).unwrap();
+                    for (record, _) in mat_records.iter() {
+                        if record.final_operation == Operation::Delete {
+                            continue;
+                        }
+                        // Records without a document do not contain the text either.
+                        match record.merged_document_ref() {
+                            Some(doc) if re.is_match(doc) => {}
+                            _ => matching_not_contains.push(record.offset_id as i32),
+                        }
+                    }
+                    matching_not_contains
                 }
             }
         };
@@ -578,6 +652,7 @@ impl Operator<MetadataFilteringInput, MetadataFilteringOutput> for MetadataFilte
                     .query(
                         input.where_clause.as_ref(),
                         input.where_document_clause.as_ref(),
+                        &all_offset_ids_in_segment,
                         Some(&vec![]),
                         0,
                         0,
@@ -724,6 +799,18 @@ impl Operator<MetadataFilteringInput, MetadataFilteringOutput> for MetadataFilte
     }
 }
 
+fn has_not_contains(where_document_clause: &WhereDocument) -> bool {
+    match where_document_clause {
+        WhereDocument::DirectWhereDocumentComparison(direct_document_comparison) => {
+            direct_document_comparison.operator == WhereDocumentOperator::NotContains
+        }
+        WhereDocument::WhereDocumentChildren(where_document_children) => where_document_children
+            .children
+            .iter()
+            .any(has_not_contains),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::{
@@ -751,8 +838,10 @@ mod test {
         },
         storage::{local::LocalStorage, Storage},
         types::{
-            DirectComparison, DirectDocumentComparison, LogRecord, Operation, OperationRecord,
-            UpdateMetadataValue, Where, WhereComparison, WhereDocument,
+            BooleanOperator, DirectComparison, DirectDocumentComparison, LogRecord, MetadataValue,
+            Operation, OperationRecord, UpdateMetadataValue, Where, WhereChildren,
+            WhereClauseComparator, WhereComparison, WhereDocument, WhereDocumentChildren,
+            WhereDocumentOperator,
         },
     };
 
@@ -806,7 +895,7 @@ mod test {
                     log_offset: 1,
                     record: OperationRecord {
                         id: "embedding_id_1".to_string(),
-                        embedding: Some(vec![1.0, 2.0, 3.0]),
+                        embedding: Some(vec![1.0, 2.0, 3.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata.clone()),
                         document: Some(String::from("This is a document about cats.")),
@@ -817,7 +906,7 @@ mod test {
                     log_offset: 2,
                     record: OperationRecord {
                         id: "embedding_id_2".to_string(),
-                        embedding: Some(vec![4.0, 5.0, 6.0]),
+                        embedding: Some(vec![4.0, 5.0, 6.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata),
                         document: Some(String::from("This is a document about dogs.")),
@@ -904,7 +993,7 @@ mod test {
                 log_offset: 4,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![7.0, 8.0, 9.0]),
+                    embedding: Some(vec![7.0, 8.0, 9.0].into()),
                     encoding: None,
                     metadata: Some(update_metadata),
                     document: Some(String::from("This is a document about dogs.")),
@@ -915,7 +1004,7 @@ mod test {
                 log_offset: 5,
                 record: OperationRecord {
                     id: "embedding_id_2".to_string(),
-                    embedding: Some(vec![10.0, 11.0, 12.0]),
+                    embedding: Some(vec![10.0, 11.0, 12.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
@@ -1018,7 +1107,7 @@ mod test {
                     log_offset: 1,
                     record: OperationRecord {
                         id: "embedding_id_1".to_string(),
-                        embedding: Some(vec![1.0, 2.0, 3.0]),
+                        embedding: Some(vec![1.0, 2.0, 3.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata.clone()),
                         document: Some(String::from("This is a document about cats.")),
@@ -1029,7 +1118,7 @@ mod test {
                     log_offset: 2,
                     record: OperationRecord {
                         id: "embedding_id_2".to_string(),
-                        embedding: Some(vec![4.0, 5.0, 6.0]),
+                        embedding: Some(vec![4.0, 5.0, 6.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata),
                         document: Some(String::from("This is a document about dogs.")),
@@ -1104,7 +1193,7 @@ mod test {
             log_offset: 3,
             record: OperationRecord {
                 id: "embedding_id_3".to_string(),
-                embedding: Some(vec![7.0, 8.0, 9.0]),
+                embedding: Some(vec![7.0, 8.0, 9.0].into()),
                 encoding: None,
                 metadata: Some(update_metadata),
                 document: Some(String::from("This is a document about dogs.")),
@@ -1151,6 +1240,870 @@ mod test {
         assert_eq!(2, *where_res.get(1).expect("Expected not none value"));
     }
 
+    #[tokio::test]
+    async fn where_comparisons_across_types() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let arrow_blockfile_provider = ArrowBlockfileProvider::new(storage);
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(arrow_blockfile_provider);
+        let mut record_segment = crate::types::Segment {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"),
+            r#type: crate::types::SegmentType::BlockfileRecord,
+            scope: crate::types::SegmentScope::RECORD,
+            collection: Some(
+                Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"),
+            ),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let mut metadata_segment = crate::types::Segment {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000001").expect("parse error"),
+            r#type: crate::types::SegmentType::BlockfileMetadata,
+            scope: crate::types::SegmentScope::METADATA,
+            collection: Some(
+                Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"),
+            ),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let add = |log_offset: i64, metadata: Vec<(&str, UpdateMetadataValue)>| LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: format!("embedding_id_{}", log_offset),
+                embedding: Some(vec![1.0, 2.0, 3.0].into()),
+                encoding: None,
+                metadata: Some(
+                    metadata
+                        .into_iter()
+                        .map(|(key, value)| (key.to_string(), value))
+                        .collect(),
+                ),
+                document: None,
+                operation: Operation::Add,
+            },
+        };
+        // Record 3 has no weight and record 4 stores size as a string.
+        let data = vec![
+            add(
+                1,
+                vec![
+                    ("color", UpdateMetadataValue::Str(String::from("red"))),
+                    ("size", UpdateMetadataValue::Int(1)),
+                    ("weight", UpdateMetadataValue::Float(1.5)),
+                    ("ok", UpdateMetadataValue::Bool(true)),
+                ],
+            ),
+            add(
+                2,
+                vec![
+                    ("color", UpdateMetadataValue::Str(String::from("blue"))),
+                    ("size", UpdateMetadataValue::Int(5)),
+                    ("weight", UpdateMetadataValue::Float(2.5)),
+                    ("ok", UpdateMetadataValue::Bool(false)),
+                ],
+            ),
+            add(
+                3,
+                vec![
+                    ("color", UpdateMetadataValue::Str(String::from("red"))),
+                    ("size", UpdateMetadataValue::Int(10)),
+                ],
+            ),
+            add(
+                4,
+                vec![("size", UpdateMetadataValue::Str(String::from("big")))],
+            ),
+        ];
+        {
+            let segment_writer =
+                RecordSegmentWriter::from_segment(&record_segment, &blockfile_provider)
+                    .await
+                    .expect("Error creating segment writer");
+            let mut metadata_writer =
+                MetadataSegmentWriter::from_segment(&metadata_segment, &blockfile_provider)
+                    .await
+                    .expect("Error creating segment writer");
+            let materializer = LogMaterializer::new(None, Chunk::new(data.into()), None);
+            let mat_records = materializer
+                .materialize()
+                .await
+                .expect("Log materialization failed");
+            metadata_writer
+                .apply_materialized_log_chunk(mat_records.clone())
+                .await
+                .expect("Apply materialized log to metadata segment failed");
+            metadata_writer
+                .write_to_blockfiles()
+                .await
+                .expect("Write to blockfiles for metadata writer failed");
+            segment_writer
+                .apply_materialized_log_chunk(mat_records)
+                .await
+                .expect("Apply materialized log to record segment failed");
+            let record_flusher = segment_writer
+                .commit()
+                .expect("Commit for segment writer failed");
+            let metadata_flusher = metadata_writer
+                .commit()
+                .expect("Commit for metadata writer failed");
+            record_segment.file_path = record_flusher
+                .flush()
+                .await
+                .expect("Flush record segment writer failed");
+            metadata_segment.file_path = metadata_flusher
+                .flush()
+                .await
+                .expect("Flush metadata segment writer failed");
+        }
+        // Record 5 is only in the log, so both the log and the segment are filtered.
+        let log: Chunk<LogRecord> = Chunk::new(
+            vec![add(
+                5,
+                vec![
+                    ("color", UpdateMetadataValue::Str(String::from("green"))),
+                    ("size", UpdateMetadataValue::Int(7)),
+                    ("ok", UpdateMetadataValue::Bool(false)),
+                ],
+            )]
+            .into(),
+        );
+
+        let compare = |key: &str, comparison: WhereComparison| {
+            Where::DirectWhereComparison(DirectComparison {
+                key: String::from(key),
+                comparison,
+            })
+        };
+        let string = |value: &str, comparator: WhereClauseComparator| {
+            WhereComparison::SingleStringComparison(String::from(value), comparator)
+        };
+        let cases = vec![
+            (
+                compare("color", string("red", WhereClauseComparator::NotEqual)),
+                vec![2, 5],
+            ),
+            (
+                compare(
+                    "ok",
+                    WhereComparison::SingleBoolComparison(true, WhereClauseComparator::NotEqual),
+                ),
+                vec![2, 5],
+            ),
+            (
+                compare(
+                    "size",
+                    WhereComparison::SingleIntComparison(5, WhereClauseComparator::NotEqual),
+                ),
+                vec![1, 3, 5],
+            ),
+            (
+                compare(
+                    "weight",
+                    WhereComparison::SingleDoubleComparison(1.5, WhereClauseComparator::NotEqual),
+                ),
+                vec![2],
+            ),
+            (
+                compare(
+                    "size",
+                    WhereComparison::SingleIntComparison(3, WhereClauseComparator::GreaterThan),
+                ),
+                vec![2, 3, 5],
+            ),
+            // Only the record that stores size as a string matches a string operand.
+            (
+                compare("size", string("big", WhereClauseComparator::Equal)),
+                vec![4],
+            ),
+            (
+                compare("color", string("a", WhereClauseComparator::GreaterThan)),
+                vec![],
+            ),
+            (
+                compare(
+                    "ok",
+                    WhereComparison::SingleBoolComparison(true, WhereClauseComparator::LessThan),
+                ),
+                vec![],
+            ),
+            (
+                compare(
+                    "size",
+                    WhereComparison::SingleIntComparison(42, WhereClauseComparator::Equal),
+                ),
+                vec![],
+            ),
+            (
+                Where::WhereChildren(WhereChildren {
+                    children: vec![
+                        compare("color", string("red", WhereClauseComparator::Equal)),
+                        compare(
+                            "size",
+                            WhereComparison::SingleIntComparison(
+                                5,
+                                WhereClauseComparator::GreaterThanOrEqual,
+                            ),
+                        ),
+                    ],
+                    operator: BooleanOperator::And,
+                }),
+                vec![3],
+            ),
+            (
+                Where::WhereChildren(WhereChildren {
+                    children: vec![
+                        compare("color", string("blue", WhereClauseComparator::Equal)),
+                        compare(
+                            "weight",
+                            WhereComparison::SingleDoubleComparison(
+                                2.0,
+                                WhereClauseComparator::LessThan,
+                            ),
+                        ),
+                    ],
+                    operator: BooleanOperator::Or,
+                }),
+                vec![1, 2],
+            ),
+        ];
+        let operator = MetadataFilteringOperator::new();
+        for (where_clause, expected) in cases {
+            let input = MetadataFilteringInput::new(
+                log.clone(),
+                record_segment.clone(),
+                metadata_segment.clone(),
+                blockfile_provider.clone(),
+                Some(where_clause.clone()),
+                None,
+                None,
+            );
+            let res = operator
+                .run(&input)
+                .await
+                .expect("Error during running of operator");
+            assert_eq!(
+                Some(expected),
+                res.where_condition_filtered_offset_ids,
+                "{:?}",
+                where_clause
+            );
+        }
+    }
+
+    // Writes `data` on top of the segments and flushes them, like a compaction does.
+    async fn compact(
+        record_segment: &mut crate::types::Segment,
+        metadata_segment: &mut crate::types::Segment,
+        blockfile_provider: &BlockfileProvider,
+        data: Vec<LogRecord>,
+    ) {
+        let segment_writer = RecordSegmentWriter::from_segment(record_segment, blockfile_provider)
+            .await
+            .expect("Error creating segment writer");
+        let mut metadata_writer =
+            MetadataSegmentWriter::from_segment(metadata_segment, blockfile_provider)
+                .await
+                .expect("Error creating segment writer");
+        let record_segment_reader =
+            RecordSegmentReader::from_segment(record_segment, blockfile_provider)
+                .await
+                .ok();
+        let materializer =
+            LogMaterializer::new(record_segment_reader, Chunk::new(data.into()), None);
+        let mat_records = materializer
+            .materialize()
+            .await
+            .expect("Log materialization failed");
+        metadata_writer
+            .apply_materialized_log_chunk(mat_records.clone())
+            .await
+            .expect("Apply materialized log to metadata segment failed");
+        metadata_writer
+            .write_to_blockfiles()
+            .await
+            .expect("Write to blockfiles for metadata writer failed");
+        segment_writer
+            .apply_materialized_log_chunk(mat_records)
+            .await
+            .expect("Apply materialized log to record segment failed");
+        let record_flusher = segment_writer
+            .commit()
+            .expect("Commit for segment writer failed");
+        let metadata_flusher = metadata_writer
+            .commit()
+            .expect("Commit for metadata writer failed");
+        record_segment.file_path = record_flusher
+            .flush()
+            .await
+            .expect("Flush record segment writer failed");
+        metadata_segment.file_path = metadata_flusher
+            .flush()
+            .await
+            .expect("Flush metadata segment writer failed");
+    }
+
+    #[tokio::test]
+    async fn where_document_from_metadata_segment() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let arrow_blockfile_provider = ArrowBlockfileProvider::new(storage);
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(arrow_blockfile_provider);
+        let mut record_segment = crate::types::Segment {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"),
+            r#type: crate::types::SegmentType::BlockfileRecord,
+            scope: crate::types::SegmentScope::RECORD,
+            collection: Some(
+                Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"),
+            ),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let mut metadata_segment = crate::types::Segment {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000001").expect("parse error"),
+            r#type: crate::types::SegmentType::BlockfileMetadata,
+            scope: crate::types::SegmentScope::METADATA,
+            collection: Some(
+                Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"),
+            ),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let record = |log_offset: i64,
+                      id: &str,
+                      color: Option<&str>,
+                      document: Option<&str>,
+                      operation: Operation| LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: match operation {
+                    Operation::Add => Some(vec![1.0, 2.0, 3.0].into()),
+                    _ => None,
+                },
+                encoding: None,
+                metadata: color.map(|color| {
+                    HashMap::from([(
+                        String::from("color"),
+                        UpdateMetadataValue::Str(String::from(color)),
+                    )])
+                }),
+                document: document.map(String::from),
+                operation,
+            },
+        };
+        compact(
+            &mut record_segment,
+            &mut metadata_segment,
+            &blockfile_provider,
+            vec![
+                record(
+                    1,
+                    "embedding_id_1",
+                    Some("red"),
+                    Some("The quick brown fox"),
+                    Operation::Add,
+                ),
+                record(
+                    2,
+                    "embedding_id_2",
+                    Some("blue"),
+                    Some("A lazy dog"),
+                    Operation::Add,
+                ),
+                record(3, "embedding_id_3", Some("red"), None, Operation::Add),
+                record(
+                    4,
+                    "embedding_id_4",
+                    None,
+                    Some("A quick goodbye"),
+                    Operation::Add,
+                ),
+            ],
+        )
+        .await;
+        // The old postings of an updated document and those of a deleted record go away.
+        compact(
+            &mut record_segment,
+            &mut metadata_segment,
+            &blockfile_provider,
+            vec![
+                record(
+                    5,
+                    "embedding_id_2",
+                    None,
+                    Some("A quick dog"),
+                    Operation::Update,
+                ),
+                record(6, "embedding_id_4", None, None, Operation::Delete),
+            ],
+        )
+        .await;
+        let log: Chunk<LogRecord> = Chunk::new(
+            vec![record(
+                7,
+                "embedding_id_5",
+                Some("red"),
+                Some("A quick note"),
+                Operation::Add,
+            )]
+            .into(),
+        );
+
+        let contains = |document: &str| {
+            WhereDocument::DirectWhereDocumentComparison(DirectDocumentComparison {
+                document: String::from(document),
+                operator: WhereDocumentOperator::Contains,
+            })
+        };
+        let not_contains = |document: &str| {
+            WhereDocument::DirectWhereDocumentComparison(DirectDocumentComparison {
+                document: String::from(document),
+                operator: WhereDocumentOperator::NotContains,
+            })
+        };
+        let red = Where::DirectWhereComparison(DirectComparison {
+            key: String::from("color"),
+            comparison: WhereComparison::SingleStringComparison(
+                String::from("red"),
+                WhereClauseComparator::Equal,
+            ),
+        });
+        let cases = vec![
+            (None, contains("quick"), vec![1, 2, 5]),
+            (None, contains("k b"), vec![1]),
+            (None, contains("Quick"), vec![]),
+            (None, contains("lazy"), vec![]),
+            (None, contains("goodbye"), vec![]),
+            // The record without a document does not contain the text either.
+            (None, not_contains("quick"), vec![3]),
+            (None, not_contains("fox"), vec![2, 3, 5]),
+            (
+                None,
+                WhereDocument::WhereDocumentChildren(WhereDocumentChildren {
+                    children: vec![contains("quick"), not_contains("fox")],
+                    operator: BooleanOperator::And,
+                }),
+                vec![2, 5],
+            ),
+            (
+                None,
+                WhereDocument::WhereDocumentChildren(WhereDocumentChildren {
+                    children: vec![contains("dog"), contains("note")],
+                    operator: BooleanOperator::Or,
+                }),
+                vec![2, 5],
+            ),
+            (Some(red.clone()), contains("quick"), vec![1, 5]),
+            (Some(red.clone()), not_contains("quick"), vec![3]),
+        ];
+        let operator = MetadataFilteringOperator::new();
+        for (where_clause, where_document_clause, expected) in cases {
+            let input = MetadataFilteringInput::new(
+                log.clone(),
+                record_segment.clone(),
+                metadata_segment.clone(),
+                blockfile_provider.clone(),
+                where_clause.clone(),
+                Some(where_document_clause.clone()),
+                None,
+            );
+            let res = operator
+                .run(&input)
+                .await
+                .expect("Error during running of operator");
+            assert_eq!(
+                Some(expected),
+                res.where_condition_filtered_offset_ids,
+                "{:?} {:?}",
+                where_clause,
+                where_document_clause
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn where_and_over_three_keys_from_persisted_metadata_segment() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(ArrowBlockfileProvider::new(storage.clone()));
+        let mut record_segment = crate::types::Segment {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"),
+            r#type: crate::types::SegmentType::BlockfileRecord,
+            scope: crate::types::SegmentScope::RECORD,
+            collection: Some(
+                Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"),
+            ),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let mut metadata_segment = crate::types::Segment {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000001").expect("parse error"),
+            r#type: crate::types::SegmentType::BlockfileMetadata,
+            scope: crate::types::SegmentScope::METADATA,
+            collection: Some(
+                Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"),
+            ),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let record = |log_offset: i64, color: &str, size: i32, in_stock: bool| LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: format!("embedding_id_{}", log_offset),
+                embedding: Some(vec![1.0, 2.0, 3.0].into()),
+                encoding: None,
+                metadata: Some(HashMap::from([
+                    (
+                        String::from("color"),
+                        UpdateMetadataValue::Str(String::from(color)),
+                    ),
+                    (String::from("size"), UpdateMetadataValue::Int(size)),
+                    (
+                        String::from("in_stock"),
+                        UpdateMetadataValue::Bool(in_stock),
+                    ),
+                ])),
+                document: None,
+                operation: Operation::Add,
+            },
+        };
+        compact(
+            &mut record_segment,
+            &mut metadata_segment,
+            &blockfile_provider,
+            vec![
+                record(1, "red", 1, true),
+                record(2, "red", 2, true),
+                record(3, "blue", 1, true),
+                record(4, "red", 1, false),
+            ],
+        )
+        .await;
+        // The second compaction adds offset ids to the bitmaps written by the first one.
+        compact(
+            &mut record_segment,
+            &mut metadata_segment,
+            &blockfile_provider,
+            vec![
+                record(5, "red", 1, true),
+                record(6, "blue", 2, false),
+                record(7, "red", 1, true),
+            ],
+        )
+        .await;
+
+        // Nothing is cached by a new provider, so the bitmaps are read back from storage.
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(ArrowBlockfileProvider::new(storage));
+        let equal = |key: &str, comparison: WhereComparison| {
+            Where::DirectWhereComparison(DirectComparison {
+                key: String::from(key),
+                comparison,
+            })
+        };
+        let where_clause = Where::WhereChildren(WhereChildren {
+            children: vec![
+                equal(
+                    "color",
+                    WhereComparison::SingleStringComparison(
+                        String::from("red"),
+                        WhereClauseComparator::Equal,
+                    ),
+                ),
+                equal(
+                    "size",
+                    WhereComparison::SingleIntComparison(1, WhereClauseComparator::Equal),
+                ),
+                equal(
+                    "in_stock",
+                    WhereComparison::SingleBoolComparison(true, WhereClauseComparator::Equal),
+                ),
+            ],
+            operator: BooleanOperator::And,
+        });
+        let input = MetadataFilteringInput::new(
+            Chunk::new(vec![].into()),
+            record_segment,
+            metadata_segment,
+            blockfile_provider,
+            Some(where_clause),
+            None,
+            None,
+        );
+        let res = MetadataFilteringOperator::new()
+            .run(&input)
+            .await
+            .expect("Error during running of operator");
+        assert_eq!(res.where_condition_filtered_offset_ids, Some(vec![1, 5, 7]));
+    }
+
+    fn empty_segments() -> (crate::types::Segment, crate::types::Segment) {
+        let collection =
+            Some(Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"));
+        let record_segment = crate::types::Segment {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000000").expect("parse error"),
+            r#type: crate::types::SegmentType::BlockfileRecord,
+            scope: crate::types::SegmentScope::RECORD,
+            collection,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let metadata_segment = crate::types::Segment {
+            id: Uuid::from_str("00000000-0000-0000-0000-000000000001").expect("parse error"),
+            r#type: crate::types::SegmentType::BlockfileMetadata,
+            scope: crate::types::SegmentScope::METADATA,
+            collection,
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        (record_segment, metadata_segment)
+    }
+
+    fn metadata_record(
+        log_offset: i64,
+        id: &str,
+        operation: Operation,
+        metadata: Vec<(&str, UpdateMetadataValue)>,
+    ) -> LogRecord {
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: String::from(id),
+                embedding: match operation {
+                    Operation::Update => None,
+                    _ => Some(vec![1.0, 2.0, 3.0].into()),
+                },
+                encoding: None,
+                metadata: Some(
+                    metadata
+                        .into_iter()
+                        .map(|(key, value)| (String::from(key), value))
+                        .collect(),
+                ),
+                document: None,
+                operation,
+            },
+        }
+    }
+
+    // The offset ids of the persisted records whose metadata has `comparison` under `key`.
+    async fn filter_segment(
+        record_segment: &crate::types::Segment,
+        metadata_segment: &crate::types::Segment,
+        blockfile_provider: &BlockfileProvider,
+        key: &str,
+        comparison: WhereComparison,
+    ) -> Option<Vec<u32>> {
+        let where_clause = Where::DirectWhereComparison(DirectComparison {
+            key: String::from(key),
+            comparison,
+        });
+        let input = MetadataFilteringInput::new(
+            Chunk::new(vec![].into()),
+            record_segment.clone(),
+            metadata_segment.clone(),
+            blockfile_provider.clone(),
+            Some(where_clause),
+            None,
+            None,
+        );
+        MetadataFilteringOperator::new()
+            .run(&input)
+            .await
+            .expect("Error during running of operator")
+            .where_condition_filtered_offset_ids
+    }
+
+    #[tokio::test]
+    async fn update_merges_into_the_persisted_metadata() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(ArrowBlockfileProvider::new(storage));
+        let (mut record_segment, mut metadata_segment) = empty_segments();
+        let color = |color: &str| UpdateMetadataValue::Str(String::from(color));
+        compact(
+            &mut record_segment,
+            &mut metadata_segment,
+            &blockfile_provider,
+            vec![
+                metadata_record(
+                    1,
+                    "a",
+                    Operation::Add,
+                    vec![
+                        ("color", color("red")),
+                        ("size", UpdateMetadataValue::Int(1)),
+                    ],
+                ),
+                metadata_record(
+                    2,
+                    "b",
+                    Operation::Add,
+                    vec![
+                        ("color", color("red")),
+                        ("size", UpdateMetadataValue::Int(1)),
+                    ],
+                ),
+                metadata_record(
+                    3,
+                    "c",
+                    Operation::Add,
+                    vec![
+                        ("color", color("red")),
+                        ("size", UpdateMetadataValue::Int(1)),
+                    ],
+                ),
+            ],
+        )
+        .await;
+        compact(
+            &mut record_segment,
+            &mut metadata_segment,
+            &blockfile_provider,
+            vec![
+                metadata_record(4, "a", Operation::Update, vec![("color", color("blue"))]),
+                // Upserting an existing record is an update, and a new record is added.
+                metadata_record(5, "b", Operation::Upsert, vec![("shape", color("round"))]),
+                metadata_record(6, "d", Operation::Upsert, vec![("color", color("blue"))]),
+                // The key changes its type, and with it its index.
+                metadata_record(7, "c", Operation::Update, vec![("size", color("large"))]),
+            ],
+        )
+        .await;
+
+        let filter = |key: &'static str, comparison: WhereComparison| {
+            filter_segment(
+                &record_segment,
+                &metadata_segment,
+                &blockfile_provider,
+                key,
+                comparison,
+            )
+        };
+        let equal = |value: &str| {
+            WhereComparison::SingleStringComparison(
+                String::from(value),
+                WhereClauseComparator::Equal,
+            )
+        };
+        assert_eq!(filter("color", equal("red")).await, Some(vec![2, 3]));
+        assert_eq!(filter("color", equal("blue")).await, Some(vec![1, 4]));
+        assert_eq!(filter("shape", equal("round")).await, Some(vec![2]));
+        assert_eq!(
+            filter(
+                "size",
+                WhereComparison::SingleIntComparison(1, WhereClauseComparator::Equal)
+            )
+            .await,
+            Some(vec![1, 2])
+        );
+        assert_eq!(filter("size", equal("large")).await, Some(vec![3]));
+
+        let record_segment_reader =
+            RecordSegmentReader::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .expect("Error creating segment reader");
+        let data = record_segment_reader
+            .get_data_for_offset_id(1)
+            .await
+            .expect("Get data failed");
+        assert_eq!(
+            data.metadata,
+            Some(HashMap::from([
+                (
+                    String::from("color"),
+                    MetadataValue::Str(String::from("blue"))
+                ),
+                (String::from("size"), MetadataValue::Int(1)),
+            ]))
+        );
+    }
+
+    #[tokio::test]
+    async fn null_metadata_value_deletes_the_key() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(ArrowBlockfileProvider::new(storage));
+        let (mut record_segment, mut metadata_segment) = empty_segments();
+        let metadata = || {
+            vec![
+                ("in_stock", UpdateMetadataValue::Bool(true)),
+                ("size", UpdateMetadataValue::Int(1)),
+            ]
+        };
+        compact(
+            &mut record_segment,
+            &mut metadata_segment,
+            &blockfile_provider,
+            vec![
+                metadata_record(1, "a", Operation::Add, metadata()),
+                metadata_record(2, "b", Operation::Add, metadata()),
+                metadata_record(3, "c", Operation::Add, metadata()),
+            ],
+        )
+        .await;
+        compact(
+            &mut record_segment,
+            &mut metadata_segment,
+            &blockfile_provider,
+            vec![
+                metadata_record(
+                    4,
+                    "a",
+                    Operation::Update,
+                    vec![("in_stock", UpdateMetadataValue::None)],
+                ),
+                metadata_record(
+                    5,
+                    "b",
+                    Operation::Upsert,
+                    vec![("size", UpdateMetadataValue::None)],
+                ),
+            ],
+        )
+        .await;
+
+        let in_stock = filter_segment(
+            &record_segment,
+            &metadata_segment,
+            &blockfile_provider,
+            "in_stock",
+            WhereComparison::SingleBoolComparison(true, WhereClauseComparator::Equal),
+        )
+        .await;
+        assert_eq!(in_stock, Some(vec![2, 3]));
+        let size = filter_segment(
+            &record_segment,
+            &metadata_segment,
+            &blockfile_provider,
+            "size",
+            WhereComparison::SingleIntComparison(1, WhereClauseComparator::Equal),
+        )
+        .await;
+        assert_eq!(size, Some(vec![1, 3]));
+
+        let record_segment_reader =
+            RecordSegmentReader::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .expect("Error creating segment reader");
+        let data = record_segment_reader
+            .get_data_for_offset_id(1)
+            .await
+            .expect("Get data failed");
+        assert_eq!(
+            data.metadata,
+            Some(HashMap::from([(
+                String::from("size"),
+                MetadataValue::Int(1)
+            )]))
+        );
+    }
+
     #[tokio::test]
     async fn query_ids_only() {
         let tmp_dir = tempfile::tempdir().unwrap();
@@ -1201,7 +2154,7 @@ mod test {
                     log_offset: 1,
                     record: OperationRecord {
                         id: "embedding_id_1".to_string(),
-                        embedding: Some(vec![1.0, 2.0, 3.0]),
+                        embedding: Some(vec![1.0, 2.0, 3.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata.clone()),
                         document: Some(String::from("This is a document about cats.")),
@@ -1212,7 +2165,7 @@ mod test {
                     log_offset: 2,
                     record: OperationRecord {
                         id: "embedding_id_2".to_string(),
-                        embedding: Some(vec![4.0, 5.0, 6.0]),
+                        embedding: Some(vec![4.0, 5.0, 6.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata),
                         document: Some(String::from("This is a document about dogs.")),
@@ -1295,7 +2248,7 @@ mod test {
                 log_offset: 4,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![7.0, 8.0, 9.0]),
+                    embedding: Some(vec![7.0, 8.0, 9.0].into()),
                     encoding: None,
                     metadata: Some(update_metadata),
                     document: Some(String::from("This is a document about dogs.")),
@@ -1306,7 +2259,7 @@ mod test {
                 log_offset: 5,
                 record: OperationRecord {
                     id: "embedding_id_2".to_string(),
-                    embedding: Some(vec![10.0, 11.0, 12.0]),
+                    embedding: Some(vec![10.0, 11.0, 12.0].into()),
                     encoding: None,
                     metadata: None,
                     document: None,
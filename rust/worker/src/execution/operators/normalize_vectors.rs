@@ -1,8 +1,6 @@
 use crate::execution::operator::Operator;
 use async_trait::async_trait;
 
-const EPS: f32 = 1e-30;
-
 #[derive(Debug)]
 pub struct NormalizeVectorOperator {}
 
@@ -15,12 +13,9 @@ pub struct NormalizeVectorOperatorOutput {
 }
 
 pub fn normalize(vector: &[f32]) -> Vec<f32> {
-    let mut norm = 0.0;
-    for x in vector {
-        norm += x * x;
-    }
-    let norm = 1.0 / (norm.sqrt() + EPS);
-    vector.iter().map(|x| x * norm).collect()
+    let mut normalized = vector.to_vec();
+    crate::distance::normalize(&mut normalized);
+    normalized
 }
 
 #[async_trait]
@@ -4,8 +4,108 @@ use crate::log::log::Log;
 use crate::log::log::PullLogsError;
 use crate::types::LogRecord;
 use async_trait::async_trait;
+use futures::{stream, Stream, StreamExt};
 use uuid::Uuid;
 
+/// Reads the log of a collection a page at a time.
+/// # Description
+/// Each page holds at most `batch_size` records, so a large backlog is never read in a
+/// single request. The puller stops at the end of the log, after the record at `end_offset`
+/// and at the records written after `end_timestamp`, which gives a consistent snapshot of a
+/// log that is still being written to.
+/// # Notes
+/// Only the offset after the last record handed out is kept. A page that fails to be read
+/// is read again by the next call to `next_page`, which resumes at that offset, so no record
+/// is handed out twice.
+#[derive(Debug)]
+pub(crate) struct LogPuller {
+    log: Box<Log>,
+    collection_id: Uuid,
+    next_offset: i64,
+    batch_size: i32,
+    end_offset: Option<i64>,
+    end_timestamp: Option<i64>,
+    done: bool,
+}
+
+impl LogPuller {
+    pub(crate) fn new(
+        log: Box<Log>,
+        collection_id: Uuid,
+        offset: i64,
+        batch_size: i32,
+        end_offset: Option<i64>,
+        end_timestamp: Option<i64>,
+    ) -> Self {
+        LogPuller {
+            log,
+            collection_id,
+            next_offset: offset,
+            batch_size,
+            end_offset,
+            end_timestamp,
+            done: false,
+        }
+    }
+
+    /// Reads the next page, or returns `None` once there are no more records to read.
+    pub(crate) async fn next_page(&mut self) -> Result<Option<Vec<LogRecord>>, PullLogsError> {
+        if self.done {
+            return Ok(None);
+        }
+        let batch_size = match self.end_offset {
+            Some(end_offset) => (end_offset - self.next_offset + 1).min(self.batch_size as i64),
+            None => self.batch_size as i64,
+        };
+        if batch_size <= 0 {
+            self.done = true;
+            return Ok(None);
+        }
+        let mut logs = self
+            .log
+            .read(
+                self.collection_id,
+                self.next_offset,
+                batch_size as i32,
+                self.end_timestamp,
+            )
+            .await?;
+        if let Some(end_offset) = self.end_offset {
+            logs.retain(|log| log.log_offset <= end_offset);
+        }
+        match logs.last() {
+            Some(last) => {
+                self.next_offset = last.log_offset + 1;
+                Ok(Some(logs))
+            }
+            None => {
+                self.done = true;
+                Ok(None)
+            }
+        }
+    }
+
+    /// The records of the pages, in order.
+    /// # Notes
+    /// The stream does not end on an error. Polling it again reads the failed page again.
+    pub(crate) fn records(self) -> impl Stream<Item = Result<LogRecord, PullLogsError>> {
+        stream::unfold(self, |mut puller| async move {
+            match puller.next_page().await {
+                Ok(Some(logs)) => Some((Ok(logs), puller)),
+                Ok(None) => None,
+                Err(e) => Some((Err(e), puller)),
+            }
+        })
+        .flat_map(|page| {
+            let records: Vec<_> = match page {
+                Ok(logs) => logs.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(records)
+        })
+    }
+}
+
 /// The pull logs operator is responsible for reading logs from the log service.
 #[derive(Debug)]
 pub struct PullLogsOperator {
@@ -91,47 +191,24 @@ impl Operator<PullLogsInput, PullLogsOutput> for PullLogsOperator {
     async fn run(&self, input: &PullLogsInput) -> Result<PullLogsOutput, PullLogsError> {
         // We expect the log to be cheaply cloneable, we need to clone it since we need
         // a mutable reference to it. Not necessarily the best, but it works for our needs.
-        let mut client_clone = self.client.clone();
-        let batch_size = input.batch_size;
-        let mut num_records_read = 0;
-        let mut offset = input.offset;
+        let puller = LogPuller::new(
+            self.client.clone(),
+            input.collection_id,
+            input.offset,
+            input.batch_size,
+            None,
+            input.end_timestamp,
+        );
+        // We only read the records up to the size we wanted
+        let num_records = match input.num_records {
+            Some(num_records) => num_records.max(0) as usize,
+            None => usize::MAX,
+        };
+        let records = puller.records().take(num_records);
+        futures::pin_mut!(records);
         let mut result = Vec::new();
-        loop {
-            let logs = client_clone
-                .read(input.collection_id, offset, batch_size, input.end_timestamp)
-                .await;
-
-            let mut logs = match logs {
-                Ok(logs) => logs,
-                Err(e) => {
-                    return Err(e);
-                }
-            };
-
-            if logs.is_empty() {
-                break;
-            }
-
-            num_records_read += logs.len();
-            // unwrap here is safe because we just checked if empty
-            offset = logs.last().unwrap().log_offset + 1;
-            result.append(&mut logs);
-
-            // We used a a timestamp and we didn't get a full batch, so we have retrieved
-            // the last batch of logs relevant to our query
-            if input.end_timestamp.is_some() && num_records_read < batch_size as usize {
-                break;
-            }
-
-            // We have read all the records up to the size we wanted
-            if input.num_records.is_some()
-                && num_records_read >= input.num_records.unwrap() as usize
-            {
-                break;
-            }
-        }
-        if input.num_records.is_some() && result.len() > input.num_records.unwrap() as usize {
-            result.truncate(input.num_records.unwrap() as usize);
+        while let Some(record) = records.next().await {
+            result.push(record?);
         }
         // Convert to DataChunk
         let data_chunk = Chunk::new(result.into());
@@ -142,6 +219,7 @@ impl Operator<PullLogsInput, PullLogsOutput> for PullLogsOperator {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::errors::ChromaError;
     use crate::log::log::InMemoryLog;
     use crate::log::log::InternalLogRecord;
     use crate::types::LogRecord;
@@ -250,4 +328,112 @@ mod tests {
         let output = operator.run(&input).await.unwrap();
         assert_eq!(output.logs().len(), 1);
     }
+
+    fn log_with_records(collection_id: Uuid, num_records: i64) -> InMemoryLog {
+        let mut log = InMemoryLog::new();
+        for log_offset in 0..num_records {
+            log.add_log(
+                collection_id,
+                Box::new(InternalLogRecord {
+                    collection_id,
+                    log_offset,
+                    log_ts: log_offset + 1,
+                    record: LogRecord {
+                        log_offset,
+                        record: OperationRecord {
+                            id: format!("embedding_id_{}", log_offset),
+                            embedding: None,
+                            encoding: None,
+                            metadata: None,
+                            document: None,
+                            operation: Operation::Add,
+                        },
+                    },
+                }),
+            );
+        }
+        log
+    }
+
+    async fn page_offsets(puller: &mut LogPuller) -> Vec<Vec<i64>> {
+        let mut pages = Vec::new();
+        while let Some(logs) = puller.next_page().await.unwrap() {
+            pages.push(logs.iter().map(|log| log.log_offset).collect());
+        }
+        pages
+    }
+
+    #[tokio::test]
+    async fn test_log_puller_pages() {
+        let collection_id = Uuid::new_v4();
+        let puller = |num_records, end_offset, end_timestamp| {
+            let log = Box::new(Log::InMemory(log_with_records(collection_id, num_records)));
+            LogPuller::new(log, collection_id, 0, 3, end_offset, end_timestamp)
+        };
+
+        // An empty log
+        let mut empty = puller(0, None, None);
+        assert!(page_offsets(&mut empty).await.is_empty());
+        assert_eq!(puller(0, None, None).records().count().await, 0);
+
+        // Exactly one page
+        let mut one_page = puller(3, None, None);
+        assert_eq!(page_offsets(&mut one_page).await, vec![vec![0, 1, 2]]);
+        assert!(one_page.next_page().await.unwrap().is_none());
+
+        // A backlog of several pages
+        let mut backlog = puller(7, None, None);
+        assert_eq!(
+            page_offsets(&mut backlog).await,
+            vec![vec![0, 1, 2], vec![3, 4, 5], vec![6]]
+        );
+
+        // Up to an end offset, or an end timestamp
+        let mut up_to_offset = puller(7, Some(4), None);
+        assert_eq!(
+            page_offsets(&mut up_to_offset).await,
+            vec![vec![0, 1, 2], vec![3, 4]]
+        );
+        let mut up_to_timestamp = puller(7, None, Some(4));
+        assert_eq!(
+            page_offsets(&mut up_to_timestamp).await,
+            vec![vec![0, 1, 2], vec![3]]
+        );
+
+        let offsets: Vec<_> = puller(7, None, None)
+            .records()
+            .map(|record| record.unwrap().log_offset)
+            .collect()
+            .await;
+        assert_eq!(offsets, (0..7).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_log_puller_resumes_after_a_failed_page() {
+        let collection_id = Uuid::new_v4();
+        let mut log = log_with_records(collection_id, 7);
+        log.fail_next_read_at(3);
+        let log = Box::new(Log::InMemory(log));
+
+        let mut puller = LogPuller::new(log.clone(), collection_id, 0, 3, None, None);
+        assert_eq!(puller.next_page().await.unwrap().unwrap().len(), 3);
+        let e = puller.next_page().await.unwrap_err();
+        assert!(e.is_retryable());
+        assert_eq!(
+            page_offsets(&mut puller).await,
+            vec![vec![3, 4, 5], vec![6]]
+        );
+
+        let records: Vec<_> = LogPuller::new(log, collection_id, 0, 3, None, None)
+            .records()
+            .collect()
+            .await;
+        assert_eq!(records.iter().filter(|record| record.is_err()).count(), 1);
+        let offsets: Vec<_> = records
+            .into_iter()
+            .filter_map(|record| record.ok())
+            .map(|record| record.log_offset)
+            .collect();
+        assert_eq!(offsets, (0..7).collect::<Vec<_>>());
+    }
 }
@@ -279,4 +279,78 @@ mod tests {
         let segment_2 = segments.iter().find(|s| s.id == segment_id_2).unwrap();
         assert_eq!(segment_2.file_path, file_path_4);
     }
+
+    #[tokio::test]
+    async fn test_register_operator_updates_no_segment_on_failure() {
+        let mut sysdb = Box::new(SysDb::Test(TestSysDb::new()));
+        let log = Box::new(Log::InMemory(InMemoryLog::new()));
+        let collection_id = Uuid::new_v4();
+        let tenant = "tenant_1".to_string();
+        let segment_id = Uuid::new_v4();
+        let mut file_path = HashMap::new();
+        file_path.insert("hnsw".to_string(), vec!["path_1".to_string()]);
+        match *sysdb {
+            SysDb::Test(ref mut sysdb) => {
+                sysdb.add_collection(Collection {
+                    id: collection_id,
+                    name: "collection_1".to_string(),
+                    metadata: None,
+                    dimension: Some(1),
+                    tenant: tenant.clone(),
+                    database: "database_1".to_string(),
+                    log_position: 0,
+                    version: 0,
+                });
+                sysdb.add_segment(Segment {
+                    id: segment_id,
+                    r#type: SegmentType::HnswDistributed,
+                    scope: SegmentScope::VECTOR,
+                    collection: Some(collection_id),
+                    metadata: None,
+                    file_path: file_path.clone(),
+                });
+            }
+            _ => panic!("Invalid sysdb type"),
+        }
+
+        // The second segment does not exist, so the flush of the first must not be applied.
+        let mut new_file_path = HashMap::new();
+        new_file_path.insert("hnsw".to_string(), vec!["path_2".to_string()]);
+        let segment_flush_info = vec![
+            SegmentFlushInfo {
+                segment_id,
+                file_paths: new_file_path.clone(),
+            },
+            SegmentFlushInfo {
+                segment_id: Uuid::new_v4(),
+                file_paths: new_file_path,
+            },
+        ];
+        let input = RegisterInput::new(
+            tenant,
+            collection_id,
+            100,
+            0,
+            segment_flush_info.into(),
+            sysdb.clone(),
+            log,
+        );
+
+        match RegisterOperator::new().run(&input).await {
+            Err(RegisterError::FlushCompactionError(FlushCompactionError::SegmentNotFound)) => {}
+            res => panic!(
+                "Expected a missing segment to fail the flush, got {:?}",
+                res
+            ),
+        }
+        let segments = sysdb.get_segments(None, None, None, None).await.unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].file_path, file_path);
+        let collection = sysdb
+            .get_collections(Some(collection_id), None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(collection[0].log_position, 0);
+        assert_eq!(collection[0].version, 0);
+    }
 }
@@ -11,6 +11,7 @@ use crate::segment::record_segment::RecordSegmentReaderCreationError;
 use crate::segment::LogMaterializer;
 use crate::segment::LogMaterializerError;
 use crate::segment::SegmentWriter;
+use crate::types::RecordConversionError;
 use crate::types::Segment;
 use crate::{
     execution::{data::data_chunk::Chunk, operator::Operator},
@@ -32,6 +33,8 @@ pub enum WriteSegmentsOperatorError {
     ApplyMaterializatedLogsError(#[from] ApplyMaterializedLogError),
     #[error("Materialized logs failed to apply {0}")]
     ApplyMaterializatedLogsErrorMetadataSegment(#[from] MetadataSegmentError),
+    #[error(transparent)]
+    DimensionMismatch(#[from] RecordConversionError),
 }
 
 impl ChromaError for WriteSegmentsOperatorError {
@@ -41,6 +44,7 @@ impl ChromaError for WriteSegmentsOperatorError {
             WriteSegmentsOperatorError::LogMaterializationError(e) => e.code(),
             WriteSegmentsOperatorError::ApplyMaterializatedLogsError(e) => e.code(),
             WriteSegmentsOperatorError::ApplyMaterializatedLogsErrorMetadataSegment(e) => e.code(),
+            WriteSegmentsOperatorError::DimensionMismatch(e) => e.code(),
         }
     }
 }
@@ -57,7 +61,9 @@ impl WriteSegmentsOperator {
 #[derive(Debug)]
 pub struct WriteSegmentsInput {
     record_segment_writer: RecordSegmentWriter,
-    hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+    // None for collections with sparse embeddings, which are not indexed by HNSW and are
+    // scored by brute force from the record segment.
+    hnsw_segment_writer: Option<Box<DistributedHNSWSegmentWriter>>,
     metadata_segment_writer: MetadataSegmentWriter<'static>,
     chunk: Chunk<LogRecord>,
     provider: BlockfileProvider,
@@ -68,7 +74,7 @@ pub struct WriteSegmentsInput {
 impl WriteSegmentsInput {
     pub fn new(
         record_segment_writer: RecordSegmentWriter,
-        hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+        hnsw_segment_writer: Option<Box<DistributedHNSWSegmentWriter>>,
         metadata_segment_writer: MetadataSegmentWriter<'static>,
         chunk: Chunk<LogRecord>,
         provider: BlockfileProvider,
@@ -90,7 +96,7 @@ impl WriteSegmentsInput {
 #[derive(Debug)]
 pub struct WriteSegmentsOutput {
     pub(crate) record_segment_writer: RecordSegmentWriter,
-    pub(crate) hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+    pub(crate) hnsw_segment_writer: Option<Box<DistributedHNSWSegmentWriter>>,
     pub(crate) metadata_segment_writer: MetadataSegmentWriter<'static>,
 }
 
@@ -100,6 +106,14 @@ impl Operator<WriteSegmentsInput, WriteSegmentsOutput> for WriteSegmentsOperator
 
     async fn run(&self, input: &WriteSegmentsInput) -> Result<WriteSegmentsOutput, Self::Error> {
         tracing::debug!("Materializing N Records: {:?}", input.chunk.len());
+        // hnswlib reads as many floats as the index has dimensions, so an embedding of another
+        // dimension must not reach any of the segments.
+        if let Some(hnsw_segment_writer) = &input.hnsw_segment_writer {
+            let dimension = hnsw_segment_writer.dimension();
+            for (record, _) in input.chunk.iter() {
+                record.record.check_dimension(dimension)?;
+            }
+        }
         // Prepare for log materialization.
         let record_segment_reader: Option<RecordSegmentReader>;
         match RecordSegmentReader::from_segment(&input.record_segment, &input.provider).await {
@@ -168,17 +182,15 @@ impl Operator<WriteSegmentsInput, WriteSegmentsOutput> for WriteSegmentsOperator
             }
         }
         tracing::debug!("Applied materialized records to metadata segment");
-        match input
-            .hnsw_segment_writer
-            .apply_materialized_log_chunk(res)
-            .await
-        {
-            Ok(()) => (),
-            Err(e) => {
-                return Err(WriteSegmentsOperatorError::ApplyMaterializatedLogsError(e));
+        if let Some(hnsw_segment_writer) = &input.hnsw_segment_writer {
+            match hnsw_segment_writer.apply_materialized_log_chunk(res).await {
+                Ok(()) => (),
+                Err(e) => {
+                    return Err(WriteSegmentsOperatorError::ApplyMaterializatedLogsError(e));
+                }
             }
+            tracing::debug!("Applied Materialized Records to HNSW Segment");
         }
-        tracing::debug!("Applied Materialized Records to HNSW Segment");
         Ok(WriteSegmentsOutput {
             record_segment_writer: input.record_segment_writer.clone(),
             hnsw_segment_writer: input.hnsw_segment_writer.clone(),
@@ -3,6 +3,7 @@ use crate::blockstore::provider::BlockfileProvider;
 use crate::compactor::CompactionJob;
 use crate::errors::ChromaError;
 use crate::execution::data::data_chunk::Chunk;
+use crate::execution::deadline::{Deadline, DeadlineExceededError};
 use crate::execution::operator::TaskResult;
 use crate::execution::operators::flush_s3::FlushS3Input;
 use crate::execution::operators::flush_s3::FlushS3Operator;
@@ -25,17 +26,22 @@ use crate::execution::operators::write_segments::WriteSegmentsOutput;
 use crate::index::hnsw_provider::HnswIndexProvider;
 use crate::log::log::Log;
 use crate::log::log::PullLogsError;
+use crate::segment::distributed_hnsw_segment::hnsw_index_id;
 use crate::segment::distributed_hnsw_segment::DistributedHNSWSegmentWriter;
 use crate::segment::metadata_segment::MetadataSegmentWriter;
 use crate::segment::record_segment::RecordSegmentReader;
 use crate::segment::record_segment::RecordSegmentWriter;
+use crate::sysdb::sysdb::GetCollectionSnapshotError;
 use crate::sysdb::sysdb::GetCollectionsError;
-use crate::sysdb::sysdb::GetSegmentsError;
+use crate::sysdb::sysdb::GetSegmentError;
 use crate::sysdb::sysdb::SysDb;
+use crate::sysdb::sysdb::UpdateCollectionError;
 use crate::system::Component;
 use crate::system::Handler;
 use crate::system::Receiver;
 use crate::system::System;
+use crate::tracing::metrics::metrics;
+use crate::types::Collection;
 use crate::types::LogRecord;
 use crate::types::Segment;
 use crate::types::SegmentFlushInfo;
@@ -43,12 +49,17 @@ use crate::types::SegmentType;
 use async_trait::async_trait;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
+use std::time::Instant;
 use std::time::SystemTime;
+use std::time::SystemTimeError;
 use std::time::UNIX_EPOCH;
 use thiserror::Error;
 use tracing::Span;
 use uuid::Uuid;
 
+// The number of records read from the log per request.
+const PULL_LOGS_BATCH_SIZE: i32 = 100;
+
 /**  The state of the orchestrator.
 In chroma, we have a relatively fixed number of query plans that we can execute. Rather
 than a flexible state machine abstraction, we just manually define the states that we
@@ -58,7 +69,7 @@ understand. We can always add more abstraction later if we need it.
 
                                    ┌───► Write─────-------┐
                                    │                      │
-  Pending ─► PullLogs ─► Partition │                      ├─► Flush ─► Finished
+  Pending ─► PullLogs ─► Partition │                      ├─► Flush ─► Register ─► Finished
                                    │                      │
                                    └───► Write ───────────┘
 
@@ -90,7 +101,19 @@ pub struct CompactOrchestrator {
     hnsw_index_provider: HnswIndexProvider,
     // State we hold across the execution
     pulled_log_offset: Option<i64>,
+    // The log offset the segments were last compacted up to, according to the sysdb
+    compacted_log_position: i64,
+    // The dimension of the collection, or of the first embedding pulled if it has none yet
+    dimension: Option<usize>,
+    // Sparse embeddings have as many elements as they have components, so no dimension is
+    // checked and no HNSW index is built for them.
+    sparse_embeddings: bool,
     record_segment: Option<Segment>,
+    // The index of the version of the vector segment this compaction forks, which new
+    // queries no longer read once the compaction is registered.
+    superseded_hnsw_index_id: Option<Uuid>,
+    // The id of the index the vector segment is rebuilt into, see `rebuilding_hnsw_index`.
+    rebuilt_hnsw_index_id: Option<Uuid>,
     // Dispatcher
     dispatcher: Box<dyn Receiver<TaskMessage>>,
     // number of write segments tasks
@@ -100,30 +123,30 @@ pub struct CompactOrchestrator {
         Option<tokio::sync::oneshot::Sender<Result<CompactionResponse, Box<dyn ChromaError>>>>,
     // Current max offset id.
     curr_max_offset_id: Arc<AtomicU32>,
+    // The size of the queue of the orchestrator, see `with_queue_size`.
+    queue_size: usize,
+    // The deadline the compaction is run with, checked before it registers.
+    deadline: Deadline,
 }
 
 #[derive(Error, Debug)]
 enum GetSegmentWritersError {
-    #[error("No segments found for collection")]
-    NoSegmentsFound,
-    #[error("SysDB GetSegments Error")]
-    SysDbGetSegmentsError(#[from] GetSegmentsError),
+    #[error("Error getting segment")]
+    GetSegmentError(#[from] GetSegmentError),
     #[error("Error creating Record Segment Writer")]
     RecordSegmentWriterError,
     #[error("Error creating Metadata Segment Writer")]
     MetadataSegmentWriterError,
     #[error("Error creating HNSW Segment Writer")]
     HnswSegmentWriterError,
-    #[error("No record segment found for collection")]
-    NoRecordSegmentFound,
-    #[error("No metadata segment found for collection")]
-    NoMetadataSegmentFound,
     #[error("Collection not found")]
     CollectionNotFound,
-    #[error("Error getting collection")]
-    GetCollectionError(#[from] GetCollectionsError),
-    #[error("No hnsw segment found for collection")]
-    NoHnswSegmentFound,
+    #[error("Error getting the collection and its segments")]
+    GetSnapshotError(#[from] GetCollectionSnapshotError),
+    #[error("Collection has no dimension set and no embeddings to set it from")]
+    CollectionHasNoDimension,
+    #[error("Error recording the dimension of the collection")]
+    UpdateCollectionError(#[from] UpdateCollectionError),
 }
 
 impl ChromaError for GetSegmentWritersError {
@@ -132,6 +155,28 @@ impl ChromaError for GetSegmentWritersError {
     }
 }
 
+#[derive(Error, Debug)]
+enum CompactionError {
+    #[error("Collection not found")]
+    CollectionNotFound,
+    #[error("Error getting collection")]
+    GetCollectionError(#[from] GetCollectionsError),
+    #[error("Error getting the current time")]
+    SystemTimeError(#[from] SystemTimeError),
+}
+
+impl ChromaError for CompactionError {
+    fn code(&self) -> crate::errors::ErrorCodes {
+        match self {
+            CompactionError::CollectionNotFound => crate::errors::ErrorCodes::NotFound,
+            CompactionError::GetCollectionError(e) => e.code(),
+            CompactionError::SystemTimeError(_) => crate::errors::ErrorCodes::Internal,
+        }
+    }
+}
+
+const DEFAULT_QUEUE_SIZE: usize = 1000;
+
 // TODO: we need to improve this response
 #[derive(Debug)]
 pub struct CompactionResponse {
@@ -167,55 +212,180 @@ impl CompactOrchestrator {
             blockfile_provider,
             hnsw_index_provider,
             pulled_log_offset: None,
+            compacted_log_position: -1,
+            dimension: None,
+            sparse_embeddings: false,
             dispatcher,
             num_write_tasks: 0,
             result_channel,
             record_segment,
+            superseded_hnsw_index_id: None,
+            rebuilt_hnsw_index_id: None,
             curr_max_offset_id,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            deadline: Deadline::default(),
         }
     }
 
-    // TODO: It is possible that the offset_id from the compaction job is wrong since the log service
-    // can have an outdated view of the offset. We should filter out entries from the log based on the start offset
-    // of the segment, and not fully respect the offset_id from the compaction job
-    async fn pull_logs(
+    /// Sets the size of the queue of the orchestrator, 1000 by default. The orchestrator
+    /// holds back the operators sending it their results while it is full.
+    pub(crate) fn with_queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Writes the vector segment to a new index `index_id` built from the record segment,
+    /// instead of to a fork of the index it has.
+    /// # Description
+    /// The records of the log that are not compacted yet are applied to the new index and
+    /// the other segments as usual, and the new index is registered like any compaction, so
+    /// it replaces the old one with the next version of the collection. Queries read the
+    /// old index until then. The compaction registers the new index even if the log has no
+    /// new records.
+    /// # Notes
+    /// Registering fails if the collection has moved past the version of the compaction
+    /// job, so the same rebuild is never registered twice.
+    pub(crate) fn rebuilding_hnsw_index(mut self, index_id: Uuid) -> Self {
+        self.rebuilt_hnsw_index_id = Some(index_id);
+        self
+    }
+
+    fn terminate_with_error(
         &mut self,
-        self_address: Box<dyn Receiver<TaskResult<PullLogsOutput, PullLogsError>>>,
+        error: Box<dyn ChromaError>,
+        ctx: &crate::system::ComponentContext<CompactOrchestrator>,
     ) {
+        let result_channel = match self.result_channel.take() {
+            Some(tx) => tx,
+            None => {
+                tracing::error!("Compaction result channel already used: {:?}", error);
+                return;
+            }
+        };
+        let _ = result_channel.send(Err(error));
+        // Stop handling the results of tasks that are still running, so that nothing is
+        // flushed or registered after the failure.
+        ctx.cancellation_token.cancel();
+    }
+
+    // Returns the collection as registered with the sysdb, whose log position is the offset of
+    // the last log record that has been compacted into its segments.
+    async fn get_collection(&mut self) -> Result<Collection, Box<dyn ChromaError>> {
+        let collections = match self
+            .sysdb
+            .get_collections(Some(self.collection_id), None, None, None)
+            .await
+        {
+            Ok(collections) => collections,
+            Err(e) => return Err(Box::new(CompactionError::GetCollectionError(e))),
+        };
+        match collections.into_iter().next() {
+            Some(collection) => Ok(collection),
+            None => Err(Box::new(CompactionError::CollectionNotFound)),
+        }
+    }
+
+    async fn pull_logs(&mut self, ctx: &crate::system::ComponentContext<CompactOrchestrator>) {
         self.state = ExecutionState::PullLogs;
+        // The offset of the compaction job comes from the log service, whose view of the
+        // compacted offset can be outdated, e.g. when a previous attempt registered its
+        // segments with the sysdb but failed before updating the log. Start after whichever
+        // is further so that no record is applied to the segments twice.
+        let collection = match self.get_collection().await {
+            Ok(collection) => collection,
+            Err(e) => {
+                tracing::error!("Error getting the compacted log position: {:?}", e);
+                self.terminate_with_error(e, ctx);
+                return;
+            }
+        };
+        self.compacted_log_position = collection.log_position;
+        self.dimension = collection.dimension.map(|dimension| dimension as usize);
+        self.sparse_embeddings = collection.has_sparse_embeddings();
         let operator = PullLogsOperator::new(self.log.clone());
         let collection_id = self.collection_id;
-        let end_timestamp = SystemTime::now().duration_since(UNIX_EPOCH);
-        let end_timestamp = match end_timestamp {
+        let end_timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
             // TODO: change protobuf definition to use u64 instead of i64
             Ok(end_timestamp) => end_timestamp.as_nanos() as i64,
             Err(e) => {
-                // Log an error and reply + return
+                tracing::error!("Error getting the end timestamp of the logs: {:?}", e);
+                self.terminate_with_error(Box::new(CompactionError::SystemTimeError(e)), ctx);
                 return;
             }
         };
         let input = PullLogsInput::new(
             collection_id,
-            // Here we do not need to be inclusive since the compaction job
-            // offset is the one after the last compaction offset
-            self.compaction_job.offset,
-            100,
+            self.pull_start_offset(),
+            PULL_LOGS_BATCH_SIZE,
             None,
             Some(end_timestamp),
         );
-        let task = wrap(operator, input, self_address);
-        match self.dispatcher.send(task, None).await {
-            Ok(_) => (),
-            Err(e) => {
-                // TODO: log an error and reply to caller
+        let task = wrap(operator, input, ctx.sender.as_receiver());
+        if let Err(e) = self.dispatcher.send(task, None).await {
+            tracing::error!("Error dispatching pull logs for compaction {:?}", e);
+            self.terminate_with_error(Box::new(e), ctx);
+        }
+    }
+
+    fn pull_start_offset(&self) -> i64 {
+        // Here we do not need to be inclusive since the compaction job
+        // offset is the one after the last compaction offset
+        self.compaction_job
+            .offset
+            .max(self.compacted_log_position + 1)
+    }
+
+    // The offset of the last pulled record, counting the malformed records that were skipped
+    // while decoding. Those are never handed out, and the puller stops at the first page
+    // without a record to hand out, so the skipped records of the pull are in its first page.
+    fn last_pulled_log_offset(&self) -> Option<i64> {
+        let start = self.pull_start_offset();
+        let first_page = start..start + PULL_LOGS_BATCH_SIZE as i64;
+        let skipped = self.log.dead_letters().and_then(|dead_letters| {
+            dead_letters
+                .get(self.collection_id)
+                .iter()
+                .map(|error| error.log_offset)
+                .filter(|log_offset| first_page.contains(log_offset))
+                .max()
+        });
+        self.pulled_log_offset.max(skipped)
+    }
+
+    // Finishes the compaction without writing anything, because every record in the log has
+    // already been compacted or is malformed.
+    async fn finish_without_new_records(&mut self) {
+        self.state = ExecutionState::Finished;
+        // Catch the log service up if it missed the update from a previous attempt, and move
+        // it past the malformed records that were pulled, so that the collection is not
+        // scheduled again for records that can never be compacted.
+        let log_offset = self
+            .last_pulled_log_offset()
+            .map_or(self.compacted_log_position, |log_offset| {
+                log_offset.max(self.compacted_log_position)
+            });
+        if log_offset >= self.compaction_job.offset {
+            if let Err(e) = self
+                .log
+                .update_collection_log_offset(self.collection_id, log_offset)
+                .await
+            {
+                tracing::error!("Error updating the log offset: {:?}", e);
             }
         }
+        if let Some(result_channel) = self.result_channel.take() {
+            let _ = result_channel.send(Ok(CompactionResponse {
+                id: self.id,
+                compaction_job: self.compaction_job.clone(),
+                message: "No new records to compact".to_string(),
+            }));
+        }
     }
 
     async fn partition(
         &mut self,
         records: Chunk<LogRecord>,
-        self_address: Box<dyn Receiver<TaskResult<PartitionOutput, PartitionError>>>,
+        ctx: &crate::system::ComponentContext<CompactOrchestrator>,
     ) {
         self.state = ExecutionState::Partition;
         // TODO: make this configurable
@@ -223,21 +393,17 @@ impl CompactOrchestrator {
         let operator = PartitionOperator::new();
         println!("Sending N Records: {:?}", records.len());
         let input = PartitionInput::new(records, max_partition_size);
-        let task = wrap(operator, input, self_address);
-        match self.dispatcher.send(task, None).await {
-            Ok(_) => (),
-            Err(e) => {
-                // TODO: log an error and reply to caller
-            }
+        let task = wrap(operator, input, ctx.sender.as_receiver());
+        if let Err(e) = self.dispatcher.send(task, None).await {
+            tracing::error!("Error dispatching partition for compaction {:?}", e);
+            self.terminate_with_error(Box::new(e), ctx);
         }
     }
 
     async fn write(
         &mut self,
         partitions: Vec<Chunk<LogRecord>>,
-        self_address: Box<
-            dyn Receiver<TaskResult<WriteSegmentsOutput, WriteSegmentsOperatorError>>,
-        >,
+        ctx: &crate::system::ComponentContext<CompactOrchestrator>,
     ) {
         self.state = ExecutionState::Write;
 
@@ -247,6 +413,7 @@ impl CompactOrchestrator {
             Ok(writers) => writers,
             Err(e) => {
                 tracing::error!("Error creating writers for compaction {:?}", e);
+                self.terminate_with_error(e, ctx);
                 return;
             }
         };
@@ -266,13 +433,11 @@ impl CompactOrchestrator {
                     .clone(),
                 self.curr_max_offset_id.clone(),
             );
-            let task = wrap(operator, input, self_address.clone());
-            match self.dispatcher.send(task, Some(Span::current())).await {
-                Ok(_) => (),
-                Err(e) => {
-                    tracing::error!("Error dispatching writers for compaction {:?}", e);
-                    // Reply to caller
-                }
+            let task = wrap(operator, input, ctx.sender.as_receiver());
+            if let Err(e) = self.dispatcher.send(task, Some(Span::current())).await {
+                tracing::error!("Error dispatching writers for compaction {:?}", e);
+                self.terminate_with_error(Box::new(e), ctx);
+                return;
             }
         }
     }
@@ -280,9 +445,9 @@ impl CompactOrchestrator {
     async fn flush_s3(
         &mut self,
         record_segment_writer: RecordSegmentWriter,
-        hnsw_segment_writer: Box<DistributedHNSWSegmentWriter>,
+        hnsw_segment_writer: Option<Box<DistributedHNSWSegmentWriter>>,
         metadata_segment_writer: MetadataSegmentWriter<'static>,
-        self_address: Box<dyn Receiver<TaskResult<FlushS3Output, Box<dyn ChromaError>>>>,
+        ctx: &crate::system::ComponentContext<CompactOrchestrator>,
     ) {
         self.state = ExecutionState::Flush;
 
@@ -293,12 +458,10 @@ impl CompactOrchestrator {
             metadata_segment_writer,
         );
 
-        let task = wrap(operator, input, self_address);
-        match self.dispatcher.send(task, Some(Span::current())).await {
-            Ok(_) => (),
-            Err(e) => {
-                // Log an error and reply to caller
-            }
+        let task = wrap(operator, input, ctx.sender.as_receiver());
+        if let Err(e) = self.dispatcher.send(task, Some(Span::current())).await {
+            tracing::error!("Error dispatching flush for compaction {:?}", e);
+            self.terminate_with_error(Box::new(e), ctx);
         }
     }
 
@@ -306,9 +469,15 @@ impl CompactOrchestrator {
         &mut self,
         log_position: i64,
         segment_flush_info: Arc<[SegmentFlushInfo]>,
-        self_address: Box<dyn Receiver<TaskResult<RegisterOutput, RegisterError>>>,
+        ctx: &crate::system::ComponentContext<CompactOrchestrator>,
     ) {
         self.state = ExecutionState::Register;
+        // Stopping the orchestrator does not interrupt the handler it is running, so a
+        // compaction that is given up while it flushes would otherwise still register.
+        if let Err(e) = self.deadline.check() {
+            self.terminate_with_error(Box::new(e), ctx);
+            return;
+        }
         let operator = RegisterOperator::new();
         let input = RegisterInput::new(
             self.compaction_job.tenant_id.clone(),
@@ -320,12 +489,10 @@ impl CompactOrchestrator {
             self.log.clone(),
         );
 
-        let task = wrap(operator, input, self_address);
-        match self.dispatcher.send(task, None).await {
-            Ok(_) => (),
-            Err(e) => {
-                // TODO: log an error and reply to caller
-            }
+        let task = wrap(operator, input, ctx.sender.as_receiver());
+        if let Err(e) = self.dispatcher.send(task, None).await {
+            tracing::error!("Error dispatching register for compaction {:?}", e);
+            self.terminate_with_error(Box::new(e), ctx);
         }
     }
 
@@ -334,7 +501,7 @@ impl CompactOrchestrator {
     ) -> Result<
         (
             RecordSegmentWriter,
-            Box<DistributedHNSWSegmentWriter>,
+            Option<Box<DistributedHNSWSegmentWriter>>,
             MetadataSegmentWriter<'static>,
         ),
         Box<dyn ChromaError>,
@@ -345,49 +512,38 @@ impl CompactOrchestrator {
         // This method is for convenience to create the writers in a single place
         // It is not meant to be called multiple times in the same compaction job
 
-        let segments = self
-            .sysdb
-            .get_segments(None, None, None, Some(self.collection_id))
-            .await;
-
-        tracing::debug!("Retrived segments: {:?}", segments);
-
-        let segments = match segments {
-            Ok(segments) => {
-                if segments.is_empty() {
-                    // Log an error and return
-                    return Err(Box::new(GetSegmentWritersError::NoSegmentsFound));
-                }
-                segments
-            }
-            Err(e) => {
-                return Err(Box::new(GetSegmentWritersError::SysDbGetSegmentsError(e)));
+        // Every segment is forked from the same version of the collection.
+        let snapshot = match self.sysdb.get_collection_snapshot(self.collection_id).await {
+            Ok(snapshot) => snapshot,
+            Err(GetCollectionSnapshotError::CollectionNotFound(_)) => {
+                return Err(Box::new(GetSegmentWritersError::CollectionNotFound))
             }
+            Err(e) => return Err(Box::new(GetSegmentWritersError::GetSnapshotError(e))),
         };
 
-        let record_segment = segments
-            .iter()
-            .find(|segment| segment.r#type == SegmentType::BlockfileRecord);
+        let record_segment = match snapshot.segment(SegmentType::BlockfileRecord) {
+            Ok(segment) => segment,
+            Err(e) => return Err(Box::new(GetSegmentWritersError::GetSegmentError(e))),
+        };
 
         tracing::debug!("Found Record Segment: {:?}", record_segment);
 
-        if record_segment.is_none() {
-            return Err(Box::new(GetSegmentWritersError::NoRecordSegmentFound));
-        }
         // Create a record segment writer
-        let record_segment = record_segment.unwrap();
-        let record_segment_writer =
-            match RecordSegmentWriter::from_segment(record_segment, &self.blockfile_provider).await
-            {
-                Ok(writer) => writer,
-                Err(e) => {
-                    println!("Error creating Record Segment Writer: {:?}", e);
-                    return Err(Box::new(GetSegmentWritersError::RecordSegmentWriterError));
-                }
-            };
+        let record_segment_writer = match RecordSegmentWriter::from_segment(
+            &record_segment,
+            &self.blockfile_provider,
+        )
+        .await
+        {
+            Ok(writer) => writer,
+            Err(e) => {
+                println!("Error creating Record Segment Writer: {:?}", e);
+                return Err(Box::new(GetSegmentWritersError::RecordSegmentWriterError));
+            }
+        };
 
         tracing::debug!("Record Segment Writer created");
-        match RecordSegmentReader::from_segment(record_segment, &self.blockfile_provider).await {
+        match RecordSegmentReader::from_segment(&record_segment, &self.blockfile_provider).await {
             Ok(reader) => {
                 self.curr_max_offset_id = reader.get_current_max_offset_id();
             }
@@ -395,67 +551,87 @@ impl CompactOrchestrator {
                 self.curr_max_offset_id = Arc::new(AtomicU32::new(0));
             }
         };
-        self.record_segment = Some(record_segment.clone()); // auto deref.
+        self.record_segment = Some(record_segment.clone());
 
-        let metadata_segment = segments
-            .iter()
-            .find(|segment| segment.r#type == SegmentType::BlockfileMetadata);
+        let mt_segment = match snapshot.segment(SegmentType::BlockfileMetadata) {
+            Ok(segment) => segment,
+            Err(e) => return Err(Box::new(GetSegmentWritersError::GetSegmentError(e))),
+        };
 
-        tracing::debug!("Found metadata segment {:?}", metadata_segment);
+        tracing::debug!("Found metadata segment {:?}", mt_segment);
 
-        if metadata_segment.is_none() {
-            return Err(Box::new(GetSegmentWritersError::NoMetadataSegmentFound));
-        }
-        // Create a record segment writer
-        let mt_segment = metadata_segment.unwrap(); // safe to unwrap here.
-        let mt_segment_writer =
-            match MetadataSegmentWriter::from_segment(mt_segment, &self.blockfile_provider).await {
-                Ok(writer) => writer,
-                Err(e) => {
-                    println!("Error creating metadata Segment Writer: {:?}", e);
-                    return Err(Box::new(GetSegmentWritersError::MetadataSegmentWriterError));
-                }
-            };
+        // Create a metadata segment writer
+        let mt_segment_writer = match MetadataSegmentWriter::from_segment(
+            &mt_segment,
+            &self.blockfile_provider,
+        )
+        .await
+        {
+            Ok(writer) => writer,
+            Err(e) => {
+                println!("Error creating metadata Segment Writer: {:?}", e);
+                return Err(Box::new(GetSegmentWritersError::MetadataSegmentWriterError));
+            }
+        };
 
         tracing::debug!("Metadata Segment Writer created");
 
         // Create a hnsw segment writer
-        let collection_res = self
-            .sysdb
-            .get_collections(Some(self.collection_id), None, None, None)
-            .await;
+        let collection = &snapshot.collection;
+        // Sparse embeddings can not be indexed by HNSW, queries score them by brute force
+        // from the record segment and the log.
+        if self.sparse_embeddings {
+            return Ok((record_segment_writer, None, mt_segment_writer));
+        }
 
-        let collection_res = match collection_res {
-            Ok(collections) => {
-                if collections.is_empty() {
-                    return Err(Box::new(GetSegmentWritersError::CollectionNotFound));
+        let hnsw_segment = match snapshot.segment(SegmentType::HnswDistributed) {
+            Ok(segment) => segment,
+            Err(e) => return Err(Box::new(GetSegmentWritersError::GetSegmentError(e))),
+        };
+        self.superseded_hnsw_index_id = hnsw_index_id(&hnsw_segment);
+        let dimension = match (collection.dimension, self.dimension) {
+            (Some(dimension), _) => dimension,
+            // The first embeddings written to the collection set its dimension, every later
+            // write is checked against it.
+            (None, Some(dimension)) => {
+                let dimension = dimension as i32;
+                match self
+                    .sysdb
+                    .update_collection_dimension(self.collection_id, dimension)
+                    .await
+                {
+                    Ok(()) => dimension,
+                    Err(e) => {
+                        return Err(Box::new(GetSegmentWritersError::UpdateCollectionError(e)))
+                    }
                 }
-                collections
-            }
-            Err(e) => {
-                return Err(Box::new(GetSegmentWritersError::GetCollectionError(e)));
             }
+            (None, None) => return Err(Box::new(GetSegmentWritersError::CollectionHasNoDimension)),
         };
-        let collection = &collection_res[0];
 
-        let hnsw_segment = segments
-            .iter()
-            .find(|segment| segment.r#type == SegmentType::HnswDistributed);
-        if hnsw_segment.is_none() {
-            return Err(Box::new(GetSegmentWritersError::NoHnswSegmentFound));
-        }
-        let hnsw_segment = hnsw_segment.unwrap();
-        let dimension = collection
-            .dimension
-            .expect("Dimension is required in the compactor");
-
-        let hnsw_segment_writer = match DistributedHNSWSegmentWriter::from_segment(
-            hnsw_segment,
-            dimension as usize,
-            self.hnsw_index_provider.clone(),
-        )
-        .await
-        {
+        let hnsw_segment_writer = match self.rebuilt_hnsw_index_id {
+            Some(index_id) => {
+                DistributedHNSWSegmentWriter::rebuilt_from_segment(
+                    &hnsw_segment,
+                    dimension as usize,
+                    self.hnsw_index_provider.clone(),
+                    &record_segment,
+                    &self.blockfile_provider,
+                    index_id,
+                )
+                .await
+            }
+            None => DistributedHNSWSegmentWriter::from_segment(
+                &hnsw_segment,
+                dimension as usize,
+                self.hnsw_index_provider.clone(),
+                &record_segment,
+                &self.blockfile_provider,
+            )
+            .await
+            .map_err(|e| e as Box<dyn ChromaError>),
+        };
+        let hnsw_segment_writer = match hnsw_segment_writer {
             Ok(writer) => writer,
             Err(e) => {
                 println!("Error creating HNSW Segment Writer: {:?}", e);
@@ -465,19 +641,36 @@ impl CompactOrchestrator {
 
         Ok((
             record_segment_writer,
-            hnsw_segment_writer,
+            Some(hnsw_segment_writer),
             mt_segment_writer,
         ))
     }
 
-    pub(crate) async fn run(mut self) -> Result<CompactionResponse, Box<dyn ChromaError>> {
+    pub(crate) async fn run(self) -> Result<CompactionResponse, Box<dyn ChromaError>> {
+        self.run_with_deadline(Deadline::default()).await
+    }
+
+    /// Runs the compaction until it completes or `deadline` is exceeded, whichever comes
+    /// first. A compaction that is given up is not registered, unless the register had
+    /// already been dispatched, and then leaves the collection as it was.
+    pub(crate) async fn run_with_deadline(
+        mut self,
+        deadline: Deadline,
+    ) -> Result<CompactionResponse, Box<dyn ChromaError>> {
         println!("Running compaction job: {:?}", self.compaction_job);
+        let start = Instant::now();
+        let collection_id = self.collection_id;
         let (tx, rx) = tokio::sync::oneshot::channel();
         self.result_channel = Some(tx);
+        self.deadline = deadline.clone();
         let mut handle = self.system.clone().start_component(self);
-        let result = rx.await;
+        let result = tokio::select! {
+            result = rx => result.unwrap(),
+            _ = deadline.exceeded() => Err(Box::new(DeadlineExceededError) as Box<dyn ChromaError>),
+        };
         handle.stop();
-        result.unwrap()
+        metrics().record_compaction(collection_id, result.is_ok(), start.elapsed());
+        result
     }
 }
 
@@ -490,11 +683,11 @@ impl Component for CompactOrchestrator {
     }
 
     fn queue_size(&self) -> usize {
-        1000 // TODO: make configurable
+        self.queue_size
     }
 
     async fn on_start(&mut self, ctx: &crate::system::ComponentContext<Self>) -> () {
-        self.pull_logs(ctx.sender.as_receiver()).await;
+        self.pull_logs(ctx).await;
     }
 }
 
@@ -507,7 +700,7 @@ impl Handler<TaskResult<PullLogsOutput, PullLogsError>> for CompactOrchestrator
         ctx: &crate::system::ComponentContext<CompactOrchestrator>,
     ) {
         let message = message.into_inner();
-        let records = match message {
+        let mut records = match message {
             Ok(result) => result.logs(),
             Err(e) => {
                 // Log an error and return
@@ -523,18 +716,53 @@ impl Handler<TaskResult<PullLogsOutput, PullLogsError>> for CompactOrchestrator
             }
         };
         println!("Pulled Records: {:?}", records.len());
-        let final_record_pulled = records.get(records.len() - 1);
-        match final_record_pulled {
-            Some(record) => {
-                self.pulled_log_offset = Some(record.log_offset);
-                println!("Pulled Logs Up To Offset: {:?}", self.pulled_log_offset);
-                self.partition(records, ctx.sender.as_receiver()).await;
+        if let Some(dead_letters) = self.log.dead_letters() {
+            let skipped = dead_letters.get(self.collection_id).len();
+            if skipped > 0 {
+                println!(
+                    "Skipped {} malformed log records of this collection, {} in total",
+                    skipped,
+                    dead_letters.count()
+                );
             }
-            None => {
-                // Log an error and return
-                return;
+        }
+        if let Some(record) = records
+            .len()
+            .checked_sub(1)
+            .and_then(|last| records.get(last))
+        {
+            self.pulled_log_offset = Some(record.log_offset);
+            println!("Pulled Logs Up To Offset: {:?}", self.pulled_log_offset);
+            if self.dimension.is_none() && !self.sparse_embeddings {
+                self.dimension = records
+                    .iter()
+                    .find_map(|(record, _)| record.record.embedding.as_ref())
+                    .map(|embedding| embedding.len());
+            }
+            // Embeddings of another dimension would be read out of bounds by the index.
+            if let (false, Some(dead_letters)) = (self.sparse_embeddings, self.log.dead_letters()) {
+                dead_letters.hide_dimension_mismatches(
+                    self.collection_id,
+                    self.dimension,
+                    &mut records,
+                );
             }
         }
+        if records.len() == 0 {
+            match self.rebuilt_hnsw_index_id {
+                // A rebuilt index is registered even without new records, at the log
+                // position the collection is at.
+                Some(_) => {
+                    if self.pulled_log_offset.is_none() {
+                        self.pulled_log_offset = Some(self.compacted_log_position);
+                    }
+                    self.write(vec![records], ctx).await;
+                }
+                None => self.finish_without_new_records().await,
+            }
+            return;
+        }
+        self.partition(records, ctx).await;
     }
 }
 
@@ -561,7 +789,7 @@ impl Handler<TaskResult<PartitionOutput, PartitionError>> for CompactOrchestrato
                 return;
             }
         };
-        self.write(records, _ctx.sender.as_receiver()).await;
+        self.write(records, _ctx).await;
     }
 }
 
@@ -580,7 +808,8 @@ impl Handler<TaskResult<WriteSegmentsOutput, WriteSegmentsOperatorError>> for Co
                 output
             }
             Err(e) => {
-                // Log an error
+                tracing::error!("Error writing segments: {:?}", e);
+                self.terminate_with_error(Box::new(e), _ctx);
                 return;
             }
         };
@@ -594,7 +823,9 @@ impl Handler<TaskResult<WriteSegmentsOutput, WriteSegmentsOperatorError>> for Co
             let mut writer = output.metadata_segment_writer.clone();
             match writer.write_to_blockfiles().await {
                 Ok(()) => (),
-                Err(_) => {
+                Err(e) => {
+                    tracing::error!("Error writing metadata segment blockfiles: {:?}", e);
+                    self.terminate_with_error(Box::new(e), _ctx);
                     return;
                 }
             }
@@ -602,7 +833,7 @@ impl Handler<TaskResult<WriteSegmentsOutput, WriteSegmentsOperatorError>> for Co
                 output.record_segment_writer,
                 output.hnsw_segment_writer,
                 output.metadata_segment_writer,
-                _ctx.sender.as_receiver(),
+                _ctx,
             )
             .await;
         }
@@ -623,12 +854,15 @@ impl Handler<TaskResult<FlushS3Output, Box<dyn ChromaError>>> for CompactOrchest
                 self.register(
                     self.pulled_log_offset.unwrap(),
                     msg.segment_flush_info,
-                    _ctx.sender.as_receiver(),
+                    _ctx,
                 )
                 .await;
             }
             Err(e) => {
-                // Log an error
+                // The segments keep pointing at the files of the last compaction, since
+                // nothing is registered unless every segment was flushed.
+                tracing::error!("Error flushing segments: {:?}", e);
+                self.terminate_with_error(e, _ctx);
             }
         }
     }
@@ -653,6 +887,11 @@ impl Handler<TaskResult<RegisterOutput, RegisterError>> for CompactOrchestrator
 
         match message {
             Ok(_) => {
+                // Queries of the versions before this one hold on to the index for as long
+                // as they read it.
+                if let Some(id) = self.superseded_hnsw_index_id {
+                    self.hnsw_index_provider.retire(&id);
+                }
                 let response = CompactionResponse {
                     id: self.id,
                     compaction_job: self.compaction_job.clone(),
@@ -667,3 +906,892 @@ impl Handler<TaskResult<RegisterOutput, RegisterError>> for CompactOrchestrator
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chroma_proto;
+    use crate::errors::ErrorCodes;
+    use crate::execution::dispatcher::Dispatcher;
+    use crate::index::config::HnswProviderConfig;
+    use crate::log::dead_letter::DeadLetterQueue;
+    use crate::log::log::InMemoryLog;
+    use crate::log::log::InternalLogRecord;
+    use crate::storage::local::LocalStorage;
+    use crate::storage::Storage;
+    use crate::sysdb::test_sysdb::TestSysDb;
+    use crate::types::Collection;
+    use crate::types::Operation;
+    use crate::types::OperationRecord;
+    use crate::types::SegmentScope;
+    use crate::types::SegmentType;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    fn add_log(
+        log: &mut InMemoryLog,
+        collection_id: Uuid,
+        log_offset: i64,
+        id: &str,
+        embedding: Vec<f32>,
+        operation: Operation,
+    ) {
+        log.add_log(
+            collection_id,
+            Box::new(InternalLogRecord {
+                collection_id,
+                log_offset,
+                log_ts: log_offset,
+                record: LogRecord {
+                    log_offset,
+                    record: OperationRecord {
+                        id: id.to_string(),
+                        embedding: Some(embedding.into()),
+                        encoding: None,
+                        metadata: None,
+                        document: Some(format!("document {}", log_offset)),
+                        operation,
+                    },
+                },
+            }),
+        );
+    }
+
+    fn delete_log(log: &mut InMemoryLog, collection_id: Uuid, log_offset: i64, id: &str) {
+        log.add_log(
+            collection_id,
+            Box::new(InternalLogRecord {
+                collection_id,
+                log_offset,
+                log_ts: log_offset,
+                record: LogRecord {
+                    log_offset,
+                    record: OperationRecord {
+                        id: id.to_string(),
+                        embedding: None,
+                        encoding: None,
+                        metadata: None,
+                        document: None,
+                        operation: Operation::Delete,
+                    },
+                },
+            }),
+        );
+    }
+
+    fn new_segment(collection_id: Uuid, r#type: SegmentType, scope: SegmentScope) -> Segment {
+        Segment {
+            id: Uuid::new_v4(),
+            r#type,
+            scope,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: HashMap::new(),
+        }
+    }
+
+    // A sysdb with an empty collection and its three segments, returns the record segment
+    // as well.
+    fn sysdb_with_collection(
+        collection_id: Uuid,
+        tenant: &str,
+        dimension: Option<i32>,
+    ) -> (SysDb, Segment) {
+        let record_segment = new_segment(
+            collection_id,
+            SegmentType::BlockfileRecord,
+            SegmentScope::RECORD,
+        );
+        let mut sysdb = TestSysDb::new();
+        sysdb.add_collection(Collection {
+            id: collection_id,
+            name: "collection".to_string(),
+            metadata: None,
+            dimension,
+            tenant: tenant.to_string(),
+            database: "database".to_string(),
+            log_position: -1,
+            version: 0,
+        });
+        sysdb.add_segment(record_segment.clone());
+        sysdb.add_segment(new_segment(
+            collection_id,
+            SegmentType::BlockfileMetadata,
+            SegmentScope::METADATA,
+        ));
+        sysdb.add_segment(new_segment(
+            collection_id,
+            SegmentType::HnswDistributed,
+            SegmentScope::VECTOR,
+        ));
+        (SysDb::Test(sysdb), record_segment)
+    }
+
+    async fn get_collection(sysdb: &mut SysDb, collection_id: Uuid) -> Collection {
+        sysdb
+            .get_collections(Some(collection_id), None, None, None)
+            .await
+            .unwrap()
+            .remove(0)
+    }
+
+    #[tokio::test]
+    async fn test_consecutive_compactions() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmpdir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage.clone());
+        let hnsw_index_provider = HnswIndexProvider::new(
+            storage,
+            PathBuf::from(tmpdir.path().to_str().unwrap()),
+            &HnswProviderConfig::default(),
+        );
+        let system = System::new();
+        let dispatcher = system.start_component(Dispatcher::new(10, 10, 10));
+
+        let collection_id = Uuid::new_v4();
+        let tenant = "tenant".to_string();
+        let (mut sysdb, record_segment) = sysdb_with_collection(collection_id, &tenant, Some(2));
+
+        let mut log = InMemoryLog::new();
+        add_log(
+            &mut log,
+            collection_id,
+            0,
+            "id_1",
+            vec![1.0, 1.0],
+            Operation::Add,
+        );
+        add_log(
+            &mut log,
+            collection_id,
+            1,
+            "id_2",
+            vec![2.0, 2.0],
+            Operation::Add,
+        );
+        add_log(
+            &mut log,
+            collection_id,
+            2,
+            "id_3",
+            vec![3.0, 3.0],
+            Operation::Add,
+        );
+
+        // The log service does not learn about the offsets registered by a compaction here,
+        // so every run starts from the offset of the first compaction, like a retry would.
+        let compact = |log: &InMemoryLog, sysdb: &SysDb, collection_version: i32| {
+            CompactOrchestrator::new(
+                CompactionJob {
+                    collection_id,
+                    tenant_id: tenant.clone(),
+                    offset: 0,
+                    collection_version,
+                },
+                system.clone(),
+                collection_id,
+                Box::new(Log::InMemory(log.clone())),
+                Box::new(sysdb.clone()),
+                blockfile_provider.clone(),
+                hnsw_index_provider.clone(),
+                dispatcher.receiver(),
+                None,
+                None,
+                Arc::new(AtomicU32::new(0)),
+            )
+            .run()
+        };
+
+        let response = compact(&log, &sysdb, 0).await.unwrap();
+        assert_eq!(response.message, "Compaction Complete");
+        let collection = get_collection(&mut sysdb, collection_id).await;
+        assert_eq!(collection.log_position, 2);
+        assert_eq!(collection.version, 1);
+
+        add_log(
+            &mut log,
+            collection_id,
+            3,
+            "id_1",
+            vec![4.0, 4.0],
+            Operation::Update,
+        );
+        add_log(
+            &mut log,
+            collection_id,
+            4,
+            "id_4",
+            vec![5.0, 5.0],
+            Operation::Add,
+        );
+        let response = compact(&log, &sysdb, 1).await.unwrap();
+        assert_eq!(response.message, "Compaction Complete");
+        let collection = get_collection(&mut sysdb, collection_id).await;
+        assert_eq!(collection.log_position, 4);
+        assert_eq!(collection.version, 2);
+
+        // The records of the first compaction were not applied a second time.
+        let segment = sysdb
+            .get_segments(Some(record_segment.id), None, None, None)
+            .await
+            .unwrap()
+            .remove(0);
+        let reader = RecordSegmentReader::from_segment(&segment, &blockfile_provider)
+            .await
+            .unwrap();
+        assert_eq!(reader.count().await.unwrap(), 4);
+        let mut offset_ids = reader.get_all_offset_ids().await.unwrap();
+        offset_ids.sort();
+        assert_eq!(offset_ids, vec![1, 2, 3, 4]);
+        let record = reader
+            .get_data_and_offset_id_for_user_id("id_1")
+            .await
+            .unwrap();
+        assert_eq!(record.0.embedding, &[4.0, 4.0]);
+        assert_eq!(record.0.document, Some("document 3"));
+        let record = reader
+            .get_data_and_offset_id_for_user_id("id_4")
+            .await
+            .unwrap();
+        assert_eq!(record.0.embedding, &[5.0, 5.0]);
+        assert_eq!(record.1, 4);
+
+        // Nothing is left to compact, so nothing is registered.
+        let response = compact(&log, &sysdb, 2).await.unwrap();
+        assert_eq!(response.message, "No new records to compact");
+        let collection = get_collection(&mut sysdb, collection_id).await;
+        assert_eq!(collection.log_position, 4);
+        assert_eq!(collection.version, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_queries_see_one_version_while_compactions_flush() {
+        use crate::distance::DistanceRegistry;
+        use crate::execution::compute_pool::ComputePool;
+        use crate::execution::deadline::Deadline;
+        use crate::execution::orchestration::HnswQueryOrchestrator;
+        use crate::types::{KnnQuery, QueryInclude};
+        use std::collections::HashSet;
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        const ROUNDS: usize = 8;
+        const RECORDS_PER_ROUND: usize = 5;
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmpdir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage.clone());
+        let hnsw_index_provider = HnswIndexProvider::new(
+            storage,
+            PathBuf::from(tmpdir.path().to_str().unwrap()),
+            &HnswProviderConfig::default(),
+        );
+        let system = System::new();
+        let dispatcher = system.start_component(Dispatcher::new(10, 10, 10));
+        let compute_pool = ComputePool::new(2).unwrap();
+
+        let collection_id = Uuid::new_v4();
+        let tenant = "tenant".to_string();
+        let (mut sysdb, _) = sysdb_with_collection(collection_id, &tenant, Some(2));
+        let hnsw_segment_id = sysdb
+            .get_collection_snapshot(collection_id)
+            .await
+            .unwrap()
+            .segment(SegmentType::HnswDistributed)
+            .unwrap()
+            .id;
+        let log = parking_lot::Mutex::new(InMemoryLog::new());
+        // The number of records in the log, and whether the last of them has been compacted.
+        let written = AtomicUsize::new(0);
+        let done = AtomicBool::new(false);
+
+        // The same providers serve the compactions and the queries, like on a worker that
+        // does both.
+        let writes = async {
+            for round in 0..ROUNDS {
+                for i in 0..RECORDS_PER_ROUND {
+                    let n = round * RECORDS_PER_ROUND + i;
+                    add_log(
+                        &mut log.lock(),
+                        collection_id,
+                        n as i64,
+                        &format!("id_{}", n),
+                        vec![n as f32, 1.0],
+                        Operation::Add,
+                    );
+                }
+                written.store((round + 1) * RECORDS_PER_ROUND, Ordering::SeqCst);
+                let log = log.lock().clone();
+                let response = CompactOrchestrator::new(
+                    CompactionJob {
+                        collection_id,
+                        tenant_id: tenant.clone(),
+                        offset: 0,
+                        collection_version: round as i32,
+                    },
+                    system.clone(),
+                    collection_id,
+                    Box::new(Log::InMemory(log)),
+                    Box::new(sysdb.clone()),
+                    blockfile_provider.clone(),
+                    hnsw_index_provider.clone(),
+                    dispatcher.receiver(),
+                    None,
+                    None,
+                    Arc::new(AtomicU32::new(0)),
+                )
+                .run()
+                .await
+                .unwrap();
+                assert_eq!(response.message, "Compaction Complete");
+            }
+            done.store(true, Ordering::SeqCst);
+        };
+
+        // Every query sees each record written before it started exactly once, whether it
+        // is read from the segments or from the log, and nothing it could not have seen.
+        let queries = async {
+            let mut queries = 0;
+            while !done.load(Ordering::SeqCst) || queries == 0 {
+                let written = written.load(Ordering::SeqCst);
+                let log = log.lock().clone();
+                let results = HnswQueryOrchestrator::new(
+                    system.clone(),
+                    vec![KnnQuery {
+                        embedding: vec![0.0, 0.0],
+                        k: 1000,
+                    }],
+                    None,
+                    false,
+                    vec![],
+                    QueryInclude::default(),
+                    None,
+                    None,
+                    hnsw_segment_id,
+                    Box::new(Log::InMemory(log)),
+                    Box::new(sysdb.clone()),
+                    hnsw_index_provider.clone(),
+                    blockfile_provider.clone(),
+                    Arc::new(DistanceRegistry::new()),
+                    compute_pool.clone(),
+                    dispatcher.receiver(),
+                    Deadline::default(),
+                )
+                .run()
+                .await
+                .unwrap()
+                .remove(0);
+
+                let ids = results
+                    .iter()
+                    .map(|result| result.id.clone())
+                    .collect::<HashSet<_>>();
+                assert_eq!(ids.len(), results.len(), "duplicate results {:?}", ids);
+                assert!(results.len() >= written);
+                assert_eq!(
+                    ids,
+                    (0..results.len()).map(|n| format!("id_{}", n)).collect(),
+                );
+                for (n, result) in results.iter().enumerate() {
+                    // The embedding of id_n is [n, 1].
+                    assert_eq!(result.id, format!("id_{}", n));
+                    assert_eq!(result.distance, (n * n + 1) as f32);
+                }
+                queries += 1;
+            }
+            queries
+        };
+
+        let ((), queries) = tokio::join!(writes, queries);
+        assert!(queries > 0);
+        let collection = get_collection(&mut sysdb, collection_id).await;
+        assert_eq!(collection.version, ROUNDS as i32);
+        assert_eq!(
+            collection.log_position,
+            (ROUNDS * RECORDS_PER_ROUND) as i64 - 1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_first_embeddings_set_the_dimension() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmpdir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage.clone());
+        let hnsw_index_provider = HnswIndexProvider::new(
+            storage,
+            PathBuf::from(tmpdir.path().to_str().unwrap()),
+            &HnswProviderConfig::default(),
+        );
+        let system = System::new();
+        let dispatcher = system.start_component(Dispatcher::new(10, 10, 10));
+
+        let collection_id = Uuid::new_v4();
+        let tenant = "tenant".to_string();
+        let (mut sysdb, record_segment) = sysdb_with_collection(collection_id, &tenant, None);
+
+        let compact = |log: &InMemoryLog, sysdb: &SysDb, offset: i64, collection_version: i32| {
+            CompactOrchestrator::new(
+                CompactionJob {
+                    collection_id,
+                    tenant_id: tenant.clone(),
+                    offset,
+                    collection_version,
+                },
+                system.clone(),
+                collection_id,
+                Box::new(Log::InMemory(log.clone())),
+                Box::new(sysdb.clone()),
+                blockfile_provider.clone(),
+                hnsw_index_provider.clone(),
+                dispatcher.receiver(),
+                None,
+                None,
+                Arc::new(AtomicU32::new(0)),
+            )
+            .run()
+        };
+
+        let mut log = InMemoryLog::new();
+        add_log(
+            &mut log,
+            collection_id,
+            0,
+            "id_1",
+            vec![1.0, 1.0, 1.0],
+            Operation::Add,
+        );
+        add_log(
+            &mut log,
+            collection_id,
+            1,
+            "id_2",
+            vec![2.0, 2.0, 2.0],
+            Operation::Upsert,
+        );
+        let response = compact(&log, &sysdb, 0, 0).await.unwrap();
+        assert_eq!(response.message, "Compaction Complete");
+        let collection = get_collection(&mut sysdb, collection_id).await;
+        assert_eq!(collection.dimension, Some(3));
+        assert_eq!(collection.log_position, 1);
+
+        // Writes of another dimension are rejected before they reach any segment.
+        for (operation, id) in [
+            (Operation::Add, "id_3"),
+            (Operation::Update, "id_1"),
+            (Operation::Upsert, "id_4"),
+        ] {
+            let mut log = log.clone();
+            add_log(&mut log, collection_id, 2, id, vec![3.0, 3.0], operation);
+            let e = compact(&log, &sysdb, 2, 1).await.unwrap_err();
+            assert_eq!(e.code(), ErrorCodes::InvalidArgument);
+            assert_eq!(
+                e.to_string(),
+                format!(
+                    "Embedding of dimension 2 for record {}, the collection has dimension 3",
+                    id
+                )
+            );
+        }
+        let collection = get_collection(&mut sysdb, collection_id).await;
+        assert_eq!(collection.dimension, Some(3));
+        assert_eq!(collection.log_position, 1);
+        let segment = sysdb
+            .get_segments(Some(record_segment.id), None, None, None)
+            .await
+            .unwrap()
+            .remove(0);
+        let reader = RecordSegmentReader::from_segment(&segment, &blockfile_provider)
+            .await
+            .unwrap();
+        assert_eq!(reader.count().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_log_moves_past_pulls_of_only_malformed_records() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmpdir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage.clone());
+        let hnsw_index_provider = HnswIndexProvider::new(
+            storage,
+            PathBuf::from(tmpdir.path().to_str().unwrap()),
+            &HnswProviderConfig::default(),
+        );
+        let system = System::new();
+        let dispatcher = system.start_component(Dispatcher::new(10, 10, 10));
+
+        let collection_id = Uuid::new_v4();
+        let tenant = "tenant".to_string();
+        let (mut sysdb, _) = sysdb_with_collection(collection_id, &tenant, Some(3));
+
+        let compact = |log: &InMemoryLog, offset: i64| {
+            CompactOrchestrator::new(
+                CompactionJob {
+                    collection_id,
+                    tenant_id: tenant.clone(),
+                    offset,
+                    collection_version: 0,
+                },
+                system.clone(),
+                collection_id,
+                Box::new(Log::InMemory(log.clone())),
+                Box::new(sysdb.clone()),
+                blockfile_provider.clone(),
+                hnsw_index_provider.clone(),
+                dispatcher.receiver(),
+                None,
+                None,
+                Arc::new(AtomicU32::new(0)),
+            )
+            .run()
+        };
+
+        let dead_letters = DeadLetterQueue::new(10);
+        let mut log = InMemoryLog::new();
+        log.set_dead_letters(dead_letters.clone());
+        // Every record has an embedding of another dimension than the collection.
+        add_log(
+            &mut log,
+            collection_id,
+            0,
+            "id_1",
+            vec![1.0, 1.0],
+            Operation::Add,
+        );
+        add_log(
+            &mut log,
+            collection_id,
+            1,
+            "id_2",
+            vec![2.0, 2.0],
+            Operation::Add,
+        );
+        let response = compact(&log, 0).await.unwrap();
+        assert_eq!(response.message, "No new records to compact");
+        assert_eq!(dead_letters.get(collection_id).len(), 2);
+        assert_eq!(log.collection_log_offset(collection_id), Some(1));
+
+        // The log service returned two more records, neither of which could be decoded.
+        let decoded = dead_letters.decode(
+            collection_id,
+            (2..4)
+                .map(|log_offset| chroma_proto::LogRecord {
+                    log_offset,
+                    record: None,
+                })
+                .collect(),
+        );
+        assert!(decoded.is_empty());
+        let response = compact(&log, 2).await.unwrap();
+        assert_eq!(response.message, "No new records to compact");
+        assert_eq!(log.collection_log_offset(collection_id), Some(3));
+
+        // Nothing was written to the collection.
+        let collection = get_collection(&mut sysdb, collection_id).await;
+        assert_eq!(collection.log_position, -1);
+        assert_eq!(collection.version, 0);
+    }
+
+    #[tokio::test]
+    async fn test_sparse_collections_are_compacted_into_the_record_segment() {
+        use crate::distance::sparse::SparseVector;
+        use crate::distance::DistanceRegistry;
+        use crate::execution::compute_pool::ComputePool;
+        use crate::execution::orchestration::HnswQueryOrchestrator;
+        use crate::types::{KnnQuery, MetadataValue, QueryInclude, EMBEDDING_ENCODING_KEY};
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmpdir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage.clone());
+        let hnsw_index_provider = HnswIndexProvider::new(
+            storage,
+            PathBuf::from(tmpdir.path().to_str().unwrap()),
+            &HnswProviderConfig::default(),
+        );
+        let system = System::new();
+        let dispatcher = system.start_component(Dispatcher::new(10, 10, 10));
+
+        let collection_id = Uuid::new_v4();
+        let tenant = "tenant".to_string();
+        let (mut sysdb, record_segment) = sysdb_with_collection(collection_id, &tenant, None);
+        let mut collection = get_collection(&mut sysdb, collection_id).await;
+        collection.metadata = Some(HashMap::from([(
+            EMBEDDING_ENCODING_KEY.to_string(),
+            MetadataValue::Str("sparse".to_string()),
+        )]));
+        match &mut sysdb {
+            SysDb::Test(test_sysdb) => test_sysdb.add_collection(collection),
+            _ => unreachable!(),
+        }
+        let snapshot = sysdb.get_collection_snapshot(collection_id).await.unwrap();
+        let hnsw_segment = snapshot.segment(SegmentType::HnswDistributed).unwrap();
+
+        // Sparse embeddings of different lengths share the collection.
+        let sparse = |indices: &[u32]| {
+            SparseVector::new(indices.to_vec(), None)
+                .unwrap()
+                .to_embedding()
+        };
+        let mut log = InMemoryLog::new();
+        for (offset, indices) in [vec![1, 2], vec![2, 3], vec![5]].iter().enumerate() {
+            add_log(
+                &mut log,
+                collection_id,
+                offset as i64,
+                &format!("id_{}", offset),
+                sparse(indices),
+                Operation::Add,
+            );
+        }
+        let response = CompactOrchestrator::new(
+            CompactionJob {
+                collection_id,
+                tenant_id: tenant.clone(),
+                offset: 0,
+                collection_version: 0,
+            },
+            system.clone(),
+            collection_id,
+            Box::new(Log::InMemory(log.clone())),
+            Box::new(sysdb.clone()),
+            blockfile_provider.clone(),
+            hnsw_index_provider.clone(),
+            dispatcher.receiver(),
+            None,
+            None,
+            Arc::new(AtomicU32::new(0)),
+        )
+        .run()
+        .await
+        .unwrap();
+        assert_eq!(response.message, "Compaction Complete");
+        let snapshot = sysdb.get_collection_snapshot(collection_id).await.unwrap();
+        assert_eq!(snapshot.collection.log_position, 2);
+        assert_eq!(snapshot.collection.dimension, None);
+        assert!(snapshot
+            .segment(SegmentType::HnswDistributed)
+            .unwrap()
+            .file_path
+            .is_empty());
+        let segment = snapshot.segment_by_id(&record_segment.id).unwrap();
+        let reader = RecordSegmentReader::from_segment(segment, &blockfile_provider)
+            .await
+            .unwrap();
+        assert_eq!(reader.count().await.unwrap(), 3);
+
+        // Queries score the compacted records along with the log.
+        add_log(
+            &mut log,
+            collection_id,
+            3,
+            "id_3",
+            sparse(&[2, 3, 4]),
+            Operation::Add,
+        );
+        delete_log(&mut log, collection_id, 4, "id_0");
+        let results = HnswQueryOrchestrator::new(
+            system.clone(),
+            vec![KnnQuery {
+                embedding: sparse(&[2, 3]),
+                k: 10,
+            }],
+            None,
+            false,
+            vec![],
+            QueryInclude::default(),
+            None,
+            None,
+            hnsw_segment.id,
+            Box::new(Log::InMemory(log.clone())),
+            Box::new(sysdb.clone()),
+            hnsw_index_provider.clone(),
+            blockfile_provider.clone(),
+            Arc::new(DistanceRegistry::new()),
+            ComputePool::new(2).unwrap(),
+            dispatcher.receiver(),
+            Deadline::default(),
+        )
+        .run()
+        .await
+        .unwrap()
+        .remove(0);
+        let ids: Vec<_> = results.iter().map(|result| result.id.as_str()).collect();
+        assert_eq!(ids, vec!["id_1", "id_3", "id_2"]);
+        assert_eq!(results[0].distance, 0.0);
+        assert_eq!(results[2].distance, 1.0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_rebuild_after_deletes_shrinks_the_index() {
+        use crate::distance::DistanceRegistry;
+        use crate::execution::compute_pool::ComputePool;
+        use crate::execution::orchestration::HnswQueryOrchestrator;
+        use crate::types::{KnnQuery, QueryInclude};
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmpdir.path().to_str().unwrap()));
+        let blockfile_provider = BlockfileProvider::new_arrow(storage.clone());
+        let hnsw_index_provider = HnswIndexProvider::new(
+            storage,
+            PathBuf::from(tmpdir.path().to_str().unwrap()),
+            &HnswProviderConfig::default(),
+        );
+        let system = System::new();
+        let dispatcher = system.start_component(Dispatcher::new(10, 10, 10));
+        let compute_pool = ComputePool::new(2).unwrap();
+
+        let collection_id = Uuid::new_v4();
+        let tenant = "tenant".to_string();
+        let (mut sysdb, _) = sysdb_with_collection(collection_id, &tenant, Some(2));
+        let hnsw_segment = |snapshot: &crate::sysdb::snapshot::CollectionSnapshot| {
+            snapshot.segment(SegmentType::HnswDistributed).unwrap()
+        };
+        let hnsw_segment_id =
+            hnsw_segment(&sysdb.get_collection_snapshot(collection_id).await.unwrap()).id;
+
+        let compact = |log: &InMemoryLog, sysdb: &SysDb, collection_version: i32| {
+            CompactOrchestrator::new(
+                CompactionJob {
+                    collection_id,
+                    tenant_id: tenant.clone(),
+                    offset: 0,
+                    collection_version,
+                },
+                system.clone(),
+                collection_id,
+                Box::new(Log::InMemory(log.clone())),
+                Box::new(sysdb.clone()),
+                blockfile_provider.clone(),
+                hnsw_index_provider.clone(),
+                dispatcher.receiver(),
+                None,
+                None,
+                Arc::new(AtomicU32::new(0)),
+            )
+        };
+        // The ids and distances of every record, closest first.
+        let query = |log: &InMemoryLog, sysdb: &SysDb| {
+            HnswQueryOrchestrator::new(
+                system.clone(),
+                vec![KnnQuery {
+                    embedding: vec![0.0, 0.0],
+                    k: 1000,
+                }],
+                None,
+                false,
+                vec![],
+                QueryInclude::default(),
+                None,
+                None,
+                hnsw_segment_id,
+                Box::new(Log::InMemory(log.clone())),
+                Box::new(sysdb.clone()),
+                hnsw_index_provider.clone(),
+                blockfile_provider.clone(),
+                Arc::new(DistanceRegistry::new()),
+                compute_pool.clone(),
+                dispatcher.receiver(),
+                Deadline::default(),
+            )
+            .run()
+        };
+        let results = |mut results: Vec<Vec<_>>| {
+            results
+                .remove(0)
+                .into_iter()
+                .map(|result: crate::types::VectorQueryResult| (result.id, result.distance))
+                .collect::<Vec<_>>()
+        };
+
+        // The embedding of id_n is [n, 1].
+        let mut log = InMemoryLog::new();
+        for n in 0..1500 {
+            add_log(
+                &mut log,
+                collection_id,
+                n,
+                &format!("id_{}", n),
+                vec![n as f32, 1.0],
+                Operation::Add,
+            );
+        }
+        compact(&log, &sysdb, 0).run().await.unwrap();
+        // Every record but every fifth one is deleted.
+        let deleted = (0..1500).filter(|n| n % 5 != 0);
+        for (i, n) in deleted.enumerate() {
+            delete_log(
+                &mut log,
+                collection_id,
+                1500 + i as i64,
+                &format!("id_{}", n),
+            );
+        }
+        compact(&log, &sysdb, 1).run().await.unwrap();
+        let snapshot = sysdb.get_collection_snapshot(collection_id).await.unwrap();
+        let old_index_id = hnsw_index_id(&hnsw_segment(&snapshot)).unwrap();
+        let old_stats = hnsw_index_provider
+            .stats(&old_index_id)
+            .await
+            .unwrap()
+            .index;
+        assert_eq!(old_stats.element_count, 300);
+        assert_eq!(old_stats.deleted_count, 1200);
+
+        // Records that are not compacted yet are written to the rebuilt index as well.
+        for n in 0..10 {
+            add_log(
+                &mut log,
+                collection_id,
+                2700 + n,
+                &format!("id_{}", 1500 + n),
+                vec![(1500 + n) as f32, 1.0],
+                Operation::Add,
+            );
+        }
+        delete_log(&mut log, collection_id, 2710, "id_1495");
+        let before = results(query(&log, &sysdb).await.unwrap());
+        assert_eq!(before.len(), 309);
+
+        let rebuild_id = Uuid::new_v4();
+        let response = compact(&log, &sysdb, 2)
+            .rebuilding_hnsw_index(rebuild_id)
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(response.message, "Compaction Complete");
+        let snapshot = sysdb.get_collection_snapshot(collection_id).await.unwrap();
+        assert_eq!(snapshot.collection.version, 3);
+        assert_eq!(snapshot.collection.log_position, 2710);
+        assert_eq!(hnsw_index_id(&hnsw_segment(&snapshot)), Some(rebuild_id));
+        let stats = hnsw_index_provider.stats(&rebuild_id).await.unwrap();
+        // Of the deleted records, only the one deleted since the last compaction is left.
+        assert_eq!(stats.index.element_count, 309);
+        assert_eq!(stats.index.deleted_count, 1);
+        assert!(!stats.dirty);
+        assert!(hnsw_index_provider.get(&old_index_id).is_none());
+        assert_eq!(results(query(&log, &sysdb).await.unwrap()), before);
+
+        // The same rebuild does not register again once the collection has moved on.
+        let e = compact(&log, &sysdb, 2)
+            .rebuilding_hnsw_index(rebuild_id)
+            .run()
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), ErrorCodes::Aborted);
+        let collection = get_collection(&mut sysdb, collection_id).await;
+        assert_eq!(collection.version, 3);
+
+        // Without new records to compact, the rebuilt index is registered at the position
+        // the log was compacted to.
+        let second_id = Uuid::new_v4();
+        let response = compact(&log, &sysdb, 3)
+            .rebuilding_hnsw_index(second_id)
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(response.message, "Compaction Complete");
+        let snapshot = sysdb.get_collection_snapshot(collection_id).await.unwrap();
+        assert_eq!(snapshot.collection.version, 4);
+        assert_eq!(snapshot.collection.log_position, 2710);
+        assert_eq!(hnsw_index_id(&hnsw_segment(&snapshot)), Some(second_id));
+        assert_eq!(results(query(&log, &sysdb).await.unwrap()), before);
+    }
+}
@@ -1,6 +1,4 @@
-use super::common::{
-    get_collection_by_id, get_hnsw_segment_by_id, get_record_segment_by_collection_id,
-};
+use super::common::{get_collection_snapshot, get_hnsw_segment_by_id};
 use crate::{
     blockstore::provider::BlockfileProvider,
     errors::{ChromaError, ErrorCodes},
@@ -18,7 +16,7 @@ use crate::{
     log::log::{Log, PullLogsError},
     sysdb::sysdb::SysDb,
     system::{ChannelError, Component, ComponentContext, Handler, Receiver, System},
-    types::{Collection, GetVectorsResult, LogRecord, Segment},
+    types::{Collection, GetVectorsResult, LogRecord, ScalarEncoding, Segment, SegmentType},
 };
 use async_trait::async_trait;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -237,22 +235,23 @@ impl Component for GetVectorsOrchestrator {
             }
         };
 
-        let collection = match get_collection_by_id(self.sysdb.clone(), collection_id).await {
-            Ok(collection) => collection,
+        // The record segment and the log position are read as of one version.
+        let snapshot = match get_collection_snapshot(self.sysdb.clone(), collection_id).await {
+            Ok(snapshot) => snapshot,
             Err(e) => {
                 self.terminate_with_error(e, ctx);
                 return;
             }
         };
+        let collection = snapshot.collection.clone();
 
-        let record_segment =
-            match get_record_segment_by_collection_id(self.sysdb.clone(), collection_id).await {
-                Ok(segment) => segment,
-                Err(e) => {
-                    self.terminate_with_error(e, ctx);
-                    return;
-                }
-            };
+        let record_segment = match snapshot.segment(SegmentType::BlockfileRecord) {
+            Ok(segment) => segment,
+            Err(e) => {
+                self.terminate_with_error(Box::new(e), ctx);
+                return;
+            }
+        };
 
         self.record_segment = Some(record_segment);
         self.collection = Some(collection);
@@ -295,9 +294,17 @@ impl Handler<TaskResult<GetVectorsOperatorOutput, GetVectorsOperatorError>>
         let message = message.into_inner();
         match message {
             Ok(output) => {
+                let sparse_embeddings = self
+                    .collection
+                    .as_ref()
+                    .is_some_and(|collection| collection.has_sparse_embeddings());
                 let result = GetVectorsResult {
                     ids: output.ids,
                     vectors: output.vectors,
+                    encoding: match sparse_embeddings {
+                        true => ScalarEncoding::SPARSE,
+                        false => ScalarEncoding::FLOAT32,
+                    },
                 };
                 let result_channel = self
                     .result_channel
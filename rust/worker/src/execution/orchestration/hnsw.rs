@@ -1,22 +1,27 @@
 use super::super::operator::{wrap, TaskMessage};
 use super::super::operators::pull_log::{PullLogsInput, PullLogsOperator};
-use super::common::{
-    get_collection_by_id, get_hnsw_segment_by_id, get_record_segment_by_collection_id,
-};
+use super::common::{get_collection_snapshot, get_hnsw_segment_by_id, GetHnswSegmentByIdError};
 use crate::blockstore::provider::BlockfileProvider;
-use crate::distance::DistanceFunction;
+use crate::distance::{Distance, DistanceFunction, DistanceRegistry};
 use crate::errors::{ChromaError, ErrorCodes};
+use crate::execution::compute_pool::ComputePool;
 use crate::execution::data::data_chunk::Chunk;
+use crate::execution::deadline::{Deadline, DeadlineExceededError};
 use crate::execution::operator::TaskResult;
 use crate::execution::operators::brute_force_knn::{
     BruteForceKnnOperator, BruteForceKnnOperatorError, BruteForceKnnOperatorInput,
-    BruteForceKnnOperatorOutput,
+    BruteForceKnnOperatorOutput, BruteForceKnnResult,
 };
 use crate::execution::operators::hnsw_knn::{
     HnswKnnOperator, HnswKnnOperatorInput, HnswKnnOperatorOutput,
 };
 use crate::execution::operators::merge_knn_results::{
-    MergeKnnResultsOperator, MergeKnnResultsOperatorInput, MergeKnnResultsOperatorOutput,
+    MergeKnnOptions, MergeKnnResultsOperator, MergeKnnResultsOperatorInput,
+    MergeKnnResultsOperatorOutput,
+};
+use crate::execution::operators::metadata_filtering::{
+    MetadataFilteringError, MetadataFilteringInput, MetadataFilteringOperator,
+    MetadataFilteringOutput,
 };
 use crate::execution::operators::normalize_vectors::normalize;
 use crate::execution::operators::pull_log::PullLogsOutput;
@@ -28,7 +33,10 @@ use crate::segment::distributed_hnsw_segment::{
 };
 use crate::sysdb::sysdb::{GetCollectionsError, GetSegmentsError, SysDb};
 use crate::system::{ComponentContext, System};
-use crate::types::{Collection, LogRecord, Segment, SegmentType, VectorQueryResult};
+use crate::types::{
+    Collection, KnnQuery, LogRecord, QueryInclude, Segment, SegmentType, VectorQueryResult, Where,
+    WhereDocument,
+};
 use crate::{
     log::log::Log,
     system::{Component, Handler, Receiver},
@@ -49,19 +57,23 @@ expect to encounter for a given query plan. This is a bit more rigid, but it's a
 understand. We can always add more abstraction later if we need it.
 ```plaintext
 
-                               ┌───► Brute Force ─────┐
-                               │                      │
-  Pending ─► PullLogs ─► Group │                      ├─► MergeResults ─► Finished
-                               │                      │
-                               └───► HNSW ────────────┘
+                                          ┌───► Brute Force ─────┐
+                                          │                      │
+  Pending ─► PullLogs ─► Group ─► Filter  │                      ├─► MergeResults ─► Finished
+                                          │                      │
+                                          └───► HNSW ────────────┘
 
 ```
+The Filter state is only entered for queries with a where or where_document clause.
+The query is given up with a deadline exceeded error as soon as its deadline is exceeded,
+and no state is entered after that.
 */
 #[derive(Debug)]
 enum ExecutionState {
     Pending,
     PullLogs,
     Partition,
+    Filter,
     QueryKnn, // This is both the Brute force and HNSW query state
     MergeResults,
     Finished,
@@ -83,6 +95,14 @@ enum HnswSegmentQueryError {
     HnswSegmentHasNoCollection,
     #[error("Collection has no dimension set")]
     CollectionHasNoDimension,
+    #[error("Query vector {index} has dimension {got}, the collection has dimension {expected}")]
+    QueryVectorDimensionMismatch {
+        index: usize,
+        expected: usize,
+        got: usize,
+    },
+    #[error("Similarities are only defined for cosine and ip, not {0}")]
+    SimilarityNotSupported(String),
 }
 
 impl ChromaError for HnswSegmentQueryError {
@@ -95,6 +115,10 @@ impl ChromaError for HnswSegmentQueryError {
             HnswSegmentQueryError::RecordSegmentNotFound(_) => ErrorCodes::NotFound,
             HnswSegmentQueryError::HnswSegmentHasNoCollection => ErrorCodes::InvalidArgument,
             HnswSegmentQueryError::CollectionHasNoDimension => ErrorCodes::InvalidArgument,
+            HnswSegmentQueryError::QueryVectorDimensionMismatch { .. } => {
+                ErrorCodes::InvalidArgument
+            }
+            HnswSegmentQueryError::SimilarityNotSupported(_) => ErrorCodes::InvalidArgument,
         }
     }
 }
@@ -105,27 +129,38 @@ pub(crate) struct HnswQueryOrchestrator {
     // Component Execution
     system: System,
     // Query state
-    query_vectors: Vec<Vec<f32>>,
-    k: i32,
+    // Searched as one batch, each for its own k.
+    queries: Arc<[KnnQuery]>,
+    // Overrides the HNSW index's configured ef when set.
+    ef_search: Option<usize>,
+    // Return similarities instead of distances.
+    return_similarity: bool,
     allowed_ids: Arc<[String]>,
     allowed_ids_hnsw_segment: Arc<[String]>,
     allowed_ids_brute_force: Arc<[String]>,
-    include_embeddings: bool,
+    include: QueryInclude,
+    where_clause: Option<Where>,
+    where_document_clause: Option<WhereDocument>,
     hnsw_segment_id: Uuid,
+    deadline: Deadline,
     // State fetched or created for query execution
     hnsw_segment: Option<Segment>,
     record_segment: Option<Segment>,
+    // Only fetched for queries with a where or where_document clause.
+    metadata_segment: Option<Segment>,
+    // Sorted offset ids of the records that match the where and where_document clauses.
+    allowed_offset_ids: Option<Arc<[u32]>>,
     collection: Option<Collection>,
+    // Only set when the distance function is one the HNSW index can be built with.
     index_config: Option<IndexConfig>,
-    // query_vectors index to the result
+    distance: Option<Arc<dyn Distance>>,
+    // Sparse embeddings have no dimension and are only ever scored by brute force.
+    sparse_embeddings: bool,
+    // queries index to the result
     hnsw_result_offset_ids: HashMap<usize, Vec<usize>>,
     hnsw_result_distances: HashMap<usize, Vec<f32>>,
-    brute_force_result_user_ids: HashMap<usize, Vec<String>>,
-    brute_force_result_distances: HashMap<usize, Vec<f32>>,
-    brute_force_result_embeddings: HashMap<usize, Vec<Vec<f32>>>,
-    // Task id to query_vectors index
-    hnsw_task_id_to_query_index: HashMap<Uuid, usize>,
-    brute_force_task_id_to_query_index: HashMap<Uuid, usize>,
+    brute_force_results: HashMap<usize, BruteForceKnnResult>,
+    // Task id to queries index
     merge_task_id_to_query_index: HashMap<Uuid, usize>,
     // Result state
     results: Option<Vec<Vec<VectorQueryResult>>>,
@@ -138,6 +173,8 @@ pub(crate) struct HnswQueryOrchestrator {
     dispatcher: Box<dyn Receiver<TaskMessage>>,
     hnsw_index_provider: HnswIndexProvider,
     blockfile_provider: BlockfileProvider,
+    distance_registry: Arc<DistanceRegistry>,
+    compute_pool: ComputePool,
     // Result channel
     result_channel: Option<
         tokio::sync::oneshot::Sender<Result<Vec<Vec<VectorQueryResult>>, Box<dyn ChromaError>>>,
@@ -147,30 +184,36 @@ pub(crate) struct HnswQueryOrchestrator {
 impl HnswQueryOrchestrator {
     pub(crate) fn new(
         system: System,
-        query_vectors: Vec<Vec<f32>>,
-        k: i32,
+        queries: Vec<KnnQuery>,
+        ef_search: Option<usize>,
+        return_similarity: bool,
         allowed_ids: Vec<String>,
-        include_embeddings: bool,
+        include: QueryInclude,
+        where_clause: Option<Where>,
+        where_document_clause: Option<WhereDocument>,
         segment_id: Uuid,
         log: Box<Log>,
         sysdb: Box<SysDb>,
         hnsw_index_provider: HnswIndexProvider,
         blockfile_provider: BlockfileProvider,
+        distance_registry: Arc<DistanceRegistry>,
+        compute_pool: ComputePool,
         dispatcher: Box<dyn Receiver<TaskMessage>>,
+        deadline: Deadline,
     ) -> Self {
-        // Set the merge dependency count to the number of query vectors * 2
-        // N for the HNSW query and N for the Brute force query
-        let merge_dependency_count = (query_vectors.len() * 2) as u32;
+        // The whole batch is answered by one HNSW task and one brute force task, whose
+        // results are merged per query vector.
+        let merge_dependency_count = 2;
         // Set the finish dependency count to the number of query vectors
         // since each query vector will have a merge task
-        let finish_dependency_count = query_vectors.len() as u32;
+        let finish_dependency_count = queries.len() as u32;
         // pre-allocate the result vectors
-        let results = Some(Vec::with_capacity(query_vectors.len()));
+        let results = Some(Vec::with_capacity(queries.len()));
         tracing::info!(
-            "Performing KNN for k = {}, allowed_ids = {:?}, num query vectors = {:?}",
-            k,
+            "Performing KNN for k = {:?}, allowed_ids = {:?}, num query vectors = {:?}",
+            queries.iter().map(|query| query.k).collect::<Vec<_>>(),
             allowed_ids,
-            query_vectors.len()
+            queries.len()
         );
 
         HnswQueryOrchestrator {
@@ -178,24 +221,28 @@ impl HnswQueryOrchestrator {
             system,
             merge_dependency_count,
             finish_dependency_count,
-            query_vectors,
-            k,
+            queries: queries.into(),
+            ef_search,
+            return_similarity,
             allowed_ids: allowed_ids.into(),
             allowed_ids_brute_force: Arc::new([]),
             allowed_ids_hnsw_segment: Arc::new([]),
-            include_embeddings,
+            include,
+            where_clause,
+            where_document_clause,
             hnsw_segment_id: segment_id,
+            deadline,
             hnsw_segment: None,
             record_segment: None,
+            metadata_segment: None,
+            allowed_offset_ids: None,
             collection: None,
             index_config: None,
+            distance: None,
+            sparse_embeddings: false,
             hnsw_result_offset_ids: HashMap::new(),
             hnsw_result_distances: HashMap::new(),
-            brute_force_result_user_ids: HashMap::new(),
-            brute_force_result_distances: HashMap::new(),
-            brute_force_result_embeddings: HashMap::new(),
-            hnsw_task_id_to_query_index: HashMap::new(),
-            brute_force_task_id_to_query_index: HashMap::new(),
+            brute_force_results: HashMap::new(),
             merge_task_id_to_query_index: HashMap::new(),
             results,
             log,
@@ -203,6 +250,8 @@ impl HnswQueryOrchestrator {
             dispatcher,
             hnsw_index_provider,
             blockfile_provider,
+            distance_registry,
+            compute_pool,
             result_channel: None,
         }
     }
@@ -255,37 +304,36 @@ impl HnswQueryOrchestrator {
         >,
     ) {
         self.state = ExecutionState::QueryKnn;
-        let distance_function = &self
-            .index_config
+        let distance = self
+            .distance
             .as_ref()
-            .expect("Invariant violation. Index config is not set")
-            .distance_function;
-
-        // TODO: We shouldn't have to clone query vectors here. We should be able to pass a Arc<[f32]>-like to the input
-        for (i, query_vector) in self.query_vectors.iter().enumerate() {
-            let bf_input = BruteForceKnnOperatorInput {
-                log: logs.clone(),
-                query: query_vector.clone(),
-                k: self.k as usize,
-                distance_metric: distance_function.clone(),
-                allowed_ids: self.allowed_ids.clone(),
-                allowed_ids_brute_force: self.allowed_ids_brute_force.clone(),
-                record_segment_definition: self
-                    .record_segment
-                    .as_ref()
-                    .expect("Invariant violation. Record segment is not set")
-                    .clone(),
-                blockfile_provider: self.blockfile_provider.clone(),
-            };
-            let operator = Box::new(BruteForceKnnOperator {});
-            let task = wrap(operator, bf_input, self_address.clone());
-            self.brute_force_task_id_to_query_index.insert(task.id(), i);
-            match self.dispatcher.send(task, Some(Span::current())).await {
-                Ok(_) => (),
-                Err(e) => {
-                    // Log an error
-                    println!("Error sending Brute Force KNN task: {:?}", e);
-                }
+            .expect("Invariant violation. Distance is not set");
+
+        let bf_input = BruteForceKnnOperatorInput {
+            log: logs,
+            queries: self.queries.clone(),
+            distance_metric: distance.clone(),
+            allowed_ids: self.allowed_ids.clone(),
+            allowed_ids_brute_force: self.allowed_ids_brute_force.clone(),
+            allowed_offset_ids: self.allowed_offset_ids.clone(),
+            include: self.include,
+            deadline: self.deadline.clone(),
+            compute_pool: self.compute_pool.clone(),
+            sparse_embeddings: self.sparse_embeddings,
+            record_segment_definition: self
+                .record_segment
+                .as_ref()
+                .expect("Invariant violation. Record segment is not set")
+                .clone(),
+            blockfile_provider: self.blockfile_provider.clone(),
+        };
+        let operator = Box::new(BruteForceKnnOperator {});
+        let task = wrap(operator, bf_input, self_address);
+        match self.dispatcher.send(task, Some(Span::current())).await {
+            Ok(_) => (),
+            Err(e) => {
+                // Log an error
+                println!("Error sending Brute Force KNN task: {:?}", e);
             }
         }
     }
@@ -293,6 +341,14 @@ impl HnswQueryOrchestrator {
     async fn hnsw_segment_query(&mut self, logs: Chunk<LogRecord>, ctx: &ComponentContext<Self>) {
         self.state = ExecutionState::QueryKnn;
 
+        // Collections using a distance the HNSW index can not be built with, such as a
+        // custom one, never have their embeddings compacted into it and are served entirely
+        // by brute force.
+        if self.index_config.is_none() {
+            self.skip_hnsw_query();
+            return;
+        }
+
         let hnsw_segment = self
             .hnsw_segment
             .as_ref()
@@ -304,12 +360,19 @@ impl HnswQueryOrchestrator {
             .dimension
             .expect("Invariant violation. Collection dimension is not set");
 
+        let record_segment = self
+            .record_segment
+            .as_ref()
+            .expect("Invariant violation. Record Segment is not set");
+
         // Fetch the data needed for the duration of the query - The HNSW Segment, The record Segment and the Collection
         let hnsw_segment_reader = match DistributedHNSWSegmentReader::from_segment(
             // These unwraps are safe because we have already checked that the segments are set in the orchestrator on_start
             hnsw_segment,
             dimensionality as usize,
             self.hnsw_index_provider.clone(),
+            record_segment,
+            &self.blockfile_provider,
         )
         .await
         {
@@ -320,11 +383,7 @@ impl HnswQueryOrchestrator {
                         tracing::error!("[HnswQueryOperation]: Error creating distributed hnsw segment reader {:?}", *e);
                         // no task, decrement the merge dependency count and return
                         // with an empty result
-                        for (i, _) in self.query_vectors.iter().enumerate() {
-                            self.merge_dependency_count -= 1;
-                            self.hnsw_result_distances.insert(i, Vec::new());
-                            self.hnsw_result_offset_ids.insert(i, Vec::new());
-                        }
+                        self.skip_hnsw_query();
                         return;
                     }
                     _ => {
@@ -337,39 +396,84 @@ impl HnswQueryOrchestrator {
         };
         println!("Created HNSW Segment Reader: {:?}", hnsw_segment_reader);
 
-        let record_segment = self
-            .record_segment
-            .as_ref()
-            .expect("Invariant violation. Record Segment is not set");
+        // A single task searches the whole batch
+        let operator = Box::new(HnswKnnOperator {});
+        let input = HnswKnnOperatorInput {
+            segment: hnsw_segment_reader,
+            queries: self.queries.clone(),
+            ef_search: self.ef_search,
+            record_segment: record_segment.clone(),
+            blockfile_provider: self.blockfile_provider.clone(),
+            allowed_ids: self.allowed_ids.clone(),
+            allowed_ids_hnsw: self.allowed_ids_hnsw_segment.clone(),
+            allowed_offset_ids: self.allowed_offset_ids.clone(),
+            logs,
+            compute_pool: self.compute_pool.clone(),
+        };
+        let task = wrap(operator, input, ctx.sender.as_receiver());
+        match self.dispatcher.send(task, Some(Span::current())).await {
+            Ok(_) => (),
+            Err(e) => {
+                // Log an error
+                println!("Error sending HNSW KNN task: {:?}", e);
+            }
+        }
+    }
 
-        // Dispatch a query task per query vector
-        for (i, query_vector) in self.query_vectors.iter().enumerate() {
-            let operator = Box::new(HnswKnnOperator {});
-            let input = HnswKnnOperatorInput {
-                segment: hnsw_segment_reader.clone(),
-                query: query_vector.clone(),
-                k: self.k as usize,
-                record_segment: record_segment.clone(),
-                blockfile_provider: self.blockfile_provider.clone(),
-                allowed_ids: self.allowed_ids.clone(),
-                allowed_ids_hnsw: self.allowed_ids_hnsw_segment.clone(),
-                logs: logs.clone(),
-            };
-            let task = wrap(operator, input, ctx.sender.as_receiver());
-            self.hnsw_task_id_to_query_index.insert(task.id(), i);
-            match self.dispatcher.send(task, Some(Span::current())).await {
-                Ok(_) => (),
-                Err(e) => {
-                    // Log an error
-                    println!("Error sending HNSW KNN task: {:?}", e);
-                }
+    // Stands in for the HNSW task when there is no index to search, with an empty result
+    // for every query vector.
+    fn skip_hnsw_query(&mut self) {
+        self.merge_dependency_count -= 1;
+        for i in 0..self.queries.len() {
+            self.hnsw_result_distances.insert(i, Vec::new());
+            self.hnsw_result_offset_ids.insert(i, Vec::new());
+        }
+    }
+
+    fn has_filter(&self) -> bool {
+        self.where_clause.is_some() || self.where_document_clause.is_some()
+    }
+
+    async fn filter(&mut self, logs: Chunk<LogRecord>, ctx: &ComponentContext<Self>) {
+        self.state = ExecutionState::Filter;
+        let input = MetadataFilteringInput::new(
+            logs,
+            self.record_segment
+                .as_ref()
+                .expect("Invariant violation. Record segment is not set")
+                .clone(),
+            self.metadata_segment
+                .as_ref()
+                .expect("Invariant violation. Metadata segment is not set")
+                .clone(),
+            self.blockfile_provider.clone(),
+            self.where_clause.clone(),
+            self.where_document_clause.clone(),
+            None,
+        );
+        let task = wrap(
+            MetadataFilteringOperator::new(),
+            input,
+            ctx.sender.as_receiver(),
+        );
+        match self.dispatcher.send(task, Some(Span::current())).await {
+            Ok(_) => (),
+            Err(e) => {
+                // Log an error
+                println!("Error sending Metadata Filtering task: {:?}", e);
             }
         }
     }
 
+    async fn query_knn(&mut self, logs: Chunk<LogRecord>, ctx: &ComponentContext<Self>) {
+        self.brute_force_query(logs.clone(), ctx.sender.as_receiver())
+            .await;
+        self.hnsw_segment_query(logs, ctx).await;
+    }
+
     async fn merge_results(&mut self, ctx: &ComponentContext<Self>) {
         self.state = ExecutionState::MergeResults;
-        for i in 0..self.query_vectors.len() {
+        for i in 0..self.queries.len() {
             self.merge_results_for_index(ctx, i).await;
         }
     }
@@ -398,15 +502,14 @@ impl HnswQueryOrchestrator {
                 "Invariant violation. HNSW result distances are not set for query vector index",
             );
 
-        let brute_force_result_user_ids = self.brute_force_result_user_ids.remove(&query_vector_index).expect("Invariant violation. Brute force result user ids are not set for query vector index");
-        let brute_force_result_distances = self.brute_force_result_distances.remove(&query_vector_index).expect("Invariant violation. Brute force result distances are not set for query vector index");
-        let brute_force_result_embeddings = self
-            .brute_force_result_embeddings
-            .remove(&query_vector_index);
+        let brute_force_result = self
+            .brute_force_results
+            .remove(&query_vector_index)
+            .expect("Invariant violation. Brute force results are not set for query vector index");
 
         tracing::info!(
             "[HnswQueryOperation]: Brute force {} user ids, hnsw {} offset ids",
-            brute_force_result_user_ids.len(),
+            brute_force_result.user_ids.len(),
             hnsw_result_offset_ids.len()
         );
 
@@ -414,11 +517,16 @@ impl HnswQueryOrchestrator {
         let input = MergeKnnResultsOperatorInput::new(
             hnsw_result_offset_ids,
             hnsw_result_distances,
-            brute_force_result_user_ids,
-            brute_force_result_distances,
-            brute_force_result_embeddings,
-            self.include_embeddings,
-            self.k as usize,
+            brute_force_result,
+            MergeKnnOptions {
+                include: self.include,
+                k: self.queries[query_vector_index].k,
+                distance_function: self
+                    .index_config
+                    .as_ref()
+                    .map(|index_config| index_config.distance_function.clone()),
+                return_similarity: self.return_similarity,
+            },
             record_segment.clone(),
             self.blockfile_provider.clone(),
         );
@@ -441,7 +549,7 @@ impl HnswQueryOrchestrator {
             .take()
             .expect("Invariant violation. Result channel is not set.");
         let mut empty_resp = vec![];
-        for _ in 0..self.query_vectors.len() {
+        for _ in 0..self.queries.len() {
             empty_resp.push(vec![]);
         }
         match result_channel.send(Ok(empty_resp)) {
@@ -473,17 +581,34 @@ impl HnswQueryOrchestrator {
         ctx.cancellation_token.cancel();
     }
 
+    // Checked before every stage, so that no more work is dispatched for a query once its
+    // deadline is exceeded.
+    fn terminate_if_deadline_exceeded(&mut self, ctx: &ComponentContext<Self>) -> bool {
+        match self.deadline.check() {
+            Ok(()) => false,
+            Err(e) => {
+                self.terminate_with_error(Box::new(e), ctx);
+                true
+            }
+        }
+    }
+
     ///  Run the orchestrator and return the result.
     ///  # Note
     ///  Use this over spawning the component directly. This method will start the component and
-    ///  wait for it to finish before returning the result.
+    ///  wait for it to finish before returning the result. If the deadline is exceeded first,
+    ///  the component is stopped and a deadline exceeded error is returned right away.
     pub(crate) async fn run(mut self) -> Result<Vec<Vec<VectorQueryResult>>, Box<dyn ChromaError>> {
         let (tx, rx) = tokio::sync::oneshot::channel();
         self.result_channel = Some(tx);
+        let deadline = self.deadline.clone();
         let mut handle = self.system.clone().start_component(self);
-        let result = rx.await;
+        let result = tokio::select! {
+            result = rx => result.unwrap(),
+            _ = deadline.exceeded() => Err(Box::new(DeadlineExceededError) as Box<dyn ChromaError>),
+        };
         handle.stop();
-        result.unwrap()
+        result
     }
 }
 
@@ -521,62 +646,160 @@ impl Component for HnswQueryOrchestrator {
             }
         };
 
-        let collection = match get_collection_by_id(self.sysdb.clone(), collection_id).await {
-            Ok(collection) => collection,
+        // Every segment and the log position are read as of one version of the collection,
+        // so a compaction that flushes while the query runs is either seen in full or not
+        // at all.
+        let snapshot = match get_collection_snapshot(self.sysdb.clone(), collection_id).await {
+            Ok(snapshot) => snapshot,
             Err(e) => {
                 self.terminate_with_error(e, ctx);
                 return;
             }
         };
+        let collection = snapshot.collection.clone();
+        let hnsw_segment = match snapshot.segment_by_id(&self.hnsw_segment_id) {
+            Some(segment) => segment.clone(),
+            None => {
+                self.terminate_with_error(
+                    Box::new(GetHnswSegmentByIdError::HnswSegmentNotFound(
+                        self.hnsw_segment_id,
+                    )),
+                    ctx,
+                );
+                return;
+            }
+        };
+
+        self.sparse_embeddings = collection.has_sparse_embeddings();
 
         // If segment is uninitialized and dimension is not set then we assume
         // that this is a query before any add so return empty response.
-        if hnsw_segment.file_path.len() <= 0 && collection.dimension.is_none() {
+        if hnsw_segment.file_path.len() <= 0
+            && collection.dimension.is_none()
+            && !self.sparse_embeddings
+        {
             self.terminate_with_empty_response(ctx);
             return;
         }
 
-        // Validate that the collection has a dimension set. Downstream steps will rely on this
-        // so that they can unwrap the dimension without checking for None
-        if collection.dimension.is_none() {
-            self.terminate_with_error(
-                Box::new(HnswSegmentQueryError::CollectionHasNoDimension),
-                ctx,
-            );
-            return;
+        if !self.sparse_embeddings {
+            // Validate that the collection has a dimension set. Downstream steps will rely on
+            // this so that they can unwrap the dimension without checking for None
+            if collection.dimension.is_none() {
+                self.terminate_with_error(
+                    Box::new(HnswSegmentQueryError::CollectionHasNoDimension),
+                    ctx,
+                );
+                return;
+            };
+
+            // The index would read past the end of a shorter query vector.
+            let expected = collection.dimension.unwrap() as usize;
+            if let Some((index, query)) = self
+                .queries
+                .iter()
+                .enumerate()
+                .find(|(_, query)| query.embedding.len() != expected)
+            {
+                self.terminate_with_error(
+                    Box::new(HnswSegmentQueryError::QueryVectorDimensionMismatch {
+                        index,
+                        expected,
+                        got: query.embedding.len(),
+                    }),
+                    ctx,
+                );
+                return;
+            }
+        }
+
+        let record_segment = match snapshot.segment(SegmentType::BlockfileRecord) {
+            Ok(segment) => segment,
+            Err(e) => {
+                self.terminate_with_error(Box::new(e), ctx);
+                return;
+            }
         };
 
-        let record_segment =
-            match get_record_segment_by_collection_id(self.sysdb.clone(), collection_id).await {
-                Ok(segment) => segment,
+        // Binarized embeddings are scored by Hamming distance over their packed bits and
+        // sparse ones by Jaccard distance, otherwise the distance is looked up by the name the
+        // collection refers to it by.
+        let distance_function_name = IndexConfig::distance_function_name(&hnsw_segment);
+        let distance: Arc<dyn Distance> = if collection.has_binary_embeddings() {
+            Arc::new(DistanceFunction::Hamming)
+        } else if self.sparse_embeddings {
+            Arc::new(DistanceFunction::Jaccard)
+        } else {
+            match self.distance_registry.get(distance_function_name) {
+                Ok(distance) => distance,
                 Err(e) => {
-                    self.terminate_with_error(e, ctx);
+                    self.terminate_with_error(Box::new(e), ctx);
                     return;
                 }
-            };
-
-        match IndexConfig::from_segment(&hnsw_segment, collection.dimension.unwrap()) {
-            Ok(index_config) => {
-                self.index_config = Some(index_config);
-
-                // Normalize the query vectors if we are using the cosine similarity
-                if self.index_config.as_ref().unwrap().distance_function == DistanceFunction::Cosine
-                {
-                    for query_vector in self.query_vectors.iter_mut() {
-                        *query_vector = normalize(query_vector);
+            }
+        };
+        self.distance = Some(distance);
+
+        // Distances other than the built-in ones, and sparse embeddings, are only ever computed
+        // by brute force, so there is no index config for them.
+        if !self.sparse_embeddings && DistanceFunction::try_from(distance_function_name).is_ok() {
+            match IndexConfig::from_segment(&hnsw_segment, collection.dimension.unwrap()) {
+                Ok(index_config) => {
+                    self.index_config = Some(index_config);
+
+                    // Normalize the query vectors if we are using the cosine similarity
+                    if self.index_config.as_ref().unwrap().distance_function
+                        == DistanceFunction::Cosine
+                    {
+                        self.queries = self
+                            .queries
+                            .iter()
+                            .map(|query| KnnQuery {
+                                embedding: normalize(&query.embedding),
+                                k: query.k,
+                            })
+                            .collect();
                     }
                 }
+                Err(e) => {
+                    self.terminate_with_error(e, ctx);
+                    return;
+                }
             }
-            Err(e) => {
-                self.terminate_with_error(e, ctx);
+        }
+
+        // Only the distances that are 1 - a similarity can be turned back into one.
+        if self.return_similarity {
+            let name = self.distance.as_ref().unwrap().name().to_string();
+            if !matches!(
+                DistanceFunction::try_from(name.as_str()),
+                Ok(DistanceFunction::Cosine | DistanceFunction::InnerProduct)
+            ) {
+                self.terminate_with_error(
+                    Box::new(HnswSegmentQueryError::SimilarityNotSupported(name)),
+                    ctx,
+                );
                 return;
             }
         }
 
+        if self.has_filter() {
+            match snapshot.segment(SegmentType::BlockfileMetadata) {
+                Ok(segment) => self.metadata_segment = Some(segment),
+                Err(e) => {
+                    self.terminate_with_error(Box::new(e), ctx);
+                    return;
+                }
+            }
+        }
+
         self.record_segment = Some(record_segment);
         self.hnsw_segment = Some(hnsw_segment);
         self.collection = Some(collection);
 
+        if self.terminate_if_deadline_exceeded(ctx) {
+            return;
+        }
         self.pull_logs(ctx.sender.as_receiver()).await;
     }
 }
@@ -595,6 +818,9 @@ impl Handler<TaskResult<PullLogsOutput, PullLogsError>> for HnswQueryOrchestrato
 
         match message {
             Ok(pull_logs_output) => {
+                if self.terminate_if_deadline_exceeded(ctx) {
+                    return;
+                }
                 let logs = pull_logs_output.logs();
                 // Divide the allowed_ids into two mutually exclusive lists
                 // one for the brute force and another for the hnsw segment query.
@@ -615,9 +841,11 @@ impl Handler<TaskResult<PullLogsOutput, PullLogsError>> for HnswQueryOrchestrato
                 }
                 self.allowed_ids_brute_force = allowed_ids_brute_force.into();
                 self.allowed_ids_hnsw_segment = allowed_ids_hnsw.into();
-                self.brute_force_query(logs.clone(), ctx.sender.as_receiver())
-                    .await;
-                self.hnsw_segment_query(logs, ctx).await;
+                if self.has_filter() {
+                    self.filter(logs, ctx).await;
+                } else {
+                    self.query_knn(logs, ctx).await;
+                }
             }
             Err(e) => {
                 self.terminate_with_error(Box::new(e), ctx);
@@ -627,45 +855,57 @@ impl Handler<TaskResult<PullLogsOutput, PullLogsError>> for HnswQueryOrchestrato
 }
 
 #[async_trait]
-impl Handler<TaskResult<BruteForceKnnOperatorOutput, BruteForceKnnOperatorError>>
+impl Handler<TaskResult<MetadataFilteringOutput, MetadataFilteringError>>
     for HnswQueryOrchestrator
 {
     async fn handle(
         &mut self,
-        message: TaskResult<BruteForceKnnOperatorOutput, BruteForceKnnOperatorError>,
+        message: TaskResult<MetadataFilteringOutput, MetadataFilteringError>,
         ctx: &crate::system::ComponentContext<HnswQueryOrchestrator>,
     ) {
-        let task_id = message.id();
         let message = message.into_inner();
-        let query_index = self
-            .brute_force_task_id_to_query_index
-            .remove(&task_id)
-            .expect("Invariant violation. Brute force task id is not set for query vector index");
-
         match message {
             Ok(output) => {
-                let mut user_ids = output.user_ids;
-                let mut embeddings = None;
-                if self.include_embeddings {
-                    embeddings = Some(output.embeddings);
+                if self.terminate_if_deadline_exceeded(ctx) {
+                    return;
                 }
-                self.brute_force_result_user_ids
-                    .insert(query_index, user_ids);
-                self.brute_force_result_distances
-                    .insert(query_index, output.distances);
-                if let Some(embeddings) = embeddings {
-                    self.brute_force_result_embeddings
-                        .insert(query_index, embeddings);
+                self.allowed_offset_ids = output
+                    .where_condition_filtered_offset_ids
+                    .map(|offset_ids| offset_ids.into());
+                self.query_knn(output.log_records, ctx).await;
+            }
+            Err(e) => {
+                self.terminate_with_error(Box::new(e), ctx);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<TaskResult<BruteForceKnnOperatorOutput, BruteForceKnnOperatorError>>
+    for HnswQueryOrchestrator
+{
+    async fn handle(
+        &mut self,
+        message: TaskResult<BruteForceKnnOperatorOutput, BruteForceKnnOperatorError>,
+        ctx: &crate::system::ComponentContext<HnswQueryOrchestrator>,
+    ) {
+        match message.into_inner() {
+            Ok(output) => {
+                for (query_index, result) in output.results.into_iter().enumerate() {
+                    self.brute_force_results.insert(query_index, result);
                 }
             }
             Err(e) => {
-                // TODO: handle this error, technically never happens
+                // A brute force failure fails the whole query, there is no partial result to merge.
+                self.terminate_with_error(Box::new(e), ctx);
+                return;
             }
         }
 
         self.merge_dependency_count -= 1;
 
-        if self.merge_dependency_count == 0 {
+        if self.merge_dependency_count == 0 && !self.terminate_if_deadline_exceeded(ctx) {
             self.merge_results(ctx).await;
         }
     }
@@ -678,18 +918,14 @@ impl Handler<TaskResult<HnswKnnOperatorOutput, Box<dyn ChromaError>>> for HnswQu
         message: TaskResult<HnswKnnOperatorOutput, Box<dyn ChromaError>>,
         ctx: &ComponentContext<Self>,
     ) {
-        let task_id = message.id();
-        let message = message.into_inner();
-        let query_index = self
-            .hnsw_task_id_to_query_index
-            .remove(&task_id)
-            .expect("Invariant violation. HNSW task id is not set for query vector index");
-        match message {
+        match message.into_inner() {
             Ok(output) => {
-                self.hnsw_result_offset_ids
-                    .insert(query_index, output.offset_ids);
-                self.hnsw_result_distances
-                    .insert(query_index, output.distances);
+                for (query_index, result) in output.results.into_iter().enumerate() {
+                    self.hnsw_result_offset_ids
+                        .insert(query_index, result.offset_ids);
+                    self.hnsw_result_distances
+                        .insert(query_index, result.distances);
+                }
             }
             Err(e) => {
                 self.terminate_with_error(e, ctx);
@@ -698,7 +934,7 @@ impl Handler<TaskResult<HnswKnnOperatorOutput, Box<dyn ChromaError>>> for HnswQu
 
         self.merge_dependency_count -= 1;
 
-        if self.merge_dependency_count == 0 {
+        if self.merge_dependency_count == 0 && !self.terminate_if_deadline_exceeded(ctx) {
             self.merge_results(ctx).await;
         }
     }
@@ -722,40 +958,13 @@ impl Handler<TaskResult<MergeKnnResultsOperatorOutput, Box<dyn ChromaError>>>
 
         self.state = ExecutionState::Finished;
 
-        let (mut output_ids, mut output_distances, output_vectors) = match message {
-            Ok(output) => (output.user_ids, output.distances, output.vectors),
+        let query_results = match message {
+            Ok(output) => output.results,
             Err(e) => {
                 self.terminate_with_error(e, ctx);
                 return;
             }
         };
-
-        let mut query_results = Vec::new();
-        if self.include_embeddings {
-            for ((index, distance), vector) in
-                output_ids.drain(..).zip(output_distances.drain(..)).zip(
-                    output_vectors
-                        .expect("Embeddings are expected if include_embeddings is set")
-                        .drain(..),
-                )
-            {
-                let query_result = VectorQueryResult {
-                    id: index,
-                    distance: distance,
-                    vector: Some(vector),
-                };
-                query_results.push(query_result);
-            }
-        } else {
-            for (index, distance) in output_ids.drain(..).zip(output_distances.drain(..)) {
-                let query_result = VectorQueryResult {
-                    id: index,
-                    distance: distance,
-                    vector: None,
-                };
-                query_results.push(query_result);
-            }
-        }
         trace!("Merged results: {:?}", query_results);
 
         let results_slice = self
@@ -781,7 +990,7 @@ impl Handler<TaskResult<MergeKnnResultsOperatorOutput, Box<dyn ChromaError>>>
                 self.results
                     .as_mut()
                     .expect("Invariant violation. Results are not set")
-                    .set_len(self.query_vectors.len());
+                    .set_len(self.queries.len());
             }
 
             match result_channel.send(Ok(self
@@ -1,3 +1,4 @@
+use super::common::get_collection_snapshot;
 use crate::errors::{ChromaError, ErrorCodes};
 use crate::execution::data::data_chunk::Chunk;
 use crate::execution::operator::{wrap, TaskResult};
@@ -6,7 +7,7 @@ use crate::execution::operators::count_records::{
 };
 use crate::execution::operators::merge_metadata_results::{
     MergeMetadataResultsOperator, MergeMetadataResultsOperatorError,
-    MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOutput,
+    MergeMetadataResultsOperatorInput, MergeMetadataResultsOperatorOutput, Page,
 };
 use crate::execution::operators::metadata_filtering::{
     MetadataFilteringError, MetadataFilteringInput, MetadataFilteringOperator,
@@ -16,7 +17,7 @@ use crate::execution::operators::pull_log::{PullLogsInput, PullLogsOperator, Pul
 use crate::index::metadata::types::MetadataIndexError;
 use crate::log::log::PullLogsError;
 use crate::segment::metadata_segment::MetadataSegmentReader;
-use crate::sysdb::sysdb::{GetCollectionsError, GetSegmentsError};
+use crate::sysdb::sysdb::GetSegmentsError;
 use crate::system::{Component, ComponentContext, Handler};
 use crate::types::{Collection, LogRecord, Metadata, SegmentType};
 use crate::types::{Where, WhereDocument};
@@ -47,6 +48,22 @@ enum ExecutionState {
 type MetadataQueryOrchestratorResult =
     Result<(Vec<String>, Vec<Option<Metadata>>, Vec<Option<String>>), Box<dyn ChromaError>>;
 
+/// The records a metadata query asks for.
+/// # Fields
+/// - query_ids: The user ids of the records, any record when None.
+/// - where_clause: The filter on the metadata of the records.
+/// - where_document_clause: The filter on the documents of the records.
+/// - offset: The number of the records that match to skip, in offset id order.
+/// - limit: The most records to return, all of them when None.
+#[derive(Debug)]
+pub(crate) struct MetadataQuery {
+    pub(crate) query_ids: Option<Vec<String>>,
+    pub(crate) where_clause: Option<Where>,
+    pub(crate) where_document_clause: Option<WhereDocument>,
+    pub(crate) offset: usize,
+    pub(crate) limit: Option<usize>,
+}
+
 #[derive(Debug)]
 pub(crate) struct MetadataQueryOrchestrator {
     state: ExecutionState,
@@ -69,6 +86,7 @@ pub(crate) struct MetadataQueryOrchestrator {
     // Query params
     where_clause: Option<Where>,
     where_document_clause: Option<WhereDocument>,
+    page: Page,
     // Result channel
     result_channel: Option<tokio::sync::oneshot::Sender<MetadataQueryOrchestratorResult>>,
 }
@@ -97,16 +115,10 @@ enum MetadataSegmentQueryError {
     BlockfileMetadataSegmentNotFound(Uuid),
     #[error("Get segments error")]
     GetSegmentsError(#[from] GetSegmentsError),
-    #[error("Record segment not found for collection: {0}")]
-    RecordSegmentNotFound(Uuid),
     #[error("Metadata segment has no collection")]
     MetadataSegmentHasNoCollection,
     #[error("System Time Error")]
     SystemTimeError(#[from] std::time::SystemTimeError),
-    #[error("Collection not found for id: {0}")]
-    CollectionNotFound(Uuid),
-    #[error("Get collection error")]
-    GetCollectionError(#[from] GetCollectionsError),
 }
 
 impl ChromaError for MetadataSegmentQueryError {
@@ -114,13 +126,10 @@ impl ChromaError for MetadataSegmentQueryError {
         match self {
             MetadataSegmentQueryError::BlockfileMetadataSegmentNotFound(_) => ErrorCodes::NotFound,
             MetadataSegmentQueryError::GetSegmentsError(e) => e.code(),
-            MetadataSegmentQueryError::RecordSegmentNotFound(_) => ErrorCodes::NotFound,
             MetadataSegmentQueryError::MetadataSegmentHasNoCollection => {
                 ErrorCodes::InvalidArgument
             }
             MetadataSegmentQueryError::SystemTimeError(_) => ErrorCodes::Internal,
-            MetadataSegmentQueryError::CollectionNotFound(_) => ErrorCodes::NotFound,
-            MetadataSegmentQueryError::GetCollectionError(e) => e.code(),
         }
     }
 }
@@ -175,30 +184,25 @@ impl CountQueryOrchestrator {
             }
         };
 
-        let record_segment = self
-            .get_record_segment_from_collection_id(self.sysdb.clone(), &collection_id)
-            .await;
-
-        let record_segment = match record_segment {
-            Ok(segment) => segment,
+        // The record segment and the log position are read as of one version.
+        let snapshot = match get_collection_snapshot(self.sysdb.clone(), &collection_id).await {
+            Ok(snapshot) => snapshot,
             Err(e) => {
-                tracing::error!("Error getting record segment: {:?}", e);
+                tracing::error!("Error getting collection: {:?}", e);
                 self.terminate_with_error(e, ctx);
                 return;
             }
         };
 
-        let collection = match self
-            .get_collection_from_id(self.sysdb.clone(), &collection_id, ctx)
-            .await
-        {
-            Ok(collection) => collection,
+        let record_segment = match snapshot.segment(SegmentType::BlockfileRecord) {
+            Ok(segment) => segment,
             Err(e) => {
-                tracing::error!("Error getting collection: {:?}", e);
-                self.terminate_with_error(e, ctx);
+                tracing::error!("Error getting record segment: {:?}", e);
+                self.terminate_with_error(Box::new(e), ctx);
                 return;
             }
         };
+        let collection = snapshot.collection.clone();
 
         self.record_segment = Some(record_segment);
         self.collection = Some(collection);
@@ -277,64 +281,6 @@ impl CountQueryOrchestrator {
         Ok(segment)
     }
 
-    async fn get_record_segment_from_collection_id(
-        &self,
-        mut sysdb: Box<SysDb>,
-        collection_id: &Uuid,
-    ) -> Result<Segment, Box<dyn ChromaError>> {
-        let segments = sysdb
-            .get_segments(
-                None,
-                Some(SegmentType::BlockfileRecord.into()),
-                None,
-                Some(*collection_id),
-            )
-            .await;
-
-        match segments {
-            Ok(segments) => {
-                if segments.is_empty() {
-                    return Err(Box::new(MetadataSegmentQueryError::RecordSegmentNotFound(
-                        *collection_id,
-                    )));
-                }
-                // Unwrap is safe as we know at least one segment exists from
-                // the check above
-                return Ok(segments.into_iter().next().unwrap());
-            }
-            Err(e) => {
-                return Err(Box::new(MetadataSegmentQueryError::GetSegmentsError(e)));
-            }
-        };
-    }
-
-    async fn get_collection_from_id(
-        &self,
-        mut sysdb: Box<SysDb>,
-        collection_id: &Uuid,
-        ctx: &ComponentContext<Self>,
-    ) -> Result<Collection, Box<dyn ChromaError>> {
-        let collections = sysdb
-            .get_collections(Some(*collection_id), None, None, None)
-            .await;
-
-        match collections {
-            Ok(collections) => {
-                if collections.is_empty() {
-                    return Err(Box::new(MetadataSegmentQueryError::CollectionNotFound(
-                        *collection_id,
-                    )));
-                }
-                // Unwrap is safe as we know at least one collection exists from
-                // the check above
-                return Ok(collections.into_iter().next().unwrap());
-            }
-            Err(e) => {
-                return Err(Box::new(MetadataSegmentQueryError::GetCollectionError(e)));
-            }
-        };
-    }
-
     fn terminate_with_error(&mut self, error: Box<dyn ChromaError>, ctx: &ComponentContext<Self>) {
         let result_channel = self
             .result_channel
@@ -449,14 +395,19 @@ impl MetadataQueryOrchestrator {
     pub(crate) fn new(
         system: System,
         metadata_segment_id: &Uuid,
-        query_ids: Option<Vec<String>>,
         log: Box<Log>,
         sysdb: Box<SysDb>,
         dispatcher: Box<dyn Receiver<TaskMessage>>,
         blockfile_provider: BlockfileProvider,
-        where_clause: Option<Where>,
-        where_document_clause: Option<WhereDocument>,
+        query: MetadataQuery,
     ) -> Self {
+        let MetadataQuery {
+            query_ids,
+            where_clause,
+            where_document_clause,
+            offset,
+            limit,
+        } = query;
         Self {
             state: ExecutionState::Pending,
             system,
@@ -472,6 +423,7 @@ impl MetadataQueryOrchestrator {
             blockfile_provider,
             where_clause,
             where_document_clause,
+            page: Page { offset, limit },
             result_channel: None,
         }
     }
@@ -501,30 +453,37 @@ impl MetadataQueryOrchestrator {
                 return;
             }
         };
-        self.metadata_segment = Some(metadata_segment);
-
-        let record_segment = self
-            .get_record_segment_from_collection_id(self.sysdb.clone(), &collection_id)
-            .await;
 
-        let record_segment = match record_segment {
-            Ok(segment) => segment,
+        // Both segments and the log position are read as of one version of the collection.
+        let snapshot = match get_collection_snapshot(self.sysdb.clone(), &collection_id).await {
+            Ok(snapshot) => snapshot,
             Err(e) => {
                 self.terminate_with_error(e, ctx);
                 return;
             }
         };
+        let metadata_segment = match snapshot.segment_by_id(&metadata_segment.id) {
+            Some(segment) => segment.clone(),
+            None => {
+                self.terminate_with_error(
+                    Box::new(MetadataSegmentQueryError::BlockfileMetadataSegmentNotFound(
+                        metadata_segment.id,
+                    )),
+                    ctx,
+                );
+                return;
+            }
+        };
+        self.metadata_segment = Some(metadata_segment);
 
-        let collection = match self
-            .get_collection_from_id(self.sysdb.clone(), &collection_id, ctx)
-            .await
-        {
-            Ok(collection) => collection,
+        let record_segment = match snapshot.segment(SegmentType::BlockfileRecord) {
+            Ok(segment) => segment,
             Err(e) => {
-                self.terminate_with_error(e, ctx);
+                self.terminate_with_error(Box::new(e), ctx);
                 return;
             }
         };
+        let collection = snapshot.collection.clone();
 
         self.record_segment = Some(record_segment);
         self.collection = Some(collection);
@@ -635,64 +594,6 @@ impl MetadataQueryOrchestrator {
         Ok(segment)
     }
 
-    async fn get_record_segment_from_collection_id(
-        &self,
-        mut sysdb: Box<SysDb>,
-        collection_id: &Uuid,
-    ) -> Result<Segment, Box<dyn ChromaError>> {
-        let segments = sysdb
-            .get_segments(
-                None,
-                Some(SegmentType::BlockfileRecord.into()),
-                None,
-                Some(*collection_id),
-            )
-            .await;
-
-        match segments {
-            Ok(segments) => {
-                if segments.is_empty() {
-                    return Err(Box::new(MetadataSegmentQueryError::RecordSegmentNotFound(
-                        *collection_id,
-                    )));
-                }
-                // Unwrap is safe as we know at least one segment exists from
-                // the check above
-                return Ok(segments.into_iter().next().unwrap());
-            }
-            Err(e) => {
-                return Err(Box::new(MetadataSegmentQueryError::GetSegmentsError(e)));
-            }
-        };
-    }
-
-    async fn get_collection_from_id(
-        &self,
-        mut sysdb: Box<SysDb>,
-        collection_id: &Uuid,
-        ctx: &ComponentContext<Self>,
-    ) -> Result<Collection, Box<dyn ChromaError>> {
-        let collections = sysdb
-            .get_collections(Some(*collection_id), None, None, None)
-            .await;
-
-        match collections {
-            Ok(collections) => {
-                if collections.is_empty() {
-                    return Err(Box::new(MetadataSegmentQueryError::CollectionNotFound(
-                        *collection_id,
-                    )));
-                }
-                // Unwrap is safe as we know at least one collection exists from
-                // the check above
-                return Ok(collections.into_iter().next().unwrap());
-            }
-            Err(e) => {
-                return Err(Box::new(MetadataSegmentQueryError::GetCollectionError(e)));
-            }
-        };
-    }
-
     fn terminate_with_error(&mut self, error: Box<dyn ChromaError>, ctx: &ComponentContext<Self>) {
         let result_channel = self
             .result_channel
@@ -790,6 +691,7 @@ impl Handler<TaskResult<MetadataFilteringOutput, MetadataFilteringError>>
                 .expect("Invariant violation. Record segment is not set.")
                 .clone(),
             self.blockfile_provider.clone(),
+            self.page,
         );
 
         let task = wrap(operator, input, ctx.sender.as_receiver());
@@ -0,0 +1,272 @@
+use crate::health_proto::health_check_response::ServingStatus;
+use crate::health_proto::{self, HealthCheckRequest, HealthCheckResponse};
+use crate::memberlist::Memberlist;
+use crate::system::{ChannelError, Receiver};
+use async_trait::async_trait;
+use futures::Stream;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tonic::{Request, Response, Status};
+
+/// The service probes check readiness with. Liveness is checked with the empty service name,
+/// which stands for the whole server.
+pub(crate) const READINESS_SERVICE: &str = "readiness";
+
+// The conditions of readiness.
+pub(crate) const ACCEPTING_REQUESTS: &str = "accepting_requests";
+pub(crate) const MEMBERLIST: &str = "memberlist";
+pub(crate) const STORAGE: &str = "storage";
+pub(crate) const SYSDB: &str = "sysdb";
+
+/// Whether the worker is ready to serve traffic.
+/// # Description
+/// The worker is ready once every condition that was added to the reporter is met. The
+/// components the worker depends on set their condition as their state changes, so that
+/// probes never reach out to the dependencies themselves.
+#[derive(Clone, Debug)]
+pub(crate) struct HealthReporter {
+    conditions: Arc<watch::Sender<BTreeMap<&'static str, bool>>>,
+}
+
+impl HealthReporter {
+    pub(crate) fn new() -> Self {
+        let (conditions, _) = watch::channel(BTreeMap::new());
+        HealthReporter {
+            conditions: Arc::new(conditions),
+        }
+    }
+
+    /// Sets whether a condition is met, adding it if it is new.
+    pub(crate) fn set(&self, condition: &'static str, met: bool) {
+        self.conditions
+            .send_if_modified(|conditions| conditions.insert(condition, met) != Some(met));
+    }
+
+    pub(crate) fn is_ready(&self) -> bool {
+        self.conditions.borrow().values().all(|met| *met)
+    }
+
+    /// Sets a condition from a check that is repeated every `interval`, for as long as the
+    /// worker runs.
+    pub(crate) fn spawn_check<F, Fut>(
+        &self,
+        condition: &'static str,
+        interval: Duration,
+        mut check: F,
+    ) where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = bool> + Send,
+    {
+        self.set(condition, false);
+        let reporter = self.clone();
+        tokio::spawn(async move {
+            loop {
+                reporter.set(condition, check().await);
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+
+    fn status(ready: bool, service: &str) -> ServingStatus {
+        match service {
+            "" => ServingStatus::Serving,
+            READINESS_SERVICE if ready => ServingStatus::Serving,
+            READINESS_SERVICE => ServingStatus::NotServing,
+            _ => ServingStatus::ServiceUnknown,
+        }
+    }
+}
+
+/// Meets the memberlist condition once the first memberlist is received.
+#[derive(Clone, Debug)]
+pub(crate) struct MemberlistReadiness {
+    reporter: HealthReporter,
+}
+
+impl MemberlistReadiness {
+    pub(crate) fn new(reporter: HealthReporter) -> Self {
+        reporter.set(MEMBERLIST, false);
+        MemberlistReadiness { reporter }
+    }
+}
+
+#[async_trait]
+impl Receiver<Memberlist> for MemberlistReadiness {
+    async fn send(
+        &self,
+        _message: Memberlist,
+        _tracing_context: Option<tracing::Span>,
+    ) -> Result<(), ChannelError> {
+        self.reporter.set(MEMBERLIST, true);
+        Ok(())
+    }
+}
+
+/// The grpc.health.v1.Health service, answered from a [`HealthReporter`].
+#[derive(Clone, Debug)]
+pub(crate) struct HealthService {
+    reporter: HealthReporter,
+}
+
+impl HealthService {
+    pub(crate) fn new(reporter: HealthReporter) -> Self {
+        HealthService { reporter }
+    }
+}
+
+#[tonic::async_trait]
+impl health_proto::health_server::Health for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = request.into_inner().service;
+        match HealthReporter::status(self.reporter.is_ready(), &service) {
+            ServingStatus::ServiceUnknown => {
+                Err(Status::not_found(format!("Unknown service: {}", service)))
+            }
+            status => Ok(Response::new(HealthCheckResponse {
+                status: status as i32,
+            })),
+        }
+    }
+
+    type WatchStream =
+        Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, Status>> + Send + 'static>>;
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service = request.into_inner().service;
+        let conditions = self.reporter.conditions.subscribe();
+        // Sends the current status, and then every change of it.
+        let stream = futures::stream::unfold(
+            (conditions, service, None),
+            |(mut conditions, service, last_status)| async move {
+                loop {
+                    let ready = conditions.borrow_and_update().values().all(|met| *met);
+                    let status = HealthReporter::status(ready, &service);
+                    if last_status != Some(status) {
+                        let response = HealthCheckResponse {
+                            status: status as i32,
+                        };
+                        return Some((Ok(response), (conditions, service, Some(status))));
+                    }
+                    if conditions.changed().await.is_err() {
+                        return None;
+                    }
+                }
+            },
+        );
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_proto::health_server::Health;
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    async fn check(service: &HealthService, name: &str) -> Result<ServingStatus, tonic::Code> {
+        match service
+            .check(Request::new(HealthCheckRequest {
+                service: name.to_string(),
+            }))
+            .await
+        {
+            Ok(response) => Ok(response.into_inner().status()),
+            Err(status) => Err(status.code()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_readiness_follows_the_conditions() {
+        let reporter = HealthReporter::new();
+        let service = HealthService::new(reporter.clone());
+        let memberlist = MemberlistReadiness::new(reporter.clone());
+        reporter.set(SYSDB, false);
+        assert_eq!(check(&service, "").await, Ok(ServingStatus::Serving));
+        assert_eq!(
+            check(&service, READINESS_SERVICE).await,
+            Ok(ServingStatus::NotServing)
+        );
+
+        reporter.set(SYSDB, true);
+        assert_eq!(
+            check(&service, READINESS_SERVICE).await,
+            Ok(ServingStatus::NotServing)
+        );
+        memberlist.send(vec![], None).await.unwrap();
+        assert_eq!(
+            check(&service, READINESS_SERVICE).await,
+            Ok(ServingStatus::Serving)
+        );
+
+        // Liveness does not depend on the conditions.
+        reporter.set(SYSDB, false);
+        assert_eq!(
+            check(&service, READINESS_SERVICE).await,
+            Ok(ServingStatus::NotServing)
+        );
+        assert_eq!(check(&service, "").await, Ok(ServingStatus::Serving));
+
+        assert_eq!(
+            check(&service, "chroma.VectorReader").await,
+            Err(tonic::Code::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_checks_set_their_condition() {
+        let reporter = HealthReporter::new();
+        let reachable = Arc::new(AtomicBool::new(false));
+        let check_reachable = reachable.clone();
+        reporter.spawn_check(SYSDB, Duration::from_millis(10), move || {
+            let reachable = check_reachable.load(Ordering::SeqCst);
+            async move { reachable }
+        });
+        assert!(!reporter.is_ready());
+
+        reachable.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(reporter.is_ready());
+
+        reachable.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!reporter.is_ready());
+    }
+
+    async fn next(stream: &mut <HealthService as Health>::WatchStream) -> ServingStatus {
+        stream.next().await.unwrap().unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_watch_sends_every_change() {
+        let reporter = HealthReporter::new();
+        reporter.set(STORAGE, false);
+        let service = HealthService::new(reporter.clone());
+        let mut stream = service
+            .watch(Request::new(HealthCheckRequest {
+                service: READINESS_SERVICE.to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(next(&mut stream).await, ServingStatus::NotServing);
+
+        // Setting a condition to what it already is, or adding a met one, changes nothing.
+        reporter.set(STORAGE, false);
+        reporter.set(SYSDB, true);
+        reporter.set(STORAGE, true);
+        assert_eq!(next(&mut stream).await, ServingStatus::Serving);
+        reporter.set(ACCEPTING_REQUESTS, false);
+        assert_eq!(next(&mut stream).await, ServingStatus::NotServing);
+    }
+}
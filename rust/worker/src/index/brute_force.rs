@@ -0,0 +1,220 @@
+use super::{Index, IndexConfig};
+use crate::distance::DistanceFunction;
+use crate::errors::ChromaError;
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BruteForceIndexConfig {
+    // The number of vectors to reserve room for up front.
+    pub(crate) capacity: usize,
+}
+
+#[derive(Default)]
+struct BruteForceIndexData {
+    ids: Vec<usize>,
+    // The vector of ids[i] is embeddings[i * dimensionality..(i + 1) * dimensionality].
+    embeddings: Vec<f32>,
+    positions: HashMap<usize, usize>,
+}
+
+/// The BruteForceIndex struct.
+/// # Description
+/// An index that keeps its vectors in one contiguous buffer and answers queries by scoring
+/// every one of them. For small collections this is cheaper than building and loading an
+/// HNSW graph, and the results are exact.
+/// # Notes
+/// Scores are on the same scale as the HNSW index's, so results from both can be merged.
+/// For cosine, vectors are expected to be normalized before they are added, as they are for
+/// the HNSW index.
+pub(crate) struct BruteForceIndex {
+    dimensionality: usize,
+    distance_function: DistanceFunction,
+    data: RwLock<BruteForceIndexData>,
+    pub(crate) id: Uuid,
+}
+
+impl BruteForceIndex {
+    pub(crate) fn len(&self) -> usize {
+        self.data.read().ids.len()
+    }
+}
+
+impl Index<BruteForceIndexConfig> for BruteForceIndex {
+    fn init(
+        index_config: &IndexConfig,
+        custom_config: Option<&BruteForceIndexConfig>,
+        id: Uuid,
+    ) -> Result<Self, Box<dyn ChromaError>> {
+        let dimensionality = index_config.dimensionality as usize;
+        let capacity = custom_config.map_or(0, |config| config.capacity);
+        Ok(BruteForceIndex {
+            dimensionality,
+            distance_function: index_config.distance_function.clone(),
+            data: RwLock::new(BruteForceIndexData {
+                ids: Vec::with_capacity(capacity),
+                embeddings: Vec::with_capacity(capacity * dimensionality),
+                positions: HashMap::with_capacity(capacity),
+            }),
+            id,
+        })
+    }
+
+    // Like the HNSW index, adding an id that is already present replaces its vector.
+    fn add(&self, id: usize, vector: &[f32]) {
+        let d = self.dimensionality;
+        let mut data = self.data.write();
+        match data.positions.get(&id) {
+            Some(&position) => {
+                data.embeddings[position * d..(position + 1) * d].copy_from_slice(&vector[..d])
+            }
+            None => {
+                let position = data.ids.len();
+                data.ids.push(id);
+                data.embeddings.extend_from_slice(&vector[..d]);
+                data.positions.insert(id, position);
+            }
+        }
+    }
+
+    fn delete(&self, id: usize) {
+        let d = self.dimensionality;
+        let mut data = self.data.write();
+        let position = match data.positions.remove(&id) {
+            Some(position) => position,
+            // Deleting an id that was never added, or that is already deleted, is a no-op.
+            None => return,
+        };
+        // Move the last vector into the freed slot to keep the buffer contiguous.
+        let last = data.ids.len() - 1;
+        data.ids.swap_remove(position);
+        if position != last {
+            data.embeddings
+                .copy_within(last * d..(last + 1) * d, position * d);
+            let moved_id = data.ids[position];
+            data.positions.insert(moved_id, position);
+        }
+        data.embeddings.truncate(last * d);
+    }
+
+    fn query(
+        &self,
+        vector: &[f32],
+        k: usize,
+        allowed_ids: &[usize],
+        disallowed_ids: &[usize],
+    ) -> (Vec<usize>, Vec<f32>) {
+        let d = self.dimensionality;
+        let allowed: HashSet<usize> = allowed_ids.iter().copied().collect();
+        let disallowed: HashSet<usize> = disallowed_ids.iter().copied().collect();
+        let data = self.data.read();
+        // An empty allowed list allows every id, as it does for the HNSW index.
+        let candidates = data
+            .embeddings
+            .chunks_exact(d)
+            .enumerate()
+            .filter(|(position, _)| {
+                let id = data.ids[*position];
+                (allowed.is_empty() || allowed.contains(&id)) && !disallowed.contains(&id)
+            })
+            .map(|(position, embedding)| (position as u32, embedding));
+        self.distance_function
+            .top_k(vector, candidates, k)
+            .into_iter()
+            .map(|(position, distance)| (data.ids[position as usize], distance))
+            .unzip()
+    }
+
+    fn get(&self, id: usize) -> Option<Vec<f32>> {
+        let d = self.dimensionality;
+        let data = self.data.read();
+        data.positions
+            .get(&id)
+            .map(|&position| data.embeddings[position * d..(position + 1) * d].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::normalize;
+    use crate::index::utils;
+
+    fn new_index(distance_function: DistanceFunction, d: usize) -> BruteForceIndex {
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function,
+        };
+        BruteForceIndex::init(&index_config, None, Uuid::new_v4()).unwrap()
+    }
+
+    #[test]
+    fn it_returns_exact_results_in_order() {
+        let (n, d, k) = (200, 16, 10);
+        for distance_function in [
+            DistanceFunction::Euclidean,
+            DistanceFunction::Cosine,
+            DistanceFunction::InnerProduct,
+        ] {
+            let index = new_index(distance_function.clone(), d);
+            let mut data: Vec<f32> = utils::generate_random_data(n, d);
+            if distance_function == DistanceFunction::Cosine {
+                for embedding in data.chunks_exact_mut(d) {
+                    normalize(embedding);
+                }
+            }
+            for i in 0..n {
+                index.add(i, &data[i * d..(i + 1) * d]);
+            }
+            let query = &data[..d];
+            let mut expected: Vec<(usize, f32)> = (0..n)
+                .map(|i| {
+                    (
+                        i,
+                        distance_function.distance(query, &data[i * d..(i + 1) * d]),
+                    )
+                })
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+
+            let (ids, distances) = index.query(query, k, &[], &[]);
+            let expected_ids: Vec<usize> = expected.iter().take(k).map(|(id, _)| *id).collect();
+            assert_eq!(ids, expected_ids);
+            assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+        }
+    }
+
+    #[test]
+    fn it_updates_and_deletes() {
+        let index = new_index(DistanceFunction::Euclidean, 2);
+        for i in 0..4 {
+            index.add(i, &[i as f32, 0.0]);
+        }
+        index.add(0, &[10.0, 0.0]);
+        index.delete(1);
+        // Never added.
+        index.delete(7);
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.get(0), Some(vec![10.0, 0.0]));
+        assert_eq!(index.get(1), None);
+        // The vector moved into the deleted slot is still found under its own id.
+        assert_eq!(index.get(3), Some(vec![3.0, 0.0]));
+
+        let (ids, distances) = index.query(&[0.0, 0.0], 4, &[], &[]);
+        assert_eq!(ids, vec![2, 3, 0]);
+        assert_eq!(distances, vec![4.0, 9.0, 100.0]);
+    }
+
+    #[test]
+    fn it_filters_by_allowed_and_disallowed_ids() {
+        let index = new_index(DistanceFunction::Euclidean, 1);
+        for i in 0..10 {
+            index.add(i, &[i as f32]);
+        }
+        let (ids, _) = index.query(&[0.0], 3, &[2, 4, 6, 8], &[4]);
+        assert_eq!(ids, vec![2, 6, 8]);
+        let (ids, _) = index.query(&[0.0], 3, &[], &[0, 1]);
+        assert_eq!(ids, vec![2, 3, 4]);
+    }
+}
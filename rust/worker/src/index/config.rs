@@ -0,0 +1,59 @@
+use crate::config::{ConfigValidator, ValidateConfig};
+use serde::Deserialize;
+
+const DEFAULT_CAPACITY_GROWTH_FACTOR: f64 = 2.0;
+const DEFAULT_BRUTE_FORCE_THRESHOLD: usize = 1000;
+
+fn default_capacity_growth_factor() -> f64 {
+    DEFAULT_CAPACITY_GROWTH_FACTOR
+}
+
+fn default_brute_force_threshold() -> usize {
+    DEFAULT_BRUTE_FORCE_THRESHOLD
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// The configuration for the HNSW index provider.
+/// # Fields
+/// - capacity_growth_factor: What the capacity of an index is multiplied by when an add
+/// finds it full. Defaults to 2. An index always grows by at least one element.
+/// - brute_force_threshold: Segments with at most this many records are queried by scanning
+/// their embeddings instead of loading their HNSW index. Defaults to 1000, 0 turns it off.
+/// - memory_budget_bytes: How much memory the cached HNSW indices may take up, estimated from
+/// their capacity, dimensionality and M. The least recently used indices that are not in use
+/// are evicted to stay within it. Defaults to none, which caches every index.
+/// # Notes
+/// See config.rs in the root of the worker crate for an example of how to use
+/// config files to configure the worker.
+#[serde(deny_unknown_fields)]
+pub(crate) struct HnswProviderConfig {
+    #[serde(default = "default_capacity_growth_factor")]
+    pub(crate) capacity_growth_factor: f64,
+    #[serde(default = "default_brute_force_threshold")]
+    pub(crate) brute_force_threshold: usize,
+    #[serde(default)]
+    pub(crate) memory_budget_bytes: Option<usize>,
+}
+
+impl Default for HnswProviderConfig {
+    fn default() -> Self {
+        HnswProviderConfig {
+            capacity_growth_factor: DEFAULT_CAPACITY_GROWTH_FACTOR,
+            brute_force_threshold: DEFAULT_BRUTE_FORCE_THRESHOLD,
+            memory_budget_bytes: None,
+        }
+    }
+}
+
+impl ValidateConfig for HnswProviderConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.check(
+            "capacity_growth_factor",
+            self.capacity_growth_factor > 1.0 && self.capacity_growth_factor.is_finite(),
+            "a finite number greater than 1",
+        );
+        if let Some(memory_budget_bytes) = self.memory_budget_bytes {
+            validator.positive("memory_budget_bytes", memory_budget_bytes);
+        }
+    }
+}
@@ -9,8 +9,9 @@ use crate::types::{BooleanOperator, WhereDocument, WhereDocumentOperator};
 use crate::utils::{merge_sorted_vecs_conjunction, merge_sorted_vecs_disjunction};
 
 use arrow::array::Int32Array;
+use futures::TryStreamExt;
 use parking_lot::Mutex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use thiserror::Error;
 use uuid::Uuid;
@@ -211,6 +212,8 @@ impl<'me> FullTextIndexWriter<'me> {
         offset_id: u32,
     ) -> Result<(), FullTextIndexError> {
         let tokens = self.encode_tokens(document);
+        // A token can occur several times in the document but its posting is removed once.
+        let mut deleted_tokens = HashSet::new();
         for token in tokens.get_tokens() {
             self.populate_frequencies_and_posting_lists_from_previous_version(token.text.as_str())
                 .await?;
@@ -225,6 +228,9 @@ impl<'me> FullTextIndexWriter<'me> {
                     return Err(FullTextIndexError::InvariantViolation);
                 }
             }
+            if !deleted_tokens.insert(token.text.as_str()) {
+                continue;
+            }
             let mut uncommitted_postings = self.uncommitted_postings.lock().await;
             match uncommitted_postings
                 .positional_postings
@@ -430,10 +436,10 @@ impl<'me> FullTextIndexReader<'me> {
         // Get query tokens sorted by frequency.
         let mut token_frequencies: Vec<(String, u32)> = vec![];
         for token in tokens {
-            // TODO better error matching (NotFoundError should return Ok(vec![])) but some others should error.
             let res = self
                 .frequencies_blockfile_reader
                 .get_by_prefix(token.text.as_str())
+                .try_collect::<Vec<_>>()
                 .await?;
             if res.len() == 0 {
                 return Ok(vec![]);
@@ -463,6 +469,7 @@ impl<'me> FullTextIndexReader<'me> {
         let first_token_positional_posting_list = self
             .posting_lists_blockfile_reader
             .get_by_prefix(first_token)
+            .try_collect::<Vec<_>>()
             .await
             .unwrap();
         for (_, doc_id, positions) in first_token_positional_posting_list.iter() {
@@ -474,12 +481,15 @@ impl<'me> FullTextIndexReader<'me> {
         }
 
         // Iterate through the rest of the tokens, intersecting the posting lists with the candidates.
-        let mut token_offset = 0;
-        for (token, _) in token_frequencies[1..].iter() {
-            token_offset += 1;
+        for (i, (token, _)) in token_frequencies.iter().enumerate().skip(1) {
+            // Candidates hold the position the query starts at. Positions are byte offsets,
+            // so the token's offset is taken from the query rather than from its index, which
+            // would only work for single byte characters.
+            let token_offset = tokens[i].offset_from as i32;
             let positional_posting_list = self
                 .posting_lists_blockfile_reader
                 .get_by_prefix(token.as_str())
+                .try_collect::<Vec<_>>()
                 .await
                 .unwrap();
             // TODO once we sort by frequency, we need to find the token position
@@ -546,6 +556,7 @@ impl<'me> FullTextIndexReader<'me> {
         let positional_posting_list = self
             .posting_lists_blockfile_reader
             .get_by_prefix(token)
+            .try_collect::<Vec<_>>()
             .await?;
         let mut results = vec![];
         for (_, doc_id, positions) in positional_posting_list.iter() {
@@ -561,6 +572,7 @@ impl<'me> FullTextIndexReader<'me> {
         let res = self
             .frequencies_blockfile_reader
             .get_by_prefix(token)
+            .try_collect::<Vec<_>>()
             .await?;
         if res.len() == 0 {
             return Ok(0);
@@ -590,7 +602,11 @@ pub(crate) fn process_where_document_clause_with_callback<
                     results = result.iter().map(|x| *x as usize).collect();
                 }
                 WhereDocumentOperator::NotContains => {
-                    todo!();
+                    let result = callback(
+                        &direct_document_comparison.document,
+                        WhereDocumentOperator::NotContains,
+                    );
+                    results = result.iter().map(|x| *x as usize).collect();
                 }
             }
         }
@@ -837,8 +853,8 @@ mod tests {
         let index_reader =
             FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
 
-        let res = index_reader.search("chroma").await;
-        assert!(res.is_err());
+        let res = index_reader.search("chroma").await.unwrap();
+        assert!(res.is_empty());
     }
 
     #[tokio::test]
@@ -1231,4 +1247,105 @@ mod tests {
         let res = index_reader.search("world").await.unwrap();
         assert_eq!(res, vec![1]);
     }
+
+    #[tokio::test]
+    async fn test_unicode_and_case_sensitive_search() {
+        let provider = BlockfileProvider::new_memory();
+        let pl_blockfile_writer = provider.create::<u32, &Int32Array>().unwrap();
+        let freq_blockfile_writer = provider.create::<u32, &str>().unwrap();
+        let pl_blockfile_id = pl_blockfile_writer.id();
+        let freq_blockfile_id = freq_blockfile_writer.id();
+
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let mut index_writer =
+            FullTextIndexWriter::new(None, pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        index_writer.add_document("héllo wörld", 1).await.unwrap();
+        index_writer
+            .add_document("日本語のテキスト", 2)
+            .await
+            .unwrap();
+        index_writer.add_document("Hello World", 3).await.unwrap();
+        index_writer.write_to_blockfiles().await.unwrap();
+        let flusher = index_writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        let freq_blockfile_reader = provider.open::<u32, u32>(&freq_blockfile_id).await.unwrap();
+        let pl_blockfile_reader = provider
+            .open::<u32, Int32Array>(&pl_blockfile_id)
+            .await
+            .unwrap();
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let index_reader =
+            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+
+        let res = index_reader.search("wörld").await.unwrap();
+        assert_eq!(res, vec![1]);
+        // Matches span the space between words.
+        let res = index_reader.search("llo wö").await.unwrap();
+        assert_eq!(res, vec![1]);
+        let res = index_reader.search("語のテ").await.unwrap();
+        assert_eq!(res, vec![2]);
+        let res = index_reader.search("語テ").await.unwrap();
+        assert!(res.is_empty());
+
+        // Search is case sensitive.
+        let res = index_reader.search("World").await.unwrap();
+        assert_eq!(res, vec![3]);
+        let res = index_reader.search("hello").await.unwrap();
+        assert!(res.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_and_update_document_with_repeating_characters() {
+        let provider = BlockfileProvider::new_memory();
+        let pl_blockfile_writer = provider.create::<u32, &Int32Array>().unwrap();
+        let freq_blockfile_writer = provider.create::<u32, &str>().unwrap();
+        let pl_blockfile_id = pl_blockfile_writer.id();
+        let freq_blockfile_id = freq_blockfile_writer.id();
+
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let mut index_writer =
+            FullTextIndexWriter::new(None, pl_blockfile_writer, freq_blockfile_writer, tokenizer);
+        index_writer.add_document("hello", 1).await.unwrap();
+        index_writer.add_document("yellow", 2).await.unwrap();
+        index_writer.add_document("balloon", 3).await.unwrap();
+        index_writer.delete_document("hello", 1).await.unwrap();
+        index_writer
+            .update_document("balloon", "moon", 3)
+            .await
+            .unwrap();
+        index_writer.write_to_blockfiles().await.unwrap();
+        let flusher = index_writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        let freq_blockfile_reader = provider.open::<u32, u32>(&freq_blockfile_id).await.unwrap();
+        let pl_blockfile_reader = provider
+            .open::<u32, Int32Array>(&pl_blockfile_id)
+            .await
+            .unwrap();
+        let tokenizer = Box::new(TantivyChromaTokenizer::new(Box::new(
+            NgramTokenizer::new(1, 1, false).unwrap(),
+        )));
+        let index_reader =
+            FullTextIndexReader::new(pl_blockfile_reader, freq_blockfile_reader, tokenizer);
+
+        let res = index_reader.search("ll").await.unwrap();
+        assert_eq!(res, vec![2]);
+        let res = index_reader.search("oo").await.unwrap();
+        assert_eq!(res, vec![3]);
+        assert_eq!(
+            index_reader.get_frequencies_for_token("l").await.unwrap(),
+            2
+        );
+        assert_eq!(
+            index_reader.get_frequencies_for_token("o").await.unwrap(),
+            3
+        );
+    }
 }
@@ -1,14 +1,40 @@
+use std::collections::HashMap;
 use std::ffi::CString;
 use std::ffi::{c_char, c_int};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::distance::{DistanceFunction, DistanceFunctionError};
 use crate::errors::{ChromaError, ErrorCodes};
 
-use super::{Index, IndexConfig, PersistentIndex};
-use crate::types::{Metadata, MetadataValue, MetadataValueConversionError, Segment};
+use super::{HnswIndexParams, HnswIndexParamsError, Index, IndexConfig, PersistentIndex};
+use crate::tracing::metrics::metrics;
+use crate::types::{KnnQuery, Segment};
+use parking_lot::{RwLock, RwLockUpgradableReadGuard};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use uuid::Uuid;
 
 const DEFAULT_MAX_ELEMENTS: usize = 10000;
+// The largest ef a query may ask for. hnswlib keeps a candidate heap of ef elements per
+// search, so anything larger only costs memory and latency.
+pub(crate) const MAX_EF_SEARCH: usize = 10000;
+
+// These are the files hnswlib writes to disk. This is strong coupling, but we need to know
+// what files to read from disk. We could in the future have the C++ code return the files
+// but ideally we have a rust implementation of hnswlib
+pub(crate) const HNSW_INDEX_FILES: [&str; 4] = [
+    "header.bin",
+    "data_level0.bin",
+    "length.bin",
+    "link_lists.bin",
+];
+// Written next to the hnswlib files on save. hnswlib aborts the process on files it can not
+// parse, so this is what lets a load reject files that are incomplete or were built for a
+// different collection before handing them to hnswlib.
+pub(crate) const HNSW_METADATA_FILE: &str = "index_metadata.json";
 
 // https://doc.rust-lang.org/nomicon/ffi.html#representing-opaque-structs
 #[repr(C)]
@@ -37,8 +63,8 @@ pub(crate) struct HnswIndexConfig {
 pub(crate) enum HnswIndexFromSegmentError {
     #[error("Missing config `{0}`")]
     MissingConfig(String),
-    #[error("Invalid metadata value")]
-    MetadataValueError(#[from] MetadataValueConversionError),
+    #[error(transparent)]
+    InvalidParams(#[from] HnswIndexParamsError),
 }
 
 impl ChromaError for HnswIndexFromSegmentError {
@@ -60,57 +86,23 @@ impl HnswIndexConfig {
                 )))
             }
         };
-        let metadata = match &segment.metadata {
-            Some(metadata) => metadata,
-            None => {
-                // TODO: This should error, but the configuration is not stored correctly
-                // after the configuration is refactored to be always stored and doesn't rely on defaults we can fix this
-                return Ok(HnswIndexConfig {
-                    max_elements: 1000,
-                    m: 16,
-                    ef_construction: 100,
-                    ef_search: 10,
-                    random_seed: 0,
-                    persist_path: persist_path.to_string(),
-                });
-                // return Err(Box::new(HnswIndexFromSegmentError::MissingConfig(
-                //     "metadata".to_string(),
-                // )))
-            }
+        // TODO: Missing metadata should error, but the configuration is not stored correctly
+        // after the configuration is refactored to be always stored and doesn't rely on defaults we can fix this
+        let (max_elements, params) = match &segment.metadata {
+            Some(metadata) => match HnswIndexParams::try_from(metadata) {
+                Ok(params) => (DEFAULT_MAX_ELEMENTS, params),
+                Err(e) => return Err(Box::new(HnswIndexFromSegmentError::InvalidParams(e))),
+            },
+            None => (1000, HnswIndexParams::default()),
         };
-
-        fn get_metadata_value_as<'a, T>(
-            metadata: &'a Metadata,
-            key: &str,
-        ) -> Result<T, Box<HnswIndexFromSegmentError>>
-        where
-            T: TryFrom<&'a MetadataValue, Error = MetadataValueConversionError>,
-        {
-            let res = match metadata.get(key) {
-                Some(value) => T::try_from(value),
-                None => {
-                    return Err(Box::new(HnswIndexFromSegmentError::MissingConfig(
-                        key.to_string(),
-                    )))
-                }
-            };
-            match res {
-                Ok(value) => Ok(value),
-                Err(e) => Err(Box::new(HnswIndexFromSegmentError::MetadataValueError(e))),
-            }
-        }
-
-        let m = get_metadata_value_as::<i32>(metadata, "hnsw:M")?;
-        let ef_construction = get_metadata_value_as::<i32>(metadata, "hnsw:construction_ef")?;
-        let ef_search = get_metadata_value_as::<i32>(metadata, "hnsw:search_ef")?;
-        return Ok(HnswIndexConfig {
-            max_elements: DEFAULT_MAX_ELEMENTS,
-            m: m as usize,
-            ef_construction: ef_construction as usize,
-            ef_search: ef_search as usize,
+        Ok(HnswIndexConfig {
+            max_elements,
+            m: params.m,
+            ef_construction: params.ef_construction,
+            ef_search: params.ef_search,
             random_seed: 0,
             persist_path: persist_path.to_string(),
-        });
+        })
     }
 }
 
@@ -119,11 +111,19 @@ impl HnswIndexConfig {
 /// # Description
 /// This struct wraps a pointer to the C++ HnswIndex class and presents a safe Rust interface.
 /// # Notes
-/// This struct is not thread safe for concurrent reads and writes. Callers should
-/// synchronize access to the index between reads and writes.
+/// The index is kept in a RwLock. hnswlib supports concurrent adds, deletes and queries, so
+/// those share the read lock; adds that may have to grow the index take the upgradable one
+/// (see `add_growing`). Resizing the index and changing its ef are not safe alongside
+/// searches and need the write lock.
 pub(crate) struct HnswIndex {
     ffi_ptr: *const IndexPtrFFI,
     dimensionality: i32,
+    distance_function: DistanceFunction,
+    m: usize,
+    ef_construction: usize,
+    persist_path: String,
+    // When the index was last saved, in milliseconds since the unix epoch, 0 if it never was.
+    saved_at_ms: AtomicU64,
     pub(crate) id: Uuid,
 }
 
@@ -148,6 +148,236 @@ impl ChromaError for HnswIndexInitError {
     }
 }
 
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum HnswEfSearchError {
+    #[error("ef_search {ef_search} must be at least k ({k})")]
+    SmallerThanK { ef_search: i32, k: i32 },
+    #[error("ef_search {ef_search} must be at most {max}")]
+    TooLarge { ef_search: i32, max: usize },
+}
+
+impl ChromaError for HnswEfSearchError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::InvalidArgument
+    }
+}
+
+/// Validates an ef_search requested for a single query of `k` results.
+pub(crate) fn validate_ef_search(ef_search: i32, k: i32) -> Result<usize, HnswEfSearchError> {
+    if ef_search < k {
+        return Err(HnswEfSearchError::SmallerThanK { ef_search, k });
+    }
+    if ef_search as usize > MAX_EF_SEARCH {
+        return Err(HnswEfSearchError::TooLarge {
+            ef_search,
+            max: MAX_EF_SEARCH,
+        });
+    }
+    Ok(ef_search as usize)
+}
+
+/// What an index holds and how much memory it takes, for operators to inspect.
+/// # Fields
+/// - `element_count` - The number of elements that are not deleted.
+/// - `deleted_count` - The number of deleted elements, whose slots are not reused.
+/// - `estimated_size` - See `HnswIndex::estimated_size`.
+/// - `saved_at` - When the index was last saved, None if it never was.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct HnswIndexStats {
+    pub(crate) element_count: usize,
+    pub(crate) deleted_count: usize,
+    pub(crate) capacity: usize,
+    pub(crate) dimensionality: usize,
+    pub(crate) distance_function: String,
+    pub(crate) estimated_size: usize,
+    pub(crate) saved_at: Option<SystemTime>,
+}
+
+impl HnswIndexStats {
+    /// The stats of the index saved with `metadata`, the contents of its HNSW_METADATA_FILE,
+    /// as of when it was saved. This does not need any of the other files of the index.
+    pub(crate) fn from_saved_metadata(metadata: &[u8]) -> Result<Self, HnswIndexLoadError> {
+        let metadata: HnswIndexMetadata = match serde_json::from_slice(metadata) {
+            Ok(metadata) => metadata,
+            Err(e) => return Err(HnswIndexLoadError::InvalidMetadata(e.to_string())),
+        };
+        Ok(HnswIndexStats {
+            element_count: metadata.element_count,
+            deleted_count: metadata.deleted_count,
+            capacity: metadata.max_elements,
+            dimensionality: metadata.dimensionality as usize,
+            distance_function: metadata.distance_function,
+            estimated_size: estimated_size(
+                metadata.dimensionality as usize,
+                metadata.m,
+                metadata.max_elements,
+            ),
+            saved_at: saved_at(metadata.saved_at_ms),
+        })
+    }
+}
+
+fn saved_at(saved_at_ms: u64) -> Option<SystemTime> {
+    match saved_at_ms {
+        0 => None,
+        ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+    }
+}
+
+// See HnswIndex::estimated_size.
+fn estimated_size(dimensionality: usize, m: usize, capacity: usize) -> usize {
+    let embedding = dimensionality * std::mem::size_of::<f32>();
+    // hnswlib keeps up to 2 * M links of 4 bytes, their count and the label of every
+    // element.
+    let links = 2 * m * 4 + 4 + 8;
+    capacity * (embedding + links)
+}
+
+// The sidecar describing a saved index, see HNSW_METADATA_FILE.
+#[derive(Debug, Serialize, Deserialize)]
+struct HnswIndexMetadata {
+    dimensionality: i32,
+    distance_function: String,
+    m: usize,
+    ef_construction: usize,
+    element_count: usize,
+    // Sidecars written before these were recorded read as 0.
+    #[serde(default)]
+    deleted_count: usize,
+    // In milliseconds since the unix epoch.
+    #[serde(default)]
+    saved_at_ms: u64,
+    // The capacity the index had grown to when it was saved.
+    max_elements: usize,
+    // The size in bytes of each of HNSW_INDEX_FILES when the index was saved.
+    file_lengths: HashMap<String, u64>,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum HnswIndexSaveError {
+    #[error("IO error on hnsw index file `{0}`: {1}")]
+    IOError(String, std::io::Error),
+    #[error("Failed to serialize hnsw index metadata: {0}")]
+    MetadataSerializationError(#[from] serde_json::Error),
+}
+
+impl ChromaError for HnswIndexSaveError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::Internal
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum HnswIndexLoadError {
+    #[error("Hnsw index file `{0}` is missing")]
+    MissingFile(String),
+    #[error("IO error on hnsw index file `{0}`: {1}")]
+    IOError(String, std::io::Error),
+    #[error("Hnsw index metadata is corrupted: {0}")]
+    InvalidMetadata(String),
+    #[error("Hnsw index file `{file}` is truncated or corrupted, expected {expected} bytes but found {actual}")]
+    FileLengthMismatch {
+        file: String,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("Hnsw index has dimensionality {actual} but the collection has {expected}")]
+    DimensionalityMismatch { expected: i32, actual: i32 },
+    #[error("Hnsw index was built for distance function `{actual}` but the collection uses `{expected}`")]
+    DistanceFunctionMismatch { expected: String, actual: String },
+    #[error("Hnsw index holds {actual} elements but was saved with {expected}")]
+    ElementCountMismatch { expected: usize, actual: usize },
+}
+
+impl ChromaError for HnswIndexLoadError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            HnswIndexLoadError::MissingFile(_) => ErrorCodes::NotFound,
+            HnswIndexLoadError::IOError(_, _) => ErrorCodes::Internal,
+            HnswIndexLoadError::InvalidMetadata(_) => ErrorCodes::Internal,
+            HnswIndexLoadError::FileLengthMismatch { .. } => ErrorCodes::Internal,
+            HnswIndexLoadError::DimensionalityMismatch { .. } => ErrorCodes::InvalidArgument,
+            HnswIndexLoadError::DistanceFunctionMismatch { .. } => ErrorCodes::InvalidArgument,
+            HnswIndexLoadError::ElementCountMismatch { .. } => ErrorCodes::Internal,
+        }
+    }
+}
+
+// Reads the metadata saved with the index at `path` and checks that the index files are
+// complete and match `index_config`.
+fn read_metadata(
+    path: &Path,
+    index_config: &IndexConfig,
+) -> Result<HnswIndexMetadata, HnswIndexLoadError> {
+    let io_error = |file: &str, e: std::io::Error| match e.kind() {
+        std::io::ErrorKind::NotFound => HnswIndexLoadError::MissingFile(file.to_string()),
+        _ => HnswIndexLoadError::IOError(file.to_string(), e),
+    };
+
+    let metadata = match std::fs::read(path.join(HNSW_METADATA_FILE)) {
+        Ok(metadata) => metadata,
+        Err(e) => return Err(io_error(HNSW_METADATA_FILE, e)),
+    };
+    let metadata: HnswIndexMetadata = match serde_json::from_slice(&metadata) {
+        Ok(metadata) => metadata,
+        Err(e) => return Err(HnswIndexLoadError::InvalidMetadata(e.to_string())),
+    };
+
+    if metadata.dimensionality != index_config.dimensionality {
+        return Err(HnswIndexLoadError::DimensionalityMismatch {
+            expected: index_config.dimensionality,
+            actual: metadata.dimensionality,
+        });
+    }
+    let distance_function: String = index_config.distance_function.clone().into();
+    if metadata.distance_function != distance_function {
+        return Err(HnswIndexLoadError::DistanceFunctionMismatch {
+            expected: distance_function,
+            actual: metadata.distance_function,
+        });
+    }
+    for file in HNSW_INDEX_FILES {
+        let expected = match metadata.file_lengths.get(file) {
+            Some(expected) => *expected,
+            None => {
+                return Err(HnswIndexLoadError::InvalidMetadata(format!(
+                    "no length recorded for `{}`",
+                    file
+                )))
+            }
+        };
+        let actual = match std::fs::metadata(path.join(file)) {
+            Ok(file_metadata) => file_metadata.len(),
+            Err(e) => return Err(io_error(file, e)),
+        };
+        if actual != expected {
+            return Err(HnswIndexLoadError::FileLengthMismatch {
+                file: file.to_string(),
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(metadata)
+}
+
+// Returns the name of the hnswlib space for the distance function. hnswlib does not check
+// the name it is given, so unsupported distance functions have to be rejected here.
+fn space_name(distance_function: &DistanceFunction) -> Result<CString, Box<dyn ChromaError>> {
+    if !distance_function.supported_by_hnsw() {
+        return Err(Box::new(DistanceFunctionError::UnsupportedByHnsw(
+            distance_function.clone(),
+        )));
+    }
+    let distance_function_string: String = distance_function.clone().into();
+    match CString::new(distance_function_string) {
+        Ok(space_name) => Ok(space_name),
+        Err(e) => Err(Box::new(HnswIndexInitError::InvalidDistanceFunction(
+            e.to_string(),
+        ))),
+    }
+}
+
 impl Index<HnswIndexConfig> for HnswIndex {
     fn init(
         index_config: &IndexConfig,
@@ -157,17 +387,7 @@ impl Index<HnswIndexConfig> for HnswIndex {
         match hnsw_config {
             None => return Err(Box::new(HnswIndexInitError::NoConfigProvided)),
             Some(config) => {
-                let distance_function_string: String =
-                    index_config.distance_function.clone().into();
-
-                let space_name = match CString::new(distance_function_string) {
-                    Ok(space_name) => space_name,
-                    Err(e) => {
-                        return Err(Box::new(HnswIndexInitError::InvalidDistanceFunction(
-                            e.to_string(),
-                        )))
-                    }
-                };
+                let space_name = space_name(&index_config.distance_function)?;
 
                 let ffi_ptr =
                     unsafe { create_index(space_name.as_ptr(), index_config.dimensionality) };
@@ -193,6 +413,11 @@ impl Index<HnswIndexConfig> for HnswIndex {
                 let hnsw_index = HnswIndex {
                     ffi_ptr: ffi_ptr,
                     dimensionality: index_config.dimensionality,
+                    distance_function: index_config.distance_function.clone(),
+                    m: config.m,
+                    ef_construction: config.ef_construction,
+                    persist_path: config.persist_path.clone(),
+                    saved_at_ms: AtomicU64::new(0),
                     id,
                 };
                 hnsw_index.set_ef(config.ef_search);
@@ -206,7 +431,15 @@ impl Index<HnswIndexConfig> for HnswIndex {
     }
 
     fn delete(&self, id: usize) {
-        unsafe { mark_deleted(self.ffi_ptr, id) }
+        let res = unsafe { mark_deleted(self.ffi_ptr, id) };
+        if res != 0 {
+            // Deleting an id that was never added, or that is already deleted, is a no-op.
+            tracing::debug!(
+                "Id {} is not in HNSW index {}, skipping delete",
+                id,
+                self.id
+            );
+        }
     }
 
     fn query(
@@ -221,10 +454,12 @@ impl Index<HnswIndexConfig> for HnswIndex {
         let mut distance = vec![0.0f32; actual_k];
         let mut total_result = actual_k;
         unsafe {
+            // Adds can run alongside the query, so the index may have grown past actual_k
+            // elements by now. Asking for more would overrun the buffers.
             total_result = knn_query(
                 self.ffi_ptr,
                 vector.as_ptr(),
-                k,
+                actual_k,
                 ids.as_mut_ptr(),
                 distance.as_mut_ptr(),
                 allowed_ids.as_ptr(),
@@ -252,7 +487,10 @@ impl Index<HnswIndexConfig> for HnswIndex {
 impl PersistentIndex<HnswIndexConfig> for HnswIndex {
     fn save(&self) -> Result<(), Box<dyn ChromaError>> {
         unsafe { persist_dirty(self.ffi_ptr) };
-        Ok(())
+        match self.write_metadata() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
     }
 
     fn load(
@@ -260,28 +498,39 @@ impl PersistentIndex<HnswIndexConfig> for HnswIndex {
         index_config: &IndexConfig,
         id: Uuid,
     ) -> Result<Self, Box<dyn ChromaError>> {
-        let distance_function_string: String = index_config.distance_function.clone().into();
-        let space_name = match CString::new(distance_function_string) {
-            Ok(space_name) => space_name,
-            Err(e) => {
-                return Err(Box::new(HnswIndexInitError::InvalidDistanceFunction(
-                    e.to_string(),
-                )))
-            }
+        let space_name = space_name(&index_config.distance_function)?;
+        let metadata = match read_metadata(Path::new(path), index_config) {
+            Ok(metadata) => metadata,
+            Err(e) => return Err(Box::new(e)),
         };
-        let ffi_ptr = unsafe { create_index(space_name.as_ptr(), index_config.dimensionality) };
+        let persist_path = path.to_string();
         let path = match CString::new(path.to_string()) {
             Ok(path) => path,
             Err(e) => return Err(Box::new(HnswIndexInitError::InvalidPath(e.to_string()))),
         };
+        let ffi_ptr = unsafe { create_index(space_name.as_ptr(), index_config.dimensionality) };
         unsafe {
             load_index(ffi_ptr, path.as_ptr(), true, true);
         }
-        let hnsw_index = HnswIndex {
+        let mut hnsw_index = HnswIndex {
             ffi_ptr: ffi_ptr,
             dimensionality: index_config.dimensionality,
+            distance_function: index_config.distance_function.clone(),
+            m: metadata.m,
+            ef_construction: metadata.ef_construction,
+            persist_path,
+            saved_at_ms: AtomicU64::new(metadata.saved_at_ms),
             id,
         };
+        if hnsw_index.capacity() < metadata.max_elements {
+            hnsw_index.resize(metadata.max_elements);
+        }
+        if hnsw_index.len() != metadata.element_count {
+            return Err(Box::new(HnswIndexLoadError::ElementCountMismatch {
+                expected: metadata.element_count,
+                actual: hnsw_index.len(),
+            }));
+        }
         Ok(hnsw_index)
     }
 }
@@ -303,9 +552,164 @@ impl HnswIndex {
         unsafe { capacity(self.ffi_ptr) as usize }
     }
 
+    pub(crate) fn dimensionality(&self) -> usize {
+        self.dimensionality as usize
+    }
+
+    /// Roughly how many bytes the index takes up in memory: the embeddings and the links of
+    /// the bottom layer of the graph for every element it has room for. The upper layers
+    /// only hold a small fraction of the elements and are left out.
+    pub(crate) fn estimated_size(&self) -> usize {
+        estimated_size(self.dimensionality as usize, self.m, self.capacity())
+    }
+
+    pub(crate) fn stats(&self) -> HnswIndexStats {
+        let element_count = self.len();
+        HnswIndexStats {
+            element_count,
+            deleted_count: self.element_count() - element_count,
+            capacity: self.capacity(),
+            dimensionality: self.dimensionality as usize,
+            distance_function: self.distance_function.clone().into(),
+            estimated_size: self.estimated_size(),
+            saved_at: saved_at(self.saved_at_ms.load(Ordering::Relaxed)),
+        }
+    }
+
     pub fn resize(&mut self, new_size: usize) {
         unsafe { resize_index(self.ffi_ptr, new_size) }
     }
+
+    // The number of slots in use. Unlike len() this counts deleted elements, since their
+    // slots are not reused by add.
+    fn element_count(&self) -> usize {
+        unsafe { element_count(self.ffi_ptr) }
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.element_count() >= self.capacity()
+    }
+
+    // Multiplies the capacity by `growth_factor`, growing it by at least one element.
+    pub(crate) fn grow(&mut self, growth_factor: f64) {
+        let capacity = self.capacity();
+        let grown = (capacity as f64 * growth_factor).ceil() as usize;
+        self.resize(std::cmp::max(grown, capacity + 1));
+    }
+
+    /// Adds `vector` under `id`, growing the index first if it is full.
+    /// # Description
+    /// hnswlib aborts on adds past the capacity the index was created with, so the capacity
+    /// is checked before every add and multiplied by `growth_factor` when it has run out.
+    /// # Notes
+    /// The resize and the add that needed it happen under the write lock, so readers never
+    /// see the index mid-resize. Other adds only take the upgradable lock and run alongside
+    /// queries.
+    pub(crate) fn add_growing(
+        index: &RwLock<HnswIndex>,
+        id: usize,
+        vector: &[f32],
+        growth_factor: f64,
+    ) {
+        let index = index.upgradable_read();
+        if index.is_full() {
+            let mut index = RwLockUpgradableReadGuard::upgrade(index);
+            index.grow(growth_factor);
+            index.add(id, vector);
+        } else {
+            index.add(id, vector);
+        }
+    }
+
+    /// Queries the index for each of `queries`, with `ef_search` in place of its configured
+    /// ef, or with the configured ef when it is None. Results are in the order of `queries`.
+    /// # Notes
+    /// hnswlib keeps ef on the index rather than taking it per search, so an override
+    /// holds the write lock for the duration of the batch. Batches without one share the
+    /// read lock as usual. Either way the lock is taken once for all of the queries, which
+    /// are searched in parallel on the threads of the rayon pool this is called from.
+    pub(crate) fn query_batch_with_ef(
+        index: &RwLock<HnswIndex>,
+        queries: &[KnnQuery],
+        allowed_ids: &[usize],
+        disallowed_ids: &[usize],
+        ef_search: Option<usize>,
+    ) -> Vec<(Vec<usize>, Vec<f32>)> {
+        let search = |index: &HnswIndex, ef: usize| -> Vec<(Vec<usize>, Vec<f32>)> {
+            queries
+                .par_iter()
+                .map(|query| {
+                    let start = Instant::now();
+                    let res = index.query(&query.embedding, query.k, allowed_ids, disallowed_ids);
+                    metrics().record_hnsw_search(query.k, ef, start.elapsed());
+                    res
+                })
+                .collect()
+        };
+        match ef_search {
+            None => {
+                let index = index.read();
+                search(&index, index.get_ef())
+            }
+            Some(ef_search) => {
+                let index = index.write();
+                let configured_ef = index.get_ef();
+                index.set_ef(ef_search);
+                let res = search(&index, ef_search);
+                index.set_ef(configured_ef);
+                res
+            }
+        }
+    }
+
+    // Writes HNSW_METADATA_FILE for the files hnswlib just persisted. It goes through a
+    // temporary file so that a crash while saving never leaves metadata that looks valid.
+    fn write_metadata(&self) -> Result<(), HnswIndexSaveError> {
+        let path = Path::new(&self.persist_path);
+        let mut file_lengths = HashMap::new();
+        for file in HNSW_INDEX_FILES {
+            match std::fs::metadata(path.join(file)) {
+                Ok(file_metadata) => {
+                    file_lengths.insert(file.to_string(), file_metadata.len());
+                }
+                Err(e) => return Err(HnswIndexSaveError::IOError(file.to_string(), e)),
+            }
+        }
+        let saved_at_ms = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.as_millis() as u64,
+            Err(_) => 0,
+        };
+        let element_count = self.len();
+        let metadata = HnswIndexMetadata {
+            dimensionality: self.dimensionality,
+            distance_function: self.distance_function.clone().into(),
+            m: self.m,
+            ef_construction: self.ef_construction,
+            element_count,
+            deleted_count: self.element_count() - element_count,
+            saved_at_ms,
+            max_elements: self.capacity(),
+            file_lengths,
+        };
+        let metadata = serde_json::to_vec(&metadata)?;
+        let tmp_path = path.join(format!("{}.tmp", HNSW_METADATA_FILE));
+        if let Err(e) = std::fs::write(&tmp_path, metadata) {
+            return Err(HnswIndexSaveError::IOError(
+                HNSW_METADATA_FILE.to_string(),
+                e,
+            ));
+        }
+        match std::fs::rename(&tmp_path, path.join(HNSW_METADATA_FILE)) {
+            Ok(_) => {
+                self.saved_at_ms.store(saved_at_ms, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(e) => Err(HnswIndexSaveError::IOError(
+                HNSW_METADATA_FILE.to_string(),
+                e,
+            )),
+        }
+    }
 }
 
 #[link(name = "bindings", kind = "static")]
@@ -333,7 +737,7 @@ extern "C" {
     fn persist_dirty(index: *const IndexPtrFFI);
 
     fn add_item(index: *const IndexPtrFFI, data: *const f32, id: usize, replace_deleted: bool);
-    fn mark_deleted(index: *const IndexPtrFFI, id: usize);
+    fn mark_deleted(index: *const IndexPtrFFI, id: usize) -> c_int;
     fn get_item(index: *const IndexPtrFFI, id: usize, data: *mut f32);
     fn knn_query(
         index: *const IndexPtrFFI,
@@ -351,6 +755,7 @@ extern "C" {
     fn set_ef(index: *const IndexPtrFFI, ef: c_int);
     fn len(index: *const IndexPtrFFI) -> c_int;
     fn capacity(index: *const IndexPtrFFI) -> c_int;
+    fn element_count(index: *const IndexPtrFFI) -> usize;
     fn resize_index(index: *const IndexPtrFFI, new_size: usize);
 }
 
@@ -360,10 +765,15 @@ pub mod test {
 
     use crate::distance::DistanceFunction;
     use crate::index::utils;
+    use proptest::prelude::*;
+    use proptest::test_runner::Config;
     use rand::seq::IteratorRandom;
     use rand::Rng;
     use rayon::prelude::*;
     use rayon::ThreadPoolBuilder;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
     use tempfile::tempdir;
 
     #[test]
@@ -398,6 +808,42 @@ pub mod test {
         }
     }
 
+    #[test]
+    fn it_rejects_distance_functions_hnswlib_does_not_support() {
+        for distance_function in [
+            DistanceFunction::Manhattan,
+            DistanceFunction::Hamming,
+            DistanceFunction::Jaccard,
+        ] {
+            let tmp_dir = tempdir().unwrap();
+            let persist_path = tmp_dir.path().to_str().unwrap().to_string();
+            let index_config = IndexConfig {
+                dimensionality: 3,
+                distance_function: distance_function.clone(),
+            };
+            let index = HnswIndex::init(
+                &index_config,
+                Some(&HnswIndexConfig {
+                    max_elements: 10,
+                    m: 16,
+                    ef_construction: 100,
+                    ef_search: 10,
+                    random_seed: 0,
+                    persist_path: persist_path.clone(),
+                }),
+                Uuid::new_v4(),
+            );
+            match index {
+                Ok(_) => panic!("Expected {} to be rejected", distance_function),
+                Err(e) => assert_eq!(e.code(), ErrorCodes::InvalidArgument),
+            }
+            match HnswIndex::load(&persist_path, &index_config, Uuid::new_v4()) {
+                Ok(_) => panic!("Expected {} to be rejected", distance_function),
+                Err(e) => assert_eq!(e.code(), ErrorCodes::InvalidArgument),
+            }
+        }
+    }
+
     #[test]
     fn it_can_add_parallel() {
         let n: usize = 100;
@@ -687,6 +1133,441 @@ pub mod test {
         }
     }
 
+    // Builds and saves an index of `n` random vectors, returning it with its data.
+    fn saved_index(
+        persist_path: &str,
+        n: usize,
+        d: usize,
+        distance_function: DistanceFunction,
+    ) -> (HnswIndex, Vec<f32>) {
+        let index = HnswIndex::init(
+            &IndexConfig {
+                dimensionality: d as i32,
+                distance_function,
+            },
+            Some(&HnswIndexConfig {
+                max_elements: n,
+                m: 24,
+                ef_construction: 150,
+                ef_search: 100,
+                random_seed: 0,
+                persist_path: persist_path.to_string(),
+            }),
+            Uuid::new_v4(),
+        )
+        .unwrap();
+        let data: Vec<f32> = utils::generate_random_data(n, d);
+        for i in 0..n {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+        index.save().unwrap();
+        (index, data)
+    }
+
+    #[test]
+    fn it_returns_identical_results_after_save_and_load() {
+        let (n, d) = (500, 32);
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap();
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Cosine,
+        };
+        let (index, data) = saved_index(persist_path, n, d, DistanceFunction::Cosine);
+        let loaded = HnswIndex::load(persist_path, &index_config, index.id).unwrap();
+        loaded.set_ef(100);
+
+        assert_eq!(loaded.len(), n);
+        assert_eq!(loaded.m, 24);
+        assert_eq!(loaded.ef_construction, 150);
+        for i in (0..n).step_by(50) {
+            let query = &data[i * d..(i + 1) * d];
+            assert_eq!(
+                index.query(query, 10, &[], &[]),
+                loaded.query(query, 10, &[], &[])
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_truncated_index_files() {
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap();
+        let (index, _) = saved_index(persist_path, 100, 8, DistanceFunction::Euclidean);
+        let data_file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(tmp_dir.path().join("data_level0.bin"))
+            .unwrap();
+        let length = data_file.metadata().unwrap().len();
+        data_file.set_len(length / 2).unwrap();
+
+        let index_config = IndexConfig {
+            dimensionality: 8,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        match HnswIndex::load(persist_path, &index_config, index.id) {
+            Ok(_) => panic!("Expected a truncated index to be rejected"),
+            Err(e) => assert_eq!(e.code(), ErrorCodes::Internal),
+        }
+    }
+
+    #[test]
+    fn it_rejects_index_built_for_another_collection() {
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap();
+        let (index, _) = saved_index(persist_path, 100, 8, DistanceFunction::Euclidean);
+
+        for index_config in [
+            IndexConfig {
+                dimensionality: 16,
+                distance_function: DistanceFunction::Euclidean,
+            },
+            IndexConfig {
+                dimensionality: 8,
+                distance_function: DistanceFunction::InnerProduct,
+            },
+        ] {
+            match HnswIndex::load(persist_path, &index_config, index.id) {
+                Ok(_) => panic!("Expected {:?} to be rejected", index_config),
+                Err(e) => assert_eq!(e.code(), ErrorCodes::InvalidArgument),
+            }
+        }
+    }
+
+    #[test]
+    fn it_rejects_index_without_metadata() {
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap();
+        let (index, _) = saved_index(persist_path, 10, 8, DistanceFunction::Euclidean);
+        std::fs::remove_file(tmp_dir.path().join(HNSW_METADATA_FILE)).unwrap();
+
+        let index_config = IndexConfig {
+            dimensionality: 8,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        match HnswIndex::load(persist_path, &index_config, index.id) {
+            Ok(_) => panic!("Expected an index without metadata to be rejected"),
+            Err(e) => assert_eq!(e.code(), ErrorCodes::NotFound),
+        }
+    }
+
+    #[test]
+    fn it_reports_the_same_stats_from_its_saved_metadata() {
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap();
+        let index = small_index(persist_path, 50, 8).into_inner();
+        for i in 0..10 {
+            index.add(i, &[i as f32; 8]);
+        }
+        for i in 0..3 {
+            index.delete(i);
+        }
+        let stats = index.stats();
+        assert_eq!(
+            stats,
+            HnswIndexStats {
+                element_count: 7,
+                deleted_count: 3,
+                capacity: 50,
+                dimensionality: 8,
+                distance_function: "l2".to_string(),
+                estimated_size: 50 * (8 * 4 + 2 * 16 * 4 + 12),
+                saved_at: None,
+            }
+        );
+
+        let before = SystemTime::now();
+        index.save().unwrap();
+        let stats = index.stats();
+        assert!(stats.saved_at.unwrap() + Duration::from_millis(1) >= before);
+        let metadata = std::fs::read(tmp_dir.path().join(HNSW_METADATA_FILE)).unwrap();
+        assert_eq!(
+            HnswIndexStats::from_saved_metadata(&metadata).unwrap(),
+            stats
+        );
+        let index_config = IndexConfig {
+            dimensionality: 8,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let loaded = HnswIndex::load(persist_path, &index_config, index.id).unwrap();
+        assert_eq!(loaded.stats(), stats);
+    }
+
+    #[test]
+    fn it_reads_stats_from_metadata_without_them() {
+        // A sidecar from before deletes and save times were recorded.
+        let metadata = r#"{"dimensionality":3,"distance_function":"cosine","m":16,
+            "ef_construction":100,"element_count":4,"max_elements":10,"file_lengths":{}}"#;
+        let stats = HnswIndexStats::from_saved_metadata(metadata.as_bytes()).unwrap();
+        assert_eq!(stats.deleted_count, 0);
+        assert_eq!(stats.saved_at, None);
+        assert_eq!(stats.distance_function, "cosine");
+        assert!(matches!(
+            HnswIndexStats::from_saved_metadata(b"{}"),
+            Err(HnswIndexLoadError::InvalidMetadata(_))
+        ));
+    }
+
+    fn small_index(persist_path: &str, capacity: usize, d: usize) -> RwLock<HnswIndex> {
+        let index = HnswIndex::init(
+            &IndexConfig {
+                dimensionality: d as i32,
+                distance_function: DistanceFunction::Euclidean,
+            },
+            Some(&HnswIndexConfig {
+                max_elements: capacity,
+                m: 16,
+                ef_construction: 100,
+                ef_search: 100,
+                random_seed: 0,
+                persist_path: persist_path.to_string(),
+            }),
+            Uuid::new_v4(),
+        )
+        .unwrap();
+        RwLock::new(index)
+    }
+
+    #[test]
+    fn it_is_at_least_as_accurate_with_a_higher_ef() {
+        let (n, d, k) = (2000, 32, 10);
+        let tmp_dir = tempdir().unwrap();
+        let index = small_index(tmp_dir.path().to_str().unwrap(), n, d);
+        let data: Vec<f32> = utils::generate_random_data(n, d);
+        for i in 0..n {
+            index.read().add(i, &data[i * d..(i + 1) * d]);
+        }
+        let configured_ef = index.read().get_ef();
+
+        let queries: Vec<usize> = (0..n).step_by(50).collect();
+        let batch: Vec<KnnQuery> = queries
+            .iter()
+            .map(|q| KnnQuery {
+                embedding: data[q * d..(q + 1) * d].to_vec(),
+                k,
+            })
+            .collect();
+        let recall = |ef_search: usize| {
+            let results = HnswIndex::query_batch_with_ef(&index, &batch, &[], &[], Some(ef_search));
+            let mut found = 0;
+            for (query, (ids, _)) in batch.iter().zip(results) {
+                let expected = DistanceFunction::Euclidean.top_k(
+                    &query.embedding,
+                    (0..n).map(|i| (i as u32, &data[i * d..(i + 1) * d])),
+                    k,
+                );
+                found += expected
+                    .iter()
+                    .filter(|(id, _)| ids.contains(&(*id as usize)))
+                    .count();
+            }
+            found as f32 / (k * queries.len()) as f32
+        };
+        let low = recall(k);
+        let high = recall(200);
+        assert!(
+            high >= low,
+            "recall {} with ef 200 < {} with ef {}",
+            high,
+            low,
+            k
+        );
+        // The override only applies to the batch it was given for.
+        assert_eq!(index.read().get_ef(), configured_ef);
+    }
+
+    #[test]
+    fn it_answers_a_batch_as_each_query_alone() {
+        let (n, d) = (1000, 16);
+        let tmp_dir = tempdir().unwrap();
+        let index = small_index(tmp_dir.path().to_str().unwrap(), n, d);
+        let data: Vec<f32> = utils::generate_random_data(n, d);
+        for i in 0..n {
+            index.read().add(i, &data[i * d..(i + 1) * d]);
+        }
+        let batch: Vec<KnnQuery> = [1, 10, 0, 50, 3]
+            .iter()
+            .enumerate()
+            .map(|(q, &k)| KnnQuery {
+                embedding: data[q * 100 * d..(q * 100 + 1) * d].to_vec(),
+                k,
+            })
+            .collect();
+        let disallowed: Vec<usize> = (0..n).step_by(7).collect();
+
+        let pool = ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let results =
+            pool.install(|| HnswIndex::query_batch_with_ef(&index, &batch, &[], &disallowed, None));
+        assert_eq!(results.len(), batch.len());
+        for (query, result) in batch.iter().zip(results) {
+            let alone = index
+                .read()
+                .query(&query.embedding, query.k, &[], &disallowed);
+            assert_eq!(result, alone);
+            assert_eq!(result.0.len(), query.k);
+        }
+    }
+
+    #[test]
+    fn it_returns_k_results_for_a_selective_filter() {
+        let (n, d, k) = (100_000, 8, 10);
+        let tmp_dir = tempdir().unwrap();
+        let index = small_index(tmp_dir.path().to_str().unwrap(), n, d);
+        let data: Vec<f32> = utils::generate_random_data(n, d);
+        let index = index.read();
+        for i in 0..n {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        }
+
+        // 1% of the index.
+        let allowed_ids: Vec<usize> = (0..n).step_by(100).collect();
+        let mut found = 0;
+        for q in (0..n).step_by(n / 10) {
+            let query = &data[q * d..(q + 1) * d];
+            let (ids, distances) = index.query(query, k, &allowed_ids, &[]);
+            assert_eq!(ids.len(), k);
+            assert!(ids.iter().all(|id| id % 100 == 0));
+            assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+
+            let expected = DistanceFunction::Euclidean.top_k(
+                query,
+                allowed_ids
+                    .iter()
+                    .map(|&i| (i as u32, &data[i * d..(i + 1) * d])),
+                k,
+            );
+            found += expected
+                .iter()
+                .filter(|(id, _)| ids.contains(&(*id as usize)))
+                .count();
+        }
+        let recall = found as f32 / (k * 10) as f32;
+        assert!(recall >= 0.9, "recall {} with a 1% filter", recall);
+    }
+
+    #[test]
+    fn it_validates_ef_search() {
+        assert_eq!(validate_ef_search(10, 10), Ok(10));
+        assert_eq!(
+            validate_ef_search(MAX_EF_SEARCH as i32, 10),
+            Ok(MAX_EF_SEARCH)
+        );
+        assert_eq!(
+            validate_ef_search(5, 10),
+            Err(HnswEfSearchError::SmallerThanK {
+                ef_search: 5,
+                k: 10
+            })
+        );
+        assert_eq!(
+            validate_ef_search(-1, 0),
+            Err(HnswEfSearchError::SmallerThanK {
+                ef_search: -1,
+                k: 0
+            })
+        );
+        assert!(matches!(
+            validate_ef_search(MAX_EF_SEARCH as i32 + 1, 10),
+            Err(HnswEfSearchError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn it_grows_past_its_initial_capacity() {
+        let (n, d) = (300, 16);
+        let tmp_dir = tempdir().unwrap();
+        let index = small_index(tmp_dir.path().to_str().unwrap(), 10, d);
+        let data: Vec<f32> = utils::generate_random_data(n, d);
+        for i in 0..n {
+            HnswIndex::add_growing(&index, i, &data[i * d..(i + 1) * d], 2.0);
+        }
+        let index = index.read();
+        assert_eq!(index.len(), n);
+        // 10 doubled five times.
+        assert_eq!(index.capacity(), 320);
+
+        let k = 10;
+        let mut found = 0;
+        for q in (0..n).step_by(15) {
+            let query = &data[q * d..(q + 1) * d];
+            let mut expected: Vec<(usize, f32)> = (0..n)
+                .map(|i| {
+                    let distance =
+                        DistanceFunction::Euclidean.distance(query, &data[i * d..(i + 1) * d]);
+                    (i, distance)
+                })
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+            let expected: Vec<usize> = expected.iter().take(k).map(|(i, _)| *i).collect();
+            let (ids, _) = index.query(query, k, &[], &[]);
+            found += ids.iter().filter(|id| expected.contains(id)).count();
+        }
+        let recall = found as f32 / (k * (0..n).step_by(15).count()) as f32;
+        assert!(recall >= 0.95, "recall {} on the grown index", recall);
+    }
+
+    #[test]
+    fn it_ignores_deletes_of_missing_ids() {
+        let tmp_dir = tempdir().unwrap();
+        let index = small_index(tmp_dir.path().to_str().unwrap(), 10, 4);
+        let index = index.read();
+        for i in 0..3 {
+            index.add(i, &[i as f32; 4]);
+        }
+        // Never added.
+        index.delete(7);
+        index.delete(1);
+        // Already deleted.
+        index.delete(1);
+        assert_eq!(index.len(), 2);
+        let (ids, _) = index.query(&[1.0; 4], 3, &[], &[]);
+        assert_eq!(ids.len(), 2);
+        assert!(!ids.contains(&1));
+    }
+
+    #[test]
+    fn it_grows_when_deleted_elements_fill_it() {
+        let d = 4;
+        let tmp_dir = tempdir().unwrap();
+        let index = small_index(tmp_dir.path().to_str().unwrap(), 10, d);
+        for i in 0..10 {
+            HnswIndex::add_growing(&index, i, &[i as f32; 4], 2.0);
+        }
+        for i in 0..5 {
+            index.read().delete(i);
+        }
+        // The deleted elements still take up their slots, so these need more room even
+        // though the index only holds five elements.
+        for i in 10..15 {
+            HnswIndex::add_growing(&index, i, &[i as f32; 4], 2.0);
+        }
+        assert_eq!(index.read().len(), 10);
+        assert_eq!(index.read().capacity(), 20);
+    }
+
+    #[test]
+    fn it_persists_its_grown_capacity() {
+        let d = 4;
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap();
+        let index = small_index(persist_path, 10, d);
+        for i in 0..25 {
+            HnswIndex::add_growing(&index, i, &[i as f32; 4], 1.5);
+        }
+        let index = index.into_inner();
+        // 10 grown by 1.5x three times, rounding up to 15, 23 and 35.
+        assert_eq!(index.capacity(), 35);
+        index.save().unwrap();
+
+        let index_config = IndexConfig {
+            dimensionality: d as i32,
+            distance_function: DistanceFunction::Euclidean,
+        };
+        let loaded = HnswIndex::load(persist_path, &index_config, index.id).unwrap();
+        assert_eq!(loaded.capacity(), 35);
+        assert_eq!(loaded.len(), 25);
+    }
+
     #[test]
     fn it_can_add_and_query_with_allowed_and_disallowed_ids() {
         let n = 1000;
@@ -732,6 +1613,80 @@ pub mod test {
         assert_eq!(distances.len(), 2);
     }
 
+    fn new_concurrent_index(d: usize, max_elements: usize, persist_path: &Path) -> HnswIndex {
+        HnswIndex::init(
+            &IndexConfig {
+                dimensionality: d as i32,
+                distance_function: DistanceFunction::Euclidean,
+            },
+            Some(&HnswIndexConfig {
+                max_elements,
+                m: 16,
+                ef_construction: 100,
+                ef_search: 100,
+                random_seed: 0,
+                persist_path: persist_path.to_str().unwrap().to_string(),
+            }),
+            Uuid::new_v4(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn it_serves_queries_while_it_is_written() {
+        let (n, d, k) = (4000, 16, 10);
+        let writers = 4;
+        let tmp_dir = tempdir().unwrap();
+        // A small capacity, so that the index is resized while it is being queried.
+        let index = RwLock::new(new_concurrent_index(d, 100, tmp_dir.path()));
+        let data = utils::generate_random_data(n, d);
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            let queriers: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        while !done.load(Ordering::Acquire) {
+                            let (ids, distances) = index.read().query(&data[..d], k, &[], &[]);
+                            assert!(ids.len() <= k);
+                            assert_eq!(ids.len(), distances.len());
+                            assert!(ids.iter().all(|id| *id < n));
+                            let unique: HashSet<usize> = ids.iter().copied().collect();
+                            assert_eq!(unique.len(), ids.len());
+                        }
+                    })
+                })
+                .collect();
+            let writer_handles: Vec<_> = (0..writers)
+                .map(|writer| {
+                    let (index, data) = (&index, &data);
+                    s.spawn(move || {
+                        for i in (writer..n).step_by(writers) {
+                            HnswIndex::add_growing(index, i, &data[i * d..(i + 1) * d], 2.0);
+                        }
+                    })
+                })
+                .collect();
+            for writer in writer_handles {
+                writer.join().unwrap();
+            }
+            done.store(true, Ordering::Release);
+            for querier in queriers {
+                querier.join().unwrap();
+            }
+        });
+
+        // Every add made it into the index exactly once.
+        let index = index.into_inner();
+        assert_eq!(index.len(), n);
+        for i in 0..n {
+            assert_eq!(index.get(i).unwrap(), &data[i * d..(i + 1) * d]);
+        }
+        let (ids, _) = index.query(&data[..d], 100, &[], &[]);
+        let unique: HashSet<usize> = ids.iter().copied().collect();
+        assert_eq!(unique.len(), 100);
+    }
+
     #[test]
     fn it_can_resize() {
         let n = 1000;
@@ -781,4 +1736,114 @@ pub mod test {
             index.add(ids[i], data);
         });
     }
+
+    #[test]
+    fn it_matches_brute_force_for_inner_product() {
+        let n = 200;
+        let d: usize = 16;
+        let distance_function = DistanceFunction::InnerProduct;
+        let tmp_dir = tempdir().unwrap();
+        let persist_path = tmp_dir.path().to_str().unwrap().to_string();
+        let index = HnswIndex::init(
+            &IndexConfig {
+                dimensionality: d as i32,
+                distance_function: distance_function.clone(),
+            },
+            Some(&HnswIndexConfig {
+                max_elements: n,
+                m: 16,
+                ef_construction: 200,
+                ef_search: 200,
+                random_seed: 0,
+                persist_path: persist_path,
+            }),
+            Uuid::new_v4(),
+        );
+
+        let index = match index {
+            Err(e) => panic!("Error initializing index: {}", e),
+            Ok(index) => index,
+        };
+
+        // Center the data around zero so that both positive and negative dot
+        // products are exercised.
+        let data: Vec<f32> = utils::generate_random_data(n, d)
+            .iter()
+            .map(|x| x - 0.5)
+            .collect();
+        (0..n).into_iter().for_each(|i| {
+            index.add(i, &data[i * d..(i + 1) * d]);
+        });
+
+        let k = 10;
+        let queries = utils::generate_random_data(5, d);
+        for q in 0..5 {
+            let query: Vec<f32> = queries[q * d..(q + 1) * d]
+                .iter()
+                .map(|x| x - 0.5)
+                .collect();
+            let (ids, distances) = index.query(&query, k, &[], &[]);
+
+            let mut expected: Vec<(usize, f32)> = (0..n)
+                .map(|i| {
+                    (
+                        i,
+                        distance_function.distance(&query, &data[i * d..(i + 1) * d]),
+                    )
+                })
+                .collect();
+            expected.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+            assert_eq!(ids.len(), k);
+            for i in 0..k {
+                assert_eq!(ids[i], expected[i].0);
+                assert!((distances[i] - expected[i].1).abs() < 1e-4);
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(Config::with_cases(10))]
+        #[test]
+        fn it_matches_brute_force_for_l2(
+            data in proptest::collection::vec(proptest::collection::vec(-10.0f32..10.0, 8), 10..50),
+            query in proptest::collection::vec(-10.0f32..10.0, 8),
+        ) {
+            let n = data.len();
+            let distance_function = DistanceFunction::Euclidean;
+            let tmp_dir = tempdir().unwrap();
+            let index = HnswIndex::init(
+                &IndexConfig {
+                    dimensionality: 8,
+                    distance_function: distance_function.clone(),
+                },
+                Some(&HnswIndexConfig {
+                    max_elements: n,
+                    m: 16,
+                    ef_construction: 100,
+                    ef_search: 100,
+                    random_seed: 0,
+                    persist_path: tmp_dir.path().to_str().unwrap().to_string(),
+                }),
+                Uuid::new_v4(),
+            )
+            .unwrap();
+            for (i, vector) in data.iter().enumerate() {
+                index.add(i, vector);
+            }
+
+            let k = std::cmp::min(n, 5);
+            let (ids, distances) = index.query(&query, k, &[], &[]);
+            prop_assert_eq!(ids.len(), k);
+            for i in 0..k {
+                // hnswlib reports squared l2, so the brute force score for the same
+                // element must match it.
+                let brute_force = distance_function.distance(&query, &data[ids[i]]);
+                prop_assert!((brute_force - distances[i]).abs() <= 1e-3 * (1.0 + brute_force));
+                if i > 0 {
+                    prop_assert!(distances[i - 1] <= distances[i]);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,279 @@
+use crate::distance::DistanceFunction;
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::types::{Metadata, MetadataValue};
+use thiserror::Error;
+
+pub(crate) const DEFAULT_HNSW_SPACE: DistanceFunction = DistanceFunction::Euclidean;
+pub(crate) const DEFAULT_HNSW_M: usize = 16;
+pub(crate) const DEFAULT_HNSW_EF_CONSTRUCTION: usize = 100;
+pub(crate) const DEFAULT_HNSW_EF_SEARCH: usize = 10;
+pub(crate) const DEFAULT_HNSW_NUM_THREADS: usize = 1;
+
+const MIN_HNSW_M: i32 = 2;
+const MAX_HNSW_M: i32 = 2048;
+
+/// The HNSW construction parameters of a collection, read from its metadata.
+/// # Fields
+/// - space: The distance function, from `hnsw:space`. Defaults to l2.
+/// - m: The number of links per node, from `hnsw:M`. Must be in 2..=2048. Defaults to 16.
+/// - ef_construction: The size of the candidate list used while building the graph, from
+/// `hnsw:construction_ef`. Must be at least 1. Defaults to 100.
+/// - ef_search: The size of the candidate list used while querying, from `hnsw:search_ef`.
+/// Must be at least 1. Defaults to 10.
+/// - num_threads: The number of threads to build the index with, from `hnsw:num_threads`.
+/// Must be at least 1. Defaults to 1.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct HnswIndexParams {
+    pub(crate) space: DistanceFunction,
+    pub(crate) m: usize,
+    pub(crate) ef_construction: usize,
+    pub(crate) ef_search: usize,
+    pub(crate) num_threads: usize,
+}
+
+impl Default for HnswIndexParams {
+    fn default() -> Self {
+        HnswIndexParams {
+            space: DEFAULT_HNSW_SPACE,
+            m: DEFAULT_HNSW_M,
+            ef_construction: DEFAULT_HNSW_EF_CONSTRUCTION,
+            ef_search: DEFAULT_HNSW_EF_SEARCH,
+            num_threads: DEFAULT_HNSW_NUM_THREADS,
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum HnswIndexParamsError {
+    #[error("Invalid `hnsw:space` `{0}`, expected one of l2, cosine or ip")]
+    InvalidSpace(String),
+    #[error("Invalid `{key}`, expected {expected}")]
+    InvalidType {
+        key: &'static str,
+        expected: &'static str,
+    },
+    #[error("Invalid `{key}` {value}, expected a value between {min} and {max}")]
+    OutOfRange {
+        key: &'static str,
+        value: i32,
+        min: i32,
+        max: i32,
+    },
+}
+
+impl ChromaError for HnswIndexParamsError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::InvalidArgument
+    }
+}
+
+// Returns the integer value of `key`, or `default` if it is not set. The value has to be in
+// min..=max; hnswlib does not check its parameters and fails in unhelpful ways on bad ones.
+fn get_int_param(
+    metadata: &Metadata,
+    key: &'static str,
+    min: i32,
+    max: i32,
+    default: usize,
+) -> Result<usize, HnswIndexParamsError> {
+    let value = match metadata.get(key) {
+        Some(MetadataValue::Int(value)) => *value,
+        Some(_) => {
+            return Err(HnswIndexParamsError::InvalidType {
+                key,
+                expected: "an integer",
+            })
+        }
+        None => return Ok(default),
+    };
+    if value < min || value > max {
+        return Err(HnswIndexParamsError::OutOfRange {
+            key,
+            value,
+            min,
+            max,
+        });
+    }
+    Ok(value as usize)
+}
+
+impl TryFrom<&Metadata> for HnswIndexParams {
+    type Error = HnswIndexParamsError;
+
+    fn try_from(metadata: &Metadata) -> Result<Self, Self::Error> {
+        let space = match metadata.get("hnsw:space") {
+            Some(MetadataValue::Str(space)) => match DistanceFunction::try_from(space.as_str()) {
+                Ok(distance_function) if distance_function.supported_by_hnsw() => distance_function,
+                _ => return Err(HnswIndexParamsError::InvalidSpace(space.clone())),
+            },
+            Some(_) => {
+                return Err(HnswIndexParamsError::InvalidType {
+                    key: "hnsw:space",
+                    expected: "a string",
+                })
+            }
+            None => DEFAULT_HNSW_SPACE,
+        };
+        Ok(HnswIndexParams {
+            space,
+            m: get_int_param(metadata, "hnsw:M", MIN_HNSW_M, MAX_HNSW_M, DEFAULT_HNSW_M)?,
+            ef_construction: get_int_param(
+                metadata,
+                "hnsw:construction_ef",
+                1,
+                i32::MAX,
+                DEFAULT_HNSW_EF_CONSTRUCTION,
+            )?,
+            ef_search: get_int_param(
+                metadata,
+                "hnsw:search_ef",
+                1,
+                i32::MAX,
+                DEFAULT_HNSW_EF_SEARCH,
+            )?,
+            num_threads: get_int_param(
+                metadata,
+                "hnsw:num_threads",
+                1,
+                i32::MAX,
+                DEFAULT_HNSW_NUM_THREADS,
+            )?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(entries: Vec<(&str, MetadataValue)>) -> Metadata {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect()
+    }
+
+    #[test]
+    fn it_applies_defaults_for_missing_keys() {
+        let params = HnswIndexParams::try_from(&Metadata::new()).unwrap();
+        assert_eq!(params, HnswIndexParams::default());
+
+        let params = HnswIndexParams::try_from(&metadata(vec![
+            ("hnsw:space", MetadataValue::Str("cosine".to_string())),
+            ("hnsw:M", MetadataValue::Int(32)),
+        ]))
+        .unwrap();
+        assert_eq!(params.space, DistanceFunction::Cosine);
+        assert_eq!(params.m, 32);
+        assert_eq!(params.ef_construction, DEFAULT_HNSW_EF_CONSTRUCTION);
+        assert_eq!(params.ef_search, DEFAULT_HNSW_EF_SEARCH);
+        assert_eq!(params.num_threads, DEFAULT_HNSW_NUM_THREADS);
+    }
+
+    #[test]
+    fn it_reads_all_keys() {
+        let params = HnswIndexParams::try_from(&metadata(vec![
+            ("hnsw:space", MetadataValue::Str("ip".to_string())),
+            ("hnsw:M", MetadataValue::Int(2048)),
+            ("hnsw:construction_ef", MetadataValue::Int(200)),
+            ("hnsw:search_ef", MetadataValue::Int(1)),
+            ("hnsw:num_threads", MetadataValue::Int(4)),
+        ]))
+        .unwrap();
+        assert_eq!(
+            params,
+            HnswIndexParams {
+                space: DistanceFunction::InnerProduct,
+                m: 2048,
+                ef_construction: 200,
+                ef_search: 1,
+                num_threads: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_spaces() {
+        for space in ["cosin", "L2", "l1", "hamming", "jaccard"] {
+            let err = HnswIndexParams::try_from(&metadata(vec![(
+                "hnsw:space",
+                MetadataValue::Str(space.to_string()),
+            )]))
+            .unwrap_err();
+            assert_eq!(err, HnswIndexParamsError::InvalidSpace(space.to_string()));
+            assert_eq!(err.code(), ErrorCodes::InvalidArgument);
+        }
+        let err = HnswIndexParams::try_from(&metadata(vec![("hnsw:space", MetadataValue::Int(2))]))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            HnswIndexParamsError::InvalidType {
+                key: "hnsw:space",
+                expected: "a string"
+            }
+        );
+        assert_eq!(
+            HnswIndexParamsError::InvalidSpace("cosin".to_string()).to_string(),
+            "Invalid `hnsw:space` `cosin`, expected one of l2, cosine or ip"
+        );
+    }
+
+    #[test]
+    fn it_rejects_out_of_range_values() {
+        for (key, value, min, max) in [
+            ("hnsw:M", -16, MIN_HNSW_M, MAX_HNSW_M),
+            ("hnsw:M", 1, MIN_HNSW_M, MAX_HNSW_M),
+            ("hnsw:M", 2049, MIN_HNSW_M, MAX_HNSW_M),
+            ("hnsw:construction_ef", 0, 1, i32::MAX),
+            ("hnsw:search_ef", -1, 1, i32::MAX),
+            ("hnsw:num_threads", 0, 1, i32::MAX),
+        ] {
+            let err = HnswIndexParams::try_from(&metadata(vec![(key, MetadataValue::Int(value))]))
+                .unwrap_err();
+            assert_eq!(
+                err,
+                HnswIndexParamsError::OutOfRange {
+                    key,
+                    value,
+                    min,
+                    max
+                }
+            );
+            assert_eq!(err.code(), ErrorCodes::InvalidArgument);
+        }
+        assert_eq!(
+            HnswIndexParamsError::OutOfRange {
+                key: "hnsw:M",
+                value: -16,
+                min: 2,
+                max: 2048
+            }
+            .to_string(),
+            "Invalid `hnsw:M` -16, expected a value between 2 and 2048"
+        );
+    }
+
+    #[test]
+    fn it_rejects_values_of_the_wrong_type() {
+        for key in [
+            "hnsw:M",
+            "hnsw:construction_ef",
+            "hnsw:search_ef",
+            "hnsw:num_threads",
+        ] {
+            for value in [
+                MetadataValue::Float(16.0),
+                MetadataValue::Str("16".to_string()),
+            ] {
+                let err = HnswIndexParams::try_from(&metadata(vec![(key, value)])).unwrap_err();
+                assert_eq!(
+                    err,
+                    HnswIndexParamsError::InvalidType {
+                        key,
+                        expected: "an integer"
+                    }
+                );
+            }
+        }
+    }
+}
@@ -1,57 +1,355 @@
+use super::config::HnswProviderConfig;
 use super::{
-    HnswIndex, HnswIndexConfig, HnswIndexFromSegmentError, Index, IndexConfig,
-    IndexConfigFromSegmentError,
+    BruteForceIndex, BruteForceIndexConfig, HnswIndex, HnswIndexConfig, HnswIndexFromSegmentError,
+    HnswIndexLoadError, HnswIndexStats, Index, IndexConfig, IndexConfigFromSegmentError,
+    HNSW_INDEX_FILES, HNSW_METADATA_FILE,
 };
 use crate::errors::ErrorCodes;
 use crate::index::types::PersistentIndex;
-use crate::{errors::ChromaError, storage::Storage, types::Segment};
-use parking_lot::RwLock;
+use crate::storage::{GetError, Storage};
+use crate::tracing::metrics::metrics;
+use crate::{errors::ChromaError, types::Segment};
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::{collections::HashMap, path::PathBuf, sync::Arc};
 use thiserror::Error;
+use tokio::io::AsyncReadExt;
 use uuid::Uuid;
 
-// These are the files hnswlib writes to disk. This is strong coupling, but we need to know
-// what files to read from disk. We could in the future have the C++ code return the files
-// but ideally we have a rust implementation of hnswlib
-const FILES: [&'static str; 4] = [
-    "header.bin",
-    "data_level0.bin",
-    "length.bin",
-    "link_lists.bin",
-];
+// Every file that makes up a saved index, and so has to be moved to and from storage.
+fn index_files() -> impl Iterator<Item = &'static str> {
+    HNSW_INDEX_FILES
+        .into_iter()
+        .chain(std::iter::once(HNSW_METADATA_FILE))
+}
+
+// An index in the cache of the provider, with what it takes to evict it.
+struct CachedIndex {
+    index: Arc<RwLock<HnswIndex>>,
+    // The estimated size of the index when it was cached or last saved.
+    size: AtomicUsize,
+    // When the index was last handed out, in ticks of the clock of the cache.
+    last_used: AtomicU64,
+    // Whether the index may hold adds that were not saved to its directory yet. Indices are
+    // only written to by the compactor, which creates or forks them to do so.
+    dirty: AtomicBool,
+}
+
+impl CachedIndex {
+    // Whether anything but the cache holds the index, like a query reading it or a
+    // compaction writing to it. Those hold it for as long as they use it, so an index that
+    // is only held by the cache is not in use.
+    fn is_pinned(&self) -> bool {
+        Arc::strong_count(&self.index) > 1
+    }
+}
+
+#[derive(Default)]
+struct IndexCache {
+    indices: HashMap<Uuid, CachedIndex>,
+    clock: AtomicU64,
+}
+
+impl IndexCache {
+    fn get(&self, id: &Uuid) -> Option<Arc<RwLock<HnswIndex>>> {
+        let cached = self.indices.get(id)?;
+        cached.last_used.store(
+            self.clock.fetch_add(1, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        Some(cached.index.clone())
+    }
+
+    fn size(&self) -> usize {
+        self.indices
+            .values()
+            .map(|cached| cached.size.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    fn remove(&mut self, id: &Uuid, reason: &'static str) {
+        if let Some(cached) = self.indices.remove(id) {
+            metrics().record_index_cache_eviction(reason);
+            metrics().record_index_cache_size_change(-(cached.size.load(Ordering::Relaxed) as i64));
+        }
+    }
+}
+
+/// The stats of an index, along with how the provider holds it.
+/// # Fields
+/// - `cached` - Whether the index is loaded in the cache. The stats of one that is not are
+/// those it was last flushed with.
+/// - `dirty` - Whether the cached index may hold adds that were not saved yet.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct IndexStats {
+    pub(crate) index: HnswIndexStats,
+    pub(crate) cached: bool,
+    pub(crate) dirty: bool,
+}
 
 #[derive(Clone)]
 pub(crate) struct HnswIndexProvider {
-    cache: Arc<RwLock<HashMap<Uuid, Arc<RwLock<HnswIndex>>>>>,
+    cache: Arc<RwLock<IndexCache>>,
+    // A lock for each index that is being opened, so that concurrent misses of an index wait
+    // for a single load into its directory rather than each downloading into it.
+    opening: Arc<Mutex<HashMap<Uuid, Arc<tokio::sync::Mutex<()>>>>>,
+    // Brute force indices stand in for the HNSW index of the same id in small segments.
+    brute_force_cache: Arc<RwLock<HashMap<Uuid, Arc<BruteForceIndex>>>>,
     pub(crate) temporary_storage_path: PathBuf,
     storage: Storage,
+    pub(crate) capacity_growth_factor: f64,
+    pub(crate) brute_force_threshold: usize,
+    memory_budget_bytes: Option<usize>,
 }
 
 impl Debug for HnswIndexProvider {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let indices = self.cache.read().indices.len();
         write!(
             f,
-            "HnswIndexProvider {{ temporary_storage_path: {:?}, cache: {} }}",
+            "HnswIndexProvider {{ temporary_storage_path: {:?}, cache: {} indices of {} bytes }}",
             self.temporary_storage_path,
-            self.cache.read().len(),
+            indices,
+            self.cache_size(),
         )
     }
 }
 
 impl HnswIndexProvider {
-    pub(crate) fn new(storage: Storage, storage_path: PathBuf) -> Self {
+    pub(crate) fn new(
+        storage: Storage,
+        storage_path: PathBuf,
+        config: &HnswProviderConfig,
+    ) -> Self {
         Self {
-            cache: Arc::new(RwLock::new(HashMap::new())),
+            cache: Arc::new(RwLock::new(IndexCache::default())),
+            opening: Arc::new(Mutex::new(HashMap::new())),
+            brute_force_cache: Arc::new(RwLock::new(HashMap::new())),
             storage,
             temporary_storage_path: storage_path,
+            capacity_growth_factor: config.capacity_growth_factor,
+            brute_force_threshold: config.brute_force_threshold,
+            memory_budget_bytes: config.memory_budget_bytes,
         }
     }
 
     pub(crate) fn get(&self, id: &Uuid) -> Option<Arc<RwLock<HnswIndex>>> {
-        let cache = self.cache.read();
-        cache.get(id).cloned()
+        self.cache.read().get(id)
+    }
+
+    /// The estimated size of the cached indices.
+    pub(crate) fn cache_size(&self) -> usize {
+        self.cache.read().size()
+    }
+
+    /// Drops the index `id` from the cache, once a newer version of its segment has been
+    /// flushed and new queries read the index of that one instead.
+    /// # Notes
+    /// Queries that still read the older version keep the index they opened until they are
+    /// done with it. One that opens it after it has been retired loads it again.
+    pub(crate) fn retire(&self, id: &Uuid) {
+        self.cache.write().remove(id, "retired");
+        self.brute_force_cache.write().remove(id);
+    }
+
+    // Caches `index` under `id`, `dirty` if it is going to be written to. An index that was
+    // already cached under `id`, like one a rebuild replaces, is evicted.
+    fn cache_index(&self, id: Uuid, index: Arc<RwLock<HnswIndex>>, dirty: bool) {
+        let size = index.read().estimated_size();
+        {
+            let mut cache = self.cache.write();
+            cache.remove(&id, "replaced");
+            let last_used = cache.clock.fetch_add(1, Ordering::Relaxed);
+            cache.indices.insert(
+                id,
+                CachedIndex {
+                    index,
+                    size: AtomicUsize::new(size),
+                    last_used: AtomicU64::new(last_used),
+                    dirty: AtomicBool::new(dirty),
+                },
+            );
+            metrics().record_index_cache_size_change(size as i64);
+        }
+        self.evict_over_budget();
+    }
+
+    // Evicts the least recently used indices that are not in use until the cache is within
+    // its memory budget. A dirty index is saved to its directory before it is evicted, and
+    // kept if that fails. Saving a large index takes a while, so it is done without holding
+    // the lock of the cache, and the index is only evicted on the next pass, if it is still
+    // the least recently used one. The cache stays over budget while the indices in use
+    // don't fit.
+    fn evict_over_budget(&self) {
+        let memory_budget_bytes = match self.memory_budget_bytes {
+            Some(memory_budget_bytes) => memory_budget_bytes,
+            None => return,
+        };
+        let mut unsaved = HashSet::new();
+        loop {
+            let (id, index) = {
+                let mut cache = self.cache.write();
+                loop {
+                    if cache.size() <= memory_budget_bytes {
+                        return;
+                    }
+                    let least_recently_used = cache
+                        .indices
+                        .iter()
+                        .filter(|(id, cached)| !cached.is_pinned() && !unsaved.contains(*id))
+                        .min_by_key(|(_, cached)| cached.last_used.load(Ordering::Relaxed))
+                        .map(|(id, cached)| (*id, cached));
+                    match least_recently_used {
+                        Some((id, cached)) if cached.dirty.load(Ordering::Relaxed) => {
+                            break (id, cached.index.clone());
+                        }
+                        Some((id, _)) => {
+                            println!("Evicting hnsw index {} from the cache", id);
+                            cache.remove(&id, "budget");
+                        }
+                        None => {
+                            // TODO: switch to logging when logging is implemented
+                            println!(
+                                "Hnsw index cache holds {} bytes, over its budget of {}, but every index is in use",
+                                cache.size(),
+                                memory_budget_bytes
+                            );
+                            return;
+                        }
+                    }
+                }
+            };
+            if let Err(e) = index.read().save() {
+                println!("Failed to save hnsw index {} before evicting it: {}", id, e);
+                unsaved.insert(id);
+                continue;
+            }
+            let cache = self.cache.read();
+            // Unless a rebuild replaced it while it was saved.
+            if let Some(cached) = cache.indices.get(&id) {
+                if Arc::ptr_eq(&cached.index, &index) {
+                    self.mark_saved(&cache, &id);
+                }
+            }
+        }
+    }
+
+    // Marks the index `id` as saved, and updates its size, which adds may have grown.
+    fn mark_saved(&self, cache: &IndexCache, id: &Uuid) {
+        if let Some(cached) = cache.indices.get(id) {
+            cached.dirty.store(false, Ordering::Relaxed);
+            let size = cached.index.read().estimated_size();
+            let previous = cached.size.swap(size, Ordering::Relaxed);
+            metrics().record_index_cache_size_change(size as i64 - previous as i64);
+        }
+    }
+
+    /// Describes the index `id`, as it is in the cache or otherwise as it was flushed.
+    /// # Notes
+    /// An index that is not cached is described by the metadata saved with it alone, so
+    /// asking for its stats neither loads its graph nor caches it. Cached ones are not
+    /// marked as used either, so this does not change which index is evicted next.
+    pub(crate) async fn stats(
+        &self,
+        id: &Uuid,
+    ) -> Result<IndexStats, Box<HnswIndexProviderStatsError>> {
+        if let Some(cached) = self.cache.read().indices.get(id) {
+            return Ok(IndexStats {
+                index: cached.index.read().stats(),
+                cached: true,
+                dirty: cached.dirty.load(Ordering::Relaxed),
+            });
+        }
+        let key = self.format_key(id, HNSW_METADATA_FILE);
+        let mut metadata = Vec::new();
+        match self.storage.get(&key).await {
+            Ok(mut reader) => {
+                if let Err(e) = reader.read_to_end(&mut metadata).await {
+                    return Err(Box::new(HnswIndexProviderStatsError::FileError(
+                        HnswIndexProviderFileError::IOError(e),
+                    )));
+                }
+            }
+            Err(GetError::NoSuchKey(_)) => {
+                return Err(Box::new(HnswIndexProviderStatsError::NoIndexFound(*id)));
+            }
+            Err(e) => {
+                return Err(Box::new(HnswIndexProviderStatsError::FileError(
+                    HnswIndexProviderFileError::StorageGetError(e),
+                )));
+            }
+        }
+        match HnswIndexStats::from_saved_metadata(&metadata) {
+            Ok(index) => Ok(IndexStats {
+                index,
+                cached: false,
+                dirty: false,
+            }),
+            Err(e) => Err(Box::new(HnswIndexProviderStatsError::InvalidMetadata(e))),
+        }
+    }
+
+    /// Loads the index `id` from the files flushed to storage, as a query that does not find
+    /// it cached would, and returns its stats.
+    /// # Notes
+    /// The index is loaded into a directory of its own and dropped again, so neither the
+    /// cache nor the files of an index that is cached are touched, and a cached index does
+    /// not hide damage to the files in storage.
+    pub(crate) async fn verify(
+        &self,
+        id: &Uuid,
+        segment: &Segment,
+        dimensionality: i32,
+    ) -> Result<HnswIndexStats, Box<HnswIndexProviderOpenError>> {
+        let verify_path = self
+            .temporary_storage_path
+            .join(format!("verify-{}", Uuid::new_v4()));
+        let res = self
+            .load_uncached(id, segment, dimensionality, &verify_path)
+            .await;
+        if let Err(e) = std::fs::remove_dir_all(&verify_path) {
+            println!("Failed to remove {:?}: {}", verify_path, e);
+        }
+        res.map(|index| index.stats())
+    }
+
+    async fn load_uncached(
+        &self,
+        id: &Uuid,
+        segment: &Segment,
+        dimensionality: i32,
+        path: &PathBuf,
+    ) -> Result<HnswIndex, Box<HnswIndexProviderOpenError>> {
+        if let Err(e) = self.create_dir_all(path) {
+            return Err(Box::new(HnswIndexProviderOpenError::FileError(*e)));
+        }
+        if let Err(e) = self.load_hnsw_segment_into_directory(id, path).await {
+            return Err(Box::new(HnswIndexProviderOpenError::FileError(*e)));
+        }
+        let index_config = match IndexConfig::from_segment(segment, dimensionality) {
+            Ok(index_config) => index_config,
+            Err(e) => {
+                return Err(Box::new(HnswIndexProviderOpenError::IndexConfigError(*e)));
+            }
+        };
+        let path = match path.to_str() {
+            Some(path) => path,
+            None => {
+                return Err(Box::new(HnswIndexProviderOpenError::FileError(
+                    HnswIndexProviderFileError::IOError(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{:?} is not valid unicode", path),
+                    )),
+                )))
+            }
+        };
+        match HnswIndex::load(path, &index_config, *id) {
+            Ok(index) => Ok(index),
+            Err(e) => Err(Box::new(HnswIndexProviderOpenError::IndexLoadError(e))),
+        }
     }
 
     fn format_key(&self, id: &Uuid, file: &str) -> String {
@@ -111,9 +409,10 @@ impl HnswIndexProvider {
 
         match HnswIndex::load(storage_path_str, &index_config, new_id) {
             Ok(index) => {
+                // hnswlib does not persist ef, so loaded indices start at its default.
+                index.set_ef(hnsw_config.ef_search);
                 let index = Arc::new(RwLock::new(index));
-                let mut cache = self.cache.write();
-                cache.insert(new_id, index.clone());
+                self.cache_index(new_id, index.clone(), true);
                 Ok(index)
             }
             Err(e) => Err(Box::new(HnswIndexProviderForkError::IndexLoadError(e))),
@@ -126,43 +425,30 @@ impl HnswIndexProvider {
         index_storage_path: &Path,
     ) -> Result<(), Box<HnswIndexProviderFileError>> {
         // Fetch the files from storage and put them in the index storage path
-        for file in FILES.iter() {
+        for file in index_files() {
             let key = self.format_key(source_id, file);
             println!("Loading hnsw index file: {}", key);
-            let res = self.storage.get(&key).await;
-            let mut reader = match res {
-                Ok(reader) => reader,
-                Err(e) => {
-                    println!("Failed to load hnsw index file from storage: {}", e);
-                    return Err(Box::new(HnswIndexProviderFileError::StorageGetError(e)));
-                }
-            };
-
             let file_path = index_storage_path.join(file);
-            // For now, we never evict from the cache, so if the index is being loaded, the file does not exist
-            let file_handle = tokio::fs::File::create(&file_path).await;
-            let mut file_handle = match file_handle {
-                Ok(file) => file,
-                Err(e) => {
-                    println!("Failed to create file: {}", e);
-                    return Err(Box::new(HnswIndexProviderFileError::IOError(e)));
-                }
-            };
-            let copy_res = tokio::io::copy(&mut reader, &mut file_handle).await;
-            match copy_res {
-                Ok(_) => {
+            // Indices evicted from the cache leave their files behind, which are replaced
+            // with the ones in storage, so that a stale saved index is never loaded. A file is
+            // only replaced once it has been downloaded whole.
+            match self.storage.get_to_file(&key, &file_path).await {
+                Ok(()) => {
                     println!(
                         "Copied storage key: {} to file: {}",
                         key,
                         file_path.to_str().unwrap()
                     );
                 }
-                Err(e) => {
-                    println!("Failed to copy file: {}", e);
+                Err(GetError::Io(e)) => {
+                    println!("Failed to write file: {}", e);
                     return Err(Box::new(HnswIndexProviderFileError::IOError(e)));
                 }
+                Err(e) => {
+                    println!("Failed to load hnsw index file from storage: {}", e);
+                    return Err(Box::new(HnswIndexProviderFileError::StorageGetError(e)));
+                }
             }
-            // bytes is an AsyncBufRead, so we fil and consume it to a file
             println!("Loaded hnsw index file: {}", file);
         }
         Ok(())
@@ -174,51 +460,56 @@ impl HnswIndexProvider {
         segment: &Segment,
         dimensionality: i32,
     ) -> Result<Arc<RwLock<HnswIndex>>, Box<HnswIndexProviderOpenError>> {
-        let index_storage_path = self.temporary_storage_path.join(id.to_string());
-
-        match self.create_dir_all(&index_storage_path) {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(Box::new(HnswIndexProviderOpenError::FileError(*e)));
-            }
+        // Only the first access to an index has to fetch it from storage.
+        if let Some(index) = self.get(id) {
+            metrics().record_index_cache_hit();
+            return Ok(index);
         }
-
-        match self
-            .load_hnsw_segment_into_directory(id, &index_storage_path)
-            .await
-        {
-            Ok(_) => {}
-            Err(e) => {
-                return Err(Box::new(HnswIndexProviderOpenError::FileError(*e)));
+        let opening = self.opening.lock().entry(*id).or_default().clone();
+        let guard = opening.lock().await;
+        let res = match self.get(id) {
+            // Loaded by a concurrent open while this one waited for it.
+            Some(index) => {
+                metrics().record_index_cache_hit();
+                Ok(index)
             }
-        }
-
-        let index_config = IndexConfig::from_segment(&segment, dimensionality);
-        let index_config = match index_config {
-            Ok(index_config) => index_config,
-            Err(e) => {
-                return Err(Box::new(HnswIndexProviderOpenError::IndexConfigError(*e)));
+            None => {
+                metrics().record_index_cache_miss();
+                self.load(id, segment, dimensionality).await
             }
         };
+        // The lock is dropped once no other open waits for it. Opens only clone it while
+        // holding the map, so none can start waiting in between.
+        let mut locks = self.opening.lock();
+        drop(guard);
+        if Arc::strong_count(&opening) == 2 {
+            locks.remove(id);
+        }
+        res
+    }
 
-        let hnsw_config = HnswIndexConfig::from_segment(segment, &index_storage_path);
-        let hnsw_config = match hnsw_config {
+    // Loads the index `id` from storage into its directory and caches it.
+    async fn load(
+        &self,
+        id: &Uuid,
+        segment: &Segment,
+        dimensionality: i32,
+    ) -> Result<Arc<RwLock<HnswIndex>>, Box<HnswIndexProviderOpenError>> {
+        let index_storage_path = self.temporary_storage_path.join(id.to_string());
+        let hnsw_config = match HnswIndexConfig::from_segment(segment, &index_storage_path) {
             Ok(hnsw_config) => hnsw_config,
             Err(e) => {
                 return Err(Box::new(HnswIndexProviderOpenError::HnswConfigError(*e)));
             }
         };
-
-        // TODO: don't unwrap path conv here
-        match HnswIndex::load(index_storage_path.to_str().unwrap(), &index_config, *id) {
-            Ok(index) => {
-                let index = Arc::new(RwLock::new(index));
-                let mut cache = self.cache.write();
-                cache.insert(*id, index.clone());
-                Ok(index)
-            }
-            Err(e) => Err(Box::new(HnswIndexProviderOpenError::IndexLoadError(e))),
-        }
+        let index = self
+            .load_uncached(id, segment, dimensionality, &index_storage_path)
+            .await?;
+        // hnswlib does not persist ef, so loaded indices start at its default.
+        index.set_ef(hnsw_config.ef_search);
+        let index = Arc::new(RwLock::new(index));
+        self.cache_index(*id, index.clone(), false);
+        Ok(index)
     }
 
     // Compactor
@@ -238,7 +529,72 @@ impl HnswIndexProvider {
         segment: &Segment,
         dimensionality: i32,
     ) -> Result<Arc<RwLock<HnswIndex>>, Box<HnswIndexProviderCreateError>> {
-        let id = Uuid::new_v4();
+        self.create_with_embeddings(Uuid::new_v4(), segment, dimensionality, &[])
+    }
+
+    // Builds the index `id` from `embeddings` instead of its saved files, for when those are
+    // missing or corrupted. The index is only cached once it holds every embedding, so that
+    // concurrent readers never see it partially built.
+    pub(crate) fn rebuild(
+        &self,
+        id: &Uuid,
+        segment: &Segment,
+        dimensionality: i32,
+        embeddings: &[(u32, Vec<f32>)],
+    ) -> Result<Arc<RwLock<HnswIndex>>, Box<HnswIndexProviderCreateError>> {
+        self.create_with_embeddings(*id, segment, dimensionality, embeddings)
+    }
+
+    pub(crate) fn get_brute_force(&self, id: &Uuid) -> Option<Arc<BruteForceIndex>> {
+        self.brute_force_cache.read().get(id).cloned()
+    }
+
+    /// Creates a brute force index holding `embeddings` to be queried in place of the HNSW
+    /// index `id`, and caches it under that id.
+    pub(crate) fn create_brute_force(
+        &self,
+        id: &Uuid,
+        segment: &Segment,
+        dimensionality: i32,
+        embeddings: &[(u32, Vec<f32>)],
+    ) -> Result<Arc<BruteForceIndex>, Box<HnswIndexProviderCreateError>> {
+        let index_config = match IndexConfig::from_segment(segment, dimensionality) {
+            Ok(index_config) => index_config,
+            Err(e) => {
+                return Err(Box::new(HnswIndexProviderCreateError::IndexConfigError(*e)));
+            }
+        };
+        let brute_force_config = BruteForceIndexConfig {
+            capacity: embeddings.len(),
+        };
+        let index = match BruteForceIndex::init(&index_config, Some(&brute_force_config), *id) {
+            Ok(index) => index,
+            Err(e) => {
+                return Err(Box::new(HnswIndexProviderCreateError::IndexInitError(e)));
+            }
+        };
+        for (offset_id, embedding) in embeddings {
+            index.add(*offset_id as usize, embedding);
+        }
+        tracing::info!(
+            "Loaded {} records into brute force index {}",
+            index.len(),
+            index.id
+        );
+        let index = Arc::new(index);
+        self.brute_force_cache
+            .write()
+            .insert(index.id, index.clone());
+        Ok(index)
+    }
+
+    fn create_with_embeddings(
+        &self,
+        id: Uuid,
+        segment: &Segment,
+        dimensionality: i32,
+        embeddings: &[(u32, Vec<f32>)],
+    ) -> Result<Arc<RwLock<HnswIndex>>, Box<HnswIndexProviderCreateError>> {
         let index_storage_path = self.temporary_storage_path.join(id.to_string());
 
         match self.create_dir_all(&index_storage_path) {
@@ -262,33 +618,42 @@ impl HnswIndexProvider {
             }
         };
 
-        let mut cache = self.cache.write();
-        let index = match HnswIndex::init(&index_config, Some(&hnsw_config), id) {
+        let mut index = match HnswIndex::init(&index_config, Some(&hnsw_config), id) {
             Ok(index) => index,
             Err(e) => {
                 return Err(Box::new(HnswIndexProviderCreateError::IndexInitError(e)));
             }
         };
+        if embeddings.len() > index.capacity() {
+            index.resize(embeddings.len());
+        }
+        for (offset_id, embedding) in embeddings {
+            index.add(*offset_id as usize, embedding);
+        }
         let index = Arc::new(RwLock::new(index));
-        cache.insert(id, index.clone());
+        self.cache_index(id, index.clone(), true);
         Ok(index)
     }
 
     pub(crate) fn commit(&self, id: &Uuid) -> Result<(), Box<HnswIndexProviderCommitError>> {
         let cache = self.cache.read();
-        let index = match cache.get(id) {
-            Some(index) => index,
+        let index = match cache.indices.get(id) {
+            Some(cached) => &cached.index,
             None => {
                 return Err(Box::new(HnswIndexProviderCommitError::NoIndexFound(*id)));
             }
         };
 
-        match index.write().save() {
+        // Saving only reads the index, so queries keep running while it is written out. Adds,
+        // updates and deletes all take the upgradable lock, so holding it keeps them out for
+        // the duration.
+        match index.upgradable_read().save() {
             Ok(_) => {}
             Err(e) => {
                 return Err(Box::new(HnswIndexProviderCommitError::HnswSaveError(e)));
             }
         }
+        self.mark_saved(&cache, id);
 
         Ok(())
     }
@@ -298,22 +663,23 @@ impl HnswIndexProvider {
         // TODO: since we commit(), we don't need to save the index here
         {
             let cache = self.cache.read();
-            let index = match cache.get(id) {
-                Some(index) => index,
+            let index = match cache.indices.get(id) {
+                Some(cached) => &cached.index,
                 None => {
                     return Err(Box::new(HnswIndexProviderFlushError::NoIndexFound(*id)));
                 }
             };
-            match index.write().save() {
+            match index.upgradable_read().save() {
                 Ok(_) => {}
                 Err(e) => {
                     return Err(Box::new(HnswIndexProviderFlushError::HnswSaveError(e)));
                 }
             };
+            self.mark_saved(&cache, id);
         }
 
         let index_storage_path = self.temporary_storage_path.join(id.to_string());
-        for file in FILES.iter() {
+        for file in index_files() {
             let file_path = index_storage_path.join(file);
             let key = self.format_key(id, file);
             let res = self
@@ -344,14 +710,26 @@ impl HnswIndexProvider {
 pub(crate) enum HnswIndexProviderOpenError {
     #[error("Index configuration error")]
     IndexConfigError(#[from] IndexConfigFromSegmentError),
-    #[error("Hnsw index file error")]
+    #[error("Hnsw index file error: {0}")]
     FileError(#[from] HnswIndexProviderFileError),
-    #[error("Hnsw config error")]
+    #[error("Hnsw config error: {0}")]
     HnswConfigError(#[from] HnswIndexFromSegmentError),
-    #[error("Index load error")]
+    #[error("Index load error: {0}")]
     IndexLoadError(#[from] Box<dyn ChromaError>),
 }
 
+impl HnswIndexProviderOpenError {
+    // Whether the saved index is missing or corrupted, so that rebuilding it from its records
+    // is the only way to serve it again.
+    pub(crate) fn index_files_unusable(&self) -> bool {
+        match self {
+            HnswIndexProviderOpenError::FileError(e) => e.index_files_unusable(),
+            HnswIndexProviderOpenError::IndexLoadError(_) => true,
+            _ => false,
+        }
+    }
+}
+
 impl ChromaError for HnswIndexProviderOpenError {
     fn code(&self) -> ErrorCodes {
         match self {
@@ -369,7 +747,7 @@ pub(crate) enum HnswIndexProviderForkError {
     IndexConfigError(#[from] IndexConfigFromSegmentError),
     #[error("Hnsw index file error")]
     FileError(#[from] HnswIndexProviderFileError),
-    #[error("Hnsw config error")]
+    #[error("Hnsw config error: {0}")]
     HnswConfigError(#[from] HnswIndexFromSegmentError),
     #[error("Index load error")]
     IndexLoadError(#[from] Box<dyn ChromaError>),
@@ -377,6 +755,17 @@ pub(crate) enum HnswIndexProviderForkError {
     PathToStringError(PathBuf),
 }
 
+impl HnswIndexProviderForkError {
+    // See HnswIndexProviderOpenError::index_files_unusable.
+    pub(crate) fn index_files_unusable(&self) -> bool {
+        match self {
+            HnswIndexProviderForkError::FileError(e) => e.index_files_unusable(),
+            HnswIndexProviderForkError::IndexLoadError(_) => true,
+            _ => false,
+        }
+    }
+}
+
 impl ChromaError for HnswIndexProviderForkError {
     fn code(&self) -> ErrorCodes {
         match self {
@@ -395,7 +784,7 @@ pub(crate) enum HnswIndexProviderCreateError {
     IndexConfigError(#[from] IndexConfigFromSegmentError),
     #[error("Hnsw index file error")]
     FileError(#[from] HnswIndexProviderFileError),
-    #[error("Hnsw config error")]
+    #[error("Hnsw config error: {0}")]
     HnswConfigError(#[from] HnswIndexFromSegmentError),
     #[error("Index init error")]
     IndexInitError(#[from] Box<dyn ChromaError>),
@@ -449,16 +838,47 @@ impl ChromaError for HnswIndexProviderFlushError {
     }
 }
 
+#[derive(Error, Debug)]
+pub(crate) enum HnswIndexProviderStatsError {
+    #[error("No index found for id: {0}")]
+    NoIndexFound(Uuid),
+    #[error("Hnsw index file error")]
+    FileError(#[from] HnswIndexProviderFileError),
+    #[error("Invalid hnsw index metadata: {0}")]
+    InvalidMetadata(#[from] HnswIndexLoadError),
+}
+
+impl ChromaError for HnswIndexProviderStatsError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            HnswIndexProviderStatsError::NoIndexFound(_) => ErrorCodes::NotFound,
+            HnswIndexProviderStatsError::FileError(_) => ErrorCodes::Internal,
+            HnswIndexProviderStatsError::InvalidMetadata(e) => e.code(),
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub(crate) enum HnswIndexProviderFileError {
-    #[error("IO Error")]
+    #[error("IO Error: {0}")]
     IOError(#[from] std::io::Error),
-    #[error("Storage Get Error")]
+    #[error("Storage Get Error: {0}")]
     StorageGetError(#[from] crate::storage::GetError),
     #[error("Storage Put Error")]
     StoragePutError(#[from] crate::storage::PutError),
 }
 
+impl HnswIndexProviderFileError {
+    fn index_files_unusable(&self) -> bool {
+        match self {
+            // A transient storage failure says nothing about the files themselves.
+            HnswIndexProviderFileError::StorageGetError(e) => !e.is_retryable(),
+            HnswIndexProviderFileError::IOError(_) => false,
+            HnswIndexProviderFileError::StoragePutError(_) => false,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -477,7 +897,8 @@ mod tests {
 
         let storage = Storage::Local(LocalStorage::new(storage_dir.to_str().unwrap()));
 
-        let provider = HnswIndexProvider::new(storage, hnsw_tmp_path);
+        let provider =
+            HnswIndexProvider::new(storage, hnsw_tmp_path, &HnswProviderConfig::default());
         let segment = Segment {
             id: Uuid::new_v4(),
             r#type: SegmentType::HnswDistributed,
@@ -490,6 +911,8 @@ mod tests {
         let dimensionality = 128;
         let created_index = provider.create(&segment, dimensionality).unwrap();
         let created_index_id = created_index.read().id;
+        // Only saved indices can be forked.
+        provider.commit(&created_index_id).unwrap();
 
         let forked_index = provider
             .fork(&created_index_id, &segment, dimensionality)
@@ -499,4 +922,369 @@ mod tests {
 
         assert_ne!(created_index_id, forked_index_id);
     }
+
+    // A provider over `storage` with its own empty cache and temporary directory, like the
+    // one a freshly started worker has.
+    fn new_provider(storage: &Storage) -> (HnswIndexProvider, tempfile::TempDir) {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let provider = HnswIndexProvider::new(
+            storage.clone(),
+            tmp_dir.path().to_path_buf(),
+            &HnswProviderConfig::default(),
+        );
+        (provider, tmp_dir)
+    }
+
+    fn test_segment() -> Segment {
+        Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::HnswDistributed,
+            scope: crate::types::SegmentScope::VECTOR,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_loads_flushed_index_from_storage() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let segment = test_segment();
+        let dimensionality = 4;
+
+        let (writer, _writer_dir) = new_provider(&storage);
+        let index = writer.create(&segment, dimensionality).unwrap();
+        let id = index.read().id;
+        for i in 0..20 {
+            index.read().add(i, &[i as f32, 1.0, -(i as f32), 0.5]);
+        }
+        writer.flush(&id).await.unwrap();
+
+        let (reader, _reader_dir) = new_provider(&storage);
+        let opened = reader.open(&id, &segment, dimensionality).await.unwrap();
+        let query = [3.0, 1.0, -3.0, 0.5];
+        assert_eq!(
+            opened.read().query(&query, 5, &[], &[]),
+            index.read().query(&query, 5, &[], &[])
+        );
+
+        // Later opens are served from the cache.
+        let reopened = reader.open(&id, &segment, dimensionality).await.unwrap();
+        assert!(Arc::ptr_eq(&opened, &reopened));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_opens_share_one_load() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let segment = test_segment();
+
+        let (writer, _writer_dir) = new_provider(&storage);
+        let index = writer.create(&segment, 4).unwrap();
+        let id = index.read().id;
+        for i in 0..100 {
+            index.read().add(i, &[i as f32, 1.0, -(i as f32), 0.5]);
+        }
+        writer.flush(&id).await.unwrap();
+
+        let (reader, reader_dir) = new_provider(&storage);
+        let opens = (0..8).map(|_| {
+            let reader = reader.clone();
+            let segment = segment.clone();
+            tokio::spawn(async move { reader.open(&id, &segment, 4).await.unwrap() })
+        });
+        let opened = futures::future::try_join_all(opens).await.unwrap();
+        // Each load would have cached an index of its own, replacing the one before.
+        for index in &opened {
+            assert!(Arc::ptr_eq(index, &opened[0]));
+        }
+        assert_eq!(opened[0].read().len(), 100);
+        assert!(reader.opening.lock().is_empty());
+        // Only the index files are left in its directory.
+        let mut files: Vec<_> = std::fs::read_dir(reader_dir.path().join(id.to_string()))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        let mut expected: Vec<_> = index_files().map(str::to_string).collect();
+        expected.sort();
+        assert_eq!(files, expected);
+    }
+
+    #[tokio::test]
+    async fn test_open_reports_missing_files_as_unusable() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let segment = test_segment();
+
+        let (writer, _writer_dir) = new_provider(&storage);
+        let id = writer.create(&segment, 4).unwrap().read().id;
+        writer.flush(&id).await.unwrap();
+
+        // A request for the index with the wrong configuration is not a damaged index.
+        let mut misconfigured = segment.clone();
+        misconfigured.metadata = Some(HashMap::from([(
+            "hnsw:space".to_string(),
+            crate::types::MetadataValue::Str("unknown".to_string()),
+        )]));
+        let (reader, _reader_dir) = new_provider(&storage);
+        match reader.open(&id, &misconfigured, 4).await {
+            Ok(_) => panic!("Expected an unknown distance function to fail"),
+            Err(e) => assert!(!e.index_files_unusable()),
+        }
+
+        std::fs::remove_file(
+            storage_dir
+                .path()
+                .join(writer.format_key(&id, HNSW_METADATA_FILE)),
+        )
+        .unwrap();
+        let (reader, _reader_dir) = new_provider(&storage);
+        match reader.open(&id, &segment, 4).await {
+            Ok(_) => panic!("Expected opening an index with a missing file to fail"),
+            Err(e) => assert!(e.index_files_unusable()),
+        }
+    }
+
+    #[test]
+    fn test_create_rejects_invalid_hnsw_params() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let (provider, _tmp_dir) = new_provider(&storage);
+        let mut segment = test_segment();
+        segment.metadata = Some(HashMap::from([(
+            "hnsw:M".to_string(),
+            crate::types::MetadataValue::Int(-16),
+        )]));
+
+        match provider.create(&segment, 4) {
+            Ok(_) => panic!("Expected a negative hnsw:M to fail"),
+            Err(e) => {
+                assert_eq!(e.code(), ErrorCodes::InvalidArgument);
+                assert_eq!(
+                    e.to_string(),
+                    "Hnsw config error: Invalid `hnsw:M` -16, expected a value between 2 and 2048"
+                );
+            }
+        }
+    }
+
+    // A provider whose cache fits `indices` empty indices of the test segment.
+    fn provider_with_budget(
+        storage: &Storage,
+        indices: f64,
+    ) -> (HnswIndexProvider, tempfile::TempDir) {
+        let (provider, tmp_dir) = new_provider(storage);
+        let size = provider
+            .create(&test_segment(), 4)
+            .unwrap()
+            .read()
+            .estimated_size();
+        let config = HnswProviderConfig {
+            memory_budget_bytes: Some((size as f64 * indices) as usize),
+            ..HnswProviderConfig::default()
+        };
+        let provider =
+            HnswIndexProvider::new(storage.clone(), tmp_dir.path().to_path_buf(), &config);
+        (provider, tmp_dir)
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used_indices() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let (provider, _tmp_dir) = provider_with_budget(&storage, 2.5);
+        let segment = test_segment();
+        let evictions = || {
+            crate::tracing::metrics::test::collected(
+                "index_cache.evictions",
+                &[opentelemetry::KeyValue::new("reason", "budget")],
+            )
+        };
+        let evicted_before = evictions();
+
+        let create = || provider.create(&segment, 4).unwrap().read().id;
+        let a = create();
+        let b = create();
+        let size = provider.cache_size();
+        // a is used after b, so b is the least recently used.
+        provider.get(&a).unwrap();
+        let c = create();
+        assert!(provider.get(&b).is_none());
+        assert_eq!(provider.cache_size(), size);
+        // Then a, which was used before c was cached.
+        let d = create();
+        assert!(provider.get(&a).is_none());
+        assert!(provider.get(&c).is_some());
+        assert!(provider.get(&d).is_some());
+        assert!(evictions() >= evicted_before + 2);
+    }
+
+    #[test]
+    fn test_cache_keeps_indices_in_use() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let (provider, _tmp_dir) = provider_with_budget(&storage, 1.0);
+        let segment = test_segment();
+
+        // A query holds on to a until the end.
+        let a = provider.create(&segment, 4).unwrap();
+        let a_id = a.read().id;
+        // Over the budget, but a and b are both in use.
+        let b = provider.create(&segment, 4).unwrap();
+        let b_id = b.read().id;
+        assert!(provider.get(&b_id).is_some());
+        drop(b);
+        let c = provider.create(&segment, 4).unwrap();
+        assert!(provider.get(&a_id).is_some());
+        assert!(provider.get(&b_id).is_none());
+        drop(c);
+        drop(a);
+        provider.create(&segment, 4).unwrap();
+        assert!(provider.get(&a_id).is_none());
+    }
+
+    #[test]
+    fn test_retired_index_stays_valid_for_its_readers() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let (provider, _tmp_dir) = new_provider(&storage);
+        let segment = test_segment();
+
+        // A query of the old version reads the index while a newer version replaces it.
+        let old = provider.create(&segment, 4).unwrap();
+        let old_id = old.read().id;
+        old.read().add(1, &[1.0, 0.0, 0.0, 0.0]);
+        let new = provider.create(&segment, 4).unwrap();
+        provider.retire(&old_id);
+        assert!(provider.get(&old_id).is_none());
+        assert!(provider.get(&new.read().id).is_some());
+        assert_eq!(old.read().len(), 1);
+        assert_eq!(provider.cache_size(), new.read().estimated_size());
+    }
+
+    #[test]
+    fn test_cache_saves_dirty_indices_before_evicting_them() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let (provider, tmp_dir) = provider_with_budget(&storage, 1.0);
+        let segment = test_segment();
+
+        let index = provider.create(&segment, 4).unwrap();
+        let id = index.read().id;
+        for i in 0..10 {
+            index.read().add(i, &[i as f32, 1.0, 0.0, 0.5]);
+        }
+        drop(index);
+        provider.create(&segment, 4).unwrap();
+        assert!(provider.get(&id).is_none());
+
+        let index_config = IndexConfig::from_segment(&segment, 4).unwrap();
+        let path = tmp_dir.path().join(id.to_string());
+        let saved = HnswIndex::load(path.to_str().unwrap(), &index_config, id).unwrap();
+        assert_eq!(saved.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_stats_of_cached_and_flushed_indices() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let segment = test_segment();
+
+        let (writer, _writer_dir) = new_provider(&storage);
+        let index = writer.create(&segment, 4).unwrap();
+        let id = index.read().id;
+        for i in 0..20 {
+            index.read().add(i, &[i as f32, 1.0, 0.0, 0.5]);
+        }
+        index.read().delete(7);
+        let stats = writer.stats(&id).await.unwrap();
+        assert!(stats.cached);
+        assert!(stats.dirty);
+        assert_eq!(stats.index, index.read().stats());
+        assert_eq!(stats.index.saved_at, None);
+
+        writer.flush(&id).await.unwrap();
+        let flushed = writer.stats(&id).await.unwrap();
+        assert!(!flushed.dirty);
+        assert!(flushed.index.saved_at.is_some());
+
+        // Another worker reads the stats of the flushed index without loading it.
+        let (reader, reader_dir) = new_provider(&storage);
+        let stats = reader.stats(&id).await.unwrap();
+        assert_eq!(
+            stats,
+            IndexStats {
+                index: flushed.index,
+                cached: false,
+                dirty: false,
+            }
+        );
+        assert_eq!(stats.index.element_count, 19);
+        assert_eq!(stats.index.deleted_count, 1);
+        assert!(reader.get(&id).is_none());
+        assert_eq!(std::fs::read_dir(reader_dir.path()).unwrap().count(), 0);
+
+        match reader.stats(&Uuid::new_v4()).await {
+            Ok(_) => panic!("Expected the stats of an unknown index to fail"),
+            Err(e) => assert_eq!(e.code(), ErrorCodes::NotFound),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_loads_the_flushed_files() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let segment = test_segment();
+        let (provider, tmp_dir) = new_provider(&storage);
+        let index = provider.create(&segment, 4).unwrap();
+        let id = index.read().id;
+        for i in 0..10 {
+            index.read().add(i, &[i as f32, 1.0, 0.0, 0.5]);
+        }
+        provider.flush(&id).await.unwrap();
+
+        let stats = provider.verify(&id, &segment, 4).await.unwrap();
+        assert_eq!(stats, index.read().stats());
+        // The verified index is dropped with its directory, the cached one is kept.
+        assert!(Arc::ptr_eq(&provider.get(&id).unwrap(), &index));
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+
+        // Damage to the flushed files is found even though the index is cached.
+        let data_file = storage_dir
+            .path()
+            .join(provider.format_key(&id, "data_level0.bin"));
+        let length = std::fs::metadata(&data_file).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&data_file)
+            .unwrap()
+            .set_len(length / 2)
+            .unwrap();
+        match provider.verify(&id, &segment, 4).await {
+            Ok(_) => panic!("Expected a truncated index to fail to verify"),
+            Err(e) => assert!(e.index_files_unusable()),
+        }
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_rebuild_caches_filled_index() {
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(storage_dir.path().to_str().unwrap()));
+        let (provider, _tmp_dir) = new_provider(&storage);
+        let segment = test_segment();
+        // More embeddings than the default capacity, so that the index has to grow.
+        let embeddings: Vec<(u32, Vec<f32>)> = (0..1500)
+            .map(|i| (i * 2, vec![i as f32, 0.0, 1.0]))
+            .collect();
+
+        let id = Uuid::new_v4();
+        let index = provider.rebuild(&id, &segment, 3, &embeddings).unwrap();
+        assert_eq!(index.read().len(), embeddings.len());
+        let (ids, _) = index.read().query(&[7.0, 0.0, 1.0], 1, &[], &[]);
+        assert_eq!(ids, vec![14]);
+        assert!(Arc::ptr_eq(&provider.get(&id).unwrap(), &index));
+    }
 }
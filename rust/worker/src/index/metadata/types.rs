@@ -7,6 +7,7 @@ use thiserror::Error;
 use uuid::Uuid;
 
 use core::ops::BitOr;
+use futures::{Stream, TryStreamExt};
 use parking_lot::Mutex;
 use roaring::RoaringBitmap;
 use std::collections::HashMap;
@@ -106,20 +107,20 @@ pub(crate) fn process_where_clause_with_callback<
                             }
                         }
                         WhereClauseComparator::NotEqual => {
-                            todo!();
+                            let result = callback(
+                                &direct_where_comparison.key,
+                                &operand.as_str().into(),
+                                MetadataType::StringType,
+                                WhereClauseComparator::NotEqual,
+                            );
+                            results = result.iter().map(|x| x as usize).collect();
                         }
-                        // We don't allow these comparators for strings.
-                        WhereClauseComparator::LessThan => {
-                            unimplemented!();
-                        }
-                        WhereClauseComparator::LessThanOrEqual => {
-                            unimplemented!();
-                        }
-                        WhereClauseComparator::GreaterThan => {
-                            unimplemented!();
-                        }
-                        WhereClauseComparator::GreaterThanOrEqual => {
-                            unimplemented!();
+                        // Strings are not ordered, so no record matches these.
+                        WhereClauseComparator::LessThan
+                        | WhereClauseComparator::LessThanOrEqual
+                        | WhereClauseComparator::GreaterThan
+                        | WhereClauseComparator::GreaterThanOrEqual => {
+                            results = vec![];
                         }
                     }
                 }
@@ -143,20 +144,20 @@ pub(crate) fn process_where_clause_with_callback<
                             }
                         }
                         WhereClauseComparator::NotEqual => {
-                            todo!();
-                        }
-                        // We don't allow these comparators for booleans.
-                        WhereClauseComparator::LessThan => {
-                            unimplemented!();
-                        }
-                        WhereClauseComparator::LessThanOrEqual => {
-                            unimplemented!();
+                            let result = callback(
+                                &direct_where_comparison.key,
+                                &(*operand).into(),
+                                MetadataType::BoolType,
+                                WhereClauseComparator::NotEqual,
+                            );
+                            results = result.iter().map(|x| x as usize).collect();
                         }
-                        WhereClauseComparator::GreaterThan => {
-                            unimplemented!();
-                        }
-                        WhereClauseComparator::GreaterThanOrEqual => {
-                            unimplemented!();
+                        // Booleans are not ordered, so no record matches these.
+                        WhereClauseComparator::LessThan
+                        | WhereClauseComparator::LessThanOrEqual
+                        | WhereClauseComparator::GreaterThan
+                        | WhereClauseComparator::GreaterThanOrEqual => {
+                            results = vec![];
                         }
                     }
                 }
@@ -179,7 +180,13 @@ pub(crate) fn process_where_clause_with_callback<
                         }
                     }
                     WhereClauseComparator::NotEqual => {
-                        todo!();
+                        let result = callback(
+                            &direct_where_comparison.key,
+                            &(*operand).into(),
+                            MetadataType::IntType,
+                            WhereClauseComparator::NotEqual,
+                        );
+                        results = result.iter().map(|x| x as usize).collect();
                     }
                     WhereClauseComparator::LessThan => {
                         let metadata_value_keywrapper = (*operand).try_into();
@@ -269,7 +276,13 @@ pub(crate) fn process_where_clause_with_callback<
                         }
                     }
                     WhereClauseComparator::NotEqual => {
-                        todo!();
+                        let result = callback(
+                            &direct_where_comparison.key,
+                            &(*operand as f32).into(),
+                            MetadataType::DoubleType,
+                            WhereClauseComparator::NotEqual,
+                        );
+                        results = result.iter().map(|x| x as usize).collect();
                     }
                     WhereClauseComparator::LessThan => {
                         let metadata_value_keywrapper = (*operand as f32).try_into();
@@ -626,17 +639,6 @@ impl<'me> MetadataIndexWriter<'me> {
         Ok(())
     }
 
-    pub async fn update(
-        &self,
-        prefix: &str,
-        old_key: KeyWrapper,
-        new_key: KeyWrapper,
-        offset_id: u32,
-    ) -> Result<(), MetadataIndexError> {
-        self.delete(prefix, old_key, offset_id).await?;
-        self.set(prefix, new_key, offset_id).await
-    }
-
     pub async fn write_to_blockfile(&mut self) -> Result<(), MetadataIndexError> {
         match self {
             MetadataIndexWriter::StringMetadataIndexWriter(
@@ -771,6 +773,25 @@ impl MetadataIndexFlusher {
     }
 }
 
+// Unions the bitmaps of the records of a blockfile read whose key passes `keep`, as the
+// blocks that hold them are fetched.
+async fn union_bitmaps<'a, K>(
+    records: impl Stream<Item = Result<(&'a str, K, RoaringBitmap), Box<dyn ChromaError>>>,
+    keep: impl Fn(&K) -> bool,
+) -> Result<RoaringBitmap, MetadataIndexError> {
+    records
+        .try_fold(RoaringBitmap::new(), |result, (_, key, rbm)| {
+            let result = if keep(&key) {
+                result.bitor(&rbm)
+            } else {
+                result
+            };
+            futures::future::ready(Ok(result))
+        })
+        .await
+        .map_err(MetadataIndexError::BlockfileError)
+}
+
 #[derive(Clone)]
 pub(crate) enum MetadataIndexReader<'me> {
     StringMetadataIndexReader(BlockfileReader<'me, &'me str, RoaringBitmap>),
@@ -863,41 +884,73 @@ impl<'me> MetadataIndexReader<'me> {
         }
     }
 
-    pub async fn lt(
+    /// Returns the offset ids of the records that have `metadata_key` set to a value other
+    /// than `metadata_value`. Records without the key, or with a value of another type,
+    /// are not included.
+    pub async fn ne(
         &'me self,
         metadata_key: &str,
         metadata_value: &'me KeyWrapper,
     ) -> Result<RoaringBitmap, MetadataIndexError> {
         match self {
+            MetadataIndexReader::StringMetadataIndexReader(blockfile_reader) => {
+                match metadata_value {
+                    KeyWrapper::String(k) => {
+                        union_bitmaps(blockfile_reader.get_by_prefix(metadata_key), |value| {
+                            *value != k
+                        })
+                        .await
+                    }
+                    _ => Err(MetadataIndexError::InvalidKeyType),
+                }
+            }
             MetadataIndexReader::U32MetadataIndexReader(blockfile_reader) => match metadata_value {
                 KeyWrapper::Uint32(k) => {
-                    let read = blockfile_reader.get_lt(metadata_key, *k).await;
-                    match read {
-                        Ok(records) => {
-                            let mut result = RoaringBitmap::new();
-                            for (_, _, rbm) in records {
-                                result = result.bitor(&rbm);
-                            }
-                            Ok(result)
-                        }
-                        Err(e) => Err(MetadataIndexError::BlockfileError(e)),
+                    union_bitmaps(blockfile_reader.get_by_prefix(metadata_key), |value| {
+                        *value != *k
+                    })
+                    .await
+                }
+                _ => Err(MetadataIndexError::InvalidKeyType),
+            },
+            MetadataIndexReader::F32MetadataIndexReader(blockfile_reader) => match metadata_value {
+                KeyWrapper::Float32(k) => {
+                    union_bitmaps(blockfile_reader.get_by_prefix(metadata_key), |value| {
+                        *value != *k
+                    })
+                    .await
+                }
+                _ => Err(MetadataIndexError::InvalidKeyType),
+            },
+            MetadataIndexReader::BoolMetadataIndexReader(blockfile_reader) => {
+                match metadata_value {
+                    KeyWrapper::Bool(k) => {
+                        union_bitmaps(blockfile_reader.get_by_prefix(metadata_key), |value| {
+                            *value != *k
+                        })
+                        .await
                     }
+                    _ => Err(MetadataIndexError::InvalidKeyType),
+                }
+            }
+        }
+    }
+
+    pub async fn lt(
+        &'me self,
+        metadata_key: &str,
+        metadata_value: &'me KeyWrapper,
+    ) -> Result<RoaringBitmap, MetadataIndexError> {
+        match self {
+            MetadataIndexReader::U32MetadataIndexReader(blockfile_reader) => match metadata_value {
+                KeyWrapper::Uint32(k) => {
+                    union_bitmaps(blockfile_reader.get_lt(metadata_key, *k), |_| true).await
                 }
                 _ => return Err(MetadataIndexError::InvalidKeyType),
             },
             MetadataIndexReader::F32MetadataIndexReader(blockfile_reader) => match metadata_value {
                 KeyWrapper::Float32(k) => {
-                    let read = blockfile_reader.get_lt(metadata_key, *k).await;
-                    match read {
-                        Ok(records) => {
-                            let mut result = RoaringBitmap::new();
-                            for (_, _, rbm) in records {
-                                result = result.bitor(&rbm);
-                            }
-                            Ok(result)
-                        }
-                        Err(e) => Err(MetadataIndexError::BlockfileError(e)),
-                    }
+                    union_bitmaps(blockfile_reader.get_lt(metadata_key, *k), |_| true).await
                 }
                 _ => return Err(MetadataIndexError::InvalidKeyType),
             },
@@ -913,33 +966,13 @@ impl<'me> MetadataIndexReader<'me> {
         match self {
             MetadataIndexReader::U32MetadataIndexReader(blockfile_reader) => match metadata_value {
                 KeyWrapper::Uint32(k) => {
-                    let read = blockfile_reader.get_lte(metadata_key, *k).await;
-                    match read {
-                        Ok(records) => {
-                            let mut result = RoaringBitmap::new();
-                            for (_, _, rbm) in records {
-                                result = result.bitor(&rbm);
-                            }
-                            Ok(result)
-                        }
-                        Err(e) => Err(MetadataIndexError::BlockfileError(e)),
-                    }
+                    union_bitmaps(blockfile_reader.get_lte(metadata_key, *k), |_| true).await
                 }
                 _ => return Err(MetadataIndexError::InvalidKeyType),
             },
             MetadataIndexReader::F32MetadataIndexReader(blockfile_reader) => match metadata_value {
                 KeyWrapper::Float32(k) => {
-                    let read = blockfile_reader.get_lt(metadata_key, *k).await;
-                    match read {
-                        Ok(records) => {
-                            let mut result = RoaringBitmap::new();
-                            for (_, _, rbm) in records {
-                                result = result.bitor(&rbm);
-                            }
-                            Ok(result)
-                        }
-                        Err(e) => Err(MetadataIndexError::BlockfileError(e)),
-                    }
+                    union_bitmaps(blockfile_reader.get_lt(metadata_key, *k), |_| true).await
                 }
                 _ => return Err(MetadataIndexError::InvalidKeyType),
             },
@@ -955,33 +988,13 @@ impl<'me> MetadataIndexReader<'me> {
         match self {
             MetadataIndexReader::U32MetadataIndexReader(blockfile_reader) => match metadata_value {
                 KeyWrapper::Uint32(k) => {
-                    let read = blockfile_reader.get_gt(metadata_key, *k).await;
-                    match read {
-                        Ok(records) => {
-                            let mut result = RoaringBitmap::new();
-                            for (_, _, rbm) in records {
-                                result = result.bitor(&rbm);
-                            }
-                            Ok(result)
-                        }
-                        Err(e) => Err(MetadataIndexError::BlockfileError(e)),
-                    }
+                    union_bitmaps(blockfile_reader.get_gt(metadata_key, *k), |_| true).await
                 }
                 _ => return Err(MetadataIndexError::InvalidKeyType),
             },
             MetadataIndexReader::F32MetadataIndexReader(blockfile_reader) => match metadata_value {
                 KeyWrapper::Float32(k) => {
-                    let read = blockfile_reader.get_gt(metadata_key, *k).await;
-                    match read {
-                        Ok(records) => {
-                            let mut result = RoaringBitmap::new();
-                            for (_, _, rbm) in records {
-                                result = result.bitor(&rbm);
-                            }
-                            Ok(result)
-                        }
-                        Err(e) => Err(MetadataIndexError::BlockfileError(e)),
-                    }
+                    union_bitmaps(blockfile_reader.get_gt(metadata_key, *k), |_| true).await
                 }
                 _ => return Err(MetadataIndexError::InvalidKeyType),
             },
@@ -997,33 +1010,13 @@ impl<'me> MetadataIndexReader<'me> {
         match self {
             MetadataIndexReader::U32MetadataIndexReader(blockfile_reader) => match metadata_value {
                 KeyWrapper::Uint32(k) => {
-                    let read = blockfile_reader.get_gte(metadata_key, *k).await;
-                    match read {
-                        Ok(records) => {
-                            let mut result = RoaringBitmap::new();
-                            for (_, _, rbm) in records {
-                                result = result.bitor(&rbm);
-                            }
-                            Ok(result)
-                        }
-                        Err(e) => Err(MetadataIndexError::BlockfileError(e)),
-                    }
+                    union_bitmaps(blockfile_reader.get_gte(metadata_key, *k), |_| true).await
                 }
                 _ => return Err(MetadataIndexError::InvalidKeyType),
             },
             MetadataIndexReader::F32MetadataIndexReader(blockfile_reader) => match metadata_value {
                 KeyWrapper::Float32(k) => {
-                    let read = blockfile_reader.get_gte(metadata_key, *k).await;
-                    match read {
-                        Ok(records) => {
-                            let mut result = RoaringBitmap::new();
-                            for (_, _, rbm) in records {
-                                result = result.bitor(&rbm);
-                            }
-                            Ok(result)
-                        }
-                        Err(e) => Err(MetadataIndexError::BlockfileError(e)),
-                    }
+                    union_bitmaps(blockfile_reader.get_gte(metadata_key, *k), |_| true).await
                 }
                 _ => return Err(MetadataIndexError::InvalidKeyType),
             },
@@ -1362,8 +1355,8 @@ mod test {
         assert_eq!(bitmap.len(), 1);
         assert!(bitmap.contains(5));
 
-        let bitmap = reader.lt("key2", &5.into()).await;
-        assert!(bitmap.is_err());
+        let bitmap = reader.lt("key2", &5.into()).await.unwrap();
+        assert!(bitmap.is_empty());
     }
 
     #[tokio::test]
@@ -1396,8 +1389,8 @@ mod test {
         assert_eq!(bitmap.len(), 1);
         assert!(bitmap.contains(5));
 
-        let bitmap = reader.lte("key2", &4.into()).await;
-        assert!(bitmap.is_err());
+        let bitmap = reader.lte("key2", &4.into()).await.unwrap();
+        assert!(bitmap.is_empty());
     }
 
     #[tokio::test]
@@ -1429,8 +1422,8 @@ mod test {
         assert_eq!(bitmap.len(), 1);
         assert!(bitmap.contains(5));
 
-        let bitmap = reader.gt("key2", &5.into()).await;
-        assert!(bitmap.is_err());
+        let bitmap = reader.gt("key2", &5.into()).await.unwrap();
+        assert!(bitmap.is_empty());
     }
 
     #[tokio::test]
@@ -1463,8 +1456,8 @@ mod test {
         assert_eq!(bitmap.len(), 1);
         assert!(bitmap.contains(5));
 
-        let bitmap = reader.gte("key2", &6.into()).await;
-        assert!(bitmap.is_err());
+        let bitmap = reader.gte("key2", &6.into()).await.unwrap();
+        assert!(bitmap.is_empty());
     }
 
     #[tokio::test]
@@ -1497,8 +1490,8 @@ mod test {
         assert_eq!(bitmap.len(), 1);
         assert!(bitmap.contains(5));
 
-        let bitmap = reader.lt("key2", &5.0.into()).await;
-        assert!(bitmap.is_err());
+        let bitmap = reader.lt("key2", &5.0.into()).await.unwrap();
+        assert!(bitmap.is_empty());
     }
 
     #[tokio::test]
@@ -1532,8 +1525,8 @@ mod test {
         assert_eq!(bitmap.len(), 1);
         assert!(bitmap.contains(5));
 
-        let bitmap = reader.lte("key2", &4.9.into()).await;
-        assert!(bitmap.is_err());
+        let bitmap = reader.lte("key2", &4.9.into()).await.unwrap();
+        assert!(bitmap.is_empty());
     }
 
     #[tokio::test]
@@ -1565,8 +1558,8 @@ mod test {
         assert_eq!(bitmap.len(), 1);
         assert!(bitmap.contains(5));
 
-        let bitmap = reader.gt("key2", &5.0.into()).await;
-        assert!(bitmap.is_err());
+        let bitmap = reader.gt("key2", &5.0.into()).await.unwrap();
+        assert!(bitmap.is_empty());
     }
 
     #[tokio::test]
@@ -1599,10 +1592,91 @@ mod test {
         assert_eq!(bitmap.len(), 1);
         assert!(bitmap.contains(5));
 
-        let bitmap = reader.gte("key2", &6.0.into()).await;
+        let bitmap = reader.gte("key2", &6.0.into()).await.unwrap();
+        assert!(bitmap.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_string_metadata_ne_operator() {
+        let provider = BlockfileProvider::new_memory();
+        let blockfile_writer = provider.create::<&str, &RoaringBitmap>().unwrap();
+        let writer_id = blockfile_writer.id();
+        let mut writer = MetadataIndexWriter::new_string(blockfile_writer, None);
+        writer.set("key1", "red", 1).await.unwrap();
+        writer.set("key1", "blue", 2).await.unwrap();
+        writer.set("key1", "green", 3).await.unwrap();
+        writer.set("key2", "red", 4).await.unwrap();
+        writer.write_to_blockfile().await.unwrap();
+        let flusher = writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        let blockfile_reader = provider
+            .open::<&str, RoaringBitmap>(&writer_id)
+            .await
+            .unwrap();
+        let reader = MetadataIndexReader::new_string(blockfile_reader);
+        let bitmap = reader.ne("key1", &"red".into()).await.unwrap();
+        assert_eq!(bitmap.len(), 2);
+        assert!(bitmap.contains(2));
+        assert!(bitmap.contains(3));
+
+        let bitmap = reader.ne("key2", &"red".into()).await.unwrap();
+        assert!(bitmap.is_empty());
+
+        let bitmap = reader.ne("key1", &1.into()).await;
         assert!(bitmap.is_err());
     }
 
+    #[tokio::test]
+    async fn test_u32_metadata_ne_operator() {
+        let provider = BlockfileProvider::new_memory();
+        let blockfile_writer = provider.create::<u32, &RoaringBitmap>().unwrap();
+        let writer_id = blockfile_writer.id();
+        let mut writer = MetadataIndexWriter::new_u32(blockfile_writer, None);
+        writer.set("key1", 1, 1).await.unwrap();
+        writer.set("key1", 2, 2).await.unwrap();
+        writer.set("key1", 2, 3).await.unwrap();
+        writer.set("key2", 5, 4).await.unwrap();
+        writer.write_to_blockfile().await.unwrap();
+        let flusher = writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        let blockfile_reader = provider
+            .open::<u32, RoaringBitmap>(&writer_id)
+            .await
+            .unwrap();
+        let reader = MetadataIndexReader::new_u32(blockfile_reader);
+        let bitmap = reader.ne("key1", &2.into()).await.unwrap();
+        assert_eq!(bitmap.len(), 1);
+        assert!(bitmap.contains(1));
+
+        let bitmap = reader.ne("key1", &7.into()).await.unwrap();
+        assert_eq!(bitmap.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_bool_metadata_ne_operator() {
+        let provider = BlockfileProvider::new_memory();
+        let blockfile_writer = provider.create::<bool, &RoaringBitmap>().unwrap();
+        let writer_id = blockfile_writer.id();
+        let mut writer = MetadataIndexWriter::new_bool(blockfile_writer, None);
+        writer.set("key1", true, 1).await.unwrap();
+        writer.set("key1", false, 2).await.unwrap();
+        writer.set("key1", true, 3).await.unwrap();
+        writer.write_to_blockfile().await.unwrap();
+        let flusher = writer.commit().unwrap();
+        flusher.flush().await.unwrap();
+
+        let blockfile_reader = provider
+            .open::<bool, RoaringBitmap>(&writer_id)
+            .await
+            .unwrap();
+        let reader = MetadataIndexReader::new_bool(blockfile_reader);
+        let bitmap = reader.ne("key1", &true.into()).await.unwrap();
+        assert_eq!(bitmap.len(), 1);
+        assert!(bitmap.contains(2));
+    }
+
     // TODO enable this test once fork() is enabled for MemoryBlockfiles.
     // #[tokio::test]
     // async fn test_set_get_set_delete() {
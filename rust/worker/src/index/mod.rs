@@ -1,5 +1,8 @@
+mod brute_force;
+pub(crate) mod config;
 pub(crate) mod fulltext;
 mod hnsw;
+mod hnsw_params;
 pub(crate) mod hnsw_provider;
 pub(crate) mod metadata;
 mod types;
@@ -7,5 +10,7 @@ mod utils;
 
 // Re-export types
 
+pub(crate) use brute_force::*;
 pub(crate) use hnsw::*;
+pub(crate) use hnsw_params::*;
 pub(crate) use types::*;
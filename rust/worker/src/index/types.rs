@@ -12,7 +12,7 @@ pub(crate) struct IndexConfig {
 
 #[derive(Error, Debug)]
 pub(crate) enum IndexConfigFromSegmentError {
-    #[error("Invalid distance function")]
+    #[error("Invalid distance function: {0}")]
     InvalidDistanceFunction(#[from] DistanceFunctionError),
 }
 
@@ -25,18 +25,23 @@ impl ChromaError for IndexConfigFromSegmentError {
 }
 
 impl IndexConfig {
-    pub(crate) fn from_segment(
-        segment: &Segment,
-        dimensionality: i32,
-    ) -> Result<Self, Box<IndexConfigFromSegmentError>> {
-        let space = match segment.metadata {
+    /// The name of the distance function the segment is queried with, from its `hnsw:space`.
+    /// Defaults to l2 when it is not set.
+    pub(crate) fn distance_function_name(segment: &Segment) -> &str {
+        match segment.metadata {
             Some(ref metadata) => match metadata.get("hnsw:space") {
                 Some(MetadataValue::Str(space)) => space,
                 _ => "l2",
             },
             None => "l2",
-        };
-        match DistanceFunction::try_from(space) {
+        }
+    }
+
+    pub(crate) fn from_segment(
+        segment: &Segment,
+        dimensionality: i32,
+    ) -> Result<Self, Box<IndexConfigFromSegmentError>> {
+        match DistanceFunction::try_from(IndexConfig::distance_function_name(segment)) {
             Ok(distance_function) => Ok(IndexConfig {
                 dimensionality,
                 distance_function,
@@ -1,10 +1,13 @@
 mod assignment;
+#[cfg(feature = "bench")]
+pub mod bench;
 mod blockstore;
 mod compactor;
 mod config;
 pub mod distance;
 mod errors;
 mod execution;
+mod health;
 mod index;
 mod log;
 mod memberlist;
@@ -18,8 +21,10 @@ mod types;
 mod utils;
 
 use config::Configurable;
+use execution::deadline::Deadline;
 use memberlist::MemberlistProvider;
 
+use std::time::Duration;
 use tokio::select;
 use tokio::signal::unix::{signal, SignalKind};
 
@@ -29,19 +34,32 @@ mod chroma_proto {
     tonic::include_proto!("chroma");
 }
 
+mod health_proto {
+    tonic::include_proto!("grpc.health.v1");
+}
+
 pub async fn query_service_entrypoint() {
     // Check if the config path is set in the env var
     let config = match std::env::var(CONFIG_PATH_ENV_VAR) {
         Ok(config_path) => config::RootConfig::load_from_path(&config_path),
         Err(_) => config::RootConfig::load(),
     };
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Refusing to start: {}", err);
+            return;
+        }
+    };
 
     let config = config.query_service;
 
     crate::tracing::opentelemetry_config::init_otel_tracing(
         &config.service_name,
-        &config.otel_endpoint,
+        config.otel_endpoint.as_deref(),
     );
+    let metrics_controller =
+        crate::tracing::metrics::init_otel_metrics(&config.service_name, &config.metrics);
 
     let system: system::System = system::System::new();
     let dispatcher =
@@ -63,7 +81,26 @@ pub async fn query_service_entrypoint() {
     worker_server.set_system(system.clone());
     worker_server.set_dispatcher(dispatcher_handle.receiver());
 
-    let server_join_handle = tokio::spawn(async move {
+    let mut memberlist = match memberlist::CustomResourceMemberlistProvider::try_from_config(
+        &config.memberlist_provider,
+    )
+    .await
+    {
+        Ok(memberlist) => memberlist,
+        Err(err) => {
+            println!("Failed to create memberlist component: {:?}", err);
+            return;
+        }
+    };
+    memberlist.subscribe(Box::new(health::MemberlistReadiness::new(
+        worker_server.health_reporter(),
+    )));
+    let mut memberlist_handle = system.start_component(memberlist);
+
+    let shutdown = worker_server.shutdown_token();
+    let grace_period = Duration::from_millis(config.shutdown_grace_period_ms);
+
+    let mut server_join_handle = tokio::spawn(async move {
         let _ = crate::server::WorkerServer::run(worker_server).await;
     });
 
@@ -80,12 +117,37 @@ pub async fn query_service_entrypoint() {
         // Kubernetes will send SIGTERM to stop the pod gracefully
         // TODO: add more signal handling
         _ = sigterm.recv() => {
-            dispatcher_handle.stop();
-            dispatcher_handle.join().await;
+            // Reject new requests, then let the ones in flight finish before stopping the
+            // dispatcher that runs them.
+            // The grace period covers the whole shutdown, each step only gets what is left.
+            println!("Received SIGTERM, draining in-flight queries");
+            let deadline = Deadline::after(grace_period);
+            shutdown.cancel();
+            memberlist_handle.stop();
+            memberlist_handle.join().await;
+            if tokio::time::timeout(deadline.remaining(), &mut server_join_handle)
+                .await
+                .is_err()
+            {
+                println!(
+                    "Queries still in flight at the end of the {:?} grace period, aborting them",
+                    grace_period
+                );
+                server_join_handle.abort();
+            }
+            if !dispatcher_handle.stop_and_join(deadline.remaining()).await {
+                println!("Dispatcher did not stop within the {:?} grace period", grace_period);
+            }
             system.stop().await;
             system.join().await;
         },
     };
+    if let Some(metrics_controller) = metrics_controller {
+        // Stopping exports the metrics recorded since the last export.
+        if let Err(e) = metrics_controller.stop(&opentelemetry::Context::current()) {
+            println!("Failed to export the last metrics: {}", e);
+        }
+    }
     println!("Server stopped");
 }
 
@@ -95,13 +157,22 @@ pub async fn compaction_service_entrypoint() {
         Ok(config_path) => config::RootConfig::load_from_path(&config_path),
         Err(_) => config::RootConfig::load(),
     };
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Refusing to start: {}", err);
+            return;
+        }
+    };
 
     let config = config.compaction_service;
 
     crate::tracing::opentelemetry_config::init_otel_tracing(
         &config.service_name,
-        &config.otel_endpoint,
+        config.otel_endpoint.as_deref(),
     );
+    let metrics_controller =
+        crate::tracing::metrics::init_otel_metrics(&config.service_name, &config.metrics);
 
     let system: system::System = system::System::new();
 
@@ -141,6 +212,7 @@ pub async fn compaction_service_entrypoint() {
     memberlist.subscribe(compaction_manager_handle.receiver());
 
     let mut memberlist_handle = system.start_component(memberlist);
+    let grace_period = Duration::from_millis(config.shutdown_grace_period_ms);
 
     let mut sigterm = match signal(SignalKind::terminate()) {
         Ok(sigterm) => sigterm,
@@ -154,15 +226,31 @@ pub async fn compaction_service_entrypoint() {
         // Kubernetes will send SIGTERM to stop the pod gracefully
         // TODO: add more signal handling
         _ = sigterm.recv() => {
+            // Stop taking on new compactions, then let the ones in flight finish before
+            // stopping the dispatcher that runs their operators.
+            // The grace period covers the whole shutdown, each step only gets what is left.
+            println!("Received SIGTERM, draining in-flight compactions");
+            let deadline = Deadline::after(grace_period);
             memberlist_handle.stop();
             memberlist_handle.join().await;
-            dispatcher_handle.stop();
-            dispatcher_handle.join().await;
-            compaction_manager_handle.stop();
-            compaction_manager_handle.join().await;
+            if !compaction_manager_handle.stop_and_join(deadline.remaining()).await {
+                println!(
+                    "Compactions still in flight at the end of the {:?} grace period, abandoning them",
+                    grace_period
+                );
+            }
+            if !dispatcher_handle.stop_and_join(deadline.remaining()).await {
+                println!("Dispatcher did not stop within the {:?} grace period", grace_period);
+            }
             system.stop().await;
             system.join().await;
         },
     };
+    if let Some(metrics_controller) = metrics_controller {
+        // Stopping exports the metrics recorded since the last export.
+        if let Err(e) = metrics_controller.stop(&opentelemetry::Context::current()) {
+            println!("Failed to export the last metrics: {}", e);
+        }
+    }
     println!("Server stopped");
 }
@@ -1,14 +1,51 @@
+use crate::config::{ConfigValidator, ValidateConfig};
 use serde::Deserialize;
 
+const DEFAULT_DEAD_LETTER_MAX_ENTRIES: usize = 1000;
+
+fn default_dead_letter_max_entries() -> usize {
+    DEFAULT_DEAD_LETTER_MAX_ENTRIES
+}
+
 #[derive(Deserialize)]
+/// The configuration for the gRPC log client.
+/// # Fields
+/// - host: The host of the log service.
+/// - port: The port of the log service.
+/// - connect_timeout_ms: How long to wait for a connection to the log service.
+/// - request_timeout_ms: How long to wait for a single request to the log service.
+/// - dead_letter_max_entries: How many of the log records that could not be decoded are
+/// kept to be looked at. Defaults to 1000.
+#[serde(deny_unknown_fields)]
 pub(crate) struct GrpcLogConfig {
     pub(crate) host: String,
     pub(crate) port: u16,
     pub(crate) connect_timeout_ms: u64,
     pub(crate) request_timeout_ms: u64,
+    #[serde(default = "default_dead_letter_max_entries")]
+    pub(crate) dead_letter_max_entries: usize,
 }
 
 #[derive(Deserialize)]
 pub(crate) enum LogConfig {
+    // case-insensitive
+    #[serde(alias = "grpc")]
     Grpc(GrpcLogConfig),
 }
+
+impl ValidateConfig for LogConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        match self {
+            LogConfig::Grpc(grpc) => validator.section("Grpc", grpc),
+        }
+    }
+}
+
+impl ValidateConfig for GrpcLogConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.non_empty("host", &self.host);
+        validator.port("port", self.port);
+        validator.positive("connect_timeout_ms", self.connect_timeout_ms);
+        validator.positive("request_timeout_ms", self.request_timeout_ms);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::chroma_proto;
+use crate::errors::{ChromaError, ErrorCodes};
+use crate::execution::data::data_chunk::Chunk;
+use crate::types::{LogRecord, RecordConversionError};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// A log record that could not be decoded.
+/// # Fields
+/// - collection_id: The collection the record was pushed to.
+/// - log_offset: The offset of the record in the log of the collection.
+/// - source: Why the record could not be decoded.
+#[derive(Error, Debug)]
+#[error("Malformed log record at offset {log_offset} of collection {collection_id}: {source}")]
+pub(crate) struct MalformedLogRecordError {
+    pub(crate) collection_id: Uuid,
+    pub(crate) log_offset: i64,
+    pub(crate) source: RecordConversionError,
+}
+
+impl ChromaError for MalformedLogRecordError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::InvalidArgument
+    }
+}
+
+/// The log records that could not be decoded.
+/// # Description
+/// Retrying a record that can't be decoded fails the same way every time, so instead of
+/// failing the pull, and with it every compaction of the collection, a malformed record is
+/// captured here and skipped. Every record is captured once, even if it is pulled again.
+/// # Notes
+/// Only the most recent `max_entries` records are kept. The payloads are not copied, they
+/// stay in the log at the captured offsets.
+#[derive(Clone, Debug)]
+pub(crate) struct DeadLetterQueue {
+    max_entries: usize,
+    count: Arc<AtomicU64>,
+    entries: Arc<Mutex<VecDeque<Arc<MalformedLogRecordError>>>>,
+}
+
+impl DeadLetterQueue {
+    pub(crate) fn new(max_entries: usize) -> Self {
+        DeadLetterQueue {
+            max_entries,
+            count: Arc::new(AtomicU64::new(0)),
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Decodes the records pulled from the log of a collection, in order. The records that
+    /// can't be decoded are captured and left out.
+    pub(crate) fn decode(
+        &self,
+        collection_id: Uuid,
+        records: Vec<chroma_proto::LogRecord>,
+    ) -> Vec<LogRecord> {
+        let mut result = Vec::with_capacity(records.len());
+        for record in records {
+            let log_offset = record.log_offset;
+            match LogRecord::try_from(record) {
+                Ok(record) => result.push(record),
+                Err(source) => self.capture(MalformedLogRecordError {
+                    collection_id,
+                    log_offset,
+                    source,
+                }),
+            }
+        }
+        result
+    }
+
+    /// Hides, and captures, the records of a collection whose embedding does not have the
+    /// dimension of the collection. Until the collection has a dimension, it is the dimension
+    /// of the first record with an embedding.
+    pub(crate) fn hide_dimension_mismatches(
+        &self,
+        collection_id: Uuid,
+        dimension: Option<usize>,
+        records: &mut Chunk<LogRecord>,
+    ) {
+        let dimension = dimension.or_else(|| {
+            records
+                .iter()
+                .find_map(|(record, _)| record.record.embedding.as_ref())
+                .map(|embedding| embedding.len())
+        });
+        let dimension = match dimension {
+            Some(dimension) => dimension,
+            None => return,
+        };
+        let mut visibility: Vec<bool> = (0..records.total_len())
+            .map(|index| records.get_visibility(index).unwrap_or(false))
+            .collect();
+        let mut hidden = false;
+        for (record, index) in records.iter() {
+            if let Err(source) = record.record.check_dimension(dimension) {
+                self.capture(MalformedLogRecordError {
+                    collection_id,
+                    log_offset: record.log_offset,
+                    source,
+                });
+                visibility[index] = false;
+                hidden = true;
+            }
+        }
+        if hidden {
+            records.set_visibility(visibility);
+        }
+    }
+
+    fn capture(&self, error: MalformedLogRecordError) {
+        let mut entries = self.entries.lock();
+        if entries.iter().any(|entry| {
+            entry.collection_id == error.collection_id && entry.log_offset == error.log_offset
+        }) {
+            return;
+        }
+        // TODO: switch to logging when logging is implemented
+        println!("Skipping log record: {}", error);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        if entries.len() >= self.max_entries {
+            entries.pop_front();
+        }
+        entries.push_back(Arc::new(error));
+    }
+
+    /// The number of records captured since the worker started.
+    pub(crate) fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// The captured records of a collection that are still kept, oldest first.
+    pub(crate) fn get(&self, collection_id: Uuid) -> Vec<Arc<MalformedLogRecordError>> {
+        self.entries
+            .lock()
+            .iter()
+            .filter(|entry| entry.collection_id == collection_id)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::VectorConversionError;
+
+    fn log_record(log_offset: i64, vector: Vec<u8>, dimension: i32) -> chroma_proto::LogRecord {
+        chroma_proto::LogRecord {
+            log_offset,
+            record: Some(chroma_proto::OperationRecord {
+                id: format!("embedding_id_{}", log_offset),
+                vector: Some(chroma_proto::Vector {
+                    dimension,
+                    vector,
+                    encoding: chroma_proto::ScalarEncoding::Float32 as i32,
+                }),
+                metadata: None,
+                operation: chroma_proto::Operation::Add as i32,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_decode_skips_and_captures_malformed_records() {
+        let queue = DeadLetterQueue::new(10);
+        let collection_id = Uuid::new_v4();
+        let records = vec![
+            log_record(0, 1.0f32.to_le_bytes().to_vec(), 1),
+            // 6 bytes can't hold float32s.
+            log_record(1, vec![0; 6], 1),
+            log_record(2, 2.0f32.to_le_bytes().to_vec(), 1),
+            // One float32 declared as two.
+            log_record(3, 3.0f32.to_le_bytes().to_vec(), 2),
+            chroma_proto::LogRecord {
+                log_offset: 4,
+                record: None,
+            },
+            log_record(5, 5.0f32.to_le_bytes().to_vec(), 1),
+        ];
+
+        let decoded = queue.decode(collection_id, records);
+        let offsets: Vec<_> = decoded.iter().map(|record| record.log_offset).collect();
+        assert_eq!(offsets, vec![0, 2, 5]);
+        assert_eq!(decoded[1].record.embedding, Some(vec![2.0].into()));
+
+        let captured = queue.get(collection_id);
+        let offsets: Vec<_> = captured.iter().map(|error| error.log_offset).collect();
+        assert_eq!(offsets, vec![1, 3, 4]);
+        assert!(matches!(
+            &captured[0].source,
+            RecordConversionError::VectorConversionError {
+                id,
+                source: VectorConversionError::InvalidByteLength
+            } if id == "embedding_id_1"
+        ));
+        assert!(matches!(
+            &captured[1].source,
+            RecordConversionError::VectorConversionError {
+                id,
+                source: VectorConversionError::DimensionMismatch {
+                    dimension: 2,
+                    byte_length: 4
+                }
+            } if id == "embedding_id_3"
+        ));
+        assert!(matches!(
+            captured[2].source,
+            RecordConversionError::MissingRecord
+        ));
+        for error in captured {
+            assert_eq!(error.code(), ErrorCodes::InvalidArgument);
+            assert_eq!(error.collection_id, collection_id);
+        }
+        assert_eq!(queue.count(), 3);
+        assert!(queue.get(Uuid::new_v4()).is_empty());
+    }
+
+    #[test]
+    fn test_records_are_captured_once() {
+        let queue = DeadLetterQueue::new(10);
+        let collection_id = Uuid::new_v4();
+        for _ in 0..3 {
+            queue.decode(collection_id, vec![log_record(7, vec![0; 3], 1)]);
+        }
+        assert_eq!(queue.count(), 1);
+        assert_eq!(queue.get(collection_id).len(), 1);
+    }
+
+    #[test]
+    fn test_only_the_most_recent_records_are_kept() {
+        let queue = DeadLetterQueue::new(2);
+        let collection_id = Uuid::new_v4();
+        let records = (0..5).map(|offset| log_record(offset, vec![0; 3], 1));
+        queue.decode(collection_id, records.collect());
+        let offsets: Vec<_> = queue
+            .get(collection_id)
+            .iter()
+            .map(|error| error.log_offset)
+            .collect();
+        assert_eq!(offsets, vec![3, 4]);
+        assert_eq!(queue.count(), 5);
+    }
+
+    #[test]
+    fn test_dimension_mismatches_are_hidden_and_captured() {
+        let queue = DeadLetterQueue::new(10);
+        let collection_id = Uuid::new_v4();
+        let records = vec![
+            log_record(
+                0,
+                [1.0f32, 2.0].iter().flat_map(|x| x.to_le_bytes()).collect(),
+                2,
+            ),
+            log_record(1, 1.0f32.to_le_bytes().to_vec(), 1),
+            log_record(
+                2,
+                [1.0f32; 3].iter().flat_map(|x| x.to_le_bytes()).collect(),
+                3,
+            ),
+            log_record(
+                3,
+                [3.0f32, 4.0].iter().flat_map(|x| x.to_le_bytes()).collect(),
+                2,
+            ),
+        ];
+        let decoded = queue.decode(collection_id, records);
+        assert_eq!(decoded.len(), 4);
+
+        // The dimension of the collection is taken over the dimension of the first record.
+        let mut chunk = Chunk::new(decoded.clone().into());
+        queue.hide_dimension_mismatches(collection_id, Some(1), &mut chunk);
+        let visible: Vec<_> = chunk.iter().map(|(record, _)| record.log_offset).collect();
+        assert_eq!(visible, vec![1]);
+
+        let other_collection_id = Uuid::new_v4();
+        let mut chunk = Chunk::new(decoded.into());
+        queue.hide_dimension_mismatches(other_collection_id, None, &mut chunk);
+        let visible: Vec<_> = chunk.iter().map(|(record, _)| record.log_offset).collect();
+        assert_eq!(visible, vec![0, 3]);
+        assert_eq!(chunk.total_len(), 4);
+
+        let captured = queue.get(other_collection_id);
+        assert_eq!(captured.len(), 2);
+        assert!(matches!(
+            &captured[0].source,
+            RecordConversionError::DimensionMismatch {
+                id,
+                expected: 2,
+                got: 1
+            } if id == "embedding_id_1"
+        ));
+        assert_eq!(captured[1].log_offset, 2);
+        assert_eq!(captured[1].code(), ErrorCodes::InvalidArgument);
+        assert_eq!(queue.count(), 5);
+    }
+}
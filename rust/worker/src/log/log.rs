@@ -1,15 +1,20 @@
 use crate::chroma_proto;
 use crate::chroma_proto::log_service_client::LogServiceClient;
 use crate::config::Configurable;
+use crate::errors::is_retryable_status;
 use crate::errors::ChromaError;
 use crate::errors::ErrorCodes;
 use crate::log::config::LogConfig;
+use crate::log::dead_letter::DeadLetterQueue;
 use crate::tracing::util::client_interceptor;
 use crate::types::LogRecord;
-use crate::types::RecordConversionError;
+use crate::types::OperationRecord;
+use crate::types::UpdateMetadataValue;
 use async_trait::async_trait;
+use parking_lot::Mutex;
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
 use tonic::service::interceptor;
@@ -23,11 +28,17 @@ use uuid::Uuid;
 /// - collection_id: the id of the collection that needs to be compacted
 /// - first_log_offset: the offset of the first log entry in the collection that needs to be compacted
 /// - first_log_ts: the timestamp of the first log entry in the collection that needs to be compacted
+/// - backlog: the number of log entries in the collection that need to be compacted, if the log
+/// reports it. The log service does not yet.
+/// - backlog_bytes: roughly how many bytes the log entries that need to be compacted take up,
+/// if the log reports it. The log service does not yet.
 #[derive(Debug)]
 pub(crate) struct CollectionInfo {
     pub(crate) collection_id: String,
     pub(crate) first_log_offset: i64,
     pub(crate) first_log_ts: i64,
+    pub(crate) backlog: Option<u64>,
+    pub(crate) backlog_bytes: Option<u64>,
 }
 
 #[derive(Clone, Debug)]
@@ -38,6 +49,7 @@ pub(crate) struct CollectionRecord {
     pub(crate) first_record_time: i64,
     pub(crate) offset: i64,
     pub(crate) collection_version: i32,
+    pub(crate) backlog: Option<u64>,
 }
 
 #[derive(Clone, Debug)]
@@ -66,6 +78,15 @@ impl Log {
         }
     }
 
+    /// The log records that could not be decoded and were skipped, if the log decodes
+    /// records.
+    pub(crate) fn dead_letters(&self) -> Option<&DeadLetterQueue> {
+        match self {
+            Log::Grpc(log) => Some(&log.dead_letters),
+            Log::InMemory(log) => log.dead_letters.as_ref(),
+        }
+    }
+
     pub(crate) async fn get_collections_with_new_data(
         &mut self,
         min_compaction_size: u64,
@@ -102,6 +123,7 @@ pub(crate) struct GrpcLog {
             fn(Request<()>) -> Result<Request<()>, Status>,
         >,
     >,
+    dead_letters: DeadLetterQueue,
 }
 
 impl GrpcLog {
@@ -112,8 +134,12 @@ impl GrpcLog {
                 fn(Request<()>) -> Result<Request<()>, Status>,
             >,
         >,
+        dead_letters: DeadLetterQueue,
     ) -> Self {
-        Self { client }
+        Self {
+            client,
+            dead_letters,
+        }
     }
 }
 
@@ -161,7 +187,10 @@ impl Configurable<LogConfig> for GrpcLog {
                                 fn(Request<()>) -> Result<Request<()>, Status>,
                             >,
                         > = LogServiceClient::with_interceptor(client, client_interceptor);
-                        return Ok(GrpcLog::new(channel));
+                        return Ok(GrpcLog::new(
+                            channel,
+                            DeadLetterQueue::new(my_config.dead_letter_max_entries),
+                        ));
                     }
                     Err(e) => {
                         return Err(Box::new(GrpcLogError::FailedToConnect(e)));
@@ -194,19 +223,7 @@ impl GrpcLog {
         match response {
             Ok(response) => {
                 let logs = response.into_inner().records;
-                let mut result = Vec::new();
-                for log_record_proto in logs {
-                    let log_record = log_record_proto.try_into();
-                    match log_record {
-                        Ok(log_record) => {
-                            result.push(log_record);
-                        }
-                        Err(err) => {
-                            return Err(PullLogsError::ConversionError(err));
-                        }
-                    }
-                }
-                Ok(result)
+                Ok(self.dead_letters.decode(collection_id, logs))
             }
             Err(e) => {
                 // TODO: switch to logging when logging is implemented
@@ -239,6 +256,8 @@ impl GrpcLog {
                         collection_id: collection.collection_id,
                         first_log_offset: collection.first_log_offset,
                         first_log_ts: collection.first_log_ts,
+                        backlog: None,
+                        backlog_bytes: None,
                     });
                 }
                 Ok(result)
@@ -274,15 +293,18 @@ impl GrpcLog {
 pub(crate) enum PullLogsError {
     #[error("Failed to fetch")]
     FailedToPullLogs(#[from] tonic::Status),
-    #[error("Failed to convert proto embedding record into EmbeddingRecord")]
-    ConversionError(#[from] RecordConversionError),
 }
 
 impl ChromaError for PullLogsError {
     fn code(&self) -> ErrorCodes {
         match self {
             PullLogsError::FailedToPullLogs(_) => ErrorCodes::Internal,
-            PullLogsError::ConversionError(_) => ErrorCodes::Internal,
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            PullLogsError::FailedToPullLogs(status) => is_retryable_status(status),
         }
     }
 }
@@ -301,6 +323,14 @@ impl ChromaError for GetCollectionsWithNewDataError {
             }
         }
     }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            GetCollectionsWithNewDataError::FailedGetCollectionsWithNewData(status) => {
+                is_retryable_status(status)
+            }
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -317,6 +347,14 @@ impl ChromaError for UpdateCollectionLogOffsetError {
             }
         }
     }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            UpdateCollectionLogOffsetError::FailedToUpdateCollectionLogOffset(status) => {
+                is_retryable_status(status)
+            }
+        }
+    }
 }
 
 // This is used for testing only, it represents a log record that is stored in memory
@@ -344,17 +382,41 @@ impl Debug for InternalLogRecord {
 #[derive(Clone, Debug)]
 pub(crate) struct InMemoryLog {
     collection_to_log: HashMap<String, Vec<Box<InternalLogRecord>>>,
-    offsets: HashMap<String, i64>,
+    // Shared by the clones, like the offsets kept by a log service.
+    offsets: Arc<Mutex<HashMap<String, i64>>>,
+    failing_read_offset: Option<i64>,
+    dead_letters: Option<DeadLetterQueue>,
 }
 
 impl InMemoryLog {
     pub fn new() -> InMemoryLog {
         InMemoryLog {
             collection_to_log: HashMap::new(),
-            offsets: HashMap::new(),
+            offsets: Arc::new(Mutex::new(HashMap::new())),
+            failing_read_offset: None,
+            dead_letters: None,
         }
     }
 
+    /// Keeps the malformed records in `dead_letters`, as a log that decodes records would.
+    #[cfg(test)]
+    pub(crate) fn set_dead_letters(&mut self, dead_letters: DeadLetterQueue) {
+        self.dead_letters = Some(dead_letters);
+    }
+
+    /// The offset the log was last updated to for a collection.
+    #[cfg(test)]
+    pub(crate) fn collection_log_offset(&self, collection_id: Uuid) -> Option<i64> {
+        self.offsets.lock().get(&collection_id.to_string()).copied()
+    }
+
+    /// Makes the next read that starts at `offset` fail, as a read from an unavailable log
+    /// service would.
+    #[cfg(test)]
+    pub(crate) fn fail_next_read_at(&mut self, offset: i64) {
+        self.failing_read_offset = Some(offset);
+    }
+
     pub fn add_log(&mut self, collection_id: Uuid, log: Box<InternalLogRecord>) {
         let logs = self
             .collection_to_log
@@ -373,6 +435,35 @@ impl InMemoryLog {
     }
 }
 
+// The size of the id, embedding, metadata and document of a record, which is roughly what
+// it takes up in the log.
+fn record_size(record: &OperationRecord) -> u64 {
+    let embedding = record
+        .embedding
+        .as_ref()
+        .map_or(0, |embedding| embedding.len() * 4);
+    let metadata = record.metadata.as_ref().map_or(0, |metadata| {
+        metadata
+            .iter()
+            .map(|(key, value)| {
+                key.len()
+                    + match value {
+                        UpdateMetadataValue::Str(value) => value.len(),
+                        UpdateMetadataValue::Int(_) => 4,
+                        UpdateMetadataValue::Float(_) => 8,
+                        UpdateMetadataValue::Bool(_) => 1,
+                        UpdateMetadataValue::None => 0,
+                    }
+            })
+            .sum()
+    });
+    let document = record
+        .document
+        .as_ref()
+        .map_or(0, |document| document.len());
+    (record.id.len() + embedding + metadata + document) as u64
+}
+
 impl InMemoryLog {
     async fn read(
         &mut self,
@@ -381,6 +472,12 @@ impl InMemoryLog {
         batch_size: i32,
         end_timestamp: Option<i64>,
     ) -> Result<Vec<LogRecord>, PullLogsError> {
+        if self.failing_read_offset == Some(offset) {
+            self.failing_read_offset = None;
+            return Err(PullLogsError::FailedToPullLogs(Status::unavailable(
+                "log service unavailable",
+            )));
+        }
         let end_timestamp = match end_timestamp {
             Some(end_timestamp) => end_timestamp,
             None => i64::MAX,
@@ -408,14 +505,14 @@ impl InMemoryLog {
             if log_records.is_empty() {
                 continue;
             }
-            let filtered_records = match self.offsets.get(collection_id) {
+            let filtered_records = match self.offsets.lock().get(collection_id).copied() {
                 Some(last_offset) => {
                     // Make sure there is at least one record past the last offset
                     let max_offset = log_records.len() as i64 - 1;
-                    if *last_offset + 1 > max_offset {
+                    if last_offset + 1 > max_offset {
                         continue;
                     }
-                    &log_records[(*last_offset + 1) as usize..]
+                    &log_records[(last_offset + 1) as usize..]
                 }
                 None => &log_records[..],
             };
@@ -430,6 +527,8 @@ impl InMemoryLog {
                 collection_id: collection_id.clone(),
                 first_log_offset: logs[0].log_offset,
                 first_log_ts: logs[0].log_ts,
+                backlog: Some(logs.len() as u64),
+                backlog_bytes: Some(logs.iter().map(|log| record_size(&log.record.record)).sum()),
             });
         }
         Ok(collections)
@@ -440,7 +539,9 @@ impl InMemoryLog {
         collection_id: Uuid,
         new_offset: i64,
     ) -> Result<(), UpdateCollectionLogOffsetError> {
-        self.offsets.insert(collection_id.to_string(), new_offset);
+        self.offsets
+            .lock()
+            .insert(collection_id.to_string(), new_offset);
         Ok(())
     }
 }
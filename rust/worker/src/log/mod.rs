@@ -1,4 +1,5 @@
 pub(crate) mod config;
+pub(crate) mod dead_letter;
 pub(crate) mod log;
 
 use crate::{config::Configurable, errors::ChromaError};
@@ -1,5 +1,12 @@
+use crate::config::{ConfigValidator, ValidateConfig};
 use serde::Deserialize;
 
+const DEFAULT_DEBOUNCE_MS: u64 = 5000;
+
+fn default_debounce_ms() -> u64 {
+    DEFAULT_DEBOUNCE_MS
+}
+
 #[derive(Deserialize)]
 /// The type of memberlist provider to use
 /// # Options
@@ -13,6 +20,8 @@ pub(crate) enum MemberlistProviderType {
 /// - CustomResource: Use a custom resource to get the memberlist
 #[derive(Deserialize)]
 pub(crate) enum MemberlistProviderConfig {
+    // case-insensitive
+    #[serde(alias = "customresource")]
     CustomResource(CustomResourceMemberlistProviderConfig),
 }
 
@@ -21,9 +30,33 @@ pub(crate) enum MemberlistProviderConfig {
 /// - kube_namespace: The namespace to use for the custom resource.
 /// - memberlist_name: The name of the custom resource to use for the memberlist.
 /// - queue_size: The size of the queue to use for the channel.
+/// - debounce_ms: How long to collect memberlist changes for before subscribers are told
+/// about them. Changes that arrive within this window, as they do during a rolling deploy,
+/// reach subscribers as one. Defaults to 5000, 0 passes every change on right away.
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct CustomResourceMemberlistProviderConfig {
     pub(crate) kube_namespace: String,
     pub(crate) memberlist_name: String,
     pub(crate) queue_size: usize,
+    #[serde(default = "default_debounce_ms")]
+    pub(crate) debounce_ms: u64,
+}
+
+impl ValidateConfig for MemberlistProviderConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        match self {
+            MemberlistProviderConfig::CustomResource(custom_resource) => {
+                validator.section("CustomResource", custom_resource)
+            }
+        }
+    }
+}
+
+impl ValidateConfig for CustomResourceMemberlistProviderConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.non_empty("kube_namespace", &self.kube_namespace);
+        validator.non_empty("memberlist_name", &self.memberlist_name);
+        validator.positive("queue_size", self.queue_size);
+    }
 }
@@ -1,4 +1,4 @@
-use std::{fmt::Debug, sync::RwLock};
+use std::{fmt::Debug, sync::RwLock, time::Duration};
 
 use super::config::MemberlistProviderConfig;
 use crate::system::Receiver;
@@ -48,6 +48,41 @@ pub(crate) struct Member {
     pub(crate) member_id: String,
 }
 
+/* =========== Debouncing ============== */
+/// Coalesces the memberlist updates that arrive within a debounce window.
+/// # Description
+/// The first update opens a window, and every update that arrives before the window
+/// closes replaces the pending one. When the window closes, the pending memberlist is only
+/// passed on if it has different members than the current one, in any order.
+#[derive(Debug, Default)]
+pub(crate) struct MemberlistUpdates {
+    pending: Option<Memberlist>,
+}
+
+impl MemberlistUpdates {
+    /// Records an update. Returns true if it opened a window, which has to be closed with
+    /// `close_window` once it has passed.
+    pub(crate) fn push(&mut self, memberlist: Memberlist) -> bool {
+        self.pending.replace(memberlist).is_none()
+    }
+
+    /// Closes the window. Returns the last memberlist pushed in it, if it changes `current`.
+    pub(crate) fn close_window(&mut self, current: &Memberlist) -> Option<Memberlist> {
+        let pending = self.pending.take()?;
+        let mut sorted_pending = pending.clone();
+        sorted_pending.sort();
+        let mut sorted_current = current.clone();
+        sorted_current.sort();
+        if sorted_pending == sorted_current {
+            return None;
+        }
+        Some(pending)
+    }
+}
+
+#[derive(Debug)]
+struct DebounceElapsed {}
+
 /* =========== CR Provider ============== */
 pub(crate) struct CustomResourceMemberlistProvider {
     memberlist_name: String,
@@ -55,6 +90,8 @@ pub(crate) struct CustomResourceMemberlistProvider {
     kube_ns: String,
     memberlist_cr_client: Api<MemberListKubeResource>,
     queue_size: usize,
+    debounce: Duration,
+    updates: MemberlistUpdates,
     current_memberlist: RwLock<Memberlist>,
     subscribers: Vec<Box<dyn Receiver<Memberlist> + Send>>,
 }
@@ -65,6 +102,7 @@ impl Debug for CustomResourceMemberlistProvider {
             .field("memberlist_name", &self.memberlist_name)
             .field("kube_ns", &self.kube_ns)
             .field("queue_size", &self.queue_size)
+            .field("debounce", &self.debounce)
             .finish()
     }
 }
@@ -112,6 +150,8 @@ impl Configurable<MemberlistProviderConfig> for CustomResourceMemberlistProvider
             kube_client,
             memberlist_cr_client,
             queue_size: my_config.queue_size,
+            debounce: Duration::from_millis(my_config.debounce_ms),
+            updates: MemberlistUpdates::default(),
             current_memberlist: RwLock::new(vec![]),
             subscribers: vec![],
         };
@@ -125,6 +165,7 @@ impl CustomResourceMemberlistProvider {
         kube_client: Client,
         kube_ns: String,
         queue_size: usize,
+        debounce: Duration,
     ) -> Self {
         let memberlist_cr_client =
             Api::<MemberListKubeResource>::namespaced(kube_client.clone(), &kube_ns);
@@ -134,6 +175,8 @@ impl CustomResourceMemberlistProvider {
             kube_client,
             memberlist_cr_client,
             queue_size,
+            debounce,
+            updates: MemberlistUpdates::default(),
             current_memberlist: RwLock::new(vec![]),
             subscribers: vec![],
         }
@@ -200,7 +243,7 @@ impl Handler<Option<MemberListKubeResource>> for CustomResourceMemberlistProvide
     async fn handle(
         &mut self,
         event: Option<MemberListKubeResource>,
-        _ctx: &ComponentContext<CustomResourceMemberlistProvider>,
+        ctx: &ComponentContext<CustomResourceMemberlistProvider>,
     ) {
         match event {
             Some(memberlist) => {
@@ -220,19 +263,14 @@ impl Handler<Option<MemberListKubeResource>> for CustomResourceMemberlistProvide
                     .iter()
                     .map(|member| member.member_id.clone())
                     .collect::<Vec<String>>();
-                {
-                    let curr_memberlist_handle = self.current_memberlist.write();
-                    match curr_memberlist_handle {
-                        Ok(mut curr_memberlist) => {
-                            *curr_memberlist = memberlist;
-                        }
-                        Err(_err) => {
-                            // TODO: Log an error
-                        }
-                    }
+                if self.updates.push(memberlist) {
+                    ctx.scheduler.schedule(
+                        ctx.sender.clone(),
+                        DebounceElapsed {},
+                        self.debounce,
+                        ctx,
+                    );
                 }
-                // Inform subscribers
-                self.notify_subscribers().await;
             }
             None => {
                 // Stream closed or error
@@ -241,6 +279,34 @@ impl Handler<Option<MemberListKubeResource>> for CustomResourceMemberlistProvide
     }
 }
 
+#[async_trait]
+impl Handler<DebounceElapsed> for CustomResourceMemberlistProvider {
+    async fn handle(
+        &mut self,
+        _message: DebounceElapsed,
+        _ctx: &ComponentContext<CustomResourceMemberlistProvider>,
+    ) {
+        {
+            let curr_memberlist_handle = self.current_memberlist.write();
+            match curr_memberlist_handle {
+                Ok(mut curr_memberlist) => match self.updates.close_window(&curr_memberlist) {
+                    Some(memberlist) => {
+                        *curr_memberlist = memberlist;
+                    }
+                    // The members are unchanged, there is nothing to tell subscribers
+                    None => return,
+                },
+                Err(_err) => {
+                    // TODO: Log an error
+                    return;
+                }
+            }
+        }
+        // Inform subscribers
+        self.notify_subscribers().await;
+    }
+}
+
 impl StreamHandler<Option<MemberListKubeResource>> for CustomResourceMemberlistProvider {}
 
 #[async_trait]
@@ -270,8 +336,106 @@ mod tests {
             kube_client.clone(),
             kube_ns.clone(),
             10,
+            Duration::from_secs(5),
         );
         let mut system = System::new();
         let handle = system.start_component(memberlist_provider);
     }
+
+    fn members(members: &[&str]) -> Memberlist {
+        members.iter().map(|member| member.to_string()).collect()
+    }
+
+    #[test]
+    fn test_memberlist_updates_are_coalesced() {
+        let mut updates = MemberlistUpdates::default();
+        let current = members(&["a", "b", "c"]);
+        assert_eq!(updates.close_window(&current), None);
+
+        // Only the first update of a window opens it, the last one is passed on.
+        assert!(updates.push(members(&["a", "b"])));
+        assert!(!updates.push(members(&["a", "b", "c", "d"])));
+        assert!(!updates.push(members(&["a", "c", "d"])));
+        assert_eq!(
+            updates.close_window(&current),
+            Some(members(&["a", "c", "d"]))
+        );
+        assert_eq!(updates.close_window(&current), None);
+
+        // A window that ends where it started changes nothing.
+        assert!(updates.push(members(&["a", "b"])));
+        assert!(!updates.push(members(&["c", "a", "b"])));
+        assert_eq!(updates.close_window(&current), None);
+        assert!(updates.push(members(&["a", "b"])));
+    }
+
+    #[test]
+    fn test_debouncing_a_rolling_deploy_reduces_churn() {
+        use crate::assignment::assignment_policy::{
+            AssignmentPolicy, RendezvousHashingAssignmentPolicy,
+        };
+
+        let collections: Vec<String> = (0..1000).map(|i| format!("collection_{}", i)).collect();
+        let mut policy = RendezvousHashingAssignmentPolicy::new();
+        // How many collections change owner when the memberlist goes from one to the next.
+        let mut churn = |from: &Memberlist, to: &Memberlist| {
+            let mut owners = Vec::new();
+            for memberlist in [from, to] {
+                policy.set_members(memberlist.clone());
+                let owner: Vec<String> = collections
+                    .iter()
+                    .map(|collection| policy.assign(collection).unwrap())
+                    .collect();
+                owners.push(owner);
+            }
+            owners[0]
+                .iter()
+                .zip(&owners[1])
+                .filter(|(from, to)| from != to)
+                .count()
+        };
+
+        // Every member of a three member fleet is restarted in turn, and updates come in
+        // seconds apart as members leave and come back.
+        let initial = members(&["a", "b", "c"]);
+        let deploy = [
+            members(&["b", "c"]),
+            members(&["a", "b", "c"]),
+            members(&["a", "c"]),
+            members(&["a", "b", "c"]),
+            members(&["a", "b"]),
+            members(&["a", "b", "c"]),
+        ];
+
+        let mut undebounced_churn = 0;
+        let mut current = initial.clone();
+        for memberlist in &deploy {
+            undebounced_churn += churn(&current, memberlist);
+            current = memberlist.clone();
+        }
+        // Every step moves about a third of the collections.
+        assert!(undebounced_churn > 1500);
+
+        // The whole deploy falls into one window, and nothing moves.
+        let mut updates = MemberlistUpdates::default();
+        for memberlist in &deploy {
+            updates.push(memberlist.clone());
+        }
+        assert_eq!(updates.close_window(&initial), None);
+
+        // A deploy that ends with a member gone only moves the collections of that member.
+        let mut updates = MemberlistUpdates::default();
+        for memberlist in deploy.iter().take(5) {
+            updates.push(memberlist.clone());
+        }
+        let last = updates.close_window(&initial).unwrap();
+        assert_eq!(last, members(&["a", "b"]));
+        let moved = churn(&initial, &last);
+        policy.set_members(initial.clone());
+        let owned_by_c = collections
+            .iter()
+            .filter(|collection| policy.assign(collection).unwrap() == "c")
+            .count();
+        assert_eq!(moved, owned_by_c);
+    }
 }
@@ -1,17 +1,22 @@
-use super::record_segment::ApplyMaterializedLogError;
+use super::record_segment::{ApplyMaterializedLogError, RecordSegmentReader};
 use super::{SegmentFlusher, SegmentWriter};
+use crate::blockstore::provider::BlockfileProvider;
+use crate::distance::{normalize, VectorSlice};
 use crate::errors::{ChromaError, ErrorCodes};
+use crate::execution::compute_pool::ComputePool;
 use crate::index::hnsw_provider::{
     HnswIndexProvider, HnswIndexProviderCommitError, HnswIndexProviderCreateError,
     HnswIndexProviderFlushError, HnswIndexProviderForkError, HnswIndexProviderOpenError,
 };
 use crate::index::{
-    HnswIndex, HnswIndexConfig, HnswIndexFromSegmentError, Index, IndexConfig,
-    IndexConfigFromSegmentError,
+    BruteForceIndex, HnswIndex, HnswIndexConfig, HnswIndexFromSegmentError, HnswIndexStats, Index,
+    IndexConfig, IndexConfigFromSegmentError,
 };
-use crate::types::{LogRecord, Operation, Segment};
+use crate::types::{KnnQuery, LogRecord, Operation, Segment};
 use async_trait::async_trait;
 use parking_lot::RwLock;
+use rayon::prelude::*;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::Arc;
@@ -20,11 +25,20 @@ use uuid::Uuid;
 
 const HNSW_INDEX: &str = "hnsw_index";
 
+/// The id of the index the files of `segment` hold, if it has been flushed.
+pub(crate) fn hnsw_index_id(segment: &Segment) -> Option<Uuid> {
+    let files = segment.file_path.get(HNSW_INDEX)?;
+    Uuid::parse_str(files.first()?).ok()
+}
+
 #[derive(Clone)]
 pub(crate) struct DistributedHNSWSegmentWriter {
     index: Arc<RwLock<HnswIndex>>,
     hnsw_index_provider: HnswIndexProvider,
     pub(crate) id: Uuid,
+    // Whether embeddings are normalized before they are added to the index, which is the
+    // case for distance functions that can then use `distance_normalized`.
+    normalize_embeddings: bool,
 }
 
 impl Debug for DistributedHNSWSegmentWriter {
@@ -43,7 +57,7 @@ pub enum DistributedHNSWSegmentFromSegmentError {
     Uninitialized,
     #[error("Index configuration error")]
     IndexConfigError(#[from] IndexConfigFromSegmentError),
-    #[error("HNSW index configuration error")]
+    #[error("HNSW index configuration error: {0}")]
     HnswIndexConfigError(#[from] HnswIndexFromSegmentError),
     #[error("HNSW index provider open error")]
     HnswIndexProviderOpenError(#[from] HnswIndexProviderOpenError),
@@ -68,23 +82,188 @@ impl ChromaError for DistributedHNSWSegmentFromSegmentError {
     }
 }
 
+// Reads the embedding of every record in the record segment, normalized the way the index
+// stores them. This is how a segment whose saved index is missing or corrupted is rebuilt
+// without replaying the log.
+async fn embeddings_for_rebuild(
+    record_segment: &Segment,
+    blockfile_provider: &BlockfileProvider,
+    normalize_embeddings: bool,
+) -> Result<Vec<(u32, Vec<f32>)>, Box<dyn ChromaError>> {
+    let record_segment_reader =
+        match RecordSegmentReader::from_segment(record_segment, blockfile_provider).await {
+            Ok(reader) => reader,
+            Err(e) => return Err(e),
+        };
+    let mut embeddings = record_segment_reader.get_all_embeddings().await?;
+    if normalize_embeddings {
+        for (_, embedding) in embeddings.iter_mut() {
+            normalize(embedding);
+        }
+    }
+    Ok(embeddings)
+}
+
+/// Something a check of the index of an HNSW segment found wrong with it.
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum HnswSegmentDiscrepancy {
+    #[error("The record segment has {records} records but the segment has no index")]
+    MissingIndex { records: usize },
+    #[error("The index does not load: {0}")]
+    Unloadable(String),
+    #[error("The index has dimension {index} but the collection has {collection:?}")]
+    DimensionMismatch {
+        index: usize,
+        collection: Option<i32>,
+    },
+    #[error("The index holds {index} elements but the record segment has {records} records")]
+    ElementCountMismatch { index: usize, records: usize },
+}
+
+/// Checks that the index flushed for `segment` loads, has the `dimension` of the collection
+/// and holds an element for every record of `record_segment`, which is flushed along with
+/// it.
+/// # Returns
+/// The stats of the index as loaded from its flushed files, None if the segment has no
+/// index or it does not load, and what was found wrong with it.
+/// # Notes
+/// The index is loaded from storage even if it is cached, see `HnswIndexProvider::verify`.
+pub(crate) async fn check_hnsw_segment(
+    segment: &Segment,
+    record_segment: &Segment,
+    dimension: Option<i32>,
+    hnsw_index_provider: &HnswIndexProvider,
+    blockfile_provider: &BlockfileProvider,
+) -> Result<(Option<HnswIndexStats>, Vec<HnswSegmentDiscrepancy>), Box<dyn ChromaError>> {
+    // A record segment that was never flushed has no records.
+    let records = if record_segment.file_path.is_empty() {
+        0
+    } else {
+        match RecordSegmentReader::from_segment(record_segment, blockfile_provider).await {
+            Ok(reader) => reader.count().await?,
+            Err(e) => return Err(e),
+        }
+    };
+    let index_id = match hnsw_index_id(segment) {
+        Some(index_id) => index_id,
+        None if records == 0 => return Ok((None, Vec::new())),
+        None => return Ok((None, vec![HnswSegmentDiscrepancy::MissingIndex { records }])),
+    };
+    // The index is loaded with the dimension it was saved with, so that an index of another
+    // dimension than the collection's is reported as such rather than as one that does not
+    // load.
+    let saved = match hnsw_index_provider.stats(&index_id).await {
+        Ok(saved) => saved.index,
+        Err(e) => {
+            return Ok((
+                None,
+                vec![HnswSegmentDiscrepancy::Unloadable(e.to_string())],
+            ))
+        }
+    };
+    let mut discrepancies = Vec::new();
+    if dimension != Some(saved.dimensionality as i32) {
+        discrepancies.push(HnswSegmentDiscrepancy::DimensionMismatch {
+            index: saved.dimensionality,
+            collection: dimension,
+        });
+    }
+    let loaded = match hnsw_index_provider
+        .verify(&index_id, segment, saved.dimensionality as i32)
+        .await
+    {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            discrepancies.push(HnswSegmentDiscrepancy::Unloadable(e.to_string()));
+            return Ok((None, discrepancies));
+        }
+    };
+    if loaded.element_count != records {
+        discrepancies.push(HnswSegmentDiscrepancy::ElementCountMismatch {
+            index: loaded.element_count,
+            records,
+        });
+    }
+    Ok((Some(loaded), discrepancies))
+}
+
 impl DistributedHNSWSegmentWriter {
     pub(crate) fn new(
         index: Arc<RwLock<HnswIndex>>,
         hnsw_index_provider: HnswIndexProvider,
         id: Uuid,
+        normalize_embeddings: bool,
     ) -> Self {
         return DistributedHNSWSegmentWriter {
             index,
             hnsw_index_provider,
             id,
+            normalize_embeddings,
+        };
+    }
+
+    /// The dimension of the embeddings in the index.
+    pub(crate) fn dimension(&self) -> usize {
+        self.index.read().dimensionality()
+    }
+
+    fn prepare_embedding<'e>(&self, embedding: VectorSlice<'e>) -> Cow<'e, [f32]> {
+        // The index only holds float32s, so quantized embeddings are dequantized here.
+        let embedding = embedding.to_f32();
+        if self.normalize_embeddings {
+            let mut normalized = embedding.into_owned();
+            normalize(&mut normalized);
+            Cow::Owned(normalized)
+        } else {
+            embedding
+        }
+    }
+
+    /// A writer of a new index `index_id` holding the embeddings of the record segment,
+    /// instead of a fork of the index the segment has. This is how an index that has
+    /// degraded, like one with many deleted elements, is replaced.
+    pub(crate) async fn rebuilt_from_segment(
+        segment: &Segment,
+        dimensionality: usize,
+        hnsw_index_provider: HnswIndexProvider,
+        record_segment: &Segment,
+        blockfile_provider: &BlockfileProvider,
+        index_id: Uuid,
+    ) -> Result<Box<DistributedHNSWSegmentWriter>, Box<dyn ChromaError>> {
+        let index_config = match IndexConfig::from_segment(segment, dimensionality as i32) {
+            Ok(index_config) => index_config,
+            Err(e) => return Err(e),
+        };
+        let normalize_embeddings = index_config.distance_function.normalizes_embeddings();
+        // A record segment that was never flushed has no records to read.
+        let embeddings = if record_segment.file_path.is_empty() {
+            Vec::new()
+        } else {
+            embeddings_for_rebuild(record_segment, blockfile_provider, normalize_embeddings).await?
+        };
+        let index = match hnsw_index_provider.rebuild(
+            &index_id,
+            segment,
+            dimensionality as i32,
+            &embeddings,
+        ) {
+            Ok(index) => index,
+            Err(e) => return Err(e),
         };
+        Ok(Box::new(DistributedHNSWSegmentWriter::new(
+            index,
+            hnsw_index_provider,
+            segment.id,
+            normalize_embeddings,
+        )))
     }
 
     pub(crate) async fn from_segment(
         segment: &Segment,
         dimensionality: usize,
         hnsw_index_provider: HnswIndexProvider,
+        record_segment: &Segment,
+        blockfile_provider: &BlockfileProvider,
     ) -> Result<Box<DistributedHNSWSegmentWriter>, Box<DistributedHNSWSegmentFromSegmentError>>
     {
         let index_config = match IndexConfig::from_segment(&segment, dimensionality as i32) {
@@ -95,6 +274,7 @@ impl DistributedHNSWSegmentWriter {
                 ));
             }
         };
+        let normalize_embeddings = index_config.distance_function.normalizes_embeddings();
         let persist_path = &hnsw_index_provider.temporary_storage_path;
 
         let hnsw_config = match HnswIndexConfig::from_segment(segment, persist_path) {
@@ -143,6 +323,48 @@ impl DistributedHNSWSegmentWriter {
                 .await
             {
                 Ok(index) => index,
+                Err(e) if e.index_files_unusable() => {
+                    tracing::error!(
+                        "HNSW index {} could not be loaded, rebuilding it from the record segment: {:?}",
+                        index_uuid,
+                        e
+                    );
+                    let embeddings = match embeddings_for_rebuild(
+                        record_segment,
+                        blockfile_provider,
+                        normalize_embeddings,
+                    )
+                    .await
+                    {
+                        Ok(embeddings) => embeddings,
+                        Err(rebuild_error) => {
+                            tracing::error!(
+                                "Failed to read the record segment to rebuild HNSW index {}: {:?}",
+                                index_uuid,
+                                rebuild_error
+                            );
+                            return Err(Box::new(
+                                DistributedHNSWSegmentFromSegmentError::HnswIndexProviderForkError(
+                                    *e,
+                                ),
+                            ));
+                        }
+                    };
+                    // Like a fork, the rebuilt index gets a new id.
+                    match hnsw_index_provider.rebuild(
+                        &Uuid::new_v4(),
+                        segment,
+                        dimensionality as i32,
+                        &embeddings,
+                    ) {
+                        Ok(index) => index,
+                        Err(e) => return Err(Box::new(
+                            DistributedHNSWSegmentFromSegmentError::HnswIndexProviderCreateError(
+                                *e,
+                            ),
+                        )),
+                    }
+                }
                 Err(e) => {
                     return Err(Box::new(
                         DistributedHNSWSegmentFromSegmentError::HnswIndexProviderForkError(*e),
@@ -154,6 +376,7 @@ impl DistributedHNSWSegmentWriter {
                 index,
                 hnsw_index_provider,
                 segment.id,
+                normalize_embeddings,
             )))
         } else {
             let index = match hnsw_index_provider.create(segment, dimensionality as i32) {
@@ -168,6 +391,7 @@ impl DistributedHNSWSegmentWriter {
                 index,
                 hnsw_index_provider,
                 segment.id,
+                normalize_embeddings,
             )))
         }
     }
@@ -187,7 +411,7 @@ impl<'a> SegmentWriter<'a> for DistributedHNSWSegmentWriter {
                     let embedding = match record.final_embedding {
                         Some(e) => e,
                         None => match record.data_record.as_ref() {
-                            Some(record) => record.embedding,
+                            Some(record) => VectorSlice::F32(record.embedding),
                             None => {
                                 tracing::error!("Embedding not set for record {:?}", record);
                                 return Err(ApplyMaterializedLogError::EmbeddingNotSet);
@@ -195,17 +419,12 @@ impl<'a> SegmentWriter<'a> for DistributedHNSWSegmentWriter {
                         },
                     };
 
-                    let mut index = self.index.upgradable_read();
-                    let index_len = index.len();
-                    let index_capacity = index.capacity();
-                    if index_len + 1 > index_capacity {
-                        index.with_upgraded(|index| {
-                            // Bump allocation by 2x
-                            index.resize(index_capacity * 2);
-                        });
-                    }
-
-                    index.add(record.offset_id as usize, embedding);
+                    HnswIndex::add_growing(
+                        &self.index,
+                        record.offset_id as usize,
+                        &self.prepare_embedding(embedding),
+                        self.hnsw_index_provider.capacity_growth_factor,
+                    );
                 }
                 // This shouldn't be reached since materialization always derefs
                 // upserts into either updates or inserts.
@@ -221,7 +440,7 @@ impl<'a> SegmentWriter<'a> for DistributedHNSWSegmentWriter {
                     let embedding = match record.final_embedding {
                         Some(e) => e,
                         None => match record.data_record.as_ref() {
-                            Some(record) => record.embedding,
+                            Some(record) => VectorSlice::F32(record.embedding),
                             None => {
                                 panic!("Invariant violation. Embedding not found on storage");
                             }
@@ -231,15 +450,22 @@ impl<'a> SegmentWriter<'a> for DistributedHNSWSegmentWriter {
                     // will update the embedding if it exists. It does not
                     // perform any validation on its own and assumes that the
                     // offset ids are correct (i.e. pertaining to records that
-                    // are actually meant to be updated).
-                    self.index.read().add(record.offset_id as usize, embedding);
+                    // are actually meant to be updated). Like adds, it takes
+                    // the upgradable lock so that it is kept out of a save.
+                    self.index.upgradable_read().add(
+                        record.offset_id as usize,
+                        &self.prepare_embedding(embedding),
+                    );
                 }
                 Operation::Delete => {
                     // HNSW segment does not perform validation of any sort. So,
                     // the assumption here is that the materialized log records
                     // contain the correct offset ids pertaining to records that
-                    // are actually meant to be deleted.
-                    self.index.read().delete(record.offset_id as usize);
+                    // are actually meant to be deleted. Deleting an offset id that is
+                    // not in the index is a no-op.
+                    self.index
+                        .upgradable_read()
+                        .delete(record.offset_id as usize);
                 }
             }
         }
@@ -270,9 +496,17 @@ impl SegmentFlusher for DistributedHNSWSegmentWriter {
     }
 }
 
+// The index a reader queries. Segments with few records are scanned by brute force rather
+// than loading their HNSW index, see HnswProviderConfig::brute_force_threshold.
+#[derive(Clone)]
+enum ReaderIndex {
+    Hnsw(Arc<RwLock<HnswIndex>>),
+    BruteForce(Arc<BruteForceIndex>),
+}
+
 #[derive(Clone)]
 pub(crate) struct DistributedHNSWSegmentReader {
-    index: Arc<RwLock<HnswIndex>>,
+    index: ReaderIndex,
     hnsw_index_provider: HnswIndexProvider,
     pub(crate) id: Uuid,
 }
@@ -284,11 +518,7 @@ impl Debug for DistributedHNSWSegmentReader {
 }
 
 impl DistributedHNSWSegmentReader {
-    fn new(
-        index: Arc<RwLock<HnswIndex>>,
-        hnsw_index_provider: HnswIndexProvider,
-        id: Uuid,
-    ) -> Self {
+    fn new(index: ReaderIndex, hnsw_index_provider: HnswIndexProvider, id: Uuid) -> Self {
         return DistributedHNSWSegmentReader {
             index,
             hnsw_index_provider,
@@ -300,6 +530,8 @@ impl DistributedHNSWSegmentReader {
         segment: &Segment,
         dimensionality: usize,
         hnsw_index_provider: HnswIndexProvider,
+        record_segment: &Segment,
+        blockfile_provider: &BlockfileProvider,
     ) -> Result<Box<DistributedHNSWSegmentReader>, Box<DistributedHNSWSegmentFromSegmentError>>
     {
         let index_config = IndexConfig::from_segment(&segment, dimensionality as i32);
@@ -345,11 +577,74 @@ impl DistributedHNSWSegmentReader {
                 }
             };
 
+            match Self::open_brute_force(
+                &index_uuid,
+                segment,
+                dimensionality,
+                &hnsw_index_provider,
+                record_segment,
+                blockfile_provider,
+                index_config.distance_function.normalizes_embeddings(),
+            )
+            .await
+            {
+                Ok(Some(index)) => {
+                    return Ok(Box::new(DistributedHNSWSegmentReader::new(
+                        ReaderIndex::BruteForce(index),
+                        hnsw_index_provider,
+                        segment.id,
+                    )))
+                }
+                Ok(None) => {}
+                Err(e) => return Err(e),
+            }
+
             let index = match hnsw_index_provider
                 .open(&index_uuid, segment, dimensionality as i32)
                 .await
             {
                 Ok(index) => index,
+                Err(e) if e.index_files_unusable() => {
+                    tracing::error!(
+                        "HNSW index {} could not be loaded, rebuilding it from the record segment: {:?}",
+                        index_uuid,
+                        e
+                    );
+                    let embeddings = match embeddings_for_rebuild(
+                        record_segment,
+                        blockfile_provider,
+                        index_config.distance_function.normalizes_embeddings(),
+                    )
+                    .await
+                    {
+                        Ok(embeddings) => embeddings,
+                        Err(rebuild_error) => {
+                            tracing::error!(
+                                "Failed to read the record segment to rebuild HNSW index {}: {:?}",
+                                index_uuid,
+                                rebuild_error
+                            );
+                            return Err(Box::new(
+                                DistributedHNSWSegmentFromSegmentError::HnswIndexProviderOpenError(
+                                    *e,
+                                ),
+                            ));
+                        }
+                    };
+                    match hnsw_index_provider.rebuild(
+                        &index_uuid,
+                        segment,
+                        dimensionality as i32,
+                        &embeddings,
+                    ) {
+                        Ok(index) => index,
+                        Err(e) => return Err(Box::new(
+                            DistributedHNSWSegmentFromSegmentError::HnswIndexProviderCreateError(
+                                *e,
+                            ),
+                        )),
+                    }
+                }
                 Err(e) => {
                     return Err(Box::new(
                         DistributedHNSWSegmentFromSegmentError::HnswIndexProviderOpenError(*e),
@@ -358,7 +653,7 @@ impl DistributedHNSWSegmentReader {
             };
 
             Ok(Box::new(DistributedHNSWSegmentReader::new(
-                index,
+                ReaderIndex::Hnsw(index),
                 hnsw_index_provider,
                 segment.id,
             )))
@@ -369,14 +664,83 @@ impl DistributedHNSWSegmentReader {
         }
     }
 
-    pub(crate) fn query(
+    /// Answers each of `queries`, in the order given, searching them in parallel on
+    /// `compute_pool`. `ef_search` overrides the configured ef of an HNSW index, a brute
+    /// force index is exact so there is no ef to trade recall for latency.
+    pub(crate) fn query_batch(
         &self,
-        vector: &[f32],
-        k: usize,
+        queries: &[KnnQuery],
         allowed_ids: &[usize],
-        disallowd_ids: &[usize],
-    ) -> (Vec<usize>, Vec<f32>) {
-        let index = self.index.read();
-        index.query(vector, k, allowed_ids, disallowd_ids)
+        disallowed_ids: &[usize],
+        ef_search: Option<usize>,
+        compute_pool: &ComputePool,
+    ) -> Vec<(Vec<usize>, Vec<f32>)> {
+        compute_pool.install(|| match &self.index {
+            ReaderIndex::Hnsw(index) => HnswIndex::query_batch_with_ef(
+                index,
+                queries,
+                allowed_ids,
+                disallowed_ids,
+                ef_search,
+            ),
+            ReaderIndex::BruteForce(index) => queries
+                .par_iter()
+                .map(|query| index.query(&query.embedding, query.k, allowed_ids, disallowed_ids))
+                .collect(),
+        })
+    }
+
+    // Returns a brute force index holding the record segment's embeddings when it has at
+    // most brute_force_threshold records, or None when the HNSW index should be used. Falls
+    // back to the HNSW index when the record segment can not be read.
+    async fn open_brute_force(
+        index_uuid: &Uuid,
+        segment: &Segment,
+        dimensionality: usize,
+        hnsw_index_provider: &HnswIndexProvider,
+        record_segment: &Segment,
+        blockfile_provider: &BlockfileProvider,
+        normalize_embeddings: bool,
+    ) -> Result<Option<Arc<BruteForceIndex>>, Box<DistributedHNSWSegmentFromSegmentError>> {
+        if hnsw_index_provider.brute_force_threshold == 0 {
+            return Ok(None);
+        }
+        if let Some(index) = hnsw_index_provider.get_brute_force(index_uuid) {
+            return Ok(Some(index));
+        }
+        let record_segment_reader =
+            match RecordSegmentReader::from_segment(record_segment, blockfile_provider).await {
+                Ok(reader) => reader,
+                Err(_) => return Ok(None),
+            };
+        match record_segment_reader.count().await {
+            Ok(count) if count <= hnsw_index_provider.brute_force_threshold => {}
+            _ => return Ok(None),
+        }
+        let embeddings =
+            match embeddings_for_rebuild(record_segment, blockfile_provider, normalize_embeddings)
+                .await
+            {
+                Ok(embeddings) => embeddings,
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to read the record segment for brute force index {}: {:?}",
+                        index_uuid,
+                        e
+                    );
+                    return Ok(None);
+                }
+            };
+        match hnsw_index_provider.create_brute_force(
+            index_uuid,
+            segment,
+            dimensionality as i32,
+            &embeddings,
+        ) {
+            Ok(index) => Ok(Some(index)),
+            Err(e) => Err(Box::new(
+                DistributedHNSWSegmentFromSegmentError::HnswIndexProviderCreateError(*e),
+            )),
+        }
     }
 }
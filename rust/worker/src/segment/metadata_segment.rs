@@ -4,7 +4,7 @@ use core::panic;
 use futures::future::BoxFuture;
 use futures::FutureExt;
 use roaring::RoaringBitmap;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Debug, Formatter};
 use std::u32;
 use tantivy::tokenizer::NgramTokenizer;
@@ -423,6 +423,52 @@ impl<'me> MetadataSegmentWriter<'me> {
 
         Ok(())
     }
+
+    // The index of the metadata values of the type of `value`.
+    fn metadata_index_writer(&self, value: &MetadataValue) -> &MetadataIndexWriter<'me> {
+        let writer = match value {
+            MetadataValue::Str(_) => &self.string_metadata_index_writer,
+            MetadataValue::Float(_) => &self.f32_metadata_index_writer,
+            MetadataValue::Int(_) => &self.u32_metadata_index_writer,
+            MetadataValue::Bool(_) => &self.bool_metadata_index_writer,
+        };
+        match writer {
+            Some(writer) => writer,
+            None => panic!("Invariant violation. Metadata index writers should be set"),
+        }
+    }
+
+    // Adds the offset id to the posting list of `value` under `key`.
+    async fn set_metadata(
+        &self,
+        key: &str,
+        value: &MetadataValue,
+        offset_id: u32,
+    ) -> Result<(), MetadataIndexError> {
+        let writer = self.metadata_index_writer(value);
+        match value {
+            MetadataValue::Str(value) => writer.set(key, value.as_str(), offset_id).await,
+            MetadataValue::Float(value) => writer.set(key, *value as f32, offset_id).await,
+            MetadataValue::Int(value) => writer.set(key, *value as u32, offset_id).await,
+            MetadataValue::Bool(value) => writer.set(key, *value, offset_id).await,
+        }
+    }
+
+    // Removes the offset id from the posting list of `value` under `key`.
+    async fn delete_metadata(
+        &self,
+        key: &str,
+        value: &MetadataValue,
+        offset_id: u32,
+    ) -> Result<(), MetadataIndexError> {
+        let writer = self.metadata_index_writer(value);
+        match value {
+            MetadataValue::Str(value) => writer.delete(key, value.as_str(), offset_id).await,
+            MetadataValue::Float(value) => writer.delete(key, *value as f32, offset_id).await,
+            MetadataValue::Int(value) => writer.delete(key, *value as u32, offset_id).await,
+            MetadataValue::Bool(value) => writer.delete(key, *value, offset_id).await,
+        }
+    }
 }
 
 impl<'log_records> SegmentWriter<'log_records> for MetadataSegmentWriter<'_> {
@@ -578,320 +624,94 @@ impl<'log_records> SegmentWriter<'log_records> for MetadataSegmentWriter<'_> {
                                     return Err(ApplyMaterializedLogError::BlockfileDeleteError);
                                 }
                             },
-                            None => {
-                                return Err(ApplyMaterializedLogError::BlockfileDeleteError);
-                            }
+                            // Records are not required to have a document.
+                            None => {}
                         };
                     }
                     None => {}
                 },
                 Operation::Update => {
-                    let metadata_delta = record.0.metadata_delta();
-                    // Updates.
-                    for (update_key, (old_value, new_value)) in metadata_delta.metadata_to_update {
-                        match new_value {
-                            MetadataValue::Str(new_val_str) => match old_value {
-                                MetadataValue::Str(old_val_str) => {
-                                    match &self.string_metadata_index_writer {
-                                        Some(writer) => {
-                                            match writer
-                                                .update(
-                                                    update_key,
-                                                    old_val_str.as_str().into(),
-                                                    new_val_str.as_str().into(),
-                                                    segment_offset_id,
-                                                )
-                                                .await
-                                            {
-                                                Ok(()) => {}
-                                                Err(e) => {
-                                                    return Err(ApplyMaterializedLogError::BlockfileUpdateError);
-                                                }
-                                            }
-                                        }
-                                        None => {
-                                            panic!("Invariant violation. String metadata index writer should be set");
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    return Err(ApplyMaterializedLogError::MetadataUpdateNotValid);
-                                }
-                            },
-                            MetadataValue::Float(new_val_float) => match old_value {
-                                MetadataValue::Float(old_val_float) => {
-                                    match &self.f32_metadata_index_writer {
-                                        Some(writer) => {
-                                            match writer
-                                                .update(
-                                                    update_key,
-                                                    (*old_val_float as f32).into(),
-                                                    (*new_val_float as f32).into(),
-                                                    segment_offset_id,
-                                                )
-                                                .await
-                                            {
-                                                Ok(()) => {}
-                                                Err(e) => {
-                                                    return Err(ApplyMaterializedLogError::BlockfileUpdateError);
-                                                }
-                                            }
-                                        }
-                                        None => {
-                                            panic!("Invariant violation. Float metadata index writer should be set");
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    return Err(ApplyMaterializedLogError::MetadataUpdateNotValid);
-                                }
-                            },
-                            MetadataValue::Int(new_val_int) => match old_value {
-                                MetadataValue::Int(old_val_int) => {
-                                    match &self.u32_metadata_index_writer {
-                                        Some(writer) => {
-                                            match writer
-                                                .update(
-                                                    update_key,
-                                                    (*old_val_int as u32).into(),
-                                                    (*new_val_int as u32).into(),
-                                                    segment_offset_id,
-                                                )
-                                                .await
-                                            {
-                                                Ok(()) => {}
-                                                Err(e) => {
-                                                    return Err(ApplyMaterializedLogError::BlockfileUpdateError);
-                                                }
-                                            }
-                                        }
-                                        None => {
-                                            panic!("Invariant violation. u32 metadata index writer should be set");
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    return Err(ApplyMaterializedLogError::MetadataUpdateNotValid);
-                                }
-                            },
-                            MetadataValue::Bool(new_val_bool) => match old_value {
-                                MetadataValue::Bool(old_val_bool) => {
-                                    match &self.bool_metadata_index_writer {
-                                        Some(writer) => {
-                                            match writer
-                                                .update(
-                                                    update_key,
-                                                    (*old_val_bool).into(),
-                                                    (*new_val_bool).into(),
-                                                    segment_offset_id,
-                                                )
-                                                .await
-                                            {
-                                                Ok(()) => {}
-                                                Err(e) => {
-                                                    return Err(ApplyMaterializedLogError::BlockfileUpdateError);
-                                                }
-                                            }
-                                        }
-                                        None => {
-                                            panic!("Invariant violation. Bool metadata index writer should be set");
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    return Err(ApplyMaterializedLogError::MetadataUpdateNotValid);
-                                }
-                            },
-                        }
-                    }
-                    // Inserts.
-                    for (insert_key, new_value) in metadata_delta.metadata_to_insert {
-                        match new_value {
-                            MetadataValue::Str(new_val_str) => {
-                                match &self.string_metadata_index_writer {
-                                    Some(writer) => {
-                                        match writer
-                                            .set(
-                                                insert_key,
-                                                new_val_str.as_str(),
-                                                segment_offset_id,
-                                            )
-                                            .await
-                                        {
-                                            Ok(()) => {}
-                                            Err(e) => {
-                                                return Err(
-                                                    ApplyMaterializedLogError::BlockfileSetError,
-                                                );
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        panic!("Invariant violation. String metadata index writer should be set");
+                    let old_document = record
+                        .0
+                        .data_record
+                        .as_ref()
+                        .and_then(|data_record| data_record.document);
+                    match (old_document, record.0.final_document) {
+                        (Some(old_document), Some(new_document))
+                            if old_document != new_document =>
+                        {
+                            match &self.full_text_index_writer {
+                                Some(writer) => {
+                                    if writer
+                                        .update_document(
+                                            old_document,
+                                            new_document,
+                                            segment_offset_id,
+                                        )
+                                        .await
+                                        .is_err()
+                                    {
+                                        return Err(
+                                            ApplyMaterializedLogError::BlockfileUpdateError,
+                                        );
                                     }
                                 }
-                            }
-                            MetadataValue::Float(new_val_float) => {
-                                match &self.f32_metadata_index_writer {
-                                    Some(writer) => {
-                                        match writer
-                                            .set(
-                                                insert_key,
-                                                *new_val_float as f32,
-                                                segment_offset_id,
-                                            )
-                                            .await
-                                        {
-                                            Ok(()) => {}
-                                            Err(e) => {
-                                                return Err(
-                                                    ApplyMaterializedLogError::BlockfileSetError,
-                                                );
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        panic!("Invariant violation. Float metadata index writer should be set");
-                                    }
+                                None => {
+                                    panic!(
+                                        "Invariant violation. Full text index writer should be set"
+                                    );
                                 }
                             }
-                            MetadataValue::Int(new_val_int) => {
-                                match &self.u32_metadata_index_writer {
-                                    Some(writer) => {
-                                        match writer
-                                            .set(insert_key, *new_val_int as u32, segment_offset_id)
-                                            .await
-                                        {
-                                            Ok(()) => {}
-                                            Err(e) => {
-                                                return Err(
-                                                    ApplyMaterializedLogError::BlockfileSetError,
-                                                );
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        panic!("Invariant violation. Int metadata index writer should be set");
-                                    }
+                        }
+                        (None, Some(new_document)) => match &self.full_text_index_writer {
+                            Some(writer) => {
+                                if writer
+                                    .add_document(new_document, segment_offset_id as i32)
+                                    .await
+                                    .is_err()
+                                {
+                                    return Err(ApplyMaterializedLogError::BlockfileSetError);
                                 }
                             }
-                            MetadataValue::Bool(new_val_bool) => {
-                                match &self.bool_metadata_index_writer {
-                                    Some(writer) => {
-                                        match writer
-                                            .set(insert_key, *new_val_bool, segment_offset_id)
-                                            .await
-                                        {
-                                            Ok(()) => {}
-                                            Err(e) => {
-                                                return Err(
-                                                    ApplyMaterializedLogError::BlockfileSetError,
-                                                );
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        panic!("Invariant violation. Bool metadata index writer should be set");
-                                    }
-                                }
+                            None => {
+                                panic!("Invariant violation. Full text index writer should be set");
                             }
+                        },
+                        // The document is unchanged.
+                        _ => {}
+                    }
+                    let metadata_delta = record.0.metadata_delta();
+                    // The old value of an updated key loses the record and the new value
+                    // gains it. They are in different indexes if the key changed its type.
+                    for (update_key, (old_value, new_value)) in metadata_delta.metadata_to_update {
+                        if self
+                            .delete_metadata(update_key, old_value, segment_offset_id)
+                            .await
+                            .is_err()
+                            || self
+                                .set_metadata(update_key, new_value, segment_offset_id)
+                                .await
+                                .is_err()
+                        {
+                            return Err(ApplyMaterializedLogError::BlockfileUpdateError);
+                        }
+                    }
+                    for (insert_key, new_value) in metadata_delta.metadata_to_insert {
+                        if self
+                            .set_metadata(insert_key, new_value, segment_offset_id)
+                            .await
+                            .is_err()
+                        {
+                            return Err(ApplyMaterializedLogError::BlockfileSetError);
                         }
                     }
-                    // Deletes.
                     for (delete_key, old_value) in metadata_delta.metadata_to_delete {
-                        match old_value {
-                            MetadataValue::Str(old_val_str) => {
-                                match &self.string_metadata_index_writer {
-                                    Some(writer) => {
-                                        match writer
-                                            .delete(
-                                                delete_key,
-                                                old_val_str.as_str(),
-                                                segment_offset_id,
-                                            )
-                                            .await
-                                        {
-                                            Ok(()) => {}
-                                            Err(e) => {
-                                                return Err(
-                                                    ApplyMaterializedLogError::BlockfileDeleteError,
-                                                );
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        panic!("Invariant violation. String metadata index writer should be set");
-                                    }
-                                }
-                            }
-                            MetadataValue::Float(old_val_float) => {
-                                match &self.f32_metadata_index_writer {
-                                    Some(writer) => {
-                                        match writer
-                                            .delete(
-                                                delete_key,
-                                                *old_val_float as f32,
-                                                segment_offset_id,
-                                            )
-                                            .await
-                                        {
-                                            Ok(()) => {}
-                                            Err(e) => {
-                                                return Err(
-                                                    ApplyMaterializedLogError::BlockfileDeleteError,
-                                                );
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        panic!("Invariant violation. Float metadata index writer should be set");
-                                    }
-                                }
-                            }
-                            MetadataValue::Int(old_val_int) => {
-                                match &self.u32_metadata_index_writer {
-                                    Some(writer) => {
-                                        match writer
-                                            .delete(
-                                                delete_key,
-                                                *old_val_int as u32,
-                                                segment_offset_id,
-                                            )
-                                            .await
-                                        {
-                                            Ok(()) => {}
-                                            Err(e) => {
-                                                return Err(
-                                                    ApplyMaterializedLogError::BlockfileDeleteError,
-                                                );
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        panic!("Invariant violation. Int metadata index writer should be set");
-                                    }
-                                }
-                            }
-                            MetadataValue::Bool(old_val_bool) => {
-                                match &self.bool_metadata_index_writer {
-                                    Some(writer) => {
-                                        match writer
-                                            .set(delete_key, *old_val_bool, segment_offset_id)
-                                            .await
-                                        {
-                                            Ok(()) => {}
-                                            Err(e) => {
-                                                return Err(
-                                                    ApplyMaterializedLogError::BlockfileDeleteError,
-                                                );
-                                            }
-                                        }
-                                    }
-                                    None => {
-                                        panic!("Invariant violation. Bool metadata index writer should be set");
-                                    }
-                                }
-                            }
+                        if self
+                            .delete_metadata(delete_key, old_value, segment_offset_id)
+                            .await
+                            .is_err()
+                        {
+                            return Err(ApplyMaterializedLogError::BlockfileDeleteError);
                         }
                     }
                 }
@@ -1244,10 +1064,15 @@ impl MetadataSegmentReader<'_> {
         })
     }
 
+    /// Returns the sorted offset ids of the records that match both clauses, or None if
+    /// neither is given. `all_offset_ids` are the sorted offset ids of every record in the
+    /// segment, which `$not_contains` matches against; it is only read when the
+    /// where_document clause has a `$not_contains`.
     pub async fn query(
         &self,
         where_clause: Option<&Where>,
         where_document_clause: Option<&WhereDocument>,
+        all_offset_ids: &[usize],
         _allowed_ids: Option<&Vec<usize>>,
         limit: usize,
         offset: usize,
@@ -1258,24 +1083,23 @@ impl MetadataSegmentReader<'_> {
         // TODO we can do lots of clever query planning here. For now, just
         // run through the Where and WhereDocument clauses sequentially.
         let where_results = match where_clause {
-            Some(where_clause) => {
-                match self.process_where_clause(where_clause).await.map_err(|e| e) {
-                    Ok(results) => {
-                        tracing::info!(
-                            "Filtered {} records from metadata segment based on where clause",
-                            results.len()
-                        );
-                        Some(results)
-                    }
-                    Err(e) => {
-                        tracing::error!(
-                            "Error fetching results from metadata segment based on where clause {:?}",
-                            e
-                        );
-                        return Err(MetadataSegmentError::MetadataIndexQueryError(e));
-                    }
+            Some(where_clause) => match self.process_where_clause(where_clause).await {
+                Ok(results) => {
+                    let results: Vec<usize> = results.iter().map(|x| x as usize).collect();
+                    tracing::info!(
+                        "Filtered {} records from metadata segment based on where clause",
+                        results.len()
+                    );
+                    Some(results)
                 }
-            }
+                Err(e) => {
+                    tracing::error!(
+                        "Error fetching results from metadata segment based on where clause {:?}",
+                        e
+                    );
+                    return Err(MetadataSegmentError::MetadataIndexQueryError(e));
+                }
+            },
             None => {
                 tracing::info!("No where clause to filter anything from metadata segment");
                 None
@@ -1294,7 +1118,7 @@ impl MetadataSegmentReader<'_> {
         let where_document_results = match where_document_clause {
             Some(where_document_clause) => {
                 match self
-                    .process_where_document_clause(where_document_clause)
+                    .process_where_document_clause(where_document_clause, all_offset_ids)
                     .await
                 {
                     Ok(results) => {
@@ -1344,9 +1168,9 @@ impl MetadataSegmentReader<'_> {
     fn process_where_clause<'me>(
         &'me self,
         where_clause: &'me Where,
-    ) -> BoxFuture<Result<Vec<usize>, MetadataIndexError>> {
+    ) -> BoxFuture<Result<RoaringBitmap, MetadataIndexError>> {
         async move {
-            let mut results = vec![];
+            let mut results = RoaringBitmap::new();
             match where_clause {
                 Where::DirectWhereComparison(direct_where_comparison) => {
                     match &direct_where_comparison.comparison {
@@ -1366,10 +1190,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1379,7 +1200,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1388,20 +1209,23 @@ impl MetadataSegmentReader<'_> {
                                     }
                                 }
                                 WhereClauseComparator::NotEqual => {
-                                    todo!();
-                                }
-                                // We don't allow these comparators for strings.
-                                WhereClauseComparator::LessThan => {
-                                    unimplemented!();
-                                }
-                                WhereClauseComparator::LessThanOrEqual => {
-                                    unimplemented!();
-                                }
-                                WhereClauseComparator::GreaterThan => {
-                                    unimplemented!();
+                                    let keywrapper = operand.as_str().into();
+                                    match &self.string_metadata_index_reader {
+                                        Some(reader) => {
+                                            results = reader
+                                                .ne(&direct_where_comparison.key, &keywrapper)
+                                                .await?;
+                                        }
+                                        // Nothing has been written to storage yet.
+                                        None => results = RoaringBitmap::new(),
+                                    }
                                 }
-                                WhereClauseComparator::GreaterThanOrEqual => {
-                                    unimplemented!();
+                                // Strings are not ordered, so no record matches these.
+                                WhereClauseComparator::LessThan
+                                | WhereClauseComparator::LessThanOrEqual
+                                | WhereClauseComparator::GreaterThan
+                                | WhereClauseComparator::GreaterThanOrEqual => {
+                                    results = RoaringBitmap::new();
                                 }
                             }
                         }
@@ -1421,10 +1245,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1434,7 +1255,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1443,20 +1264,23 @@ impl MetadataSegmentReader<'_> {
                                     }
                                 }
                                 WhereClauseComparator::NotEqual => {
-                                    todo!();
-                                }
-                                // We don't allow these comparators for bools.
-                                WhereClauseComparator::LessThan => {
-                                    unimplemented!();
-                                }
-                                WhereClauseComparator::LessThanOrEqual => {
-                                    unimplemented!();
-                                }
-                                WhereClauseComparator::GreaterThan => {
-                                    unimplemented!();
+                                    let keywrapper = (*operand).into();
+                                    match &self.bool_metadata_index_reader {
+                                        Some(reader) => {
+                                            results = reader
+                                                .ne(&direct_where_comparison.key, &keywrapper)
+                                                .await?;
+                                        }
+                                        // Nothing has been written to storage yet.
+                                        None => results = RoaringBitmap::new(),
+                                    }
                                 }
-                                WhereClauseComparator::GreaterThanOrEqual => {
-                                    unimplemented!();
+                                // Bools are not ordered, so no record matches these.
+                                WhereClauseComparator::LessThan
+                                | WhereClauseComparator::LessThanOrEqual
+                                | WhereClauseComparator::GreaterThan
+                                | WhereClauseComparator::GreaterThanOrEqual => {
+                                    results = RoaringBitmap::new();
                                 }
                             }
                         }
@@ -1476,10 +1300,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1489,7 +1310,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1498,7 +1319,16 @@ impl MetadataSegmentReader<'_> {
                                     }
                                 }
                                 WhereClauseComparator::NotEqual => {
-                                    todo!();
+                                    let keywrapper = (*operand).into();
+                                    match &self.u32_metadata_index_reader {
+                                        Some(reader) => {
+                                            results = reader
+                                                .ne(&direct_where_comparison.key, &keywrapper)
+                                                .await?;
+                                        }
+                                        // Nothing has been written to storage yet.
+                                        None => results = RoaringBitmap::new(),
+                                    }
                                 }
                                 WhereClauseComparator::LessThan => {
                                     let metadata_value_keywrapper = (*operand).try_into();
@@ -1514,10 +1344,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1527,7 +1354,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1549,10 +1376,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1562,7 +1386,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1584,10 +1408,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1597,7 +1418,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1619,10 +1440,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1632,7 +1450,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1658,10 +1476,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1671,7 +1486,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1680,7 +1495,16 @@ impl MetadataSegmentReader<'_> {
                                     }
                                 }
                                 WhereClauseComparator::NotEqual => {
-                                    todo!();
+                                    let keywrapper = (*operand as f32).into();
+                                    match &self.f32_metadata_index_reader {
+                                        Some(reader) => {
+                                            results = reader
+                                                .ne(&direct_where_comparison.key, &keywrapper)
+                                                .await?;
+                                        }
+                                        // Nothing has been written to storage yet.
+                                        None => results = RoaringBitmap::new(),
+                                    }
                                 }
                                 WhereClauseComparator::LessThan => {
                                     let metadata_value_keywrapper = (*operand as f32).try_into();
@@ -1696,10 +1520,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1709,7 +1530,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1731,10 +1552,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1744,7 +1562,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1766,10 +1584,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1779,7 +1594,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1801,10 +1616,7 @@ impl MetadataSegmentReader<'_> {
                                                         .await;
                                                     match result {
                                                         Ok(r) => {
-                                                            results = r
-                                                                .iter()
-                                                                .map(|x| x as usize)
-                                                                .collect();
+                                                            results = r;
                                                         }
                                                         Err(e) => {
                                                             return Err(e);
@@ -1814,7 +1626,7 @@ impl MetadataSegmentReader<'_> {
                                                 // This is expected. Before the first ever compaction
                                                 // the reader will be uninitialized, hence an empty vector
                                                 // here since nothing has been written to storage yet.
-                                                None => results = vec![],
+                                                None => results = RoaringBitmap::new(),
                                             }
                                         }
                                         Err(_) => {
@@ -1841,23 +1653,20 @@ impl MetadataSegmentReader<'_> {
                 Where::WhereChildren(where_children) => {
                     let mut first_iteration = true;
                     for child in where_children.children.iter() {
-                        let child_results: Vec<usize> =
-                            match self.process_where_clause(&child).await {
-                                Ok(result) => result,
-                                Err(_) => vec![],
-                            };
+                        let child_results = match self.process_where_clause(child).await {
+                            Ok(result) => result,
+                            Err(_) => RoaringBitmap::new(),
+                        };
                         if first_iteration {
                             results = child_results;
                             first_iteration = false;
                         } else {
                             match where_children.operator {
                                 BooleanOperator::And => {
-                                    results =
-                                        merge_sorted_vecs_conjunction(&results, &child_results);
+                                    results &= child_results;
                                 }
                                 BooleanOperator::Or => {
-                                    results =
-                                        merge_sorted_vecs_disjunction(&results, &child_results);
+                                    results |= child_results;
                                 }
                             }
                         }
@@ -1872,6 +1681,7 @@ impl MetadataSegmentReader<'_> {
     fn process_where_document_clause<'me>(
         &'me self,
         where_document_clause: &'me WhereDocument,
+        all_offset_ids: &'me [usize],
     ) -> BoxFuture<Result<Vec<usize>, MetadataIndexError>> {
         async move {
             let mut results = vec![];
@@ -1899,18 +1709,37 @@ impl MetadataSegmentReader<'_> {
                             }
                         }
                         WhereDocumentOperator::NotContains => {
-                            todo!();
+                            let contains: HashSet<usize> = match &self.full_text_index_reader {
+                                Some(reader) => {
+                                    match reader.search(&direct_document_comparison.document).await
+                                    {
+                                        Ok(r) => r.iter().map(|x| *x as usize).collect(),
+                                        Err(e) => {
+                                            return Err(MetadataIndexError::FullTextError(e));
+                                        }
+                                    }
+                                }
+                                None => HashSet::new(),
+                            };
+                            // Records without a document do not contain the text either.
+                            results = all_offset_ids
+                                .iter()
+                                .copied()
+                                .filter(|offset_id| !contains.contains(offset_id))
+                                .collect();
                         }
                     }
                 }
                 WhereDocument::WhereDocumentChildren(where_document_children) => {
                     let mut first_iteration = true;
                     for child in where_document_children.children.iter() {
-                        let child_results: Vec<usize> =
-                            match self.process_where_document_clause(&child).await {
-                                Ok(result) => result,
-                                Err(_) => vec![],
-                            };
+                        let child_results: Vec<usize> = match self
+                            .process_where_document_clause(&child, all_offset_ids)
+                            .await
+                        {
+                            Ok(result) => result,
+                            Err(_) => vec![],
+                        };
                         if first_iteration {
                             results = child_results;
                             first_iteration = false;
@@ -66,7 +66,8 @@ impl RecordSegmentWriter {
     ) -> Result<(), ApplyMaterializedLogError> {
         // Merge data record with updates.
         let updated_document = mat_record.merged_document_ref();
-        let updated_embeddings = mat_record.merged_embeddings();
+        // Record segments only hold float32 embeddings.
+        let updated_embeddings = mat_record.merged_embeddings().to_f32();
         let final_metadata = mat_record.merged_metadata();
         let mut final_metadata_opt = None;
         if !final_metadata.is_empty() {
@@ -75,7 +76,7 @@ impl RecordSegmentWriter {
         // Time to create a data record now.
         let data_record = DataRecord {
             id: user_id,
-            embedding: updated_embeddings,
+            embedding: &updated_embeddings,
             metadata: final_metadata_opt,
             document: updated_document,
         };
@@ -295,8 +296,6 @@ pub enum ApplyMaterializedLogError {
     BlockfileUpdateError,
     #[error("Embedding not set in the user write")]
     EmbeddingNotSet,
-    #[error("Metadata update not valid")]
-    MetadataUpdateNotValid,
 }
 
 impl ChromaError for ApplyMaterializedLogError {
@@ -305,7 +304,6 @@ impl ChromaError for ApplyMaterializedLogError {
             ApplyMaterializedLogError::BlockfileSetError => ErrorCodes::Internal,
             ApplyMaterializedLogError::BlockfileDeleteError => ErrorCodes::Internal,
             ApplyMaterializedLogError::BlockfileUpdateError => ErrorCodes::Internal,
-            ApplyMaterializedLogError::MetadataUpdateNotValid => ErrorCodes::Internal,
             ApplyMaterializedLogError::EmbeddingNotSet => ErrorCodes::InvalidArgument,
         }
     }
@@ -772,6 +770,7 @@ impl RecordSegmentReader<'_> {
 
     /// Returns all data in the record segment, sorted by
     /// embedding id
+    #[cfg(test)]
     pub(crate) async fn get_all_data(&self) -> Result<Vec<DataRecord>, Box<dyn ChromaError>> {
         let mut data = Vec::new();
         let max_size = self.user_id_to_id.count().await?;
@@ -790,6 +789,89 @@ impl RecordSegmentReader<'_> {
         Ok(data)
     }
 
+    /// Returns the offset id and embedding of every record in the segment, sorted by
+    /// offset id
+    pub(crate) async fn get_all_embeddings(
+        &self,
+    ) -> Result<Vec<(u32, Vec<f32>)>, Box<dyn ChromaError>> {
+        Ok(self
+            .get_data_page(0, None)
+            .await?
+            .into_iter()
+            .map(|(offset_id, data_record)| (offset_id, data_record.embedding.to_vec()))
+            .collect())
+    }
+
+    /// Returns up to `limit` records with their offset ids, skipping the first `offset`
+    /// records. Records are sorted by offset id, which is the order they were added in.
+    /// With no limit, every record after `offset` is returned.
+    pub(crate) async fn get_data_page(
+        &self,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<(u32, DataRecord)>, Box<dyn ChromaError>> {
+        let count = self.id_to_data.count().await?;
+        let end = match limit {
+            Some(limit) => count.min(offset.saturating_add(limit)),
+            None => count,
+        };
+        let mut data = Vec::with_capacity(end.saturating_sub(offset));
+        for i in offset..end {
+            let (_, offset_id, data_record) = self.id_to_data.get_at_index(i).await?;
+            data.push((offset_id, data_record));
+        }
+        Ok(data)
+    }
+
+    /// Like `get_data_page`, but skips the records with the offset ids in `excluded` as if
+    /// they were not in the segment, they neither count towards `offset` nor are returned.
+    /// `excluded` has to be sorted and only hold offset ids of the segment.
+    pub(crate) async fn get_data_page_excluding(
+        &self,
+        offset: usize,
+        limit: Option<usize>,
+        excluded: &[u32],
+    ) -> Result<Vec<(u32, DataRecord)>, Box<dyn ChromaError>> {
+        let count = self.id_to_data.count().await?;
+        // The number of records before an index that are not excluded grows by at most one
+        // per index, so search for the first index with `offset` of them before it.
+        let mut low = 0;
+        let mut high = count;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let (_, offset_id, _) = self.id_to_data.get_at_index(mid).await?;
+            let kept_before = mid - excluded.partition_point(|excluded| *excluded < offset_id);
+            if kept_before < offset {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut data = Vec::new();
+        for i in low..count {
+            if data.len() >= limit {
+                break;
+            }
+            let (_, offset_id, data_record) = self.id_to_data.get_at_index(i).await?;
+            if excluded.binary_search(&offset_id).is_err() {
+                data.push((offset_id, data_record));
+            }
+        }
+        Ok(data)
+    }
+
+    /// Returns the offset id of every record in the segment, sorted
+    pub(crate) async fn get_all_offset_ids(&self) -> Result<Vec<u32>, Box<dyn ChromaError>> {
+        let count = self.id_to_user_id.count().await?;
+        let mut offset_ids = Vec::with_capacity(count);
+        for i in 0..count {
+            let (_, offset_id, _) = self.id_to_user_id.get_at_index(i).await?;
+            offset_ids.push(offset_id);
+        }
+        Ok(offset_ids)
+    }
+
     pub(crate) async fn count(&self) -> Result<usize, Box<dyn ChromaError>> {
         self.id_to_data.count().await
     }
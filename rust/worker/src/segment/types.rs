@@ -1,3 +1,4 @@
+use crate::distance::VectorSlice;
 use crate::errors::{ChromaError, ErrorCodes};
 use crate::execution::data::data_chunk::Chunk;
 use crate::types::{
@@ -165,7 +166,9 @@ pub(crate) struct MaterializedLogRecord<'referred_data> {
     // from the last non null operation.
     // E.g. if log has [Insert(emb0), Update(emb1), Update(emb2), Update()]
     // then this will contain emb2. None if final operation is Delete.
-    pub(crate) final_embedding: Option<&'referred_data [f32]>,
+    // It is in the representation it was written to the log with, which
+    // for float16 embeddings is not float32.
+    pub(crate) final_embedding: Option<VectorSlice<'referred_data>>,
 }
 
 impl<'referred_data> MaterializedLogRecord<'referred_data> {
@@ -336,11 +339,11 @@ impl<'referred_data> MaterializedLogRecord<'referred_data> {
         final_metadata
     }
 
-    pub(crate) fn merged_embeddings(&self) -> &[f32] {
+    pub(crate) fn merged_embeddings(&self) -> VectorSlice<'referred_data> {
         return match self.final_embedding {
             Some(embed) => embed,
             None => match self.data_record.as_ref() {
-                Some(data_record) => data_record.embedding,
+                Some(data_record) => VectorSlice::F32(data_record.embedding),
                 None => panic!("Expected at least one source of embedding"),
             },
         };
@@ -423,6 +426,16 @@ impl<'referred_data> TryFrom<(&'referred_data OperationRecord, u32, &'referred_d
     }
 }
 
+// Whether a record that exists in the record segment has been deleted by the log.
+fn is_deleted(
+    existing_id_to_materialized: &HashMap<&str, MaterializedLogRecord>,
+    id: &str,
+) -> bool {
+    existing_id_to_materialized
+        .get(id)
+        .is_some_and(|record| record.final_operation == Operation::Delete)
+}
+
 pub(crate) struct LogMaterializer<'me> {
     // Is None when record segment is uninitialized.
     pub(crate) record_segment_reader: Option<RecordSegmentReader<'me>>,
@@ -520,8 +533,14 @@ impl<'me> LogMaterializer<'me> {
                 Operation::Add => {
                     // If user is trying to insert a key that is invalid then ignore.
                     // Also if it already existed in the log before then ignore.
+                    // A key that exists in the record segment becomes valid again once
+                    // it has been deleted earlier in the log, and is given a new offset id.
                     if !new_id_to_materialized.contains_key(log_record.record.id.as_str())
-                        && !invalid_adds.contains(log_record.record.id.as_str())
+                        && (!invalid_adds.contains(log_record.record.id.as_str())
+                            || is_deleted(
+                                &existing_id_to_materialized,
+                                log_record.record.id.as_str(),
+                            ))
                     {
                         let next_offset_id =
                             next_offset_id.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
@@ -566,8 +585,11 @@ impl<'me> LogMaterializer<'me> {
                 }
                 Operation::Update => {
                     let mut created_in_log = true;
+                    // Records deleted earlier in the log can only be updated if they
+                    // were added again after the delete.
                     let record_from_map = match existing_id_to_materialized
                         .get_mut(log_record.record.id.as_str())
+                        .filter(|res| res.final_operation != Operation::Delete)
                     {
                         Some(res) => {
                             created_in_log = false;
@@ -613,7 +635,10 @@ impl<'me> LogMaterializer<'me> {
                     }
                 }
                 Operation::Upsert => {
-                    if existing_id_to_materialized.contains_key(log_record.record.id.as_str()) {
+                    // Upserting a record deleted earlier in the log inserts it afresh.
+                    if existing_id_to_materialized.contains_key(log_record.record.id.as_str())
+                        && !is_deleted(&existing_id_to_materialized, log_record.record.id.as_str())
+                    {
                         // Just another update.
                         let record_from_map = existing_id_to_materialized
                             .get_mut(log_record.record.id.as_str())
@@ -751,7 +776,7 @@ mod tests {
         blockstore::{arrow::provider::ArrowBlockfileProvider, provider::BlockfileProvider},
         segment::record_segment::{RecordSegmentReaderCreationError, RecordSegmentWriter},
         storage::{local::LocalStorage, Storage},
-        types::{MetadataValue, Operation, OperationRecord, UpdateMetadataValue},
+        types::{Embedding, MetadataValue, Operation, OperationRecord, UpdateMetadataValue},
     };
     use std::{collections::HashMap, str::FromStr};
 
@@ -791,7 +816,7 @@ mod tests {
                     log_offset: 1,
                     record: OperationRecord {
                         id: "embedding_id_1".to_string(),
-                        embedding: Some(vec![1.0, 2.0, 3.0]),
+                        embedding: Some(vec![1.0, 2.0, 3.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata.clone()),
                         document: Some(String::from("doc1")),
@@ -802,7 +827,7 @@ mod tests {
                     log_offset: 2,
                     record: OperationRecord {
                         id: "embedding_id_2".to_string(),
-                        embedding: Some(vec![4.0, 5.0, 6.0]),
+                        embedding: Some(vec![4.0, 5.0, 6.0].into()),
                         encoding: None,
                         metadata: Some(update_metadata),
                         document: Some(String::from("doc2")),
@@ -871,7 +896,7 @@ mod tests {
                 log_offset: 4,
                 record: OperationRecord {
                     id: "embedding_id_3".to_string(),
-                    embedding: Some(vec![7.0, 8.0, 9.0]),
+                    embedding: Some(vec![7.0, 8.0, 9.0].into()),
                     encoding: None,
                     metadata: Some(update_metadata),
                     document: Some(String::from("doc3")),
@@ -914,7 +939,10 @@ mod tests {
                 assert_eq!("embedding_id_3", log.user_id.unwrap());
                 assert_eq!(true, log.data_record.is_none());
                 assert_eq!("doc3", log.final_document.unwrap());
-                assert_eq!(vec![7.0, 8.0, 9.0], log.final_embedding.unwrap());
+                assert_eq!(
+                    VectorSlice::F32(&[7.0, 8.0, 9.0]),
+                    log.final_embedding.unwrap()
+                );
                 assert_eq!(3, log.offset_id);
                 assert_eq!(Operation::Add, log.final_operation);
                 let mut hello_found = 0;
@@ -1091,4 +1119,329 @@ mod tests {
             }
         }
     }
+
+    fn log_record(
+        log_offset: i64,
+        id: &str,
+        embedding: Option<Vec<f32>>,
+        operation: Operation,
+    ) -> LogRecord {
+        LogRecord {
+            log_offset,
+            record: OperationRecord {
+                id: id.to_string(),
+                embedding: embedding.map(Embedding::from),
+                encoding: None,
+                metadata: None,
+                document: None,
+                operation,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_materializer_readds_deleted_records() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(ArrowBlockfileProvider::new(storage));
+        let mut record_segment = crate::types::Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::BlockfileRecord,
+            scope: crate::types::SegmentScope::RECORD,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        {
+            let segment_writer =
+                RecordSegmentWriter::from_segment(&record_segment, &blockfile_provider)
+                    .await
+                    .expect("Error creating segment writer");
+            let data: Chunk<LogRecord> = Chunk::new(
+                vec![
+                    log_record(
+                        1,
+                        "embedding_id_1",
+                        Some(vec![1.0, 2.0, 3.0]),
+                        Operation::Add,
+                    ),
+                    log_record(
+                        2,
+                        "embedding_id_2",
+                        Some(vec![4.0, 5.0, 6.0]),
+                        Operation::Add,
+                    ),
+                    log_record(
+                        3,
+                        "embedding_id_3",
+                        Some(vec![7.0, 8.0, 9.0]),
+                        Operation::Add,
+                    ),
+                ]
+                .into(),
+            );
+            let materializer = LogMaterializer::new(None, data, None);
+            let mat_records = materializer
+                .materialize()
+                .await
+                .expect("Log materialization failed");
+            segment_writer
+                .apply_materialized_log_chunk(mat_records)
+                .await
+                .expect("Apply materialized log failed");
+            let flusher = segment_writer
+                .commit()
+                .expect("Commit for segment writer failed");
+            record_segment.file_path = flusher.flush().await.expect("Flush segment writer failed");
+        }
+        // Delete every record, then add 1 back, upsert 2 back and update 3, which no
+        // longer exists.
+        let data: Chunk<LogRecord> = Chunk::new(
+            vec![
+                log_record(4, "embedding_id_1", None, Operation::Delete),
+                log_record(5, "embedding_id_2", None, Operation::Delete),
+                log_record(6, "embedding_id_3", None, Operation::Delete),
+                log_record(
+                    7,
+                    "embedding_id_1",
+                    Some(vec![1.5, 2.5, 3.5]),
+                    Operation::Add,
+                ),
+                log_record(
+                    8,
+                    "embedding_id_2",
+                    Some(vec![4.5, 5.5, 6.5]),
+                    Operation::Upsert,
+                ),
+                log_record(
+                    9,
+                    "embedding_id_3",
+                    Some(vec![7.5, 8.5, 9.5]),
+                    Operation::Update,
+                ),
+            ]
+            .into(),
+        );
+        let reader = RecordSegmentReader::from_segment(&record_segment, &blockfile_provider)
+            .await
+            .expect("Error creating segment reader");
+        let materializer = LogMaterializer::new(Some(reader), data, None);
+        let res = materializer
+            .materialize()
+            .await
+            .expect("Error materializing logs");
+        let mut materialized: Vec<(u32, Operation, String)> = res
+            .iter()
+            .map(|(log, _)| {
+                (
+                    log.offset_id,
+                    log.final_operation.clone(),
+                    log.merged_user_id(),
+                )
+            })
+            .collect();
+        materialized.sort_by_key(|(offset_id, _, _)| *offset_id);
+        assert_eq!(
+            materialized,
+            vec![
+                (1, Operation::Delete, "embedding_id_1".to_string()),
+                (2, Operation::Delete, "embedding_id_2".to_string()),
+                (3, Operation::Delete, "embedding_id_3".to_string()),
+                (4, Operation::Add, "embedding_id_1".to_string()),
+                (5, Operation::Add, "embedding_id_2".to_string()),
+            ]
+        );
+        let segment_writer =
+            RecordSegmentWriter::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .expect("Error creating segment writer");
+        segment_writer
+            .apply_materialized_log_chunk(res)
+            .await
+            .expect("Error applying materialized log chunk");
+        let flusher = segment_writer
+            .commit()
+            .expect("Commit for segment writer failed");
+        record_segment.file_path = flusher.flush().await.expect("Flush segment writer failed");
+        let segment_reader =
+            RecordSegmentReader::from_segment(&record_segment, &blockfile_provider)
+                .await
+                .expect("Error creating segment reader");
+        assert_eq!(segment_reader.count().await.expect("Count failed"), 2);
+        let (data, offset_id) = segment_reader
+            .get_data_and_offset_id_for_user_id("embedding_id_1")
+            .await
+            .expect("Re-added record not found");
+        assert_eq!(offset_id, 4);
+        assert_eq!(data.embedding, vec![1.5, 2.5, 3.5]);
+        let (data, offset_id) = segment_reader
+            .get_data_and_offset_id_for_user_id("embedding_id_2")
+            .await
+            .expect("Upserted record not found");
+        assert_eq!(offset_id, 5);
+        assert_eq!(data.embedding, vec![4.5, 5.5, 6.5]);
+        assert!(!segment_reader
+            .data_exists_for_user_id("embedding_id_3")
+            .await
+            .expect("Exists check failed"));
+    }
+
+    // Flushes `data` into the record segment, on top of what it already holds.
+    async fn apply_to_record_segment(
+        record_segment: &mut crate::types::Segment,
+        blockfile_provider: &BlockfileProvider,
+        data: Vec<LogRecord>,
+    ) {
+        let reader = RecordSegmentReader::from_segment(record_segment, blockfile_provider)
+            .await
+            .ok();
+        let materializer = LogMaterializer::new(reader, Chunk::new(data.into()), None);
+        let mat_records = materializer
+            .materialize()
+            .await
+            .expect("Log materialization failed");
+        let segment_writer = RecordSegmentWriter::from_segment(record_segment, blockfile_provider)
+            .await
+            .expect("Error creating segment writer");
+        segment_writer
+            .apply_materialized_log_chunk(mat_records)
+            .await
+            .expect("Apply materialized log failed");
+        let flusher = segment_writer
+            .commit()
+            .expect("Commit for segment writer failed");
+        record_segment.file_path = flusher.flush().await.expect("Flush segment writer failed");
+    }
+
+    #[tokio::test]
+    async fn test_record_segment_replays_interleaved_operations() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let blockfile_provider =
+            BlockfileProvider::ArrowBlockfileProvider(ArrowBlockfileProvider::new(storage));
+        let mut record_segment = crate::types::Segment {
+            id: Uuid::new_v4(),
+            r#type: crate::types::SegmentType::BlockfileRecord,
+            scope: crate::types::SegmentScope::RECORD,
+            collection: Some(Uuid::new_v4()),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+        let with_document = |mut log_record: LogRecord, document: &str| {
+            log_record.record.document = Some(document.to_string());
+            log_record
+        };
+        apply_to_record_segment(
+            &mut record_segment,
+            &blockfile_provider,
+            vec![
+                with_document(
+                    log_record(1, "id_a", Some(vec![1.0, 1.0]), Operation::Add),
+                    "a",
+                ),
+                log_record(2, "id_b", Some(vec![2.0, 2.0]), Operation::Add),
+                log_record(3, "id_c", Some(vec![3.0, 3.0]), Operation::Upsert),
+                // A second add of an id is ignored.
+                with_document(
+                    log_record(4, "id_a", Some(vec![9.0, 9.0]), Operation::Add),
+                    "ignored",
+                ),
+                with_document(log_record(5, "id_b", None, Operation::Update), "b"),
+                log_record(6, "id_c", None, Operation::Delete),
+                // An upsert of an existing id is applied.
+                log_record(7, "id_a", Some(vec![1.5, 1.5]), Operation::Upsert),
+                log_record(8, "id_d", Some(vec![4.0, 4.0]), Operation::Add),
+                // An update of a missing id is ignored.
+                log_record(9, "id_e", Some(vec![5.0, 5.0]), Operation::Update),
+            ],
+        )
+        .await;
+        // The same semantics hold for ids that are already in the segment.
+        apply_to_record_segment(
+            &mut record_segment,
+            &blockfile_provider,
+            vec![
+                log_record(10, "id_b", Some(vec![9.0, 9.0]), Operation::Add),
+                log_record(11, "id_d", Some(vec![4.5, 4.5]), Operation::Upsert),
+                log_record(12, "id_f", Some(vec![6.0, 6.0]), Operation::Upsert),
+                log_record(13, "id_a", None, Operation::Delete),
+                log_record(14, "id_g", Some(vec![7.0, 7.0]), Operation::Add),
+            ],
+        )
+        .await;
+
+        let reader = RecordSegmentReader::from_segment(&record_segment, &blockfile_provider)
+            .await
+            .expect("Error creating segment reader");
+        assert_eq!(reader.count().await.unwrap(), 4);
+        let (data, offset_id) = reader
+            .get_data_and_offset_id_for_user_id("id_b")
+            .await
+            .unwrap();
+        assert_eq!(offset_id, 2);
+        assert_eq!(data.embedding, vec![2.0, 2.0]);
+        assert_eq!(data.document, Some("b"));
+        assert_eq!(reader.get_offset_id_for_user_id("id_d").await.unwrap(), 4);
+        assert_eq!(
+            reader.get_data_for_offset_id(4).await.unwrap().embedding,
+            vec![4.5, 4.5]
+        );
+        assert_eq!(reader.get_user_id_for_offset_id(5).await.unwrap(), "id_f");
+        assert_eq!(reader.get_user_id_for_offset_id(6).await.unwrap(), "id_g");
+        for deleted in ["id_a", "id_c", "id_e"] {
+            assert!(!reader.data_exists_for_user_id(deleted).await.unwrap());
+        }
+        assert!(reader.get_data_for_offset_id(1).await.is_err());
+
+        let page = |offset: usize, limit: Option<usize>| {
+            let reader = &reader;
+            async move {
+                reader
+                    .get_data_page(offset, limit)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|(offset_id, data)| (offset_id, data.id.to_string()))
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(
+            page(0, None).await,
+            vec![
+                (2, "id_b".to_string()),
+                (4, "id_d".to_string()),
+                (5, "id_f".to_string()),
+                (6, "id_g".to_string()),
+            ]
+        );
+        assert_eq!(
+            page(1, Some(2)).await,
+            vec![(4, "id_d".to_string()), (5, "id_f".to_string())]
+        );
+        assert_eq!(page(3, Some(10)).await, vec![(6, "id_g".to_string())]);
+        assert!(page(4, Some(1)).await.is_empty());
+        assert!(page(7, None).await.is_empty());
+
+        // Excluded records take no position.
+        let page_excluding = |offset: usize, limit: Option<usize>, excluded: &[u32]| {
+            let reader = &reader;
+            let excluded = excluded.to_vec();
+            async move {
+                reader
+                    .get_data_page_excluding(offset, limit, &excluded)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|(offset_id, _)| offset_id)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(page_excluding(0, None, &[]).await, vec![2, 4, 5, 6]);
+        assert_eq!(page_excluding(0, Some(2), &[2, 4]).await, vec![5, 6]);
+        assert_eq!(page_excluding(1, Some(2), &[4]).await, vec![5, 6]);
+        assert_eq!(page_excluding(1, None, &[2, 5]).await, vec![6]);
+        assert!(page_excluding(2, None, &[2, 5]).await.is_empty());
+        assert!(page_excluding(0, None, &[2, 4, 5, 6]).await.is_empty());
+    }
 }
@@ -1,33 +1,77 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crate::blockstore::provider::BlockfileProvider;
 use crate::chroma_proto::{
     self, CountRecordsRequest, CountRecordsResponse, QueryMetadataRequest, QueryMetadataResponse,
 };
 use crate::chroma_proto::{
-    GetVectorsRequest, GetVectorsResponse, QueryVectorsRequest, QueryVectorsResponse,
+    get_index_stats_request, CheckIndexRequest, CheckIndexResponse, GetIndexStatsRequest,
+    GetVectorsRequest, GetVectorsResponse, IndexStats, QueryVectorsRequest, QueryVectorsResponse,
 };
+use crate::compactor::CompactionJob;
 use crate::config::{Configurable, QueryServiceConfig};
-use crate::errors::ChromaError;
+use crate::distance::DistanceRegistry;
+use crate::errors::{into_status, ChromaError};
+use crate::execution::compute_pool::ComputePool;
+use crate::execution::deadline::Deadline;
 use crate::execution::operator::TaskMessage;
 use crate::execution::orchestration::{
-    CountQueryOrchestrator, GetVectorsOrchestrator, HnswQueryOrchestrator,
-    MetadataQueryOrchestrator,
+    CompactOrchestrator, CountQueryOrchestrator, GetVectorsOrchestrator, HnswQueryOrchestrator,
+    MetadataQuery, MetadataQueryOrchestrator,
 };
+use crate::health::{self, HealthReporter, HealthService};
 use crate::index::hnsw_provider::HnswIndexProvider;
+use crate::index::validate_ef_search;
 use crate::log::log::Log;
+use crate::segment::distributed_hnsw_segment::{check_hnsw_segment, hnsw_index_id};
+use crate::storage::Storage;
 use crate::sysdb::sysdb::SysDb;
 use crate::system::{Receiver, System};
+use crate::tracing::metrics::metrics;
 use crate::tracing::util::wrap_span_with_parent_context;
+use crate::types::KnnQuery;
 use crate::types::MetadataValue;
+use crate::types::QueryInclude;
 use crate::types::ScalarEncoding;
+use crate::types::{Segment, SegmentType};
 use async_trait::async_trait;
-use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+use tonic::metadata::MetadataMap;
 use tonic::{transport::Server, Request, Response, Status};
 use tracing::{trace, trace_span, Instrument};
 use uuid::Uuid;
 
+// How often the health service checks that sysdb and storage can be reached.
+const SYSDB_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const STORAGE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// The timeout a client set on its request, which gRPC sends as an integer of at most 8
+// digits followed by the unit.
+fn grpc_timeout(metadata: &MetadataMap) -> Option<Duration> {
+    let timeout = metadata.get("grpc-timeout")?.to_str().ok()?;
+    if timeout.len() < 2 || timeout.len() > 9 {
+        return None;
+    }
+    let (amount, unit) = timeout.split_at(timeout.len() - 1);
+    if !amount.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
 #[derive(Clone)]
 pub struct WorkerServer {
     // System
@@ -37,9 +81,19 @@ pub struct WorkerServer {
     // Service dependencies
     log: Box<Log>,
     sysdb: Box<SysDb>,
+    storage: Storage,
     hnsw_index_provider: HnswIndexProvider,
     blockfile_provider: BlockfileProvider,
+    distance_registry: Arc<DistanceRegistry>,
+    compute_pool: ComputePool,
     port: u16,
+    // The deadline of queries that arrive without one
+    default_request_timeout: Duration,
+    // The deadline of index checks and rebuilds that arrive without one
+    admin_request_timeout: Duration,
+    // Cancelled when the worker starts shutting down
+    shutdown: CancellationToken,
+    health: HealthReporter,
 }
 
 #[async_trait]
@@ -61,6 +115,7 @@ impl Configurable<QueryServiceConfig> for WorkerServer {
                 return Err(err);
             }
         };
+        let health = HealthReporter::new();
         let storage = match crate::storage::from_config(&config.storage).await {
             Ok(storage) => storage,
             Err(err) => {
@@ -68,6 +123,20 @@ impl Configurable<QueryServiceConfig> for WorkerServer {
                 return Err(err);
             }
         };
+        let distance_registry = match DistanceRegistry::try_from_config(&config.distance).await {
+            Ok(distance_registry) => distance_registry,
+            Err(err) => {
+                println!("Failed to create distance registry: {:?}", err);
+                return Err(err);
+            }
+        };
+        let compute_pool = match ComputePool::new(config.dispatcher.num_compute_threads) {
+            Ok(compute_pool) => compute_pool,
+            Err(err) => {
+                println!("Failed to create compute pool: {:?}", err);
+                return Err(Box::new(err));
+            }
+        };
         // TODO: inject hnsw index provider somehow
         // TODO: inject blockfile provider somehow
         // TODO: real path
@@ -77,9 +146,23 @@ impl Configurable<QueryServiceConfig> for WorkerServer {
             system: None,
             sysdb,
             log,
-            hnsw_index_provider: HnswIndexProvider::new(storage.clone(), path),
-            blockfile_provider: BlockfileProvider::new_arrow(storage),
+            storage: storage.clone(),
+            hnsw_index_provider: HnswIndexProvider::new(
+                storage.clone(),
+                path,
+                &config.hnsw_provider,
+            ),
+            blockfile_provider: BlockfileProvider::new_arrow_with_config(
+                storage,
+                &config.blockfile_provider,
+            ),
+            distance_registry: Arc::new(distance_registry),
+            compute_pool,
             port: config.my_port,
+            default_request_timeout: Duration::from_millis(config.default_request_timeout_ms),
+            admin_request_timeout: Duration::from_millis(config.admin_request_timeout_ms),
+            shutdown: CancellationToken::new(),
+            health,
         })
     }
 }
@@ -88,28 +171,45 @@ impl WorkerServer {
     pub(crate) async fn run(worker: WorkerServer) -> Result<(), Box<dyn std::error::Error>> {
         let addr = format!("[::]:{}", worker.port).parse().unwrap();
         println!("Worker listening on {}", addr);
+        let sysdb = worker.sysdb.clone();
+        worker
+            .health
+            .spawn_check(health::SYSDB, SYSDB_CHECK_INTERVAL, move || {
+                let mut sysdb = sysdb.clone();
+                async move { sysdb.ping().await.is_ok() }
+            });
+        let storage = worker.storage.clone();
+        worker
+            .health
+            .spawn_check(health::STORAGE, STORAGE_CHECK_INTERVAL, move || {
+                let storage = storage.clone();
+                async move { storage.ping().await.is_ok() }
+            });
         let server = Server::builder()
+            .add_service(crate::health_proto::health_server::HealthServer::new(
+                HealthService::new(worker.health.clone()),
+            ))
             .add_service(chroma_proto::vector_reader_server::VectorReaderServer::new(
                 worker.clone(),
             ))
             .add_service(
                 chroma_proto::metadata_reader_server::MetadataReaderServer::new(worker.clone()),
-            );
+            )
+            .add_service(chroma_proto::index_admin_server::IndexAdminServer::new(
+                worker.clone(),
+            ));
 
         #[cfg(debug_assertions)]
         let server =
             server.add_service(chroma_proto::debug_server::DebugServer::new(worker.clone()));
 
-        let server = server.serve_with_shutdown(addr, async {
-            let mut sigterm = match signal(SignalKind::terminate()) {
-                Ok(sigterm) => sigterm,
-                Err(e) => {
-                    tracing::error!("Failed to create signal handler: {:?}", e);
-                    return;
-                }
-            };
-            sigterm.recv().await;
-            tracing::info!("Received SIGTERM, shutting down");
+        // The server stops accepting connections once the shutdown token is cancelled, and
+        // returns when the requests in flight have been answered.
+        worker.health.set(health::ACCEPTING_REQUESTS, true);
+        let (shutdown, health) = (worker.shutdown.clone(), worker.health.clone());
+        let server = server.serve_with_shutdown(addr, async move {
+            shutdown.cancelled().await;
+            health.set(health::ACCEPTING_REQUESTS, false);
         });
 
         server.await?;
@@ -124,9 +224,28 @@ impl WorkerServer {
         self.system = Some(system);
     }
 
+    /// The token that shuts the server down when cancelled. From then on new requests are
+    /// rejected as unavailable, so that clients retry them on other workers.
+    pub(crate) fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Reports the readiness of the worker through the gRPC health service.
+    pub(crate) fn health_reporter(&self) -> HealthReporter {
+        self.health.clone()
+    }
+
+    fn check_not_shutting_down(&self) -> Result<(), Status> {
+        if self.shutdown.is_cancelled() {
+            return Err(Status::unavailable("The worker is shutting down"));
+        }
+        Ok(())
+    }
+
     pub(crate) async fn query_vectors_instrumented(
         &self,
         request: Request<QueryVectorsRequest>,
+        deadline: Deadline,
     ) -> Result<Response<QueryVectorsResponse>, Status> {
         let request = request.into_inner();
         let segment_uuid = match Uuid::parse_str(&request.segment_id) {
@@ -136,23 +255,62 @@ impl WorkerServer {
             }
         };
 
+        let ef_search = match request.ef_search {
+            Some(ef_search) => match validate_ef_search(ef_search, request.k) {
+                Ok(ef_search) => Some(ef_search),
+                Err(e) => return Err(into_status(&e)),
+            },
+            None => None,
+        };
+
+        let where_clause = match request.r#where {
+            Some(where_clause) => match where_clause.try_into() {
+                Ok(where_clause) => Some(where_clause),
+                Err(_) => {
+                    tracing::error!("Error converting where clause");
+                    return Err(Status::invalid_argument("Error converting where clause"));
+                }
+            },
+            None => None,
+        };
+
+        let where_document_clause = match request.where_document {
+            Some(where_document_clause) => match where_document_clause.try_into() {
+                Ok(where_document_clause) => Some(where_document_clause),
+                Err(_) => {
+                    tracing::error!("Error converting where document clause");
+                    return Err(Status::invalid_argument(
+                        "Error converting where document clause",
+                    ));
+                }
+            },
+            None => None,
+        };
+
         let mut proto_results_for_all = Vec::new();
 
         let parse_vectors_span = trace_span!("Input vectors parsing");
         let mut query_vectors = Vec::new();
-        let _ = parse_vectors_span.in_scope(|| {
+        // Sparse query vectors are asked of sparse collections, whose vectors are returned
+        // sparse as well.
+        let mut result_encoding = ScalarEncoding::FLOAT32;
+        parse_vectors_span.in_scope(|| {
             for proto_query_vector in request.vectors {
-                let (query_vector, _encoding) = match proto_query_vector.try_into() {
+                let (query_vector, encoding) = match proto_query_vector.try_into() {
                     Ok((vector, encoding)) => (vector, encoding),
                     Err(e) => {
-                        return Err(Status::internal(format!("Error converting vector: {}", e)));
+                        return Err(into_status(&e));
                     }
                 };
-                query_vectors.push(query_vector);
+                if encoding == ScalarEncoding::SPARSE {
+                    result_encoding = ScalarEncoding::SPARSE;
+                }
+                // Queries are scored as float32s, whatever the encoding of the candidates.
+                query_vectors.push(query_vector.into_f32());
             }
             trace!("Parsed vectors {:?}", query_vectors);
             Ok(())
-        });
+        })?;
 
         let dispatcher = match self.dispatcher {
             Some(ref dispatcher) => dispatcher,
@@ -166,16 +324,32 @@ impl WorkerServer {
                 let orchestrator = HnswQueryOrchestrator::new(
                     // TODO: Should not have to clone query vectors here
                     system.clone(),
-                    query_vectors.clone(),
-                    request.k,
+                    query_vectors
+                        .iter()
+                        .map(|embedding| KnnQuery {
+                            embedding: embedding.clone(),
+                            k: request.k as usize,
+                        })
+                        .collect(),
+                    ef_search,
+                    request.return_similarity,
                     request.allowed_ids,
-                    request.include_embeddings,
+                    QueryInclude {
+                        embeddings: request.include_embeddings,
+                        metadatas: request.include_metadatas,
+                        documents: request.include_documents,
+                    },
+                    where_clause,
+                    where_document_clause,
                     segment_uuid,
                     self.log.clone(),
                     self.sysdb.clone(),
                     self.hnsw_index_provider.clone(),
                     self.blockfile_provider.clone(),
+                    self.distance_registry.clone(),
+                    self.compute_pool.clone(),
                     dispatcher.clone(),
+                    deadline,
                 );
                 orchestrator.run().await
             }
@@ -187,10 +361,7 @@ impl WorkerServer {
         let result = match result {
             Ok(result) => result,
             Err(e) => {
-                return Err(Status::internal(format!(
-                    "Error running orchestrator: {}",
-                    e
-                )));
+                return Err(e.into());
             }
         };
 
@@ -202,20 +373,21 @@ impl WorkerServer {
                     distance: query_result.distance,
                     vector: match query_result.vector {
                         Some(vector) => {
-                            match (vector, ScalarEncoding::FLOAT32, query_vectors[0].len())
+                            match (vector, result_encoding.clone(), query_vectors[0].len())
                                 .try_into()
                             {
                                 Ok(proto_vector) => Some(proto_vector),
                                 Err(e) => {
-                                    return Err(Status::internal(format!(
-                                        "Error converting vector: {}",
-                                        e
-                                    )));
+                                    return Err(into_status(&e));
                                 }
                             }
                         }
                         None => None,
                     },
+                    document: query_result.document,
+                    metadata: query_result
+                        .metadata
+                        .map(chroma_proto::UpdateMetadata::from),
                 };
                 proto_results.push(proto_result);
             }
@@ -270,10 +442,7 @@ impl WorkerServer {
         let mut result = match result {
             Ok(result) => result,
             Err(e) => {
-                return Err(Status::internal(format!(
-                    "Error running orchestrator: {}",
-                    e
-                )));
+                return Err(e.into());
             }
         };
 
@@ -282,17 +451,29 @@ impl WorkerServer {
         let vector_drain = result.vectors.drain(..);
 
         for (id, vector) in id_drain.zip(vector_drain) {
+            let vector = match vector {
+                Some(vector) => vector,
+                None => {
+                    output.push(chroma_proto::VectorEmbeddingRecord {
+                        id,
+                        vector: None,
+                        not_found: true,
+                    });
+                    continue;
+                }
+            };
             let vector_len = vector.len();
-            let proto_vector = match (vector, ScalarEncoding::FLOAT32, vector_len).try_into() {
+            let proto_vector = match (vector, result.encoding.clone(), vector_len).try_into() {
                 Ok(vector) => vector,
-                Err(_) => {
-                    return Err(Status::internal("Error converting vector"));
+                Err(e) => {
+                    return Err(into_status(&e));
                 }
             };
 
             let proto_vector_record = chroma_proto::VectorEmbeddingRecord {
                 id,
                 vector: Some(proto_vector),
+                not_found: false,
             };
             output.push(proto_vector_record);
         }
@@ -301,6 +482,251 @@ impl WorkerServer {
         Ok(Response::new(response))
     }
 
+    async fn get_index_stats_instrumented(
+        &self,
+        request: Request<GetIndexStatsRequest>,
+    ) -> Result<Response<IndexStats>, Status> {
+        let parse_uuid = |id: &str| match Uuid::parse_str(id) {
+            Ok(uuid) => Ok(uuid),
+            Err(_) => Err(Status::invalid_argument(format!("Invalid UUID {}", id))),
+        };
+        let mut sysdb = self.sysdb.clone();
+        let segment: Segment = match request.into_inner().target {
+            Some(get_index_stats_request::Target::SegmentId(segment_id)) => {
+                let segment_uuid = parse_uuid(&segment_id)?;
+                let segment = match sysdb
+                    .get_segments(Some(segment_uuid), None, None, None)
+                    .await
+                {
+                    Ok(segments) => segments.into_iter().next(),
+                    Err(e) => return Err(into_status(&e)),
+                };
+                match segment {
+                    Some(segment) if segment.r#type == SegmentType::HnswDistributed => segment,
+                    Some(_) => {
+                        return Err(Status::invalid_argument(format!(
+                            "Segment {} is not an hnsw segment",
+                            segment_uuid
+                        )))
+                    }
+                    None => {
+                        return Err(Status::not_found(format!(
+                            "Segment {} not found",
+                            segment_uuid
+                        )))
+                    }
+                }
+            }
+            Some(get_index_stats_request::Target::CollectionId(collection_id)) => {
+                let collection_uuid = parse_uuid(&collection_id)?;
+                let snapshot = match sysdb.get_collection_snapshot(collection_uuid).await {
+                    Ok(snapshot) => snapshot,
+                    Err(e) => return Err(into_status(&e)),
+                };
+                match snapshot.segment(SegmentType::HnswDistributed) {
+                    Ok(segment) => segment,
+                    Err(e) => return Err(into_status(&e)),
+                }
+            }
+            None => {
+                return Err(Status::invalid_argument(
+                    "Either a segment or a collection id is required",
+                ))
+            }
+        };
+        // Segments only have an index once a compaction has flushed one.
+        let index_id = match hnsw_index_id(&segment) {
+            Some(index_id) => index_id,
+            None => {
+                return Err(Status::not_found(format!(
+                    "Segment {} has no index yet",
+                    segment.id
+                )))
+            }
+        };
+        match self.hnsw_index_provider.stats(&index_id).await {
+            Ok(stats) => Ok(Response::new(proto_index_stats(index_id, stats))),
+            Err(e) => Err(into_status(&*e)),
+        }
+    }
+
+    async fn check_index_instrumented(
+        &self,
+        request: Request<CheckIndexRequest>,
+        deadline: Deadline,
+    ) -> Result<Response<CheckIndexResponse>, Status> {
+        let request = request.into_inner();
+        let collection_uuid = match Uuid::parse_str(&request.collection_id) {
+            Ok(uuid) => uuid,
+            Err(_) => return Err(Status::invalid_argument("Invalid Collection UUID")),
+        };
+        let rebuild = match (
+            request.rebuild,
+            request.rebuild_id,
+            request.collection_version,
+        ) {
+            (false, _, _) => None,
+            (true, Some(rebuild_id), Some(collection_version)) => {
+                match Uuid::parse_str(&rebuild_id) {
+                    Ok(rebuild_id) => Some((rebuild_id, collection_version)),
+                    Err(_) => return Err(Status::invalid_argument("Invalid rebuild UUID")),
+                }
+            }
+            (true, _, _) => {
+                return Err(Status::invalid_argument(
+                    "A rebuild requires a rebuild id and a collection version",
+                ))
+            }
+        };
+        let mut sysdb = self.sysdb.clone();
+        let snapshot = match sysdb.get_collection_snapshot(collection_uuid).await {
+            Ok(snapshot) => snapshot,
+            Err(e) => return Err(into_status(&e)),
+        };
+        let (hnsw_segment, record_segment) = match (
+            snapshot.segment(SegmentType::HnswDistributed),
+            snapshot.segment(SegmentType::BlockfileRecord),
+        ) {
+            (Ok(hnsw_segment), Ok(record_segment)) => (hnsw_segment, record_segment),
+            (Err(e), _) | (_, Err(e)) => return Err(into_status(&e)),
+        };
+        // A rebuild is only registered on top of the version it was asked for. If the
+        // collection moved on since, either this rebuild was registered by an earlier attempt,
+        // which is then reported instead of rebuilding again, or something else changed the
+        // index, which has to be checked again before deciding to rebuild it.
+        let (rebuild, rebuilt_id) = match rebuild {
+            Some((rebuild_id, collection_version))
+                if collection_version != snapshot.collection.version =>
+            {
+                if hnsw_index_id(&hnsw_segment) != Some(rebuild_id) {
+                    return Err(Status::failed_precondition(format!(
+                        "Collection {} is at version {}, not {}",
+                        collection_uuid, snapshot.collection.version, collection_version
+                    )));
+                }
+                (None, Some(rebuild_id))
+            }
+            Some((rebuild_id, collection_version)) => {
+                (Some((rebuild_id, collection_version)), Some(rebuild_id))
+            }
+            None => (None, None),
+        };
+
+        let (stats, discrepancies) = match check_hnsw_segment(
+            &hnsw_segment,
+            &record_segment,
+            snapshot.collection.dimension,
+            &self.hnsw_index_provider,
+            &self.blockfile_provider,
+        )
+        .await
+        {
+            Ok(check) => check,
+            Err(e) => return Err(into_status(&*e)),
+        };
+        let mut response = CheckIndexResponse {
+            collection_version: snapshot.collection.version,
+            stats: None,
+            discrepancies: discrepancies.iter().map(|d| d.to_string()).collect(),
+            rebuilt: None,
+        };
+        if let (Some(index_id), Some(stats)) = (hnsw_index_id(&hnsw_segment), stats) {
+            let stats = crate::index::hnsw_provider::IndexStats {
+                index: stats,
+                cached: false,
+                dirty: false,
+            };
+            response.stats = Some(proto_index_stats(index_id, stats));
+        }
+
+        if let Some((rebuild_id, collection_version)) = rebuild {
+            let (dispatcher, system) = match (&self.dispatcher, &self.system) {
+                (Some(dispatcher), Some(system)) => (dispatcher, system),
+                _ => return Err(Status::internal("No dispatcher or system found")),
+            };
+            let compaction_job = CompactionJob {
+                collection_id: collection_uuid,
+                tenant_id: snapshot.collection.tenant.clone(),
+                offset: snapshot.collection.log_position + 1,
+                collection_version,
+            };
+            let orchestrator = CompactOrchestrator::new(
+                compaction_job,
+                system.clone(),
+                collection_uuid,
+                self.log.clone(),
+                self.sysdb.clone(),
+                self.blockfile_provider.clone(),
+                self.hnsw_index_provider.clone(),
+                dispatcher.clone(),
+                None,
+                None,
+                Arc::new(AtomicU32::new(0)),
+            )
+            .rebuilding_hnsw_index(rebuild_id);
+            if let Err(e) = orchestrator.run_with_deadline(deadline).await {
+                return Err(into_status(&*e));
+            }
+        }
+        if let Some(rebuilt_id) = rebuilt_id {
+            match self.hnsw_index_provider.stats(&rebuilt_id).await {
+                Ok(stats) => response.rebuilt = Some(proto_index_stats(rebuilt_id, stats)),
+                Err(e) => return Err(into_status(&*e)),
+            }
+        }
+        Ok(Response::new(response))
+    }
+
+    async fn count_records_instrumented(
+        &self,
+        request: Request<CountRecordsRequest>,
+    ) -> Result<Response<CountRecordsResponse>, Status> {
+        let request = request.into_inner();
+        let segment_uuid = match Uuid::parse_str(&request.segment_id) {
+            Ok(uuid) => uuid,
+            Err(_) => {
+                return Err(Status::invalid_argument("Invalid Segment UUID"));
+            }
+        };
+        println!("Querying count for segment {}", segment_uuid);
+        let dispatcher = match self.dispatcher {
+            Some(ref dispatcher) => dispatcher,
+            None => {
+                return Err(Status::internal("No dispatcher found"));
+            }
+        };
+
+        let system = match self.system {
+            Some(ref system) => system,
+            None => {
+                return Err(Status::internal("No system found"));
+            }
+        };
+
+        let orchestrator = CountQueryOrchestrator::new(
+            system.clone(),
+            &segment_uuid,
+            self.log.clone(),
+            self.sysdb.clone(),
+            dispatcher.clone(),
+            self.blockfile_provider.clone(),
+        );
+
+        let result = orchestrator.run().await;
+        let c = match result {
+            Ok(r) => {
+                println!("Count value {}", r);
+                r
+            }
+            Err(e) => {
+                println!("Error! {:?}", e);
+                return Err(e.into());
+            }
+        };
+        let response = CountRecordsResponse { count: c as u32 };
+        Ok(Response::new(response))
+    }
+
     async fn query_metadata_instrumented(
         &self,
         request: Request<QueryMetadataRequest>,
@@ -330,11 +756,22 @@ impl WorkerServer {
             }
         };
 
-        // For now we don't support limit/offset/where/where document
-        if request.limit.is_some() || request.offset.is_some() {
-            tracing::error!("Limit and offset not supported");
-            return Err(Status::unimplemented("Limit and offset not supported"));
-        }
+        // Pages are taken in offset id order, which only changes when the collection is
+        // compacted.
+        let offset = match usize::try_from(request.offset.unwrap_or(0)) {
+            Ok(offset) => offset,
+            Err(_) => {
+                tracing::error!("Negative offset");
+                return Err(Status::invalid_argument("Offset must not be negative"));
+            }
+        };
+        let limit = match request.limit.map(usize::try_from).transpose() {
+            Ok(limit) => limit,
+            Err(_) => {
+                tracing::error!("Negative limit");
+                return Err(Status::invalid_argument("Limit must not be negative"));
+            }
+        };
 
         // If no ids are provided, pass None to the orchestrator
         let query_ids = match request.ids.len() {
@@ -347,7 +784,7 @@ impl WorkerServer {
                 Ok(where_clause) => Some(where_clause),
                 Err(_) => {
                     tracing::error!("Error converting where clause");
-                    return Err(Status::internal(format!("Error converting where clause",)));
+                    return Err(Status::invalid_argument("Error converting where clause"));
                 }
             },
             None => None,
@@ -358,9 +795,9 @@ impl WorkerServer {
                 Ok(where_document_clause) => Some(where_document_clause),
                 Err(_) => {
                     tracing::error!("Error converting where document clause");
-                    return Err(Status::internal(format!(
+                    return Err(Status::invalid_argument(
                         "Error converting where document clause",
-                    )));
+                    ));
                 }
             },
             None => None,
@@ -369,13 +806,17 @@ impl WorkerServer {
         let orchestrator = MetadataQueryOrchestrator::new(
             system.clone(),
             &segment_uuid,
-            query_ids,
             self.log.clone(),
             self.sysdb.clone(),
             dispatcher.clone(),
             self.blockfile_provider.clone(),
-            where_clause,
-            where_document_clause,
+            MetadataQuery {
+                query_ids,
+                where_clause,
+                where_document_clause,
+                offset,
+                limit,
+            },
         );
 
         let result = orchestrator.run().await;
@@ -383,10 +824,7 @@ impl WorkerServer {
             Ok(result) => result,
             Err(e) => {
                 tracing::error!("Error running orchestrator: {}", e);
-                return Err(Status::internal(format!(
-                    "Error running orchestrator: {}",
-                    e
-                )));
+                return Err(e.into());
             }
         };
 
@@ -429,6 +867,7 @@ impl chroma_proto::vector_reader_server::VectorReader for WorkerServer {
         &self,
         request: Request<GetVectorsRequest>,
     ) -> Result<Response<GetVectorsResponse>, Status> {
+        self.check_not_shutting_down()?;
         // Note: We cannot write a middleware that instruments every service rpc
         // with a span because of https://github.com/hyperium/tonic/pull/1202.
         let request_span = trace_span!(
@@ -438,15 +877,20 @@ impl chroma_proto::vector_reader_server::VectorReader for WorkerServer {
         );
 
         let instrumented_span = wrap_span_with_parent_context(request_span, request.metadata());
-        self.get_vectors_instrumented(request)
+        let start = Instant::now();
+        let result = self
+            .get_vectors_instrumented(request)
             .instrument(instrumented_span)
-            .await
+            .await;
+        metrics().record_query("get_vectors", result.is_ok(), start.elapsed());
+        result
     }
 
     async fn query_vectors(
         &self,
         request: Request<QueryVectorsRequest>,
     ) -> Result<Response<QueryVectorsResponse>, Status> {
+        self.check_not_shutting_down()?;
         // Note: We cannot write a middleware that instruments every service rpc
         // with a span because of https://github.com/hyperium/tonic/pull/1202.
         let query_span = trace_span!(
@@ -457,9 +901,18 @@ impl chroma_proto::vector_reader_server::VectorReader for WorkerServer {
             allowed_ids = ?request.get_ref().allowed_ids
         );
         let instrumented_span = wrap_span_with_parent_context(query_span, request.metadata());
-        self.query_vectors_instrumented(request)
+        let deadline = Deadline::after(
+            grpc_timeout(request.metadata()).unwrap_or(self.default_request_timeout),
+        );
+        // The query is given up as soon as its client goes away, and with it this future.
+        let _cancel_on_drop = deadline.cancel_on_drop();
+        let start = Instant::now();
+        let result = self
+            .query_vectors_instrumented(request, deadline)
             .instrument(instrumented_span)
-            .await
+            .await;
+        metrics().record_query("query_vectors", result.is_ok(), start.elapsed());
+        result
     }
 }
 
@@ -469,60 +922,85 @@ impl chroma_proto::metadata_reader_server::MetadataReader for WorkerServer {
         &self,
         request: Request<CountRecordsRequest>,
     ) -> Result<Response<CountRecordsResponse>, Status> {
-        let request = request.into_inner();
-        let segment_uuid = match Uuid::parse_str(&request.segment_id) {
-            Ok(uuid) => uuid,
-            Err(_) => {
-                return Err(Status::invalid_argument("Invalid Segment UUID"));
-            }
-        };
-        println!("Querying count for segment {}", segment_uuid);
-        let dispatcher = match self.dispatcher {
-            Some(ref dispatcher) => dispatcher,
-            None => {
-                return Err(Status::internal("No dispatcher found"));
-            }
-        };
-
-        let system = match self.system {
-            Some(ref system) => system,
-            None => {
-                return Err(Status::internal("No system found"));
-            }
-        };
-
-        let orchestrator = CountQueryOrchestrator::new(
-            system.clone(),
-            &segment_uuid,
-            self.log.clone(),
-            self.sysdb.clone(),
-            dispatcher.clone(),
-            self.blockfile_provider.clone(),
-        );
-
-        let result = orchestrator.run().await;
-        let c = match result {
-            Ok(r) => {
-                println!("Count value {}", r);
-                r
-            }
-            Err(e) => {
-                println!("Error! {:?}", e);
-                // TODO: Return 0 for now but should return an error at some point.
-                0
-            }
-        };
-        let response = CountRecordsResponse { count: c as u32 };
-        Ok(Response::new(response))
+        self.check_not_shutting_down()?;
+        let count_span = trace_span!("Count records", segment_id = request.get_ref().segment_id);
+        let instrumented_span = wrap_span_with_parent_context(count_span, request.metadata());
+        self.count_records_instrumented(request)
+            .instrument(instrumented_span)
+            .await
     }
 
     async fn query_metadata(
         &self,
         request: Request<QueryMetadataRequest>,
     ) -> Result<Response<QueryMetadataResponse>, Status> {
+        self.check_not_shutting_down()?;
         let query_span = trace_span!("Query metadata", segment_id = request.get_ref().segment_id);
         let instrumented_span = wrap_span_with_parent_context(query_span, request.metadata());
-        self.query_metadata_instrumented(request)
+        let start = Instant::now();
+        let result = self
+            .query_metadata_instrumented(request)
+            .instrument(instrumented_span)
+            .await;
+        metrics().record_query("query_metadata", result.is_ok(), start.elapsed());
+        result
+    }
+}
+
+fn proto_index_stats(index_id: Uuid, stats: crate::index::hnsw_provider::IndexStats) -> IndexStats {
+    let last_persisted_ms = stats.index.saved_at.and_then(|saved_at| {
+        saved_at
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|since_epoch| since_epoch.as_millis() as u64)
+    });
+    IndexStats {
+        index_id: index_id.to_string(),
+        element_count: stats.index.element_count as u64,
+        deleted_count: stats.index.deleted_count as u64,
+        capacity: stats.index.capacity as u64,
+        dimension: stats.index.dimensionality as u32,
+        distance_function: stats.index.distance_function,
+        estimated_bytes: stats.index.estimated_size as u64,
+        last_persisted_ms,
+        dirty: stats.dirty,
+        cached: stats.cached,
+    }
+}
+
+#[tonic::async_trait]
+impl chroma_proto::index_admin_server::IndexAdmin for WorkerServer {
+    async fn get_index_stats(
+        &self,
+        request: Request<GetIndexStatsRequest>,
+    ) -> Result<Response<IndexStats>, Status> {
+        self.check_not_shutting_down()?;
+        let request_span = trace_span!("Get index stats", target = ?request.get_ref().target);
+        let instrumented_span = wrap_span_with_parent_context(request_span, request.metadata());
+        self.get_index_stats_instrumented(request)
+            .instrument(instrumented_span)
+            .await
+    }
+
+    async fn check_index(
+        &self,
+        request: Request<CheckIndexRequest>,
+    ) -> Result<Response<CheckIndexResponse>, Status> {
+        self.check_not_shutting_down()?;
+        let request_span = trace_span!(
+            "Check index",
+            collection_id = request.get_ref().collection_id,
+            rebuild = request.get_ref().rebuild
+        );
+        let instrumented_span = wrap_span_with_parent_context(request_span, request.metadata());
+        let deadline =
+            Deadline::after(grpc_timeout(request.metadata()).unwrap_or(self.admin_request_timeout));
+        // A rebuild that is given up on, because its client went away or its deadline passed,
+        // is not registered unless it had already started registering. Either way it can be
+        // retried with the same id, and a retry of a rebuild that did get registered reports
+        // it instead of rebuilding again.
+        let _cancel_on_drop = deadline.cancel_on_drop();
+        self.check_index_instrumented(request, deadline)
             .instrument(instrumented_span)
             .await
     }
@@ -563,6 +1041,8 @@ impl chroma_proto::debug_server::Debug for WorkerServer {
 #[cfg(test)]
 mod tests {
     use crate::execution::dispatcher;
+    use crate::index::config::HnswProviderConfig;
+    use crate::index::MAX_EF_SEARCH;
     use crate::log::log::InMemoryLog;
     use crate::storage::local::LocalStorage;
     use crate::storage::Storage;
@@ -586,12 +1066,20 @@ mod tests {
             system: None,
             sysdb: Box::new(SysDb::Test(sysdb)),
             log: Box::new(Log::InMemory(log)),
+            storage: storage.clone(),
             hnsw_index_provider: HnswIndexProvider::new(
                 storage.clone(),
                 tmp_dir.path().to_path_buf(),
+                &HnswProviderConfig::default(),
             ),
             blockfile_provider: BlockfileProvider::new_arrow(storage),
+            distance_registry: Arc::new(DistanceRegistry::new()),
+            compute_pool: ComputePool::new(1).unwrap(),
             port,
+            default_request_timeout: Duration::from_secs(30),
+            admin_request_timeout: Duration::from_secs(600),
+            shutdown: CancellationToken::new(),
+            health: HealthReporter::new(),
         };
 
         let system: system::System = system::System::new();
@@ -617,4 +1105,1093 @@ mod tests {
         let response = client.get_info(Request::new(())).await;
         assert!(response.is_ok());
     }
+
+    fn query_vectors_request(segment_id: Uuid) -> Request<QueryVectorsRequest> {
+        let vector = (vec![1.0, 2.0, 3.0], ScalarEncoding::FLOAT32, 3)
+            .try_into()
+            .unwrap();
+        Request::new(QueryVectorsRequest {
+            vectors: vec![vector],
+            k: 1,
+            allowed_ids: vec![],
+            include_embeddings: false,
+            segment_id: segment_id.to_string(),
+            ef_search: None,
+            r#where: None,
+            where_document: None,
+            include_documents: false,
+            include_metadatas: false,
+            return_similarity: false,
+        })
+    }
+
+    // A worker over `sysdb` and `log` with nothing cached yet, like one that just started.
+    fn start_worker(sysdb: &TestSysDb, log: Log) -> (WorkerServer, tempfile::TempDir) {
+        let tmp_dir = tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let mut server = WorkerServer {
+            dispatcher: None,
+            system: None,
+            sysdb: Box::new(SysDb::Test(sysdb.clone())),
+            log: Box::new(log),
+            storage: storage.clone(),
+            hnsw_index_provider: HnswIndexProvider::new(
+                storage.clone(),
+                tmp_dir.path().to_path_buf(),
+                &HnswProviderConfig::default(),
+            ),
+            blockfile_provider: BlockfileProvider::new_arrow(storage),
+            distance_registry: Arc::new(DistanceRegistry::new()),
+            compute_pool: ComputePool::new(1).unwrap(),
+            port: 0,
+            default_request_timeout: Duration::from_secs(30),
+            admin_request_timeout: Duration::from_secs(600),
+            shutdown: CancellationToken::new(),
+            health: HealthReporter::new(),
+        };
+        let system: system::System = system::System::new();
+        let dispatcher = dispatcher::Dispatcher::new(4, 10, 10);
+        let dispatcher_handle = system.start_component(dispatcher);
+        server.set_system(system);
+        server.set_dispatcher(dispatcher_handle.receiver());
+        (server, tmp_dir)
+    }
+
+    #[tokio::test]
+    async fn query_vectors_maps_error_codes_to_status() {
+        use crate::chroma_proto::metadata_reader_server::MetadataReader;
+        use crate::chroma_proto::vector_reader_server::VectorReader;
+        use crate::types::{Collection, Segment, SegmentScope, SegmentType};
+
+        let mut sysdb = TestSysDb::new();
+        let collection_id = Uuid::new_v4();
+        sysdb.add_collection(Collection {
+            id: collection_id,
+            name: "collection".to_string(),
+            metadata: None,
+            dimension: Some(3),
+            tenant: "tenant".to_string(),
+            database: "database".to_string(),
+            log_position: -1,
+            version: 0,
+        });
+        // An HNSW segment that does not belong to any collection is an invalid argument.
+        let orphan_segment_id = Uuid::new_v4();
+        sysdb.add_segment(Segment {
+            id: orphan_segment_id,
+            r#type: SegmentType::HnswDistributed,
+            scope: SegmentScope::VECTOR,
+            collection: None,
+            metadata: None,
+            file_path: HashMap::new(),
+        });
+        // An HNSW segment whose index files are missing from storage is an internal error.
+        let missing_files_segment_id = Uuid::new_v4();
+        sysdb.add_segment(Segment {
+            id: missing_files_segment_id,
+            r#type: SegmentType::HnswDistributed,
+            scope: SegmentScope::VECTOR,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: HashMap::from([(
+                "hnsw_index".to_string(),
+                vec![Uuid::new_v4().to_string()],
+            )]),
+        });
+        sysdb.add_segment(Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::BlockfileRecord,
+            scope: SegmentScope::RECORD,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: HashMap::new(),
+        });
+
+        let log = InMemoryLog::new();
+        let tmp_dir = tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let mut server = WorkerServer {
+            dispatcher: None,
+            system: None,
+            sysdb: Box::new(SysDb::Test(sysdb)),
+            log: Box::new(Log::InMemory(log)),
+            storage: storage.clone(),
+            hnsw_index_provider: HnswIndexProvider::new(
+                storage.clone(),
+                tmp_dir.path().to_path_buf(),
+                &HnswProviderConfig::default(),
+            ),
+            blockfile_provider: BlockfileProvider::new_arrow(storage),
+            distance_registry: Arc::new(DistanceRegistry::new()),
+            compute_pool: ComputePool::new(1).unwrap(),
+            port: 0,
+            default_request_timeout: Duration::from_secs(30),
+            admin_request_timeout: Duration::from_secs(600),
+            shutdown: CancellationToken::new(),
+            health: HealthReporter::new(),
+        };
+        let system: system::System = system::System::new();
+        let dispatcher = dispatcher::Dispatcher::new(4, 10, 10);
+        let dispatcher_handle = system.start_component(dispatcher);
+        server.set_system(system.clone());
+        server.set_dispatcher(dispatcher_handle.receiver());
+
+        let status = server
+            .query_vectors(query_vectors_request(orphan_segment_id))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(status.message(), "HNSW segment has no collection");
+
+        let missing_segment_id = Uuid::new_v4();
+        let status = server
+            .query_vectors(query_vectors_request(missing_segment_id))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(
+            status.message(),
+            format!("Hnsw segment with id: {} not found", missing_segment_id)
+        );
+
+        let status = server
+            .query_vectors(query_vectors_request(missing_files_segment_id))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Internal);
+
+        // Query vectors of another dimension are rejected before the index is opened.
+        let mut request = query_vectors_request(missing_files_segment_id);
+        request.get_mut().vectors.push(
+            (vec![1.0, 2.0], ScalarEncoding::FLOAT32, 2)
+                .try_into()
+                .unwrap(),
+        );
+        let status = server.query_vectors(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "Query vector 1 has dimension 2, the collection has dimension 3"
+        );
+
+        // Collections using l2 have no similarity to return.
+        let mut request = query_vectors_request(missing_files_segment_id);
+        request.get_mut().return_similarity = true;
+        let status = server.query_vectors(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "Similarities are only defined for cosine and ip, not l2"
+        );
+
+        // The ef_search override is validated before the segment is looked up.
+        for ef_search in [0, (MAX_EF_SEARCH + 1) as i32] {
+            let mut request = query_vectors_request(missing_segment_id);
+            request.get_mut().ef_search = Some(ef_search);
+            let status = server.query_vectors(request).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+
+        // Once the worker starts shutting down, even valid requests are turned away.
+        server.shutdown_token().cancel();
+        let status = server
+            .query_vectors(query_vectors_request(missing_segment_id))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        let status = server
+            .count_records(Request::new(CountRecordsRequest {
+                segment_id: missing_segment_id.to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn query_vectors_reads_the_log_that_is_not_compacted() {
+        use crate::chroma_proto::vector_reader_server::VectorReader;
+        use crate::log::log::InternalLogRecord;
+        use crate::tracing::metrics::test::collected;
+        use crate::types::{
+            Collection, LogRecord, Operation, OperationRecord, Segment, SegmentScope, SegmentType,
+        };
+        use opentelemetry::KeyValue;
+
+        let mut sysdb = TestSysDb::new();
+        let collection_id = Uuid::new_v4();
+        sysdb.add_collection(Collection {
+            id: collection_id,
+            name: "collection".to_string(),
+            metadata: None,
+            dimension: Some(3),
+            tenant: "tenant".to_string(),
+            database: "database".to_string(),
+            log_position: -1,
+            version: 0,
+        });
+        // Neither segment has ever been compacted.
+        let segment_id = Uuid::new_v4();
+        for (id, r#type, scope) in [
+            (
+                segment_id,
+                SegmentType::HnswDistributed,
+                SegmentScope::VECTOR,
+            ),
+            (
+                Uuid::new_v4(),
+                SegmentType::BlockfileRecord,
+                SegmentScope::RECORD,
+            ),
+        ] {
+            sysdb.add_segment(Segment {
+                id,
+                r#type,
+                scope,
+                collection: Some(collection_id),
+                metadata: None,
+                file_path: HashMap::new(),
+            });
+        }
+
+        let (mut server, _tmp_dir) = start_worker(&sysdb, Log::InMemory(InMemoryLog::new()));
+
+        let mut log_offset = 0;
+        let mut push =
+            |server: &mut WorkerServer, id: &str, embedding: Option<Vec<f32>>, operation| {
+                let log = match server.log.as_mut() {
+                    Log::InMemory(log) => log,
+                    _ => unreachable!(),
+                };
+                log.add_log(
+                    collection_id,
+                    Box::new(InternalLogRecord {
+                        collection_id,
+                        log_offset,
+                        log_ts: 1,
+                        record: LogRecord {
+                            log_offset,
+                            record: OperationRecord {
+                                id: id.to_string(),
+                                embedding: embedding.map(Into::into),
+                                encoding: None,
+                                metadata: None,
+                                document: None,
+                                operation,
+                            },
+                        },
+                    }),
+                );
+                log_offset += 1;
+            };
+        // The query is the origin, and k is larger than the number of records.
+        let query = |server: &WorkerServer| {
+            let server = server.clone();
+            async move {
+                let mut request = query_vectors_request(segment_id);
+                request.get_mut().vectors = vec![(vec![0.0, 0.0, 0.0], ScalarEncoding::FLOAT32, 3)
+                    .try_into()
+                    .unwrap()];
+                request.get_mut().k = 10;
+                let response = server.query_vectors(request).await.unwrap().into_inner();
+                assert_eq!(response.results.len(), 1);
+                response.results[0]
+                    .results
+                    .iter()
+                    .map(|result| (result.id.clone(), result.distance))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let expected = |results: &[(&str, f32)]| -> Vec<(String, f32)> {
+            results
+                .iter()
+                .map(|(id, distance)| (id.to_string(), *distance))
+                .collect()
+        };
+
+        // Other tests record into the same instruments, so the queries only add to them.
+        let recorded = || {
+            [
+                collected(
+                    "query.duration",
+                    &[
+                        KeyValue::new("method", "query_vectors"),
+                        KeyValue::new("succeeded", true),
+                    ],
+                ),
+                collected(
+                    "operator.duration",
+                    &[KeyValue::new("operator", "BruteForceKnnOperator")],
+                ),
+                collected("brute_force.candidates", &[]),
+            ]
+        };
+        let recorded_before = recorded();
+
+        for (id, x) in [("a", 1.0), ("b", 2.0), ("c", 3.0)] {
+            push(&mut server, id, Some(vec![x, 0.0, 0.0]), Operation::Add);
+        }
+        assert_eq!(
+            query(&server).await,
+            expected(&[("a", 1.0), ("b", 4.0), ("c", 9.0)])
+        );
+
+        // Updated embeddings are scored where they are now, and only once.
+        push(
+            &mut server,
+            "a",
+            Some(vec![4.0, 0.0, 0.0]),
+            Operation::Update,
+        );
+        push(
+            &mut server,
+            "d",
+            Some(vec![0.0, 0.0, 0.5]),
+            Operation::Upsert,
+        );
+        assert_eq!(
+            query(&server).await,
+            expected(&[("d", 0.25), ("b", 4.0), ("c", 9.0), ("a", 16.0)])
+        );
+
+        push(&mut server, "b", None, Operation::Delete);
+        push(&mut server, "d", None, Operation::Delete);
+        assert_eq!(query(&server).await, expected(&[("c", 9.0), ("a", 16.0)]));
+
+        for (before, after) in recorded_before.into_iter().zip(recorded()) {
+            assert!(
+                after >= before + 3,
+                "{} recordings before, {} after",
+                before,
+                after
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn a_restarted_worker_answers_like_one_that_never_crashed() {
+        use crate::chroma_proto::vector_reader_server::VectorReader;
+        use crate::log::log::InternalLogRecord;
+        use crate::types::{
+            Collection, LogRecord, Operation, OperationRecord, Segment, SegmentScope, SegmentType,
+        };
+
+        let mut sysdb = TestSysDb::new();
+        let collection_id = Uuid::new_v4();
+        sysdb.add_collection(Collection {
+            id: collection_id,
+            name: "collection".to_string(),
+            metadata: None,
+            dimension: Some(3),
+            tenant: "tenant".to_string(),
+            database: "database".to_string(),
+            log_position: -1,
+            version: 0,
+        });
+        let segment_id = Uuid::new_v4();
+        for (id, r#type, scope) in [
+            (
+                segment_id,
+                SegmentType::HnswDistributed,
+                SegmentScope::VECTOR,
+            ),
+            (
+                Uuid::new_v4(),
+                SegmentType::BlockfileRecord,
+                SegmentScope::RECORD,
+            ),
+        ] {
+            sysdb.add_segment(Segment {
+                id,
+                r#type,
+                scope,
+                collection: Some(collection_id),
+                metadata: None,
+                file_path: HashMap::new(),
+            });
+        }
+
+        let push = |server: &mut WorkerServer, log_offset: i64, id: &str, x: f32, operation| {
+            let log = match server.log.as_mut() {
+                Log::InMemory(log) => log,
+                _ => unreachable!(),
+            };
+            log.add_log(
+                collection_id,
+                Box::new(InternalLogRecord {
+                    collection_id,
+                    log_offset,
+                    log_ts: 1,
+                    record: LogRecord {
+                        log_offset,
+                        record: OperationRecord {
+                            id: id.to_string(),
+                            embedding: match operation {
+                                Operation::Delete => None,
+                                _ => Some(vec![x, 0.0, 0.0].into()),
+                            },
+                            encoding: None,
+                            metadata: None,
+                            document: None,
+                            operation,
+                        },
+                    },
+                }),
+            );
+        };
+        let query = |server: &WorkerServer| {
+            let server = server.clone();
+            async move {
+                let mut request = query_vectors_request(segment_id);
+                request.get_mut().vectors = vec![(vec![0.0, 0.0, 0.0], ScalarEncoding::FLOAT32, 3)
+                    .try_into()
+                    .unwrap()];
+                request.get_mut().k = 10;
+                let response = server.query_vectors(request).await.unwrap().into_inner();
+                response.results[0]
+                    .results
+                    .iter()
+                    .map(|result| (result.id.clone(), result.distance))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let writes = [
+            ("a", 1.0, Operation::Add),
+            ("b", 2.0, Operation::Add),
+            ("c", 3.0, Operation::Add),
+            ("a", 4.0, Operation::Update),
+            ("b", 0.0, Operation::Delete),
+            ("d", 0.5, Operation::Upsert),
+            ("c", 0.0, Operation::Delete),
+            ("b", 5.0, Operation::Add),
+        ];
+        let (mut baseline, _baseline_dir) = start_worker(&sysdb, Log::InMemory(InMemoryLog::new()));
+        let (mut worker, mut _worker_dir) = start_worker(&sysdb, Log::InMemory(InMemoryLog::new()));
+        for (log_offset, (id, x, operation)) in writes.into_iter().enumerate() {
+            let log_offset = log_offset as i64;
+            push(&mut baseline, log_offset, id, x, operation.clone());
+            push(&mut worker, log_offset, id, x, operation);
+            if log_offset == 4 {
+                // The worker crashes mid-stream. It comes back with nothing in memory, while
+                // the log keeps every record that was written.
+                let log = (*worker.log).clone();
+                drop(worker);
+                (worker, _worker_dir) = start_worker(&sysdb, log);
+            }
+            assert_eq!(query(&worker).await, query(&baseline).await);
+        }
+        assert_eq!(
+            query(&worker).await,
+            vec![
+                ("d".to_string(), 0.25),
+                ("a".to_string(), 16.0),
+                ("b".to_string(), 25.0)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn query_metadata_pages_through_the_collection() {
+        use crate::chroma_proto::metadata_reader_server::MetadataReader;
+        use crate::log::log::InternalLogRecord;
+        use crate::types::{
+            Collection, LogRecord, Operation, OperationRecord, Segment, SegmentScope, SegmentType,
+            UpdateMetadataValue,
+        };
+
+        let mut sysdb = TestSysDb::new();
+        let collection_id = Uuid::new_v4();
+        sysdb.add_collection(Collection {
+            id: collection_id,
+            name: "collection".to_string(),
+            metadata: None,
+            dimension: Some(3),
+            tenant: "tenant".to_string(),
+            database: "database".to_string(),
+            log_position: -1,
+            version: 0,
+        });
+        let segment_id = Uuid::new_v4();
+        for (id, r#type, scope) in [
+            (
+                segment_id,
+                SegmentType::BlockfileMetadata,
+                SegmentScope::METADATA,
+            ),
+            (
+                Uuid::new_v4(),
+                SegmentType::BlockfileRecord,
+                SegmentScope::RECORD,
+            ),
+        ] {
+            sysdb.add_segment(Segment {
+                id,
+                r#type,
+                scope,
+                collection: Some(collection_id),
+                metadata: None,
+                file_path: HashMap::new(),
+            });
+        }
+        let (mut server, _tmp_dir) = start_worker(&sysdb, Log::InMemory(InMemoryLog::new()));
+
+        // 25 records, of which every fifth is deleted again.
+        let mut log_offset = 0;
+        let mut push = |server: &mut WorkerServer, i: i32, operation| {
+            let log = match server.log.as_mut() {
+                Log::InMemory(log) => log,
+                _ => unreachable!(),
+            };
+            let add = operation == Operation::Add;
+            log.add_log(
+                collection_id,
+                Box::new(InternalLogRecord {
+                    collection_id,
+                    log_offset,
+                    log_ts: 1,
+                    record: LogRecord {
+                        log_offset,
+                        record: OperationRecord {
+                            id: format!("id_{}", i),
+                            embedding: add.then(|| vec![i as f32, 0.0, 0.0].into()),
+                            encoding: None,
+                            metadata: add.then(|| {
+                                HashMap::from([("i".to_string(), UpdateMetadataValue::Int(i))])
+                            }),
+                            document: add.then(|| format!("document {}", i)),
+                            operation,
+                        },
+                    },
+                }),
+            );
+            log_offset += 1;
+        };
+        for i in 0..25 {
+            push(&mut server, i, Operation::Add);
+        }
+        for i in (0..25).step_by(5) {
+            push(&mut server, i, Operation::Delete);
+        }
+
+        let get = |server: &WorkerServer, offset: Option<i32>, limit: Option<i32>| {
+            let server = server.clone();
+            async move {
+                server
+                    .query_metadata(Request::new(QueryMetadataRequest {
+                        segment_id: segment_id.to_string(),
+                        r#where: None,
+                        where_document: None,
+                        ids: vec![],
+                        limit,
+                        offset,
+                    }))
+                    .await
+                    .map(|response| response.into_inner().records)
+            }
+        };
+        let all = get(&server, None, None).await.unwrap();
+        let expected: Vec<String> = (0..25)
+            .filter(|i| i % 5 != 0)
+            .map(|i| format!("id_{}", i))
+            .collect();
+        assert_eq!(
+            all.iter()
+                .map(|record| record.id.clone())
+                .collect::<Vec<_>>(),
+            expected
+        );
+        let count = server
+            .count_records(Request::new(CountRecordsRequest {
+                segment_id: segment_id.to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .count;
+        assert_eq!(count as usize, expected.len());
+
+        // Pages of 4 put together are the collection, metadata and documents included, and
+        // only the last one is short. Stepping the offset up to the count reads them all.
+        let pages = |server: &WorkerServer, count: i32| {
+            let server = server.clone();
+            async move {
+                let mut paged = Vec::new();
+                for offset in (0..count).step_by(4) {
+                    let page = get(&server, Some(offset), Some(4)).await.unwrap();
+                    assert_eq!(page.len(), 4.min(count - offset) as usize);
+                    paged.extend(page);
+                }
+                paged
+            }
+        };
+        assert_eq!(pages(&server, count as i32).await, all);
+        assert!(get(&server, Some(1000), Some(4)).await.unwrap().is_empty());
+
+        // A record of the first page is deleted. The records after it move up a position, so
+        // that the pages still agree with the count.
+        push(&mut server, 2, Operation::Delete);
+        let all: Vec<_> = all
+            .into_iter()
+            .filter(|record| record.id != "id_2")
+            .collect();
+        assert_eq!(pages(&server, count as i32 - 1).await, all);
+
+        for (offset, limit) in [(Some(-1), None), (None, Some(-4))] {
+            let status = get(&server, offset, limit).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn test_grpc_timeout() {
+        let timeout = |value: &str| {
+            let mut metadata = MetadataMap::new();
+            metadata.insert("grpc-timeout", value.parse().unwrap());
+            grpc_timeout(&metadata)
+        };
+        assert_eq!(timeout("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(timeout("3M"), Some(Duration::from_secs(180)));
+        assert_eq!(timeout("10S"), Some(Duration::from_secs(10)));
+        assert_eq!(timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(timeout("99999999u"), Some(Duration::from_micros(99999999)));
+        assert_eq!(timeout("7n"), Some(Duration::from_nanos(7)));
+        for invalid in ["S", "10", "10s", "+10S", "-1S", "123456789S"] {
+            assert_eq!(timeout(invalid), None, "{}", invalid);
+        }
+        assert_eq!(grpc_timeout(&MetadataMap::new()), None);
+    }
+
+    // Holds on to every task instead of running it, like operators that never finish.
+    #[derive(Clone, Debug, Default)]
+    struct StalledDispatcher {
+        tasks: Arc<parking_lot::Mutex<Vec<TaskMessage>>>,
+    }
+
+    #[async_trait]
+    impl Receiver<TaskMessage> for StalledDispatcher {
+        async fn send(
+            &self,
+            task: TaskMessage,
+            _tracing_context: Option<tracing::Span>,
+        ) -> Result<(), crate::system::ChannelError> {
+            self.tasks.lock().push(task);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn query_vectors_is_given_up_at_its_deadline() {
+        use crate::chroma_proto::vector_reader_server::VectorReader;
+        use crate::types::{Collection, Segment, SegmentScope, SegmentType};
+
+        let mut sysdb = TestSysDb::new();
+        let collection_id = Uuid::new_v4();
+        sysdb.add_collection(Collection {
+            id: collection_id,
+            name: "collection".to_string(),
+            metadata: None,
+            dimension: Some(3),
+            tenant: "tenant".to_string(),
+            database: "database".to_string(),
+            log_position: -1,
+            version: 0,
+        });
+        let segment_id = Uuid::new_v4();
+        sysdb.add_segment(Segment {
+            id: segment_id,
+            r#type: SegmentType::HnswDistributed,
+            scope: SegmentScope::VECTOR,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: HashMap::new(),
+        });
+        sysdb.add_segment(Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::BlockfileRecord,
+            scope: SegmentScope::RECORD,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: HashMap::new(),
+        });
+
+        let tmp_dir = tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let dispatcher = StalledDispatcher::default();
+        let mut server = WorkerServer {
+            dispatcher: None,
+            system: None,
+            sysdb: Box::new(SysDb::Test(sysdb)),
+            log: Box::new(Log::InMemory(InMemoryLog::new())),
+            storage: storage.clone(),
+            hnsw_index_provider: HnswIndexProvider::new(
+                storage.clone(),
+                tmp_dir.path().to_path_buf(),
+                &HnswProviderConfig::default(),
+            ),
+            blockfile_provider: BlockfileProvider::new_arrow(storage),
+            distance_registry: Arc::new(DistanceRegistry::new()),
+            compute_pool: ComputePool::new(1).unwrap(),
+            port: 0,
+            default_request_timeout: Duration::from_millis(100),
+            admin_request_timeout: Duration::from_secs(600),
+            shutdown: CancellationToken::new(),
+            health: HealthReporter::new(),
+        };
+        server.set_system(system::System::new());
+        server.set_dispatcher(Box::new(dispatcher.clone()));
+
+        // Without a deadline of its own, the request gets the default one.
+        let start = std::time::Instant::now();
+        let status = server
+            .query_vectors(query_vectors_request(segment_id))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // The deadline the client set takes precedence.
+        server.default_request_timeout = Duration::from_secs(60);
+        let mut request = query_vectors_request(segment_id);
+        request
+            .metadata_mut()
+            .insert("grpc-timeout", "50m".parse().unwrap());
+        let start = std::time::Instant::now();
+        let status = server.query_vectors(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // Only the log pulls were ever dispatched. Once they finish, the orchestrators that
+        // gave up on them are gone and nothing else is dispatched.
+        let stalled: Vec<TaskMessage> = dispatcher.tasks.lock().drain(..).collect();
+        assert_eq!(stalled.len(), 2);
+        for task in stalled {
+            task.run().await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(dispatcher.tasks.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_index_stats_describes_the_flushed_index() {
+        use crate::chroma_proto::index_admin_server::IndexAdmin;
+        use crate::index::Index;
+        use crate::types::{Collection, Segment, SegmentScope, SegmentType};
+
+        let mut sysdb = TestSysDb::new();
+        let collection_id = Uuid::new_v4();
+        sysdb.add_collection(Collection {
+            id: collection_id,
+            name: "collection".to_string(),
+            metadata: None,
+            dimension: Some(4),
+            tenant: "tenant".to_string(),
+            database: "database".to_string(),
+            log_position: 30,
+            version: 1,
+        });
+        let mut hnsw_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::HnswDistributed,
+            scope: SegmentScope::VECTOR,
+            collection: Some(collection_id),
+            metadata: Some(HashMap::from([(
+                "hnsw:space".to_string(),
+                MetadataValue::Str("ip".to_string()),
+            )])),
+            file_path: HashMap::new(),
+        };
+        let record_segment = Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::BlockfileRecord,
+            scope: SegmentScope::RECORD,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: HashMap::new(),
+        };
+
+        let (server, tmp_dir) = start_worker(&sysdb, Log::InMemory(InMemoryLog::new()));
+
+        // A compactor flushes an index with deletes to the storage of the worker.
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let compactor_dir = tempdir().unwrap();
+        let compactor = HnswIndexProvider::new(
+            storage,
+            compactor_dir.path().to_path_buf(),
+            &HnswProviderConfig::default(),
+        );
+        let index = compactor.create(&hnsw_segment, 4).unwrap();
+        let index_id = index.read().id;
+        for i in 0..30 {
+            index.read().add(i, &[i as f32, 1.0, 0.0, 0.5]);
+        }
+        for i in (0..30).step_by(6) {
+            index.read().delete(i);
+        }
+        let before_flush = std::time::SystemTime::now();
+        compactor.flush(&index_id).await.unwrap();
+        let flushed_size = index.read().estimated_size();
+        let capacity = index.read().capacity();
+        // An index that was never flushed has no stats to report.
+        let mut unflushed_segment = hnsw_segment.clone();
+        unflushed_segment.id = Uuid::new_v4();
+        unflushed_segment.collection = Some(Uuid::new_v4());
+        sysdb.add_segment(unflushed_segment.clone());
+        hnsw_segment.file_path =
+            HashMap::from([("hnsw_index".to_string(), vec![index_id.to_string()])]);
+        sysdb.add_segment(hnsw_segment.clone());
+        sysdb.add_segment(record_segment.clone());
+
+        let get_stats = |target| {
+            let server = server.clone();
+            async move {
+                server
+                    .get_index_stats(Request::new(GetIndexStatsRequest {
+                        target: Some(target),
+                    }))
+                    .await
+                    .map(|response| response.into_inner())
+            }
+        };
+
+        let stats = get_stats(get_index_stats_request::Target::CollectionId(
+            collection_id.to_string(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(stats.index_id, index_id.to_string());
+        assert_eq!(stats.element_count, 25);
+        assert_eq!(stats.deleted_count, 5);
+        assert_eq!(stats.capacity, capacity as u64);
+        assert_eq!(stats.dimension, 4);
+        assert_eq!(stats.distance_function, "ip");
+        assert_eq!(stats.estimated_bytes, flushed_size as u64);
+        let before_flush_ms = before_flush
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let last_persisted_ms = stats.last_persisted_ms.unwrap();
+        assert!(last_persisted_ms >= before_flush_ms);
+        assert!(
+            std::time::UNIX_EPOCH + Duration::from_millis(last_persisted_ms)
+                <= std::time::SystemTime::now()
+        );
+        assert!(!stats.dirty);
+        assert!(!stats.cached);
+        // The stats were read without loading the index.
+        assert!(server.hnsw_index_provider.get(&index_id).is_none());
+
+        let by_segment = get_stats(get_index_stats_request::Target::SegmentId(
+            hnsw_segment.id.to_string(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(by_segment, stats);
+
+        // Once a query loads the index, its stats are those of the cached index.
+        server
+            .hnsw_index_provider
+            .open(&index_id, &hnsw_segment, 4)
+            .await
+            .unwrap();
+        let cached = get_stats(get_index_stats_request::Target::CollectionId(
+            collection_id.to_string(),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            cached,
+            IndexStats {
+                cached: true,
+                ..stats
+            }
+        );
+
+        for (target, code) in [
+            (
+                get_index_stats_request::Target::SegmentId(record_segment.id.to_string()),
+                tonic::Code::InvalidArgument,
+            ),
+            (
+                get_index_stats_request::Target::SegmentId(unflushed_segment.id.to_string()),
+                tonic::Code::NotFound,
+            ),
+            (
+                get_index_stats_request::Target::SegmentId(Uuid::new_v4().to_string()),
+                tonic::Code::NotFound,
+            ),
+            (
+                get_index_stats_request::Target::CollectionId(Uuid::new_v4().to_string()),
+                tonic::Code::NotFound,
+            ),
+            (
+                get_index_stats_request::Target::CollectionId("not a uuid".to_string()),
+                tonic::Code::InvalidArgument,
+            ),
+        ] {
+            assert_eq!(get_stats(target).await.unwrap_err().code(), code);
+        }
+        let status = server
+            .get_index_stats(Request::new(GetIndexStatsRequest { target: None }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn check_index_rebuilds_the_index_once() {
+        use crate::chroma_proto::index_admin_server::IndexAdmin;
+        use crate::log::log::InternalLogRecord;
+        use crate::types::{
+            Collection, LogRecord, Operation, OperationRecord, Segment, SegmentScope,
+        };
+
+        let mut sysdb = TestSysDb::new();
+        let collection_id = Uuid::new_v4();
+        sysdb.add_collection(Collection {
+            id: collection_id,
+            name: "collection".to_string(),
+            metadata: None,
+            dimension: Some(2),
+            tenant: "tenant".to_string(),
+            database: "database".to_string(),
+            log_position: -1,
+            version: 0,
+        });
+        for (r#type, scope) in [
+            (SegmentType::BlockfileRecord, SegmentScope::RECORD),
+            (SegmentType::BlockfileMetadata, SegmentScope::METADATA),
+            (SegmentType::HnswDistributed, SegmentScope::VECTOR),
+        ] {
+            sysdb.add_segment(Segment {
+                id: Uuid::new_v4(),
+                r#type,
+                scope,
+                collection: Some(collection_id),
+                metadata: None,
+                file_path: HashMap::new(),
+            });
+        }
+        let mut log = InMemoryLog::new();
+        for n in 0..20 {
+            log.add_log(
+                collection_id,
+                Box::new(InternalLogRecord {
+                    collection_id,
+                    log_offset: n,
+                    log_ts: n,
+                    record: LogRecord {
+                        log_offset: n,
+                        record: OperationRecord {
+                            id: format!("id_{}", n),
+                            embedding: Some(vec![n as f32, 1.0].into()),
+                            encoding: None,
+                            metadata: None,
+                            document: None,
+                            operation: Operation::Add,
+                        },
+                    },
+                }),
+            );
+        }
+        let (server, tmp_dir) = start_worker(&sysdb, Log::InMemory(log));
+        let check = |rebuild: Option<(Uuid, i32)>, timeout: Option<&str>| {
+            let server = server.clone();
+            let mut request = Request::new(CheckIndexRequest {
+                collection_id: collection_id.to_string(),
+                rebuild: rebuild.is_some(),
+                rebuild_id: rebuild.map(|(id, _)| id.to_string()),
+                collection_version: rebuild.map(|(_, version)| version),
+            });
+            if let Some(timeout) = timeout {
+                request
+                    .metadata_mut()
+                    .insert("grpc-timeout", timeout.parse().unwrap());
+            }
+            async move {
+                server
+                    .check_index(request)
+                    .await
+                    .map(|response| response.into_inner())
+            }
+        };
+        let collection_version = || {
+            let mut sysdb = sysdb.clone();
+            async move {
+                sysdb
+                    .get_collection_snapshot(collection_id)
+                    .await
+                    .unwrap()
+                    .collection
+                    .version
+            }
+        };
+
+        // Nothing is compacted yet, so there is no index and nothing wrong with it.
+        let response = check(None, None).await.unwrap();
+        assert_eq!(response.collection_version, 0);
+        assert_eq!(response.stats, None);
+        assert!(response.discrepancies.is_empty());
+
+        // A rebuild of another version than the one checked is refused.
+        let first_id = Uuid::new_v4();
+        let status = check(Some((first_id, 3)), None).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let mut request = CheckIndexRequest {
+            collection_id: collection_id.to_string(),
+            rebuild: true,
+            rebuild_id: None,
+            collection_version: Some(0),
+        };
+        let status = server
+            .check_index(Request::new(request.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        request.rebuild_id = Some("not a uuid".to_string());
+        let status = server.check_index(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        // A rebuild that is given up on registers nothing.
+        let status = check(Some((first_id, 0)), Some("0m")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+        assert_eq!(collection_version().await, 0);
+
+        // Retrying it registers it once, with the records of the log.
+        let response = check(Some((first_id, 0)), None).await.unwrap();
+        let rebuilt = response.rebuilt.unwrap();
+        assert_eq!(rebuilt.index_id, first_id.to_string());
+        assert_eq!(rebuilt.element_count, 20);
+        assert_eq!(collection_version().await, 1);
+        let again = check(Some((first_id, 0)), None).await.unwrap();
+        assert_eq!(again.collection_version, 1);
+        assert_eq!(again.rebuilt.unwrap().index_id, first_id.to_string());
+        assert!(again.discrepancies.is_empty());
+        let stats = again.stats.unwrap();
+        assert_eq!(stats.index_id, first_id.to_string());
+        assert_eq!(stats.element_count, 20);
+        assert_eq!(stats.dimension, 2);
+        assert_eq!(collection_version().await, 1);
+
+        // Damaged files are found even though the index is cached, and a rebuild from the
+        // records replaces them.
+        std::fs::remove_file(
+            tmp_dir
+                .path()
+                .join(format!("hnsw/{}/data_level0.bin", first_id)),
+        )
+        .unwrap();
+        let response = check(None, None).await.unwrap();
+        assert_eq!(response.stats, None);
+        assert_eq!(response.discrepancies.len(), 1);
+        assert!(response.discrepancies[0].starts_with("The index does not load"));
+        let second_id = Uuid::new_v4();
+        let response = check(Some((second_id, 1)), None).await.unwrap();
+        assert_eq!(response.rebuilt.unwrap().element_count, 20);
+        let response = check(None, None).await.unwrap();
+        assert_eq!(response.collection_version, 2);
+        assert_eq!(response.stats.unwrap().index_id, second_id.to_string());
+        assert!(response.discrepancies.is_empty());
+    }
 }
@@ -0,0 +1,149 @@
+// Objects are written with the CRC32C of their bytes, which is checked when they are read
+// back, so that an object that was truncated or corrupted in storage is never handed to
+// the blockstore or an index. CRC32C is hardware accelerated on the platforms we run on,
+// and checksums of consecutive ranges can be combined, which lets ranges that are
+// downloaded out of order be checked as a whole.
+
+use crate::errors::{ChromaError, ErrorCodes};
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+// The name of the S3 object metadata that holds the checksum of an object.
+pub(crate) const CHECKSUM_METADATA: &str = "crc32c";
+
+#[derive(Error, Debug)]
+#[error("Checksum mismatch for {key}: expected {expected:08x}, got {actual:08x}")]
+pub(crate) struct ChecksumMismatchError {
+    pub(crate) key: String,
+    pub(crate) expected: u32,
+    pub(crate) actual: u32,
+}
+
+impl ChromaError for ChecksumMismatchError {
+    fn code(&self) -> ErrorCodes {
+        ErrorCodes::DataLoss
+    }
+}
+
+pub(crate) fn encode(checksum: u32) -> String {
+    format!("{:08x}", checksum)
+}
+
+pub(crate) fn decode(checksum: &str) -> Option<u32> {
+    u32::from_str_radix(checksum.trim(), 16).ok()
+}
+
+pub(crate) fn verify(key: &str, expected: u32, actual: u32) -> Result<(), ChecksumMismatchError> {
+    match expected == actual {
+        true => Ok(()),
+        false => Err(ChecksumMismatchError {
+            key: key.to_string(),
+            expected,
+            actual,
+        }),
+    }
+}
+
+/// The checksum of a file, read a buffer at a time.
+pub(crate) async fn file_checksum(path: &Path) -> io::Result<u32> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buf = vec![0; 1024 * 1024];
+    let mut checksum = 0;
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(checksum);
+        }
+        checksum = crc32c::crc32c_append(checksum, &buf[..read]);
+    }
+}
+
+/// Passes the bytes of an object through while computing their checksum. Reaching the end
+/// of the object fails with an `InvalidData` error wrapping a [`ChecksumMismatchError`] if
+/// the checksum is not the expected one.
+pub(crate) struct VerifyingReader<R> {
+    inner: R,
+    key: String,
+    expected: u32,
+    checksum: u32,
+}
+
+impl<R> VerifyingReader<R> {
+    pub(crate) fn new(inner: R, key: &str, expected: u32) -> Self {
+        VerifyingReader {
+            inner,
+            key: key.to_string(),
+            expected,
+            checksum: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for VerifyingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let this = &mut *self;
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                let read = &buf.filled()[filled..];
+                if read.is_empty() && buf.remaining() > 0 {
+                    if let Err(e) = verify(&this.key, this.expected, this.checksum) {
+                        return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, e)));
+                    }
+                }
+                this.checksum = crc32c::crc32c_append(this.checksum, read);
+                Poll::Ready(Ok(()))
+            }
+            poll => poll,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_through(bytes: &[u8], expected: u32) -> io::Result<Vec<u8>> {
+        let mut reader = VerifyingReader::new(bytes, "key", expected);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await?;
+        Ok(read)
+    }
+
+    #[tokio::test]
+    async fn test_verifying_reader() {
+        let bytes: Vec<u8> = (0..100000).map(|i| (i % 251) as u8).collect();
+        let checksum = crc32c::crc32c(&bytes);
+        assert_eq!(read_through(&bytes, checksum).await.unwrap(), bytes);
+
+        let e = read_through(&bytes[..99999], checksum).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let mismatch = e
+            .into_inner()
+            .unwrap()
+            .downcast::<ChecksumMismatchError>()
+            .unwrap();
+        assert_eq!(mismatch.expected, checksum);
+        assert_eq!(mismatch.code(), ErrorCodes::DataLoss);
+
+        assert_eq!(decode(&encode(checksum)), Some(checksum));
+        assert_eq!(decode("not hex"), None);
+    }
+
+    #[tokio::test]
+    async fn test_file_checksum() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("file");
+        let bytes: Vec<u8> = (0..3 * 1024 * 1024 + 5).map(|i| (i % 13) as u8).collect();
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(file_checksum(&path).await.unwrap(), crc32c::crc32c(&bytes));
+    }
+}
@@ -1,10 +1,35 @@
+use crate::config::{ConfigValidator, ValidateConfig};
 use serde::Deserialize;
 use std::path::Path;
 
+const DEFAULT_PART_SIZE_BYTES: u64 = 8 * 1024 * 1024;
+const DEFAULT_PART_CONCURRENCY: usize = 8;
+const DEFAULT_MULTIPART_UPLOAD_THRESHOLD_BYTES: u64 = 64 * 1024 * 1024;
+const DEFAULT_PART_MAX_ATTEMPTS: u32 = 4;
+// S3 rejects smaller parts, other than the last one of an object.
+const MIN_PART_SIZE_BYTES: u64 = 5 * 1024 * 1024;
+
+fn default_part_size_bytes() -> u64 {
+    DEFAULT_PART_SIZE_BYTES
+}
+
+fn default_part_concurrency() -> usize {
+    DEFAULT_PART_CONCURRENCY
+}
+
+fn default_multipart_upload_threshold_bytes() -> u64 {
+    DEFAULT_MULTIPART_UPLOAD_THRESHOLD_BYTES
+}
+
+fn default_part_max_attempts() -> u32 {
+    DEFAULT_PART_MAX_ATTEMPTS
+}
+
 #[derive(Deserialize, Debug)]
 /// The configuration for the chosen storage.
 /// # Options
 /// - S3: The configuration for the s3 storage.
+/// - Local: The configuration for the local filesystem storage.
 /// # Notes
 /// See config.rs in the root of the worker crate for an example of how to use
 /// config files to configure the worker.
@@ -26,11 +51,28 @@ pub(crate) enum S3CredentialsConfig {
 /// The configuration for the s3 storage type
 /// # Fields
 /// - bucket: The name of the bucket to use.
+/// - part_size_bytes: Objects are downloaded, and large files uploaded, in parts of this
+/// size. S3 requires parts of at least 5 MiB. Defaults to 8 MiB.
+/// - part_concurrency: How many parts of an object are transferred at the same time.
+/// Defaults to 8.
+/// - multipart_upload_threshold_bytes: Files larger than this are uploaded in parts.
+/// Defaults to 64 MiB.
+/// - part_max_attempts: How many times a part is transferred before the whole transfer
+/// fails. Defaults to 4.
+#[serde(deny_unknown_fields)]
 pub(crate) struct S3StorageConfig {
     pub(crate) bucket: String,
     pub(crate) credentials: S3CredentialsConfig,
     pub(crate) connect_timeout_ms: u64,
     pub(crate) request_timeout_ms: u64,
+    #[serde(default = "default_part_size_bytes")]
+    pub(crate) part_size_bytes: u64,
+    #[serde(default = "default_part_concurrency")]
+    pub(crate) part_concurrency: usize,
+    #[serde(default = "default_multipart_upload_threshold_bytes")]
+    pub(crate) multipart_upload_threshold_bytes: u64,
+    #[serde(default = "default_part_max_attempts")]
+    pub(crate) part_max_attempts: u32,
 }
 
 #[derive(Deserialize, Debug)]
@@ -38,8 +80,63 @@ pub(crate) struct S3StorageConfig {
 /// # Fields
 /// - root: The root directory to use for storage.
 /// # Notes
-/// The root directory is the directory where files will be stored.
-/// This is not intended to be used in production.
+/// The root directory is the directory where files will be stored. Storing on the local
+/// filesystem is meant for development and single-node deployments, where every worker
+/// shares the root directory.
+#[serde(deny_unknown_fields)]
 pub(crate) struct LocalStorageConfig {
     pub(crate) root: String,
 }
+
+impl ValidateConfig for StorageConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        match self {
+            StorageConfig::S3(s3) => validator.section("S3", s3),
+            StorageConfig::Local(local) => validator.section("Local", local),
+        }
+    }
+}
+
+impl ValidateConfig for S3StorageConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.non_empty("bucket", &self.bucket);
+        validator.positive("connect_timeout_ms", self.connect_timeout_ms);
+        validator.positive("request_timeout_ms", self.request_timeout_ms);
+        validator.check(
+            "part_size_bytes",
+            self.part_size_bytes >= MIN_PART_SIZE_BYTES,
+            "at least 5242880, the smallest part S3 accepts",
+        );
+        validator.positive("part_concurrency", self.part_concurrency);
+        validator.positive("part_max_attempts", self.part_max_attempts);
+    }
+}
+
+impl ValidateConfig for LocalStorageConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.non_empty("root", &self.root);
+        if !self.root.trim().is_empty() {
+            validator.check(
+                "root",
+                is_writable_dir(Path::new(&self.root)),
+                "a writable directory, or a path one can be created at",
+            );
+        }
+    }
+}
+
+// Whether `root` is a writable directory, or the closest directory it is in that exists is
+// writable, so that it can be created.
+pub(crate) fn is_writable_dir(root: &Path) -> bool {
+    let existing = root
+        .ancestors()
+        .map(|path| match path.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => path,
+        })
+        .find(|path| path.exists());
+    match existing.map(std::fs::metadata) {
+        Some(Ok(metadata)) => metadata.is_dir() && !metadata.permissions().readonly(),
+        _ => false,
+    }
+}
@@ -1,65 +1,174 @@
-use crate::{config::Configurable, errors::ChromaError};
+use crate::{
+    config::Configurable,
+    errors::{ChromaError, ErrorCodes},
+};
 use async_trait::async_trait;
-use tokio::io::AsyncBufRead;
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
 
-use super::{config::StorageConfig, s3::StorageConfigError};
+use super::{checksum, config::StorageConfig, s3::StorageConfigError};
 
+// Objects start with a header of this magic followed by the little endian checksum of the
+// rest of the file.
+const CHECKSUM_HEADER_MAGIC: &[u8; 8] = b"CRC32C\x00\x01";
+const CHECKSUM_HEADER_LEN: usize = CHECKSUM_HEADER_MAGIC.len() + 4;
+
+#[derive(Error, Debug)]
+pub enum LocalStorageError {
+    #[error("No such key: {0}")]
+    NoSuchKey(String),
+    #[error("Key is not a relative path under the root: {0}")]
+    InvalidKey(String),
+    #[error("Local storage IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+impl ChromaError for LocalStorageError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            LocalStorageError::NoSuchKey(_) => ErrorCodes::NotFound,
+            LocalStorageError::InvalidKey(_) => ErrorCodes::InvalidArgument,
+            LocalStorageError::IoError(_) => ErrorCodes::Internal,
+        }
+    }
+}
+
+/// Storage of objects as files under a root directory.
+/// # Description
+/// The key of an object is its path relative to the root. Objects are written to a
+/// temporary file next to their final path and renamed into place, so that a reader never
+/// sees a partially written object and concurrent writers of a key leave one complete
+/// object behind. The checksum of an object is kept in a header at the start of its file,
+/// so that the object and its checksum are published by the same rename.
 #[derive(Clone)]
 pub(crate) struct LocalStorage {
-    root: String,
+    root: PathBuf,
 }
 
 impl LocalStorage {
     pub(crate) fn new(root: &str) -> LocalStorage {
-        // Create the local storage with the root path.
-        return LocalStorage {
-            root: root.to_string(),
-        };
+        LocalStorage {
+            root: PathBuf::from(root),
+        }
     }
 
+    // The path of a key, which has to stay under the root.
+    fn path(&self, key: &str) -> Result<PathBuf, LocalStorageError> {
+        let key_path = Path::new(key);
+        let mut has_file = false;
+        for component in key_path.components() {
+            match component {
+                Component::Normal(_) => has_file = true,
+                Component::CurDir => {}
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                    return Err(LocalStorageError::InvalidKey(key.to_string()));
+                }
+            }
+        }
+        if !has_file {
+            return Err(LocalStorageError::InvalidKey(key.to_string()));
+        }
+        Ok(self.root.join(key_path))
+    }
+
+    /// Returns a reader of the object, along with the checksum it was written with if it was
+    /// written with one.
     pub(crate) async fn get(
         &self,
         key: &str,
-    ) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, String> {
-        let file_path = format!("{}/{}", self.root, key);
-        let file = tokio::fs::File::open(file_path).await;
-        match file {
-            Ok(file) => {
-                return Ok(Box::new(tokio::io::BufReader::new(file)));
+    ) -> Result<(Box<dyn AsyncBufRead + Unpin + Send>, Option<u32>), LocalStorageError> {
+        let path = self.path(key)?;
+        let mut file = match tokio::fs::File::open(&path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(LocalStorageError::NoSuchKey(key.to_string()))
             }
-            Err(e) => {
-                return Err::<_, String>(e.to_string());
+            Err(e) => return Err(LocalStorageError::IoError(e)),
+        };
+        let mut header = [0u8; CHECKSUM_HEADER_LEN];
+        let mut read = 0;
+        while read < CHECKSUM_HEADER_LEN {
+            match file.read(&mut header[read..]).await? {
+                0 => break,
+                n => read += n,
             }
         }
+        // Files without a header were not written with a checksum and are read whole.
+        let (magic, stored) = header.split_at(CHECKSUM_HEADER_MAGIC.len());
+        let checksum = if read == CHECKSUM_HEADER_LEN && magic == CHECKSUM_HEADER_MAGIC {
+            Some(u32::from_le_bytes(stored.try_into().unwrap()))
+        } else {
+            file.seek(std::io::SeekFrom::Start(0)).await?;
+            None
+        };
+        Ok((Box::new(tokio::io::BufReader::new(file)), checksum))
     }
 
-    pub(crate) async fn put_bytes(&self, key: &str, bytes: &[u8]) -> Result<(), String> {
-        let path = format!("{}/{}", self.root, key);
-        // Create the path if it doesn't exist, we unwrap since this should only be used in tests
-        let as_path = std::path::Path::new(&path);
-        let parent = as_path.parent().unwrap();
-        tokio::fs::create_dir_all(parent).await.unwrap();
-        let res = tokio::fs::write(&path, bytes).await;
-        match res {
-            Ok(_) => {
-                return Ok(());
-            }
-            Err(e) => {
-                return Err::<(), String>(e.to_string());
-            }
+    /// Checks that the root is a directory, creating it if it does not exist yet as writes
+    /// would.
+    pub(crate) async fn ping(&self) -> Result<(), LocalStorageError> {
+        Ok(tokio::fs::create_dir_all(&self.root).await?)
+    }
+
+    pub(crate) async fn put_bytes(&self, key: &str, bytes: &[u8]) -> Result<(), LocalStorageError> {
+        let path = self.path(key)?;
+        let temp_path = Self::prepare_temp_path(&path).await?;
+        let res = Self::write_object(&temp_path, crc32c::crc32c(bytes), bytes).await;
+        Self::rename_into_place(res, &temp_path, &path).await
+    }
+
+    pub(crate) async fn put_file(&self, key: &str, path: &str) -> Result<(), LocalStorageError> {
+        let key_path = self.path(key)?;
+        let checksum = checksum::file_checksum(Path::new(path)).await?;
+        let temp_path = Self::prepare_temp_path(&key_path).await?;
+        let res = match tokio::fs::File::open(path).await {
+            Ok(file) => Self::write_object(&temp_path, checksum, file).await,
+            Err(e) => Err(e),
+        };
+        Self::rename_into_place(res, &temp_path, &key_path).await
+    }
+
+    // Writes the checksum header followed by the contents of an object to `temp_path`.
+    async fn write_object(
+        temp_path: &Path,
+        checksum: u32,
+        mut contents: impl AsyncRead + Unpin,
+    ) -> std::io::Result<()> {
+        let mut file = tokio::fs::File::create(temp_path).await?;
+        file.write_all(CHECKSUM_HEADER_MAGIC).await?;
+        file.write_all(&checksum.to_le_bytes()).await?;
+        tokio::io::copy(&mut contents, &mut file).await?;
+        file.flush().await
+    }
+
+    // Creates the directories of `path` and returns a unique temporary path next to it.
+    async fn prepare_temp_path(path: &Path) -> Result<PathBuf, LocalStorageError> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
         }
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        Ok(path.with_file_name(format!(".{}.{}.tmp", file_name, Uuid::new_v4())))
     }
 
-    pub(crate) async fn put_file(&self, key: &str, path: &str) -> Result<(), String> {
-        let file = tokio::fs::read(path).await;
-        match file {
-            Ok(bytes_u8) => {
-                return self.put_bytes(key, &bytes_u8).await;
-            }
-            Err(e) => {
-                return Err::<(), String>(e.to_string());
-            }
+    async fn rename_into_place(
+        written: std::io::Result<()>,
+        temp_path: &Path,
+        path: &Path,
+    ) -> Result<(), LocalStorageError> {
+        let res = match written {
+            Ok(()) => tokio::fs::rename(temp_path, path).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            let _ = tokio::fs::remove_file(temp_path).await;
+            return Err(LocalStorageError::IoError(e));
         }
+        Ok(())
     }
 }
 
@@ -77,3 +186,171 @@ impl Configurable<StorageConfig> for LocalStorage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{GetError, Storage};
+
+    async fn read(storage: &Storage, key: &str) -> Result<Vec<u8>, GetError> {
+        let mut reader = storage.get(key).await?;
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await.unwrap();
+        Ok(bytes)
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut entries: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[tokio::test]
+    async fn test_put_and_get_round_trip() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        storage
+            .put_bytes("block/a/b", b"hello".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(read(&storage, "block/a/b").await.unwrap(), b"hello");
+
+        let file = tmp_dir.path().join("source");
+        std::fs::write(&file, b"from a file").unwrap();
+        storage
+            .put_file("hnsw/index", file.to_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(read(&storage, "hnsw/index").await.unwrap(), b"from a file");
+
+        // No temporary files are left behind.
+        assert_eq!(entries(&tmp_dir.path().join("block/a")), vec!["b"]);
+        assert_eq!(entries(&tmp_dir.path().join("hnsw")), vec!["index"]);
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("root");
+        let storage = Storage::Local(LocalStorage::new(root.to_str().unwrap()));
+        storage.ping().await.unwrap();
+        assert!(root.is_dir());
+
+        // A root under a file can't be a directory.
+        std::fs::write(tmp_dir.path().join("file"), b"").unwrap();
+        let root = tmp_dir.path().join("file").join("root");
+        let storage = Storage::Local(LocalStorage::new(root.to_str().unwrap()));
+        assert!(storage.ping().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_put_overwrites() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        storage.put_bytes("key", b"first".to_vec()).await.unwrap();
+        storage.put_bytes("key", b"second".to_vec()).await.unwrap();
+        assert_eq!(read(&storage, "key").await.unwrap(), b"second");
+    }
+
+    #[tokio::test]
+    async fn test_missing_key_is_not_found() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        match read(&storage, "missing/key").await {
+            Err(e @ GetError::NoSuchKey(_)) => {
+                assert_eq!(e.code(), ErrorCodes::NotFound);
+                assert!(!e.is_retryable());
+            }
+            other => panic!("Expected NoSuchKey, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keys_outside_the_root_are_rejected() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let root = tmp_dir.path().join("root");
+        let storage = LocalStorage::new(root.to_str().unwrap());
+        for key in ["../escaped", "a/../../escaped", "/etc/escaped", "", "./"] {
+            match storage.put_bytes(key, b"nope").await {
+                Err(e @ LocalStorageError::InvalidKey(_)) => {
+                    assert_eq!(e.code(), ErrorCodes::InvalidArgument)
+                }
+                other => panic!("Expected InvalidKey for {:?}, got {:?}", key, other),
+            }
+            assert!(matches!(
+                storage.get(key).await,
+                Err(LocalStorageError::InvalidKey(_))
+            ));
+        }
+        assert!(!tmp_dir.path().join("escaped").exists());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writers_to_different_keys() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let writers = (0..32).map(|i| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                let bytes = vec![i as u8; 4096];
+                storage
+                    .put_bytes(&format!("shared/dir/{}", i), bytes)
+                    .await
+                    .unwrap();
+            })
+        });
+        for writer in futures::future::join_all(writers).await {
+            writer.unwrap();
+        }
+        for i in 0..32 {
+            let key = format!("shared/dir/{}", i);
+            assert_eq!(read(&storage, &key).await.unwrap(), vec![i as u8; 4096]);
+        }
+        assert_eq!(entries(&tmp_dir.path().join("shared/dir")).len(), 32);
+    }
+
+    #[tokio::test]
+    async fn test_tampered_objects_are_rejected() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local(LocalStorage::new(tmp_dir.path().to_str().unwrap()));
+        let bytes: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+        storage.put_bytes("block/1", bytes.clone()).await.unwrap();
+        storage.put_bytes("block/2", bytes.clone()).await.unwrap();
+
+        // One object is truncated and the other has a flipped bit.
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(tmp_dir.path().join("block/1"))
+            .unwrap()
+            .set_len((CHECKSUM_HEADER_LEN + 5000) as u64)
+            .unwrap();
+        let mut flipped = std::fs::read(tmp_dir.path().join("block/2")).unwrap();
+        flipped[CHECKSUM_HEADER_LEN + 1234] ^= 1;
+        std::fs::write(tmp_dir.path().join("block/2"), &flipped).unwrap();
+
+        for key in ["block/1", "block/2"] {
+            let mut reader = storage.get(key).await.unwrap();
+            let e = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+
+            let path = tmp_dir.path().join("loaded");
+            match storage.get_to_file(key, &path).await {
+                Err(e @ GetError::ChecksumMismatch(_)) => {
+                    assert_eq!(e.code(), ErrorCodes::DataLoss);
+                    assert!(!e.is_retryable());
+                }
+                other => panic!("Expected a checksum mismatch, got {:?}", other),
+            }
+            assert!(!path.exists());
+        }
+
+        // Objects written without a checksum are read unchecked.
+        std::fs::write(tmp_dir.path().join("block/3"), &bytes[..5000]).unwrap();
+        assert_eq!(read(&storage, "block/3").await.unwrap(), &bytes[..5000]);
+        std::fs::write(tmp_dir.path().join("block/4"), b"short").unwrap();
+        assert_eq!(read(&storage, "block/4").await.unwrap(), b"short");
+    }
+}
@@ -2,11 +2,16 @@ use self::config::StorageConfig;
 use self::s3::S3GetError;
 use crate::config::Configurable;
 use crate::errors::{ChromaError, ErrorCodes};
-use tokio::io::AsyncBufRead;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufRead, AsyncWriteExt};
+pub(crate) mod checksum;
 pub(crate) mod config;
 pub(crate) mod local;
+pub(crate) mod multipart;
 pub(crate) mod s3;
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Clone)]
 pub(crate) enum Storage {
@@ -21,7 +26,31 @@ pub enum GetError {
     #[error("S3 error: {0}")]
     S3Error(#[from] S3GetError),
     #[error("Local storage error: {0}")]
-    LocalError(String),
+    LocalError(#[from] local::LocalStorageError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    ChecksumMismatch(#[from] checksum::ChecksumMismatchError),
+}
+
+impl GetError {
+    // Reading an object fails with an IO error when its checksum does not match, which is
+    // surfaced as the mismatch itself.
+    fn from_read_error(e: std::io::Error) -> GetError {
+        let is_mismatch = e
+            .get_ref()
+            .is_some_and(|inner| inner.is::<checksum::ChecksumMismatchError>());
+        if !is_mismatch {
+            return GetError::Io(e);
+        }
+        match e
+            .into_inner()
+            .map(|inner| inner.downcast::<checksum::ChecksumMismatchError>())
+        {
+            Some(Ok(mismatch)) => GetError::ChecksumMismatch(*mismatch),
+            _ => unreachable!("Checked that the error is a checksum mismatch"),
+        }
+    }
 }
 
 impl ChromaError for GetError {
@@ -29,7 +58,19 @@ impl ChromaError for GetError {
         match self {
             GetError::NoSuchKey(_) => ErrorCodes::NotFound,
             GetError::S3Error(_) => ErrorCodes::Internal,
-            GetError::LocalError(_) => ErrorCodes::Internal,
+            GetError::LocalError(e) => e.code(),
+            GetError::Io(_) => ErrorCodes::Internal,
+            GetError::ChecksumMismatch(e) => e.code(),
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            GetError::NoSuchKey(_) => false,
+            GetError::S3Error(e) => e.is_retryable(),
+            GetError::LocalError(_) => false,
+            GetError::Io(_) => false,
+            GetError::ChecksumMismatch(_) => false,
         }
     }
 }
@@ -39,23 +80,51 @@ pub enum PutError {
     #[error("S3 error: {0}")]
     S3Error(#[from] s3::S3PutError),
     #[error("Local storage error: {0}")]
-    LocalError(String),
+    LocalError(#[from] local::LocalStorageError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 impl ChromaError for PutError {
     fn code(&self) -> ErrorCodes {
         match self {
             PutError::S3Error(_) => ErrorCodes::Internal,
-            PutError::LocalError(_) => ErrorCodes::Internal,
+            PutError::LocalError(e) => e.code(),
+            PutError::Io(_) => ErrorCodes::Internal,
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            PutError::S3Error(e) => e.is_retryable(),
+            PutError::LocalError(_) => false,
+            PutError::Io(_) => false,
         }
     }
 }
 
 impl Storage {
+    /// Returns a reader of the object. Objects are checked against the checksum they were
+    /// written with, and reading an object whose checksum does not match fails at its end
+    /// with an `InvalidData` error. Objects written without a checksum are not checked.
     pub(crate) async fn get(
         &self,
         key: &str,
     ) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, GetError> {
+        let (reader, expected) = self.get_unverified(key).await?;
+        match expected {
+            Some(expected) => Ok(Box::new(tokio::io::BufReader::new(
+                checksum::VerifyingReader::new(reader, key, expected),
+            ))),
+            None => Ok(reader),
+        }
+    }
+
+    // Returns a reader of the object along with the checksum it was written with.
+    async fn get_unverified(
+        &self,
+        key: &str,
+    ) -> Result<(Box<dyn AsyncBufRead + Unpin + Send>, Option<u32>), GetError> {
         match self {
             Storage::S3(s3) => {
                 let res = s3.get(key).await;
@@ -71,19 +140,57 @@ impl Storage {
                 let res = local.get(key).await;
                 match res {
                     Ok(res) => Ok(res),
-                    // TODO: Special case no such key if possible
+                    Err(local::LocalStorageError::NoSuchKey(_)) => {
+                        Err(GetError::NoSuchKey(key.to_string()))
+                    }
                     Err(e) => Err(GetError::LocalError(e)),
                 }
             }
         }
     }
 
-    pub(crate) async fn put_file(&self, key: &str, path: &str) -> Result<(), PutError> {
+    /// Checks that the storage can be reached, without reading or writing an object.
+    pub(crate) async fn ping(&self) -> Result<(), GetError> {
         match self {
-            Storage::S3(s3) => s3
-                .put_file(key, path)
+            Storage::S3(s3) => s3.ping().await.map_err(GetError::S3Error),
+            Storage::Local(local) => local.ping().await.map_err(GetError::LocalError),
+        }
+    }
+
+    /// Downloads an object to the file at `path`, without holding all of it in memory. The
+    /// object is checked against its checksum like in [`Storage::get`], and only takes the
+    /// place of `path` once it has been downloaded whole and matches it.
+    pub(crate) async fn get_to_file(&self, key: &str, path: &Path) -> Result<(), GetError> {
+        match self {
+            Storage::S3(s3) => {
+                multipart::download_to_file(s3, key, path, s3.multipart_policy()).await
+            }
+            Storage::Local(_) => {
+                download_into_place(path, |download_path| async move {
+                    let mut reader = self.get(key).await?;
+                    let mut file = tokio::fs::File::create(&download_path).await?;
+                    tokio::io::copy(&mut reader, &mut file)
+                        .await
+                        .map_err(GetError::from_read_error)?;
+                    file.flush().await?;
+                    Ok(())
+                })
                 .await
-                .map_err(|e| PutError::S3Error(e)),
+            }
+        }
+    }
+
+    pub(crate) async fn put_file(&self, key: &str, path: &str) -> Result<(), PutError> {
+        match self {
+            Storage::S3(s3) => {
+                let policy = s3.multipart_policy();
+                if tokio::fs::metadata(path).await?.len() > policy.upload_threshold {
+                    return multipart::upload_file(s3, key, Path::new(path), policy).await;
+                }
+                s3.put_file(key, path)
+                    .await
+                    .map_err(|e| PutError::S3Error(e))
+            }
             Storage::Local(local) => local
                 .put_file(key, path)
                 .await
@@ -105,6 +212,31 @@ impl Storage {
     }
 }
 
+// Downloads an object with `download`, to a temporary file next to `path` that is renamed
+// into place once the download succeeds, so that `path` never holds part of an object. The
+// temporary file is removed when the download fails.
+async fn download_into_place<F, Fut>(path: &Path, download: F) -> Result<(), GetError>
+where
+    F: FnOnce(PathBuf) -> Fut,
+    Fut: Future<Output = Result<(), GetError>>,
+{
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let download_path = path.with_file_name(format!(".{}.{}.download", file_name, Uuid::new_v4()));
+    let res = match download(download_path.clone()).await {
+        Ok(()) => tokio::fs::rename(&download_path, path)
+            .await
+            .map_err(GetError::Io),
+        Err(e) => Err(e),
+    };
+    if res.is_err() {
+        let _ = tokio::fs::remove_file(&download_path).await;
+    }
+    res
+}
+
 pub(crate) async fn from_config(config: &StorageConfig) -> Result<Storage, Box<dyn ChromaError>> {
     match &config {
         StorageConfig::S3(_) => Ok(Storage::S3(s3::S3Storage::try_from_config(config).await?)),
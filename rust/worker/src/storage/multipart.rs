@@ -0,0 +1,499 @@
+// Moves large objects between storage and files on disk in parts.
+// Downloads fetch ranges of the object concurrently and write each one at its offset
+// in the file, and uploads read the file a part at a time, so at most `concurrency`
+// parts are held in memory however large the object is. The checksum of a download is
+// combined from the checksums of its parts, so that it is checked without reading the file
+// again.
+
+use super::checksum;
+use super::config::S3StorageConfig;
+use super::{GetError, PutError};
+use crate::errors::ChromaError;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use std::future::Future;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+// S3 rejects uploads of more parts than this.
+const MAX_PARTS: u64 = 10000;
+const PART_RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// How objects are transferred in parts.
+/// # Fields
+/// - part_size: The size of every part but the last one.
+/// - concurrency: How many parts are transferred at the same time.
+/// - upload_threshold: Files larger than this are uploaded in parts.
+/// - max_part_attempts: How many times a part is transferred before giving up on the object.
+/// - retry_base_delay: The delay before the first retry of a part, doubled for every retry
+/// after it.
+#[derive(Clone, Debug)]
+pub(crate) struct MultipartPolicy {
+    pub(crate) part_size: u64,
+    pub(crate) concurrency: usize,
+    pub(crate) upload_threshold: u64,
+    pub(crate) max_part_attempts: u32,
+    pub(crate) retry_base_delay: Duration,
+}
+
+impl From<&S3StorageConfig> for MultipartPolicy {
+    fn from(config: &S3StorageConfig) -> Self {
+        MultipartPolicy {
+            part_size: config.part_size_bytes,
+            concurrency: config.part_concurrency,
+            upload_threshold: config.multipart_upload_threshold_bytes,
+            max_part_attempts: config.part_max_attempts,
+            retry_base_delay: PART_RETRY_BASE_DELAY,
+        }
+    }
+}
+
+/// What is known about an object before it is downloaded.
+/// # Fields
+/// - size: The size of the object in bytes.
+/// - checksum: The checksum the object was written with, if it was written with one.
+#[derive(Clone, Debug)]
+pub(crate) struct ObjectInfo {
+    pub(crate) size: u64,
+    pub(crate) checksum: Option<u32>,
+}
+
+/// A storage objects can be read from in byte ranges.
+#[async_trait]
+pub(crate) trait PartSource: Send + Sync {
+    async fn head(&self, key: &str) -> Result<ObjectInfo, GetError>;
+    async fn get_range(&self, key: &str, range: Range<u64>) -> Result<Bytes, GetError>;
+}
+
+/// A storage objects can be written to in parts, which are assembled when the upload is
+/// completed. Parts are numbered from 1.
+#[async_trait]
+pub(crate) trait PartSink: Send + Sync {
+    /// Starts an upload of an object with the given checksum, returning its id.
+    async fn start_upload(&self, key: &str, checksum: u32) -> Result<String, PutError>;
+    /// Uploads a part, returning its tag.
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        bytes: Bytes,
+    ) -> Result<String, PutError>;
+    /// Assembles the parts, given in order with their tags, into the object.
+    async fn complete_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<(i32, String)>,
+    ) -> Result<(), PutError>;
+    async fn abort_upload(&self, key: &str, upload_id: &str) -> Result<(), PutError>;
+}
+
+// Runs the transfer of a part again while it fails with a retryable error, so that a failed
+// part does not restart the whole object.
+async fn with_part_retries<T, E, F, Fut>(policy: &MultipartPolicy, mut transfer: F) -> Result<T, E>
+where
+    E: ChromaError,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        match transfer().await {
+            Err(e) if e.is_retryable() && attempt < policy.max_part_attempts => {
+                tokio::time::sleep(policy.retry_base_delay * 2u32.saturating_pow(attempt - 1))
+                    .await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Downloads an object to `path`, replacing the file if it exists. The parts are written
+/// to a temporary file that only takes the place of `path` once all of them have been
+/// downloaded and the object matches its checksum.
+pub(crate) async fn download_to_file<S: PartSource>(
+    source: &S,
+    key: &str,
+    path: &Path,
+    policy: &MultipartPolicy,
+) -> Result<(), GetError> {
+    super::download_into_place(path, |download_path| async move {
+        download_parts(source, key, &download_path, policy).await
+    })
+    .await
+}
+
+async fn download_parts<S: PartSource>(
+    source: &S,
+    key: &str,
+    path: &Path,
+    policy: &MultipartPolicy,
+) -> Result<(), GetError> {
+    let info = with_part_retries(policy, || source.head(key)).await?;
+    let size = info.size;
+    let file = tokio::fs::File::create(path).await?;
+    file.set_len(size).await?;
+    drop(file);
+
+    let part_size = policy.part_size.max(1);
+    let ranges = (0..size)
+        .step_by(part_size as usize)
+        .map(|start| start..(start + part_size).min(size));
+    let mut parts = futures::stream::iter(ranges)
+        .map(|range| async move {
+            let bytes = with_part_retries(policy, || source.get_range(key, range.clone())).await?;
+            if bytes.len() as u64 != range.end - range.start {
+                return Err(GetError::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("Got {} bytes for range {:?} of {}", bytes.len(), range, key),
+                )));
+            }
+            let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+            file.seek(SeekFrom::Start(range.start)).await?;
+            file.write_all(&bytes).await?;
+            file.flush().await?;
+            Ok((range, crc32c::crc32c(&bytes)))
+        })
+        .buffer_unordered(policy.concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    if let Some(expected) = info.checksum {
+        parts.sort_by_key(|(range, _)| range.start);
+        let actual = parts.iter().fold(0, |crc, (range, part_crc)| {
+            crc32c::crc32c_combine(crc, *part_crc, (range.end - range.start) as usize)
+        });
+        checksum::verify(key, expected, actual).map_err(GetError::ChecksumMismatch)?;
+    }
+    Ok(())
+}
+
+/// Uploads the file at `path` as an object in parts. The upload is aborted if a part can't
+/// be uploaded.
+pub(crate) async fn upload_file<S: PartSink>(
+    sink: &S,
+    key: &str,
+    path: &Path,
+    policy: &MultipartPolicy,
+) -> Result<(), PutError> {
+    let checksum = checksum::file_checksum(path).await?;
+    let file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let part_size = policy.part_size.max(size.div_ceil(MAX_PARTS)).max(1);
+    let upload_id = with_part_retries(policy, || sink.start_upload(key, checksum)).await?;
+
+    let upload_id_ref = upload_id.as_str();
+    let parts = futures::stream::try_unfold((file, 1), |(file, part_number)| async move {
+        let mut bytes = Vec::with_capacity(part_size as usize);
+        let mut part = file.take(part_size);
+        part.read_to_end(&mut bytes).await?;
+        match bytes.is_empty() && part_number > 1 {
+            true => Ok(None),
+            false => Ok(Some((
+                (part_number, Bytes::from(bytes)),
+                (part.into_inner(), part_number + 1),
+            ))),
+        }
+    })
+    .map_ok(|(part_number, bytes)| async move {
+        let tag = with_part_retries(policy, || {
+            sink.upload_part(key, upload_id_ref, part_number, bytes.clone())
+        })
+        .await?;
+        Ok((part_number, tag))
+    })
+    .try_buffer_unordered(policy.concurrency.max(1))
+    .try_collect::<Vec<_>>()
+    .await;
+
+    let res = match parts {
+        Ok(mut parts) => {
+            parts.sort_by_key(|(part_number, _)| *part_number);
+            with_part_retries(policy, || {
+                sink.complete_upload(key, upload_id_ref, parts.clone())
+            })
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    if res.is_err() {
+        let _ = sink.abort_upload(key, upload_id_ref).await;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::s3::{S3GetError, S3PutError};
+    use parking_lot::Mutex;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Keeps objects in memory, fails the first attempt of every part it is told to, and
+    // records how many bytes were being transferred at most at the same time.
+    #[derive(Default)]
+    struct FakeStorage {
+        objects: Mutex<HashMap<String, Vec<u8>>>,
+        checksums: Mutex<HashMap<String, u32>>,
+        uploads: Mutex<HashMap<String, HashMap<i32, Bytes>>>,
+        failing_parts: Mutex<Vec<u64>>,
+        in_flight_bytes: AtomicUsize,
+        peak_in_flight_bytes: AtomicUsize,
+        aborted: AtomicUsize,
+    }
+
+    impl FakeStorage {
+        // Fails the first attempt of the part that starts at `offset`, or has `offset` as its
+        // part number.
+        fn fail_once(&self, offset: u64) {
+            self.failing_parts.lock().push(offset);
+        }
+
+        fn should_fail(&self, offset: u64) -> bool {
+            let mut failing_parts = self.failing_parts.lock();
+            match failing_parts.iter().position(|part| *part == offset) {
+                Some(index) => {
+                    failing_parts.remove(index);
+                    true
+                }
+                None => false,
+            }
+        }
+
+        async fn transfer(&self, len: usize) {
+            let in_flight = self.in_flight_bytes.fetch_add(len, Ordering::SeqCst) + len;
+            self.peak_in_flight_bytes
+                .fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            self.in_flight_bytes.fetch_sub(len, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl PartSource for FakeStorage {
+        async fn head(&self, key: &str) -> Result<ObjectInfo, GetError> {
+            match self.objects.lock().get(key) {
+                Some(object) => Ok(ObjectInfo {
+                    size: object.len() as u64,
+                    checksum: self.checksums.lock().get(key).copied(),
+                }),
+                None => Err(GetError::NoSuchKey(key.to_string())),
+            }
+        }
+
+        async fn get_range(&self, key: &str, range: Range<u64>) -> Result<Bytes, GetError> {
+            if self.should_fail(range.start) {
+                return Err(GetError::S3Error(S3GetError::S3GetError(
+                    "slow down".to_string(),
+                )));
+            }
+            let bytes = Bytes::copy_from_slice(
+                &self.objects.lock()[key][range.start as usize..range.end as usize],
+            );
+            self.transfer(bytes.len()).await;
+            Ok(bytes)
+        }
+    }
+
+    #[async_trait]
+    impl PartSink for FakeStorage {
+        async fn start_upload(&self, key: &str, checksum: u32) -> Result<String, PutError> {
+            self.checksums.lock().insert(key.to_string(), checksum);
+            let upload_id = format!("upload-{}", key);
+            self.uploads
+                .lock()
+                .insert(upload_id.clone(), HashMap::new());
+            Ok(upload_id)
+        }
+
+        async fn upload_part(
+            &self,
+            _key: &str,
+            upload_id: &str,
+            part_number: i32,
+            bytes: Bytes,
+        ) -> Result<String, PutError> {
+            if self.should_fail(part_number as u64) {
+                return Err(PutError::S3Error(S3PutError::S3PutError(
+                    "slow down".to_string(),
+                )));
+            }
+            self.transfer(bytes.len()).await;
+            let mut uploads = self.uploads.lock();
+            uploads
+                .get_mut(upload_id)
+                .unwrap()
+                .insert(part_number, bytes);
+            Ok(format!("tag-{}", part_number))
+        }
+
+        async fn complete_upload(
+            &self,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String)>,
+        ) -> Result<(), PutError> {
+            let uploaded = self.uploads.lock().remove(upload_id).unwrap();
+            let mut object = Vec::new();
+            for (part_number, tag) in parts {
+                assert_eq!(tag, format!("tag-{}", part_number));
+                object.extend_from_slice(&uploaded[&part_number]);
+            }
+            self.objects.lock().insert(key.to_string(), object);
+            Ok(())
+        }
+
+        async fn abort_upload(&self, _key: &str, upload_id: &str) -> Result<(), PutError> {
+            self.uploads.lock().remove(upload_id);
+            self.aborted.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn policy(part_size: u64, concurrency: usize) -> MultipartPolicy {
+        MultipartPolicy {
+            part_size,
+            concurrency,
+            upload_threshold: 0,
+            max_part_attempts: 2,
+            retry_base_delay: Duration::from_millis(1),
+        }
+    }
+
+    fn object(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn test_download_reassembles_the_parts() {
+        let storage = FakeStorage::default();
+        let data = object(10 * 1024 + 17);
+        storage
+            .objects
+            .lock()
+            .insert("index".to_string(), data.clone());
+        // A failed part is retried on its own.
+        storage.fail_once(3 * 1024);
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("index");
+        std::fs::write(&path, vec![7; 20 * 1024]).unwrap();
+        download_to_file(&storage, "index", &path, &policy(1024, 1))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        assert!(storage.failing_parts.lock().is_empty());
+        assert_eq!(storage.peak_in_flight_bytes.load(Ordering::SeqCst), 1024);
+
+        download_to_file(&storage, "index", &path, &policy(1000, 4))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        assert!(storage.peak_in_flight_bytes.load(Ordering::SeqCst) <= 4 * 1024);
+    }
+
+    #[tokio::test]
+    async fn test_download_fails_once_a_part_runs_out_of_attempts() {
+        let storage = FakeStorage::default();
+        storage
+            .objects
+            .lock()
+            .insert("index".to_string(), object(4096));
+        storage.fail_once(2048);
+        storage.fail_once(2048);
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("index");
+        let res = download_to_file(&storage, "index", &path, &policy(1024, 2)).await;
+        assert!(matches!(res, Err(GetError::S3Error(_))));
+        // Neither the file nor a partial download is left behind.
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+
+        // A failed download leaves the file it would have replaced in place.
+        std::fs::write(&path, b"previous").unwrap();
+        let res = download_to_file(&storage, "missing", &path, &policy(1024, 2)).await;
+        assert!(matches!(res, Err(GetError::NoSuchKey(_))));
+        storage.fail_once(1024);
+        storage.fail_once(1024);
+        let res = download_to_file(&storage, "index", &path, &policy(1024, 2)).await;
+        assert!(matches!(res, Err(GetError::S3Error(_))));
+        assert_eq!(std::fs::read(&path).unwrap(), b"previous");
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upload_in_parts() {
+        let storage = FakeStorage::default();
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("index");
+        let data = object(5 * 1024 + 3);
+        std::fs::write(&path, &data).unwrap();
+        storage.fail_once(2);
+
+        upload_file(&storage, "index", &path, &policy(1024, 1))
+            .await
+            .unwrap();
+        assert_eq!(storage.objects.lock()["index"], data);
+        assert_eq!(storage.peak_in_flight_bytes.load(Ordering::SeqCst), 1024);
+
+        upload_file(&storage, "index", &path, &policy(1024, 3))
+            .await
+            .unwrap();
+        assert_eq!(storage.objects.lock()["index"], data);
+        assert!(storage.peak_in_flight_bytes.load(Ordering::SeqCst) <= 3 * 1024);
+        assert_eq!(storage.aborted.load(Ordering::SeqCst), 0);
+        assert_eq!(storage.checksums.lock()["index"], crc32c::crc32c(&data));
+    }
+
+    #[tokio::test]
+    async fn test_tampered_download_is_rejected() {
+        let storage = FakeStorage::default();
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("index");
+        let data = object(10 * 1024 + 17);
+        std::fs::write(&path, &data).unwrap();
+        upload_file(&storage, "index", &path, &policy(1024, 2))
+            .await
+            .unwrap();
+
+        // Parts arrive out of order, and their checksums are combined in order.
+        let loaded = tmp_dir.path().join("loaded");
+        download_to_file(&storage, "index", &loaded, &policy(1000, 4))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&loaded).unwrap(), data);
+
+        storage.objects.lock().get_mut("index").unwrap()[4321] ^= 0x80;
+        match download_to_file(&storage, "index", &loaded, &policy(1000, 4)).await {
+            Err(e @ GetError::ChecksumMismatch(_)) => {
+                assert_eq!(e.code(), crate::errors::ErrorCodes::DataLoss);
+            }
+            other => panic!("Expected a checksum mismatch, got {:?}", other),
+        }
+        // The earlier download is kept, and the rejected one is removed.
+        assert_eq!(std::fs::read(&loaded).unwrap(), data);
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_upload_is_aborted() {
+        let storage = FakeStorage::default();
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("index");
+        std::fs::write(&path, object(3000)).unwrap();
+        storage.fail_once(3);
+        storage.fail_once(3);
+
+        let res = upload_file(&storage, "index", &path, &policy(1024, 2)).await;
+        assert!(matches!(res, Err(PutError::S3Error(_))));
+        assert_eq!(storage.aborted.load(Ordering::SeqCst), 1);
+        assert!(storage.uploads.lock().is_empty());
+        assert!(storage.objects.lock().is_empty());
+    }
+}
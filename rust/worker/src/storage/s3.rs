@@ -8,7 +8,10 @@
 // Once we move to our own implementation of hnswlib we can support
 // streaming from s3.
 
+use super::checksum::{self, CHECKSUM_METADATA};
 use super::config::StorageConfig;
+use super::multipart::{MultipartPolicy, ObjectInfo, PartSink, PartSource};
+use super::{GetError, PutError};
 use crate::config::Configurable;
 use crate::errors::ChromaError;
 use async_trait::async_trait;
@@ -17,8 +20,12 @@ use aws_sdk_s3;
 use aws_sdk_s3::error::ProvideErrorMetadata;
 use aws_sdk_s3::error::SdkError;
 use aws_sdk_s3::operation::create_bucket::CreateBucketError;
+use aws_sdk_s3::operation::head_object::HeadObjectError;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
 use aws_smithy_types::byte_stream::ByteStream;
+use bytes::Bytes;
 use std::clone::Clone;
+use std::ops::Range;
 use std::time::Duration;
 use thiserror::Error;
 use tokio::io::AsyncBufRead;
@@ -27,6 +34,7 @@ use tokio::io::AsyncBufRead;
 pub(crate) struct S3Storage {
     bucket: String,
     client: aws_sdk_s3::Client,
+    multipart: MultipartPolicy,
 }
 
 #[derive(Error, Debug)]
@@ -39,6 +47,11 @@ impl ChromaError for S3PutError {
     fn code(&self) -> crate::errors::ErrorCodes {
         crate::errors::ErrorCodes::Internal
     }
+
+    // S3 failures are mostly throttling and transient service errors.
+    fn is_retryable(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Error, Debug)]
@@ -53,16 +66,28 @@ impl ChromaError for S3GetError {
     fn code(&self) -> crate::errors::ErrorCodes {
         crate::errors::ErrorCodes::Internal
     }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            S3GetError::S3GetError(_) => true,
+            S3GetError::NoSuchKey(_) => false,
+        }
+    }
 }
 
 impl S3Storage {
-    fn new(bucket: &str, client: aws_sdk_s3::Client) -> S3Storage {
+    fn new(bucket: &str, client: aws_sdk_s3::Client, multipart: MultipartPolicy) -> S3Storage {
         return S3Storage {
             bucket: bucket.to_string(),
             client: client,
+            multipart,
         };
     }
 
+    pub(crate) fn multipart_policy(&self) -> &MultipartPolicy {
+        &self.multipart
+    }
+
     async fn create_bucket(&self) -> Result<(), String> {
         // Creates a public bucket with default settings in the region.
         // This should only be used for testing and in production
@@ -101,10 +126,23 @@ impl S3Storage {
         }
     }
 
+    /// Checks that the bucket can be reached with a single request.
+    pub(crate) async fn ping(&self) -> Result<(), S3GetError> {
+        self.client
+            .head_bucket()
+            .bucket(self.bucket.clone())
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| S3GetError::S3GetError(e.to_string()))
+    }
+
+    /// Returns a reader of the object along with the checksum it was written with, which is
+    /// kept in its metadata.
     pub(crate) async fn get(
         &self,
         key: &str,
-    ) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, S3GetError> {
+    ) -> Result<(Box<dyn AsyncBufRead + Unpin + Send>, Option<u32>), S3GetError> {
         let res = self
             .client
             .get_object()
@@ -114,7 +152,11 @@ impl S3Storage {
             .await;
         match res {
             Ok(res) => {
-                return Ok(Box::new(res.body.into_async_read()));
+                let checksum = res
+                    .metadata()
+                    .and_then(|metadata| metadata.get(CHECKSUM_METADATA))
+                    .and_then(|checksum| checksum::decode(checksum));
+                return Ok((Box::new(res.body.into_async_read()), checksum));
             }
             Err(e) => {
                 println!("error: {}", e);
@@ -148,26 +190,36 @@ impl S3Storage {
     }
 
     pub(crate) async fn put_bytes(&self, key: &str, bytes: Vec<u8>) -> Result<(), S3PutError> {
+        let checksum = crc32c::crc32c(&bytes);
         let bytestream = ByteStream::from(bytes);
-        self.put_bytestream(key, bytestream).await
+        self.put_bytestream(key, bytestream, checksum).await
     }
 
     pub(crate) async fn put_file(&self, key: &str, path: &str) -> Result<(), S3PutError> {
+        let checksum = checksum::file_checksum(path.as_ref())
+            .await
+            .map_err(|e| S3PutError::S3PutError(e.to_string()))?;
         let bytestream = ByteStream::from_path(path).await;
         match bytestream {
-            Ok(bytestream) => return self.put_bytestream(key, bytestream).await,
+            Ok(bytestream) => return self.put_bytestream(key, bytestream, checksum).await,
             Err(e) => {
                 return Err(S3PutError::S3PutError(e.to_string()));
             }
         }
     }
 
-    async fn put_bytestream(&self, key: &str, bytestream: ByteStream) -> Result<(), S3PutError> {
+    async fn put_bytestream(
+        &self,
+        key: &str,
+        bytestream: ByteStream,
+        checksum: u32,
+    ) -> Result<(), S3PutError> {
         let res = self
             .client
             .put_object()
             .bucket(self.bucket.clone())
             .key(key)
+            .metadata(CHECKSUM_METADATA, checksum::encode(checksum))
             .body(bytestream)
             .send()
             .await;
@@ -196,6 +248,144 @@ impl S3Storage {
     }
 }
 
+#[async_trait]
+impl PartSource for S3Storage {
+    async fn head(&self, key: &str) -> Result<ObjectInfo, GetError> {
+        let res = self
+            .client
+            .head_object()
+            .bucket(self.bucket.clone())
+            .key(key)
+            .send()
+            .await;
+        match res {
+            Ok(res) => Ok(ObjectInfo {
+                size: res.content_length().unwrap_or(0).max(0) as u64,
+                checksum: res
+                    .metadata()
+                    .and_then(|metadata| metadata.get(CHECKSUM_METADATA))
+                    .and_then(|checksum| checksum::decode(checksum)),
+            }),
+            Err(SdkError::ServiceError(err))
+                if matches!(err.err(), HeadObjectError::NotFound(_)) =>
+            {
+                Err(GetError::NoSuchKey(key.to_string()))
+            }
+            Err(e) => Err(GetError::S3Error(S3GetError::S3GetError(e.to_string()))),
+        }
+    }
+
+    async fn get_range(&self, key: &str, range: Range<u64>) -> Result<Bytes, GetError> {
+        // HTTP ranges include their end.
+        let res = self
+            .client
+            .get_object()
+            .bucket(self.bucket.clone())
+            .key(key)
+            .range(format!("bytes={}-{}", range.start, range.end - 1))
+            .send()
+            .await
+            .map_err(|e| S3GetError::S3GetError(e.to_string()))?;
+        match res.body.collect().await {
+            Ok(bytes) => Ok(bytes.into_bytes()),
+            Err(e) => Err(GetError::S3Error(S3GetError::S3GetError(e.to_string()))),
+        }
+    }
+}
+
+#[async_trait]
+impl PartSink for S3Storage {
+    async fn start_upload(&self, key: &str, checksum: u32) -> Result<String, PutError> {
+        let res = self
+            .client
+            .create_multipart_upload()
+            .bucket(self.bucket.clone())
+            .key(key)
+            .metadata(CHECKSUM_METADATA, checksum::encode(checksum))
+            .send()
+            .await
+            .map_err(|e| S3PutError::S3PutError(e.to_string()))?;
+        match res.upload_id() {
+            Some(upload_id) => Ok(upload_id.to_string()),
+            None => Err(PutError::S3Error(S3PutError::S3PutError(format!(
+                "No upload id for multipart upload of {}",
+                key
+            )))),
+        }
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        bytes: Bytes,
+    ) -> Result<String, PutError> {
+        let res = self
+            .client
+            .upload_part()
+            .bucket(self.bucket.clone())
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(bytes))
+            .send()
+            .await
+            .map_err(|e| S3PutError::S3PutError(e.to_string()))?;
+        match res.e_tag() {
+            Some(e_tag) => Ok(e_tag.to_string()),
+            None => Err(PutError::S3Error(S3PutError::S3PutError(format!(
+                "No ETag for part {} of {}",
+                part_number, key
+            )))),
+        }
+    }
+
+    async fn complete_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<(i32, String)>,
+    ) -> Result<(), PutError> {
+        let parts = parts
+            .into_iter()
+            .map(|(part_number, e_tag)| {
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .e_tag(e_tag)
+                    .build()
+            })
+            .collect();
+        self.client
+            .complete_multipart_upload()
+            .bucket(self.bucket.clone())
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| S3PutError::S3PutError(e.to_string()))?;
+        println!("put object {} to bucket {} in parts", key, self.bucket);
+        Ok(())
+    }
+
+    async fn abort_upload(&self, key: &str, upload_id: &str) -> Result<(), PutError> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(self.bucket.clone())
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|e| S3PutError::S3PutError(e.to_string()))?;
+        Ok(())
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum StorageConfigError {
     #[error("Invalid storage config")]
@@ -249,7 +439,7 @@ impl Configurable<StorageConfig> for S3Storage {
                         aws_sdk_s3::Client::new(&config)
                     }
                 };
-                let storage = S3Storage::new(&s3_config.bucket, client);
+                let storage = S3Storage::new(&s3_config.bucket, client, s3_config.into());
                 // for minio we create the bucket since it is only used for testing
                 match &s3_config.credentials {
                     super::config::S3CredentialsConfig::Minio => {
@@ -304,6 +494,13 @@ mod tests {
         let storage = S3Storage {
             bucket: "test".to_string(),
             client,
+            multipart: MultipartPolicy {
+                part_size: 5 * 1024 * 1024,
+                concurrency: 4,
+                upload_threshold: 5 * 1024 * 1024,
+                max_part_attempts: 2,
+                retry_base_delay: Duration::from_millis(10),
+            },
         };
         storage.create_bucket().await.unwrap();
 
@@ -315,7 +512,8 @@ mod tests {
         let test_file_in = format!("{}/test_file_in", persist_path);
         std::fs::write(&test_file_in, test_data).unwrap();
         storage.put_file("test", &test_file_in).await.unwrap();
-        let mut bytes = storage.get("test").await.unwrap();
+        let (mut bytes, checksum) = storage.get("test").await.unwrap();
+        assert_eq!(checksum, Some(crc32c::crc32c(test_data.as_bytes())));
 
         let mut buf = String::new();
         bytes.read_to_string(&mut buf).await.unwrap();
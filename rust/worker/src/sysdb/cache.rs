@@ -0,0 +1,402 @@
+use super::snapshot::CollectionSnapshot;
+use crate::types::{Collection, Segment, SegmentFlushInfo};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    loaded_at: Instant,
+}
+
+// Every key has its own slot, so that loading one key does not block reads of the others
+// and concurrent misses of the same key all wait for a single load.
+type Slot<V> = Arc<tokio::sync::Mutex<Option<Entry<V>>>>;
+// The slots by key, with the time each key was added.
+type Slots<V> = HashMap<Uuid, (Instant, Slot<V>)>;
+
+/// A read-through cache of values keyed by id.
+/// # Description
+/// A value is loaded on the first read of its key and served from memory until it is
+/// older than the TTL or is invalidated. When the cache is full, loading a new key evicts
+/// the key that was added first.
+#[derive(Clone, Debug)]
+pub(crate) struct TtlCache<V> {
+    ttl: Duration,
+    max_entries: usize,
+    slots: Arc<Mutex<Slots<V>>>,
+}
+
+impl<V: Clone> TtlCache<V> {
+    pub(crate) fn new(ttl: Duration, max_entries: usize) -> Self {
+        TtlCache {
+            ttl,
+            max_entries,
+            slots: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the value of `key`, calling `load` if it isn't cached or has expired. A load
+    /// that finds nothing, or fails, is not cached.
+    pub(crate) async fn get_or_load<E, F, Fut>(&self, key: Uuid, load: F) -> Result<Option<V>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<V>, E>>,
+    {
+        let slot = self.slot(key);
+        let mut entry = slot.lock().await;
+        if let Some(entry) = entry.as_ref() {
+            if entry.loaded_at.elapsed() < self.ttl {
+                return Ok(Some(entry.value.clone()));
+            }
+        }
+        match load().await? {
+            Some(value) => {
+                *entry = Some(Entry {
+                    value: value.clone(),
+                    loaded_at: Instant::now(),
+                });
+                Ok(Some(value))
+            }
+            None => {
+                *entry = None;
+                drop(entry);
+                self.invalidate(&key);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Replaces the cached value of `key` with `update` of it, if one is cached and has not
+    /// expired. Reads of the key wait for the update, so they see either the value before
+    /// it or the one after.
+    pub(crate) async fn update(&self, key: &Uuid, update: impl FnOnce(&V) -> V) {
+        let slot = match self.slots.lock().get(key) {
+            Some((_, slot)) => slot.clone(),
+            None => return,
+        };
+        let mut entry = slot.lock().await;
+        if let Some(entry) = entry.as_mut() {
+            if entry.loaded_at.elapsed() < self.ttl {
+                entry.value = update(&entry.value);
+                entry.loaded_at = Instant::now();
+            }
+        }
+    }
+
+    pub(crate) fn invalidate(&self, key: &Uuid) {
+        self.slots.lock().remove(key);
+    }
+
+    /// Whether `key` has not been evicted or invalidated, even if its value has expired.
+    pub(crate) fn contains(&self, key: &Uuid) -> bool {
+        self.slots.lock().contains_key(key)
+    }
+
+    fn slot(&self, key: Uuid) -> Slot<V> {
+        let mut slots = self.slots.lock();
+        if let Some((_, slot)) = slots.get(&key) {
+            return slot.clone();
+        }
+        if slots.len() >= self.max_entries {
+            let oldest = slots
+                .iter()
+                .min_by_key(|(_, (added_at, _))| *added_at)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                slots.remove(&oldest);
+            }
+        }
+        let slot = Slot::default();
+        slots.insert(key, (Instant::now(), slot.clone()));
+        slot
+    }
+}
+
+/// The collections and segments the query service reads from sysdb, by collection id.
+/// # Notes
+/// Entries are invalidated when this worker flushes a compaction of the collection. Flushes
+/// by other workers are only picked up once the entry expires.
+#[derive(Clone, Debug)]
+pub(crate) struct SysDbCache {
+    collections: TtlCache<Collection>,
+    // All segments of a collection.
+    segments: TtlCache<Vec<Segment>>,
+    // The collection and its segments as of one version, which flushes by this worker
+    // replace with the version they create.
+    snapshots: TtlCache<Arc<CollectionSnapshot>>,
+    // The collection of every segment whose collection has its segments or snapshot cached,
+    // so that segments can also be looked up by their own id.
+    segment_collections: Arc<Mutex<HashMap<Uuid, Uuid>>>,
+}
+
+impl SysDbCache {
+    pub(crate) fn new(ttl: Duration, max_entries: usize) -> Self {
+        SysDbCache {
+            collections: TtlCache::new(ttl, max_entries),
+            segments: TtlCache::new(ttl, max_entries),
+            snapshots: TtlCache::new(ttl, max_entries),
+            segment_collections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) async fn get_collection<E, F, Fut>(
+        &self,
+        collection_id: Uuid,
+        load: F,
+    ) -> Result<Option<Collection>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<Collection>, E>>,
+    {
+        let collection = self.collections.get_or_load(collection_id, load).await?;
+        // The collection was deleted, and its segments with it.
+        if collection.is_none() {
+            self.invalidate(&collection_id);
+        }
+        Ok(collection)
+    }
+
+    pub(crate) async fn get_segments<E, F, Fut>(
+        &self,
+        collection_id: Uuid,
+        load: F,
+    ) -> Result<Option<Vec<Segment>>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<Vec<Segment>>, E>>,
+    {
+        let segments = self.segments.get_or_load(collection_id, load).await?;
+        if let Some(segments) = &segments {
+            self.add_segment_collections(collection_id, segments);
+        }
+        Ok(segments)
+    }
+
+    pub(crate) async fn get_snapshot<E, F, Fut>(
+        &self,
+        collection_id: Uuid,
+        load: F,
+    ) -> Result<Option<Arc<CollectionSnapshot>>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<Arc<CollectionSnapshot>>, E>>,
+    {
+        let snapshot = self.snapshots.get_or_load(collection_id, load).await?;
+        if let Some(snapshot) = &snapshot {
+            self.add_segment_collections(collection_id, &snapshot.segments);
+        }
+        Ok(snapshot)
+    }
+
+    // Adding segments that are not known yet means a collection was loaded, which may have
+    // evicted another one. The segments of collections that are no longer cached are dropped
+    // then, so that there are never more than those of the cached collections.
+    fn add_segment_collections(&self, collection_id: Uuid, segments: &[Segment]) {
+        let mut segment_collections = self.segment_collections.lock();
+        let mut added = false;
+        for segment in segments {
+            added |= segment_collections.insert(segment.id, collection_id) != Some(collection_id);
+        }
+        if added {
+            segment_collections.retain(|_, segment_collection_id| {
+                self.segments.contains(segment_collection_id)
+                    || self.snapshots.contains(segment_collection_id)
+            });
+        }
+    }
+
+    /// Records a flush of the collection by this worker. The cached snapshot is replaced
+    /// with the version the flush created, in one step, while queries that hold the one it
+    /// replaces keep reading that.
+    pub(crate) async fn flushed(
+        &self,
+        collection_id: &Uuid,
+        log_position: i64,
+        collection_version: i32,
+        segment_flush_info: &[SegmentFlushInfo],
+    ) {
+        self.collections.invalidate(collection_id);
+        self.segments.invalidate(collection_id);
+        self.snapshots
+            .update(collection_id, |snapshot| {
+                Arc::new(snapshot.flushed(log_position, collection_version, segment_flush_info))
+            })
+            .await;
+    }
+
+    /// The collection of a segment that has been cached before.
+    pub(crate) fn collection_of_segment(&self, segment_id: &Uuid) -> Option<Uuid> {
+        self.segment_collections.lock().get(segment_id).copied()
+    }
+
+    pub(crate) fn invalidate(&self, collection_id: &Uuid) {
+        self.collections.invalidate(collection_id);
+        self.segments.invalidate(collection_id);
+        self.snapshots.invalidate(collection_id);
+        self.segment_collections
+            .lock()
+            .retain(|_, segment_collection_id| segment_collection_id != collection_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{SegmentScope, SegmentType};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    async fn get(cache: &TtlCache<u32>, key: Uuid, loads: &AtomicU32) -> Option<u32> {
+        cache
+            .get_or_load(key, || async {
+                Ok::<_, ()>(Some(loads.fetch_add(1, Ordering::SeqCst)))
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire() {
+        let cache = TtlCache::new(Duration::from_millis(50), 10);
+        let key = Uuid::new_v4();
+        let loads = AtomicU32::new(0);
+        assert_eq!(get(&cache, key, &loads).await, Some(0));
+        assert_eq!(get(&cache, key, &loads).await, Some(0));
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(get(&cache, key, &loads).await, Some(1));
+        assert_eq!(get(&cache, key, &loads).await, Some(1));
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate() {
+        let cache = TtlCache::new(Duration::from_secs(60), 10);
+        let (key, other_key) = (Uuid::new_v4(), Uuid::new_v4());
+        let loads = AtomicU32::new(0);
+        assert_eq!(get(&cache, key, &loads).await, Some(0));
+        assert_eq!(get(&cache, other_key, &loads).await, Some(1));
+        cache.invalidate(&key);
+        assert_eq!(get(&cache, key, &loads).await, Some(2));
+        assert_eq!(get(&cache, other_key, &loads).await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_misses_and_errors_are_not_cached() {
+        let cache = TtlCache::new(Duration::from_secs(60), 10);
+        let key = Uuid::new_v4();
+        let loads = AtomicU32::new(0);
+        for _ in 0..2 {
+            let value = cache
+                .get_or_load(key, || async {
+                    loads.fetch_add(1, Ordering::SeqCst);
+                    Ok::<Option<u32>, ()>(None)
+                })
+                .await;
+            assert_eq!(value, Ok(None));
+        }
+        let value = cache
+            .get_or_load(key, || async { Err::<Option<u32>, _>("sysdb is down") })
+            .await;
+        assert_eq!(value, Err("sysdb is down"));
+        assert_eq!(get(&cache, key, &loads).await, Some(2));
+        assert_eq!(loads.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_load_once() {
+        let cache = TtlCache::new(Duration::from_secs(60), 10);
+        let key = Uuid::new_v4();
+        let loads = Arc::new(AtomicU32::new(0));
+        let handles = (0..50).map(|_| {
+            let (cache, loads) = (cache.clone(), loads.clone());
+            tokio::spawn(async move {
+                cache
+                    .get_or_load(key, || async {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        Ok::<_, ()>(Some(loads.fetch_add(1, Ordering::SeqCst)))
+                    })
+                    .await
+                    .unwrap()
+            })
+        });
+        for value in futures::future::join_all(handles).await {
+            assert_eq!(value.unwrap(), Some(0));
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_update() {
+        let cache = TtlCache::new(Duration::from_secs(60), 10);
+        let (key, missing_key) = (Uuid::new_v4(), Uuid::new_v4());
+        let loads = AtomicU32::new(0);
+        assert_eq!(get(&cache, key, &loads).await, Some(0));
+        cache.update(&key, |value| value + 10).await;
+        assert_eq!(get(&cache, key, &loads).await, Some(10));
+        // Keys that are not cached stay that way.
+        cache.update(&missing_key, |value| value + 10).await;
+        assert_eq!(get(&cache, missing_key, &loads).await, Some(1));
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+
+    fn segment(collection_id: Uuid) -> Segment {
+        Segment {
+            id: Uuid::new_v4(),
+            r#type: SegmentType::BlockfileRecord,
+            scope: SegmentScope::RECORD,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_segments_of_evicted_collections_are_dropped() {
+        let cache = SysDbCache::new(Duration::from_secs(60), 2);
+        let collection_ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let segments: Vec<_> = collection_ids.iter().map(|id| segment(*id)).collect();
+        for (collection_id, segment) in collection_ids.iter().zip(&segments) {
+            let segments = cache
+                .get_segments(*collection_id, || async {
+                    Ok::<_, ()>(Some(vec![segment.clone()]))
+                })
+                .await
+                .unwrap();
+            assert_eq!(segments, Some(vec![segment.clone()]));
+        }
+        // The first collection was evicted to make room for the third.
+        assert_eq!(cache.collection_of_segment(&segments[0].id), None);
+        assert_eq!(
+            cache.collection_of_segment(&segments[1].id),
+            Some(collection_ids[1])
+        );
+        assert_eq!(
+            cache.collection_of_segment(&segments[2].id),
+            Some(collection_ids[2])
+        );
+        assert_eq!(cache.segment_collections.lock().len(), 2);
+
+        cache.invalidate(&collection_ids[1]);
+        assert_eq!(cache.collection_of_segment(&segments[1].id), None);
+        assert_eq!(cache.segment_collections.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_full_cache_evicts_the_oldest_key() {
+        let cache = TtlCache::new(Duration::from_secs(60), 2);
+        let keys = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let loads = AtomicU32::new(0);
+        for key in keys {
+            get(&cache, key, &loads).await;
+        }
+        assert_eq!(get(&cache, keys[2], &loads).await, Some(2));
+        assert_eq!(get(&cache, keys[0], &loads).await, Some(3));
+        assert_eq!(loads.load(Ordering::SeqCst), 4);
+    }
+}
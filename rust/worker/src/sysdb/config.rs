@@ -1,14 +1,85 @@
+use crate::config::{ConfigValidator, ValidateConfig};
 use serde::Deserialize;
 
+const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 4;
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 50;
+const DEFAULT_RETRY_DEADLINE_MS: u64 = 5000;
+const DEFAULT_CACHE_MAX_ENTRIES: usize = 10000;
+
+fn default_retry_max_attempts() -> u32 {
+    DEFAULT_RETRY_MAX_ATTEMPTS
+}
+
+fn default_retry_base_delay_ms() -> u64 {
+    DEFAULT_RETRY_BASE_DELAY_MS
+}
+
+fn default_retry_deadline_ms() -> u64 {
+    DEFAULT_RETRY_DEADLINE_MS
+}
+
+fn default_cache_max_entries() -> usize {
+    DEFAULT_CACHE_MAX_ENTRIES
+}
+
 #[derive(Deserialize)]
+/// The configuration for the gRPC sysdb client.
+/// # Fields
+/// - host: The host of the sysdb service.
+/// - port: The port of the sysdb service.
+/// - connect_timeout_ms: How long to wait for a connection to sysdb.
+/// - request_timeout_ms: How long to wait for a single request to sysdb.
+/// - retry_max_attempts: How many times a request that fails with a retryable error is
+/// sent in total. Defaults to 4, 1 turns retries off.
+/// - retry_base_delay_ms: The delay before the first retry. It doubles for every retry
+/// after that, and each delay is jittered by up to half. Defaults to 50.
+/// - retry_deadline_ms: No retry is started once this much time has passed since the
+/// first attempt. Defaults to 5000.
+/// - cache_ttl_ms: How long collections and their segments are cached after they were read
+/// from sysdb. Defaults to 0, which turns the cache off.
+/// - cache_max_entries: How many collections are cached at most. Defaults to 10000.
+/// # Notes
+/// See config.rs in the root of the worker crate for an example of how to use
+/// config files to configure the worker.
+#[serde(deny_unknown_fields)]
 pub(crate) struct GrpcSysDbConfig {
     pub(crate) host: String,
     pub(crate) port: u16,
     pub(crate) connect_timeout_ms: u64,
     pub(crate) request_timeout_ms: u64,
+    #[serde(default = "default_retry_max_attempts")]
+    pub(crate) retry_max_attempts: u32,
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub(crate) retry_base_delay_ms: u64,
+    #[serde(default = "default_retry_deadline_ms")]
+    pub(crate) retry_deadline_ms: u64,
+    #[serde(default)]
+    pub(crate) cache_ttl_ms: u64,
+    #[serde(default = "default_cache_max_entries")]
+    pub(crate) cache_max_entries: usize,
 }
 
 #[derive(Deserialize)]
 pub(crate) enum SysDbConfig {
+    // case-insensitive
+    #[serde(alias = "grpc")]
     Grpc(GrpcSysDbConfig),
 }
+
+impl ValidateConfig for SysDbConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        match self {
+            SysDbConfig::Grpc(grpc) => validator.section("Grpc", grpc),
+        }
+    }
+}
+
+impl ValidateConfig for GrpcSysDbConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        validator.non_empty("host", &self.host);
+        validator.port("port", self.port);
+        validator.positive("connect_timeout_ms", self.connect_timeout_ms);
+        validator.positive("request_timeout_ms", self.request_timeout_ms);
+        validator.positive("retry_max_attempts", self.retry_max_attempts);
+    }
+}
@@ -1,4 +1,6 @@
+pub(crate) mod cache;
 pub(crate) mod config;
+pub(crate) mod snapshot;
 pub(crate) mod sysdb;
 pub(crate) mod test_sysdb;
 
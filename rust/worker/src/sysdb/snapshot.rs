@@ -0,0 +1,146 @@
+use super::sysdb::GetSegmentError;
+use crate::types::{Collection, Segment, SegmentFlushInfo, SegmentType};
+use uuid::Uuid;
+
+/// A collection and all of its segments as of one version of the collection.
+/// # Description
+/// A flush of a compaction moves the log position of a collection and the files of all of
+/// its segments in one step, and bumps its version. A query resolves the snapshot of the
+/// collection once when it starts and reads every segment through it, so it never combines
+/// the files of one version with the files, or the log position, of another.
+/// # Notes
+/// Flushes write new files instead of changing the ones of older versions, and nothing
+/// deletes those, so a snapshot stays readable for as long as a query holds on to it.
+#[derive(Clone, Debug)]
+pub(crate) struct CollectionSnapshot {
+    pub(crate) collection: Collection,
+    pub(crate) segments: Vec<Segment>,
+}
+
+impl CollectionSnapshot {
+    /// Returns the segment of type `r#type`. A collection has at most one segment of each
+    /// type, so finding more than one is an error.
+    pub(crate) fn segment(&self, r#type: SegmentType) -> Result<Segment, GetSegmentError> {
+        let mut segments = self
+            .segments
+            .iter()
+            .filter(|segment| segment.r#type == r#type);
+        match (segments.next(), segments.count()) {
+            (None, _) => Err(GetSegmentError::NotFound(r#type, self.collection.id)),
+            (Some(segment), 0) => Ok(segment.clone()),
+            (Some(_), others) => Err(GetSegmentError::MultipleFound(
+                r#type,
+                self.collection.id,
+                others + 1,
+            )),
+        }
+    }
+
+    pub(crate) fn segment_by_id(&self, id: &Uuid) -> Option<&Segment> {
+        self.segments.iter().find(|segment| segment.id == *id)
+    }
+
+    /// The snapshot of the version that flushing `segment_flush_info` at `log_position`
+    /// creates from version `collection_version`, the way sysdb applies the flush.
+    /// # Notes
+    /// The new snapshot only depends on the flush, not on what this one holds, so applying
+    /// a flush to a snapshot that already has it changes nothing.
+    pub(crate) fn flushed(
+        &self,
+        log_position: i64,
+        collection_version: i32,
+        segment_flush_info: &[SegmentFlushInfo],
+    ) -> CollectionSnapshot {
+        let mut snapshot = self.clone();
+        snapshot.collection.log_position = log_position;
+        snapshot.collection.version = collection_version + 1;
+        for segment in snapshot.segments.iter_mut() {
+            if let Some(flush_info) = segment_flush_info
+                .iter()
+                .find(|flush_info| flush_info.segment_id == segment.id)
+            {
+                segment.file_path.clone_from(&flush_info.file_paths);
+            }
+        }
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::SegmentScope;
+    use std::collections::HashMap;
+
+    fn segment(r#type: SegmentType, scope: SegmentScope, collection_id: Uuid) -> Segment {
+        Segment {
+            id: Uuid::new_v4(),
+            r#type,
+            scope,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_flushed_snapshot() {
+        let collection_id = Uuid::new_v4();
+        let snapshot = CollectionSnapshot {
+            collection: Collection {
+                id: collection_id,
+                name: "collection".to_string(),
+                metadata: None,
+                dimension: Some(3),
+                tenant: "tenant".to_string(),
+                database: "database".to_string(),
+                log_position: 10,
+                version: 4,
+            },
+            segments: vec![
+                segment(
+                    SegmentType::BlockfileRecord,
+                    SegmentScope::RECORD,
+                    collection_id,
+                ),
+                segment(
+                    SegmentType::HnswDistributed,
+                    SegmentScope::VECTOR,
+                    collection_id,
+                ),
+            ],
+        };
+        let record_segment = snapshot.segment(SegmentType::BlockfileRecord).unwrap();
+        assert!(matches!(
+            snapshot.segment(SegmentType::BlockfileMetadata),
+            Err(GetSegmentError::NotFound(SegmentType::BlockfileMetadata, _))
+        ));
+
+        let file_paths = HashMap::from([("user_id_to_id".to_string(), vec!["1".to_string()])]);
+        let flush_info = [SegmentFlushInfo {
+            segment_id: record_segment.id,
+            file_paths: file_paths.clone(),
+        }];
+        let flushed = snapshot.flushed(20, 4, &flush_info);
+        assert_eq!(flushed.collection.version, 5);
+        assert_eq!(flushed.collection.log_position, 20);
+        assert_eq!(
+            flushed
+                .segment(SegmentType::BlockfileRecord)
+                .unwrap()
+                .file_path,
+            file_paths
+        );
+        assert!(flushed
+            .segment(SegmentType::HnswDistributed)
+            .unwrap()
+            .file_path
+            .is_empty());
+        // The snapshot the flush started from is unchanged, and flushing again is a no-op.
+        assert_eq!(snapshot.collection.version, 4);
+        assert!(record_segment.file_path.is_empty());
+        let again = flushed.flushed(20, 4, &flush_info);
+        assert_eq!(again.collection.version, 5);
+        assert_eq!(again.segments, flushed.segments);
+    }
+}
@@ -1,11 +1,16 @@
+use super::cache::SysDbCache;
+use super::config::GrpcSysDbConfig;
 use super::config::SysDbConfig;
+use super::snapshot::CollectionSnapshot;
 use super::test_sysdb::TestSysDb;
 use crate::chroma_proto;
 use crate::chroma_proto::sys_db_client;
 use crate::chroma_proto::sys_db_client::SysDbClient;
 use crate::config::Configurable;
+use crate::errors::is_retryable_status;
 use crate::errors::ChromaError;
 use crate::errors::ErrorCodes;
+use crate::tracing::metrics::metrics;
 use crate::tracing::util::client_interceptor;
 use crate::types::Collection;
 use crate::types::CollectionConversionError;
@@ -16,11 +21,15 @@ use crate::types::SegmentConversionError;
 use crate::types::SegmentFlushInfo;
 use crate::types::SegmentFlushInfoConversionError;
 use crate::types::SegmentScope;
+use crate::types::SegmentType;
 use crate::types::Tenant;
 use async_trait::async_trait;
+use rand::Rng;
 use std::fmt::Debug;
+use std::future::Future;
 use std::sync::Arc;
 use std::time::Duration;
+use std::time::Instant;
 use thiserror::Error;
 use tonic::service::interceptor;
 use tonic::transport::Endpoint;
@@ -30,6 +39,9 @@ use uuid::Uuid;
 
 const DEFAULT_DATBASE: &str = "default_database";
 const DEFAULT_TENANT: &str = "default_tenant";
+// How many times the collection and its segments are read before giving up on finding them
+// at the same version, while compactions keep flushing it.
+const SNAPSHOT_ATTEMPTS: u32 = 3;
 
 #[derive(Debug, Clone)]
 pub(crate) enum SysDb {
@@ -47,9 +59,36 @@ impl SysDb {
     ) -> Result<Vec<Collection>, GetCollectionsError> {
         match self {
             SysDb::Grpc(grpc) => {
-                return grpc
-                    .get_collections(collection_id, name, tenant, database)
-                    .await;
+                let cached = match (&grpc.cache, collection_id) {
+                    (Some(cache), Some(collection_id))
+                        if name.is_none() && tenant.is_none() && database.is_none() =>
+                    {
+                        Some((cache.clone(), collection_id))
+                    }
+                    _ => None,
+                };
+                let get_collections = with_retries(&grpc.retry_policy, "get_collections", || {
+                    // Cloning only clones the channel handle, see GrpcSysDb.
+                    let mut grpc = grpc.clone();
+                    let (name, tenant, database) = (name.clone(), tenant.clone(), database.clone());
+                    async move {
+                        grpc.get_collections(collection_id, name, tenant, database)
+                            .await
+                    }
+                });
+                match cached {
+                    Some((cache, collection_id)) => {
+                        let collection = cache
+                            .get_collection(collection_id, || async {
+                                Ok::<_, GetCollectionsError>(
+                                    get_collections.await?.into_iter().next(),
+                                )
+                            })
+                            .await?;
+                        Ok(collection.into_iter().collect())
+                    }
+                    None => get_collections.await,
+                }
             }
             SysDb::Test(test) => {
                 return test
@@ -68,7 +107,42 @@ impl SysDb {
     ) -> Result<Vec<Segment>, GetSegmentsError> {
         match self {
             SysDb::Grpc(grpc) => {
-                return grpc.get_segments(id, r#type, scope, collection).await;
+                // Segments are cached per collection. Segments looked up by their own id can
+                // be served from the cache once their collection has been cached.
+                let cached = grpc.cache.as_ref().and_then(|cache| {
+                    let collection_id = match collection {
+                        Some(collection_id) => Some(collection_id),
+                        None => cache.collection_of_segment(&id?),
+                    }?;
+                    Some((cache.clone(), collection_id))
+                });
+                if let Some((cache, collection_id)) = cached {
+                    let segments = cache
+                        .get_segments(collection_id, || async {
+                            let segments = with_retries(&grpc.retry_policy, "get_segments", || {
+                                let mut grpc = grpc.clone();
+                                async move {
+                                    grpc.get_segments(None, None, None, Some(collection_id))
+                                        .await
+                                }
+                            })
+                            .await?;
+                            // A collection without segments is not cached, it may not exist.
+                            Ok::<_, GetSegmentsError>((!segments.is_empty()).then_some(segments))
+                        })
+                        .await?;
+                    return Ok(segments
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|segment| segment_matches(segment, id, &r#type, &scope))
+                        .collect());
+                }
+                return with_retries(&grpc.retry_policy, "get_segments", || {
+                    let mut grpc = grpc.clone();
+                    let (r#type, scope) = (r#type.clone(), scope.clone());
+                    async move { grpc.get_segments(id, r#type, scope, collection).await }
+                })
+                .await;
             }
             SysDb::Test(test) => {
                 return test.get_segments(id, r#type, scope, collection).await;
@@ -76,13 +150,40 @@ impl SysDb {
         }
     }
 
+    /// Returns the collection and all of its segments as of one version of the collection,
+    /// see `CollectionSnapshot`.
+    pub(crate) async fn get_collection_snapshot(
+        &mut self,
+        collection_id: Uuid,
+    ) -> Result<Arc<CollectionSnapshot>, GetCollectionSnapshotError> {
+        let snapshot = match self {
+            SysDb::Grpc(grpc) => match grpc.cache.clone() {
+                Some(cache) => {
+                    cache
+                        .get_snapshot(collection_id, || grpc.load_snapshot(collection_id))
+                        .await?
+                }
+                None => grpc.load_snapshot(collection_id).await?,
+            },
+            SysDb::Test(test) => test.get_collection_snapshot(collection_id).await,
+        };
+        snapshot.ok_or(GetCollectionSnapshotError::CollectionNotFound(
+            collection_id,
+        ))
+    }
+
     pub(crate) async fn get_last_compaction_time(
         &mut self,
         tanant_ids: Vec<String>,
     ) -> Result<Vec<Tenant>, GetLastCompactionTimeError> {
         match self {
             SysDb::Grpc(grpc) => {
-                return grpc.get_last_compaction_time(tanant_ids).await;
+                return with_retries(&grpc.retry_policy, "get_last_compaction_time", || {
+                    let mut grpc = grpc.clone();
+                    let tanant_ids = tanant_ids.clone();
+                    async move { grpc.get_last_compaction_time(tanant_ids).await }
+                })
+                .await;
             }
             SysDb::Test(test) => {
                 return test.get_last_compaction_time(tanant_ids).await;
@@ -90,6 +191,42 @@ impl SysDb {
         }
     }
 
+    /// Records the dimension of a collection that did not have one yet.
+    pub(crate) async fn update_collection_dimension(
+        &mut self,
+        collection_id: Uuid,
+        dimension: i32,
+    ) -> Result<(), UpdateCollectionError> {
+        match self {
+            SysDb::Grpc(grpc) => {
+                let res = with_retries(&grpc.retry_policy, "update_collection", || {
+                    let mut grpc = grpc.clone();
+                    async move {
+                        grpc.update_collection_dimension(collection_id, dimension)
+                            .await
+                    }
+                })
+                .await;
+                if let Some(cache) = &grpc.cache {
+                    cache.invalidate(&collection_id);
+                }
+                res
+            }
+            SysDb::Test(test) => {
+                test.update_collection_dimension(collection_id, dimension)
+                    .await
+            }
+        }
+    }
+
+    /// Checks that sysdb can be reached with a single request, without retrying it.
+    pub(crate) async fn ping(&mut self) -> Result<(), GetLastCompactionTimeError> {
+        match self {
+            SysDb::Grpc(grpc) => grpc.get_last_compaction_time(vec![]).await.map(|_| ()),
+            SysDb::Test(_) => Ok(()),
+        }
+    }
+
     pub(crate) async fn flush_compaction(
         &mut self,
         tenant_id: String,
@@ -100,15 +237,43 @@ impl SysDb {
     ) -> Result<FlushCompactionResponse, FlushCompactionError> {
         match self {
             SysDb::Grpc(grpc) => {
-                return grpc
-                    .flush_compaction(
-                        tenant_id,
-                        collection_id,
-                        log_position,
-                        collection_version,
-                        segment_flush_info,
-                    )
-                    .await;
+                // A flush that timed out may still have been applied. Sending it again is safe,
+                // it then fails on the stale collection version instead of being applied twice.
+                let res = with_retries(&grpc.retry_policy, "flush_compaction", || {
+                    let mut grpc = grpc.clone();
+                    let (tenant_id, segment_flush_info) =
+                        (tenant_id.clone(), segment_flush_info.clone());
+                    async move {
+                        grpc.flush_compaction(
+                            tenant_id,
+                            collection_id,
+                            log_position,
+                            collection_version,
+                            segment_flush_info,
+                        )
+                        .await
+                    }
+                })
+                .await;
+                // Even a failed flush may have been applied, so the cached segment files
+                // can't be trusted either way. A flush that went through creates a version
+                // that is known here in full, queries that start after it read that one.
+                if let Some(cache) = &grpc.cache {
+                    match &res {
+                        Ok(_) => {
+                            cache
+                                .flushed(
+                                    &collection_id,
+                                    log_position,
+                                    collection_version,
+                                    &segment_flush_info,
+                                )
+                                .await
+                        }
+                        Err(_) => cache.invalidate(&collection_id),
+                    }
+                }
+                return res;
             }
             SysDb::Test(test) => {
                 return test
@@ -125,6 +290,84 @@ impl SysDb {
     }
 }
 
+// Whether a segment of the collection that is being looked up matches the other filters
+// of get_segments.
+fn segment_matches(
+    segment: &Segment,
+    id: Option<Uuid>,
+    r#type: &Option<String>,
+    scope: &Option<SegmentScope>,
+) -> bool {
+    id.map_or(true, |id| id == segment.id)
+        && r#type.as_ref().map_or(true, |r#type| {
+            *r#type == String::from(segment.r#type.clone())
+        })
+        && scope.as_ref().map_or(true, |scope| *scope == segment.scope)
+}
+
+/// How requests to sysdb that fail with a retryable error are sent again.
+/// # Fields
+/// - max_attempts: The number of times a request is sent in total.
+/// - base_delay: The delay before the first retry, doubled for every retry after it.
+/// - deadline: How long after the first attempt a retry may still be started.
+#[derive(Clone, Debug)]
+pub(crate) struct RetryPolicy {
+    pub(crate) max_attempts: u32,
+    pub(crate) base_delay: Duration,
+    pub(crate) deadline: Duration,
+}
+
+impl RetryPolicy {
+    // The delay before the retry that follows `attempt`, jittered down by up to half so that
+    // workers failing at the same time do not all retry at the same time.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay * 2u32.saturating_pow(attempt - 1);
+        delay / 2 + delay.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+    }
+}
+
+impl From<&GrpcSysDbConfig> for RetryPolicy {
+    fn from(config: &GrpcSysDbConfig) -> Self {
+        RetryPolicy {
+            max_attempts: config.retry_max_attempts,
+            base_delay: Duration::from_millis(config.retry_base_delay_ms),
+            deadline: Duration::from_millis(config.retry_deadline_ms),
+        }
+    }
+}
+
+// Runs a sysdb request, sending it again while it fails with a retryable error, until it
+// has been sent `max_attempts` times or the next retry would start after the deadline. The
+// request is recorded under `method`, once for all of its attempts.
+async fn with_retries<T, E, F, Fut>(
+    policy: &RetryPolicy,
+    method: &'static str,
+    mut request: F,
+) -> Result<T, E>
+where
+    E: ChromaError,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let start = Instant::now();
+    let mut attempt = 1;
+    let result = loop {
+        match request().await {
+            Err(e) if e.is_retryable() && attempt < policy.max_attempts => {
+                let backoff = policy.backoff(attempt);
+                if start.elapsed() + backoff > policy.deadline {
+                    break Err(e);
+                }
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => break result,
+        }
+    };
+    metrics().record_sysdb_request(method, result.is_ok(), start.elapsed());
+    result
+}
+
 #[derive(Clone, Debug)]
 // Since this uses tonic transport channel, cloning is cheap. Each client only supports
 // one inflight request at a time, so we need to clone the client for each requester.
@@ -135,6 +378,9 @@ pub(crate) struct GrpcSysDb {
             fn(Request<()>) -> Result<Request<()>, Status>,
         >,
     >,
+    retry_policy: RetryPolicy,
+    // Shared by all clones, None if caching is turned off.
+    cache: Option<SysDbCache>,
 }
 
 #[derive(Error, Debug)]
@@ -172,28 +418,80 @@ impl Configurable<SysDbConfig> for GrpcSysDb {
                 let endpoint = endpoint
                     .connect_timeout(Duration::from_millis(my_config.connect_timeout_ms))
                     .timeout(Duration::from_millis(my_config.request_timeout_ms));
-                match endpoint.connect().await {
-                    Ok(channel) => {
-                        let client: SysDbClient<
-                            interceptor::InterceptedService<
-                                tonic::transport::Channel,
-                                fn(Request<()>) -> Result<Request<()>, Status>,
-                            >,
-                        > = SysDbClient::with_interceptor(channel, client_interceptor);
-                        return Ok(GrpcSysDb { client });
-                    }
-                    Err(e) => {
-                        return Err(Box::new(GrpcSysDbError::FailedToConnect(
-                            tonic::transport::Error::from(e),
-                        )));
-                    }
+                // The channel connects on its first request and reconnects after the
+                // connection is lost, so sysdb restarting does not leave us with a dead
+                // connection. Requests made while it can't connect fail with Unavailable
+                // and are retried.
+                let channel = endpoint.connect_lazy();
+                let client: SysDbClient<
+                    interceptor::InterceptedService<
+                        tonic::transport::Channel,
+                        fn(Request<()>) -> Result<Request<()>, Status>,
+                    >,
+                > = SysDbClient::with_interceptor(channel, client_interceptor);
+                let cache = if my_config.cache_ttl_ms > 0 {
+                    Some(SysDbCache::new(
+                        Duration::from_millis(my_config.cache_ttl_ms),
+                        my_config.cache_max_entries,
+                    ))
+                } else {
+                    None
                 };
+                return Ok(GrpcSysDb {
+                    client,
+                    retry_policy: RetryPolicy::from(my_config),
+                    cache,
+                });
             }
         }
     }
 }
 
 impl GrpcSysDb {
+    // Reads the collection before and after its segments, and takes them to be of the same
+    // version when the collection did not change in between. Sysdb flushes the collection
+    // and its segments in one transaction, so segments read between two reads of the same
+    // version are of that version.
+    async fn load_snapshot(
+        &self,
+        collection_id: Uuid,
+    ) -> Result<Option<Arc<CollectionSnapshot>>, GetCollectionSnapshotError> {
+        let get_collection = || {
+            with_retries(&self.retry_policy, "get_collections", || {
+                let mut grpc = self.clone();
+                async move {
+                    grpc.get_collections(Some(collection_id), None, None, None)
+                        .await
+                }
+            })
+        };
+        for _ in 0..SNAPSHOT_ATTEMPTS {
+            let before = match get_collection().await?.into_iter().next() {
+                Some(collection) => collection,
+                None => return Ok(None),
+            };
+            let segments = with_retries(&self.retry_policy, "get_segments", || {
+                let mut grpc = self.clone();
+                async move {
+                    grpc.get_segments(None, None, None, Some(collection_id))
+                        .await
+                }
+            })
+            .await?;
+            let after = match get_collection().await?.into_iter().next() {
+                Some(collection) => collection,
+                None => return Ok(None),
+            };
+            if after.version == before.version {
+                return Ok(Some(Arc::new(CollectionSnapshot {
+                    collection: after,
+                    segments,
+                })));
+            }
+        }
+        Err(GetCollectionSnapshotError::VersionChanged(collection_id))
+    }
+
     async fn get_collections(
         &mut self,
         collection_id: Option<Uuid>,
@@ -335,6 +633,26 @@ impl GrpcSysDb {
         }
     }
 
+    async fn update_collection_dimension(
+        &mut self,
+        collection_id: Uuid,
+        dimension: i32,
+    ) -> Result<(), UpdateCollectionError> {
+        let res = self
+            .client
+            .update_collection(chroma_proto::UpdateCollectionRequest {
+                id: collection_id.to_string(),
+                name: None,
+                dimension: Some(dimension),
+                metadata_update: None,
+            })
+            .await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => Err(UpdateCollectionError::FailedToUpdateCollection(e)),
+        }
+    }
+
     async fn flush_compaction(
         &mut self,
         tenant_id: String,
@@ -405,6 +723,13 @@ impl ChromaError for GetCollectionsError {
             GetCollectionsError::ConversionError(_) => ErrorCodes::Internal,
         }
     }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            GetCollectionsError::FailedToGetCollections(status) => is_retryable_status(status),
+            GetCollectionsError::ConversionError(_) => false,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -424,6 +749,72 @@ impl ChromaError for GetSegmentsError {
             GetSegmentsError::ConversionError(_) => ErrorCodes::Internal,
         }
     }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            GetSegmentsError::FailedToGetSegments(status) => is_retryable_status(status),
+            GetSegmentsError::ConversionError(_) => false,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum GetSegmentError {
+    #[error("No {0:?} segment found for collection {1}")]
+    NotFound(SegmentType, Uuid),
+    #[error("Found {2} {0:?} segments for collection {1}, expected one")]
+    MultipleFound(SegmentType, Uuid, usize),
+    #[error("Failed to get segments")]
+    GetSegmentsError(#[from] GetSegmentsError),
+}
+
+impl ChromaError for GetSegmentError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            GetSegmentError::NotFound(_, _) => ErrorCodes::NotFound,
+            GetSegmentError::MultipleFound(_, _, _) => ErrorCodes::Internal,
+            GetSegmentError::GetSegmentsError(e) => e.code(),
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            GetSegmentError::GetSegmentsError(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum GetCollectionSnapshotError {
+    #[error("Collection {0} not found")]
+    CollectionNotFound(Uuid),
+    #[error("Collection {0} kept changing while its segments were read")]
+    VersionChanged(Uuid),
+    #[error("Failed to get collection")]
+    GetCollectionsError(#[from] GetCollectionsError),
+    #[error("Failed to get segments")]
+    GetSegmentsError(#[from] GetSegmentsError),
+}
+
+impl ChromaError for GetCollectionSnapshotError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            GetCollectionSnapshotError::CollectionNotFound(_) => ErrorCodes::NotFound,
+            GetCollectionSnapshotError::VersionChanged(_) => ErrorCodes::Unavailable,
+            GetCollectionSnapshotError::GetCollectionsError(e) => e.code(),
+            GetCollectionSnapshotError::GetSegmentsError(e) => e.code(),
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            GetCollectionSnapshotError::CollectionNotFound(_) => false,
+            GetCollectionSnapshotError::VersionChanged(_) => true,
+            GetCollectionSnapshotError::GetCollectionsError(e) => e.is_retryable(),
+            GetCollectionSnapshotError::GetSegmentsError(e) => e.is_retryable(),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -442,6 +833,39 @@ impl ChromaError for GetLastCompactionTimeError {
             GetLastCompactionTimeError::TenantNotFound => ErrorCodes::Internal,
         }
     }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            GetLastCompactionTimeError::FailedToGetLastCompactionTime(status) => {
+                is_retryable_status(status)
+            }
+            GetLastCompactionTimeError::TenantNotFound => false,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum UpdateCollectionError {
+    #[error("Failed to update collection")]
+    FailedToUpdateCollection(#[from] tonic::Status),
+    #[error("Collection not found in sysdb")]
+    CollectionNotFound,
+}
+
+impl ChromaError for UpdateCollectionError {
+    fn code(&self) -> ErrorCodes {
+        match self {
+            UpdateCollectionError::FailedToUpdateCollection(_) => ErrorCodes::Internal,
+            UpdateCollectionError::CollectionNotFound => ErrorCodes::NotFound,
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            UpdateCollectionError::FailedToUpdateCollection(status) => is_retryable_status(status),
+            UpdateCollectionError::CollectionNotFound => false,
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -456,6 +880,8 @@ pub(crate) enum FlushCompactionError {
     CollectionNotFound,
     #[error("Segment not found in sysdb")]
     SegmentNotFound,
+    #[error("Collection is at version {actual}, not {expected}")]
+    CollectionVersionMismatch { expected: i32, actual: i32 },
 }
 
 impl ChromaError for FlushCompactionError {
@@ -466,6 +892,623 @@ impl ChromaError for FlushCompactionError {
             FlushCompactionError::FlushCompactionResponseConversionError(_) => ErrorCodes::Internal,
             FlushCompactionError::CollectionNotFound => ErrorCodes::Internal,
             FlushCompactionError::SegmentNotFound => ErrorCodes::Internal,
+            FlushCompactionError::CollectionVersionMismatch { .. } => ErrorCodes::Aborted,
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        match self {
+            FlushCompactionError::FailedToFlushCompaction(status) => is_retryable_status(status),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chroma_proto::sys_db_server;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tonic::{Code, Response};
+
+    fn retry_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            deadline: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn test_status_errors_classify_by_status_code() {
+        // These report Internal to our callers, but whether they are worth retrying depends
+        // on what sysdb returned.
+        let err = GetCollectionsError::FailedToGetCollections(Status::unavailable("down"));
+        assert_eq!(err.code(), ErrorCodes::Internal);
+        assert!(err.is_retryable());
+        let err = GetCollectionsError::FailedToGetCollections(Status::invalid_argument("bad"));
+        assert!(!err.is_retryable());
+        let err = GetLastCompactionTimeError::FailedToGetLastCompactionTime(
+            Status::deadline_exceeded("slow"),
+        );
+        assert!(err.is_retryable());
+        assert!(!GetLastCompactionTimeError::TenantNotFound.is_retryable());
+        assert!(!FlushCompactionError::CollectionNotFound.is_retryable());
+    }
+
+    #[test]
+    fn test_backoff_doubles_with_jitter() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            deadline: Duration::from_secs(10),
+        };
+        for attempt in 1..=4 {
+            let delay = Duration::from_millis(100) * 2u32.pow(attempt - 1);
+            for _ in 0..100 {
+                let backoff = policy.backoff(attempt);
+                assert!(backoff >= delay / 2 && backoff <= delay);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_with_retries_retries_until_success() {
+        let attempts = AtomicU32::new(0);
+        let result = with_retries(&retry_policy(4), "test", || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if attempt < 2 {
+                    Err(GetSegmentsError::FailedToGetSegments(Status::unavailable(
+                        "down",
+                    )))
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_with_retries_gives_up() {
+        // Errors that are not retryable are returned after a single attempt.
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = with_retries(&retry_policy(4), "test", || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async {
+                Err(GetSegmentsError::FailedToGetSegments(Status::not_found(
+                    "gone",
+                )))
+            }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // Retryable errors are sent max_attempts times before the last error is returned.
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = with_retries(&retry_policy(4), "test", || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async {
+                Err(GetSegmentsError::FailedToGetSegments(Status::aborted(
+                    "retry",
+                )))
+            }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_with_retries_stops_at_the_deadline() {
+        // The first retry would wait at least 100ms, which is past the deadline.
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(200),
+            deadline: Duration::from_millis(100),
+        };
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = with_retries(&policy, "test", || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async {
+                Err(GetSegmentsError::FailedToGetSegments(Status::unavailable(
+                    "down",
+                )))
+            }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    fn new_segment(collection_id: Uuid, r#type: SegmentType, scope: SegmentScope) -> Segment {
+        Segment {
+            id: Uuid::new_v4(),
+            r#type,
+            scope,
+            collection: Some(collection_id),
+            metadata: None,
+            file_path: std::collections::HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_segment_by_type() {
+        let collection_id = Uuid::new_v4();
+        let other_collection_id = Uuid::new_v4();
+        let mut test_sysdb = TestSysDb::new();
+        let record_segment = new_segment(
+            collection_id,
+            SegmentType::BlockfileRecord,
+            SegmentScope::RECORD,
+        );
+        test_sysdb.add_segment(record_segment.clone());
+        test_sysdb.add_segment(new_segment(
+            collection_id,
+            SegmentType::HnswDistributed,
+            SegmentScope::VECTOR,
+        ));
+        // Segments of other collections are never returned.
+        test_sysdb.add_segment(new_segment(
+            other_collection_id,
+            SegmentType::BlockfileRecord,
+            SegmentScope::RECORD,
+        ));
+        test_sysdb.add_segment(new_segment(
+            other_collection_id,
+            SegmentType::BlockfileMetadata,
+            SegmentScope::METADATA,
+        ));
+        test_sysdb.add_collection(Collection {
+            id: collection_id,
+            name: "collection".to_string(),
+            metadata: None,
+            dimension: None,
+            tenant: DEFAULT_TENANT.to_string(),
+            database: DEFAULT_DATBASE.to_string(),
+            log_position: 0,
+            version: 0,
+        });
+        let mut sysdb = SysDb::Test(test_sysdb.clone());
+        let snapshot = sysdb.get_collection_snapshot(collection_id).await.unwrap();
+
+        // One match.
+        assert_eq!(
+            snapshot.segment(SegmentType::BlockfileRecord).unwrap(),
+            record_segment
+        );
+        let vector_segment = snapshot.segment(SegmentType::HnswDistributed).unwrap();
+        assert_eq!(vector_segment.r#type, SegmentType::HnswDistributed);
+        assert_eq!(vector_segment.collection, Some(collection_id));
+
+        // No match.
+        let err = snapshot
+            .segment(SegmentType::BlockfileMetadata)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            GetSegmentError::NotFound(SegmentType::BlockfileMetadata, id) if id == collection_id
+        ));
+        assert_eq!(err.code(), ErrorCodes::NotFound);
+
+        // More than one match.
+        test_sysdb.add_segment(new_segment(
+            collection_id,
+            SegmentType::HnswDistributed,
+            SegmentScope::VECTOR,
+        ));
+        let err = sysdb
+            .get_collection_snapshot(collection_id)
+            .await
+            .unwrap()
+            .segment(SegmentType::HnswDistributed)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            GetSegmentError::MultipleFound(SegmentType::HnswDistributed, id, 2)
+                if id == collection_id
+        ));
+        assert_eq!(err.code(), ErrorCodes::Internal);
+    }
+
+    // A sysdb that fails the first `failures` calls to get_collections, get_segments and
+    // flush_collection_compaction with `code`. Every collection looked up by id exists and
+    // has one record segment, `segment_id`.
+    struct FlakySysDb {
+        failures: u32,
+        code: Code,
+        calls: AtomicU32,
+        segment_id: Uuid,
+    }
+
+    impl FlakySysDb {
+        fn call(&self) -> Result<(), Status> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(Status::new(self.code, "flaky sysdb"));
+            }
+            Ok(())
+        }
+    }
+
+    #[tonic::async_trait]
+    impl sys_db_server::SysDb for FlakySysDb {
+        async fn create_database(
+            &self,
+            _: Request<chroma_proto::CreateDatabaseRequest>,
+        ) -> Result<Response<chroma_proto::CreateDatabaseResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn get_database(
+            &self,
+            _: Request<chroma_proto::GetDatabaseRequest>,
+        ) -> Result<Response<chroma_proto::GetDatabaseResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn create_tenant(
+            &self,
+            _: Request<chroma_proto::CreateTenantRequest>,
+        ) -> Result<Response<chroma_proto::CreateTenantResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn get_tenant(
+            &self,
+            _: Request<chroma_proto::GetTenantRequest>,
+        ) -> Result<Response<chroma_proto::GetTenantResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn create_segment(
+            &self,
+            _: Request<chroma_proto::CreateSegmentRequest>,
+        ) -> Result<Response<chroma_proto::CreateSegmentResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn delete_segment(
+            &self,
+            _: Request<chroma_proto::DeleteSegmentRequest>,
+        ) -> Result<Response<chroma_proto::DeleteSegmentResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn get_segments(
+            &self,
+            request: Request<chroma_proto::GetSegmentsRequest>,
+        ) -> Result<Response<chroma_proto::GetSegmentsResponse>, Status> {
+            self.call()?;
+            let segments = match request.into_inner().collection {
+                Some(collection_id) => vec![chroma_proto::Segment {
+                    id: self.segment_id.to_string(),
+                    r#type: SegmentType::BlockfileRecord.into(),
+                    scope: chroma_proto::SegmentScope::Record as i32,
+                    collection: Some(collection_id),
+                    metadata: None,
+                    file_paths: std::collections::HashMap::new(),
+                }],
+                None => vec![],
+            };
+            Ok(Response::new(chroma_proto::GetSegmentsResponse {
+                segments,
+                status: None,
+            }))
+        }
+
+        async fn update_segment(
+            &self,
+            _: Request<chroma_proto::UpdateSegmentRequest>,
+        ) -> Result<Response<chroma_proto::UpdateSegmentResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn create_collection(
+            &self,
+            _: Request<chroma_proto::CreateCollectionRequest>,
+        ) -> Result<Response<chroma_proto::CreateCollectionResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn delete_collection(
+            &self,
+            _: Request<chroma_proto::DeleteCollectionRequest>,
+        ) -> Result<Response<chroma_proto::DeleteCollectionResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn get_collections(
+            &self,
+            request: Request<chroma_proto::GetCollectionsRequest>,
+        ) -> Result<Response<chroma_proto::GetCollectionsResponse>, Status> {
+            self.call()?;
+            let collections = match request.into_inner().id {
+                Some(id) => vec![chroma_proto::Collection {
+                    id,
+                    name: "collection".to_string(),
+                    tenant: DEFAULT_TENANT.to_string(),
+                    database: DEFAULT_DATBASE.to_string(),
+                    ..Default::default()
+                }],
+                None => vec![],
+            };
+            Ok(Response::new(chroma_proto::GetCollectionsResponse {
+                collections,
+                status: None,
+            }))
+        }
+
+        async fn update_collection(
+            &self,
+            _: Request<chroma_proto::UpdateCollectionRequest>,
+        ) -> Result<Response<chroma_proto::UpdateCollectionResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn reset_state(
+            &self,
+            _: Request<()>,
+        ) -> Result<Response<chroma_proto::ResetStateResponse>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn get_last_compaction_time_for_tenant(
+            &self,
+            _: Request<chroma_proto::GetLastCompactionTimeForTenantRequest>,
+        ) -> Result<Response<chroma_proto::GetLastCompactionTimeForTenantResponse>, Status>
+        {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn set_last_compaction_time_for_tenant(
+            &self,
+            _: Request<chroma_proto::SetLastCompactionTimeForTenantRequest>,
+        ) -> Result<Response<()>, Status> {
+            Err(Status::unimplemented(""))
+        }
+
+        async fn flush_collection_compaction(
+            &self,
+            request: Request<chroma_proto::FlushCollectionCompactionRequest>,
+        ) -> Result<Response<chroma_proto::FlushCollectionCompactionResponse>, Status> {
+            self.call()?;
+            let request = request.into_inner();
+            Ok(Response::new(
+                chroma_proto::FlushCollectionCompactionResponse {
+                    collection_id: request.collection_id,
+                    collection_version: request.collection_version + 1,
+                    last_compaction_time: 0,
+                },
+            ))
+        }
+    }
+
+    // Serves `sysdb` on `port`, returning once the port accepts connections.
+    async fn serve(sysdb: Arc<FlakySysDb>, port: u16) {
+        let addr = format!("127.0.0.1:{}", port).parse().unwrap();
+        tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(sys_db_server::SysDbServer::from_arc(sysdb))
+                .serve(addr)
+                .await
+                .unwrap();
+        });
+        while tokio::net::TcpStream::connect(addr).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn client(port: u16, retry_max_attempts: u32, cache_ttl_ms: u64) -> SysDb {
+        let config = SysDbConfig::Grpc(GrpcSysDbConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            connect_timeout_ms: 1000,
+            request_timeout_ms: 1000,
+            retry_max_attempts,
+            retry_base_delay_ms: 1,
+            retry_deadline_ms: 10000,
+            cache_ttl_ms,
+            cache_max_entries: 100,
+        });
+        SysDb::Grpc(GrpcSysDb::try_from_config(&config).await.unwrap())
+    }
+
+    async fn connect(sysdb: Arc<FlakySysDb>) -> SysDb {
+        let port = random_port::PortPicker::new().pick().unwrap();
+        serve(sysdb, port).await;
+        client(port, 4, 0).await
+    }
+
+    fn flaky_sysdb(failures: u32, code: Code) -> Arc<FlakySysDb> {
+        Arc::new(FlakySysDb {
+            failures,
+            code,
+            calls: AtomicU32::new(0),
+            segment_id: Uuid::new_v4(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_grpc_sysdb_caches_collections_and_segments() {
+        let server = flaky_sysdb(0, Code::Unavailable);
+        let port = random_port::PortPicker::new().pick().unwrap();
+        serve(server.clone(), port).await;
+        let mut sysdb = client(port, 4, 60000).await;
+        let collection_id = Uuid::new_v4();
+        for _ in 0..3 {
+            let collections = sysdb
+                .get_collections(Some(collection_id), None, None, None)
+                .await
+                .unwrap();
+            assert_eq!(collections.len(), 1);
+            assert_eq!(collections[0].id, collection_id);
+            let record_segment = sysdb
+                .get_segments(None, None, None, Some(collection_id))
+                .await
+                .unwrap()
+                .pop()
+                .unwrap();
+            assert_eq!(record_segment.id, server.segment_id);
+            // Once its collection is cached, a segment can be looked up by its own id too.
+            let segments = sysdb
+                .get_segments(Some(server.segment_id), None, None, None)
+                .await
+                .unwrap();
+            assert_eq!(segments, vec![record_segment]);
+        }
+        assert_eq!(server.calls.load(Ordering::SeqCst), 2);
+
+        // Other lookups always go to sysdb.
+        sysdb
+            .get_collections(None, Some("collection".to_string()), None, None)
+            .await
+            .unwrap();
+        assert_eq!(server.calls.load(Ordering::SeqCst), 3);
+
+        // Flushing a compaction invalidates the collection, so the new segment files are
+        // read from sysdb.
+        sysdb
+            .flush_compaction(
+                DEFAULT_TENANT.to_string(),
+                collection_id,
+                10,
+                0,
+                Arc::new([]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(server.calls.load(Ordering::SeqCst), 4);
+        sysdb
+            .get_collections(Some(collection_id), None, None, None)
+            .await
+            .unwrap();
+        sysdb
+            .get_segments(None, None, None, Some(collection_id))
+            .await
+            .unwrap();
+        assert_eq!(server.calls.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_sysdb_caches_snapshots_across_flushes() {
+        let server = flaky_sysdb(0, Code::Unavailable);
+        let port = random_port::PortPicker::new().pick().unwrap();
+        serve(server.clone(), port).await;
+        let mut sysdb = client(port, 4, 60000).await;
+        let collection_id = Uuid::new_v4();
+
+        // The collection is read before and after its segments.
+        let snapshot = sysdb.get_collection_snapshot(collection_id).await.unwrap();
+        assert_eq!(snapshot.collection.version, 0);
+        assert_eq!(snapshot.segments.len(), 1);
+        assert_eq!(server.calls.load(Ordering::SeqCst), 3);
+        sysdb.get_collection_snapshot(collection_id).await.unwrap();
+        assert_eq!(server.calls.load(Ordering::SeqCst), 3);
+
+        // A flush replaces the cached snapshot with the version it creates, without reading
+        // it back from sysdb. The snapshot of the version before is left as it was.
+        let file_paths =
+            std::collections::HashMap::from([("hnsw_index".to_string(), vec!["1".to_string()])]);
+        sysdb
+            .flush_compaction(
+                DEFAULT_TENANT.to_string(),
+                collection_id,
+                10,
+                0,
+                Arc::new([SegmentFlushInfo {
+                    segment_id: server.segment_id,
+                    file_paths: file_paths.clone(),
+                }]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(server.calls.load(Ordering::SeqCst), 4);
+        let flushed = sysdb.get_collection_snapshot(collection_id).await.unwrap();
+        assert_eq!(server.calls.load(Ordering::SeqCst), 4);
+        assert_eq!(flushed.collection.version, 1);
+        assert_eq!(flushed.collection.log_position, 10);
+        assert_eq!(flushed.segments[0].file_path, file_paths);
+        assert_eq!(snapshot.collection.version, 0);
+        assert!(snapshot.segments[0].file_path.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_grpc_sysdb_retries_transient_errors() {
+        for code in [
+            Code::Unavailable,
+            Code::DeadlineExceeded,
+            Code::ResourceExhausted,
+        ] {
+            let server = flaky_sysdb(3, code);
+            let mut sysdb = connect(server.clone()).await;
+            let collections = sysdb.get_collections(None, None, None, None).await;
+            assert!(collections.unwrap().is_empty());
+            assert_eq!(server.calls.load(Ordering::SeqCst), 4);
+        }
+
+        // The last error is returned once every attempt has failed.
+        let server = flaky_sysdb(4, Code::Unavailable);
+        let mut sysdb = connect(server.clone()).await;
+        match sysdb.get_collections(None, None, None, None).await {
+            Err(GetCollectionsError::FailedToGetCollections(status)) => {
+                assert_eq!(status.code(), Code::Unavailable)
+            }
+            _ => panic!("Expected the get to fail with Unavailable"),
+        }
+        assert_eq!(server.calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_sysdb_fails_fast_on_other_errors() {
+        for code in [Code::InvalidArgument, Code::NotFound] {
+            let server = flaky_sysdb(1, code);
+            let mut sysdb = connect(server.clone()).await;
+            match sysdb.get_collections(None, None, None, None).await {
+                Err(GetCollectionsError::FailedToGetCollections(status)) => {
+                    assert_eq!(status.code(), code)
+                }
+                _ => panic!("Expected the get to fail with {:?}", code),
+            }
+            assert_eq!(server.calls.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_sysdb_retries_flushes() {
+        let server = flaky_sysdb(2, Code::Unavailable);
+        let mut sysdb = connect(server.clone()).await;
+        let collection_id = Uuid::new_v4();
+        let response = sysdb
+            .flush_compaction("tenant".to_string(), collection_id, 10, 1, Arc::new([]))
+            .await
+            .unwrap();
+        assert_eq!(response.collection_id, collection_id);
+        assert_eq!(response.collection_version, 2);
+        assert_eq!(server.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_sysdb_connects_lazily() {
+        // Nothing is listening yet, which only fails the requests that are made before
+        // sysdb is up.
+        let port = random_port::PortPicker::new().pick().unwrap();
+        let mut sysdb = client(port, 1, 0).await;
+        match sysdb.get_collections(None, None, None, None).await {
+            Err(GetCollectionsError::FailedToGetCollections(status)) => {
+                assert_eq!(status.code(), Code::Unavailable)
+            }
+            _ => panic!("Expected the get to fail with Unavailable"),
         }
+
+        serve(flaky_sysdb(0, Code::Unavailable), port).await;
+        assert!(sysdb
+            .get_collections(None, None, None, None)
+            .await
+            .unwrap()
+            .is_empty());
     }
 }
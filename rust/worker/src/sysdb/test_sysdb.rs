@@ -10,10 +10,12 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use uuid::Uuid;
 
+use super::snapshot::CollectionSnapshot;
 use super::sysdb::FlushCompactionError;
 use super::sysdb::GetCollectionsError;
 use super::sysdb::GetLastCompactionTimeError;
 use super::sysdb::GetSegmentsError;
+use super::sysdb::UpdateCollectionError;
 
 #[derive(Clone, Debug)]
 pub(crate) struct TestSysDb {
@@ -92,7 +94,10 @@ impl TestSysDb {
             return false;
         }
         if let Some(r#type) = r#type {
-            return segment.r#type == SegmentType::try_from(r#type.as_str()).unwrap();
+            match SegmentType::try_from(r#type.as_str()) {
+                Ok(r#type) if r#type == segment.r#type => {}
+                _ => return false,
+            }
         }
         if scope.is_some() && scope.unwrap() != segment.scope {
             return false;
@@ -150,6 +155,25 @@ impl TestSysDb {
         Ok(segments)
     }
 
+    // The collection and its segments are read under one lock, which flushes take as well.
+    pub(crate) async fn get_collection_snapshot(
+        &mut self,
+        collection_id: Uuid,
+    ) -> Option<Arc<CollectionSnapshot>> {
+        let inner = self.inner.lock();
+        let collection = inner.collections.get(&collection_id)?.clone();
+        let segments = inner
+            .segments
+            .values()
+            .filter(|segment| segment.collection == Some(collection_id))
+            .cloned()
+            .collect();
+        Some(Arc::new(CollectionSnapshot {
+            collection,
+            segments,
+        }))
+    }
+
     pub(crate) async fn get_last_compaction_time(
         &mut self,
         tenant_ids: Vec<String>,
@@ -172,6 +196,21 @@ impl TestSysDb {
         Ok(tenants)
     }
 
+    pub(crate) async fn update_collection_dimension(
+        &mut self,
+        collection_id: Uuid,
+        dimension: i32,
+    ) -> Result<(), UpdateCollectionError> {
+        let mut inner = self.inner.lock();
+        match inner.collections.get_mut(&collection_id) {
+            Some(collection) => {
+                collection.dimension = Some(dimension);
+                Ok(())
+            }
+            None => Err(UpdateCollectionError::CollectionNotFound),
+        }
+    }
+
     pub(crate) async fn flush_compaction(
         &mut self,
         tenant_id: String,
@@ -185,7 +224,22 @@ impl TestSysDb {
         if collection.is_none() {
             return Err(FlushCompactionError::CollectionNotFound);
         }
+        // Like the sysdb, either every segment is updated or none is, so check them all
+        // before changing anything.
+        for segment_flush_info in segment_flush_info.iter() {
+            if !inner.segments.contains_key(&segment_flush_info.segment_id) {
+                return Err(FlushCompactionError::SegmentNotFound);
+            }
+        }
         let collection = collection.unwrap();
+        // Like the sysdb, only a flush of the current version of the collection replaces
+        // it, so that of two flushes of the same version only the first one is applied.
+        if collection.version != collection_version {
+            return Err(FlushCompactionError::CollectionVersionMismatch {
+                expected: collection_version,
+                actual: collection.version,
+            });
+        }
         let mut collection = collection.clone();
         collection.log_position = log_position;
         let new_collection_version = collection_version + 1;
@@ -199,11 +253,7 @@ impl TestSysDb {
 
         // update segments
         for segment_flush_info in segment_flush_info.iter() {
-            let segment = inner.segments.get(&segment_flush_info.segment_id);
-            if segment.is_none() {
-                return Err(FlushCompactionError::SegmentNotFound);
-            }
-            let mut segment = segment.unwrap().clone();
+            let mut segment = inner.segments[&segment_flush_info.segment_id].clone();
             segment.file_path = segment_flush_info.file_paths.clone();
             inner.segments.insert(segment.id, segment);
         }
@@ -64,6 +64,8 @@ where
             .await;
         loop {
             select! {
+                // A stopped component does not start on the messages still in its queue.
+                biased;
                 _ = self.inner.cancellation_token.cancelled() => {
                     break;
                 }
@@ -96,5 +98,13 @@ where
                 }
             }
         }
+        self.handler
+            .on_stop(&ComponentContext {
+                system: self.inner.system.clone(),
+                sender: self.inner.sender.clone(),
+                cancellation_token: self.inner.cancellation_token.clone(),
+                scheduler: self.inner.scheduler.clone(),
+            })
+            .await;
     }
 }
@@ -58,6 +58,11 @@ where
     }
 }
 
+/// The number of messages waiting in the queue `sender` sends to.
+pub(super) fn queue_depth<T>(sender: &tokio::sync::mpsc::Sender<T>) -> usize {
+    sender.max_capacity() - sender.capacity()
+}
+
 // Sender
 pub(crate) struct Sender<C>
 where
@@ -1,8 +1,9 @@
 use super::scheduler::Scheduler;
-use super::sender::Sender;
+use super::sender::{queue_depth, Sender};
 use super::ComponentContext;
 use super::ComponentRuntime;
 use super::{executor::ComponentExecutor, Component, ComponentHandle, Handler, StreamHandler};
+use crate::tracing::metrics::metrics;
 use futures::Stream;
 use futures::StreamExt;
 use std::fmt::Debug;
@@ -35,6 +36,12 @@ impl System {
         C: Component + Send + 'static,
     {
         let (tx, rx) = tokio::sync::mpsc::channel(component.queue_size());
+        // The gauge holds on to the queue weakly, so that it does not keep it open.
+        let queue = tx.downgrade();
+        metrics().watch_component_queue(
+            C::get_name(),
+            Box::new(move || queue.upgrade().map(|sender| queue_depth(&sender))),
+        );
         let sender = Sender::new(tx);
         let cancel_token = tokio_util::sync::CancellationToken::new();
         let mut executor = ComponentExecutor::new(
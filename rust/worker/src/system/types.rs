@@ -2,8 +2,10 @@ use super::scheduler::Scheduler;
 use async_trait::async_trait;
 use futures::Stream;
 use std::fmt::Debug;
+use std::time::Duration;
 
-use super::{sender::Sender, system::System, Receiver, ReceiverImpl};
+use super::sender::{queue_depth, Sender};
+use super::{system::System, Receiver, ReceiverImpl};
 
 #[derive(Debug, PartialEq)]
 /// The state of a component
@@ -28,8 +30,11 @@ pub(crate) enum ComponentRuntime {
 /// It is a data object, and stores some parameterization
 /// for how the system should run it.
 /// # Methods
-/// - queue_size: The size of the queue to use for the component before it starts dropping messages
+/// - queue_size: The size of the queue to use for the component. Once it is full, senders wait
+/// until the component has handled a message, so a slow component is never sent more than this.
 /// - on_start: Called when the component is started
+/// - on_stop: Called when the component is stopped, after the message it was handling. The
+/// component is only joined once it returns.
 #[async_trait]
 pub(crate) trait Component: Send + Sized + Debug + 'static {
     fn get_name() -> &'static str;
@@ -38,6 +43,7 @@ pub(crate) trait Component: Send + Sized + Debug + 'static {
         ComponentRuntime::Inherit
     }
     async fn on_start(&mut self, _ctx: &ComponentContext<Self>) -> () {}
+    async fn on_stop(&mut self, _ctx: &ComponentContext<Self>) -> () {}
 }
 
 /// A handler is a component that can process messages of a given type.
@@ -116,10 +122,26 @@ impl<C: Component> ComponentHandle<C> {
         };
     }
 
+    /// Stops the component and waits for it to finish the message it is handling and its
+    /// `on_stop`, for at most `timeout`. Returns false if it didn't finish in time.
+    /// # Notes
+    /// Stop components before the components they depend on, a component that is
+    /// finishing its work may still need them.
+    pub(crate) async fn stop_and_join(&mut self, timeout: Duration) -> bool {
+        self.stop();
+        tokio::time::timeout(timeout, self.join()).await.is_ok()
+    }
+
     pub(crate) fn state(&self) -> &ComponentState {
         return &self.state;
     }
 
+    /// The number of messages waiting in the queue of the component. It stays at the queue
+    /// size while senders are held back by a component that can't keep up.
+    pub(crate) fn queue_depth(&self) -> usize {
+        queue_depth(&self.sender.sender)
+    }
+
     pub(crate) fn receiver<M>(&self) -> Box<dyn Receiver<M> + Send>
     where
         C: Handler<M>,
@@ -144,8 +166,10 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tracing::metrics::test::collected;
     use async_trait::async_trait;
     use futures::stream;
+    use opentelemetry::KeyValue;
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
 
@@ -210,4 +234,93 @@ mod tests {
         // Expect an error because the component is stopped
         assert!(res.is_err());
     }
+
+    #[derive(Debug)]
+    struct SlowComponent {
+        handled: Arc<parking_lot::Mutex<Vec<usize>>>,
+    }
+
+    #[async_trait]
+    impl Handler<usize> for SlowComponent {
+        async fn handle(&mut self, message: usize, _ctx: &ComponentContext<SlowComponent>) -> () {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            self.handled.lock().push(message);
+        }
+    }
+
+    #[async_trait]
+    impl Component for SlowComponent {
+        fn get_name() -> &'static str {
+            "Slow component"
+        }
+
+        fn queue_size(&self) -> usize {
+            4
+        }
+    }
+
+    #[tokio::test]
+    async fn it_finishes_in_flight_work_when_stopped() {
+        let system = System::new();
+        let handled = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let mut handle = system.start_component(SlowComponent {
+            handled: handled.clone(),
+        });
+        // One message is being handled when the component is stopped, the rest is queued.
+        for message in 0..3 {
+            handle.sender.send(message, None).await.unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        assert!(handle.stop_and_join(Duration::from_secs(1)).await);
+        assert_eq!(*handled.lock(), vec![0]);
+        assert!(handle.sender.send(3, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_on_work_that_takes_too_long() {
+        let system = System::new();
+        let handled = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let mut handle = system.start_component(SlowComponent {
+            handled: handled.clone(),
+        });
+        handle.sender.send(0, None).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        assert!(!handle.stop_and_join(Duration::from_micros(10)).await);
+        assert!(handled.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_holds_back_senders_of_a_slow_component() {
+        let component = [KeyValue::new("component", SlowComponent::get_name())];
+        let system = System::new();
+        let handled = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let mut handle = system.start_component(SlowComponent {
+            handled: handled.clone(),
+        });
+        let sender = handle.sender.clone();
+        let producer = tokio::spawn(async move {
+            for message in 0..50 {
+                sender.send(message, None).await.unwrap();
+            }
+        });
+
+        let gauge = || collected("component.queue_depth", &component);
+        let (mut max_depth, mut max_gauge) = (0, 0);
+        while !producer.is_finished() {
+            max_depth = max_depth.max(handle.queue_depth());
+            max_gauge = max_gauge.max(gauge());
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        // The producer was held back at the queue size, not buffered without bound.
+        assert_eq!(max_depth, 4);
+        assert_eq!(max_gauge, 4);
+        while handled.lock().len() < 50 {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        assert_eq!(handle.queue_depth(), 0);
+        assert_eq!(gauge(), 0);
+        // Nothing was dropped or reordered.
+        assert_eq!(*handled.lock(), (0..50).collect::<Vec<_>>());
+        handle.stop();
+    }
 }
@@ -0,0 +1,43 @@
+use crate::config::{ConfigValidator, ValidateConfig};
+use serde::Deserialize;
+
+const DEFAULT_EXPORT_INTERVAL_MS: u64 = 30000;
+
+fn default_export_interval_ms() -> u64 {
+    DEFAULT_EXPORT_INTERVAL_MS
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// The configuration for the metrics of the worker.
+/// # Fields
+/// - otlp_endpoint: The OTLP collector the metrics are exported to. Unset by default, in
+/// which case the metrics are not recorded at all.
+/// - export_interval_ms: How often the metrics are exported. Defaults to 30000.
+/// # Notes
+/// See config.rs in the root of the worker crate for an example of how to use
+/// config files to configure the worker.
+#[serde(deny_unknown_fields)]
+pub(crate) struct MetricsConfig {
+    #[serde(default)]
+    pub(crate) otlp_endpoint: Option<String>,
+    #[serde(default = "default_export_interval_ms")]
+    pub(crate) export_interval_ms: u64,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            otlp_endpoint: None,
+            export_interval_ms: DEFAULT_EXPORT_INTERVAL_MS,
+        }
+    }
+}
+
+impl ValidateConfig for MetricsConfig {
+    fn validate(&self, validator: &mut ConfigValidator) {
+        if let Some(otlp_endpoint) = &self.otlp_endpoint {
+            validator.non_empty("otlp_endpoint", otlp_endpoint);
+        }
+        validator.positive("export_interval_ms", self.export_interval_ms);
+    }
+}
@@ -0,0 +1,407 @@
+// The metrics of the worker are recorded with OpenTelemetry and exported to the OTLP
+// collector of the worker config. When no collector is configured, the global meter provider
+// stays the no-op one, and recording a metric does nothing.
+
+use super::config::MetricsConfig;
+use opentelemetry::metrics::{Counter, Histogram, Meter, Unit, UpDownCounter};
+use opentelemetry::sdk::export::metrics::aggregation::cumulative_temporality_selector;
+use opentelemetry::sdk::export::metrics::AggregatorSelector;
+use opentelemetry::sdk::metrics::aggregators::{self, Aggregator};
+use opentelemetry::sdk::metrics::controllers::BasicController;
+use opentelemetry::sdk::metrics::sdk_api::{Descriptor, InstrumentKind};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+const METER_NAME: &str = "chroma-worker";
+
+// The buckets of the histograms of durations, in seconds, and of the other histograms.
+const DURATION_BOUNDARIES: [f64; 14] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const COUNT_BOUNDARIES: [f64; 12] = [
+    1.0, 10.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 50000.0, 100000.0,
+];
+
+// Histograms of seconds are bucketed as durations, and every other histogram as a count.
+#[derive(Debug)]
+struct WorkerAggregatorSelector;
+
+impl AggregatorSelector for WorkerAggregatorSelector {
+    fn aggregator_for(&self, descriptor: &Descriptor) -> Option<Arc<dyn Aggregator + Send + Sync>> {
+        match descriptor.instrument_kind() {
+            InstrumentKind::GaugeObserver => Some(Arc::new(aggregators::last_value())),
+            InstrumentKind::Histogram if descriptor.unit() == Some("s") => {
+                Some(Arc::new(aggregators::histogram(&DURATION_BOUNDARIES)))
+            }
+            InstrumentKind::Histogram => Some(Arc::new(aggregators::histogram(&COUNT_BOUNDARIES))),
+            _ => Some(Arc::new(aggregators::sum())),
+        }
+    }
+}
+
+/// Starts exporting the metrics of the worker to the OTLP collector of `config`.
+/// # Returns
+/// The controller that exports them, which should be stopped on shutdown to export the
+/// last of them, or None if no collector is configured or the exporter can't be started.
+/// # Notes
+/// Must be called before the first metric is recorded, since the instruments bind to the
+/// meter provider that is installed when they are created.
+pub(crate) fn init_otel_metrics(
+    service_name: &str,
+    config: &MetricsConfig,
+) -> Option<BasicController> {
+    let otlp_endpoint = config.otlp_endpoint.as_ref()?;
+    println!(
+        "Exporting metrics for {} to endpoint {}",
+        service_name, otlp_endpoint
+    );
+    let resource = opentelemetry::sdk::Resource::new(vec![KeyValue::new(
+        "service.name",
+        service_name.to_string(),
+    )]);
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(otlp_endpoint);
+    // The pipeline installs the controller as the global meter provider.
+    match opentelemetry_otlp::new_pipeline()
+        .metrics(
+            WorkerAggregatorSelector,
+            cumulative_temporality_selector(),
+            opentelemetry::runtime::Tokio,
+        )
+        .with_exporter(exporter)
+        .with_period(Duration::from_millis(config.export_interval_ms))
+        .with_resource(resource)
+        .build()
+    {
+        Ok(controller) => Some(controller),
+        Err(e) => {
+            // TODO: switch to logging when logging is implemented
+            println!("Failed to start exporting metrics: {}", e);
+            None
+        }
+    }
+}
+
+/// Reports the number of messages waiting in the queue of a component, or None once the
+/// queue is gone.
+pub(crate) type QueueDepthProbe = Box<dyn Fn() -> Option<usize> + Send + Sync>;
+
+/// The instruments of the hot paths of the query and compaction services.
+/// # Notes
+/// Only the collection id of compactions is recorded. Queries are far more frequent and
+/// their collections too many for a label.
+pub(crate) struct WorkerMetrics {
+    operator_duration: Histogram<f64>,
+    query_duration: Histogram<f64>,
+    compaction_duration: Histogram<f64>,
+    hnsw_search_duration: Histogram<f64>,
+    hnsw_search_k: Histogram<u64>,
+    hnsw_search_ef: Histogram<u64>,
+    brute_force_candidates: Histogram<u64>,
+    block_reads: Counter<u64>,
+    sysdb_request_duration: Histogram<f64>,
+    sysdb_request_errors: Counter<u64>,
+    index_cache_hits: Counter<u64>,
+    index_cache_misses: Counter<u64>,
+    index_cache_evictions: Counter<u64>,
+    index_cache_size: UpDownCounter<i64>,
+    // The queues of the running components by component name, observed by the
+    // `component.queue_depth` gauge when the metrics are collected.
+    component_queues: Arc<Mutex<HashMap<&'static str, Vec<QueueDepthProbe>>>>,
+}
+
+impl WorkerMetrics {
+    fn new(meter: &Meter) -> Self {
+        let duration = |name: &str, description: &str| {
+            meter
+                .f64_histogram(name.to_string())
+                .with_description(description)
+                .with_unit(Unit::new("s"))
+                .init()
+        };
+        let histogram = |name: &str, description: &str| {
+            meter
+                .u64_histogram(name.to_string())
+                .with_description(description)
+                .init()
+        };
+        let counter = |name: &str, description: &str| {
+            meter
+                .u64_counter(name.to_string())
+                .with_description(description)
+                .init()
+        };
+        let component_queues: Arc<Mutex<HashMap<&'static str, Vec<QueueDepthProbe>>>> =
+            Arc::default();
+        let queue_depth = meter
+            .u64_observable_gauge("component.queue_depth")
+            .with_description("The messages waiting in the queues of components")
+            .init();
+        let queues = component_queues.clone();
+        let registered = meter.register_callback(move |cx| {
+            for (component, probes) in queues.lock().iter_mut() {
+                let mut depth = 0;
+                probes.retain(|probe| match probe() {
+                    Some(probe_depth) => {
+                        depth += probe_depth as u64;
+                        true
+                    }
+                    None => false,
+                });
+                // Components that are no longer running are reported with an empty queue.
+                queue_depth.observe(cx, depth, &[KeyValue::new("component", *component)]);
+            }
+        });
+        if let Err(e) = registered {
+            // TODO: switch to logging when logging is implemented
+            println!("Failed to register the component queue depth gauge: {}", e);
+        }
+        WorkerMetrics {
+            operator_duration: duration("operator.duration", "How long operators run"),
+            query_duration: duration("query.duration", "How long queries take to answer"),
+            compaction_duration: duration("compaction.duration", "How long compactions take"),
+            hnsw_search_duration: duration(
+                "hnsw.search.duration",
+                "How long searches of HNSW indices take",
+            ),
+            hnsw_search_k: histogram("hnsw.search.k", "The k of searches of HNSW indices"),
+            hnsw_search_ef: histogram("hnsw.search.ef", "The ef of searches of HNSW indices"),
+            brute_force_candidates: histogram(
+                "brute_force.candidates",
+                "The number of records scored by brute force searches",
+            ),
+            block_reads: counter("blockstore.block_reads", "The blocks read by blockfiles"),
+            sysdb_request_duration: duration(
+                "sysdb.request.duration",
+                "How long requests to sysdb take, retries included",
+            ),
+            sysdb_request_errors: counter("sysdb.request.errors", "The requests sysdb failed"),
+            index_cache_hits: counter("index_cache.hits", "The HNSW indices opened from the cache"),
+            index_cache_misses: counter(
+                "index_cache.misses",
+                "The HNSW indices loaded from storage when opened",
+            ),
+            index_cache_evictions: counter(
+                "index_cache.evictions",
+                "The HNSW indices evicted from the cache",
+            ),
+            index_cache_size: meter
+                .i64_up_down_counter("index_cache.size")
+                .with_description("The estimated size of the cached HNSW indices")
+                .with_unit(Unit::new("By"))
+                .init(),
+            component_queues,
+        }
+    }
+
+    /// Reports the queue of a running `component` in the `component.queue_depth` gauge,
+    /// summed with the queues of the other running components of the same name.
+    /// # Notes
+    /// The probes of stopped components are dropped here as well as when the gauge is
+    /// observed, since it never is when no collector is configured.
+    pub(crate) fn watch_component_queue(&self, component: &'static str, probe: QueueDepthProbe) {
+        let mut queues = self.component_queues.lock();
+        let probes = queues.entry(component).or_default();
+        probes.retain(|probe| probe().is_some());
+        probes.push(probe);
+    }
+
+    /// Records a run of an operator, the stages queries and compactions are made of.
+    pub(crate) fn record_operator(&self, operator: &'static str, elapsed: Duration) {
+        self.operator_duration.record(
+            &Context::current(),
+            elapsed.as_secs_f64(),
+            &[KeyValue::new("operator", operator)],
+        );
+    }
+
+    /// Records a query answered by the gRPC `method`.
+    pub(crate) fn record_query(&self, method: &'static str, succeeded: bool, elapsed: Duration) {
+        self.query_duration.record(
+            &Context::current(),
+            elapsed.as_secs_f64(),
+            &[
+                KeyValue::new("method", method),
+                KeyValue::new("succeeded", succeeded),
+            ],
+        );
+    }
+
+    pub(crate) fn record_compaction(
+        &self,
+        collection_id: Uuid,
+        succeeded: bool,
+        elapsed: Duration,
+    ) {
+        self.compaction_duration.record(
+            &Context::current(),
+            elapsed.as_secs_f64(),
+            &[
+                KeyValue::new("collection_id", collection_id.to_string()),
+                KeyValue::new("succeeded", succeeded),
+            ],
+        );
+    }
+
+    pub(crate) fn record_hnsw_search(&self, k: usize, ef: usize, elapsed: Duration) {
+        let cx = Context::current();
+        self.hnsw_search_duration
+            .record(&cx, elapsed.as_secs_f64(), &[]);
+        self.hnsw_search_k.record(&cx, k as u64, &[]);
+        self.hnsw_search_ef.record(&cx, ef as u64, &[]);
+    }
+
+    pub(crate) fn record_brute_force_candidates(&self, candidates: usize) {
+        self.brute_force_candidates
+            .record(&Context::current(), candidates as u64, &[]);
+    }
+
+    /// Records a block read from `source`: the cache, storage, or a memory-mapped file.
+    pub(crate) fn record_block_read(&self, source: &'static str) {
+        self.block_reads
+            .add(&Context::current(), 1, &[KeyValue::new("source", source)]);
+    }
+
+    pub(crate) fn record_sysdb_request(
+        &self,
+        method: &'static str,
+        succeeded: bool,
+        elapsed: Duration,
+    ) {
+        let cx = Context::current();
+        let attributes = [KeyValue::new("method", method)];
+        self.sysdb_request_duration
+            .record(&cx, elapsed.as_secs_f64(), &attributes);
+        if !succeeded {
+            self.sysdb_request_errors.add(&cx, 1, &attributes);
+        }
+    }
+
+    pub(crate) fn record_index_cache_hit(&self) {
+        self.index_cache_hits.add(&Context::current(), 1, &[]);
+    }
+
+    pub(crate) fn record_index_cache_miss(&self) {
+        self.index_cache_misses.add(&Context::current(), 1, &[]);
+    }
+
+    /// Records an index evicted from the cache, either to stay within its memory budget or
+    /// because another index replaced it.
+    pub(crate) fn record_index_cache_eviction(&self, reason: &'static str) {
+        self.index_cache_evictions
+            .add(&Context::current(), 1, &[KeyValue::new("reason", reason)]);
+    }
+
+    /// Records indices of `bytes` in total added to the cache, or removed if negative.
+    pub(crate) fn record_index_cache_size_change(&self, bytes: i64) {
+        self.index_cache_size.add(&Context::current(), bytes, &[]);
+    }
+}
+
+/// The instruments of the worker, created on first use from the global meter provider.
+pub(crate) fn metrics() -> &'static WorkerMetrics {
+    static METRICS: OnceLock<WorkerMetrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        #[cfg(test)]
+        test::controller();
+        WorkerMetrics::new(&global::meter(METER_NAME))
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use opentelemetry::sdk::export::metrics::aggregation::LastValue;
+    use opentelemetry::sdk::export::metrics::aggregation::{Count, Sum};
+    use opentelemetry::sdk::export::metrics::InstrumentationLibraryReader;
+    use opentelemetry::sdk::metrics::aggregators::{
+        HistogramAggregator, LastValueAggregator, SumAggregator,
+    };
+    use opentelemetry::sdk::metrics::{controllers, processors};
+
+    // Tests record into a controller that is only read when a test collects it, instead of
+    // exporting to a collector.
+    pub(super) fn controller() -> &'static BasicController {
+        static CONTROLLER: OnceLock<BasicController> = OnceLock::new();
+        CONTROLLER.get_or_init(|| {
+            let controller = controllers::basic(processors::factory(
+                WorkerAggregatorSelector,
+                cumulative_temporality_selector(),
+            ))
+            .with_collect_period(Duration::ZERO)
+            .build();
+            global::set_meter_provider(controller.clone());
+            controller
+        })
+    }
+
+    /// The value of a counter or gauge, or the number of values recorded by a histogram,
+    /// summed over the recordings that have every one of `attributes`.
+    /// # Notes
+    /// Tests run concurrently and record into the same instruments, so tests should compare
+    /// values before and after what they exercise, with attributes only they record.
+    pub(crate) fn collected(name: &str, attributes: &[KeyValue]) -> u64 {
+        metrics();
+        let controller = controller();
+        controller.collect(&Context::current()).unwrap();
+        let mut total = 0;
+        controller
+            .try_for_each(&mut |_, reader| {
+                reader.try_for_each(&cumulative_temporality_selector(), &mut |record| {
+                    let descriptor = record.descriptor();
+                    let matches = attributes.iter().all(|attribute| {
+                        record
+                            .attributes()
+                            .iter()
+                            .any(|(key, value)| *key == attribute.key && *value == attribute.value)
+                    });
+                    if descriptor.name() != name || !matches {
+                        return Ok(());
+                    }
+                    let aggregator = match record.aggregator() {
+                        Some(aggregator) => aggregator.as_any(),
+                        None => return Ok(()),
+                    };
+                    if let Some(sum) = aggregator.downcast_ref::<SumAggregator>() {
+                        total += sum.sum()?.to_u64(descriptor.number_kind());
+                    } else if let Some(histogram) = aggregator.downcast_ref::<HistogramAggregator>()
+                    {
+                        total += histogram.count()?;
+                    } else if let Some(gauge) = aggregator.downcast_ref::<LastValueAggregator>() {
+                        total += gauge.last_value()?.0.to_u64(descriptor.number_kind());
+                    }
+                    Ok(())
+                })
+            })
+            .unwrap();
+        total
+    }
+
+    #[test]
+    fn test_metrics_are_collected() {
+        let method = KeyValue::new("method", "test_metrics_are_collected");
+        let errors = collected("sysdb.request.errors", &[method.clone()]);
+        let requests = collected("sysdb.request.duration", &[method.clone()]);
+        metrics().record_sysdb_request("test_metrics_are_collected", true, Duration::ZERO);
+        metrics().record_sysdb_request("test_metrics_are_collected", false, Duration::ZERO);
+        assert_eq!(
+            collected("sysdb.request.errors", &[method.clone()]),
+            errors + 1
+        );
+        assert_eq!(collected("sysdb.request.duration", &[method]), requests + 2);
+
+        let collection_id = Uuid::new_v4();
+        metrics().record_compaction(collection_id, true, Duration::from_millis(3));
+        let labels = [
+            KeyValue::new("collection_id", collection_id.to_string()),
+            KeyValue::new("succeeded", true),
+        ];
+        assert_eq!(collected("compaction.duration", &labels), 1);
+    }
+}
@@ -1,2 +1,4 @@
+pub(crate) mod config;
+pub(crate) mod metrics;
 pub(crate) mod opentelemetry_config;
 pub(crate) mod util;
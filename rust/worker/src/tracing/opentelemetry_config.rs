@@ -5,34 +5,38 @@ use opentelemetry_otlp::WithExportConfig;
 use tracing_bunyan_formatter::BunyanFormattingLayer;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Layer};
 
-pub(crate) fn init_otel_tracing(service_name: &String, otel_endpoint: &String) {
-    println!(
-        "Registering jaeger subscriber for {} at endpoint {}",
-        service_name, otel_endpoint
-    );
-    let resource = opentelemetry::sdk::Resource::new(vec![opentelemetry::KeyValue::new(
-        "service.name",
-        service_name.clone(),
-    )]);
-    // Prepare trace config.
-    let trace_config = trace::config()
-        .with_sampler(opentelemetry::sdk::trace::Sampler::AlwaysOn)
-        .with_resource(resource);
-    // Prepare exporter.
-    let exporter = opentelemetry_otlp::new_exporter()
-        .tonic()
-        .with_endpoint(otel_endpoint);
-    let otlp_tracer = opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(exporter)
-        .with_trace_config(trace_config)
-        .install_batch(opentelemetry::runtime::Tokio)
-        .expect("Error - Failed to create tracer.");
+/// Installs the subscriber of the spans of the worker, which prints them and, if
+/// `otel_endpoint` is set, exports them to that OTLP collector.
+pub(crate) fn init_otel_tracing(service_name: &String, otel_endpoint: Option<&str>) {
     // Layer for adding our configured tracer.
     // Export everything at this layer. The backend i.e. honeycomb or jaeger will filter at its end.
-    let exporter_layer = tracing_opentelemetry::layer()
-        .with_tracer(otlp_tracer)
-        .with_filter(tracing_subscriber::filter::LevelFilter::TRACE);
+    let exporter_layer = otel_endpoint.map(|otel_endpoint| {
+        println!(
+            "Registering jaeger subscriber for {} at endpoint {}",
+            service_name, otel_endpoint
+        );
+        let resource = opentelemetry::sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+            "service.name",
+            service_name.clone(),
+        )]);
+        // Prepare trace config.
+        let trace_config = trace::config()
+            .with_sampler(opentelemetry::sdk::trace::Sampler::AlwaysOn)
+            .with_resource(resource);
+        // Prepare exporter.
+        let exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(otel_endpoint);
+        let otlp_tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(exporter)
+            .with_trace_config(trace_config)
+            .install_batch(opentelemetry::runtime::Tokio)
+            .expect("Error - Failed to create tracer.");
+        tracing_opentelemetry::layer()
+            .with_tracer(otlp_tracer)
+            .with_filter(tracing_subscriber::filter::LevelFilter::TRACE)
+    });
     // Layer for printing spans to stdout. Only print INFO logs by default.
     let stdout_layer =
         BunyanFormattingLayer::new(service_name.clone().to_string(), std::io::stdout)
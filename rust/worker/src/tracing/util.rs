@@ -1,8 +1,10 @@
 use std::str::FromStr;
 
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
 use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
 use tonic::{
-    metadata::{MetadataMap, MetadataValue},
+    metadata::{KeyRef, MetadataMap, MetadataValue},
     Request, Status,
 };
 use tracing::Span;
@@ -86,10 +88,39 @@ pub(crate) fn try_parse_tracecontext(metadata: &MetadataMap) -> (Option<TraceId>
     (traceid, spanid)
 }
 
+// Reads the W3C trace context headers out of gRPC metadata.
+struct MetadataExtractor<'a>(&'a MetadataMap);
+
+impl Extractor for MetadataExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .filter_map(|key| match key {
+                KeyRef::Ascii(key) => Some(key.as_str()),
+                KeyRef::Binary(_) => None,
+            })
+            .collect()
+    }
+}
+
+/// Makes the span of a request a child of the span the caller sent along with the request,
+/// so that the spans of the worker join the trace of the request.
+/// # Notes
+/// The W3C `traceparent` header is used if it is valid. The chroma headers are only read
+/// when it is missing, for callers that don't send it yet.
 pub(crate) fn wrap_span_with_parent_context(
     request_span: tracing::Span,
     metadata: &MetadataMap,
 ) -> tracing::Span {
+    let context = TraceContextPropagator::new().extract(&MetadataExtractor(metadata));
+    if context.span().span_context().is_valid() {
+        request_span.set_parent(context);
+        return request_span;
+    }
     let (traceid, spanid) = try_parse_tracecontext(metadata);
     // Attach context passed by FE as parent.
     if traceid.is_some() && spanid.is_some() {
@@ -108,3 +139,70 @@ pub(crate) fn wrap_span_with_parent_context(
     }
     request_span
 }
+
+/// Installs a subscriber that gives spans OpenTelemetry trace ids without exporting them,
+/// once for all tests.
+#[cfg(test)]
+pub(crate) fn init_test_tracing() {
+    use opentelemetry::trace::TracerProvider;
+    use std::sync::Once;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        // Tracers only hold on to their provider weakly.
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let tracer = provider.tracer("worker-test");
+        opentelemetry::global::set_tracer_provider(provider);
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        tracing::subscriber::set_global_default(subscriber)
+            .expect("Set global default subscriber failed");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace_id(span: &Span) -> TraceId {
+        span.context().span().span_context().trace_id()
+    }
+
+    #[test]
+    fn test_request_spans_join_the_trace_of_the_caller() {
+        init_test_tracing();
+        let traceparent_trace_id = "0af7651916cd43dd8448eb211c80319c";
+        let chroma_trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert(TRACE_ID_HEADER_KEY, chroma_trace_id.parse().unwrap());
+        metadata.insert(SPAN_ID_HEADER_KEY, "00f067aa0ba902b7".parse().unwrap());
+        let span = wrap_span_with_parent_context(tracing::trace_span!("Request"), &metadata);
+        assert_eq!(trace_id(&span), TraceId::from_hex(chroma_trace_id).unwrap());
+
+        // The W3C header is preferred over the chroma headers.
+        metadata.insert(
+            "traceparent",
+            format!("00-{}-b7ad6b7169203331-01", traceparent_trace_id)
+                .parse()
+                .unwrap(),
+        );
+        let span = wrap_span_with_parent_context(tracing::trace_span!("Request"), &metadata);
+        assert_eq!(
+            trace_id(&span),
+            TraceId::from_hex(traceparent_trace_id).unwrap()
+        );
+        let child = span.in_scope(|| tracing::trace_span!("Child"));
+        assert_eq!(trace_id(&child), trace_id(&span));
+
+        // Without a caller, the request starts a trace of its own.
+        let span =
+            wrap_span_with_parent_context(tracing::trace_span!("Request"), &MetadataMap::new());
+        assert_ne!(trace_id(&span), TraceId::INVALID);
+        assert_ne!(
+            trace_id(&span),
+            TraceId::from_hex(traceparent_trace_id).unwrap()
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Metadata, MetadataValueConversionError};
+use super::{Metadata, MetadataValue, MetadataValueConversionError};
 use crate::{
     chroma_proto,
     errors::{ChromaError, ErrorCodes},
@@ -18,6 +18,33 @@ pub(crate) struct Collection {
     pub(crate) version: i32,
 }
 
+/// The collection metadata key declaring how embeddings are encoded.
+pub(crate) const EMBEDDING_ENCODING_KEY: &str = "embedding:encoding";
+
+impl Collection {
+    /// Whether the collection metadata declares that embeddings are binarized, one bit per
+    /// dimension. Such collections are scored by Hamming distance over the packed bits.
+    pub(crate) fn has_binary_embeddings(&self) -> bool {
+        self.has_embedding_encoding("binary")
+    }
+
+    /// Whether the collection metadata declares that embeddings are sparse vectors. Such
+    /// collections are scored by Jaccard distance, and only ever by brute force.
+    pub(crate) fn has_sparse_embeddings(&self) -> bool {
+        self.has_embedding_encoding("sparse")
+    }
+
+    fn has_embedding_encoding(&self, expected: &str) -> bool {
+        match &self.metadata {
+            Some(metadata) => matches!(
+                metadata.get(EMBEDDING_ENCODING_KEY),
+                Some(MetadataValue::Str(encoding)) if encoding == expected
+            ),
+            None => false,
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub(crate) enum CollectionConversionError {
     #[error("Invalid UUID")]
@@ -87,4 +114,40 @@ mod test {
         assert_eq!(converted_collection.tenant, "baz".to_string());
         assert_eq!(converted_collection.database, "qux".to_string());
     }
+
+    #[test]
+    fn test_collection_embedding_encoding() {
+        let mut collection = Collection {
+            id: Uuid::nil(),
+            name: "foo".to_string(),
+            metadata: None,
+            dimension: Some(128),
+            tenant: "baz".to_string(),
+            database: "qux".to_string(),
+            log_position: 0,
+            version: 0,
+        };
+        assert!(!collection.has_binary_embeddings());
+        let mut metadata = Metadata::new();
+        metadata.insert(
+            EMBEDDING_ENCODING_KEY.to_string(),
+            MetadataValue::Str("float32".to_string()),
+        );
+        collection.metadata = Some(metadata.clone());
+        assert!(!collection.has_binary_embeddings());
+        metadata.insert(
+            EMBEDDING_ENCODING_KEY.to_string(),
+            MetadataValue::Str("binary".to_string()),
+        );
+        collection.metadata = Some(metadata.clone());
+        assert!(collection.has_binary_embeddings());
+        assert!(!collection.has_sparse_embeddings());
+        metadata.insert(
+            EMBEDDING_ENCODING_KEY.to_string(),
+            MetadataValue::Str("sparse".to_string()),
+        );
+        collection.metadata = Some(metadata);
+        assert!(collection.has_sparse_embeddings());
+        assert!(!collection.has_binary_embeddings());
+    }
 }
@@ -1,18 +1,25 @@
 use super::{
-    ConversionError, Operation, OperationConversionError, ScalarEncoding,
+    ConversionError, Metadata, Operation, OperationConversionError, ScalarEncoding,
     ScalarEncodingConversionError, UpdateMetadata, UpdateMetadataValue,
     UpdateMetadataValueConversionError,
 };
 use crate::{
     chroma_proto,
+    distance::{
+        sparse::{SparseVector, SparseVectorError},
+        VectorSlice,
+    },
     errors::{ChromaError, ErrorCodes},
 };
+use half::f16;
 use thiserror::Error;
 
 #[derive(Clone, Debug)]
 pub(crate) struct OperationRecord {
     pub(crate) id: String,
-    pub(crate) embedding: Option<Vec<f32>>, // NOTE: we only support float32 embeddings for now so this ignores the encoding
+    // The embedding in the representation it was written with, see `Embedding`.
+    pub(crate) embedding: Option<Embedding>,
+    // The encoding the embedding was written with.
     pub(crate) encoding: Option<ScalarEncoding>,
     pub(crate) metadata: Option<UpdateMetadata>,
     // Document is implemented in the python code as a special key "chroma:document" in the metadata
@@ -22,6 +29,44 @@ pub(crate) struct OperationRecord {
     pub(crate) operation: Operation,
 }
 
+/// An embedding as it was written to the log.
+/// # Variants
+/// - `F32` - Float32 embeddings, and those of the encodings float32s represent exactly: int32
+///   and sparse.
+/// - `F16` - Float16 embeddings, as the raw bits of their components. They are scored without
+///   being dequantized, and only widened to float32s when they are written to a segment.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Embedding {
+    F32(Vec<f32>),
+    F16(Vec<u16>),
+}
+
+impl Embedding {
+    pub(crate) fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    pub(crate) fn as_slice(&self) -> VectorSlice<'_> {
+        match self {
+            Embedding::F32(values) => VectorSlice::F32(values),
+            Embedding::F16(values) => VectorSlice::F16(values),
+        }
+    }
+
+    pub(crate) fn into_f32(self) -> Vec<f32> {
+        match self {
+            Embedding::F32(values) => values,
+            Embedding::F16(values) => VectorSlice::F16(&values).to_f32().into_owned(),
+        }
+    }
+}
+
+impl From<Vec<f32>> for Embedding {
+    fn from(values: Vec<f32>) -> Self {
+        Embedding::F32(values)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct LogRecord {
     pub(crate) log_offset: i64,
@@ -32,6 +77,8 @@ pub(crate) struct LogRecord {
 pub(crate) enum RecordConversionError {
     #[error("Invalid UUID")]
     InvalidUuid,
+    #[error("Log record without an operation record")]
+    MissingRecord,
     #[error(transparent)]
     DecodeError(#[from] ConversionError),
     #[error(transparent)]
@@ -40,16 +87,29 @@ pub(crate) enum RecordConversionError {
     ScalarEncodingConversionError(#[from] ScalarEncodingConversionError),
     #[error(transparent)]
     UpdateMetadataValueConversionError(#[from] UpdateMetadataValueConversionError),
-    #[error(transparent)]
-    VectorConversionError(#[from] VectorConversionError),
+    #[error("Invalid embedding for record {id}: {source}")]
+    VectorConversionError {
+        id: String,
+        source: VectorConversionError,
+    },
+    #[error(
+        "Embedding of dimension {got} for record {id}, the collection has dimension {expected}"
+    )]
+    DimensionMismatch {
+        id: String,
+        expected: usize,
+        got: usize,
+    },
 }
 
 impl_base_convert_error!(RecordConversionError, {
     RecordConversionError::InvalidUuid => ErrorCodes::InvalidArgument,
+    RecordConversionError::MissingRecord => ErrorCodes::InvalidArgument,
     RecordConversionError::OperationConversionError(inner) => inner.code(),
     RecordConversionError::ScalarEncodingConversionError(inner) => inner.code(),
     RecordConversionError::UpdateMetadataValueConversionError(inner) => inner.code(),
-    RecordConversionError::VectorConversionError(inner) => inner.code(),
+    RecordConversionError::VectorConversionError { source, .. } => source.code(),
+    RecordConversionError::DimensionMismatch { .. } => ErrorCodes::InvalidArgument,
 });
 
 impl TryFrom<chroma_proto::OperationRecord> for OperationRecord {
@@ -66,7 +126,12 @@ impl TryFrom<chroma_proto::OperationRecord> for OperationRecord {
         let (embedding, encoding) = match operation_record_proto.vector {
             Some(proto_vector) => match proto_vector.try_into() {
                 Ok((embedding, encoding)) => (Some(embedding), Some(encoding)),
-                Err(e) => return Err(RecordConversionError::VectorConversionError(e)),
+                Err(e) => {
+                    return Err(RecordConversionError::VectorConversionError {
+                        id: operation_record_proto.id,
+                        source: e,
+                    })
+                }
             },
             // If there is no vector, there is no encoding
             None => (None, None),
@@ -99,17 +164,30 @@ impl TryFrom<chroma_proto::OperationRecord> for OperationRecord {
     }
 }
 
+impl OperationRecord {
+    /// Checks that the embedding of the record, if it has one, has the dimension of its
+    /// collection.
+    pub(crate) fn check_dimension(&self, dimension: usize) -> Result<(), RecordConversionError> {
+        match &self.embedding {
+            Some(embedding) if embedding.len() != dimension => {
+                Err(RecordConversionError::DimensionMismatch {
+                    id: self.id.clone(),
+                    expected: dimension,
+                    got: embedding.len(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 impl TryFrom<chroma_proto::LogRecord> for LogRecord {
     type Error = RecordConversionError;
 
     fn try_from(log_record_proto: chroma_proto::LogRecord) -> Result<Self, Self::Error> {
         let record = match log_record_proto.record {
             Some(proto_record) => OperationRecord::try_from(proto_record)?,
-            None => {
-                return Err(RecordConversionError::DecodeError(
-                    ConversionError::DecodeError,
-                ))
-            }
+            None => return Err(RecordConversionError::MissingRecord),
         };
         Ok(LogRecord {
             log_offset: log_record_proto.log_offset,
@@ -123,7 +201,7 @@ impl TryFrom<chroma_proto::LogRecord> for LogRecord {
 Vector
 ===========================================
 */
-impl TryFrom<chroma_proto::Vector> for (Vec<f32>, ScalarEncoding) {
+impl TryFrom<chroma_proto::Vector> for (Embedding, ScalarEncoding) {
     type Error = VectorConversionError;
 
     fn try_from(proto_vector: chroma_proto::Vector) -> Result<Self, Self::Error> {
@@ -132,80 +210,180 @@ impl TryFrom<chroma_proto::Vector> for (Vec<f32>, ScalarEncoding) {
             Err(e) => return Err(VectorConversionError::ScalarEncodingConversionError(e)),
         };
 
-        if out_encoding != ScalarEncoding::FLOAT32 {
-            // We only support float32 embeddings for now
-            return Err(VectorConversionError::UnsupportedEncoding);
+        let out_vector = match out_encoding {
+            ScalarEncoding::FLOAT32 => {
+                Embedding::F32(decode_components(&proto_vector.vector, f32::from_le_bytes)?)
+            }
+            ScalarEncoding::INT32 => {
+                Embedding::F32(decode_components(&proto_vector.vector, |bytes| {
+                    i32::from_le_bytes(bytes) as f32
+                })?)
+            }
+            ScalarEncoding::FLOAT16 => {
+                Embedding::F16(decode_components(&proto_vector.vector, u16::from_le_bytes)?)
+            }
+            // The dimension of a sparse vector is its number of components, which decoding
+            // it checks, and its weights are checked to be finite.
+            ScalarEncoding::SPARSE => {
+                let sparse = decode_sparse(&proto_vector.vector, proto_vector.dimension)?;
+                return Ok((Embedding::F32(sparse.to_embedding()), out_encoding));
+            }
+        };
+        if out_vector.len() != proto_vector.dimension as usize {
+            return Err(VectorConversionError::DimensionMismatch {
+                dimension: proto_vector.dimension,
+                byte_length: proto_vector.vector.len(),
+            });
         }
-
-        let out_vector = vec_to_f32(&proto_vector.vector);
-        match (out_vector, out_encoding) {
-            (Ok(vector), encoding) => Ok((vector.to_vec(), encoding)),
-            _ => Err(VectorConversionError::DecodeError(
-                ConversionError::DecodeError,
-            )),
+        // A NaN or infinite component makes every distance to the vector NaN or infinite,
+        // which can neither be ranked nor indexed.
+        let non_finite = match &out_vector {
+            Embedding::F32(values) => values.iter().position(|x| !x.is_finite()),
+            Embedding::F16(values) => values.iter().position(|x| !f16::from_bits(*x).is_finite()),
+        };
+        if let Some(index) = non_finite {
+            return Err(VectorConversionError::NonFiniteComponent { index });
         }
+        Ok((out_vector, out_encoding))
     }
 }
 
 #[derive(Error, Debug)]
 pub(crate) enum VectorConversionError {
-    #[error("Invalid byte length, must be divisible by 4")]
+    #[error("Invalid byte length, must be a multiple of the size of the encoding")]
     InvalidByteLength,
     #[error(transparent)]
     ScalarEncodingConversionError(#[from] ScalarEncodingConversionError),
-    #[error("Unsupported encoding")]
-    UnsupportedEncoding,
+    #[error("Invalid byte length {byte_length} for a vector of dimension {dimension}")]
+    DimensionMismatch { dimension: i32, byte_length: usize },
+    #[error("Component {index} can't be encoded as {encoding:?}")]
+    UnrepresentableComponent {
+        index: usize,
+        encoding: ScalarEncoding,
+    },
+    #[error("Component {index} is NaN or infinite")]
+    NonFiniteComponent { index: usize },
+    #[error(transparent)]
+    InvalidSparseVector(#[from] SparseVectorError),
     #[error(transparent)]
     DecodeError(#[from] ConversionError),
 }
 
 impl_base_convert_error!(VectorConversionError, {
     VectorConversionError::InvalidByteLength => ErrorCodes::InvalidArgument,
-    VectorConversionError::UnsupportedEncoding => ErrorCodes::InvalidArgument,
+    VectorConversionError::DimensionMismatch { .. } => ErrorCodes::InvalidArgument,
+    VectorConversionError::UnrepresentableComponent { .. } => ErrorCodes::InvalidArgument,
+    VectorConversionError::NonFiniteComponent { .. } => ErrorCodes::InvalidArgument,
     VectorConversionError::ScalarEncodingConversionError(inner) => inner.code(),
+    VectorConversionError::InvalidSparseVector(inner) => inner.code(),
 });
 
-/// Converts a vector of bytes to a vector of f32s
-/// # WARNING
-/// - This will only work if the machine is little endian since protobufs are little endian
-/// - TODO: convert to big endian if the machine is big endian
+/// Converts a vector of bytes to a vector of components, `N` bytes per component.
 /// # Notes
-/// This method internally uses unsafe code to convert the bytes to f32s
-fn vec_to_f32(bytes: &[u8]) -> Result<&[f32], VectorConversionError> {
-    // Transmutes a vector of bytes into vector of f32s
-
-    if bytes.len() % 4 != 0 {
+/// The components are little endian, as they are written by the clients. They are decoded
+/// the same way on machines of either endianness and whatever the alignment of the bytes.
+fn decode_components<const N: usize, T>(
+    bytes: &[u8],
+    decode: impl Fn([u8; N]) -> T,
+) -> Result<Vec<T>, VectorConversionError> {
+    let chunks = bytes.chunks_exact(N);
+    if !chunks.remainder().is_empty() {
         return Err(VectorConversionError::InvalidByteLength);
     }
+    Ok(chunks
+        .map(|chunk| decode(chunk.try_into().expect("Chunks have exactly N bytes")))
+        .collect())
+}
 
-    unsafe {
-        let (pre, mid, post) = bytes.align_to::<f32>();
-        if pre.len() != 0 || post.len() != 0 {
-            return Err(VectorConversionError::InvalidByteLength);
-        }
-        return Ok(mid);
+/// Decodes the payload of a sparse vector with `len` components, as described on the SPARSE
+/// scalar encoding. The payload has weights when it is twice as long as the indices alone.
+fn decode_sparse(bytes: &[u8], len: i32) -> Result<SparseVector, VectorConversionError> {
+    let mismatch = || VectorConversionError::DimensionMismatch {
+        dimension: len,
+        byte_length: bytes.len(),
+    };
+    let len = usize::try_from(len).map_err(|_| mismatch())?;
+    if bytes.len() != 4 * len && bytes.len() != 8 * len {
+        return Err(mismatch());
     }
+    let (indices, weights) = bytes.split_at(4 * len);
+    let indices = indices
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().expect("Chunks have exactly 4 bytes")))
+        .collect();
+    let weights = match weights.is_empty() {
+        true => None,
+        false => Some(decode_components(weights, f32::from_le_bytes)?),
+    };
+    Ok(SparseVector::new(indices, weights)?)
 }
 
-fn f32_to_vec(vector: &[f32]) -> Vec<u8> {
-    unsafe {
-        std::slice::from_raw_parts(
-            vector.as_ptr() as *const u8,
-            vector.len() * std::mem::size_of::<f32>(),
-        )
+fn encode_sparse(sparse: &SparseVector) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(8 * sparse.len());
+    for index in sparse.indices() {
+        bytes.extend(index.to_le_bytes());
+    }
+    for weight in sparse.weights().unwrap_or_default() {
+        bytes.extend(weight.to_le_bytes());
     }
-    .to_vec()
+    bytes
 }
 
+/// Converts a vector of f32s to the little endian bytes of `encoding`.
+/// # Notes
+/// Integer encodings only take integers, and float16 rounds every component to the closest
+/// half precision value. A component that would change otherwise is an error.
+fn encode_components(
+    vector: &[f32],
+    encoding: &ScalarEncoding,
+) -> Result<Vec<u8>, VectorConversionError> {
+    let unrepresentable = |index: usize| VectorConversionError::UnrepresentableComponent {
+        index,
+        encoding: encoding.clone(),
+    };
+    let mut bytes = Vec::with_capacity(vector.len() * 4);
+    for (index, x) in vector.iter().enumerate() {
+        match encoding {
+            ScalarEncoding::FLOAT32 => bytes.extend(x.to_le_bytes()),
+            ScalarEncoding::INT32 => {
+                // i32::MAX as f32 rounds up to 2^31, which is out of range.
+                if x.fract() != 0.0 || !(i32::MIN as f32..i32::MAX as f32).contains(x) {
+                    return Err(unrepresentable(index));
+                }
+                bytes.extend((*x as i32).to_le_bytes())
+            }
+            ScalarEncoding::FLOAT16 => {
+                let half = f16::from_f32(*x);
+                if x.is_finite() && !half.is_finite() {
+                    return Err(unrepresentable(index));
+                }
+                bytes.extend(half.to_le_bytes())
+            }
+            // Sparse vectors are not encoded component by component, see encode_sparse.
+            ScalarEncoding::SPARSE => return Err(unrepresentable(index)),
+        }
+    }
+    Ok(bytes)
+}
+
+// The dimension is ignored for sparse vectors, which are encoded with their number of
+// components as their dimension.
 impl TryFrom<(Vec<f32>, ScalarEncoding, usize)> for chroma_proto::Vector {
     type Error = VectorConversionError;
 
     fn try_from(
         (vector, encoding, dimension): (Vec<f32>, ScalarEncoding, usize),
     ) -> Result<Self, Self::Error> {
+        let (bytes, dimension) = match encoding {
+            ScalarEncoding::SPARSE => {
+                let sparse = SparseVector::from_embedding(&vector)?;
+                (encode_sparse(&sparse), sparse.len())
+            }
+            _ => (encode_components(&vector, &encoding)?, dimension),
+        };
         let proto_vector = chroma_proto::Vector {
-            vector: f32_to_vec(&vector),
-            encoding: encoding as i32,
+            vector: bytes,
+            encoding: chroma_proto::ScalarEncoding::from(encoding) as i32,
             dimension: dimension as i32,
         };
         Ok(proto_vector)
@@ -230,11 +408,28 @@ Vector Query Result
 ===========================================
  */
 
+/// One of the vectors of a query, with the number of nearest neighbors to find for it.
+#[derive(Clone, Debug)]
+pub(crate) struct KnnQuery {
+    pub(crate) embedding: Vec<f32>,
+    pub(crate) k: usize,
+}
+
+/// What a query returns for each of its results besides the id and distance.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct QueryInclude {
+    pub(crate) embeddings: bool,
+    pub(crate) metadatas: bool,
+    pub(crate) documents: bool,
+}
+
 #[derive(Debug)]
 pub(crate) struct VectorQueryResult {
     pub(crate) id: String,
     pub(crate) distance: f32,
     pub(crate) vector: Option<Vec<f32>>,
+    pub(crate) metadata: Option<Metadata>,
+    pub(crate) document: Option<String>,
 }
 
 /*
@@ -246,7 +441,10 @@ Get Vector Results
 #[derive(Debug)]
 pub(crate) struct GetVectorsResult {
     pub(crate) ids: Vec<String>,
-    pub(crate) vectors: Vec<Vec<f32>>,
+    // None for the ids that have no record.
+    pub(crate) vectors: Vec<Option<Vec<f32>>>,
+    // How the vectors are to be returned, SPARSE for collections with sparse embeddings.
+    pub(crate) encoding: ScalarEncoding,
 }
 
 /*
@@ -324,7 +522,7 @@ mod tests {
         assert_eq!(converted_operation_record.id, Uuid::nil().to_string());
         assert_eq!(
             converted_operation_record.embedding,
-            Some(vec![1.0, 2.0, 3.0])
+            Some(vec![1.0, 2.0, 3.0].into())
         );
         assert_eq!(
             converted_operation_record.encoding,
@@ -374,7 +572,7 @@ mod tests {
         assert_eq!(converted_log_record.log_offset, 42);
         assert_eq!(
             converted_log_record.record.embedding,
-            Some(vec![1.0, 2.0, 3.0])
+            Some(vec![1.0, 2.0, 3.0].into())
         );
         assert_eq!(
             converted_log_record.record.encoding,
@@ -385,4 +583,219 @@ mod tests {
         assert_eq!(metadata.get("foo").unwrap(), &UpdateMetadataValue::Int(42));
         assert_eq!(converted_log_record.record.operation, Operation::Add);
     }
+
+    fn proto_record(vector: Vec<u8>, dimension: i32) -> chroma_proto::OperationRecord {
+        chroma_proto::OperationRecord {
+            id: "embedding_id_1".to_string(),
+            vector: Some(chroma_proto::Vector {
+                vector,
+                encoding: chroma_proto::ScalarEncoding::Float32 as i32,
+                dimension,
+            }),
+            metadata: None,
+            operation: chroma_proto::Operation::Add as i32,
+        }
+    }
+
+    #[test]
+    fn test_vector_round_trip() {
+        let vector = vec![1.5, -0.0, f32::MIN_POSITIVE, f32::MAX];
+        let proto_vector: chroma_proto::Vector = (vector.clone(), ScalarEncoding::FLOAT32, 4)
+            .try_into()
+            .unwrap();
+        assert_eq!(proto_vector.vector[..4], 1.5f32.to_le_bytes());
+        let (converted, encoding) = proto_vector.try_into().unwrap();
+        assert_eq!(converted, Embedding::F32(vector.clone()));
+        assert_eq!(encoding, ScalarEncoding::FLOAT32);
+
+        // The bytes of the embedding don't have to be aligned for float32s.
+        let mut bytes = vec![0u8];
+        bytes.extend(as_byte_view(&vector));
+        let record = OperationRecord::try_from(proto_record(bytes[1..].to_vec(), 4)).unwrap();
+        assert_eq!(record.embedding, Some(vector.into()));
+    }
+
+    #[test]
+    fn test_vector_round_trip_per_encoding() {
+        let vector = vec![1.0, -2.0, 0.0, 1024.0, -65504.0];
+        for (encoding, size) in [
+            (ScalarEncoding::FLOAT32, 4),
+            (ScalarEncoding::INT32, 4),
+            (ScalarEncoding::FLOAT16, 2),
+        ] {
+            let proto_vector: chroma_proto::Vector =
+                (vector.clone(), encoding.clone(), vector.len())
+                    .try_into()
+                    .unwrap();
+            assert_eq!(proto_vector.vector.len(), vector.len() * size);
+            assert_eq!(
+                proto_vector.encoding,
+                chroma_proto::ScalarEncoding::from(encoding.clone()) as i32
+            );
+            let (converted, converted_encoding): (Embedding, _) = proto_vector.try_into().unwrap();
+            // Float16 components are kept as they are rather than dequantized.
+            assert_eq!(
+                matches!(converted, Embedding::F16(_)),
+                encoding == ScalarEncoding::FLOAT16
+            );
+            assert_eq!(converted.into_f32(), vector);
+            assert_eq!(converted_encoding, encoding);
+        }
+
+        // Integers are written as little endian int32s.
+        let proto_vector: chroma_proto::Vector = (vec![-2.0, 3.0], ScalarEncoding::INT32, 2)
+            .try_into()
+            .unwrap();
+        assert_eq!(proto_vector.vector[..4], (-2i32).to_le_bytes());
+
+        // Float16 rounds to the closest half precision value.
+        let proto_vector: chroma_proto::Vector = (vec![0.1, 1.0 / 3.0], ScalarEncoding::FLOAT16, 2)
+            .try_into()
+            .unwrap();
+        let (converted, _): (Embedding, _) = proto_vector.try_into().unwrap();
+        let converted = converted.into_f32();
+        assert!((converted[0] - 0.1).abs() < 1e-3);
+        assert!((converted[1] - 1.0 / 3.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_sparse_vector_round_trip() {
+        let payload = |indices: &[u32], weights: &[f32]| {
+            let mut bytes = Vec::new();
+            for index in indices {
+                bytes.extend(index.to_le_bytes());
+            }
+            for weight in weights {
+                bytes.extend(weight.to_le_bytes());
+            }
+            chroma_proto::Vector {
+                vector: bytes,
+                encoding: chroma_proto::ScalarEncoding::Sparse as i32,
+                dimension: indices.len() as i32,
+            }
+        };
+        for (indices, weights) in [
+            (vec![], None),
+            (vec![3, 70000, u32::MAX], None),
+            (vec![1, 2], Some(vec![0.5, 2.0])),
+        ] {
+            let proto_vector = payload(&indices, weights.as_deref().unwrap_or_default());
+            let (embedding, encoding): (Embedding, _) = proto_vector.clone().try_into().unwrap();
+            assert_eq!(encoding, ScalarEncoding::SPARSE);
+            let embedding = embedding.into_f32();
+            let sparse = SparseVector::from_embedding(&embedding).unwrap();
+            assert_eq!(sparse, SparseVector::new(indices, weights).unwrap());
+            let encoded: chroma_proto::Vector =
+                (embedding, ScalarEncoding::SPARSE, 0).try_into().unwrap();
+            assert_eq!(encoded, proto_vector);
+        }
+
+        // The payload is either the indices alone, or the indices and weights.
+        let mut proto_vector = payload(&[1, 2], &[]);
+        proto_vector.dimension = 3;
+        assert!(matches!(
+            <(Embedding, ScalarEncoding)>::try_from(proto_vector),
+            Err(VectorConversionError::DimensionMismatch { dimension: 3, .. })
+        ));
+        for (indices, weights, expected) in [
+            (vec![2, 1], vec![], SparseVectorError::UnsortedIndices(1)),
+            (
+                vec![1, 2],
+                vec![1.0, f32::NAN],
+                SparseVectorError::InvalidWeight(1),
+            ),
+        ] {
+            let error =
+                <(Embedding, ScalarEncoding)>::try_from(payload(&indices, &weights)).unwrap_err();
+            assert_eq!(error.code(), ErrorCodes::InvalidArgument);
+            assert!(matches!(
+                error,
+                VectorConversionError::InvalidSparseVector(e) if e == expected
+            ));
+        }
+    }
+
+    #[test]
+    fn test_unrepresentable_components() {
+        let encode = |vector: Vec<f32>, encoding: ScalarEncoding| {
+            let dimension = vector.len();
+            chroma_proto::Vector::try_from((vector, encoding, dimension))
+        };
+        for vector in [vec![1.0, 1.5], vec![1.0, 2147483648.0], vec![1.0, f32::NAN]] {
+            let error = encode(vector, ScalarEncoding::INT32).unwrap_err();
+            assert!(matches!(
+                error,
+                VectorConversionError::UnrepresentableComponent {
+                    index: 1,
+                    encoding: ScalarEncoding::INT32
+                }
+            ));
+            assert_eq!(error.code(), ErrorCodes::InvalidArgument);
+        }
+        assert!(encode(vec![-2147483648.0], ScalarEncoding::INT32).is_ok());
+        assert!(matches!(
+            encode(vec![65536.0], ScalarEncoding::FLOAT16),
+            Err(VectorConversionError::UnrepresentableComponent { index: 0, .. })
+        ));
+
+        // Float16 payloads have 2 bytes per component.
+        let proto_vector = chroma_proto::Vector {
+            vector: vec![0; 3],
+            encoding: chroma_proto::ScalarEncoding::Float16 as i32,
+            dimension: 2,
+        };
+        assert!(matches!(
+            <(Embedding, ScalarEncoding)>::try_from(proto_vector),
+            Err(VectorConversionError::InvalidByteLength)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_embeddings_name_the_record() {
+        for length in [1, 3, 5, 7] {
+            let error = OperationRecord::try_from(proto_record(vec![0; length], 1)).unwrap_err();
+            assert!(matches!(
+                &error,
+                RecordConversionError::VectorConversionError {
+                    id,
+                    source: VectorConversionError::InvalidByteLength,
+                } if id == "embedding_id_1"
+            ));
+            assert_eq!(error.code(), ErrorCodes::InvalidArgument);
+        }
+
+        for component in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let bytes = as_byte_view(&[1.0, component, 3.0]);
+            let error = OperationRecord::try_from(proto_record(bytes, 3)).unwrap_err();
+            assert!(matches!(
+                error,
+                RecordConversionError::VectorConversionError {
+                    source: VectorConversionError::NonFiniteComponent { index: 1 },
+                    ..
+                }
+            ));
+            assert_eq!(error.code(), ErrorCodes::InvalidArgument);
+            assert!(error.to_string().contains("embedding_id_1"));
+        }
+    }
+
+    #[test]
+    fn test_check_dimension() {
+        let record = OperationRecord::try_from(proto_record(as_byte_view(&[1.0, 2.0]), 2)).unwrap();
+        assert!(record.check_dimension(2).is_ok());
+        let error = record.check_dimension(3).unwrap_err();
+        assert!(matches!(
+            &error,
+            RecordConversionError::DimensionMismatch {
+                id,
+                expected: 3,
+                got: 2
+            } if id == "embedding_id_1"
+        ));
+        assert_eq!(error.code(), ErrorCodes::InvalidArgument);
+
+        let mut record = record;
+        record.embedding = None;
+        assert!(record.check_dimension(3).is_ok());
+    }
 }
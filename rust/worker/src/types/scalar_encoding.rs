@@ -5,15 +5,20 @@ use crate::{
 };
 use thiserror::Error;
 
+// The variants are named after the proto values.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) enum ScalarEncoding {
     FLOAT32,
     INT32,
+    FLOAT16,
+    // Sparse vectors, which are carried as `SparseVector::to_embedding` lays them out.
+    SPARSE,
 }
 
 #[derive(Error, Debug)]
 pub(crate) enum ScalarEncodingConversionError {
-    #[error("Invalid encoding, valid encodings are: Float32, Int32")]
+    #[error("Invalid encoding, valid encodings are: Float32, Int32, Float16, Sparse")]
     InvalidEncoding,
     #[error(transparent)]
     DecodeError(#[from] ConversionError),
@@ -30,7 +35,19 @@ impl TryFrom<chroma_proto::ScalarEncoding> for ScalarEncoding {
         match encoding {
             chroma_proto::ScalarEncoding::Float32 => Ok(ScalarEncoding::FLOAT32),
             chroma_proto::ScalarEncoding::Int32 => Ok(ScalarEncoding::INT32),
-            _ => Err(ScalarEncodingConversionError::InvalidEncoding),
+            chroma_proto::ScalarEncoding::Float16 => Ok(ScalarEncoding::FLOAT16),
+            chroma_proto::ScalarEncoding::Sparse => Ok(ScalarEncoding::SPARSE),
+        }
+    }
+}
+
+impl From<ScalarEncoding> for chroma_proto::ScalarEncoding {
+    fn from(encoding: ScalarEncoding) -> Self {
+        match encoding {
+            ScalarEncoding::FLOAT32 => chroma_proto::ScalarEncoding::Float32,
+            ScalarEncoding::INT32 => chroma_proto::ScalarEncoding::Int32,
+            ScalarEncoding::FLOAT16 => chroma_proto::ScalarEncoding::Float16,
+            ScalarEncoding::SPARSE => chroma_proto::ScalarEncoding::Sparse,
         }
     }
 }
@@ -44,11 +61,11 @@ impl TryFrom<i32> for ScalarEncoding {
             Ok(encoding) => match encoding {
                 chroma_proto::ScalarEncoding::Float32 => Ok(ScalarEncoding::FLOAT32),
                 chroma_proto::ScalarEncoding::Int32 => Ok(ScalarEncoding::INT32),
-                _ => Err(ScalarEncodingConversionError::InvalidEncoding),
+                chroma_proto::ScalarEncoding::Float16 => Ok(ScalarEncoding::FLOAT16),
+                chroma_proto::ScalarEncoding::Sparse => Ok(ScalarEncoding::SPARSE),
             },
-            Err(_) => Err(ScalarEncodingConversionError::DecodeError(
-                ConversionError::DecodeError,
-            )),
+            // An encoding this worker doesn't know of, such as one added to the protocol later.
+            Err(_) => Err(ScalarEncodingConversionError::InvalidEncoding),
         }
     }
 }
@@ -63,4 +80,26 @@ mod tests {
         let converted_encoding: ScalarEncoding = proto_encoding.try_into().unwrap();
         assert_eq!(converted_encoding, ScalarEncoding::FLOAT32);
     }
+
+    #[test]
+    fn test_scalar_encoding_round_trip() {
+        for encoding in [
+            ScalarEncoding::FLOAT32,
+            ScalarEncoding::INT32,
+            ScalarEncoding::FLOAT16,
+            ScalarEncoding::SPARSE,
+        ] {
+            let proto_encoding = chroma_proto::ScalarEncoding::from(encoding.clone());
+            assert_eq!(
+                ScalarEncoding::try_from(proto_encoding as i32).unwrap(),
+                encoding
+            );
+        }
+        let error = ScalarEncoding::try_from(4).unwrap_err();
+        assert!(matches!(
+            error,
+            ScalarEncodingConversionError::InvalidEncoding
+        ));
+        assert_eq!(error.code(), ErrorCodes::InvalidArgument);
+    }
 }